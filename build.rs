@@ -1,13 +1,15 @@
-#[cfg(windows)]
 fn main() {
+    // Reported by `sshpass --version --build-info`
+    if let Ok(target) = std::env::var("TARGET") {
+        println!("cargo:rustc-env=SSHPASS_BUILD_TARGET={target}");
+    }
+
+    #[cfg(windows)]
     if let Err(err) = copy_conpty_artifacts() {
         println!("cargo:warning=Windows PTY runtime files not prepared: {err}");
     }
 }
 
-#[cfg(not(windows))]
-fn main() {}
-
 #[cfg(windows)]
 fn copy_conpty_artifacts() -> Result<(), String> {
     use std::env;
@@ -16,10 +18,14 @@ fn copy_conpty_artifacts() -> Result<(), String> {
 
     let profile = env::var("PROFILE").map_err(|e| e.to_string())?;
 
-    let target_root = env::var_os("CARGO_TARGET_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("target"));
-    let dest_dir = target_root.join(&profile);
+    // OUT_DIR is <target>/[<triple>/]<profile>/build/<pkg>/out; going up from
+    // it finds the executable's directory for `--target` builds (e.g. ARM64) too
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").ok_or("OUT_DIR is not set")?);
+    let dest_dir = out_dir
+        .ancestors()
+        .nth(3)
+        .ok_or("unexpected OUT_DIR layout")?
+        .to_path_buf();
     fs::create_dir_all(&dest_dir).map_err(|e| e.to_string())?;
 
     let Some(src_dir) = locate_winpty_artifacts(&profile) else {
@@ -0,0 +1,25 @@
+//! Remote output filtered in arbitrary chunks must never panic, and no
+//! escape byte may survive the filter.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sshpass::ansi::AnsiFilter;
+
+fuzz_target!(|input: (Vec<u8>, Vec<u8>)| {
+    let (data, chunk_sizes) = input;
+    let mut filter = AnsiFilter::new();
+
+    let mut rest = data.as_slice();
+    for size in chunk_sizes.iter().map(|&n| n as usize + 1) {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at(size.min(rest.len()));
+        let output = filter.process(chunk);
+        assert!(!output.contains(&0x1b));
+        rest = tail;
+    }
+    let output = filter.process(rest);
+    assert!(!output.contains(&0x1b));
+});
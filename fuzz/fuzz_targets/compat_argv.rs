@@ -0,0 +1,14 @@
+//! `parse_compat` accepts any argument list without panicking, and a
+//! command it returns is always a suffix of the input.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sshpass::cli::parse_compat;
+
+fuzz_target!(|argv: Vec<String>| {
+    if let Ok(config) = parse_compat(argv.clone()) {
+        assert!(argv.ends_with(&config.command));
+        assert!(!config.command.is_empty() || config.help || config.version);
+    }
+});
@@ -0,0 +1,34 @@
+//! The inline `-ppassword` absorption only ever takes the password from
+//! the first command argument, and never one that follows `--`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sshpass::cli::{inline_password_from_command, Cli};
+use std::ffi::OsString;
+
+fuzz_target!(|input: (Vec<String>, usize)| {
+    let (raw, split) = input;
+    let raw_args: Vec<OsString> = raw.iter().map(OsString::from).collect();
+    // clap leaves a suffix of argv as the command
+    let command = raw[split.min(raw.len())..].to_vec();
+
+    let password = inline_password_from_command(&command, &raw_args);
+    if let Some(ref password) = password {
+        let first = &command[0];
+        assert!(!password.is_empty());
+        assert!(first == &format!("-p{}", password) || first == &format!("-p={}", password));
+    }
+
+    let mut cli = Cli {
+        command: command.clone(),
+        ..Cli::default()
+    };
+    cli.absorb_inline_password(&raw_args);
+    assert_eq!(cli.password, password);
+    if password.is_some() {
+        assert_eq!(cli.command, command[1..]);
+    } else {
+        assert_eq!(cli.command, command);
+    }
+});
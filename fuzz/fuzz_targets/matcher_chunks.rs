@@ -0,0 +1,35 @@
+//! A prompt must be found at the same offset however the output is split
+//! into reads.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sshpass::monitor::Matcher;
+
+/// Offset just past the first match, feeding `data` in the given chunks
+fn first_match(pattern: &str, data: &[u8], chunk_sizes: &[u8]) -> Option<usize> {
+    let mut matcher = Matcher::new(pattern);
+    let mut offset = 0;
+    let mut sizes = chunk_sizes.iter().map(|&n| n as usize + 1);
+
+    while offset < data.len() {
+        let size = sizes.next().unwrap_or(data.len()).min(data.len() - offset);
+        if let Some(end) = matcher.find(&data[offset..offset + size]) {
+            return Some(offset + end);
+        }
+        offset += size;
+    }
+    None
+}
+
+fuzz_target!(|input: (String, Vec<u8>, Vec<u8>)| {
+    let (pattern, data, chunk_sizes) = input;
+
+    let whole = first_match(&pattern, &data, &[]);
+    let chunked = first_match(&pattern, &data, &chunk_sizes);
+    assert_eq!(whole, chunked);
+
+    if let Some(end) = whole {
+        assert!(data[..end].ends_with(pattern.as_bytes()));
+    }
+});
@@ -0,0 +1,13 @@
+//! Terminal queries are detected in arbitrary (including invalid UTF-8)
+//! output without panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sshpass::terminal_response::get_terminal_response;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(response) = get_terminal_response(data) {
+        assert!(response.starts_with(b"\x1b["));
+    }
+});
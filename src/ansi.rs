@@ -1,17 +1,26 @@
 //! ANSI/VT100 控制碼處理
 //!
 //! 透過持續化的 VTE parser 來濾除控制碼，並正規化行結尾。
+//!
+//! parser 處於 ground 狀態時，可列印的 ASCII 與 `\n`、`\r`、`\t`、`\x08`
+//! 原樣輸出且不改變狀態，因此這類位元組整段直接複製，只有 ESC、其他控制碼與
+//! 非 ASCII 位元組才逐一交給 parser。
 
 use vte::{Parser, Perform};
 
 /// 執行 VTE 回呼的實作者
 struct AnsiPerformer {
     output: Vec<u8>,
+    /// 最後一個位元組被印出，parser 必定回到 ground 狀態
+    ground: bool,
 }
 
 impl AnsiPerformer {
     fn new() -> Self {
-        Self { output: Vec::new() }
+        Self {
+            output: Vec::new(),
+            ground: true,
+        }
     }
 
     fn take_output(&mut self) -> Vec<u8> {
@@ -24,6 +33,7 @@ impl Perform for AnsiPerformer {
         let mut buf = [0u8; 4];
         let s = c.encode_utf8(&mut buf);
         self.output.extend_from_slice(s.as_bytes());
+        self.ground = true;
     }
 
     fn execute(&mut self, byte: u8) {
@@ -54,6 +64,8 @@ impl Perform for AnsiPerformer {
 pub struct AnsiFilter {
     parser: Parser,
     performer: AnsiPerformer,
+    /// 上一段輸出以 CR 結尾，下一段開頭的 LF 屬於同一個 CRLF
+    after_cr: bool,
 }
 
 impl AnsiFilter {
@@ -61,16 +73,40 @@ impl AnsiFilter {
         Self {
             parser: Parser::new(),
             performer: AnsiPerformer::new(),
+            after_cr: false,
         }
     }
 
     /// 濾除控制碼，並回傳正規化換行後的結果
     pub fn process(&mut self, input: &[u8]) -> Vec<u8> {
-        for &byte in input {
-            self.parser.advance(&mut self.performer, byte);
+        let mut at = 0;
+        while at < input.len() {
+            if self.performer.ground {
+                let rest = &input[at..];
+                let plain = rest
+                    .iter()
+                    .position(|&byte| !is_plain(byte))
+                    .unwrap_or(rest.len());
+                self.performer.output.extend_from_slice(&rest[..plain]);
+                at += plain;
+                if at == input.len() {
+                    break;
+                }
+            }
+            // 不確定 parser 何時回到 ground，直到下一個字元被印出
+            self.performer.ground = false;
+            self.parser.advance(&mut self.performer, input[at]);
+            at += 1;
         }
 
-        let filtered = self.performer.take_output();
+        let mut filtered = self.performer.take_output();
+        if self.after_cr && filtered.first() == Some(&b'\n') {
+            filtered.remove(0);
+            self.after_cr = false;
+        }
+        if let Some(&last) = filtered.last() {
+            self.after_cr = last == b'\r';
+        }
         normalize_line_endings(&filtered)
     }
 }
@@ -81,6 +117,11 @@ impl Default for AnsiFilter {
     }
 }
 
+/// ground 狀態下 parser 會原樣輸出的位元組
+fn is_plain(byte: u8) -> bool {
+    matches!(byte, b' '..=b'~' | b'\n' | b'\r' | b'\t' | b'\x08')
+}
+
 /// 將 CRLF/CR 轉成 LF
 pub fn normalize_line_endings(input: &[u8]) -> Vec<u8> {
     let mut output = Vec::with_capacity(input.len());
@@ -114,6 +155,7 @@ pub fn process_output(input: &[u8]) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_ansi_filter_plain_text() {
@@ -149,6 +191,15 @@ mod tests {
         assert!(output.is_empty());
     }
 
+    #[test]
+    fn test_ansi_filter_split_crlf() {
+        let mut filter = AnsiFilter::new();
+        assert_eq!(filter.process(b"Line1\r"), b"Line1\n");
+        assert_eq!(filter.process(b"\x1b[0m"), b"");
+        assert_eq!(filter.process(b"\nLine2\r"), b"Line2\n");
+        assert_eq!(filter.process(b"\r\n"), b"\n");
+    }
+
     #[test]
     fn test_normalize_line_endings_crlf() {
         let input = b"Line1\r\nLine2\r\nLine3";
@@ -183,4 +234,55 @@ mod tests {
         let output = process_output(input);
         assert!(output.windows(8).any(|w| w == b"password"));
     }
+
+    proptest! {
+        #[test]
+        fn test_ansi_filter_chunk_split_invariance(
+            input in proptest::collection::vec(
+                prop_oneof![
+                    Just(0x1b),
+                    Just(b'['),
+                    Just(b'\r'),
+                    Just(b'\n'),
+                    Just(0xc3),
+                    any::<u8>(),
+                ],
+                0..64,
+            ),
+            cut in any::<proptest::sample::Index>(),
+        ) {
+            let (head, tail) = input.split_at(cut.index(input.len() + 1));
+            let mut filter = AnsiFilter::new();
+            let mut split = filter.process(head);
+            split.extend(filter.process(tail));
+            prop_assert_eq!(process_output(&input), split);
+        }
+
+        #[test]
+        fn test_ansi_filter_matches_parser(
+            input in proptest::collection::vec(
+                prop_oneof![
+                    4 => b' '..=b'~',
+                    1 => Just(0x1b),
+                    1 => Just(b'['),
+                    1 => Just(b']'),
+                    1 => Just(b'\r'),
+                    1 => Just(0x07),
+                    1 => Just(0xc3),
+                    1 => any::<u8>(),
+                ],
+                0..64,
+            ),
+        ) {
+            // Every byte through the parser, as before the plain runs were
+            // copied
+            let mut parser = Parser::new();
+            let mut performer = AnsiPerformer::new();
+            for &byte in &input {
+                parser.advance(&mut performer, byte);
+            }
+            let expected = normalize_line_endings(&performer.take_output());
+            prop_assert_eq!(process_output(&input), expected);
+        }
+    }
 }
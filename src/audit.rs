@@ -0,0 +1,494 @@
+//! Tamper-evident audit log (`--audit-log`)
+//!
+//! For environments that must keep evidence of automated privileged access,
+//! sshpass appends two JSON lines per session to the log: a `start` record
+//! with who ran what (the command with the password masked wherever it
+//! appears) and an `end` record with the exit code, duration and the number
+//! of output bytes seen. Both carry the session ID and `--label`s (see
+//! [`crate::labels`]). Records from all sessions form one chain:
+//!
+//! ```text
+//! {"seq":7,"prev":"<hash of record 6>",...,"hash":"<SHA-256 of this record>"}
+//! ```
+//!
+//! `hash` is the SHA-256 of the line up to `,"hash"` with the closing brace
+//! put back, so editing, removing or reordering records breaks the chain.
+//! The first record's `prev` is 64 zeros. `sshpass audit verify FILE` checks
+//! a log. The file is locked while a record is appended, so concurrent
+//! sessions keep a single chain. This detects tampering by anyone who cannot
+//! rewrite the whole file; ship the log to write-once storage to go further.
+//!
+//! With `--log-target syslog` or `eventlog` the same records, without the
+//! chain fields, go to the system log instead (see [`crate::system_log`]).
+
+use crate::error::{Result, SshpassError};
+use crate::file_lock::FileLock;
+use crate::labels::{self, SessionLabels};
+use crate::system_log::{Severity, SystemLog};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// `prev` of the first record
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How much of the end of the log is read to find the last record
+const TAIL: u64 = 64 * 1024;
+
+/// Where the records go
+enum Sink {
+    File { file: File, path: PathBuf },
+    System(SystemLog),
+}
+
+/// The records of one session
+pub struct AuditLog {
+    sink: Sink,
+    session: &'static SessionLabels,
+    started: Instant,
+}
+
+impl AuditLog {
+    /// Append the `start` record for `command`
+    ///
+    /// Fails if the log cannot be written or its last record is damaged, in
+    /// which case the session should not run. The file stays open for the
+    /// `end` record, which a `--sandbox` could otherwise not reopen.
+    pub fn start(path: &Path, command: &[String], secrets: &[&[u8]]) -> Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).append(true).create(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(path).map_err(|e| {
+            SshpassError::RuntimeError(format!(
+                "Failed to open audit log {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let path = path.to_path_buf();
+        Self::begin(Sink::File { file, path }, command, secrets)
+    }
+
+    /// Send the `start` record for `command` to the system log
+    pub fn start_system(log: SystemLog, command: &[String], secrets: &[&[u8]]) -> Result<Self> {
+        Self::begin(Sink::System(log), command, secrets)
+    }
+
+    fn begin(sink: Sink, command: &[String], secrets: &[&[u8]]) -> Result<Self> {
+        let now = SystemTime::now();
+        let log = Self {
+            sink,
+            session: labels::session(),
+            started: Instant::now(),
+        };
+
+        let command = redact(command, secrets)
+            .iter()
+            .map(|arg| json_string(arg))
+            .collect::<Vec<_>>()
+            .join(",");
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_default();
+        #[cfg(unix)]
+        // SAFETY: getuid has no preconditions and cannot fail
+        let user = format!("{},\"uid\":{}", json_string(&user), unsafe {
+            libc::getuid()
+        });
+        #[cfg(windows)]
+        let user = json_string(&user);
+
+        let fields = format!(
+            "\"time\":{},\"event\":\"start\",{},\"user\":{},\"pid\":{},\"command\":[{}]",
+            json_string(&utc_timestamp(now)),
+            log.session.json_fields(),
+            user,
+            std::process::id(),
+            command
+        );
+        log.append(Severity::Info, &fields)?;
+        Ok(log)
+    }
+
+    /// Append the `end` record for the session's outcome
+    pub fn finish(&self, result: &Result<i32>, output_bytes: u64) -> Result<()> {
+        let (severity, exit_code, error) = match result {
+            Ok(code) => (Severity::Info, *code, "null".to_string()),
+            Err(e) => (
+                Severity::Warning,
+                e.exit_code(),
+                json_string(&e.to_string()),
+            ),
+        };
+        let fields = format!(
+            "\"time\":{},\"event\":\"end\",{},\"exit_code\":{},\"error\":{},\"duration_ms\":{},\"output_bytes\":{}",
+            json_string(&utc_timestamp(SystemTime::now())),
+            self.session.json_fields(),
+            exit_code,
+            error,
+            self.started.elapsed().as_millis(),
+            output_bytes
+        );
+        self.append(severity, &fields)
+    }
+
+    fn append(&self, severity: Severity, fields: &str) -> Result<()> {
+        match self.sink {
+            Sink::File { ref file, ref path } => append_chained(file, path, fields),
+            Sink::System(ref log) => {
+                log.send(severity, &format!("{{{}}}", fields));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Chain `fields` to the last record of the file and append them
+fn append_chained(mut file: &File, path: &Path, fields: &str) -> Result<()> {
+    let failed = |e: std::io::Error| {
+        SshpassError::RuntimeError(format!(
+            "Failed to write audit log {}: {}",
+            path.display(),
+            e
+        ))
+    };
+
+    let _lock = FileLock::new(file).map_err(failed)?;
+
+    let (seq, prev) = match last_line(file).map_err(failed)? {
+        Some(line) => {
+            let record = parse_record(&line).map_err(|e| {
+                SshpassError::RuntimeError(format!(
+                    "Audit log {} ends with a damaged record: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            (record.seq + 1, record.hash)
+        }
+        None => (1, GENESIS.to_string()),
+    };
+
+    let body = format!("{{\"seq\":{},\"prev\":\"{}\",{}}}", seq, prev, fields);
+    let hash = hex(&sha256(body.as_bytes()));
+    let line = format!("{},\"hash\":\"{}\"}}\n", &body[..body.len() - 1], hash);
+    file.write_all(line.as_bytes()).map_err(failed)?;
+    file.flush().map_err(failed)
+}
+
+/// Check the chain of a log, returning the number of records
+pub fn verify(path: &Path) -> std::result::Result<u64, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut prev = GENESIS.to_string();
+    let mut count = 0;
+    for (number, line) in text.lines().enumerate() {
+        let bad = |reason: String| format!("line {}: {}", number + 1, reason);
+        let record = parse_record(line).map_err(bad)?;
+        if record.seq != count + 1 {
+            return Err(bad(format!(
+                "expected record {}, found {}",
+                count + 1,
+                record.seq
+            )));
+        }
+        if record.prev != prev {
+            return Err(bad("does not follow the previous record".to_string()));
+        }
+        prev = record.hash;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Mask every secret in the command's arguments
+pub fn redact(command: &[String], secrets: &[&[u8]]) -> Vec<String> {
+    let secrets = secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .filter_map(|secret| std::str::from_utf8(secret).ok())
+        .collect::<Vec<_>>();
+    command
+        .iter()
+        .map(|arg| {
+            secrets
+                .iter()
+                .fold(arg.clone(), |arg, secret| arg.replace(secret, "****"))
+        })
+        .collect()
+}
+
+/// The chain fields of a record whose own hash checks out
+struct Record {
+    seq: u64,
+    prev: String,
+    hash: String,
+}
+
+fn parse_record(line: &str) -> std::result::Result<Record, String> {
+    const HASH_FIELD: &str = ",\"hash\":\"";
+    let damaged = || "not a valid audit record".to_string();
+
+    let start = line.rfind(HASH_FIELD).ok_or_else(damaged)?;
+    let hash = line[start + HASH_FIELD.len()..]
+        .strip_suffix("\"}")
+        .ok_or_else(damaged)?;
+    let body = format!("{}}}", &line[..start]);
+    if hex(&sha256(body.as_bytes())) != hash {
+        return Err("hash does not match the record".to_string());
+    }
+
+    let seq = body
+        .strip_prefix("{\"seq\":")
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|seq| seq.parse().ok())
+        .ok_or_else(damaged)?;
+    let prev = body
+        .split_once(",\"prev\":\"")
+        .and_then(|(_, rest)| rest.get(..64))
+        .ok_or_else(damaged)?;
+    Ok(Record {
+        seq,
+        prev: prev.to_string(),
+        hash: hash.to_string(),
+    })
+}
+
+/// The last non-empty line of the file
+fn last_line(mut file: &File) -> std::io::Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let start = len.saturating_sub(TAIL);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = String::new();
+    file.read_to_string(&mut tail)?;
+    let tail = tail.trim_end_matches('\n');
+    match tail.rfind('\n') {
+        Some(newline) => Ok(Some(tail[newline + 1..].to_string())),
+        // A record longer than the tail: read it all
+        None if start > 0 => {
+            file.seek(SeekFrom::Start(0))?;
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            Ok(text.lines().last().map(str::to_string))
+        }
+        None if tail.is_empty() => Ok(None),
+        None => Ok(Some(tail.to_string())),
+    }
+}
+
+/// RFC 3339 time in UTC with milliseconds
+pub(crate) fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, second_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Quote a string as a JSON string literal
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 (FIPS 180-4)
+fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (out, word) in digest.chunks_exact_mut(4).zip(state) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks of padding
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        let at = |millis: u64| utc_timestamp(UNIX_EPOCH + std::time::Duration::from_millis(millis));
+        assert_eq!(at(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(951_782_400_123), "2000-02-29T00:00:00.123Z");
+        assert_eq!(at(1_792_152_059_000), "2026-10-16T12:00:59.000Z");
+    }
+
+    #[test]
+    fn test_redact() {
+        let command = ["mysql", "-uroot", "-phunter2", "--", "echo hunter2"].map(String::from);
+        assert_eq!(
+            redact(&command, &[b"hunter2", b""]),
+            vec!["mysql", "-uroot", "-p****", "--", "echo ****"]
+        );
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let path = std::env::temp_dir().join(format!("sshpass-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let command = ["ssh", "-o", "Pass=secret", "host"].map(String::from);
+        let first = AuditLog::start(&path, &command, &[b"secret"]).unwrap();
+        first.finish(&Ok(0), 42).unwrap();
+        let second = AuditLog::start(&path, &command, &[]).unwrap();
+        second
+            .finish(&Err(SshpassError::IncorrectPassword), 0)
+            .unwrap();
+        assert_eq!(verify(&path), Ok(4));
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with(&format!("{{\"seq\":1,\"prev\":\"{}\"", GENESIS)));
+        assert!(lines[0].contains("\"command\":[\"ssh\",\"-o\",\"Pass=****\",\"host\"]"));
+        assert!(lines[1].contains("\"exit_code\":0,\"error\":null"));
+        assert!(lines[1].contains("\"output_bytes\":42"));
+        let session = labels::session().json_fields();
+        assert!(lines[0].contains(&session) && lines[1].contains(&session));
+        assert!(lines[3].contains("\"exit_code\":5,\"error\":\"Incorrect password provided\""));
+
+        // Any edit breaks the chain
+        std::fs::write(
+            &path,
+            text.replace("\"output_bytes\":42", "\"output_bytes\":7"),
+        )
+        .unwrap();
+        assert_eq!(
+            verify(&path),
+            Err("line 2: hash does not match the record".to_string())
+        );
+        let without_first = lines[1..].join("\n");
+        std::fs::write(&path, without_first).unwrap();
+        assert!(verify(&path)
+            .unwrap_err()
+            .starts_with("line 1: expected record 1"));
+
+        // A damaged log is not appended to
+        std::fs::write(&path, "not json\n").unwrap();
+        assert!(AuditLog::start(&path, &command, &[]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+//! Reaching hosts behind bastions
+//!
+//! A [`Target`] whose inventory entry has a `jump` carries the chain of
+//! bastions in front of it. [`ssh_command`] turns it into one ssh command
+//! with `-J`, so the job does not have to template the command per host,
+//! and [`hop_for`] tells which hop a password prompt comes from.
+//!
+//! With `-J`, ssh asks for the password of each hop in turn, naming it in
+//! the prompt: `USER@HOST's password:`, or `(USER@HOST) Password:` for
+//! keyboard-interactive. The hop named there gets its own password from the
+//! inventory; a prompt that names no hop is taken to be the target's.
+
+use crate::inventory::{Jump, Settings, Target};
+
+/// The ssh command that runs `remote` on the target, through its bastions
+pub fn ssh_command(target: &Target, remote: &[String]) -> Vec<String> {
+    let mut command = vec!["ssh".to_string()];
+    if !target.jumps.is_empty() {
+        let chain = target.jumps.iter().map(jump_spec).collect::<Vec<_>>();
+        command.extend(["-J".to_string(), chain.join(",")]);
+    }
+    if let Some(port) = target.settings.port {
+        command.extend(["-p".to_string(), port.to_string()]);
+    }
+    if let Some(user) = &target.settings.user {
+        command.extend(["-l".to_string(), user.clone()]);
+    }
+    // The host cannot be taken for an option, whatever its name
+    command.extend(["--".to_string(), target.host.clone()]);
+    command.extend(remote.iter().cloned());
+    command
+}
+
+/// The bastion `prompt` asks about, or None for the target itself
+pub fn hop_for<'a>(target: &'a Target, prompt: &str) -> Option<&'a Jump> {
+    let prompt = prompt.to_ascii_lowercase();
+    let names = |host: &str| {
+        let host = host.to_ascii_lowercase();
+        prompt.contains(&format!("@{}'s", host)) || prompt.contains(&format!("@{})", host))
+    };
+    target.jumps.iter().rev().find(|jump| names(&jump.host))
+}
+
+/// Where the password is kept for the hop `prompt` asks about
+pub fn password_for<'a>(target: &'a Target, prompt: &str) -> Option<&'a str> {
+    let settings: &Settings =
+        hop_for(target, prompt).map_or(&target.settings, |jump| &jump.settings);
+    settings.password.as_deref()
+}
+
+/// `[USER@]HOST[:PORT]` for `-J`
+fn jump_spec(jump: &Jump) -> String {
+    let host = if jump.host.contains(':') {
+        format!("[{}]", jump.host)
+    } else {
+        jump.host.clone()
+    };
+    let user = jump
+        .settings
+        .user
+        .as_ref()
+        .map_or(String::new(), |user| format!("{}@", user));
+    let port = jump
+        .settings
+        .port
+        .map_or(String::new(), |port| format!(":{}", port));
+    format!("{}{}{}", user, host, port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Inventory;
+
+    const INVENTORY: &str = "\
+[bastions]
+outer.example.com user=gate password=env:OUTER_PASSWORD
+inner.example.com port=2200 jump=outer.example.com
+
+[bastions:vars]
+password = env:BASTION_PASSWORD
+
+[db]
+db1 jump=ops@inner.example.com
+db2 port=2222 jump=[fe80::1]:22
+
+[db:vars]
+user = dba
+password = env:DB_PASSWORD
+";
+
+    fn words(text: &str) -> Vec<String> {
+        text.split(' ').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_jump_chain() {
+        let inventory = Inventory::parse(INVENTORY).unwrap();
+        let db = inventory.targets("db").unwrap();
+        let hosts = db[0]
+            .jumps
+            .iter()
+            .map(|jump| jump.host.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(hosts, ["outer.example.com", "inner.example.com"]);
+        // The user given with the jump wins over the bastion's own
+        assert_eq!(db[0].jumps[1].settings.user.as_deref(), Some("ops"));
+        assert_eq!(db[0].jumps[1].settings.port, Some(2200));
+        assert_eq!(
+            ssh_command(&db[0], &words("uptime -p")),
+            words(
+                "ssh -J gate@outer.example.com,ops@inner.example.com:2200 -l dba -- db1 uptime -p"
+            )
+        );
+        assert_eq!(
+            ssh_command(&db[1], &[]),
+            words("ssh -J [fe80::1]:22 -p 2222 -l dba -- db2")
+        );
+        assert_eq!(
+            ssh_command(&inventory.targets("outer.example.com").unwrap()[0], &[]),
+            words("ssh -l gate -- outer.example.com")
+        );
+    }
+
+    #[test]
+    fn test_password_for_hop() {
+        let inventory = Inventory::parse(INVENTORY).unwrap();
+        let db1 = &inventory.targets("db1").unwrap()[0];
+        let password = |prompt| password_for(db1, prompt);
+        assert_eq!(
+            password("gate@outer.example.com's password: "),
+            Some("env:OUTER_PASSWORD")
+        );
+        assert_eq!(
+            password("(ops@Inner.Example.com) Password: "),
+            Some("env:BASTION_PASSWORD")
+        );
+        assert_eq!(password("dba@db1's password: "), Some("env:DB_PASSWORD"));
+        assert_eq!(password("Password: "), Some("env:DB_PASSWORD"));
+    }
+
+    #[test]
+    fn test_jump_errors() {
+        let error = |text: &str| {
+            Inventory::parse(text)
+                .and_then(|inventory| inventory.targets("all"))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("a jump=b\nb jump=a").contains("The jump hosts of a loop back to a"));
+        assert!(error("a jump=a").contains("loop back"));
+        assert!(error("a jump=b:0").contains("invalid jump host 'b:0'"));
+        assert!(error("a jump=@b").contains("invalid jump host"));
+        assert!(error("a jump=b,c").contains("invalid jump host"));
+        assert!(error("a jump=[::1").contains("invalid jump host"));
+    }
+}
@@ -0,0 +1,544 @@
+//! Running one job against many hosts
+//!
+//! [`run`] takes the [`Target`]s of an inventory and a job, such as a
+//! session against one host, and runs the job for each target on a pool of
+//! worker threads:
+//!
+//! - at most [`BatchOptions::max_parallel`] hosts at a time;
+//! - with `fail_fast`, or once `max_failures` hosts failed, no further host
+//!   is started: those left are reported as skipped, and jobs still running
+//!   see [`HostContext::aborted`] so they can cancel their session; a
+//!   termination signal in [`BatchOptions::signals`] stops the batch the
+//!   same way;
+//! - with a `host_timeout`, each job is given a deadline; a job running a
+//!   session cancels it through its
+//!   [`SessionHandle`](crate::session::SessionHandle) once
+//!   [`HostContext::remaining`] runs out, and reports a
+//!   [`SshpassError::Timeout`].
+//!
+//! The results come back in the order of the targets, whatever order the
+//! hosts finished in. An [`ExitPolicy`] turns them into one exit code:
+//! `any-failed` (the default) fails the batch if any host did not succeed,
+//! `percent:N` if fewer than N percent succeeded, and `always-zero` never.
+//! The [`report`] module writes them as JSON lines, JUnit XML or CSV, and
+//! [`output`] keeps the output of each host in a file of its own. On Unix,
+//! `tail` lets an operator watch a host while it runs.
+//!
+//! With a [`BatchOptions::state_file`], the results are also kept on disk as
+//! hosts finish, and [`resume`] picks up an interrupted batch where it
+//! stopped (see [`state`]). [`runner`] runs the batch `--inventory` asks
+//! for.
+
+pub mod jump;
+pub mod output;
+pub mod report;
+pub mod runner;
+pub mod state;
+#[cfg(unix)]
+pub mod tail;
+pub mod template;
+
+use crate::error::{Result, ReturnCode, SshpassError};
+use crate::inventory::Target;
+use crate::signal::SignalFlags;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many hosts run at a time unless told otherwise
+pub const DEFAULT_MAX_PARALLEL: usize = 10;
+
+/// How the batch is scheduled
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Hosts running at the same time, at least 1
+    pub max_parallel: usize,
+    /// Start no further host after the first failure
+    pub fail_fast: bool,
+    /// Start no further host after this many failures
+    pub max_failures: Option<usize>,
+    /// Time each host is given
+    pub host_timeout: Option<Duration>,
+    pub exit: ExitPolicy,
+    /// File the results are saved to after each host
+    pub state_file: Option<PathBuf>,
+    /// Start no further host once one of these reports SIGINT, SIGTERM or
+    /// SIGHUP
+    pub signals: Option<SignalFlags>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            max_parallel: DEFAULT_MAX_PARALLEL,
+            fail_fast: false,
+            max_failures: None,
+            host_timeout: None,
+            exit: ExitPolicy::AnyFailed,
+            state_file: None,
+            signals: None,
+        }
+    }
+}
+
+impl BatchOptions {
+    /// Failures after which no further host is started
+    fn failure_limit(&self) -> Option<usize> {
+        if self.fail_fast {
+            Some(1)
+        } else {
+            self.max_failures
+        }
+    }
+}
+
+/// How the results of the hosts make the exit code of the batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExitPolicy {
+    /// Fail if any host did not succeed
+    #[default]
+    AnyFailed,
+    /// Fail if fewer than this percentage of the hosts succeeded
+    Percent(u8),
+    /// Exit with 0 whatever happened
+    AlwaysZero,
+}
+
+/// Parse an exit policy: `any-failed`, `percent:N` or `always-zero`
+pub fn parse_exit_policy(value: &str) -> std::result::Result<ExitPolicy, String> {
+    match value {
+        "any-failed" => Ok(ExitPolicy::AnyFailed),
+        "always-zero" => Ok(ExitPolicy::AlwaysZero),
+        _ => value
+            .strip_prefix("percent:")
+            .and_then(|percent| percent.parse::<u8>().ok())
+            .filter(|&percent| percent <= 100)
+            .map(ExitPolicy::Percent)
+            .ok_or_else(|| {
+                format!(
+                    "invalid exit policy '{}': expected any-failed, percent:N (0-100) or always-zero",
+                    value
+                )
+            }),
+    }
+}
+
+/// How a host ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostStatus {
+    /// The job returned exit code 0
+    Succeeded,
+    /// The job returned another exit code, or an error
+    Failed,
+    /// The job ran out of time
+    TimedOut,
+    /// Not started, because the batch stopped first
+    Skipped,
+}
+
+/// What happened on one host
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostResult {
+    pub host: String,
+    pub group: String,
+    pub status: HostStatus,
+    /// The exit code the job returned, or that of its error
+    pub exit_code: Option<i32>,
+    /// Why the host failed, when the job returned an error
+    pub message: Option<String>,
+    pub duration: Duration,
+}
+
+impl HostResult {
+    fn new(target: &Target, status: HostStatus) -> Self {
+        Self {
+            host: target.host.clone(),
+            group: target.group.clone(),
+            status,
+            exit_code: None,
+            message: None,
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// Whether the host counts toward the failure limits
+    pub fn failed(&self) -> bool {
+        matches!(self.status, HostStatus::Failed | HostStatus::TimedOut)
+    }
+}
+
+/// What a job is told about its host
+pub struct HostContext<'a> {
+    deadline: Option<Instant>,
+    aborted: &'a AtomicBool,
+}
+
+impl HostContext<'_> {
+    /// Time left before the host times out, None without a timeout
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the batch stopped and the job had better stop too
+    pub fn aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+}
+
+/// The results of a batch, in the order of its targets
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchOutcome {
+    pub results: Vec<HostResult>,
+    /// Whether a failure limit or a signal stopped the batch
+    pub aborted: bool,
+}
+
+impl BatchOutcome {
+    /// Hosts with each status, in the order of [`HostStatus`]
+    pub fn counts(&self) -> [usize; 4] {
+        let count = |status| self.results.iter().filter(|r| r.status == status).count();
+        [
+            count(HostStatus::Succeeded),
+            count(HostStatus::Failed),
+            count(HostStatus::TimedOut),
+            count(HostStatus::Skipped),
+        ]
+    }
+
+    /// The exit code of the batch under `policy`
+    pub fn exit_code(&self, policy: ExitPolicy) -> i32 {
+        let succeeded = self.counts()[0];
+        let total = self.results.len();
+        let passed = match policy {
+            ExitPolicy::AnyFailed => succeeded == total,
+            ExitPolicy::Percent(percent) => succeeded * 100 >= usize::from(percent) * total,
+            ExitPolicy::AlwaysZero => true,
+        };
+        if passed {
+            0
+        } else {
+            ReturnCode::BatchFailed.as_exit_code()
+        }
+    }
+}
+
+/// Run `job` for every target, as `options` say
+pub fn run<F>(targets: &[Target], options: &BatchOptions, job: F) -> BatchOutcome
+where
+    F: Fn(&Target, &HostContext<'_>) -> Result<i32> + Sync,
+{
+    schedule(targets, options, vec![None; targets.len()], job)
+}
+
+/// Run `job` for the targets that did not succeed in the batch saved to
+/// `options.state_file`; those that did keep their saved results
+pub fn resume<F>(targets: &[Target], options: &BatchOptions, job: F) -> Result<BatchOutcome>
+where
+    F: Fn(&Target, &HostContext<'_>) -> Result<i32> + Sync,
+{
+    let path = options.state_file.as_deref().ok_or_else(|| {
+        SshpassError::InvalidArguments("Resuming a batch needs a state file".to_string())
+    })?;
+    let saved = state::load(path)?;
+    let done = targets
+        .iter()
+        .map(|target| {
+            saved
+                .iter()
+                .find(|result| result.host == target.host && result.status == HostStatus::Succeeded)
+                .cloned()
+        })
+        .collect();
+    Ok(schedule(targets, options, done, job))
+}
+
+/// Run `job` for the targets without a result in `done`
+fn schedule<F>(
+    targets: &[Target],
+    options: &BatchOptions,
+    done: Vec<Option<HostResult>>,
+    job: F,
+) -> BatchOutcome
+where
+    F: Fn(&Target, &HostContext<'_>) -> Result<i32> + Sync,
+{
+    let queue = Mutex::new(
+        (0..targets.len())
+            .filter(|&index| done[index].is_none())
+            .collect::<VecDeque<_>>(),
+    );
+    let results = Mutex::new(done);
+    let failures = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+    let limit = options.failure_limit();
+
+    let terminated = || {
+        options
+            .signals
+            .as_ref()
+            .is_some_and(SignalFlags::should_terminate)
+    };
+    let worker = || loop {
+        if terminated() {
+            aborted.store(true, Ordering::Relaxed);
+        }
+        if aborted.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(index) = queue.lock().unwrap().pop_front() else {
+            return;
+        };
+        let target = &targets[index];
+        let started = Instant::now();
+        let context = HostContext {
+            deadline: options.host_timeout.map(|timeout| started + timeout),
+            aborted: &aborted,
+        };
+        let mut result = HostResult::new(target, HostStatus::Succeeded);
+        match job(target, &context) {
+            Ok(0) => result.exit_code = Some(0),
+            Ok(code) => {
+                result.status = HostStatus::Failed;
+                result.exit_code = Some(code);
+            }
+            Err(e) => {
+                result.status = match e {
+                    SshpassError::Timeout(_) => HostStatus::TimedOut,
+                    _ => HostStatus::Failed,
+                };
+                result.exit_code = Some(e.exit_code());
+                result.message = Some(e.to_string());
+            }
+        }
+        result.duration = started.elapsed();
+        if result.failed() {
+            let failed = failures.fetch_add(1, Ordering::Relaxed) + 1;
+            if limit.is_some_and(|limit| failed >= limit) {
+                aborted.store(true, Ordering::Relaxed);
+            }
+        }
+        let mut results = results.lock().unwrap();
+        results[index] = Some(result);
+        if let Some(path) = &options.state_file {
+            if let Err(e) = state::save(path, results.iter().flatten()) {
+                crate::diag!(
+                    "Failed to save the batch state to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    };
+
+    let workers = options.max_parallel.clamp(1, targets.len().max(1));
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(worker);
+        }
+    });
+
+    let results = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .zip(targets)
+        .map(|(result, target)| {
+            result.unwrap_or_else(|| HostResult::new(target, HostStatus::Skipped))
+        })
+        .collect();
+    BatchOutcome {
+        results,
+        aborted: aborted.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Settings;
+
+    fn targets(hosts: &[&str]) -> Vec<Target> {
+        hosts
+            .iter()
+            .map(|host| Target {
+                host: host.to_string(),
+                group: "linux".to_string(),
+                settings: Settings::default(),
+                jumps: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Exit code 0 except for hosts named `fail*`, after a short wait
+    fn job(target: &Target, _context: &HostContext<'_>) -> Result<i32> {
+        std::thread::sleep(Duration::from_millis(5));
+        Ok(if target.host.starts_with("fail") {
+            2
+        } else {
+            0
+        })
+    }
+
+    fn statuses(outcome: &BatchOutcome) -> Vec<HostStatus> {
+        outcome.results.iter().map(|result| result.status).collect()
+    }
+
+    #[test]
+    fn test_batch_runs_every_host_in_order() {
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let options = BatchOptions {
+            max_parallel: 2,
+            ..BatchOptions::default()
+        };
+        let hosts = targets(&["a", "fail1", "b", "c", "d"]);
+        let outcome = run(&hosts, &options, |target, context| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            let code = job(target, context);
+            running.fetch_sub(1, Ordering::SeqCst);
+            code
+        });
+        assert!(most.load(Ordering::SeqCst) <= 2);
+        assert!(!outcome.aborted);
+        assert_eq!(outcome.results[1].host, "fail1");
+        assert_eq!(outcome.results[1].exit_code, Some(2));
+        assert_eq!(outcome.counts(), [4, 1, 0, 0]);
+
+        assert_eq!(outcome.exit_code(ExitPolicy::AnyFailed), 13);
+        assert_eq!(outcome.exit_code(ExitPolicy::Percent(80)), 0);
+        assert_eq!(outcome.exit_code(ExitPolicy::Percent(90)), 13);
+        assert_eq!(outcome.exit_code(ExitPolicy::AlwaysZero), 0);
+    }
+
+    #[test]
+    fn test_batch_failure_limits() {
+        let hosts = targets(&["fail1", "a", "b", "c"]);
+        let options = BatchOptions {
+            max_parallel: 1,
+            fail_fast: true,
+            ..BatchOptions::default()
+        };
+        let outcome = run(&hosts, &options, job);
+        assert!(outcome.aborted);
+        assert_eq!(
+            statuses(&outcome),
+            [
+                HostStatus::Failed,
+                HostStatus::Skipped,
+                HostStatus::Skipped,
+                HostStatus::Skipped
+            ]
+        );
+
+        let hosts = targets(&["fail1", "a", "fail2", "b"]);
+        let options = BatchOptions {
+            max_parallel: 1,
+            max_failures: Some(2),
+            ..BatchOptions::default()
+        };
+        let outcome = run(&hosts, &options, job);
+        assert_eq!(outcome.counts(), [1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn test_batch_stops_on_signal() {
+        let signals = SignalFlags::new();
+        let options = BatchOptions {
+            max_parallel: 1,
+            signals: Some(signals.clone()),
+            ..BatchOptions::default()
+        };
+        let outcome = run(&targets(&["a", "b", "c"]), &options, |_, _| {
+            // SIGINT while the first host runs
+            signals.sigint_received.store(true, Ordering::SeqCst);
+            Ok(0)
+        });
+        assert!(outcome.aborted);
+        assert_eq!(
+            statuses(&outcome),
+            [
+                HostStatus::Succeeded,
+                HostStatus::Skipped,
+                HostStatus::Skipped
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_host_timeout() {
+        let options = BatchOptions {
+            host_timeout: Some(Duration::from_millis(20)),
+            ..BatchOptions::default()
+        };
+        let outcome = run(&targets(&["slow"]), &options, |_, context| {
+            // A session would be cancelled here through its handle
+            while context.remaining() != Some(Duration::ZERO) {
+                std::thread::sleep(Duration::from_millis(2));
+            }
+            Err(SshpassError::Timeout("waiting for the host".to_string()))
+        });
+        assert_eq!(statuses(&outcome), [HostStatus::TimedOut]);
+        assert_eq!(outcome.results[0].exit_code, Some(11));
+        assert!(outcome.results[0].duration >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_batch_resume() {
+        let dir = std::env::temp_dir().join(format!("sshpass-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = BatchOptions {
+            max_parallel: 1,
+            max_failures: Some(1),
+            state_file: Some(dir.join("run.json")),
+            ..BatchOptions::default()
+        };
+        let hosts = targets(&["a", "fail1", "b"]);
+        let first = run(&hosts, &options, job);
+        assert_eq!(first.counts(), [1, 1, 0, 1]);
+        // Saved to the millisecond
+        let saved = |outcome: &[HostResult]| {
+            outcome
+                .iter()
+                .map(|result| {
+                    (
+                        result.host.clone(),
+                        result.status,
+                        format!("{:.3}", result.duration.as_secs_f64()),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let kept = state::load(&dir.join("run.json")).unwrap();
+        assert_eq!(saved(&kept), saved(&first.results[..2]));
+
+        // Only the hosts that did not succeed run again
+        let ran = Mutex::new(Vec::new());
+        let second = resume(&hosts, &options, |target, _| {
+            ran.lock().unwrap().push(target.host.clone());
+            Ok(0)
+        })
+        .unwrap();
+        assert_eq!(ran.into_inner().unwrap(), ["fail1", "b"]);
+        assert_eq!(saved(&second.results[..1]), saved(&first.results[..1]));
+        assert_eq!(second.counts(), [3, 0, 0, 0]);
+        let kept = state::load(&dir.join("run.json")).unwrap();
+        assert_eq!(saved(&kept), saved(&second.results));
+
+        let without = BatchOptions::default();
+        assert!(resume(&hosts, &without, job).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_exit_policy() {
+        assert_eq!(parse_exit_policy("any-failed"), Ok(ExitPolicy::AnyFailed));
+        assert_eq!(parse_exit_policy("percent:90"), Ok(ExitPolicy::Percent(90)));
+        assert_eq!(parse_exit_policy("always-zero"), Ok(ExitPolicy::AlwaysZero));
+        assert!(parse_exit_policy("percent:101").is_err());
+        assert!(parse_exit_policy("never").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Per-host output of a batch
+//!
+//! Hosts running side by side share sshpass's stdout, so [`Prefixed`] leads
+//! each line a host prints with its name and writes whole lines only.
+//!
+//! The output of a run against a thousand hosts is easier to find in files
+//! of their own: with `--output-dir DIR`, [`OutputDir`] gives each host an
+//! [`OutputSink`] writing to `DIR/GROUP/HOST.log`, created with mode 0600
+//! and rotated like `--tee` targets when a [`Rotation`] is given
+//! (`HOST.log.1`, `HOST.log.2`, ...). Characters of a host or group name
+//! that do not belong in a file name are replaced by `_`.
+//!
+//! After the batch, [`OutputDir::write_manifest`] writes `DIR/manifest.json`,
+//! one JSON object per host and line in the order of the targets, so the
+//! logs can be found from the results:
+//!
+//! ```text
+//! {"host":"web1","group":"linux","status":"failed","exit_code":5,"log":"linux/web1.log","bytes":5120,"rotated":1}
+//! ```
+//!
+//! `log` is null for a host that never started, and `bytes` counts the log
+//! and its rotated copies.
+
+use super::{BatchOutcome, HostResult};
+use crate::audit::json_string;
+use crate::error::{Result, SshpassError};
+use crate::inventory::Target;
+use crate::output_sink::OutputSink;
+use crate::tee::{Rotation, Tee};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Name of the index of the logs
+pub const MANIFEST: &str = "manifest.json";
+
+/// The directory the hosts of a batch write their output to
+#[derive(Debug, Clone)]
+pub struct OutputDir {
+    root: PathBuf,
+    rotation: Option<Rotation>,
+}
+
+/// The output of one host
+pub struct HostOutput {
+    tee: Tee,
+}
+
+impl OutputSink for HostOutput {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        // A log that fails is reported and dropped, like a --tee target
+        self.tee.write(data);
+        Ok(())
+    }
+}
+
+/// The output of one host on sshpass's stdout, each line led by `HOST: `
+pub struct Prefixed {
+    prefix: Vec<u8>,
+    /// The start of a line that has not ended yet
+    partial: Vec<u8>,
+}
+
+impl Prefixed {
+    pub fn new(host: &str) -> Self {
+        Self {
+            prefix: format!("{}: ", host).into_bytes(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// The lines `data` ends, prefixed
+    fn lines(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut lines = Vec::new();
+        for byte in data {
+            self.partial.push(*byte);
+            if *byte == b'\n' {
+                lines.extend_from_slice(&self.prefix);
+                lines.append(&mut self.partial);
+            }
+        }
+        lines
+    }
+}
+
+impl OutputSink for Prefixed {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let lines = self.lines(data);
+        if lines.is_empty() {
+            return Ok(());
+        }
+        // One write, so lines of hosts running side by side do not mix
+        crate::broken_pipe::write_stdout(&lines)
+    }
+}
+
+impl Drop for Prefixed {
+    fn drop(&mut self) {
+        // The last line, even without its line break
+        if !self.partial.is_empty() {
+            let _ = self.write(b"\n");
+        }
+    }
+}
+
+impl OutputDir {
+    /// Use `root`, creating it if needed
+    pub fn create(root: &Path, rotation: Option<Rotation>) -> Result<Self> {
+        create_dir(root)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            rotation,
+        })
+    }
+
+    /// Where the log of `host` in `group` goes, relative to the directory
+    pub fn log_path(group: &str, host: &str) -> PathBuf {
+        Path::new(&file_name(group)).join(format!("{}.log", file_name(host)))
+    }
+
+    /// Start the log of `target`
+    pub fn open(&self, target: &Target) -> Result<HostOutput> {
+        let path = self.root.join(Self::log_path(&target.group, &target.host));
+        if let Some(dir) = path.parent() {
+            create_dir(dir)?;
+        }
+        let tee = Tee::open(&[path.display().to_string()])?.with_rotation(self.rotation);
+        Ok(HostOutput { tee })
+    }
+
+    /// Write the index of the logs, returning its path
+    pub fn write_manifest(&self, outcome: &BatchOutcome) -> Result<PathBuf> {
+        let path = self.root.join(MANIFEST);
+        let text = outcome
+            .results
+            .iter()
+            .map(|result| self.manifest_line(result) + "\n")
+            .collect::<String>();
+        crate::tee::create_file(&path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| {
+                SshpassError::RuntimeError(format!("Failed to write {}: {}", path.display(), e))
+            })?;
+        Ok(path)
+    }
+
+    fn manifest_line(&self, result: &HostResult) -> String {
+        let relative = Self::log_path(&result.group, &result.host);
+        let log = self.root.join(&relative);
+        let size = |path: &Path| std::fs::metadata(path).map(|meta| meta.len()).ok();
+        let (log, bytes, rotated) = match size(&log) {
+            Some(bytes) => {
+                let keep = self.rotation.map_or(0, |rotation| rotation.keep);
+                let copies = (1..=keep)
+                    .map_while(|n| {
+                        let mut name = log.as_os_str().to_owned();
+                        name.push(format!(".{}", n));
+                        size(Path::new(&name))
+                    })
+                    .collect::<Vec<_>>();
+                let log = relative.to_string_lossy().replace('\\', "/");
+                (
+                    json_string(&log),
+                    bytes + copies.iter().sum::<u64>(),
+                    copies.len(),
+                )
+            }
+            None => ("null".to_string(), 0, 0),
+        };
+        format!(
+            "{{\"host\":{},\"group\":{},\"status\":\"{}\",\"exit_code\":{},\"log\":{},\"bytes\":{},\"rotated\":{}}}",
+            json_string(&result.host),
+            json_string(&result.group),
+            result.status.as_str(),
+            result
+                .exit_code
+                .map_or("null".to_string(), |code| code.to_string()),
+            log,
+            bytes,
+            rotated
+        )
+    }
+}
+
+/// `name` with anything but letters, digits, `.`, `-` and `_` replaced
+fn file_name(name: &str) -> String {
+    let safe = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    match safe.as_str() {
+        "" | "." | ".." => safe.replace('.', "_") + "_",
+        _ => safe,
+    }
+}
+
+/// Create `dir` and its parents, readable by the user only on Unix
+fn create_dir(dir: &Path) -> Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir).map_err(|e| {
+        SshpassError::RuntimeError(format!("Failed to create {}: {}", dir.display(), e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{self, BatchOptions};
+    use crate::inventory::Inventory;
+
+    #[test]
+    fn test_prefixed_lines() {
+        let mut web1 = Prefixed::new("web1");
+        assert_eq!(web1.lines(b"up 3 d"), b"");
+        assert_eq!(
+            web1.lines(b"ays\r\nload 0.1\r\n$ "),
+            b"web1: up 3 days\r\nweb1: load 0.1\r\n"
+        );
+        assert_eq!(web1.lines(b"exit\n"), b"web1: $ exit\n");
+    }
+
+    #[test]
+    fn test_log_path() {
+        assert_eq!(
+            OutputDir::log_path("linux", "web1.example.com"),
+            Path::new("linux/web1.example.com.log")
+        );
+        assert_eq!(
+            OutputDir::log_path("a/b", "fe80::1"),
+            Path::new("a_b/fe80__1.log")
+        );
+        assert_eq!(OutputDir::log_path("..", ""), Path::new("___/_.log"));
+    }
+
+    #[test]
+    fn test_output_dir() {
+        let root = std::env::temp_dir().join(format!("sshpass-outdir-{}", std::process::id()));
+        let rotation = Rotation {
+            max_size: 8,
+            keep: 2,
+        };
+        let output = OutputDir::create(&root, Some(rotation)).unwrap();
+        let inventory = Inventory::parse("[linux]\nweb1\nweb2\n[db]\ndb1").unwrap();
+        let targets = inventory.targets("all").unwrap();
+        let options = BatchOptions {
+            fail_fast: true,
+            max_parallel: 1,
+            ..BatchOptions::default()
+        };
+        let outcome = batch::run(&targets, &options, |target, _| {
+            let mut log = output.open(target)?;
+            log.write(b"0123456789")?;
+            Ok(if target.host == "web2" { 1 } else { 0 })
+        });
+
+        let web1 = std::fs::read_to_string(root.join("linux/web1.log")).unwrap();
+        assert_eq!(web1, "89");
+        let rotated = std::fs::read_to_string(root.join("linux/web1.log.1")).unwrap();
+        assert_eq!(rotated, "01234567");
+
+        let manifest = output.write_manifest(&outcome).unwrap();
+        let manifest = std::fs::read_to_string(manifest).unwrap();
+        let lines = manifest.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            r#"{"host":"web1","group":"linux","status":"succeeded","exit_code":0,"log":"linux/web1.log","bytes":10,"rotated":1}"#
+        );
+        assert!(lines[1].contains(r#""status":"failed","exit_code":1,"log":"linux/web2.log""#));
+        assert!(lines[2].contains(r#""status":"skipped","exit_code":null,"log":null,"bytes":0"#));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
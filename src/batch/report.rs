@@ -0,0 +1,323 @@
+//! Reports of a batch for other tools (`--report FORMAT:PATH`)
+//!
+//! Every report is written from the same [`HostResult`] records:
+//!
+//! | Format | Content |
+//! |--------|---------|
+//! | `json` | one JSON object per host and line |
+//! | `junit` | JUnit XML, one test case per host in a suite per group, for CI dashboards |
+//! | `csv` | a header and one row per host |
+//!
+//! A host that failed or timed out is a `<failure>` in JUnit XML, and one
+//! that was skipped a `<skipped/>`. Files are created or truncated with
+//! mode 0600 on Unix.
+
+use super::{BatchOutcome, HostResult, HostStatus};
+use crate::audit::json_string;
+use crate::error::{Result, SshpassError};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// What a report is written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Junit,
+    Csv,
+}
+
+/// A report to write: `FORMAT:PATH`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+/// Parse a `FORMAT:PATH` report
+pub fn parse_report(value: &str) -> std::result::Result<Report, String> {
+    let (format, path) = value
+        .split_once(':')
+        .filter(|(_, path)| !path.is_empty())
+        .ok_or_else(|| format!("invalid report '{}': expected FORMAT:PATH", value))?;
+    let format = match format {
+        "json" => ReportFormat::Json,
+        "junit" => ReportFormat::Junit,
+        "csv" => ReportFormat::Csv,
+        _ => {
+            return Err(format!(
+                "invalid report format '{}': expected json, junit or csv",
+                format
+            ))
+        }
+    };
+    Ok(Report {
+        format,
+        path: PathBuf::from(path),
+    })
+}
+
+impl HostStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HostStatus::Succeeded => "succeeded",
+            HostStatus::Failed => "failed",
+            HostStatus::TimedOut => "timed-out",
+            HostStatus::Skipped => "skipped",
+        }
+    }
+}
+
+impl HostResult {
+    /// The result as a JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"host\":{},\"group\":{},\"status\":\"{}\",\"exit_code\":{},\"duration\":{:.3},\"message\":{}}}",
+            json_string(&self.host),
+            json_string(&self.group),
+            self.status.as_str(),
+            self.exit_code
+                .map_or("null".to_string(), |code| code.to_string()),
+            self.duration.as_secs_f64(),
+            self.message
+                .as_deref()
+                .map_or("null".to_string(), json_string),
+        )
+    }
+}
+
+impl Report {
+    /// Write the report of `outcome`
+    pub fn write(&self, outcome: &BatchOutcome) -> Result<()> {
+        let text = match self.format {
+            ReportFormat::Json => json(outcome),
+            ReportFormat::Junit => junit(outcome),
+            ReportFormat::Csv => csv(outcome),
+        };
+        crate::tee::create_file(&self.path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| {
+                SshpassError::RuntimeError(format!(
+                    "Failed to write report {}: {}",
+                    self.path.display(),
+                    e
+                ))
+            })
+    }
+}
+
+/// One JSON object per host and line
+pub fn json(outcome: &BatchOutcome) -> String {
+    outcome
+        .results
+        .iter()
+        .map(|result| result.to_json() + "\n")
+        .collect()
+}
+
+/// JUnit XML with a test suite per group
+pub fn junit(outcome: &BatchOutcome) -> String {
+    let mut groups: Vec<(&str, Vec<&HostResult>)> = Vec::new();
+    for result in &outcome.results {
+        match groups.iter_mut().find(|(name, _)| *name == result.group) {
+            Some((_, results)) => results.push(result),
+            None => groups.push((&result.group, vec![result])),
+        }
+    }
+
+    let summary = |results: &[&HostResult]| {
+        let count = |status| results.iter().filter(|r| r.status == status).count();
+        let time: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+        format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\"",
+            results.len(),
+            count(HostStatus::Failed) + count(HostStatus::TimedOut),
+            count(HostStatus::Skipped),
+            time
+        )
+    };
+    let all = outcome.results.iter().collect::<Vec<_>>();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"sshpass\" {}>\n",
+        summary(&all)
+    ));
+    for (group, results) in &groups {
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" {}>\n",
+            xml_escape(group),
+            summary(results)
+        ));
+        for result in results {
+            let open = format!(
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                xml_escape(&result.group),
+                xml_escape(&result.host),
+                result.duration.as_secs_f64()
+            );
+            let message = result
+                .message
+                .clone()
+                .unwrap_or_else(|| match result.exit_code {
+                    Some(code) => format!("exit code {}", code),
+                    None => String::new(),
+                });
+            match result.status {
+                HostStatus::Succeeded => xml.push_str(&format!("{}/>\n", open)),
+                HostStatus::Skipped => {
+                    xml.push_str(&format!("{}>\n      <skipped/>\n    </testcase>\n", open))
+                }
+                status => xml.push_str(&format!(
+                    "{}>\n      <failure type=\"{}\" message=\"{}\"/>\n    </testcase>\n",
+                    open,
+                    status.as_str(),
+                    xml_escape(&message)
+                )),
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// A header and one row per host
+pub fn csv(outcome: &BatchOutcome) -> String {
+    let mut text = String::from("host,group,status,exit_code,duration,message\n");
+    for result in &outcome.results {
+        let row = [
+            csv_field(&result.host),
+            csv_field(&result.group),
+            result.status.as_str().to_string(),
+            result
+                .exit_code
+                .map_or(String::new(), |code| code.to_string()),
+            format!("{:.3}", result.duration.as_secs_f64()),
+            csv_field(result.message.as_deref().unwrap_or_default()),
+        ];
+        text.push_str(&row.join(","));
+        text.push('\n');
+    }
+    text
+}
+
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            // Not allowed in XML 1.0
+            c if (c as u32) < 0x20 && c != '\t' => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quote a field that holds a comma, quote or line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn outcome() -> BatchOutcome {
+        let result =
+            |host: &str, group: &str, status, exit_code, message: Option<&str>| HostResult {
+                host: host.to_string(),
+                group: group.to_string(),
+                status,
+                exit_code,
+                message: message.map(str::to_string),
+                duration: Duration::from_millis(1500),
+            };
+        BatchOutcome {
+            results: vec![
+                result("r1", "routers", HostStatus::Succeeded, Some(0), None),
+                result(
+                    "r2",
+                    "routers",
+                    HostStatus::TimedOut,
+                    Some(11),
+                    Some("Timed out waiting for the password prompt"),
+                ),
+                result(
+                    "web1",
+                    "linux",
+                    HostStatus::Failed,
+                    Some(5),
+                    Some("bad \"password\", <again>"),
+                ),
+                result("web2", "linux", HostStatus::Skipped, None, None),
+            ],
+            aborted: true,
+        }
+    }
+
+    #[test]
+    fn test_parse_report() {
+        assert_eq!(
+            parse_report("junit:out/results.xml"),
+            Ok(Report {
+                format: ReportFormat::Junit,
+                path: PathBuf::from("out/results.xml"),
+            })
+        );
+        assert_eq!(
+            parse_report("csv:c:/a.csv").unwrap().path,
+            PathBuf::from("c:/a.csv")
+        );
+        assert!(parse_report("junit").is_err());
+        assert!(parse_report("junit:").is_err());
+        assert!(parse_report("html:report.html").is_err());
+    }
+
+    #[test]
+    fn test_json_report() {
+        let lines = json(&outcome());
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            r#"{"host":"r1","group":"routers","status":"succeeded","exit_code":0,"duration":1.500,"message":null}"#
+        );
+        assert!(lines[3].contains(r#""status":"skipped","exit_code":null"#));
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let xml = junit(&outcome());
+        assert!(xml.contains(
+            "<testsuites name=\"sshpass\" tests=\"4\" failures=\"2\" errors=\"0\" skipped=\"1\" time=\"6.000\">"
+        ));
+        assert!(xml.contains("<testsuite name=\"routers\" tests=\"2\" failures=\"1\""));
+        assert!(xml.contains("<testcase classname=\"routers\" name=\"r1\" time=\"1.500\"/>"));
+        assert!(xml.contains("<failure type=\"timed-out\" message=\"Timed out waiting"));
+        assert!(xml.contains("message=\"bad &quot;password&quot;, &lt;again&gt;\""));
+        assert!(xml.contains("<skipped/>"));
+        assert_eq!(xml.matches("</testsuite>").count(), 2);
+    }
+
+    #[test]
+    fn test_csv_report() {
+        let text = csv(&outcome());
+        let rows = text.lines().collect::<Vec<_>>();
+        assert_eq!(rows[0], "host,group,status,exit_code,duration,message");
+        assert_eq!(rows[1], "r1,routers,succeeded,0,1.500,");
+        assert_eq!(
+            rows[3],
+            "web1,linux,failed,5,1.500,\"bad \"\"password\"\", <again>\""
+        );
+        assert_eq!(rows[4], "web2,linux,skipped,,1.500,");
+    }
+}
@@ -0,0 +1,405 @@
+//! The batch of the command line: `--inventory` and `sshpass tui`
+//!
+//! [`run_batch`] reads the inventory and every password up front, then
+//! runs a [`Launch`] for each host under [`run`] or [`resume`], each with
+//! its own output, the passwords of its bastions, and a watcher that
+//! cancels the session once the host runs out of time or the batch stops.
+//! With the `tui` feature, `run_dashboard` runs the same batch on the
+//! dashboard of `sshpass tui`.
+
+#[cfg(unix)]
+use super::tail;
+use super::{jump, output, resume, run, template, BatchOptions, HostContext, DEFAULT_MAX_PARALLEL};
+use crate::cli::Cli;
+use crate::engine::launch::{self, Launch};
+use crate::error::{Result, SshpassError};
+use crate::inventory::{self, Target};
+use crate::output_sink::{self, OutputSink};
+use crate::password::{PasswordSource, SecureString};
+#[cfg(unix)]
+use crate::runtime_dir;
+use crate::session::{SessionControl, SessionHandle, SessionStatus};
+use crate::signal::{setup_signal_handlers, SignalFlags};
+use crate::{broken_pipe, exit_map, panic_guard, password_policy, tee};
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// `sshpass tui`: the batch of `args`, shown on a dashboard
+#[cfg(feature = "tui")]
+pub fn run_dashboard(args: Cli) -> i32 {
+    use std::io::IsTerminal;
+
+    let problem = if args.inventory.is_none() {
+        Some("sshpass tui needs --inventory")
+    } else if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        Some("sshpass tui needs a terminal")
+    } else {
+        None
+    };
+    if let Some(problem) = problem {
+        let e = SshpassError::InvalidArguments(problem.to_string());
+        crate::diag!("{}", e);
+        return e.exit_code();
+    }
+    run_batch(args, true)
+}
+
+/// `--inventory FILE`: run the command on each host of the inventory, on a
+/// dashboard with `dashboard`
+pub fn run_batch(mut args: Cli, dashboard: bool) -> i32 {
+    panic_guard::install(args.on_panic);
+    broken_pipe::set_mode(args.on_broken_pipe);
+    match batch_main(&mut args, dashboard) {
+        Ok(code) => code,
+        Err(e) => {
+            crate::diag!("{}", e);
+            e.exit_code()
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "tui"), allow(unused_variables))]
+fn batch_main(args: &mut Cli, dashboard: bool) -> Result<i32> {
+    let path = args.inventory.clone().unwrap_or_default();
+    let targets =
+        inventory::Inventory::load(&path)?.targets(args.group.as_deref().unwrap_or("all"))?;
+    let template = template::Template::parse(&args.command)?;
+    let passwords = BatchPasswords::read(args, &targets)?;
+    let rotation = args.record_max_size.map(|max_size| tee::Rotation {
+        max_size,
+        keep: args.record_rotate,
+    });
+    let output_dir = args
+        .output_dir
+        .as_deref()
+        .map(|dir| output::OutputDir::create(dir, rotation))
+        .transpose()?;
+
+    #[cfg(windows)]
+    let signals = if args.no_console {
+        crate::signal::setup_signal_handlers_no_console()
+    } else {
+        setup_signal_handlers()
+    }?;
+    #[cfg(unix)]
+    let signals = setup_signal_handlers()?;
+    launch::apply_ssh_env(args);
+
+    // An operator can watch the hosts while they run
+    #[cfg(unix)]
+    let runtime = runtime_dir::RuntimeDir::new();
+    #[cfg(unix)]
+    let tail = tail::Tail::new();
+    #[cfg(unix)]
+    let _socket = match runtime
+        .file("tail.sock")
+        .map_err(SshpassError::from)
+        .and_then(|path| tail.serve(&path))
+    {
+        Ok(socket) => {
+            if args.verbose > 0 {
+                crate::diag!(
+                    "Watch a host with: sshpass tail {} HOST",
+                    socket.path().display()
+                );
+            }
+            Some(socket)
+        }
+        Err(e) => {
+            if args.verbose > 0 {
+                crate::diag!("Hosts cannot be watched: {}", e);
+            }
+            None
+        }
+    };
+
+    let args = &*args;
+    let shared = BatchRun {
+        args,
+        template,
+        passwords,
+        signals,
+        output_dir,
+        #[cfg(unix)]
+        tail,
+        #[cfg(feature = "tui")]
+        dashboard: dashboard.then(|| crate::tui::Dashboard::new(&targets)),
+    };
+    let options = batch_options(args, &shared.signals);
+    let job = |target: &Target, context: &HostContext<'_>| {
+        let result = run_host(&shared, target, context);
+        #[cfg(feature = "tui")]
+        if let Some(ref dashboard) = shared.dashboard {
+            dashboard.finish_host(target, &result);
+        }
+        result
+    };
+    let run = || {
+        if args.resume {
+            resume(&targets, &options, job)
+        } else {
+            Ok(run(&targets, &options, job))
+        }
+    };
+    #[cfg(feature = "tui")]
+    let outcome = match shared.dashboard {
+        Some(ref dashboard) => dashboard.show(&shared.signals, run)??,
+        None => run()?,
+    };
+    #[cfg(not(feature = "tui"))]
+    let outcome = run()?;
+
+    let [succeeded, failed, timed_out, skipped] = outcome.counts();
+    crate::diag!(
+        "{} host(s): {} succeeded, {} failed, {} timed out, {} skipped",
+        targets.len(),
+        succeeded,
+        failed,
+        timed_out,
+        skipped
+    );
+    for result in outcome.results.iter().filter(|result| result.failed()) {
+        match result.message {
+            Some(ref message) => crate::diag!("{}: {}", result.host, message),
+            None => crate::diag!(
+                "{}: exit code {}",
+                result.host,
+                result.exit_code.unwrap_or_default()
+            ),
+        }
+    }
+
+    let mut code = outcome.exit_code(args.batch_exit);
+    let manifest = shared
+        .output_dir
+        .as_ref()
+        .map(|dir| dir.write_manifest(&outcome).map(drop));
+    let reports = args.report.iter().map(|report| report.write(&outcome));
+    for written in manifest.into_iter().chain(reports) {
+        if let Err(e) = written {
+            crate::diag!("{}", e);
+            // A batch that passed still fails without its reports
+            if code == 0 {
+                code = e.exit_code();
+            }
+        }
+    }
+    Ok(code)
+}
+
+/// How the batch is scheduled, from the command line; `signals` stop it
+fn batch_options(args: &Cli, signals: &SignalFlags) -> BatchOptions {
+    BatchOptions {
+        max_parallel: args.max_parallel.unwrap_or(DEFAULT_MAX_PARALLEL),
+        fail_fast: args.fail_fast,
+        max_failures: args.max_failures,
+        host_timeout: args.host_timeout.map(Duration::from_secs),
+        exit: args.batch_exit,
+        state_file: args.state_file.clone(),
+        signals: Some(signals.clone()),
+    }
+}
+
+/// The passwords of a batch, all read before the first host starts
+struct BatchPasswords {
+    /// The command line's, for hosts and hops without a `password` setting
+    default: Option<SecureString>,
+    /// The inventory's, by their `password` setting
+    by_source: BTreeMap<String, SecureString>,
+}
+
+impl BatchPasswords {
+    fn read(args: &mut Cli, targets: &[Target]) -> Result<Self> {
+        let verbose = args.is_verbose();
+        // Taken even when unused, so no copy of a -p password is left
+        let source = launch::password_source(args);
+        let mut by_source = BTreeMap::new();
+        let mut wanted = false;
+        let settings = targets.iter().flat_map(|target| {
+            std::iter::once(&target.settings).chain(target.jumps.iter().map(|jump| &jump.settings))
+        });
+        for settings in settings {
+            match settings.password {
+                Some(ref spec) if !by_source.contains_key(spec) => {
+                    let password = PasswordSource::from_spec(spec)?.read_password(verbose)?;
+                    password_policy::check(&args.validate_password, &password)?;
+                    by_source.insert(spec.clone(), password);
+                }
+                Some(_) => {}
+                None => wanted = true,
+            }
+        }
+        let default = if wanted {
+            let password = launch::read_password(args, source)?;
+            password_policy::check(&args.validate_password, &password)?;
+            Some(password)
+        } else {
+            None
+        };
+        Ok(Self { default, by_source })
+    }
+
+    /// The password a `password` setting names, or the default without one
+    fn get(&self, source: Option<&str>) -> Option<&SecureString> {
+        match source {
+            Some(spec) => self.by_source.get(spec),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+/// What the hosts of a batch share
+struct BatchRun<'a> {
+    args: &'a Cli,
+    /// The command, with the variables of each host
+    template: template::Template,
+    passwords: BatchPasswords,
+    signals: SignalFlags,
+    /// Where the hosts write their output, instead of stdout
+    output_dir: Option<output::OutputDir>,
+    #[cfg(unix)]
+    tail: tail::Tail,
+    /// Shows the hosts instead of their output, with `sshpass tui`
+    #[cfg(feature = "tui")]
+    dashboard: Option<crate::tui::Dashboard>,
+}
+
+/// Run the session of one host of a batch
+fn run_host(shared: &BatchRun<'_>, target: &Target, context: &HostContext<'_>) -> Result<i32> {
+    let BatchRun {
+        args,
+        ref template,
+        ref passwords,
+        ref signals,
+        ref output_dir,
+        #[cfg(unix)]
+        ref tail,
+        #[cfg(feature = "tui")]
+        ref dashboard,
+    } = *shared;
+    let password = passwords
+        .get(target.settings.password.as_deref())
+        .cloned()
+        .ok_or_else(|| SshpassError::RuntimeError("No password for the host".to_string()))?;
+
+    let mut host_args = args.clone();
+    // A command naming the host is a whole command of its own, such as scp;
+    // any other runs on the host over ssh
+    host_args.command = if template.variables().contains(&"host") {
+        template.render(target)?
+    } else {
+        jump::ssh_command(target, &template.render_remote(target)?)
+    };
+    host_args.profile = target.settings.profile.clone().or(host_args.profile);
+    // Nothing is typed into the sessions of a batch
+    host_args.input = Some("none".to_string());
+
+    let hop_passwords = target
+        .jumps
+        .iter()
+        .filter_map(|jump| passwords.get(jump.settings.password.as_deref()))
+        .collect::<Vec<_>>();
+    let control = SessionControl::new(signals.clone())?;
+    #[cfg(unix)]
+    let control = {
+        let mut secrets = vec![password.as_bytes()];
+        secrets.extend(
+            hop_passwords
+                .iter()
+                .map(|hop_password| hop_password.as_bytes()),
+        );
+        control.with_observer(tail.observer(&target.host, &secrets))
+    };
+    #[cfg(feature = "tui")]
+    let control = match dashboard
+        .as_ref()
+        .and_then(|d| d.observer(target, control.handle()))
+    {
+        Some(observer) => control.with_observer(observer),
+        None => control,
+    };
+    #[cfg(feature = "tui")]
+    let on_dashboard = dashboard.is_some();
+    #[cfg(not(feature = "tui"))]
+    let on_dashboard = false;
+    let output: Box<dyn OutputSink> = match output_dir {
+        Some(dir) => Box::new(dir.open(target)?),
+        // The dashboard shows the last lines instead
+        None if on_dashboard => Box::new(output_sink::Null),
+        None => Box::new(output::Prefixed::new(&target.host)),
+    };
+    let mut session = Launch::new(&host_args, password)
+        .with_output(output)
+        .with_hop_passwords(hop_passwords)
+        .with_password_chooser(Box::new(|prompt| {
+            passwords.get(jump::password_for(target, prompt))
+        }));
+    if !target.jumps.is_empty() {
+        // Each bastion asks for its password before the host does
+        let target = target.clone();
+        session = session.with_attempt_key(Box::new(move |prompt: &str| {
+            jump::hop_for(&target, prompt).map_or_else(String::new, |jump| jump.host.clone())
+        }));
+    }
+    let handle = control.handle();
+    let (finished, finish) = mpsc::channel::<()>();
+    let (result, stop) = std::thread::scope(|scope| {
+        let watcher = scope.spawn(move || watch_host(context, &handle, &finish));
+        let result = session.run(&control);
+        drop(finished);
+        (result, watcher.join().unwrap_or(None))
+    });
+    let result = match stop {
+        Some(HostStop::TimedOut) => Err(SshpassError::Timeout(format!(
+            "after {} seconds on the host",
+            args.host_timeout.unwrap_or_default()
+        ))),
+        Some(HostStop::Aborted) => Err(SshpassError::RuntimeError(
+            "Cancelled, the batch stopped".to_string(),
+        )),
+        None => result.map(|code| exit_map::apply(&args.map_exit_code, code)),
+    };
+    if let Err(ref e) = result {
+        control.error(e);
+    }
+    control.set_status(SessionStatus::Exited(match result {
+        Ok(exit_code) => exit_code,
+        Err(ref e) => e.exit_code(),
+    }));
+    result
+}
+
+/// Why the batch cancelled the session of a host
+enum HostStop {
+    TimedOut,
+    Aborted,
+}
+
+/// Cancel the session of a host once it runs out of time or the batch
+/// stops, until `finish` says the session ended
+fn watch_host(
+    context: &HostContext<'_>,
+    handle: &SessionHandle,
+    finish: &mpsc::Receiver<()>,
+) -> Option<HostStop> {
+    // How often a failure elsewhere in the batch is noticed
+    const POLL: Duration = Duration::from_millis(100);
+    loop {
+        let stop = if context.remaining() == Some(Duration::ZERO) {
+            Some(HostStop::TimedOut)
+        } else if context.aborted() {
+            Some(HostStop::Aborted)
+        } else {
+            None
+        };
+        if stop.is_some() {
+            handle.cancel();
+            return stop;
+        }
+        let wait = context.remaining().map_or(POLL, |left| left.min(POLL));
+        if let Err(mpsc::RecvTimeoutError::Disconnected) = finish.recv_timeout(wait) {
+            return None;
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Resuming an interrupted batch (`--state-file FILE`, `--resume`)
+//!
+//! With a [`BatchOptions::state_file`](super::BatchOptions::state_file),
+//! the scheduler rewrites the file after every host that finishes, with the
+//! results of all hosts finished so far in the format of the `json` report.
+//! The new content goes to `FILE.tmp` first and is renamed over the file, so
+//! a batch killed halfway leaves either the previous state or the new one,
+//! never a torn file.
+//!
+//! [`resume`](super::resume) reads the file back and skips the hosts that
+//! succeeded in it; hosts that failed, timed out or were skipped run again.
+//! A state file that does not exist yet means nothing has run.
+
+use super::{HostResult, HostStatus};
+use crate::error::{Result, SshpassError};
+use crate::recording::parse_string;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Read the results kept in a state file
+pub fn load(path: &Path) -> Result<Vec<HostResult>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(SshpassError::RuntimeError(format!(
+                "Failed to read {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            parse_result(line).map_err(|reason| {
+                SshpassError::RuntimeError(format!("{}:{}: {}", path.display(), index + 1, reason))
+            })
+        })
+        .collect()
+}
+
+/// Replace the state file with the finished `results`
+pub(super) fn save<'a>(
+    path: &Path,
+    results: impl Iterator<Item = &'a HostResult>,
+) -> std::io::Result<()> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    let temporary = Path::new(&name);
+    let mut file = crate::tee::create_file(temporary)?;
+    for result in results {
+        writeln!(file, "{}", result.to_json())?;
+    }
+    file.sync_all()?;
+    std::fs::rename(temporary, path)
+}
+
+/// Parse a line written by [`HostResult::to_json`]
+fn parse_result(line: &str) -> std::result::Result<HostResult, String> {
+    let damaged = || "not a host result".to_string();
+    let rest = line.trim().strip_prefix('{').ok_or_else(damaged)?;
+    let (host, rest) = parse_string(field(rest, "host").ok_or_else(damaged)?)?;
+    let (group, rest) = parse_string(field(rest, "group").ok_or_else(damaged)?)?;
+    let (status, rest) = parse_string(field(rest, "status").ok_or_else(damaged)?)?;
+    let status = [
+        HostStatus::Succeeded,
+        HostStatus::Failed,
+        HostStatus::TimedOut,
+        HostStatus::Skipped,
+    ]
+    .into_iter()
+    .find(|known| known.as_str() == status)
+    .ok_or_else(|| format!("unknown status '{}'", status))?;
+    let (exit_code, rest) = value(field(rest, "exit_code").ok_or_else(damaged)?);
+    let exit_code = match exit_code {
+        "null" => None,
+        code => Some(code.parse().map_err(|_| damaged())?),
+    };
+    let (duration, rest) = value(field(rest, "duration").ok_or_else(damaged)?);
+    let duration = duration
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(damaged)?;
+    let rest = field(rest, "message").ok_or_else(damaged)?;
+    let (message, rest) = match rest.strip_prefix("null") {
+        Some(rest) => (None, rest),
+        None => parse_string(rest).map(|(message, rest)| (Some(message), rest))?,
+    };
+    if rest.trim() != "}" {
+        return Err(damaged());
+    }
+    Ok(HostResult {
+        host,
+        group,
+        status,
+        exit_code,
+        message,
+        duration,
+    })
+}
+
+/// The value after `"KEY":` at the start of `rest`, past the comma that
+/// separates it from the previous field
+fn field<'a>(rest: &'a str, key: &str) -> Option<&'a str> {
+    let rest = rest.strip_prefix(',').unwrap_or(rest);
+    rest.strip_prefix('"')?
+        .strip_prefix(key)?
+        .strip_prefix("\":")
+}
+
+/// A number or `null` at the start of `rest`, and what follows it
+fn value(rest: &str) -> (&str, &str) {
+    let end = rest.find([',', '}']).unwrap_or(rest.len());
+    rest.split_at(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = std::env::temp_dir().join(format!("sshpass-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.json");
+        assert_eq!(load(&path).unwrap(), []);
+
+        let results = [
+            HostResult {
+                host: "r1".to_string(),
+                group: "routers".to_string(),
+                status: HostStatus::Succeeded,
+                exit_code: Some(0),
+                message: None,
+                duration: Duration::from_millis(1250),
+            },
+            HostResult {
+                host: "web \"1\"".to_string(),
+                group: "linux".to_string(),
+                status: HostStatus::TimedOut,
+                exit_code: Some(11),
+                message: Some("Timed out, {waiting}\nfor the prompt".to_string()),
+                duration: Duration::from_secs(30),
+            },
+        ];
+        save(&path, results.iter()).unwrap();
+        assert_eq!(load(&path).unwrap(), results);
+        assert!(!dir.join("run.json.tmp").exists());
+
+        std::fs::write(&path, "{\"host\":\"r1\"}\n").unwrap();
+        let error = load(&path).unwrap_err().to_string();
+        assert!(error.contains("run.json:1: not a host result"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
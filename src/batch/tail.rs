@@ -0,0 +1,361 @@
+//! Watching a host of a running batch
+//!
+//! When one host of a long batch hangs, the operator wants to see what it
+//! is printing without stopping the job. [`Tail`] keeps track of the
+//! sessions in flight: the job gives each session the
+//! [`observer`](Tail::observer) of its host, and [`Tail::serve`] answers on
+//! a Unix socket (the batch's [`RuntimeDir`](crate::runtime_dir::RuntimeDir)
+//! is a good place for it, mode 0600). A client sends one line:
+//!
+//! | Line | Answer |
+//! |------|--------|
+//! | `hosts` | `ok`, then the hosts in flight, one per line |
+//! | `tail HOST` | `ok`, then the output of HOST as it comes, until its session ends |
+//!
+//! or `error: REASON` instead of `ok`. Watching is read-only: nothing the
+//! client sends reaches the session. The passwords given to the observer
+//! are replaced by `****` before the output leaves, also when one arrives
+//! split across reads; the few bytes that might begin a password are held
+//! back until the next read tells. A watcher that does not keep up with the
+//! output is disconnected rather than slowing down the session.
+//!
+//! [`watch`] and [`hosts`] are the client side.
+
+use crate::error::{Result, SshpassError};
+use crate::session::{SessionEvent, SessionObserver, SessionStatus};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// What a password is replaced by
+const MASK: &[u8] = b"****";
+/// Time a client is given to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Watchers of each host in flight
+type Watchers = BTreeMap<String, Vec<UnixStream>>;
+
+/// The sessions of a batch that can be watched
+#[derive(Clone, Default)]
+pub struct Tail {
+    hosts: Arc<Mutex<Watchers>>,
+}
+
+impl Tail {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The observer of the session of `host`, which masks `secrets`; the
+    /// host can be watched until the session ends or the observer is dropped
+    pub fn observer(&self, host: &str, secrets: &[&[u8]]) -> TailObserver {
+        self.hosts
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), Vec::new());
+        TailObserver {
+            host: host.to_string(),
+            hosts: Arc::clone(&self.hosts),
+            redactor: Redactor::new(secrets),
+            finished: false,
+        }
+    }
+
+    /// The hosts in flight
+    pub fn running(&self) -> Vec<String> {
+        self.hosts.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Clients watching `host`
+    pub fn watchers(&self, host: &str) -> usize {
+        self.hosts.lock().unwrap().get(host).map_or(0, Vec::len)
+    }
+
+    /// Answer clients on a new Unix socket at `path` until the returned
+    /// [`ControlSocket`] is dropped
+    pub fn serve(&self, path: &Path) -> Result<ControlSocket> {
+        let failed = |e: std::io::Error| {
+            SshpassError::RuntimeError(format!("Failed to listen on {}: {}", path.display(), e))
+        };
+        let listener = UnixListener::bind(path).map_err(failed)?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .map_err(failed)?;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (tail, stop) = (self.clone(), Arc::clone(&stop));
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    // A client slow to send its request holds up no other
+                    if let Ok(stream) = stream {
+                        let tail = tail.clone();
+                        std::thread::spawn(move || tail.answer(stream));
+                    }
+                }
+            })
+        };
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Read the request of a client and answer it
+    fn answer(&self, mut stream: UnixStream) {
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+        let mut line = String::new();
+        let read = stream
+            .try_clone()
+            .and_then(|reader| BufReader::new(reader).read_line(&mut line));
+        if read.is_err() {
+            return;
+        }
+        let line = line.trim_end();
+        let mut hosts = self.hosts.lock().unwrap();
+        let answer = match line.split_once(' ') {
+            None if line == "hosts" => {
+                let list = hosts.keys().map(|host| format!("{}\n", host));
+                Ok(format!("ok\n{}", list.collect::<String>()))
+            }
+            Some(("tail", host)) => match hosts.get_mut(host) {
+                Some(watchers) => {
+                    if stream.write_all(b"ok\n").is_ok() && stream.set_nonblocking(true).is_ok() {
+                        watchers.push(stream);
+                    }
+                    return;
+                }
+                None => Err(format!("{} is not running", host)),
+            },
+            _ => Err(format!("unknown request '{}'", line)),
+        };
+        drop(hosts);
+        let text = answer.unwrap_or_else(|reason| format!("error: {}\n", reason));
+        let _ = stream.write_all(text.as_bytes());
+    }
+}
+
+/// The socket of [`Tail::serve`]; removed when dropped
+pub struct ControlSocket {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake the thread from accept
+        if UnixStream::connect(&self.path).is_ok() {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Passes the output of one session to its watchers
+pub struct TailObserver {
+    host: String,
+    hosts: Arc<Mutex<Watchers>>,
+    redactor: Redactor,
+    finished: bool,
+}
+
+impl TailObserver {
+    /// Send `data` to every watcher, dropping those that fail or lag
+    fn send(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(watchers) = self.hosts.lock().unwrap().get_mut(&self.host) {
+            watchers.retain_mut(|watcher| watcher.write_all(data).is_ok());
+        }
+    }
+
+    /// Flush what is held back and disconnect the watchers
+    fn finish(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let rest = self.redactor.finish();
+        self.send(&rest);
+        self.hosts.lock().unwrap().remove(&self.host);
+    }
+}
+
+impl SessionObserver for TailObserver {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        match event {
+            SessionEvent::Output(data) => {
+                let data = self.redactor.feed(data);
+                self.send(&data);
+            }
+            SessionEvent::Status(SessionStatus::Exited(_)) => self.finish(),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for TailObserver {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Masks secrets in a stream of output
+struct Redactor {
+    secrets: Vec<Zeroizing<Vec<u8>>>,
+    /// Output held back because it may begin a secret
+    pending: Zeroizing<Vec<u8>>,
+}
+
+impl Redactor {
+    fn new(secrets: &[&[u8]]) -> Self {
+        Self {
+            secrets: secrets
+                .iter()
+                .filter(|secret| !secret.is_empty())
+                .map(|secret| Zeroizing::new(secret.to_vec()))
+                .collect(),
+            pending: Zeroizing::new(Vec::new()),
+        }
+    }
+
+    /// The output of `data` that can be shown, masked
+    fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(data);
+        let mut shown = Vec::with_capacity(self.pending.len());
+        let mut at = 0;
+        'scan: while at < self.pending.len() {
+            let rest = &self.pending[at..];
+            for secret in &self.secrets {
+                if rest.starts_with(secret) {
+                    shown.extend_from_slice(MASK);
+                    at += secret.len();
+                    continue 'scan;
+                }
+            }
+            let unsure = self
+                .secrets
+                .iter()
+                .any(|secret| secret.len() > rest.len() && secret.starts_with(rest));
+            if unsure {
+                break;
+            }
+            shown.push(rest[0]);
+            at += 1;
+        }
+        self.pending.drain(..at);
+        shown
+    }
+
+    /// What is held back, once no more output will come
+    fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut *self.pending)
+    }
+}
+
+/// Write the output of `host` to `out` until its session ends
+pub fn watch(socket: &Path, host: &str, out: &mut impl Write) -> Result<()> {
+    let mut reader = request(socket, &format!("tail {}", host))?;
+    std::io::copy(&mut reader, out)?;
+    Ok(())
+}
+
+/// The hosts in flight
+pub fn hosts(socket: &Path) -> Result<Vec<String>> {
+    let reader = request(socket, "hosts")?;
+    Ok(reader.lines().collect::<std::io::Result<_>>()?)
+}
+
+/// Send `line`, returning the answer after its `ok`
+fn request(socket: &Path, line: &str) -> Result<BufReader<UnixStream>> {
+    let failed = |e: std::io::Error| {
+        SshpassError::RuntimeError(format!("Failed to reach {}: {}", socket.display(), e))
+    };
+    let mut stream = UnixStream::connect(socket).map_err(failed)?;
+    stream
+        .write_all(format!("{}\n", line).as_bytes())
+        .map_err(failed)?;
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).map_err(failed)?;
+    match status.trim_end() {
+        "ok" => Ok(reader),
+        answer => Err(SshpassError::RuntimeError(
+            answer
+                .strip_prefix("error: ")
+                .unwrap_or("no answer")
+                .to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redactor_masks_split_secrets() {
+        let mut redactor = Redactor::new(&[b"hunter2", b""]);
+        let mut shown = redactor.feed(b"pass: hun");
+        assert_eq!(shown, b"pass: ");
+        shown.extend(redactor.feed(b"ter2 ok, hunt"));
+        shown.extend(redactor.feed(b"ing h"));
+        shown.extend(redactor.finish());
+        assert_eq!(shown, b"pass: **** ok, hunting h");
+    }
+
+    #[test]
+    fn test_tail_running_session() {
+        let dir = std::env::temp_dir().join(format!("sshpass-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tail = Tail::new();
+        let socket = tail.serve(&dir.join("control.sock")).unwrap();
+        let mut web1 = tail.observer("web1", &[b"s3cret"]);
+        let _web2 = tail.observer("web2", &[]);
+        // A client that sends nothing does not keep the others waiting
+        let _silent = UnixStream::connect(socket.path()).unwrap();
+        assert_eq!(hosts(socket.path()).unwrap(), ["web1", "web2"]);
+        let error = watch(socket.path(), "db1", &mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("db1 is not running"));
+
+        let path = socket.path().to_path_buf();
+        let client = std::thread::spawn(move || {
+            let mut seen = Vec::new();
+            watch(&path, "web1", &mut seen).unwrap();
+            seen
+        });
+        while tail.watchers("web1") == 0 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        web1.on_event(SessionEvent::Output(b"sudo: s3c"));
+        web1.on_event(SessionEvent::Output(b"ret\r\nhung"));
+        web1.on_event(SessionEvent::Status(SessionStatus::Exited(0)));
+        assert_eq!(client.join().unwrap(), b"sudo: ****\r\nhung");
+        assert_eq!(tail.running(), ["web2"]);
+
+        drop(socket);
+        assert!(!dir.join("control.sock").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+//! Command templates of a batch
+//!
+//! The command run against each host may name the host's settings:
+//!
+//! | Variable | Value |
+//! |----------|-------|
+//! | `{host}` | the host |
+//! | `{group}` | the group its settings came from |
+//! | `{user}` | its `user` |
+//! | `{port}` | its `port` |
+//! | `{NAME}` | its `var.NAME` setting |
+//!
+//! `{{` and `}}` stand for literal braces. Substitution happens inside each
+//! argument of the command on its own, and a value becomes part of that one
+//! argument whatever it holds: spaces, quotes or `;` in an inventory value
+//! never turn into more arguments or shell syntax, since no shell is
+//! involved. A variable the host has no value for is an error rather than
+//! an empty string.
+//!
+//! ssh joins a remote command into one line for the remote shell, so
+//! [`Template::render_remote`] quotes each value for a POSIX shell: it
+//! reaches the remote command as the same single word.
+
+use crate::error::{Result, SshpassError};
+use crate::inventory::Target;
+
+/// A piece of an argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Variable(String),
+}
+
+/// A command with `{NAME}` variables, checked once for the whole batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    args: Vec<Vec<Piece>>,
+}
+
+impl Template {
+    /// Parse the arguments of a command
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let args = args
+            .iter()
+            .map(|arg| {
+                parse_arg(arg).map_err(|reason| {
+                    SshpassError::InvalidArguments(format!(
+                        "Invalid command template '{}': {}",
+                        arg, reason
+                    ))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { args })
+    }
+
+    /// The variables the template uses, in order of appearance
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for piece in self.args.iter().flatten() {
+            if let Piece::Variable(name) = piece {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// The command for `target`
+    pub fn render(&self, target: &Target) -> Result<Vec<String>> {
+        self.render_with(target, str::to_string)
+    }
+
+    /// The command for `target`, to be run by its shell over ssh
+    pub fn render_remote(&self, target: &Target) -> Result<Vec<String>> {
+        self.render_with(target, shell_quote)
+    }
+
+    fn render_with(&self, target: &Target, quote: fn(&str) -> String) -> Result<Vec<String>> {
+        self.args
+            .iter()
+            .map(|pieces| {
+                let mut arg = String::new();
+                for piece in pieces {
+                    match piece {
+                        Piece::Text(text) => arg.push_str(text),
+                        Piece::Variable(name) => {
+                            let value = variable(target, name).ok_or_else(|| {
+                                SshpassError::InvalidArguments(format!(
+                                    "{} has no value for {{{}}} in the command",
+                                    target.host, name
+                                ))
+                            })?;
+                            arg.push_str(&quote(&value));
+                        }
+                    }
+                }
+                Ok(arg)
+            })
+            .collect()
+    }
+}
+
+/// The value of variable `name` for `target`
+fn variable(target: &Target, name: &str) -> Option<String> {
+    match name {
+        "host" => Some(target.host.clone()),
+        "group" => Some(target.group.clone()),
+        "user" => target.settings.user.clone(),
+        "port" => target.settings.port.map(|port| port.to_string()),
+        _ => target.settings.vars.get(name).cloned(),
+    }
+}
+
+/// `value` in single quotes, as one word of a POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Split one argument into text and variables
+fn parse_arg(arg: &str) -> std::result::Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = arg.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                        Some(c) => return Err(format!("unexpected '{}' in a variable name", c)),
+                        None => return Err("unclosed '{'".to_string()),
+                    }
+                }
+                if name.is_empty() {
+                    return Err("empty variable name".to_string());
+                }
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(Piece::Variable(name));
+            }
+            '}' => return Err("unmatched '}', write '}}' for a brace".to_string()),
+            ch => text.push(ch),
+        }
+    }
+    if !text.is_empty() || pieces.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Inventory;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    fn target(line: &str) -> Target {
+        let inventory = Inventory::parse(&format!("[web]\n{}\n[web:vars]\nvar.env=prod", line));
+        inventory.unwrap().targets("all").unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = Template::parse(&args(&[
+            "deploy",
+            "--to={user}@{host}:{port}",
+            "{group}/{env}/{role}",
+            "",
+        ]))
+        .unwrap();
+        assert_eq!(
+            template.variables(),
+            ["user", "host", "port", "group", "env", "role"]
+        );
+        let web1 = target("web1 user=deploy port=2222 var.role=frontend var.env=staging");
+        assert_eq!(
+            template.render(&web1).unwrap(),
+            args(&[
+                "deploy",
+                "--to=deploy@web1:2222",
+                "web/staging/frontend",
+                ""
+            ])
+        );
+        let error = template.render(&target("web2")).unwrap_err().to_string();
+        assert!(error.contains("web2 has no value for {user}"));
+    }
+
+    #[test]
+    fn test_values_stay_one_argument() {
+        let template = Template::parse(&args(&["echo", "{note}"])).unwrap();
+        let tricky = "a b; rm -rf / $(id) 'q' \"{host}\"";
+        let mut web1 = target("web1");
+        web1.settings
+            .vars
+            .insert("note".to_string(), tricky.to_string());
+        // Not split, not interpreted, and not expanded again
+        assert_eq!(template.render(&web1).unwrap(), args(&["echo", tricky]));
+        // Nor by the shell of the host
+        let remote = template.render_remote(&web1).unwrap().join(" ");
+        assert_eq!(remote, r#"echo 'a b; rm -rf / $(id) '\''q'\'' "{host}"'"#);
+        #[cfg(unix)]
+        {
+            let output = std::process::Command::new("sh")
+                .args(["-c", &format!("printf %s {}", &remote[5..])])
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), tricky);
+        }
+    }
+
+    #[test]
+    fn test_template_escapes_and_errors() {
+        let template = Template::parse(&args(&["awk", "{{print ${n}}}"])).unwrap();
+        let mut web1 = target("web1");
+        web1.settings.vars.insert("n".to_string(), "2".to_string());
+        assert_eq!(
+            template.render(&web1).unwrap(),
+            args(&["awk", "{print $2}"])
+        );
+
+        let error = |arg: &str| Template::parse(&args(&[arg])).unwrap_err().to_string();
+        assert!(error("{host").contains("unclosed '{'"));
+        assert!(error("{}").contains("empty variable name"));
+        assert!(error("{a b}").contains("unexpected ' '"));
+        assert!(error("a}b").contains("unmatched '}'"));
+        assert!(error("{{host}").contains("unmatched '}'"));
+    }
+}
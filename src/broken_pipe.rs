@@ -0,0 +1,141 @@
+//! Our standard output going away (`--on-broken-pipe`)
+//!
+//! Rust ignores SIGPIPE, so when the reader of sshpass's stdout exits
+//! (`sshpass ... | head`), writes fail with EPIPE instead of killing the
+//! process. With `exit`, the default, the session then ends with an error
+//! the way the rest of a pipeline would; with `ignore`, sshpass stops writing
+//! to stdout and lets the session run to its end. The child is spawned with
+//! SIGPIPE back at its default action, so our disposition does not leak into
+//! the programs sshpass runs.
+
+use crate::error::{Result, SshpassError};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// `--on-broken-pipe` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum BrokenPipeMode {
+    /// End the session with an error
+    #[default]
+    Exit,
+    /// Stop writing to stdout and keep the session going
+    Ignore,
+}
+
+static IGNORE: AtomicBool = AtomicBool::new(false);
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Choose what [`write_stdout`] does once stdout is closed
+pub fn set_mode(mode: BrokenPipeMode) {
+    IGNORE.store(mode == BrokenPipeMode::Ignore, Ordering::Relaxed);
+}
+
+/// Write `data` to stdout and flush it
+///
+/// Once the reader has gone away, this fails with `exit` and does nothing
+/// with `ignore`. Other errors are ignored as before.
+pub fn write_stdout(data: &[u8]) -> Result<()> {
+    write_to(&mut std::io::stdout().lock(), data, mode(), &STDOUT_CLOSED)
+}
+
+/// The mode [`set_mode`] chose
+pub(crate) fn mode() -> BrokenPipeMode {
+    if IGNORE.load(Ordering::Relaxed) {
+        BrokenPipeMode::Ignore
+    } else {
+        BrokenPipeMode::Exit
+    }
+}
+
+/// Write `data` to `writer` and flush it, `closed` recording that its reader
+/// has gone away
+pub(crate) fn write_to(
+    writer: &mut impl Write,
+    data: &[u8],
+    mode: BrokenPipeMode,
+    closed: &AtomicBool,
+) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    if !closed.load(Ordering::Relaxed) {
+        match writer.write_all(data).and_then(|_| writer.flush()) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                closed.store(true, Ordering::Relaxed);
+            }
+            _ => return Ok(()),
+        }
+    }
+    match mode {
+        BrokenPipeMode::Exit => Err(SshpassError::RuntimeError(
+            "Standard output was closed".to_string(),
+        )),
+        BrokenPipeMode::Ignore => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts `capacity` writes, then fails like a pipe without a reader
+    struct Pipe {
+        data: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.capacity == 0 {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            self.capacity -= 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_broken_pipe_modes() {
+        for mode in [BrokenPipeMode::Exit, BrokenPipeMode::Ignore] {
+            let mut pipe = Pipe {
+                data: Vec::new(),
+                capacity: 1,
+            };
+            let closed = AtomicBool::new(false);
+            assert!(write_to(&mut pipe, b"one", mode, &closed).is_ok());
+            assert!(!closed.load(Ordering::Relaxed));
+
+            let second = write_to(&mut pipe, b"two", mode, &closed);
+            assert!(closed.load(Ordering::Relaxed));
+            assert_eq!(second.is_err(), mode == BrokenPipeMode::Exit);
+
+            // Nothing more is written once the reader is gone
+            pipe.capacity = 1;
+            let third = write_to(&mut pipe, b"three", mode, &closed);
+            assert_eq!(third.is_err(), mode == BrokenPipeMode::Exit);
+            assert_eq!(pipe.data, b"one");
+        }
+    }
+
+    #[test]
+    fn test_other_errors_are_ignored() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::Other.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let closed = AtomicBool::new(false);
+        assert!(write_to(&mut Full, b"x", BrokenPipeMode::Exit, &closed).is_ok());
+        assert!(!closed.load(Ordering::Relaxed));
+    }
+}
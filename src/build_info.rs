@@ -0,0 +1,97 @@
+//! Build details reported by `--version --build-info`
+//!
+//! Lets users of prebuilt binaries (for example a static musl build in a
+//! container) check which PTY backend and optional features they got.
+
+/// Target triple sshpass was compiled for
+pub const TARGET: &str = match option_env!("SSHPASS_BUILD_TARGET") {
+    Some(target) => target,
+    None => "unknown",
+};
+
+/// PTY implementation compiled into this build
+pub fn pty_backend() -> &'static str {
+    #[cfg(unix)]
+    {
+        "posix_openpt (Unix98 PTY)"
+    }
+    #[cfg(windows)]
+    {
+        "ConPTY (portable-pty)"
+    }
+}
+
+/// Optional cargo features enabled in this build
+pub fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "cli") {
+        features.push("cli");
+    }
+    if cfg!(feature = "profile-files") {
+        features.push("profile-files");
+    }
+    if cfg!(feature = "sandbox") {
+        features.push("sandbox");
+    }
+    if cfg!(feature = "plugins") {
+        features.push("plugins");
+    }
+    if cfg!(feature = "scripting") {
+        features.push("scripting");
+    }
+    if cfg!(feature = "compression") {
+        features.push("compression");
+    }
+    if cfg!(feature = "encryption") {
+        features.push("encryption");
+    }
+    if cfg!(feature = "no-verbose-strings") {
+        features.push("no-verbose-strings");
+    }
+    features
+}
+
+/// Text printed by `--version`, with build details when `build_info` is set
+pub fn version_text(build_info: bool) -> String {
+    let mut text = format!("sshpass {}\n", env!("CARGO_PKG_VERSION"));
+    if build_info {
+        let linkage = if cfg!(target_feature = "crt-static") {
+            "static"
+        } else {
+            "dynamic"
+        };
+        let features = features();
+        text.push_str(&format!("target: {} ({})\n", TARGET, linkage));
+        text.push_str(&format!("pty backend: {}\n", pty_backend()));
+        text.push_str(&format!(
+            "features: {}\n",
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            }
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_text() {
+        assert_eq!(
+            version_text(false),
+            format!("sshpass {}\n", env!("CARGO_PKG_VERSION"))
+        );
+
+        let info = version_text(true);
+        assert!(info.contains(&format!("target: {}", TARGET)));
+        assert!(info.contains(pty_backend()));
+        assert_eq!(
+            info.contains("profile-files"),
+            cfg!(feature = "profile-files")
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Command lines written for the original sshpass
+//!
+//! [`parse_compat`] follows the getopt grammar of the C sshpass
+//! (`+f:d:p:P:he::vV`) so tools embedding this crate read legacy invocations
+//! exactly as sshpass does:
+//!
+//! - short options can be grouped (`-vv`, `-vp secret`)
+//! - `-f`, `-d`, `-p` and `-P` take the rest of the argument (`-psecret`) or,
+//!   when nothing follows, the next argument even if it starts with `-`
+//! - `-e` only takes an attached value (`-eVAR`); a bare `-e` means `SSHPASS`
+//! - `--` ends the options, and so does the first argument that is not one
+//! - `-h` and `-V` end parsing, like the C version printing and exiting
+//!
+//! The clap parser of the full build accepts many more options and splits
+//! the command line differently; [`Cli::absorb_inline_password`] and
+//! [`Cli::absorb_env_var`] mend the cases where that matters, with the
+//! helpers at the end of this module.
+//!
+//! [`Cli::absorb_inline_password`]: super::Cli::absorb_inline_password
+//! [`Cli::absorb_env_var`]: super::Cli::absorb_env_var
+
+use crate::error::{Result, SshpassError};
+use crate::password::{PasswordSource, SecureString};
+use std::ffi::{OsStr, OsString};
+use zeroize::Zeroize;
+
+/// A parsed sshpass command line
+#[derive(Debug)]
+pub struct Config {
+    /// Where the password comes from; standard input unless an option names
+    /// a source
+    pub source: PasswordSource,
+    /// Password prompt to search for (`-P`)
+    pub prompt: Option<String>,
+    /// How many times `-v` was given
+    pub verbose: u8,
+    /// `-h` was given; nothing after it was parsed
+    pub help: bool,
+    /// `-V` was given; nothing after it was parsed
+    pub version: bool,
+    /// Command to run and its arguments
+    pub command: Vec<String>,
+}
+
+/// Parse an sshpass command line, the first argument being the program name
+///
+/// Arguments holding a password are wiped once parsed.
+///
+/// # Errors
+/// * `InvalidArguments` - Unknown option, missing value or no command
+/// * `ConflictingArguments` - More than one password source
+pub fn parse_compat<I, S>(argv: I) -> Result<Config>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    parse_argv(argv.into_iter().map(Into::into), false).map(|(config, _)| config)
+}
+
+/// Parse with the sshpass grammar, optionally also accepting the
+/// `--name[=value]` spellings of the minimal build
+///
+/// Returns the configuration and whether `--build-info` was given.
+pub(super) fn parse_argv(
+    args: impl IntoIterator<Item = String>,
+    long_options: bool,
+) -> Result<(Config, bool)> {
+    let mut parser = Parser {
+        config: Config {
+            source: PasswordSource::Stdin,
+            prompt: None,
+            verbose: 0,
+            help: false,
+            version: false,
+            command: Vec::new(),
+        },
+        source_given: false,
+    };
+    let mut build_info = false;
+    let mut args = args.into_iter().skip(1);
+
+    while let Some(mut arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+
+        let done = match arg.strip_prefix("--").filter(|_| long_options) {
+            Some("build-info") => {
+                build_info = true;
+                false
+            }
+            Some(long) => {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let option = match name {
+                    "verbose" => 'v',
+                    "help" => 'h',
+                    "version" => 'V',
+                    // --env only takes a value with '='
+                    "env" => 'e',
+                    "file" => 'f',
+                    "fd" => 'd',
+                    "password" => 'p',
+                    "prompt" => 'P',
+                    _ => {
+                        return Err(SshpassError::InvalidArguments(format!(
+                            "unrecognized option '--{}'",
+                            name
+                        )))
+                    }
+                };
+                let value = match value {
+                    None if takes_value(option) => {
+                        Some(args.next().ok_or_else(|| missing_value(option))?)
+                    }
+                    value => value,
+                };
+                parser.apply(option, value)?
+            }
+            None => {
+                let Some(shorts) = arg.strip_prefix('-').filter(|s| !s.is_empty()) else {
+                    // The command starts here
+                    parser.config.command.push(arg);
+                    break;
+                };
+
+                let mut done = false;
+                for (i, c) in shorts.char_indices() {
+                    let rest = &shorts[i + c.len_utf8()..];
+                    let value = if c == 'e' {
+                        Some(rest.to_string()).filter(|name| !name.is_empty())
+                    } else if takes_value(c) && rest.is_empty() {
+                        Some(args.next().ok_or_else(|| missing_value(c))?)
+                    } else if takes_value(c) {
+                        Some(rest.to_string())
+                    } else {
+                        None
+                    };
+                    done = parser.apply(c, value)?;
+                    if done || c == 'e' || takes_value(c) {
+                        break;
+                    }
+                }
+                done
+            }
+        };
+        // May hold an inline password
+        arg.zeroize();
+        if done {
+            return Ok((parser.config, build_info));
+        }
+    }
+    parser.config.command.extend(args);
+
+    if parser.config.command.is_empty() {
+        return Err(SshpassError::InvalidArguments(
+            "No command specified".to_string(),
+        ));
+    }
+    Ok((parser.config, build_info))
+}
+
+/// Whether the short option takes a value that may be a separate argument
+fn takes_value(option: char) -> bool {
+    matches!(option, 'f' | 'd' | 'p' | 'P')
+}
+
+fn missing_value(option: char) -> SshpassError {
+    SshpassError::InvalidArguments(format!("option requires an argument -- '{}'", option))
+}
+
+struct Parser {
+    config: Config,
+    source_given: bool,
+}
+
+impl Parser {
+    /// Apply one option, returning true when parsing ends here
+    fn apply(&mut self, option: char, value: Option<String>) -> Result<bool> {
+        let value = value.unwrap_or_default();
+        match option {
+            'v' => self.config.verbose = self.config.verbose.saturating_add(1),
+            'h' => {
+                self.config.help = true;
+                return Ok(true);
+            }
+            'V' => {
+                self.config.version = true;
+                return Ok(true);
+            }
+            'P' => self.config.prompt = Some(value),
+            'e' if value.is_empty() => {
+                self.set_source(PasswordSource::Env("SSHPASS".to_string()))?
+            }
+            'e' => self.set_source(PasswordSource::Env(value))?,
+            'f' => self.set_source(PasswordSource::File(value.into()))?,
+            #[cfg(unix)]
+            'd' => {
+                let fd = value.parse().map_err(|_| {
+                    SshpassError::InvalidArguments(format!("invalid file descriptor '{}'", value))
+                })?;
+                self.set_source(PasswordSource::Fd(fd))?
+            }
+            'p' => self.set_source(PasswordSource::Password(SecureString::new(value)))?,
+            _ => {
+                return Err(SshpassError::InvalidArguments(format!(
+                    "invalid option -- '{}'",
+                    option
+                )))
+            }
+        }
+        Ok(false)
+    }
+
+    /// Like the C version, a second password source is an error right away
+    fn set_source(&mut self, source: PasswordSource) -> Result<()> {
+        if self.source_given {
+            return Err(SshpassError::ConflictingArguments);
+        }
+        self.source_given = true;
+        self.config.source = source;
+        Ok(())
+    }
+}
+
+/// Determine whether the first command argument encodes "-ppassword".
+pub fn inline_password_from_command(
+    command_args: &[String],
+    raw_args: &[OsString],
+) -> Option<String> {
+    inline_value_from_command("-p", command_args, raw_args)
+}
+
+/// Determine whether the first command argument encodes "-eVAR" or "-e=VAR".
+pub fn inline_env_var_from_command(
+    command_args: &[String],
+    raw_args: &[OsString],
+) -> Option<String> {
+    inline_value_from_command("-e", command_args, raw_args)
+}
+
+/// Determine whether a bare "-e" is followed by the variable name, as in
+/// "-e VAR ssh host", rather than by the command.
+///
+/// The argument right after the "-e" is taken as the name only if it is a
+/// valid variable name that `is_set` reports as set and more arguments
+/// follow it; otherwise it stays the command and the variable is SSHPASS.
+pub fn separate_env_var_in_command(
+    command_args: &[String],
+    raw_args: &[OsString],
+    is_set: impl Fn(&str) -> bool,
+) -> bool {
+    let [name, _, ..] = command_args else {
+        return false;
+    };
+    if !is_env_var_name(name) || !is_set(name) {
+        return false;
+    }
+
+    // The command must start right after the "-e" in argv
+    let Some(position) = raw_args
+        .iter()
+        .skip(1)
+        .take_while(|arg| *arg != "--")
+        .position(|arg| arg.to_str().is_some_and(is_bare_env_option))
+    else {
+        return false;
+    };
+    raw_args[position + 2..]
+        .iter()
+        .map(|arg| arg.to_str())
+        .eq(command_args.iter().map(|arg| Some(arg.as_str())))
+}
+
+/// "-e", "--env" or short flags ending in "e" such as "-ve"
+fn is_bare_env_option(arg: &str) -> bool {
+    arg == "--env"
+        || arg
+            .strip_prefix('-')
+            .and_then(|flags| flags.strip_suffix('e'))
+            .is_some_and(|flags| flags.chars().all(|c| c == 'v'))
+}
+
+fn is_env_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The value of `option` given inline in the first command argument
+/// ("-ovalue" or "-o=value"), unless it follows "--"
+fn inline_value_from_command(
+    option: &str,
+    command_args: &[String],
+    raw_args: &[OsString],
+) -> Option<String> {
+    let first = command_args.first()?;
+    let value = parse_inline_token(option, first)?;
+
+    if inline_arg_after_double_dash(first, raw_args) {
+        return None;
+    }
+
+    Some(value)
+}
+
+/// Extract the value from an inline "-ovalue" or "-o=value" token.
+fn parse_inline_token(option: &str, token: &str) -> Option<String> {
+    let rest = token.strip_prefix(option)?;
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+    if rest.is_empty() {
+        return None;
+    }
+    Some(rest.to_string())
+}
+
+/// Check whether the token appeared after "--", meaning it belongs to the target command.
+fn inline_arg_after_double_dash(candidate: &str, raw_args: &[OsString]) -> bool {
+    let candidate_os = OsStr::new(candidate);
+    let mut after_double_dash = false;
+
+    for arg in raw_args.iter().skip(1) {
+        if arg == "--" {
+            after_double_dash = true;
+            continue;
+        }
+        if arg == candidate_os {
+            return after_double_dash;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config> {
+        parse_compat(std::iter::once("sshpass").chain(args.iter().copied()))
+    }
+
+    /// The source as a short string, e.g. `p:secret`
+    fn source(config: &Config) -> String {
+        match &config.source {
+            PasswordSource::Stdin => "stdin".to_string(),
+            PasswordSource::File(path) => format!("f:{}", path.display()),
+            #[cfg(unix)]
+            PasswordSource::Fd(fd) => format!("d:{}", fd),
+            PasswordSource::Password(password) => format!("p:{}", &**password),
+            PasswordSource::Credential(name) => format!("credential:{}", name),
+            #[cfg(unix)]
+            PasswordSource::ListenFd(name) => format!("fd-name:{}", name),
+            PasswordSource::Env(name) => format!("e:{}", name),
+        }
+    }
+
+    #[test]
+    fn test_compat_grammar() {
+        #[rustfmt::skip]
+        let cases: &[(&[&str], &str, &[&str])] = &[
+            // argv, password source, command
+            (&["ssh", "host"], "stdin", &["ssh", "host"]),
+            (&["-p", "secret", "ssh"], "p:secret", &["ssh"]),
+            (&["-psecret", "ssh"], "p:secret", &["ssh"]),
+            (&["-p", "-v", "ssh"], "p:-v", &["ssh"]),
+            (&["-p=x", "ssh"], "p:=x", &["ssh"]),
+            (&["-vpsecret", "ssh"], "p:secret", &["ssh"]),
+            (&["-f", "/tmp/pw", "ssh"], "f:/tmp/pw", &["ssh"]),
+            (&["-f/tmp/pw", "ssh"], "f:/tmp/pw", &["ssh"]),
+            (&["-e", "ssh"], "e:SSHPASS", &["ssh"]),
+            (&["-eMYPASS", "ssh"], "e:MYPASS", &["ssh"]),
+            (&["-ve", "ssh"], "e:SSHPASS", &["ssh"]),
+            (&["--", "-psecret", "ssh"], "stdin", &["-psecret", "ssh"]),
+            (&["ssh", "-p", "22", "host"], "stdin", &["ssh", "-p", "22", "host"]),
+            (&["-", "ssh"], "stdin", &["-", "ssh"]),
+            (&["-p", "s", "--", "--", "x"], "p:s", &["--", "x"]),
+        ];
+        for (argv, expected_source, command) in cases {
+            let config = parse(argv).unwrap_or_else(|e| panic!("{:?}: {}", argv, e));
+            assert_eq!(source(&config), *expected_source, "{:?}", argv);
+            assert_eq!(config.command, *command, "{:?}", argv);
+        }
+
+        #[cfg(unix)]
+        assert_eq!(source(&parse(&["-d3", "ssh"]).unwrap()), "d:3");
+    }
+
+    #[test]
+    fn test_compat_other_options() {
+        let config = parse(&["-vv", "-P", "PIN:", "-v", "ssh"]).unwrap();
+        assert_eq!(config.verbose, 3);
+        assert_eq!(config.prompt.as_deref(), Some("PIN:"));
+        assert!(!config.help && !config.version);
+
+        // -P is not a password source
+        let config = parse(&["-PPIN", "-p", "x", "ssh"]).unwrap();
+        assert_eq!(config.prompt.as_deref(), Some("PIN"));
+        assert_eq!(source(&config), "p:x");
+
+        // -h and -V stop parsing, even before errors
+        let config = parse(&["-v", "-h", "-x"]).unwrap();
+        assert!(config.help);
+        assert_eq!(config.verbose, 1);
+        assert!(parse(&["-vV"]).unwrap().version);
+        assert!(parse(&["-V", "--bogus"]).unwrap().version);
+    }
+
+    #[test]
+    fn test_compat_errors() {
+        for argv in [
+            &["-x", "ssh"][..],
+            &["--password=x", "ssh"],
+            &["--verbose", "ssh"],
+            &["-p"],
+            &["-vP"],
+            &["-v"],
+            &[],
+            &["-p", "x"],
+            &["-dfoo", "ssh"],
+        ] {
+            assert!(
+                matches!(parse(argv), Err(SshpassError::InvalidArguments(_))),
+                "{:?}",
+                argv
+            );
+        }
+        for argv in [
+            &["-p", "a", "-e", "ssh"][..],
+            &["-e", "-f", "pw", "ssh"],
+            &["-eX", "-eY", "ssh"],
+        ] {
+            assert!(
+                matches!(parse(argv), Err(SshpassError::ConflictingArguments)),
+                "{:?}",
+                argv
+            );
+        }
+    }
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn detects_inline_password_before_command() {
+        let command = vec!["-ppassword".to_string(), "ssh".to_string()];
+        let raw = os_args(&["sshpass", "-ppassword", "ssh", "example.com"]);
+        assert_eq!(
+            inline_password_from_command(&command, &raw),
+            Some("password".to_string())
+        );
+    }
+
+    #[test]
+    fn ignores_inline_password_after_double_dash() {
+        let command = vec!["-ppassword".to_string(), "echo".to_string()];
+        let raw = os_args(&["sshpass", "--", "-ppassword", "echo", "ok"]);
+        assert_eq!(inline_password_from_command(&command, &raw), None);
+    }
+
+    #[test]
+    fn parses_inline_password_with_equals() {
+        let command = vec!["-p=secret".to_string(), "ssh".to_string()];
+        let raw = os_args(&["sshpass", "-p=secret", "ssh", "example.com"]);
+        assert_eq!(
+            inline_password_from_command(&command, &raw),
+            Some("secret".to_string())
+        );
+    }
+
+    #[test]
+    fn detects_inline_env_var() {
+        let raw = os_args(&["sshpass", "-e=PW", "ssh", "host"]);
+        let command = vec!["-e=PW".to_string(), "ssh".to_string()];
+        assert_eq!(
+            inline_env_var_from_command(&command, &raw),
+            Some("PW".to_string())
+        );
+
+        let raw = os_args(&["sshpass", "-ePW", "ssh"]);
+        let command = vec!["-ePW".to_string(), "ssh".to_string()];
+        assert_eq!(
+            inline_env_var_from_command(&command, &raw),
+            Some("PW".to_string())
+        );
+        assert_eq!(inline_password_from_command(&command, &raw), None);
+    }
+
+    #[test]
+    fn separate_env_var_only_when_unambiguous() {
+        let set = |name: &str| name == "PW" || name == "ssh";
+        let check = |raw: &[&str]| {
+            let raw = os_args(raw);
+            let start = raw.len() - 2;
+            let command: Vec<String> = raw[start..]
+                .iter()
+                .map(|arg| arg.to_str().unwrap().to_string())
+                .collect();
+            separate_env_var_in_command(&command, &raw, set)
+        };
+
+        assert!(check(&["sshpass", "-e", "PW", "ssh"]));
+        assert!(check(&["sshpass", "-ve", "PW", "ssh"]));
+        assert!(check(&["sshpass", "--env", "PW", "ssh"]));
+        // Not set in the environment: the command
+        assert!(!check(&["sshpass", "-e", "OTHER", "ssh"]));
+        // Not a variable name
+        assert!(!check(&["sshpass", "-e", "ssh-add", "-l"]));
+        assert!(!check(&["sshpass", "-e", "./PW", "ssh"]));
+        // Not right after the -e
+        assert!(!check(&["sshpass", "-e", "-v", "PW", "ssh"]));
+        assert!(!check(&["sshpass", "-v", "PW", "ssh"]));
+        // After "--" it belongs to the command
+        assert!(!check(&["sshpass", "-e", "--", "PW", "ssh"]));
+        // A lone argument is always the command
+        let raw = os_args(&["sshpass", "-e", "PW"]);
+        assert!(!separate_env_var_in_command(&["PW".to_string()], &raw, set));
+    }
+
+    #[test]
+    fn returns_none_when_first_command_arg_not_password() {
+        let command = vec!["ssh".to_string(), "-p2222".to_string()];
+        let raw = os_args(&["sshpass", "ssh", "-p2222", "example.com"]);
+        assert_eq!(inline_password_from_command(&command, &raw), None);
+    }
+}
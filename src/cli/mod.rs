@@ -0,0 +1,1144 @@
+//! Command line options
+//!
+//! The full parser is generated by clap (the default `cli` feature). Minimal
+//! builds without it use a small hand-written parser that only understands
+//! the options of the original C sshpass; everything else keeps its default.
+//! That grammar is also available to library callers as [`parse_compat`].
+
+// Partly library API; the binary only uses what its parser needs
+#[allow(dead_code)]
+mod compat;
+#[cfg(any(test, not(feature = "cli")))]
+#[cfg_attr(feature = "cli", allow(dead_code))]
+mod tiny;
+mod validation;
+
+#[cfg(feature = "cli")]
+use crate::batch::parse_exit_policy;
+#[cfg(feature = "cli")]
+use crate::batch::report::parse_report;
+use crate::batch::report::Report;
+use crate::batch::ExitPolicy;
+use crate::broken_pipe::BrokenPipeMode;
+use crate::compression::Compression;
+#[cfg(feature = "cli")]
+use crate::encryption::parse_recipient;
+#[cfg(feature = "cli")]
+use crate::escape::parse_escape_char;
+use crate::escape::EscapeChar;
+#[cfg(feature = "cli")]
+use crate::exit_map::parse_exit_mapping;
+use crate::exit_map::ExitMapping;
+use crate::flow_control::FlowControlMode;
+#[cfg(feature = "cli")]
+use crate::input_source::parse_input;
+use crate::keymap::EnterMode;
+#[cfg(feature = "cli")]
+use crate::labels::parse_label;
+use crate::labels::Label;
+use crate::logging::ColorMode;
+use crate::panic_guard::ChildPolicy;
+#[cfg(feature = "cli")]
+use crate::profiles::parse_reply_bytes;
+use crate::profiles::ReplyBytes;
+use crate::progress::ProgressMode;
+use crate::recording::RecordFormat;
+use crate::ssh_env::SshEnvMode;
+use crate::stdin_eof::StdinEofMode;
+use crate::system_log::LogTarget;
+#[cfg(feature = "cli")]
+use crate::tee::parse_size;
+#[cfg(feature = "cli")]
+use clap::Parser;
+#[cfg(feature = "cli")]
+use std::ffi::OsString;
+use std::path::PathBuf;
+#[cfg(feature = "cli")]
+use zeroize::Zeroize;
+
+// Library API; the binary only needs part of it
+#[allow(unused_imports)]
+pub use compat::{
+    inline_env_var_from_command, inline_password_from_command, parse_compat,
+    separate_env_var_in_command, Config,
+};
+#[allow(unused_imports)]
+pub use validation::{Problem, ValidationErrors};
+
+/// sshpass - noninteractive ssh password provider
+///
+/// This is a Rust implementation of sshpass, a utility designed for running ssh
+/// using the mode referred to as "keyboard-interactive" password authentication,
+/// but in non-interactive mode.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "cli", derive(Parser))]
+#[cfg_attr(
+    feature = "cli",
+    command(
+        name = "sshpass",
+        version = "0.1.0",
+        disable_version_flag = true,
+        about = "Noninteractive ssh password provider",
+        long_about = "A Rust implementation of sshpass for automated SSH password authentication.\n\
+                  SSH uses direct TTY access to ensure passwords are issued by interactive users.\n\
+                  sshpass runs ssh in a dedicated PTY, allowing automated password entry."
+    )
+)]
+pub struct Cli {
+    /// Take password from file
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            short = 'f',
+            long = "file",
+            value_name = "filename",
+            group = "password_source"
+        )
+    )]
+    pub password_file: Option<PathBuf>,
+
+    /// Use number as file descriptor for getting password (Unix only)
+    #[cfg(unix)]
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            short = 'd',
+            long = "fd",
+            value_name = "number",
+            group = "password_source"
+        )
+    )]
+    pub password_fd: Option<i32>,
+
+    /// Use the file descriptor systemd passed under this name (LISTEN_FDNAMES)
+    /// for getting password (Unix only)
+    #[cfg(unix)]
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "password-fd-name",
+            value_name = "NAME",
+            group = "password_source"
+        )
+    )]
+    pub password_fd_name: Option<String>,
+
+    /// Provide password as argument (security unwise)
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            short = 'p',
+            long = "password",
+            value_name = "password",
+            group = "password_source"
+        )
+    )]
+    pub password: Option<String>,
+
+    /// Password is passed as env-var "SSHPASS" or specified variable
+    #[cfg_attr(feature = "cli", arg(
+        short = 'e',
+        long = "env",
+        value_name = "env_var",
+        group = "password_source",
+        num_args = 0..=1,
+        default_missing_value = "SSHPASS",
+        require_equals = true
+    ))]
+    pub env_var: Option<String>,
+
+    /// Take password from the systemd credential NAME in $CREDENTIALS_DIRECTORY
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "credential", value_name = "NAME", group = "password_source")
+    )]
+    pub credential: Option<String>,
+
+    /// Refuse a password file others can read or replace, rather than only
+    /// warning about it
+    #[cfg_attr(feature = "cli", arg(long = "strict-perms"))]
+    pub strict_perms: bool,
+
+    /// Which string should sshpass search for to detect a password prompt
+    #[cfg_attr(
+        feature = "cli",
+        arg(short = 'P', long = "prompt", value_name = "prompt")
+    )]
+    pub prompt: Option<String>,
+
+    /// Coalesce forwarded keystrokes arriving within this many milliseconds into one PTY write
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "input-coalesce", value_name = "ms", default_value_t = 0)
+    )]
+    pub input_coalesce_ms: u64,
+
+    /// What the Enter key sends to the remote side when forwarding console input
+    #[cfg_attr(feature = "cli", arg(long = "enter-sends", value_name = "mode", value_enum, default_value_t = EnterMode::Lf))]
+    pub enter_sends: EnterMode,
+
+    /// Send pasted input with plain LF newlines instead of translated Enter keys
+    #[cfg_attr(feature = "cli", arg(long = "paste-safe"))]
+    pub paste_safe: bool,
+
+    /// Escape character for the local session menu ("none" disables it)
+    #[cfg_attr(feature = "cli", arg(
+        long = "escape-char",
+        value_name = "char",
+        default_value = "~",
+        value_parser = parse_escape_char
+    ))]
+    pub escape_char: EscapeChar,
+
+    /// Whether to honor XON/XOFF flow control from the remote side
+    #[cfg_attr(feature = "cli", arg(long = "flow-control", value_name = "mode", value_enum, default_value_t = FlowControlMode::Ignore))]
+    pub flow_control: FlowControlMode,
+
+    /// When the forwarded stdin ends, leave the session running (keep-open),
+    /// type Ctrl-D (send-eof) or hang up on the child (close-session)
+    #[cfg_attr(feature = "cli", arg(long = "on-stdin-eof", value_name = "mode", value_enum, default_value_t = StdinEofMode::KeepOpen))]
+    pub on_stdin_eof: StdinEofMode,
+
+    /// Seconds the child may run on after --on-stdin-eof passed the end of
+    /// stdin on before it is hung up on, then terminated, then killed
+    /// [default: wait for it]
+    #[cfg_attr(feature = "cli", arg(long = "drain-timeout", value_name = "seconds"))]
+    pub drain_timeout: Option<u64>,
+
+    /// Shortly after logging in, change the window size and back so the
+    /// server learns it again (for servers that drop the size set before
+    /// the shell starts)
+    #[cfg_attr(feature = "cli", arg(long = "initial-resize-retry"))]
+    pub initial_resize_retry: bool,
+
+    /// After logging in, run this command in the same shell session (repeatable)
+    #[cfg_attr(feature = "cli", arg(long = "then", value_name = "cmd"))]
+    pub then: Vec<String>,
+
+    /// Read commands to run in the same shell session from a file, one per line
+    #[cfg_attr(feature = "cli", arg(long = "then-file", value_name = "filename"))]
+    pub then_file: Option<PathBuf>,
+
+    /// Answer prompts with an expect/send script ("-" reads it from stdin)
+    #[cfg_attr(feature = "cli", arg(long = "script", value_name = "filename"))]
+    pub script: Option<PathBuf>,
+
+    /// Drive the session with a Lua script (on_output/on_prompt/on_exit callbacks)
+    #[cfg_attr(feature = "cli", arg(long = "lua-script", value_name = "filename"))]
+    pub lua_script: Option<PathBuf>,
+
+    /// Forward input from this source instead of the terminal: tty, pipe,
+    /// none, or on Unix fd:N, unix:PATH or tcp:HOST:PORT
+    #[cfg_attr(feature = "cli", arg(long = "input", value_name = "source", value_parser = parse_input))]
+    pub input: Option<String>,
+
+    /// Write the output after the password prompt here instead of stdout:
+    /// stdout, null, a file, fd:N, unix:PATH or tcp:HOST:PORT (repeatable)
+    #[cfg_attr(feature = "cli", arg(long = "output", value_name = "target"))]
+    pub output: Vec<String>,
+
+    /// Also write the output after the password prompt to this file, or to
+    /// fd:N (repeatable)
+    #[cfg_attr(feature = "cli", arg(long = "tee", value_name = "target"))]
+    pub tee: Vec<String>,
+
+    /// Write the --tee targets as they are (raw), as an asciinema recording,
+    /// or as a script(1) typescript with its timing in TARGET.timing
+    #[cfg_attr(feature = "cli", arg(long = "record-format", value_name = "format", value_enum, default_value_t = RecordFormat::Raw))]
+    pub record_format: RecordFormat,
+
+    /// Rotate a --tee file once this much was written to it (bytes, or with a
+    /// K, M or G suffix); other targets stop there
+    #[cfg_attr(feature = "cli", arg(
+        long = "record-max-size",
+        value_name = "size",
+        value_parser = parse_size
+    ))]
+    pub record_max_size: Option<u64>,
+
+    /// Keep this many rotated copies of each --tee file (0 stops writing at
+    /// --record-max-size instead)
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "record-rotate", value_name = "count", default_value_t = 0)
+    )]
+    pub record_rotate: u32,
+
+    /// Compress the --tee targets as they are written
+    #[cfg_attr(feature = "cli", arg(long = "record-compress", value_name = "method", value_enum, default_value_t = Compression::None))]
+    pub record_compress: Compression,
+
+    /// Encrypt the --tee targets to this age recipient (age1..., repeatable)
+    #[cfg_attr(feature = "cli", arg(
+        long = "record-recipient",
+        value_name = "recipient",
+        value_parser = parse_recipient
+    ))]
+    pub record_recipient: Vec<String>,
+
+    /// Append hash-chained records of the session to this file
+    #[cfg_attr(feature = "cli", arg(long = "audit-log", value_name = "filename"))]
+    pub audit_log: Option<PathBuf>,
+
+    /// Where audit records go: the --audit-log file, syslog (Unix) or the
+    /// Windows event log, which also get the session's progress
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "log-target", value_name = "target", value_enum, default_value_t = LogTarget::File)
+    )]
+    pub log_target: LogTarget,
+
+    /// Print a JSON array of per-command results for --then/--then-file
+    #[cfg_attr(feature = "cli", arg(long = "json"))]
+    pub json: bool,
+
+    /// Prompt profile (openssh, keytools, sudo, telnet, cisco-ios, junos, mikrotik, fortinet or a user profile)
+    #[cfg_attr(feature = "cli", arg(long = "profile", value_name = "name"))]
+    pub profile: Option<String>,
+
+    /// Load a profile from a TOML file, layered over --profile
+    #[cfg_attr(feature = "cli", arg(long = "profile-file", value_name = "filename"))]
+    pub profile_file: Option<PathBuf>,
+
+    /// Do not pick the profile from the command name (keytools for
+    /// ssh-add/ssh-keygen, sudo for sudo/su/doas, telnet for telnet)
+    #[cfg_attr(feature = "cli", arg(long = "no-auto-detect"))]
+    pub no_auto_detect: bool,
+
+    /// With the sudo profile, set SUDO_PROMPT to a fixed prompt sshpass looks
+    /// for, so the prompt is found in any locale
+    #[cfg_attr(feature = "cli", arg(long = "sudo-sentinel"))]
+    pub sudo_sentinel: bool,
+
+    /// Show a spinner on stderr while waiting for the password prompt (auto:
+    /// when stderr is a terminal and -v is off)
+    #[cfg_attr(feature = "cli", arg(long = "progress", value_name = "when", value_enum, default_value_t = ProgressMode::Auto))]
+    pub progress: ProgressMode,
+
+    /// Once the data forwarded after the login flows fast, show the bytes,
+    /// rate and elapsed time on stderr, like pv (when stderr is a terminal)
+    #[cfg_attr(feature = "cli", arg(long = "progress-io"))]
+    pub progress_io: bool,
+
+    /// Give up if the password prompt does not appear within this many seconds
+    #[cfg_attr(feature = "cli", arg(long = "prompt-timeout", value_name = "seconds"))]
+    pub prompt_timeout: Option<u64>,
+
+    /// Change an expired password to the one read from SOURCE
+    /// (env:NAME, file:PATH, fd:N or credential:NAME)
+    #[cfg_attr(feature = "cli", arg(long = "handle-expiry", value_name = "source"))]
+    pub handle_expiry: Option<String>,
+
+    /// Acknowledge a banner (e.g. "Press any key to continue") when this text appears
+    #[cfg_attr(feature = "cli", arg(long = "ack-banner", value_name = "pattern"))]
+    pub ack_banner: Option<String>,
+
+    /// Bytes sent to acknowledge the banner instead of a newline (\r, \n, \xHH escapes)
+    #[cfg_attr(feature = "cli", arg(
+        long = "ack-send",
+        value_name = "bytes",
+        requires = "ack_banner",
+        value_parser = parse_reply_bytes
+    ))]
+    pub ack_send: Option<ReplyBytes>,
+
+    /// Fail with exit code 8 if the child exits before a password prompt is seen
+    #[cfg_attr(feature = "cli", arg(long = "require-prompt"))]
+    pub require_prompt: bool,
+
+    /// Exit with TO when the child exits with FROM, e.g. 24=0 for rsync's
+    /// vanished files (repeatable)
+    #[cfg_attr(feature = "cli", arg(
+        long = "map-exit-code",
+        value_name = "from=to",
+        value_parser = parse_exit_mapping
+    ))]
+    pub map_exit_code: Vec<ExitMapping>,
+
+    /// On exit, write a JSON line saying whether the exit code came from the
+    /// child (and which signal killed it) or from sshpass, to this file or to
+    /// fd:N
+    #[cfg_attr(feature = "cli", arg(long = "status-channel", value_name = "target"))]
+    pub status_channel: Option<String>,
+
+    /// On exit, write the --status-channel object with the prompt the
+    /// password was typed at (pattern, offset and the output before it), to
+    /// this file or to fd:N
+    #[cfg_attr(feature = "cli", arg(long = "json-result", value_name = "target"))]
+    pub json_result: Option<String>,
+
+    /// Add the child's CPU time and peak memory to --status-channel,
+    /// --json-result and -v output
+    #[cfg_attr(feature = "cli", arg(long = "rusage"))]
+    pub rusage: bool,
+
+    /// Attach KEY=VALUE, with a generated session ID, to the audit, status
+    /// channel and system log records and to every diagnostic (repeatable)
+    #[cfg_attr(feature = "cli", arg(
+        long = "label",
+        value_name = "key=value",
+        value_parser = parse_label
+    ))]
+    pub label: Vec<Label>,
+
+    /// Pass SSH_AUTH_SOCK, SSH_AGENT_PID, SSH_ASKPASS and SSH_ASKPASS_REQUIRE
+    /// to the child (preserve), or remove them so ssh can only use the
+    /// password (scrub)
+    #[cfg_attr(feature = "cli", arg(long = "ssh-env", value_name = "mode", value_enum, default_value_t = SshEnvMode::Preserve))]
+    pub ssh_env: SshEnvMode,
+
+    /// Treat a repeated password prompt as a wrong password only within this
+    /// many seconds of sending it and before the login looks successful
+    #[cfg_attr(feature = "cli", arg(long = "reauth-window", value_name = "seconds"))]
+    pub reauth_window: Option<u64>,
+
+    /// Stop looking for the password prompt and ssh's messages once the login
+    /// succeeded or this many seconds passed after the password without a
+    /// re-prompt (0 keeps looking for the whole session) [default: 10]
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "stop-matching-after-auth", value_name = "seconds")
+    )]
+    pub stop_matching_after_auth: Option<u64>,
+
+    /// Answer up to this many password prompts with the same password [default: 1]
+    #[cfg_attr(feature = "cli", arg(long = "max-attempts", value_name = "count"))]
+    pub max_attempts: Option<u32>,
+
+    /// Milliseconds to ignore the password prompt after sending a password
+    /// that contains it, as a device echoing the password would seem to ask
+    /// again; 0 refuses such a password instead [default: 1000]
+    #[cfg_attr(feature = "cli", arg(long = "echo-guard", value_name = "ms"))]
+    pub echo_guard: Option<u64>,
+
+    /// Match prompts on the raw output, without removing escape sequences,
+    /// for large plain-text streams whose prompt is known to be clean
+    #[cfg_attr(feature = "cli", arg(long = "no-ansi-filter"))]
+    pub no_ansi_filter: bool,
+
+    /// Check the password before using it: nonempty, printable, len:MIN-MAX
+    /// or regex:PATTERN (repeatable)
+    #[cfg_attr(feature = "cli", arg(long = "validate-password", value_name = "rule"))]
+    pub validate_password: Vec<String>,
+
+    /// Do not try a host again after this many incorrect passwords within
+    /// --lockout-window [default: 3]
+    #[cfg_attr(feature = "cli", arg(long = "lockout-attempts", value_name = "count"))]
+    pub lockout_attempts: Option<u32>,
+
+    /// Minutes an incorrect password counts against its host [default: 10]
+    #[cfg_attr(feature = "cli", arg(long = "lockout-window", value_name = "minutes"))]
+    pub lockout_window: Option<u64>,
+
+    /// Keep trying hosts however many incorrect passwords they have seen
+    #[cfg_attr(feature = "cli", arg(long = "no-lockout-guard"))]
+    pub no_lockout_guard: bool,
+
+    /// Seconds to wait for a security key confirmation to be answered (0 waits forever) [default: 60]
+    #[cfg_attr(feature = "cli", arg(long = "confirm-timeout", value_name = "seconds"))]
+    pub confirm_timeout: Option<u64>,
+
+    /// Send a desktop notification when the login fails or a security key
+    /// confirmation waits longer than --notify-after
+    #[cfg_attr(feature = "cli", arg(long = "notify"))]
+    pub notify: bool,
+
+    /// Seconds a security key confirmation may wait before --notify sends a
+    /// notification [default: 30]
+    #[cfg_attr(feature = "cli", arg(long = "notify-after", value_name = "seconds"))]
+    pub notify_after: Option<u64>,
+
+    /// When running ssh, scp or sftp, add -o options that make the login
+    /// independent of ssh_config (NumberOfPasswordPrompts from --max-attempts,
+    /// PreferredAuthentications=password,keyboard-interactive,
+    /// StrictHostKeyChecking=yes); options already on the command line win
+    #[cfg_attr(feature = "cli", arg(long = "ssh-hardened"))]
+    pub ssh_hardened: bool,
+
+    /// Do not add this option with --ssh-hardened (repeatable)
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "ssh-hardened-skip",
+            value_name = "option",
+            requires = "ssh_hardened"
+        )
+    )]
+    pub ssh_hardened_skip: Vec<String>,
+
+    /// When running ssh, have it log to a separate pipe (-E /dev/fd/N) so its
+    /// diagnostics are labelled on stderr instead of mixed into the session
+    #[cfg(unix)]
+    #[cfg_attr(feature = "cli", arg(long = "ssh-stderr-pipe"))]
+    pub ssh_stderr_pipe: bool,
+
+    /// Keep the ssh connection open this many seconds for later invocations
+    /// to reuse without a password (ControlMaster); `sshpass persist stop`
+    /// closes them
+    #[cfg(unix)]
+    #[cfg_attr(feature = "cli", arg(long = "persist", value_name = "seconds"))]
+    pub persist: Option<u64>,
+
+    /// Run the command on each host of this inventory file, over ssh,
+    /// instead of running it once
+    #[cfg_attr(feature = "cli", arg(long = "inventory", value_name = "filename"))]
+    pub inventory: Option<PathBuf>,
+
+    /// Group or host of the --inventory to run against [default: all]
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "group", value_name = "name", requires = "inventory")
+    )]
+    pub group: Option<String>,
+
+    /// Hosts of the --inventory to run at the same time [default: 10]
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "max-parallel", value_name = "count", requires = "inventory")
+    )]
+    pub max_parallel: Option<usize>,
+
+    /// Start no further host of the --inventory after the first failure
+    #[cfg_attr(feature = "cli", arg(long = "fail-fast", requires = "inventory"))]
+    pub fail_fast: bool,
+
+    /// Start no further host of the --inventory after this many failures
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "max-failures", value_name = "count", requires = "inventory")
+    )]
+    pub max_failures: Option<usize>,
+
+    /// Cancel the session of a host of the --inventory still running after
+    /// this many seconds
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "host-timeout", value_name = "seconds", requires = "inventory")
+    )]
+    pub host_timeout: Option<u64>,
+
+    /// When the --inventory batch fails: any-failed, percent:N (fewer than
+    /// N percent of the hosts succeeded) or always-zero
+    #[cfg_attr(feature = "cli", arg(
+        long = "batch-exit",
+        value_name = "policy",
+        requires = "inventory",
+        default_value = "any-failed",
+        value_parser = parse_exit_policy
+    ))]
+    pub batch_exit: ExitPolicy,
+
+    /// When the --inventory batch ends, write its results as json, junit
+    /// or csv to PATH (repeatable)
+    #[cfg_attr(feature = "cli", arg(
+        long = "report",
+        value_name = "format:path",
+        requires = "inventory",
+        value_parser = parse_report
+    ))]
+    pub report: Vec<Report>,
+
+    /// Keep the results of the --inventory batch in this file as hosts
+    /// finish, for --resume
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "state-file", value_name = "filename", requires = "inventory")
+    )]
+    pub state_file: Option<PathBuf>,
+
+    /// Run only the hosts that did not succeed in the --state-file, as when
+    /// the batch was interrupted
+    #[cfg_attr(feature = "cli", arg(long = "resume", requires = "state_file"))]
+    pub resume: bool,
+
+    /// Write the output of each host of the --inventory to DIR/GROUP/HOST.log
+    /// instead of stdout, and an index of the logs to DIR/manifest.json
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "output-dir", value_name = "directory", requires = "inventory")
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    /// When the reader of stdout goes away, end the session (exit) or stop
+    /// writing to stdout and let it finish (ignore)
+    #[cfg_attr(feature = "cli", arg(long = "on-broken-pipe", value_name = "mode", value_enum, default_value_t = BrokenPipeMode::Exit))]
+    pub on_broken_pipe: BrokenPipeMode,
+
+    /// If sshpass hits an internal error, terminate the child (kill) or
+    /// leave it running (detach); the terminal modes are restored either way
+    #[cfg_attr(feature = "cli", arg(long = "on-panic", value_name = "policy", value_enum, default_value_t = ChildPolicy::Kill))]
+    pub on_panic: ChildPolicy,
+
+    /// Restrict the sshpass parent with seccomp and Landlock after spawning (Linux)
+    #[cfg(unix)]
+    #[cfg_attr(feature = "cli", arg(long = "sandbox"))]
+    pub sandbox: bool,
+
+    /// Run without an attached console (services, scheduled tasks): read stdin as a pipe
+    #[cfg(windows)]
+    #[cfg_attr(feature = "cli", arg(long = "no-console"))]
+    pub no_console: bool,
+
+    /// Print version information
+    #[cfg_attr(feature = "cli", arg(short = 'V', long = "version"))]
+    pub version: bool,
+
+    /// With --version, also print the target, PTY backend and enabled features
+    #[cfg_attr(feature = "cli", arg(long = "build-info", requires = "version"))]
+    pub build_info: bool,
+
+    /// Color the diagnostics: prompt matches green, failures red (auto
+    /// follows NO_COLOR and CLICOLOR when stderr is a terminal)
+    #[cfg_attr(feature = "cli", arg(long = "color", value_name = "when", value_enum, default_value_t = ColorMode::Auto))]
+    pub color: ColorMode,
+
+    /// Be verbose about what you're doing
+    #[cfg_attr(feature = "cli", arg(short = 'v', long = "verbose", action = clap::ArgAction::Count))]
+    pub verbose: u8,
+
+    /// Command and its arguments to execute
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            required_unless_present = "version",
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )
+    )]
+    pub command: Vec<String>,
+}
+
+impl Cli {
+    /// Parse command line arguments
+    #[cfg(feature = "cli")]
+    pub fn parse_args() -> Self {
+        Self::parse_args_from(std::env::args_os().collect())
+    }
+
+    /// Parse `raw_args`, program name first, wiping them afterwards
+    #[cfg(feature = "cli")]
+    pub fn parse_args_from(mut raw_args: Vec<OsString>) -> Self {
+        let mut cli = Cli::parse_from(&raw_args);
+
+        // Allow "-ppassword" inline form (unless user forced command parsing via "--")
+        cli.absorb_inline_password(&raw_args);
+        cli.absorb_env_var(&raw_args);
+
+        // Our copy of argv may hold the password; it is not needed any more
+        wipe_os_strings(&mut raw_args);
+        cli
+    }
+
+    /// Parse command line arguments (minimal parser)
+    #[cfg(not(feature = "cli"))]
+    pub fn parse_args() -> Self {
+        match tiny::parse(std::env::args()) {
+            Ok(tiny::Parsed::Args(cli)) => *cli,
+            Ok(tiny::Parsed::Help) => {
+                print!("{}", tiny::USAGE);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                crate::diag!("{}", e);
+                eprint!("{}", tiny::USAGE);
+                std::process::exit(e.exit_code());
+            }
+        }
+    }
+
+    /// Validate the parsed arguments
+    ///
+    /// Runs every check and reports all the problems found, each with a
+    /// hint, rather than only the first.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        use crate::error::SshpassError;
+
+        let mut problems = ValidationErrors::default();
+        let invalid = |message: &str| SshpassError::InvalidArguments(message.to_string());
+
+        // Ensure at least one command is provided
+        if self.command.is_empty() {
+            problems.push(
+                invalid("No command specified"),
+                "put the command to run after the options, e.g. `sshpass -e ssh host`",
+            );
+        }
+
+        // clap allows one source; a Cli built by hand may have more
+        let sources = [
+            self.password_file.is_some(),
+            self.password.is_some(),
+            self.env_var.is_some(),
+            self.credential.is_some(),
+            #[cfg(unix)]
+            self.password_fd.is_some(),
+            #[cfg(unix)]
+            self.password_fd_name.is_some(),
+        ];
+        if sources.iter().filter(|&&set| set).count() > 1 {
+            problems.push(
+                SshpassError::ConflictingArguments,
+                "give the password with only one of -f, -d, -p, -e, --credential and \
+                 --password-fd-name",
+            );
+        }
+
+        // Validate file descriptor if provided (Unix only)
+        #[cfg(unix)]
+        {
+            if let Some(fd) = self.password_fd {
+                if fd < 0 {
+                    problems.push(
+                        SshpassError::InvalidFileDescriptor(fd),
+                        "pass a descriptor open for reading, e.g. `-d 3 3<passfile`",
+                    );
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(ref name) = self.password_fd_name {
+            problems.check(
+                crate::password::listen_fd(name),
+                "run sshpass from a systemd unit that passes the descriptor, named with \
+                 FileDescriptorName= (sockets) or FDNAME= (file descriptor store)",
+            );
+        }
+
+        // Validate file exists if provided
+        if let Some(ref path) = self.password_file {
+            if !path.exists() {
+                problems.push(
+                    SshpassError::PasswordFileError(format!(
+                        "File does not exist: {}",
+                        path.display()
+                    )),
+                    "check the path given to -f",
+                );
+            }
+        }
+
+        // Validate the systemd credential can be resolved
+        if let Some(ref name) = self.credential {
+            let hint = "run sshpass from a unit with LoadCredential= or SetCredential= for \
+                        this name";
+            if let Some(path) = problems.check(crate::password::credential_path(name), hint) {
+                if !path.exists() {
+                    problems.push(
+                        SshpassError::PasswordFileError(format!(
+                            "Credential does not exist: {}",
+                            path.display()
+                        )),
+                        hint,
+                    );
+                }
+            }
+        }
+
+        if let Some(ref spec) = self.handle_expiry {
+            problems.check(
+                crate::password::PasswordSource::from_spec(spec),
+                "give --handle-expiry the new password's source, e.g. env:NEW_PASSWORD",
+            );
+        }
+
+        if let Some(ref path) = self.profile_file {
+            if !cfg!(feature = "profile-files") {
+                problems.push(
+                    invalid(
+                        "--profile-file requires sshpass built with the `profile-files` feature",
+                    ),
+                    "rebuild with `--features profile-files`",
+                );
+            } else if !path.exists() {
+                problems.push(
+                    SshpassError::InvalidArguments(format!(
+                        "Profile file does not exist: {}",
+                        path.display()
+                    )),
+                    "check the path given to --profile-file",
+                );
+            }
+        }
+
+        if let Some(ref path) = self.then_file {
+            if !path.exists() {
+                problems.push(
+                    SshpassError::InvalidArguments(format!(
+                        "Command file does not exist: {}",
+                        path.display()
+                    )),
+                    "check the path given to --then-file",
+                );
+            }
+        }
+
+        if let Some(ref path) = self.script {
+            if path.as_os_str() == "-" {
+                let has_source = sources.contains(&true);
+                if !has_source {
+                    problems.push(
+                        invalid(
+                            "--script - reads stdin, so the password must come from -p, -f, -d, \
+                             -e, --credential or --password-fd-name",
+                        ),
+                        "add a password source, or give --script a file",
+                    );
+                }
+            } else if !path.exists() {
+                problems.push(
+                    SshpassError::InvalidArguments(format!(
+                        "Script does not exist: {}",
+                        path.display()
+                    )),
+                    "check the path given to --script, or use `-` to read the script from stdin",
+                );
+            }
+        }
+
+        if let Some(ref path) = self.lua_script {
+            if !cfg!(feature = "scripting") {
+                problems.push(
+                    invalid("--lua-script requires sshpass built with the `scripting` feature"),
+                    "rebuild with `--features scripting`",
+                );
+            } else if !path.exists() {
+                problems.push(
+                    SshpassError::InvalidArguments(format!(
+                        "Lua script does not exist: {}",
+                        path.display()
+                    )),
+                    "check the path given to --lua-script",
+                );
+            }
+        }
+
+        if self.notify && !cfg!(feature = "notifications") {
+            problems.push(
+                invalid("--notify requires sshpass built with the `notifications` feature"),
+                "rebuild with `--features notifications`",
+            );
+        }
+        if self.drain_timeout.is_some() && self.on_stdin_eof == StdinEofMode::KeepOpen {
+            problems.push(
+                invalid("--drain-timeout requires --on-stdin-eof send-eof or close-session"),
+                "add `--on-stdin-eof send-eof`",
+            );
+        }
+        if self.notify_after.is_some() && !self.notify {
+            problems.push(invalid("--notify-after requires --notify"), "add --notify");
+        }
+        if self.sudo_sentinel && self.prompt.is_some() {
+            problems.push(
+                invalid("--sudo-sentinel sets the prompt, it cannot be used with -P"),
+                "drop -P, or drop --sudo-sentinel to match the prompt yourself",
+            );
+        }
+
+        #[cfg(unix)]
+        if self.sandbox && !cfg!(all(target_os = "linux", feature = "sandbox")) {
+            problems.push(
+                invalid("--sandbox requires Linux and sshpass built with the `sandbox` feature"),
+                "rebuild with `--features sandbox` on Linux, or drop --sandbox",
+            );
+        }
+
+        let recording = self.record_format != RecordFormat::Raw
+            || self.record_compress != Compression::None
+            || !self.record_recipient.is_empty();
+        if self.tee.is_empty() && recording {
+            problems.push(
+                invalid("--record-format, --record-compress and --record-recipient require --tee"),
+                "add `--tee FILE` for the recording",
+            );
+        }
+        // The logs of --output-dir rotate like --tee files
+        let rotating = self.record_max_size.is_some() || self.record_rotate > 0;
+        if self.tee.is_empty() && self.output_dir.is_none() && rotating {
+            problems.push(
+                invalid("--record-max-size and --record-rotate require --tee or --output-dir"),
+                "add `--tee FILE` for the recording, or `--output-dir DIR` for the logs of a batch",
+            );
+        }
+        if self.record_rotate > 0 && self.record_max_size.is_none() {
+            problems.push(
+                invalid("--record-rotate requires --record-max-size"),
+                "add `--record-max-size SIZE`, e.g. 10M",
+            );
+        }
+        if self.record_compress != Compression::None && !cfg!(feature = "compression") {
+            problems.push(
+                invalid("--record-compress requires sshpass built with the `compression` feature"),
+                "rebuild with `--features compression`",
+            );
+        }
+        if !self.record_recipient.is_empty() && !cfg!(feature = "encryption") {
+            problems.push(
+                invalid("--record-recipient requires sshpass built with the `encryption` feature"),
+                "rebuild with `--features encryption`",
+            );
+        }
+        // Landlock does not allow renaming or creating files
+        #[cfg(unix)]
+        if self.sandbox && self.record_rotate > 0 {
+            problems.push(
+                invalid("--record-rotate cannot be used with --sandbox"),
+                "drop one of --record-rotate and --sandbox",
+            );
+        }
+        // The seccomp filter leaves no socket to reach D-Bus with
+        #[cfg(unix)]
+        if self.sandbox && self.notify {
+            problems.push(
+                invalid("--notify cannot be used with --sandbox"),
+                "drop one of --notify and --sandbox",
+            );
+        }
+
+        problems.check(
+            crate::system_log::check_target(self.log_target),
+            "use `--log-target file`, or the native log of this system",
+        );
+        if self.log_target != LogTarget::File && self.audit_log.is_some() {
+            problems.push(
+                invalid("--audit-log requires --log-target file"),
+                "drop --audit-log; the system log records the session already",
+            );
+        }
+
+        for rule in &self.validate_password {
+            problems.check(
+                crate::password_policy::Rule::parse(rule),
+                "use nonempty, printable, len:MIN-MAX or regex:PATTERN",
+            );
+        }
+
+        if self.lockout_attempts == Some(0) {
+            problems.push(
+                invalid("--lockout-attempts must be at least 1"),
+                "give a count of 1 or more",
+            );
+        }
+
+        if self.max_attempts == Some(0) {
+            problems.push(
+                invalid("--max-attempts must be at least 1"),
+                "give a count of 1 or more",
+            );
+        }
+
+        if self.max_parallel == Some(0) {
+            problems.push(
+                invalid("--max-parallel must be at least 1"),
+                "give a count of 1 or more",
+            );
+        }
+
+        if self.max_failures == Some(0) {
+            problems.push(
+                invalid("--max-failures must be at least 1"),
+                "give a count of 1 or more, or --fail-fast to stop at the first failure",
+            );
+        }
+
+        if self.host_timeout == Some(0) {
+            problems.push(
+                invalid("--host-timeout must be at least 1 second"),
+                "give a number of seconds of 1 or more",
+            );
+        }
+
+        if self.json && self.then.is_empty() && self.then_file.is_none() {
+            problems.push(
+                invalid("--json requires --then or --then-file"),
+                "add the commands to run with --then or --then-file",
+            );
+        }
+
+        if let Some(ref path) = self.inventory {
+            if !path.exists() {
+                problems.push(
+                    SshpassError::InvalidArguments(format!(
+                        "Inventory does not exist: {}",
+                        path.display()
+                    )),
+                    "check the path given to --inventory",
+                );
+            }
+            // Made for one session, while a batch runs one per host
+            let single = [
+                ("--input", self.input.is_some()),
+                ("--output", !self.output.is_empty()),
+                ("--tee", !self.tee.is_empty()),
+                ("--json", self.json),
+                ("--status-channel", self.status_channel.is_some()),
+                ("--json-result", self.json_result.is_some()),
+                ("--audit-log", self.audit_log.is_some()),
+                ("--handle-expiry", self.handle_expiry.is_some()),
+                ("--lua-script", self.lua_script.is_some()),
+                ("--notify", self.notify),
+                ("--log-target", self.log_target != LogTarget::File),
+                ("--rusage", self.rusage),
+                #[cfg(unix)]
+                ("--sandbox", self.sandbox),
+            ];
+            for (option, _) in single.iter().filter(|(_, set)| *set) {
+                problems.push(
+                    SshpassError::InvalidArguments(format!(
+                        "{} cannot be used with --inventory",
+                        option
+                    )),
+                    format!(
+                        "drop {}; it is made for one session, and a batch runs one per host",
+                        option
+                    ),
+                );
+            }
+        }
+
+        // Validate environment variable exists if specified
+        if let Some(ref env_var) = self.env_var {
+            if std::env::var(env_var).is_err() {
+                problems.push(
+                    SshpassError::EnvVarNotSet(env_var.clone()),
+                    format!(
+                        "export {} before running sshpass, or name another variable with -e=NAME",
+                        env_var
+                    ),
+                );
+            }
+        }
+
+        problems.into_result()
+    }
+
+    /// Check if verbose mode is enabled
+    ///
+    /// Always false with the `no-verbose-strings` feature, so the optimizer
+    /// can drop the diagnostic messages.
+    pub fn is_verbose(&self) -> bool {
+        !cfg!(feature = "no-verbose-strings") && self.verbose > 0
+    }
+
+    /// Get the verbosity level
+    #[allow(dead_code)]
+    pub fn verbosity_level(&self) -> u8 {
+        self.verbose
+    }
+
+    /// Get the password prompt to use (default: "assword")
+    #[allow(dead_code)]
+    pub fn get_prompt(&self) -> &str {
+        self.prompt.as_deref().unwrap_or("assword")
+    }
+
+    /// Detect and consume "-ppassword" inline values that clap treated as part of the command.
+    ///
+    /// [`parse_compat`] reads these itself; clap cannot, because it takes
+    /// the command's own `-p` options as sshpass ones otherwise.
+    #[cfg(feature = "cli")]
+    pub fn absorb_inline_password(&mut self, raw_args: &[OsString]) {
+        if self.password.is_some() || self.command.is_empty() {
+            return;
+        }
+
+        if let Some(password) = inline_password_from_command(&self.command, raw_args) {
+            self.password = Some(password);
+            self.command.remove(0).zeroize();
+        }
+    }
+
+    /// Accept "-eVAR", "-e=VAR" and "-e VAR" as well as "--env=VAR"
+    ///
+    /// clap only takes a value for `-e` after '=', so the other spellings
+    /// end up in the command. "-e VAR" is ambiguous with a bare `-e` followed
+    /// by the command; see [`separate_env_var_in_command`].
+    #[cfg(feature = "cli")]
+    pub fn absorb_env_var(&mut self, raw_args: &[OsString]) {
+        let has_source =
+            self.password_file.is_some() || self.password.is_some() || self.credential.is_some();
+        #[cfg(unix)]
+        let has_source = has_source || self.password_fd.is_some();
+
+        match self.env_var {
+            None if !has_source => {
+                if let Some(name) = inline_env_var_from_command(&self.command, raw_args) {
+                    self.env_var = Some(name);
+                    self.command.remove(0);
+                }
+            }
+            // What clap sets for a bare -e
+            Some(ref name)
+                if name == "SSHPASS"
+                    && separate_env_var_in_command(&self.command, raw_args, |name| {
+                        std::env::var_os(name).is_some()
+                    }) =>
+            {
+                self.env_var = Some(self.command.remove(0));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Overwrite and drop argument strings that may contain the password
+#[cfg(feature = "cli")]
+fn wipe_os_strings(args: &mut Vec<OsString>) {
+    for arg in args.drain(..) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            arg.into_vec().zeroize();
+        }
+        #[cfg(not(unix))]
+        {
+            // into_string reuses the buffer when the argument is valid UTF-8
+            if let Ok(mut s) = arg.into_string() {
+                s.zeroize();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg_attr(not(feature = "cli"), allow(unused_imports))]
+    use super::*;
+
+    #[test]
+    fn test_cli_parsing() {
+        // This would require setting up clap test environment
+        // For now, we validate the structure compiles correctly
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_absorb_inline_password() {
+        let mut cli = Cli {
+            command: vec!["-psecret".to_string(), "ssh".to_string()],
+            ..Cli::default()
+        };
+        let raw = ["sshpass", "-psecret", "ssh"].map(OsString::from);
+        cli.absorb_inline_password(&raw);
+        assert_eq!(cli.password.as_deref(), Some("secret"));
+        assert_eq!(cli.command, vec!["ssh"]);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_absorb_env_var() {
+        for args in [&["sshpass", "-ePW", "ssh"], &["sshpass", "-e=PW", "ssh"]] {
+            let mut cli = Cli::try_parse_from(args).unwrap();
+            cli.absorb_env_var(&args.map(OsString::from));
+            assert_eq!(cli.env_var.as_deref(), Some("PW"), "{:?}", args);
+            assert_eq!(cli.command, vec!["ssh"]);
+        }
+
+        // A variable that is not set is taken for the command
+        let args = ["sshpass", "-e", "SSHPASS_TEST_UNSET_VARIABLE", "ssh"];
+        let mut cli = Cli::try_parse_from(args).unwrap();
+        cli.absorb_env_var(&args.map(OsString::from));
+        assert_eq!(cli.env_var.as_deref(), Some("SSHPASS"));
+        assert_eq!(cli.command, vec!["SSHPASS_TEST_UNSET_VARIABLE", "ssh"]);
+    }
+}
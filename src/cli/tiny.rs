@@ -0,0 +1,131 @@
+//! Minimal command line parser for builds without clap
+//!
+//! Understands the options of the C sshpass with the grammar of
+//! [`parse_compat`](super::parse_compat), plus long spellings of the same
+//! options (`--password`, `--env=VAR`, ...) and `--build-info`.
+
+use super::{compat, Cli};
+use crate::error::SshpassError;
+use crate::password::PasswordSource;
+
+/// Help text printed for `-h`
+#[cfg(unix)]
+pub const USAGE: &str = "\
+Usage: sshpass [-f|-d|-p|-e[env_var]] [-hvV] command parameters
+   -f filename   Take password to use from file
+   -d number     Use number as file descriptor for getting password
+   -p password   Provide password as argument (security unwise)
+   -e[env_var]   Password is passed as env-var \"SSHPASS\" or specified variable
+   With no parameters - password will be taken from stdin
+
+   -P prompt     Which string should sshpass search for to detect a password prompt
+   -v            Be verbose about what you're doing
+   -h            Show help (this screen)
+   -V            Print version information
+At most one of -f, -d, -p or -e should be used
+";
+
+/// Help text printed for `-h`
+#[cfg(windows)]
+pub const USAGE: &str = "\
+Usage: sshpass [-f|-p|-e[env_var]] [-hvV] command parameters
+   -f filename   Take password to use from file
+   -p password   Provide password as argument (security unwise)
+   -e[env_var]   Password is passed as env-var \"SSHPASS\" or specified variable
+   With no parameters - password will be taken from stdin
+
+   -P prompt     Which string should sshpass search for to detect a password prompt
+   -v            Be verbose about what you're doing
+   -h            Show help (this screen)
+   -V            Print version information
+At most one of -f, -p or -e should be used
+";
+
+/// Result of parsing the command line
+#[derive(Debug)]
+pub enum Parsed {
+    Args(Box<Cli>),
+    Help,
+}
+
+/// Parse `args`, the first of which is the program name
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Parsed, SshpassError> {
+    let (config, build_info) = compat::parse_argv(args, true)?;
+    if config.help {
+        return Ok(Parsed::Help);
+    }
+
+    let mut cli = Cli {
+        verbose: config.verbose,
+        prompt: config.prompt,
+        version: config.version,
+        build_info,
+        command: config.command,
+        ..Cli::default()
+    };
+    match config.source {
+        PasswordSource::Stdin => {}
+        PasswordSource::File(path) => cli.password_file = Some(path),
+        #[cfg(unix)]
+        PasswordSource::Fd(fd) => cli.password_fd = Some(fd),
+        PasswordSource::Password(password) => cli.password = Some(password.to_string()),
+        PasswordSource::Credential(name) => cli.credential = Some(name),
+        #[cfg(unix)]
+        PasswordSource::ListenFd(name) => cli.password_fd_name = Some(name),
+        PasswordSource::Env(name) => cli.env_var = Some(name),
+    }
+    Ok(Parsed::Args(Box::new(cli)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Parsed, SshpassError> {
+        parse(
+            std::iter::once("sshpass")
+                .chain(args.iter().copied())
+                .map(String::from),
+        )
+    }
+
+    fn parse_ok(args: &[&str]) -> Cli {
+        match parse_args(args) {
+            Ok(Parsed::Args(cli)) => *cli,
+            other => panic!("unexpected parse result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tiny_parses_classic_options() {
+        let cli = parse_ok(&["-vv", "-ppass", "-P", "PIN:", "ssh", "-p", "22", "host"]);
+        assert_eq!(cli.verbose, 2);
+        assert_eq!(cli.password.as_deref(), Some("pass"));
+        assert_eq!(cli.prompt.as_deref(), Some("PIN:"));
+        assert_eq!(cli.command, vec!["ssh", "-p", "22", "host"]);
+
+        let cli = parse_ok(&["-e", "--", "-x"]);
+        assert_eq!(cli.env_var.as_deref(), Some("SSHPASS"));
+        assert_eq!(cli.command, vec!["-x"]);
+
+        let cli = parse_ok(&["--env=PW", "--prompt", "PIN:", "ssh"]);
+        assert_eq!(cli.env_var.as_deref(), Some("PW"));
+        assert_eq!(cli.prompt.as_deref(), Some("PIN:"));
+
+        let cli = parse_ok(&["--file", "/tmp/pw", "ssh"]);
+        assert_eq!(cli.password_file, Some("/tmp/pw".into()));
+    }
+
+    #[test]
+    fn test_tiny_rejects_bad_input() {
+        assert!(matches!(
+            parse_args(&["-p", "a", "-e", "ssh"]),
+            Err(SshpassError::ConflictingArguments)
+        ));
+        assert!(parse_args(&["-x", "ssh"]).is_err());
+        assert!(parse_args(&["-p"]).is_err());
+        assert!(parse_args(&["-v"]).is_err());
+        assert!(matches!(parse_args(&["-h"]), Ok(Parsed::Help)));
+        assert!(parse_ok(&["-V"]).version);
+    }
+}
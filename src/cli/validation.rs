@@ -0,0 +1,300 @@
+//! Problems [`Cli::validate`](super::Cli::validate) found
+//!
+//! Validation goes through every check rather than stopping at the first
+//! failure, so one run reports everything wrong with the command line. Each
+//! problem carries a hint on how to fix it.
+
+use crate::error::SshpassError;
+use std::fmt;
+
+/// One thing wrong with the options
+#[derive(Debug)]
+pub struct Problem {
+    pub error: SshpassError,
+    /// What to do about it
+    pub hint: String,
+}
+
+/// Everything wrong with the options, in the order the checks ran
+#[derive(Debug, Default)]
+pub struct ValidationErrors {
+    problems: Vec<Problem>,
+}
+
+impl ValidationErrors {
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// The exit code of the first problem, as if validation had stopped there
+    pub fn exit_code(&self) -> i32 {
+        self.problems
+            .first()
+            .map_or(0, |problem| problem.error.exit_code())
+    }
+
+    pub(super) fn push(&mut self, error: SshpassError, hint: impl Into<String>) {
+        self.problems.push(Problem {
+            error,
+            hint: hint.into(),
+        });
+    }
+
+    /// Record the error of `result`, if any
+    pub(super) fn check<T>(
+        &mut self,
+        result: Result<T, SshpassError>,
+        hint: impl Into<String>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.push(error, hint);
+                None
+            }
+        }
+    }
+
+    pub(super) fn into_result(self) -> Result<(), Self> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (hint: {})", self.error, self.hint)
+    }
+}
+
+/// One problem per line
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::super::Cli;
+    use super::*;
+    use clap::Parser;
+
+    const MISSING: &str = "/nonexistent/sshpass-test";
+
+    fn problems(args: &[&str]) -> Vec<String> {
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .problems()
+                .iter()
+                .map(|problem| problem.error.to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        assert!(problems(&["sshpass", "-p", "secret", "ssh", "host"]).is_empty());
+    }
+
+    #[test]
+    fn test_validate_collects_every_problem() {
+        let cli = Cli::try_parse_from([
+            "sshpass",
+            "-f",
+            MISSING,
+            "--then-file",
+            MISSING,
+            "--drain-timeout",
+            "5",
+            "--max-attempts",
+            "0",
+            "ssh",
+        ])
+        .unwrap();
+        let errors = cli.validate().unwrap_err();
+        let problems = errors.problems();
+        assert_eq!(problems.len(), 4);
+        assert!(matches!(
+            problems[0].error,
+            SshpassError::PasswordFileError(_)
+        ));
+        assert!(problems[1].error.to_string().contains("Command file"));
+        assert!(problems[2].error.to_string().contains("--drain-timeout"));
+        assert!(problems[3].error.to_string().contains("--max-attempts"));
+        assert!(problems.iter().all(|problem| !problem.hint.is_empty()));
+
+        // The first problem decides the exit code, a missing file being a
+        // runtime error as in the C sshpass
+        assert_eq!(errors.exit_code(), 3);
+        let message = errors.to_string();
+        assert_eq!(message.lines().count(), 4);
+        assert!(message.lines().all(|line| line.contains("(hint: ")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_bad_fd() {
+        let cli = Cli::try_parse_from(["sshpass", "-d", "-1", "ssh"]).unwrap();
+        let errors = cli.validate().unwrap_err();
+        assert!(matches!(
+            errors.problems()[0].error,
+            SshpassError::InvalidFileDescriptor(-1)
+        ));
+        assert_eq!(errors.exit_code(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_password_fd_name() {
+        // Not started by systemd
+        let found = problems(&["sshpass", "--password-fd-name", "validate-test", "ssh"]);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].contains("passed to sshpass"), "{:?}", found);
+        assert!(
+            Cli::try_parse_from(["sshpass", "--password-fd-name", "x", "-d", "3", "ssh"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_missing_files() {
+        for (option, message) in [
+            ("-f", "File does not exist"),
+            ("--then-file", "Command file does not exist"),
+            ("--script", "Script does not exist"),
+        ] {
+            let source: &[&str] = if option == "-f" { &[] } else { &["-p", "x"] };
+            let mut argv = vec!["sshpass"];
+            argv.extend_from_slice(source);
+            argv.extend_from_slice(&[option, MISSING, "ssh"]);
+            let found = problems(&argv);
+            assert_eq!(found.len(), 1, "{}: {:?}", option, found);
+            assert!(found[0].contains(message), "{}: {:?}", option, found);
+        }
+    }
+
+    #[test]
+    fn test_validate_unset_env_var() {
+        let cli =
+            Cli::try_parse_from(["sshpass", "--env=SSHPASS_TEST_UNSET_VARIABLE", "ssh"]).unwrap();
+        let errors = cli.validate().unwrap_err();
+        let problem = &errors.problems()[0];
+        assert!(matches!(problem.error, SshpassError::EnvVarNotSet(ref name)
+            if name == "SSHPASS_TEST_UNSET_VARIABLE"));
+        assert!(problem.hint.contains("export SSHPASS_TEST_UNSET_VARIABLE"));
+    }
+
+    #[test]
+    fn test_validate_conflicting_sources() {
+        // clap turns these down while parsing
+        assert!(Cli::try_parse_from(["sshpass", "-p", "x", "-e", "ssh"]).is_err());
+
+        // A Cli built by hand is checked by validate
+        let cli = Cli {
+            password: Some("x".to_string()),
+            env_var: Some("SSHPASS".to_string()),
+            command: vec!["ssh".to_string()],
+            ..Cli::default()
+        };
+        let errors = cli.validate().unwrap_err();
+        assert!(matches!(
+            errors.problems()[0].error,
+            SshpassError::ConflictingArguments
+        ));
+        assert_eq!(errors.exit_code(), 2);
+    }
+
+    #[test]
+    fn test_validate_option_dependencies() {
+        for (args, message) in [
+            (&["--drain-timeout", "5"][..], "--drain-timeout requires"),
+            (&["--notify-after", "5"], "--notify-after requires"),
+            (&["--sudo-sentinel", "-P", "x"], "--sudo-sentinel"),
+            (&["--record-rotate", "2"], "--record-rotate requires"),
+            (
+                &["--record-max-size", "1M"],
+                "require --tee or --output-dir",
+            ),
+            (&["--json"], "--json requires"),
+            (&["--lockout-attempts", "0"], "--lockout-attempts"),
+            (
+                &["--inventory", "hosts.ini", "--tee", "log"],
+                "--tee cannot be used with --inventory",
+            ),
+            (
+                &["--inventory", "hosts.ini", "--notify"],
+                "--notify cannot be used with --inventory",
+            ),
+            (
+                &["--inventory", "hosts.ini", "--log-target", "syslog"],
+                "--log-target cannot be used with --inventory",
+            ),
+            (
+                &["--inventory", "hosts.ini", "--rusage"],
+                "--rusage cannot be used with --inventory",
+            ),
+            (
+                &["--inventory", "hosts.ini", "--max-parallel", "0"],
+                "--max-parallel must be at least 1",
+            ),
+        ] {
+            let mut argv = vec!["sshpass", "-p", "x"];
+            argv.extend_from_slice(args);
+            argv.push("ssh");
+            let found = problems(&argv);
+            assert!(
+                found.iter().any(|problem| problem.contains(message)),
+                "{:?}: {:?}",
+                args,
+                found
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            let found = problems(&["sshpass", "-p", "x", "--notify", "--sandbox", "ssh"]);
+            assert!(
+                found
+                    .iter()
+                    .any(|problem| problem.contains("--notify cannot be used with --sandbox")),
+                "{:?}",
+                found
+            );
+        }
+
+        // --record-rotate without --tee or --record-max-size is two problems
+        let found = problems(&["sshpass", "-p", "x", "--record-rotate", "2", "ssh"]);
+        assert_eq!(found.len(), 2, "{:?}", found);
+    }
+
+    #[test]
+    fn test_validate_no_command() {
+        // clap asks for the command itself; a Cli built by hand may lack it
+        assert!(Cli::try_parse_from(["sshpass", "-p", "x"]).is_err());
+        let cli = Cli {
+            password: Some("x".to_string()),
+            ..Cli::default()
+        };
+        let errors = cli.validate().unwrap_err();
+        let found: Vec<_> = errors
+            .problems()
+            .iter()
+            .map(|p| p.error.to_string())
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].contains("No command specified"));
+    }
+}
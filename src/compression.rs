@@ -0,0 +1,162 @@
+//! Compressed recordings (`--record-compress`)
+//!
+//! Transcripts of verbose provisioning runs grow large. With
+//! `--record-compress gzip` or `zstd`, every `--tee` target is compressed as
+//! it is written: the encoder sits between the recorder and the file, so
+//! nothing is held back beyond the encoder's own window. The encoder is
+//! flushed at most once a second, since flushing after every chunk of output
+//! would cost most of the gain; the stream is finished when the target is
+//! closed or rotated. `--record-max-size` counts the data before compression,
+//! and the timing file of a typescript is left uncompressed.
+//!
+//! `sshpass play` recognises compressed recordings by their first bytes, so
+//! `zcat` is not needed in between. Both need the `compression` feature.
+
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+/// The first bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// The first bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Time between two flushes of a compressed target
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `--record-compress` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of a stream starting with `head`
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Compress what is written to `writer`; the stream is finished when the
+/// returned writer is dropped
+pub fn encoder<W>(writer: W, compression: Compression) -> std::io::Result<Box<dyn Write + Send>>
+where
+    W: Write + Send + 'static,
+{
+    match compression {
+        Compression::None => Ok(Box::new(writer)),
+        #[cfg(feature = "compression")]
+        Compression::Gzip => Ok(Box::new(flate2::write::GzEncoder::new(
+            writer,
+            flate2::Compression::default(),
+        ))),
+        #[cfg(feature = "compression")]
+        Compression::Zstd => Ok(Box::new(
+            zstd::stream::write::Encoder::new(writer, 0)?.auto_finish(),
+        )),
+        #[cfg(not(feature = "compression"))]
+        Compression::Gzip | Compression::Zstd => Err(unsupported()),
+    }
+}
+
+/// Read `reader`, decompressing it if it is compressed
+pub fn decoder<R>(mut reader: R) -> std::io::Result<Box<dyn BufRead + Send>>
+where
+    R: BufRead + Send + 'static,
+{
+    match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Ok(Box::new(reader)),
+        #[cfg(feature = "compression")]
+        Compression::Gzip => Ok(Box::new(std::io::BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        ))),
+        #[cfg(feature = "compression")]
+        Compression::Zstd => Ok(Box::new(std::io::BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        ))),
+        #[cfg(not(feature = "compression"))]
+        Compression::Gzip | Compression::Zstd => Err(unsupported()),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "compressed recordings need sshpass built with the `compression` feature",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer whose bytes the test can still read after it is dropped
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn round_trip(compression: Compression) -> (Vec<u8>, Vec<u8>) {
+        let shared = Shared::default();
+        let mut writer = encoder(shared.clone(), compression).unwrap();
+        writer.write_all(b"line one\r\n").unwrap();
+        writer.flush().unwrap();
+        writer.write_all(b"line two\r\n").unwrap();
+        drop(writer);
+
+        let written = shared.0.lock().unwrap().clone();
+        let mut read = Vec::new();
+        decoder(std::io::Cursor::new(written.clone()))
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        (written, read)
+    }
+
+    #[test]
+    fn test_plain_passes_through() {
+        let (written, read) = round_trip(Compression::None);
+        assert_eq!(written, b"line one\r\nline two\r\n");
+        assert_eq!(read, written);
+        assert_eq!(Compression::detect(b"{\"version\": 2}"), Compression::None);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_round_trip() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let (written, read) = round_trip(compression);
+            assert_eq!(Compression::detect(&written), compression);
+            assert_eq!(read, b"line one\r\nline two\r\n");
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn test_compression_needs_feature() {
+        assert!(encoder(Shared::default(), Compression::Gzip).is_err());
+        let gzip = std::io::Cursor::new(vec![0x1f, 0x8b, 0x08, 0x00]);
+        assert!(decoder(gzip).is_err());
+    }
+}
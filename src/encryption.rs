@@ -0,0 +1,202 @@
+//! Encrypted recordings (`--record-recipient`)
+//!
+//! A session can print secrets that should not sit in a plain file, yet an
+//! audit still wants the recording. With `--record-recipient age1...`
+//! (repeatable) every `--tee` target is encrypted as it is written, in the
+//! [age](https://age-encryption.org) format, to the X25519 public keys
+//! given. Only the public keys are on the machine running sshpass; whoever
+//! holds one of the matching identities reads the recording back with
+//! `sshpass play --identity FILE`, or with `age -d -i FILE`.
+//!
+//! age encrypts in chunks of 64 KiB, so up to that much output is held in
+//! memory until the chunk is full or the file is closed, and a recording cut
+//! short by SIGKILL loses its last chunk. Compression (`--record-compress`)
+//! is applied before encryption, where it still has something to gain. The
+//! timing file of a typescript, which only holds delays and sizes, is left
+//! plain. Both need the `encryption` feature.
+
+use std::io::{BufRead, Write};
+
+/// The first line of an age file
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// Check a `--record-recipient` value
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_recipient(value: &str) -> std::result::Result<String, String> {
+    #[cfg(feature = "encryption")]
+    let valid = value.parse::<age::x25519::Recipient>().map(|_| ());
+    #[cfg(not(feature = "encryption"))]
+    let valid = if value.starts_with("age1") {
+        Ok(())
+    } else {
+        Err("not an age recipient")
+    };
+    valid
+        .map(|()| value.to_string())
+        .map_err(|e| format!("invalid recipient '{}': {}", value, e))
+}
+
+/// Whether a stream starting with `head` is an age file
+pub fn is_encrypted(head: &[u8]) -> bool {
+    head.starts_with(AGE_MAGIC)
+}
+
+/// Encrypt what is written to `writer` to `recipients`, or leave it plain if
+/// there are none; the file is finished when the returned writer is dropped
+pub fn encryptor<W>(writer: W, recipients: &[String]) -> std::io::Result<Box<dyn Write + Send>>
+where
+    W: Write + Send + 'static,
+{
+    if recipients.is_empty() {
+        return Ok(Box::new(writer));
+    }
+    #[cfg(feature = "encryption")]
+    {
+        let keys = recipients
+            .iter()
+            .map(|value| value.parse::<age::x25519::Recipient>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let encryptor =
+            age::Encryptor::with_recipients(keys.iter().map(|key| key as &dyn age::Recipient))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        Ok(Box::new(Encrypted(Some(encryptor.wrap_output(writer)?))))
+    }
+    #[cfg(not(feature = "encryption"))]
+    Err(unsupported())
+}
+
+/// Read `reader`, decrypting it with the identities in `identity` if it is
+/// an age file
+pub fn decryptor<R>(
+    mut reader: R,
+    identity: Option<&str>,
+) -> std::io::Result<Box<dyn BufRead + Send>>
+where
+    R: BufRead + Send + 'static,
+{
+    if !is_encrypted(reader.fill_buf()?) {
+        return Ok(Box::new(reader));
+    }
+    let Some(identity) = identity else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the recording is encrypted; give the identity file with --identity",
+        ));
+    };
+    #[cfg(feature = "encryption")]
+    {
+        let invalid =
+            |e: age::DecryptError| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let identities = age::IdentityFile::from_file(identity.to_string())?
+            .into_identities()
+            .map_err(invalid)?;
+        let decrypted = age::Decryptor::new_buffered(reader)
+            .and_then(|decryptor| {
+                decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))
+            })
+            .map_err(invalid)?;
+        Ok(Box::new(std::io::BufReader::new(decrypted)))
+    }
+    #[cfg(not(feature = "encryption"))]
+    {
+        let _ = identity;
+        Err(unsupported())
+    }
+}
+
+/// An age stream, finished when it is dropped
+#[cfg(feature = "encryption")]
+struct Encrypted<W: Write>(Option<age::stream::StreamWriter<W>>);
+
+#[cfg(feature = "encryption")]
+impl<W: Write> Write for Encrypted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.as_mut() {
+            Some(stream) => stream.write(buf),
+            None => Err(std::io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<W: Write> Drop for Encrypted<W> {
+    fn drop(&mut self) {
+        if let Some(stream) = self.0.take() {
+            if let Err(e) = stream.finish() {
+                crate::diag!("Failed to finish the encrypted recording: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "encrypted recordings need sshpass built with the `encryption` feature",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_plain_passes_through() {
+        assert!(encryptor(Vec::new(), &[]).is_ok());
+        let mut read = String::new();
+        decryptor(std::io::Cursor::new(b"plain\n".to_vec()), None)
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, "plain\n");
+
+        assert!(parse_recipient("ssh-ed25519 AAAA").is_err());
+        let encrypted = std::io::Cursor::new(AGE_MAGIC.to_vec());
+        assert!(decryptor(encrypted, None).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_round_trip() {
+        use age::secrecy::ExposeSecret;
+
+        let dir = std::env::temp_dir().join(format!("sshpass-encryption-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let identity = age::x25519::Identity::generate();
+        let identity_file = dir.join("key.txt");
+        std::fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+        let recipient = parse_recipient(&identity.to_public().to_string()).unwrap();
+
+        let path = dir.join("session.log.age");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = encryptor(file, &[recipient]).unwrap();
+        writer.write_all(b"secret output\r\n").unwrap();
+        drop(writer);
+
+        let reader = || std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+        assert!(decryptor(reader(), None).is_err());
+        let mut read = Vec::new();
+        decryptor(reader(), Some(&identity_file.display().to_string()))
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, b"secret output\r\n");
+
+        // Another identity cannot read it
+        let other = dir.join("other.txt");
+        let stranger = age::x25519::Identity::generate();
+        std::fs::write(&other, stranger.to_string().expose_secret()).unwrap();
+        assert!(decryptor(reader(), Some(&other.display().to_string())).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+//! What the event loop drives: the child and the terminal it runs on
+//!
+//! [`ChildProcess`] and its [`Pty`] are the real ones. A library caller
+//! can pass its own [`SessionChild`], for a child it started some other way
+//! or a fake one in a test; on Unix its terminal has to have a descriptor
+//! for `pselect` to wait on.
+
+use crate::error::Result;
+use crate::process::{ChildProcess, ResourceUsage};
+use crate::pty::Pty;
+use crate::resize_retry;
+use crate::signal::{handle_window_resize, retry_window_size};
+
+#[cfg(unix)]
+use crate::process::Termination;
+#[cfg(unix)]
+use crate::pty::MasterRead;
+#[cfg(unix)]
+use crate::ssh_log::SshLog;
+#[cfg(unix)]
+use std::os::fd::RawFd;
+
+/// The terminal a child runs on
+pub trait Terminal {
+    /// Type `data` into the terminal
+    fn write_all(&self, data: &[u8]) -> Result<()>;
+    /// Send a break, as `~B` asks
+    fn send_break(&self) -> Result<()>;
+    /// Follow the size of the local terminal
+    fn resize(&self) -> Result<()>;
+    /// Take a step of `--initial-resize-retry`
+    fn retry_size(&self, step: resize_retry::Step) -> Result<()>;
+
+    /// Descriptor the event loop waits on for output
+    #[cfg(unix)]
+    fn master_fd(&self) -> RawFd;
+    /// Read output once the descriptor is ready
+    #[cfg(unix)]
+    fn read_master(&self, buffer: &mut [u8]) -> Result<MasterRead>;
+    /// Read what is left without waiting, once the child has exited
+    #[cfg(unix)]
+    fn read(&self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// The output, read by a thread of the event loop
+    #[cfg(windows)]
+    fn reader(&self) -> std::sync::Arc<std::sync::Mutex<Box<dyn std::io::Read + Send>>>;
+}
+
+/// A child for the event loop to run
+pub trait SessionChild {
+    /// The terminal the child runs on
+    fn terminal(&self) -> &dyn Terminal;
+    /// The exit code, once the child has exited
+    fn try_wait(&mut self) -> Result<Option<i32>>;
+    /// Wait for the child to exit
+    fn wait(&mut self) -> Result<i32>;
+
+    /// Send `signal` to the child
+    #[cfg(unix)]
+    fn send_signal(&self, signal: nix::sys::signal::Signal) -> Result<()>;
+    /// End the child
+    #[cfg(windows)]
+    fn kill(&mut self) -> Result<()>;
+
+    /// Whether the child's standard input comes through the terminal
+    #[cfg(unix)]
+    fn stdin_on_pty(&self) -> bool {
+        true
+    }
+    /// Whether the child's standard output arrives through the terminal
+    #[cfg(unix)]
+    fn stdout_on_pty(&self) -> bool {
+        true
+    }
+    /// The signal that killed the child, once it has exited
+    #[cfg(unix)]
+    fn termination(&self) -> Option<Termination> {
+        None
+    }
+    /// CPU time and peak memory of the child, once it has exited
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        None
+    }
+    /// ssh's own log (`--ssh-stderr-pipe`), until it is closed
+    #[cfg(unix)]
+    fn ssh_log(&mut self) -> Option<&mut SshLog> {
+        None
+    }
+    /// Stop reading ssh's log, once it is closed
+    #[cfg(unix)]
+    fn close_ssh_log(&mut self) {}
+}
+
+impl Terminal for Pty {
+    fn write_all(&self, data: &[u8]) -> Result<()> {
+        Pty::write_all(self, data)
+    }
+
+    fn send_break(&self) -> Result<()> {
+        Pty::send_break(self)
+    }
+
+    fn resize(&self) -> Result<()> {
+        handle_window_resize(self)
+    }
+
+    fn retry_size(&self, step: resize_retry::Step) -> Result<()> {
+        retry_window_size(self, step)
+    }
+
+    #[cfg(unix)]
+    fn master_fd(&self) -> RawFd {
+        Pty::master_fd(self)
+    }
+
+    #[cfg(unix)]
+    fn read_master(&self, buffer: &mut [u8]) -> Result<MasterRead> {
+        Pty::read_master(self, buffer)
+    }
+
+    #[cfg(unix)]
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        Pty::read(self, buffer)
+    }
+
+    #[cfg(windows)]
+    fn reader(&self) -> std::sync::Arc<std::sync::Mutex<Box<dyn std::io::Read + Send>>> {
+        self.reader.clone()
+    }
+}
+
+#[cfg(unix)]
+impl SessionChild for ChildProcess {
+    fn terminal(&self) -> &dyn Terminal {
+        &self.pty
+    }
+
+    fn try_wait(&mut self) -> Result<Option<i32>> {
+        ChildProcess::try_wait(self)
+    }
+
+    fn wait(&mut self) -> Result<i32> {
+        ChildProcess::wait(self)
+    }
+
+    fn send_signal(&self, signal: nix::sys::signal::Signal) -> Result<()> {
+        self.kill(signal)
+    }
+
+    fn stdin_on_pty(&self) -> bool {
+        ChildProcess::stdin_on_pty(self)
+    }
+
+    fn stdout_on_pty(&self) -> bool {
+        ChildProcess::stdout_on_pty(self)
+    }
+
+    fn termination(&self) -> Option<Termination> {
+        ChildProcess::termination(self)
+    }
+
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        ChildProcess::resource_usage(self)
+    }
+
+    fn ssh_log(&mut self) -> Option<&mut SshLog> {
+        self.ssh_log.as_mut()
+    }
+
+    fn close_ssh_log(&mut self) {
+        self.ssh_log = None;
+    }
+}
+
+#[cfg(windows)]
+impl SessionChild for ChildProcess {
+    fn terminal(&self) -> &dyn Terminal {
+        self.pty_ref()
+    }
+
+    fn try_wait(&mut self) -> Result<Option<i32>> {
+        ChildProcess::try_wait(self)
+    }
+
+    fn wait(&mut self) -> Result<i32> {
+        ChildProcess::wait(self)
+    }
+
+    fn kill(&mut self) -> Result<()> {
+        ChildProcess::kill(self)
+    }
+
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        ChildProcess::resource_usage(self)
+    }
+}
@@ -0,0 +1,644 @@
+//! Setting up a session from the command line
+//!
+//! [`Launch`] does what `sshpass [OPTIONS] COMMAND` does once the password
+//! is read: it picks the prompt profile, starts the child on its PTY, builds
+//! the monitor and what is typed from the options, and runs the
+//! [`EventLoop`]. The observers of the session come with the
+//! [`SessionControl`] it is run with. A host of a batch runs with its own
+//! output and the passwords of its bastions.
+
+use super::EventLoop;
+use crate::cli::Cli;
+use crate::error::{Result, SshpassError};
+use crate::logging::{self, Tone};
+use crate::monitor::{self, AttemptKey, OutputMonitor, ReauthWindow};
+use crate::output_sink::{self, OutputSink};
+use crate::password::{read_password_from_env, PasswordSource, SecureString};
+use crate::process::{ChildProcess, SpawnOptions};
+use crate::profiles::{self, PasswordChooser};
+use crate::session::SessionControl;
+use crate::{
+    audit, broken_pipe, expect_script, file_perms, input_source, keymap, lockout, panic_guard,
+    plugins, progress, sequence, ssh_argv, ssh_env, stdin_forwarder, sudo_prompt, system_log,
+};
+#[cfg(unix)]
+use crate::{persist, sandbox, ssh_log};
+
+/// Determine the password source from command line arguments
+///
+/// A password given with `-p` is moved out of `args` so no other copy remains.
+pub fn password_source(args: &mut Cli) -> PasswordSource {
+    #[cfg(unix)]
+    if let Some(fd) = args.password_fd {
+        return PasswordSource::Fd(fd);
+    }
+    #[cfg(unix)]
+    if let Some(ref name) = args.password_fd_name {
+        return PasswordSource::ListenFd(name.clone());
+    }
+
+    if let Some(ref path) = args.password_file {
+        PasswordSource::File(path.clone())
+    } else if let Some(ref name) = args.credential {
+        PasswordSource::Credential(name.clone())
+    } else if let Some(pw) = args.password.take() {
+        PasswordSource::Password(SecureString::new(pw))
+    } else if args.env_var.is_some() {
+        // Will be handled separately because we need to clear the env var
+        PasswordSource::Stdin // Placeholder
+    } else {
+        PasswordSource::Stdin
+    }
+}
+
+/// Read the password from the configured source
+pub fn read_password(args: &Cli, source: PasswordSource) -> Result<SecureString> {
+    // Special handling for environment variables
+    if let Some(ref env_var) = args.env_var {
+        return read_password_from_env(env_var, args.is_verbose());
+    }
+
+    if let PasswordSource::File(ref path) = source {
+        file_perms::enforce(path, args.strict_perms)?;
+    }
+
+    // Security warning for -p option
+    if matches!(source, PasswordSource::Password(_)) {
+        logging::emit(
+            Tone::Warning,
+            "Warning: Using -p option is insecure. Consider using -f or -e instead.",
+        );
+    }
+
+    source.read_password(args.is_verbose())
+}
+
+/// Keep an agent or askpass helper from answering instead of sshpass
+pub fn apply_ssh_env(args: &Cli) {
+    let removed = ssh_env::apply(args.ssh_env);
+    if args.is_verbose() {
+        if !removed.is_empty() {
+            crate::diag!(
+                "Removed from the child's environment: {}",
+                removed.join(", ")
+            );
+        } else if ssh_env::agent_present() {
+            crate::diag!("SSH_AUTH_SOCK is set, ssh may log in without a password prompt");
+        }
+    }
+}
+
+/// A session to run from the command line's options
+pub struct Launch<'a> {
+    args: &'a Cli,
+    password: SecureString,
+    /// Replaces an expired password (`--handle-expiry`)
+    new_password: Option<SecureString>,
+    /// Where the output goes instead of the `--output` targets
+    output: Option<Box<dyn OutputSink>>,
+    /// Keys each password prompt by the hop it names, so that every hop
+    /// gets its own attempts
+    attempt_key: Option<AttemptKey>,
+    /// Passwords of the bastions, masked like the host's
+    hop_passwords: Vec<&'a SecureString>,
+    /// Picks the password of the hop a prompt names
+    chooser: Option<PasswordChooser<'a>>,
+}
+
+impl<'a> Launch<'a> {
+    /// Run the command of `args`, typing `password`
+    pub fn new(args: &'a Cli, password: SecureString) -> Self {
+        Self {
+            args,
+            password,
+            new_password: None,
+            output: None,
+            attempt_key: None,
+            hop_passwords: Vec::new(),
+            chooser: None,
+        }
+    }
+
+    /// Answer the forced change of an expired password with `new_password`
+    pub fn with_new_password(mut self, new_password: Option<SecureString>) -> Self {
+        self.new_password = new_password;
+        self
+    }
+
+    /// Send the output to `output` instead of the `--output` targets
+    pub fn with_output(mut self, output: Box<dyn OutputSink>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Count `--max-attempts` per prompt, by the key `key` gives it
+    pub fn with_attempt_key(mut self, key: AttemptKey) -> Self {
+        self.attempt_key = Some(key);
+        self
+    }
+
+    /// Mask the passwords of the bastions in front of the host, too
+    pub fn with_hop_passwords(mut self, passwords: Vec<&'a SecureString>) -> Self {
+        self.hop_passwords = passwords;
+        self
+    }
+
+    /// Type the password `chooser` picks for a prompt, or the session's
+    /// when it picks none
+    pub fn with_password_chooser(mut self, chooser: PasswordChooser<'a>) -> Self {
+        self.chooser = Some(chooser);
+        self
+    }
+
+    /// Spawn the child and run the session until it exits, returning its
+    /// exit code
+    pub fn run(self, control: &SessionControl) -> Result<i32> {
+        let Launch {
+            args,
+            password,
+            new_password,
+            output,
+            attempt_key,
+            hop_passwords,
+            chooser,
+        } = self;
+        let verbose = args.is_verbose();
+        if verbose {
+            crate::diag!("Verbose logging enabled");
+        }
+
+        // Commands to run in the same shell session after login
+        let mut commands = args.then.clone();
+        if let Some(ref path) = args.then_file {
+            let from_file = sequence::read_command_file(path).map_err(|e| {
+                SshpassError::InvalidArguments(format!(
+                    "Failed to read command file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            commands.extend(from_file);
+        }
+        let mut sequence = if commands.is_empty() {
+            None
+        } else {
+            if verbose {
+                crate::diag!("Sequential mode, {} command(s) to run", commands.len());
+            }
+            Some(sequence::CommandSequence::new(commands, args.json))
+        };
+
+        // Pick the prompt profile, falling back to one matching the command name
+        let mut profile = match args.profile {
+            Some(ref name) => Some(profiles::lookup(name)?),
+            None if args.no_auto_detect => None,
+            None => args.command.first().and_then(|cmd| profiles::detect(cmd)),
+        };
+        if let Some(ref path) = args.profile_file {
+            profile = Some(profiles::load_file(path, profile)?);
+        }
+        if verbose {
+            if let Some(ref profile) = profile {
+                crate::diag!("Using prompt profile {}", profile.name);
+            }
+        }
+        let plugins = plugins::load_all(profile.as_ref().map_or(&[], |p| &p.plugins), verbose)?;
+
+        // sudo shows the sentinel instead of its translated prompt
+        let sudo = profile.as_ref().is_some_and(|p| p.name == "sudo");
+        if args.sudo_sentinel {
+            if !sudo {
+                return Err(SshpassError::InvalidArguments(
+                    "--sudo-sentinel needs the sudo profile (run sudo, or use --profile sudo)"
+                        .to_string(),
+                ));
+            }
+            sudo_prompt::set_sentinel();
+        }
+
+        // A device echoing such a password would seem to ask for it again
+        let prompt = args
+            .prompt
+            .as_deref()
+            .or_else(|| args.sudo_sentinel.then_some(sudo_prompt::SENTINEL))
+            .or_else(|| profile.as_ref().map(|p| p.prompt.as_str()));
+        let pattern = prompt.unwrap_or(monitor::DEFAULT_PROMPT);
+        let echo_guard = if monitor::prompt_in_password(pattern, password.as_bytes()) {
+            let window = args.echo_guard.unwrap_or(monitor::DEFAULT_ECHO_GUARD_MS);
+            if window == 0 {
+                return Err(SshpassError::RuntimeError(format!(
+                    "The password contains the prompt pattern \"{}\", refused by --echo-guard 0",
+                    pattern
+                )));
+            }
+            logging::emit(
+                Tone::Warning,
+                &format!(
+                    "Warning: The password contains the prompt pattern \"{}\", ignoring the prompt for {} ms after sending it",
+                    pattern, window
+                ),
+            );
+            Some(std::time::Duration::from_millis(window))
+        } else {
+            None
+        };
+
+        // Read before the child starts, which inherits our stdin
+        let script = match args.script {
+            Some(ref path) => {
+                let steps = expect_script::read_script(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| expect_script::parse(&text))
+                    .map_err(|e| {
+                        SshpassError::InvalidArguments(format!(
+                            "Invalid script {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                if verbose {
+                    crate::diag!("Running a script of {} step(s)", steps.len());
+                }
+                let newline = profile
+                    .as_ref()
+                    .and_then(|p| p.newline)
+                    .map_or(PASSWORD_NEWLINE, keymap::EnterMode::as_bytes);
+                Some(expect_script::ExpectScript::new(steps, newline))
+            }
+            None => None,
+        };
+
+        let mut command = args.command.clone();
+
+        // Make the login independent of the user's ssh_config
+        if args.ssh_hardened {
+            if ssh_argv::tool(&command).is_some() {
+                let options = ssh_argv::hardened_options(args.max_attempts.unwrap_or(1));
+                let added = ssh_argv::add_options(&mut command, &options, &args.ssh_hardened_skip);
+                if verbose {
+                    crate::diag!("Added ssh options: {}", added.join(" "));
+                }
+            } else if verbose {
+                crate::diag!("Not running ssh, scp or sftp, ignoring --ssh-hardened");
+            }
+        }
+
+        // Start or reuse a ControlMaster that outlives this invocation
+        #[cfg(unix)]
+        let reusing = match args.persist {
+            Some(seconds) => use_persistent_master(&mut command, seconds, verbose)?,
+            None => false,
+        };
+        #[cfg(windows)]
+        let reusing = false;
+
+        // Give ssh's own diagnostics a pipe of their own
+        #[cfg(unix)]
+        let mut ssh_log = None;
+        #[cfg(unix)]
+        if args.ssh_stderr_pipe {
+            let log = ssh_log::SshLog::new()?;
+            if log.inject(&mut command) {
+                ssh_log = Some(log);
+            } else if verbose {
+                crate::diag!("Not running ssh, ignoring --ssh-stderr-pipe");
+            }
+        }
+
+        // Times in the -vv and -vvv output count from the spawn
+        let level = if verbose { args.verbose } else { 0 };
+        let mut log = logging::Logger::new(level).with_secret(password.as_bytes());
+        for hop_password in &hop_passwords {
+            log = log.with_secret(hop_password.as_bytes());
+        }
+        if let Some(ref new_password) = new_password {
+            log = log.with_secret(new_password.as_bytes());
+        }
+        if level >= logging::DUMP_LEVEL {
+            crate::diag!("Dumping PTY traffic, password masked");
+        }
+
+        // Stay clear of fail2ban after repeated incorrect passwords
+        let lockout = if args.no_lockout_guard {
+            None
+        } else {
+            let window = args
+                .lockout_window
+                .unwrap_or(lockout::DEFAULT_WINDOW_MINUTES);
+            lockout::guard(
+                &command,
+                args.lockout_attempts.unwrap_or(lockout::DEFAULT_ATTEMPTS),
+                std::time::Duration::from_secs(window.saturating_mul(60)),
+                verbose,
+            )?
+        };
+
+        // Record the session before the child can do anything
+        let mut secrets = vec![password.as_bytes()];
+        secrets.extend(new_password.as_ref().map(SecureString::as_bytes));
+        let audit = match args.audit_log {
+            _ if args.log_target.is_native() => Some(audit::AuditLog::start_system(
+                system_log::SystemLog::open()?,
+                &command,
+                &secrets,
+            )?),
+            Some(ref path) => Some(audit::AuditLog::start(path, &command, &secrets)?),
+            None => None,
+        };
+        let finish_audit = |result: Result<i32>| {
+            if let Some(ref audit) = audit {
+                if let Err(e) = audit.finish(&result, control.handle().output_bytes()) {
+                    crate::diag!("{}", e);
+                }
+            }
+            result
+        };
+
+        // A terminal in the foreground is forwarded by sshpass, which then sees
+        // the escape character; pipes and files are left to the child unless
+        // --input names another source
+        let input_spec = args.input.as_deref().unwrap_or("tty");
+        #[cfg(unix)]
+        let forward_stdin =
+            sequence.is_none() && (input_spec != "tty" || stdin_forwarder::StdinReader::wanted());
+        // The Windows event loop always forwards stdin
+        #[cfg(windows)]
+        let forward_stdin = false;
+
+        // With --tee or --output sshpass passes the output on, so it can be
+        // copied or sent elsewhere; a host of a batch has its own output
+        let passes_output =
+            output.is_some() || !args.tee.is_empty() || output_sink::redirects(&args.output);
+        // Where the forwarded output goes, opened before there is a child to
+        // clean up after
+        let mut output = match output {
+            Some(output) => output,
+            None => output_sink::open_all(&args.output)?,
+        };
+        let source = input_source::open(input_spec)?;
+
+        // Spawn the child process with PTY
+        let options = SpawnOptions::new(command.clone()).verbose(verbose);
+        let options = if sequence.is_some() {
+            options.interactive()
+        } else {
+            options
+                .stdin_on_pty(forward_stdin)
+                .stdout_on_pty(passes_output)
+        };
+        let spawned = ChildProcess::spawn(&options);
+        #[cfg(windows)]
+        let spawned = spawned.map_err(|e| {
+            if args.no_console {
+                SshpassError::PtyCreationError(format!(
+                    "ConPTY could not be created without an attached console \
+                     (requires Windows 10 1809 or later): {}",
+                    e
+                ))
+            } else {
+                e
+            }
+        });
+        let child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                crate::diag!("Failed to spawn child process: {}", e);
+                return finish_audit(Err(e));
+            }
+        };
+
+        #[cfg(unix)]
+        let child = child.with_ssh_log(ssh_log);
+
+        // Drop privileges the parent no longer needs now that the child is running
+        #[cfg(unix)]
+        if args.sandbox {
+            if let Err(e) = sandbox::apply(&[], verbose) {
+                let _ = child.kill(nix::sys::signal::SIGTERM);
+                return finish_audit(Err(e));
+            }
+        }
+
+        // Until the event loop has reaped it
+        #[cfg(unix)]
+        let _watch = panic_guard::watch_child(child.pid.as_raw() as u32);
+        #[cfg(windows)]
+        let _watch = child.process_id.map(panic_guard::watch_child);
+
+        if verbose {
+            crate::diag!("Spawned child process (debug)");
+            #[cfg(unix)]
+            {
+                crate::diag!("Child process spawned with PID: {}", child.pid);
+            }
+            #[cfg(windows)]
+            {
+                if let Some(pid) = child.process_id {
+                    crate::diag!("Child process spawned with PID: {}", pid);
+                }
+            }
+        }
+
+        // Create output monitor
+        let mut monitor = OutputMonitor::new(prompt, verbose);
+        monitor.set_echo_guard(echo_guard);
+        monitor.set_ansi_filter(!args.no_ansi_filter);
+        monitor.set_repeat_prompt(profile.as_ref().and_then(|p| p.repeat_prompt.as_deref()));
+        monitor.set_expiry_handling(new_password.is_some());
+        monitor.set_ssh_checks(profile.as_ref().map_or(true, |p| p.ssh_checks));
+        if let Some(ref profile) = profile {
+            monitor.set_responses(profile.responses.iter().map(|r| r.expect.as_str()));
+            monitor.set_abort_patterns(profile.abort.iter().map(String::as_str));
+        }
+        for plugin in plugins {
+            monitor.add_detector(plugin);
+        }
+        if let Some(script) = script {
+            monitor.add_detector(script);
+        }
+        // Translated prompts lack "assword"
+        if sudo && args.prompt.is_none() && !args.sudo_sentinel {
+            if let Some(program) = args.command.first() {
+                monitor.add_detector(sudo_prompt::PamPrompt::new(program));
+            }
+        }
+
+        // What to type: the password, profile replies and setup lines
+        let mut login = profiles::LoginScript::new(&password, profile.as_ref(), PASSWORD_NEWLINE)
+            .with_new_password(new_password.as_ref());
+        if let Some(chooser) = chooser {
+            login = login.with_password_chooser(Box::new(move |prompt| chooser(prompt)));
+        }
+        if let Some(ref banner) = args.ack_banner {
+            monitor.add_response(banner);
+            login.push_response(args.ack_send.as_ref().map(|reply| reply.0.as_slice()));
+        }
+
+        // Command line flags win over the profile
+        let prompt_timeout = args
+            .prompt_timeout
+            .or_else(|| profile.as_ref().and_then(|p| p.prompt_timeout));
+        monitor.set_prompt_timeout(prompt_timeout.map(std::time::Duration::from_secs));
+        let confirm_timeout = args
+            .confirm_timeout
+            .or_else(|| profile.as_ref().and_then(|p| p.confirm_timeout))
+            .unwrap_or(60);
+        if confirm_timeout > 0 {
+            monitor.set_confirm_timeout(Some(std::time::Duration::from_secs(confirm_timeout)));
+        }
+
+        if let Some(attempts) = args.max_attempts {
+            monitor.set_max_attempts(attempts);
+        }
+        if let Some(key) = attempt_key {
+            monitor.set_attempt_key(key);
+        }
+        let quiet = args
+            .stop_matching_after_auth
+            .unwrap_or(monitor::DEFAULT_STOP_MATCHING_SECS);
+        if quiet > 0 {
+            monitor.set_stop_matching_after_auth(Some(std::time::Duration::from_secs(quiet)));
+        }
+        if let Some(window) = args.reauth_window {
+            monitor
+                .set_reprompt_heuristic(ReauthWindow::new(std::time::Duration::from_secs(window)));
+        }
+
+        let forwarder_config = stdin_forwarder::ForwarderConfig {
+            coalesce: std::time::Duration::from_millis(args.input_coalesce_ms),
+            drain_timeout: args.drain_timeout.map(std::time::Duration::from_secs),
+            enter: args.enter_sends,
+            escape_char: args.escape_char.0,
+            flow_control: args.flow_control,
+            initial_resize_retry: args.initial_resize_retry,
+            #[cfg(windows)]
+            no_console: args.no_console,
+            on_eof: args.on_stdin_eof,
+            paste_safe: args.paste_safe,
+            progress_io: progress::transfer_wanted(args.progress_io),
+        };
+
+        // Keystrokes from a terminal stdin, or the --input source. On Unix they
+        // are forwarded from the start: ssh discards typeahead when it turns echo
+        // off for the password, and a login with a key never asks for one.
+        #[cfg(unix)]
+        let forwarded = child.stdin_on_pty() && sequence.is_none();
+        #[cfg(windows)]
+        let forwarded = true;
+        let mut input: Box<dyn input_source::InputSource> = match source {
+            Some(source) if forwarded => source,
+            Some(_) => Box::new(input_source::Null),
+            #[cfg(unix)]
+            None if forwarded => Box::new(
+                stdin_forwarder::StdinReader::new(&forwarder_config, verbose).map_err(|e| {
+                    SshpassError::RuntimeError(format!("Failed to setup stdin forwarder: {}", e))
+                })?,
+            ),
+            #[cfg(windows)]
+            None => Box::new(
+                stdin_forwarder::StdinForwarder::new(forwarder_config.clone(), verbose).map_err(
+                    |e| {
+                        SshpassError::RuntimeError(format!(
+                            "Failed to setup stdin forwarder: {}",
+                            e
+                        ))
+                    },
+                )?,
+            ),
+            #[cfg(unix)]
+            None => Box::new(input_source::Null),
+        };
+
+        // Run the event loop
+        let result = EventLoop {
+            child,
+            login,
+            monitor: &mut monitor,
+            control,
+            config: forwarder_config,
+            input: input.as_mut(),
+            sequence: sequence.as_mut(),
+            output: output.as_mut(),
+            log: &mut log,
+        }
+        .run();
+
+        if verbose {
+            crate::diag!(
+                "Server asked for the password {} time(s)",
+                monitor.password_prompts()
+            );
+        }
+
+        // Report whatever completed, even if the session ended early
+        let mut result = result;
+        if let Some(mut seq) = sequence.filter(|seq| seq.is_json()) {
+            seq.set_auth_attempts(monitor.password_prompts());
+            let written = broken_pipe::write_stdout(seq.to_json().as_bytes());
+            result = result.and_then(|exit_code| written.map(|_| exit_code));
+        }
+
+        if monitor.password_changed() {
+            crate::diag!("Expired password was changed; log in again with the new password");
+        }
+
+        // Prove that password authentication actually took place
+        if let Ok(exit_code) = result {
+            // A reused connection logged in with the password earlier
+            if args.require_prompt && !monitor.password_sent() && !reusing {
+                if verbose {
+                    crate::diag!(
+                        "Child exited with code {} before any password prompt",
+                        exit_code
+                    );
+                    if ssh_env::agent_present() {
+                        crate::diag!("An ssh agent was available; --ssh-env scrub hides it");
+                    }
+                }
+                result = Err(SshpassError::PromptNotSeen);
+            }
+        }
+
+        if let Some(ref lockout) = lockout {
+            lockout.finish(&result, monitor.password_sent(), verbose);
+        }
+        finish_audit(result)
+    }
+}
+
+/// Add the `--persist` options to an ssh command, returning whether a
+/// master is already running for it
+#[cfg(unix)]
+fn use_persistent_master(command: &mut Vec<String>, seconds: u64, verbose: bool) -> Result<bool> {
+    let Some(tool) = ssh_argv::tool(command) else {
+        if verbose {
+            crate::diag!("Not running ssh, scp or sftp, ignoring --persist");
+        }
+        return Ok(false);
+    };
+    let dir = persist::control_dir().ok_or_else(|| {
+        SshpassError::RuntimeError(
+            "--persist needs $HOME or $XDG_CACHE_HOME for its sockets".to_string(),
+        )
+    })?;
+    persist::prepare_dir(&dir).map_err(|e| {
+        SshpassError::RuntimeError(format!("Failed to create {}: {}", dir.display(), e))
+    })?;
+
+    let added = ssh_argv::add_options(command, &persist::options(&dir, seconds), &[]);
+    if verbose {
+        crate::diag!("Added ssh options: {}", added.join(" "));
+    }
+    // scp and sftp cannot ask; they still reuse a running master
+    let running = tool == ssh_argv::SshTool::Ssh && persist::master_running(command);
+    if verbose && running {
+        crate::diag!("Reusing a persistent connection, no password prompt expected");
+    }
+    Ok(running)
+}
+
+/// Line ending typed after the password unless the profile sets one
+#[cfg(unix)]
+const PASSWORD_NEWLINE: &[u8] = b"\n";
+#[cfg(windows)]
+const PASSWORD_NEWLINE: &[u8] = b"\r\n";
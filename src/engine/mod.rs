@@ -0,0 +1,140 @@
+//! The event loop that drives a session
+//!
+//! [`EventLoop`] holds everything a session runs with, all of it built by
+//! the caller: the child on its terminal (a [`SessionChild`]), the monitor
+//! that finds prompts in the output, the [`SessionControl`] whose
+//! [`SignalSource`] and requests the loop handles, and the [`InputSource`]
+//! to forward. The sshpass binary builds them from its command line with
+//! [`launch::Launch`]; a library caller builds its own, and may leave the
+//! input out and type through a
+//! [`SessionHandle`](crate::session::SessionHandle) instead, and pass an
+//! [`OutputSink`] of its own to receive the output.
+//!
+//! On Unix the loop waits in `pselect` on the PTY, the input source and the
+//! session's wake-up pipe. On Windows threads read the PTY and stdin, and
+//! the loop polls what they send.
+
+use crate::error::{Result, SshpassError};
+use crate::event_queue::{EventQueue, Priority};
+use crate::input_source::InputSource;
+use crate::monitor::OutputMonitor;
+use crate::output_sink::OutputSink;
+use crate::process::ChildProcess;
+use crate::profiles::LoginScript;
+use crate::sequence::CommandSequence;
+use crate::session::{self, SessionControl};
+use crate::signal::SignalSource;
+use crate::stdin_forwarder::ForwarderConfig;
+use crate::{logging, signal};
+
+mod child;
+pub mod launch;
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+pub use child::{SessionChild, Terminal};
+
+/// A session for the event loop to run
+pub struct EventLoop<'a, C: SessionChild = ChildProcess> {
+    /// The child, spawned on its PTY
+    pub child: C,
+    /// What to type: the password, profile replies and setup lines
+    pub login: LoginScript<'a>,
+    /// Finds prompts in the child's output
+    pub monitor: &'a mut OutputMonitor,
+    /// Signal flags, and requests of session handles
+    pub control: &'a SessionControl,
+    /// How forwarded input is treated
+    pub config: ForwarderConfig,
+    /// Input to forward, [`input_source::Null`](crate::input_source::Null)
+    /// for none; on Unix only a child spawned with its stdin on the PTY
+    /// reads it
+    pub input: &'a mut dyn InputSource,
+    /// Commands typed in turn instead of forwarding stdin (`--then`)
+    pub sequence: Option<&'a mut CommandSequence>,
+    /// Where the output goes once the password is typed, usually
+    /// [`output_sink::Stdout`](crate::output_sink::Stdout)
+    pub output: &'a mut dyn OutputSink,
+    /// Verbosity of the diagnostics, and the secrets masked in the `-vvv`
+    /// dump of the PTY traffic
+    pub log: &'a mut logging::Logger,
+}
+
+impl<C: SessionChild> EventLoop<'_, C> {
+    /// Run the session until the child exits, returning its exit code
+    pub fn run(self) -> Result<i32> {
+        #[cfg(unix)]
+        return unix::run(self);
+        #[cfg(windows)]
+        return windows::run(self);
+    }
+}
+
+/// What the event loops handle ahead of I/O
+enum LoopEvent {
+    /// SIGTERM, SIGINT or SIGHUP (a console close or Ctrl+C on Windows)
+    Terminate(signal::TermSignal),
+    /// SIGTSTP
+    #[cfg(unix)]
+    Stop,
+    /// SIGWINCH
+    Resize,
+    Request(session::Request),
+}
+
+/// Queue the signals that came in; a termination signal stays set, so it
+/// is queued until the session is being terminated
+fn queue_signals(events: &mut EventQueue<LoopEvent>, signals: &dyn SignalSource, terminated: bool) {
+    if !terminated {
+        if let Some(sig) = signals.term_signal() {
+            events.push(Priority::Signal, LoopEvent::Terminate(sig));
+        }
+    }
+    #[cfg(unix)]
+    if signals.take_stop() {
+        events.push(Priority::Signal, LoopEvent::Stop);
+    }
+    if signals.take_resize() {
+        events.push(Priority::Signal, LoopEvent::Resize);
+    }
+}
+
+/// Write to the child's terminal, dumping the bytes at `-vvv`
+fn write_pty(pty: &dyn Terminal, log: &logging::Logger, data: &[u8]) -> Result<()> {
+    log.pty_write(data);
+    pty.write_all(data)
+}
+
+/// Show child output to the local user on stderr
+fn write_stderr(data: &[u8]) {
+    use std::io::Write;
+
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(data);
+    let _ = stderr.flush();
+}
+
+/// Error returned when a security key confirmation is never answered
+fn confirmation_timeout_error() -> SshpassError {
+    SshpassError::Timeout("waiting for security key confirmation".to_string())
+}
+
+/// Error returned when the password prompt does not appear in time
+fn prompt_timeout_error() -> SshpassError {
+    SshpassError::Timeout("waiting for the password prompt".to_string())
+}
+
+/// Error returned when the child had to be stopped after the drain timeout
+fn drain_timeout_error() -> SshpassError {
+    SshpassError::Timeout("waiting for the child to exit after the end of stdin".to_string())
+}
+
+/// Error returned when the output matches a profile abort pattern
+fn abort_error(monitor: &OutputMonitor) -> SshpassError {
+    SshpassError::RuntimeError(format!(
+        "Output matched abort pattern \"{}\"",
+        monitor.aborted_on().unwrap_or_default()
+    ))
+}
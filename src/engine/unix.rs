@@ -0,0 +1,584 @@
+//! The Unix event loop
+//!
+//! One thread waits in `pselect` on the PTY master, the forwarded stdin, the
+//! session's wake-up pipe and ssh's log pipe, with the signal mask lifted
+//! only while it waits.
+
+use super::{
+    abort_error, confirmation_timeout_error, drain_timeout_error, prompt_timeout_error,
+    queue_signals, write_pty, write_stderr, EventLoop, LoopEvent, SessionChild,
+};
+use crate::error::{Result, SshpassError};
+use crate::event_queue::{EventQueue, Priority};
+use crate::input_source::InputSource;
+use crate::monitor::MonitorResult;
+use crate::pty::MasterRead;
+use crate::signal::forward_signal_to_child;
+use crate::{
+    escape, flow_control, logging, paste, progress, resize_retry, session, stats, stdin_eof,
+    stdin_forwarder,
+};
+use nix::sys::select::{pselect, FdSet};
+use nix::sys::signal::SigSet;
+use std::os::fd::{AsRawFd, BorrowedFd};
+
+/// First and longest pause in polling the master after it reported EOF
+const EOF_BACKOFF: (std::time::Duration, std::time::Duration) = (
+    std::time::Duration::from_millis(1),
+    std::time::Duration::from_millis(100),
+);
+
+/// Run the session in a `pselect` loop
+pub(super) fn run<C: SessionChild>(parts: EventLoop<C>) -> Result<i32> {
+    let EventLoop {
+        mut child,
+        mut login,
+        monitor,
+        control,
+        config: forwarder_config,
+        input: source,
+        mut sequence,
+        output,
+        log,
+    } = parts;
+    let verbose = log.verbose();
+    let mut buffer = vec![0u8; 256];
+    let master_fd = child.terminal().master_fd();
+    let wake_fd = control.wake_fd();
+    let signal_flags = control.signals();
+    let mut terminated = false;
+    // The master stays readable after EOF or EIO, until the child is reaped
+    // and longer if the child closed the terminal but keeps running, so it
+    // is left out of pselect for a growing pause instead of spinning
+    let mut eof_backoff = std::time::Duration::ZERO;
+    let mut skip_master = false;
+    // Whether Authenticated was reported
+    let mut logged_in = false;
+
+    let mut input = ForwardedInput::new(&forwarder_config);
+    let mut initial_resize = resize_retry::ResizeRetry::new(forwarder_config.initial_resize_retry);
+
+    control.set_status(session::SessionStatus::WaitingForPrompt);
+
+    // Handle initial window size
+    if let Err(e) = child.terminal().resize() {
+        if verbose {
+            crate::diag!("Warning: Failed to set initial window size: {}", e);
+        }
+    }
+
+    loop {
+        // Signals, then requests from session handles
+        let mut events = EventQueue::new();
+        queue_signals(&mut events, signal_flags, terminated);
+        for request in control.take_requests() {
+            events.push(Priority::Request, LoopEvent::Request(request));
+        }
+        while let Some(event) = events.pop() {
+            match event {
+                LoopEvent::Terminate(sig) => {
+                    if verbose {
+                        crate::diag!("Received termination signal, forwarding to child");
+                    }
+                    let _ = forward_signal_to_child(sig, &child, verbose);
+                    terminated = true;
+                }
+                LoopEvent::Stop => {
+                    if let Err(e) =
+                        forward_signal_to_child(nix::sys::signal::SIGTSTP, &child, verbose)
+                    {
+                        if verbose {
+                            crate::diag!("Warning: Failed to forward SIGTSTP: {}", e);
+                        }
+                    }
+                }
+                LoopEvent::Resize => {
+                    if let Err(e) = child.terminal().resize() {
+                        if verbose {
+                            crate::diag!("Warning: Failed to handle window resize: {}", e);
+                        }
+                    }
+                }
+                LoopEvent::Request(session::Request::Cancel) => {
+                    if verbose {
+                        crate::diag!("Session cancelled, terminating child");
+                    }
+                    let _ = forward_signal_to_child(nix::sys::signal::SIGTERM, &child, verbose);
+                    terminated = true;
+                }
+                LoopEvent::Request(session::Request::Input(data)) => {
+                    input.inject(data, &child, log)?;
+                }
+                LoopEvent::Request(session::Request::Signal(number)) => {
+                    match nix::sys::signal::Signal::try_from(number) {
+                        Ok(sig) => {
+                            let _ = forward_signal_to_child(sig, &child, verbose);
+                        }
+                        Err(_) if verbose => {
+                            crate::diag!("Ignoring unknown signal {}", number);
+                        }
+                        Err(_) => {}
+                    }
+                }
+            }
+            // A signal that came in meanwhile goes ahead of what is left
+            queue_signals(&mut events, signal_flags, terminated);
+        }
+
+        terminated |= input.escalate(&child, verbose);
+
+        if let Some(step) = initial_resize.due() {
+            if let Err(e) = child.terminal().retry_size(step) {
+                if verbose {
+                    crate::diag!("Warning: Failed to resend window size: {}", e);
+                }
+            }
+        }
+
+        // Check if child has exited
+        if let Some(exit_code) = child.try_wait()? {
+            if verbose {
+                crate::diag!("Child process exited with code: {}", exit_code);
+            }
+            // What it printed last may still be in the PTY
+            if child.stdout_on_pty() && monitor.password_sent() {
+                while let Ok(n @ 1..) = child.terminal().read(&mut buffer) {
+                    control.output(&buffer[..n]);
+                    output.write(&buffer[..n])?;
+                }
+            }
+            control.set_termination(child.termination());
+            control.set_resource_usage(child.resource_usage());
+            if input.drain.escalated() {
+                return Err(drain_timeout_error());
+            }
+            return Ok(exit_code);
+        }
+
+        if terminated {
+            // Wait for child to exit
+            let exit_code = child.wait();
+            control.set_termination(child.termination());
+            control.set_resource_usage(child.resource_usage());
+            if input.drain.escalated() {
+                return Err(drain_timeout_error());
+            }
+            return exit_code;
+        }
+
+        if monitor.confirmation_expired() {
+            return Err(confirmation_timeout_error());
+        }
+        if monitor.prompt_timed_out() {
+            return Err(prompt_timeout_error());
+        }
+
+        // Use pselect to monitor the PTY with signal handling
+        let mut read_fds = FdSet::new();
+        let master_fd_borrowed = unsafe { BorrowedFd::borrow_raw(master_fd) };
+        if !skip_master {
+            read_fds.insert(&master_fd_borrowed);
+        }
+        let wake_fd_borrowed = unsafe { BorrowedFd::borrow_raw(wake_fd) };
+        read_fds.insert(&wake_fd_borrowed);
+        let log_fd = child.ssh_log().map(|log| log.as_raw_fd());
+        let log_fd_borrowed = log_fd.map(|fd| unsafe { BorrowedFd::borrow_raw(fd) });
+        if let Some(ref fd) = log_fd_borrowed {
+            read_fds.insert(fd);
+        }
+        let stdin_fd = source.fd();
+        let stdin_fd_borrowed = stdin_fd.map(|fd| unsafe { BorrowedFd::borrow_raw(fd) });
+        if let Some(ref fd) = stdin_fd_borrowed {
+            read_fds.insert(fd);
+        }
+
+        // Only wake up on a timer while a prompt or confirmation is awaited,
+        // or the drain timeout or the resize retry runs
+        let mut timeout = monitor.wait_timeout();
+        for remaining in [input.drain.remaining(), initial_resize.remaining()]
+            .into_iter()
+            .flatten()
+        {
+            timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+        }
+        if std::mem::take(&mut skip_master) {
+            timeout = Some(timeout.map_or(eof_backoff, |t| t.min(eof_backoff)));
+        }
+        if source.ready() {
+            timeout = Some(std::time::Duration::ZERO);
+        }
+        let timeout = timeout.map(nix::sys::time::TimeSpec::from_duration);
+
+        let empty_sigset = SigSet::empty();
+        let ready = pselect(
+            master_fd
+                .max(wake_fd)
+                .max(log_fd.unwrap_or(-1))
+                .max(stdin_fd.unwrap_or(-1))
+                + 1,
+            Some(&mut read_fds),
+            None,
+            None,
+            timeout.as_ref(),
+            Some(&empty_sigset),
+        );
+
+        // Signals that came in go ahead of the data that is ready, which
+        // waits in its descriptor for the next pass
+        if signal_flags.pending() {
+            continue;
+        }
+
+        // Label and pass on what ssh logged to its own pipe
+        if ready.is_ok() && log_fd_borrowed.is_some_and(|fd| read_fds.contains(&fd)) {
+            if let Some(log) = child.ssh_log() {
+                match log.read() {
+                    Ok(Some(data)) => {
+                        write_stderr(&log.label(&data));
+                        match monitor.handle_ssh_log(&data) {
+                            MonitorResult::IncorrectPassword => {
+                                return Err(SshpassError::IncorrectPassword);
+                            }
+                            MonitorResult::HostKeyChanged => {
+                                return Err(SshpassError::HostKeyChanged);
+                            }
+                            _ => {}
+                        }
+                    }
+                    // ssh has exited or closed its log
+                    Ok(None) | Err(_) => child.close_ssh_log(),
+                }
+            }
+        }
+
+        if ready.is_ok()
+            && (source.ready() || stdin_fd_borrowed.is_some_and(|fd| read_fds.contains(&fd)))
+        {
+            for event in source.read(verbose) {
+                terminated |= input.forward(event, &child, source, log)?;
+            }
+        }
+
+        let n = match ready {
+            Ok(_) if read_fds.contains(&master_fd_borrowed) => {
+                // Data available to read
+                match child.terminal().read_master(&mut buffer) {
+                    Ok(MasterRead::Data(n)) => {
+                        eof_backoff = std::time::Duration::ZERO;
+                        n
+                    }
+                    // Spurious wakeup, or interrupted by a signal
+                    Ok(MasterRead::Empty) => continue,
+                    Ok(MasterRead::Hangup) => {
+                        // The slave is not open yet, or the child closed it
+                        // or exited; an exit shows up before the next wait,
+                        // otherwise look again after a pause
+                        if verbose && eof_backoff.is_zero() {
+                            crate::diag!("EOF on PTY");
+                        }
+                        eof_backoff = next_eof_backoff(eof_backoff);
+                        skip_master = true;
+                        continue;
+                    }
+                    Err(e) => {
+                        if verbose {
+                            crate::diag!("Read error: {}", e);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            // The output paused after a prompt the monitor held back
+            Ok(_) if monitor.hold_expired() => 0,
+            Ok(_) => {
+                // Woken up by a session handle, or a timeout or signal
+                continue;
+            }
+            Err(nix::errno::Errno::EINTR) => {
+                // Interrupted by signal, continue
+                continue;
+            }
+            Err(e) => {
+                return Err(SshpassError::RuntimeError(format!("pselect error: {}", e)));
+            }
+        };
+
+        let data = &buffer[..n];
+        let mut result = if n == 0 {
+            monitor.finish()
+        } else {
+            log.pty_read(data);
+            control.output(data);
+            input.observe_output(data);
+            // Decided before the monitor sees the read holding the prompt;
+            // with stdin forwarded the terminal shows what the PTY echoes
+            let forward =
+                (child.stdout_on_pty() || child.stdin_on_pty()) && monitor.password_sent();
+
+            // In sequential mode the shell is driven by sshpass,
+            // so prompt matching stops once it is ready
+            let result = match sequence {
+                Some(ref seq) if seq.is_ready() => MonitorResult::Continue,
+                _ => monitor.handle_output(data),
+            };
+
+            if let Some(ref mut seq) = sequence {
+                let step = seq.feed(data);
+                output.write(&step.output)?;
+                write_pty(child.terminal(), log, &step.input)?;
+            } else if forward {
+                output.write(&input.flow_control.filter_output(data))?;
+                input.follow_flow_control(source);
+                input.show_transfer();
+                // Input held back while the remote side sent XOFF
+                if let Some(held) = input.flow_control.take_released() {
+                    write_pty(child.terminal(), log, &held)?;
+                    input.stats.record_input(held.len());
+                }
+            }
+            result
+        };
+
+        // A read can hold several prompts; handle each of them
+        loop {
+            match result {
+                MonitorResult::Continue => {
+                    if monitor.password_sent() {
+                        if monitor.login_succeeded() && !logged_in {
+                            logged_in = true;
+                            control.set_status(session::SessionStatus::Authenticated);
+                        }
+                        control.set_status(session::SessionStatus::Running);
+                        initial_resize.running();
+                    }
+
+                    // Type the profile's setup lines once logged in
+                    if sequence.is_none() && monitor.password_sent() {
+                        if let Some(setup) = login.feed(data) {
+                            if verbose {
+                                crate::diag!("Sending profile setup lines");
+                            }
+                            write_pty(child.terminal(), log, &setup)?;
+                        }
+                    }
+                }
+                MonitorResult::SendPassword => {
+                    // Send the password
+                    if verbose {
+                        crate::diag!("Sending password");
+                    }
+                    write_pty(
+                        child.terminal(),
+                        log,
+                        &login.password_line(monitor.prompt_match()),
+                    )?;
+                    log.password_sent();
+                    control.set_prompt_match(monitor.prompt_match().cloned());
+                    if let Some(ref mut seq) = sequence {
+                        write_pty(child.terminal(), log, &seq.start())?;
+                    }
+                    control.set_status(session::SessionStatus::PasswordSent);
+                }
+                MonitorResult::IncorrectPassword => {
+                    // Wrong password, terminate
+                    return Err(SshpassError::IncorrectPassword);
+                }
+                MonitorResult::HostKeyUnknown => {
+                    return Err(SshpassError::HostKeyUnknown);
+                }
+                MonitorResult::HostKeyChanged => {
+                    return Err(SshpassError::HostKeyChanged);
+                }
+                MonitorResult::PassThroughPrompt => {
+                    // No password to inject, the user has to answer
+                    write_stderr(data);
+                }
+                MonitorResult::SendResponse(index) => {
+                    write_pty(child.terminal(), log, login.response_line(index))?;
+                }
+                MonitorResult::Abort => {
+                    return Err(abort_error(monitor));
+                }
+                MonitorResult::PasswordExpired => {
+                    return Err(SshpassError::PasswordExpired);
+                }
+                MonitorResult::SendNewPassword => {
+                    write_pty(child.terminal(), log, &login.new_password_line())?;
+                }
+                MonitorResult::SendDetectorReply => {
+                    write_pty(child.terminal(), log, &monitor.take_detector_reply())?;
+                }
+            }
+
+            result = monitor.next_result();
+            if result == MonitorResult::Continue {
+                break;
+            }
+        }
+    }
+}
+
+/// The terminal input the Unix event loop forwards, and what it tracks of
+/// the session for it
+struct ForwardedInput {
+    stats: stats::SessionStats,
+    flow_control: flow_control::FlowControl,
+    bracketed_paste: paste::BracketedPaste,
+    on_eof: stdin_eof::StdinEofMode,
+    /// The last byte typed, to end a partial line before Ctrl-D
+    last_input: Option<u8>,
+    drain: stdin_eof::Drain,
+    /// The source was paused for the remote side's XOFF
+    source_paused: bool,
+    /// `--progress-io`
+    meter: Option<progress::TransferMeter>,
+}
+
+impl ForwardedInput {
+    fn new(config: &stdin_forwarder::ForwarderConfig) -> Self {
+        Self {
+            stats: stats::SessionStats::new(),
+            flow_control: flow_control::FlowControl::new(config.flow_control),
+            bracketed_paste: paste::BracketedPaste::new(config.paste_safe),
+            on_eof: config.on_eof,
+            last_input: None,
+            drain: stdin_eof::Drain::new(config.drain_timeout),
+            source_paused: false,
+            meter: config.progress_io.then(progress::TransferMeter::new),
+        }
+    }
+
+    /// Act on a child that outlasts the drain timeout, returning whether it
+    /// was killed
+    fn escalate(&mut self, child: &dyn SessionChild, verbose: bool) -> bool {
+        use nix::sys::signal::{SIGHUP, SIGKILL, SIGTERM};
+
+        let Some(step) = self.drain.due() else {
+            return false;
+        };
+        if verbose {
+            crate::diag!("Child still running after the drain timeout");
+        }
+        let signal = match step {
+            stdin_eof::Escalation::Hangup => SIGHUP,
+            stdin_eof::Escalation::Terminate => SIGTERM,
+            stdin_eof::Escalation::Kill => SIGKILL,
+        };
+        let _ = forward_signal_to_child(signal, child, verbose);
+        step == stdin_eof::Escalation::Kill
+    }
+
+    /// Count the PTY output and follow the remote side's bracketed paste mode
+    fn observe_output(&mut self, data: &[u8]) {
+        self.stats.record_output(data.len());
+        self.bracketed_paste.observe_output(data);
+    }
+
+    /// Redraw the `--progress-io` line after forwarding data
+    fn show_transfer(&mut self) {
+        if let Some(ref mut meter) = self.meter {
+            meter.update(&self.stats);
+        }
+    }
+
+    /// Pause the source while the remote side has sent XOFF, and resume it
+    /// after XON
+    fn follow_flow_control(&mut self, source: &mut dyn InputSource) {
+        let paused = self.flow_control.is_paused();
+        if paused != self.source_paused {
+            if paused {
+                source.pause();
+            } else {
+                source.resume();
+            }
+            self.source_paused = paused;
+        }
+    }
+
+    /// Type input from a session handle, held back like keystrokes while
+    /// the remote side has sent XOFF; it is typed, never wrapped as a paste
+    fn inject(
+        &mut self,
+        data: Vec<u8>,
+        child: &dyn SessionChild,
+        log: &mut logging::Logger,
+    ) -> Result<()> {
+        self.last_input = data.last().copied().or(self.last_input);
+        self.type_input(data, child, log)
+    }
+
+    fn type_input(
+        &mut self,
+        data: Vec<u8>,
+        child: &dyn SessionChild,
+        log: &mut logging::Logger,
+    ) -> Result<()> {
+        if let Some(data) = self.flow_control.gate_input(data) {
+            write_pty(child.terminal(), log, &data)?;
+            self.stats.record_input(data.len());
+            self.show_transfer();
+        } else if log.verbose() {
+            crate::diag!("Remote sent XOFF, holding input");
+        }
+        Ok(())
+    }
+
+    /// Write input to the PTY or act on an escape sequence, returning
+    /// whether the session is being terminated
+    fn forward(
+        &mut self,
+        event: stdin_forwarder::StdinEvent,
+        child: &dyn SessionChild,
+        source: &dyn InputSource,
+        log: &mut logging::Logger,
+    ) -> Result<bool> {
+        let verbose = log.verbose();
+        match event {
+            stdin_forwarder::StdinEvent::Data(data) => {
+                self.last_input = data.last().copied().or(self.last_input);
+                let data = self.bracketed_paste.wrap_input(data);
+                self.type_input(data, child, log)?;
+            }
+            stdin_forwarder::StdinEvent::Escape(action) => match action {
+                escape::EscapeAction::Terminate => {
+                    eprint!("\r\nSSHPASS: Terminating session\r\n");
+                    let _ = forward_signal_to_child(nix::sys::signal::SIGTERM, child, verbose);
+                    return Ok(true);
+                }
+                escape::EscapeAction::SendBreak => child.terminal().send_break()?,
+                escape::EscapeAction::Suspend => {
+                    if let Err(e) = source.suspend() {
+                        eprint!("\r\nSSHPASS: Failed to suspend: {}\r\n", e);
+                    }
+                }
+                escape::EscapeAction::ShowStats => {
+                    eprint!("\r\nSSHPASS: {}\r\n", self.stats.summary());
+                }
+                escape::EscapeAction::Help => eprint!("\r\n{}", escape::HELP_TEXT),
+            },
+            stdin_forwarder::StdinEvent::Eof => {
+                if verbose {
+                    crate::diag!("EOF on the input ({:?})", self.on_eof);
+                }
+                match self.on_eof {
+                    // The child reads on until it exits
+                    stdin_eof::StdinEofMode::KeepOpen => {}
+                    stdin_eof::StdinEofMode::SendEof => {
+                        write_pty(child.terminal(), log, stdin_eof::eof_input(self.last_input))?;
+                    }
+                    stdin_eof::StdinEofMode::CloseSession => {
+                        let _ = forward_signal_to_child(nix::sys::signal::SIGHUP, child, verbose);
+                    }
+                }
+                // Pass on what the child still prints until it exits
+                self.drain.start(self.on_eof);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Pause before looking at the master again after another EOF
+fn next_eof_backoff(previous: std::time::Duration) -> std::time::Duration {
+    (previous * 2).clamp(EOF_BACKOFF.0, EOF_BACKOFF.1)
+}
@@ -0,0 +1,512 @@
+//! Windows 的事件迴圈
+//!
+//! ConPTY 與 stdin 各由一個執行緒讀取，透過通道交給主迴圈輪詢。
+
+use super::{
+    abort_error, confirmation_timeout_error, drain_timeout_error, prompt_timeout_error,
+    queue_signals, write_pty, write_stderr, EventLoop, LoopEvent, SessionChild,
+};
+use crate::error::{Result, SshpassError};
+use crate::event_queue::{EventQueue, Priority};
+use crate::monitor::MonitorResult;
+use crate::signal::forward_signal_to_child;
+use crate::{
+    escape, flow_control, paste, progress, resize_retry, session, stats, stdin_eof,
+    stdin_forwarder, terminal_response,
+};
+use std::time::Duration;
+
+/// PTY 輸出事件
+enum PtyEvent {
+    Data(Vec<u8>),
+    Eof,
+    Error(String),
+}
+
+/// 以輪詢執行 session：PTY 與 stdin 由各自的執行緒讀取
+pub(super) fn run<C: SessionChild>(parts: EventLoop<C>) -> Result<i32> {
+    let EventLoop {
+        mut child,
+        mut login,
+        monitor,
+        control,
+        config: forwarder_config,
+        input: source,
+        mut sequence,
+        output,
+        log,
+    } = parts;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    let verbose = log.verbose();
+
+    if verbose {
+        crate::diag!("[DEBUG] Entering the event loop (Windows)");
+    }
+
+    let signal_flags = control.signals();
+    let mut terminated = false;
+    let mut empty_read_count = 0u32;
+    let mut last_status_report = std::time::Instant::now();
+
+    // session 統計（供 ~s 顯示）
+    let mut session_stats = stats::SessionStats::new();
+    // --progress-io 的傳輸進度
+    let mut transfer = forwarder_config
+        .progress_io
+        .then(progress::TransferMeter::new);
+
+    // 沒有 console 時無法查詢視窗大小，保留 ConPTY 預設的 80x24
+    let track_resize = !forwarder_config.no_console;
+
+    // 追蹤遠端的 XON/XOFF 狀態，以及輸入來源是否因此暫停
+    let mut flow_control = flow_control::FlowControl::new(forwarder_config.flow_control);
+    let mut source_paused = false;
+
+    // 追蹤遠端的 bracketed paste 狀態
+    let mut bracketed_paste = paste::BracketedPaste::new(forwarder_config.paste_safe);
+
+    // stdin 結束時的處理，以及最後送出的位元組（Ctrl-D 前是否要先結束該行）
+    let on_eof = forwarder_config.on_eof;
+    let mut last_input = None;
+    let mut drain = stdin_eof::Drain::new(forwarder_config.drain_timeout);
+    let mut initial_resize =
+        resize_retry::ResizeRetry::new(forwarder_config.initial_resize_retry && track_resize);
+
+    // 創建 PTY 讀取線程
+    let (pty_tx, pty_rx) = channel();
+    let pty_reader = child.terminal().reader();
+
+    if verbose {
+        crate::diag!("[DEBUG] Starting PTY reader thread...");
+    }
+
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; 512];
+        loop {
+            let mut reader = match pty_reader.lock() {
+                Ok(r) => r,
+                Err(_) => {
+                    let _ = pty_tx.send(PtyEvent::Error("Reader lock poisoned".into()));
+                    break;
+                }
+            };
+
+            match reader.read(&mut buffer) {
+                Ok(0) => {
+                    // EOF
+                    let _ = pty_tx.send(PtyEvent::Eof);
+                    break;
+                }
+                Ok(n) => {
+                    let data = buffer[..n].to_vec();
+                    if pty_tx.send(PtyEvent::Data(data)).is_err() {
+                        break; // 接收端已關閉
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    // 非阻塞模式下沒有數據
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                    continue;
+                }
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof
+                        || e.kind() == std::io::ErrorKind::BrokenPipe
+                    {
+                        let _ = pty_tx.send(PtyEvent::Eof);
+                    } else {
+                        let _ = pty_tx.send(PtyEvent::Error(format!("PTY read failed: {}", e)));
+                    }
+                    break;
+                }
+            }
+        }
+    });
+
+    if verbose {
+        crate::diag!("[DEBUG] PTY reader thread started, entering main loop");
+    }
+
+    let mut password_sent = false;
+    // 是否已回報 Authenticated
+    let mut logged_in = false;
+    control.set_status(session::SessionStatus::WaitingForPrompt);
+
+    if track_resize {
+        if let Err(e) = child.terminal().resize() {
+            if verbose {
+                crate::diag!("Warning: Failed to set initial window size: {}", e);
+            }
+        }
+    }
+
+    loop {
+        // 處理輸入來源（在密碼發送後才開始轉發；依序執行模式下由 sshpass 輸入指令）
+        if password_sent && sequence.is_none() {
+            // 有 signal 待處理時先停止轉發，剩下的輸入留待下一輪
+            'input: while !signal_flags.pending() {
+                let events = source.read(verbose);
+                if events.is_empty() {
+                    break;
+                }
+                for event in events {
+                    match event {
+                        stdin_forwarder::StdinEvent::Data(data) => {
+                            if verbose {
+                                crate::diag!(
+                                    "[DEBUG] Forwarding {} bytes from stdin to PTY",
+                                    data.len()
+                                );
+                            }
+                            last_input = data.last().copied().or(last_input);
+                            let data = bracketed_paste.wrap_input(data);
+                            if let Some(data) = flow_control.gate_input(data) {
+                                write_pty(child.terminal(), log, &data)?;
+                                session_stats.record_input(data.len());
+                                if let Some(ref mut meter) = transfer {
+                                    meter.update(&session_stats);
+                                }
+                            } else if verbose {
+                                crate::diag!("[DEBUG] Remote sent XOFF, holding stdin input");
+                            }
+                        }
+                        stdin_forwarder::StdinEvent::Escape(action) => {
+                            if verbose {
+                                crate::diag!("[DEBUG] Escape action: {:?}", action);
+                            }
+                            match action {
+                                escape::EscapeAction::Terminate => {
+                                    eprint!("\r\nSSHPASS: Terminating session\r\n");
+                                    let _ = child.kill();
+                                    terminated = true;
+                                    break 'input;
+                                }
+                                escape::EscapeAction::SendBreak => {
+                                    child.terminal().send_break()?;
+                                }
+                                escape::EscapeAction::Suspend => {
+                                    eprint!("\r\nSSHPASS: Suspend is not supported on Windows\r\n");
+                                }
+                                escape::EscapeAction::ShowStats => {
+                                    eprint!("\r\nSSHPASS: {}\r\n", session_stats.summary());
+                                }
+                                escape::EscapeAction::Help => {
+                                    eprint!("\r\n{}", escape::HELP_TEXT);
+                                }
+                            }
+                        }
+                        stdin_forwarder::StdinEvent::Eof => {
+                            if verbose {
+                                crate::diag!("[DEBUG] stdin EOF received ({:?})", on_eof);
+                            }
+                            // 不要立即終止 - 繼續讀取 PTY 輸出直到子進程退出
+                            // 這對於非互動式使用很重要（例如 echo "command" | sshpass ...）
+                            match on_eof {
+                                stdin_eof::StdinEofMode::KeepOpen => {}
+                                stdin_eof::StdinEofMode::SendEof => {
+                                    write_pty(
+                                        child.terminal(),
+                                        log,
+                                        stdin_eof::eof_input(last_input),
+                                    )?;
+                                }
+                                // 無法掛斷，直接結束子程序
+                                stdin_eof::StdinEofMode::CloseSession => {
+                                    let _ = child.kill();
+                                }
+                            }
+                            drain.start(on_eof);
+                        }
+                    }
+                }
+            }
+        }
+
+        // 先處理 signal，再處理 session handle 的請求
+        let mut events = EventQueue::new();
+        queue_signals(&mut events, signal_flags, terminated);
+        for request in control.take_requests() {
+            events.push(Priority::Request, LoopEvent::Request(request));
+        }
+        while let Some(event) = events.pop() {
+            match event {
+                LoopEvent::Terminate(()) => {
+                    if verbose {
+                        crate::diag!("Received console termination event, forwarding to child");
+                    }
+                    let _ = forward_signal_to_child((), &mut child, verbose);
+                    terminated = true;
+                }
+                LoopEvent::Resize => {
+                    if track_resize {
+                        if let Err(e) = child.terminal().resize() {
+                            if verbose {
+                                crate::diag!("Warning: Failed to handle window resize: {}", e);
+                            }
+                        }
+                    }
+                }
+                // 無法送出任意 signal，一律結束子程序
+                LoopEvent::Request(session::Request::Cancel | session::Request::Signal(_)) => {
+                    if verbose {
+                        crate::diag!("Session cancelled, terminating child");
+                    }
+                    let _ = forward_signal_to_child((), &mut child, verbose);
+                    terminated = true;
+                }
+                // 與按鍵相同，遠端送出 XOFF 期間暫存；不包成貼上內容
+                LoopEvent::Request(session::Request::Input(data)) => {
+                    last_input = data.last().copied().or(last_input);
+                    if let Some(data) = flow_control.gate_input(data) {
+                        write_pty(child.terminal(), log, &data)?;
+                        session_stats.record_input(data.len());
+                    }
+                }
+            }
+            // 期間收到的 signal 排在其餘請求之前
+            queue_signals(&mut events, signal_flags, terminated);
+        }
+
+        // stdin 結束後，子程序超過排空時間仍未結束：無法送出 signal，一律結束子程序
+        if drain.due().is_some() {
+            if verbose {
+                crate::diag!("Child still running after the drain timeout");
+            }
+            let _ = forward_signal_to_child((), &mut child, verbose);
+            terminated = true;
+        }
+
+        if let Some(step) = initial_resize.due() {
+            if let Err(e) = child.terminal().retry_size(step) {
+                if verbose {
+                    crate::diag!("Warning: Failed to resend window size: {}", e);
+                }
+            }
+        }
+
+        if let Some(exit_code) = child.try_wait()? {
+            if verbose {
+                crate::diag!("Child process exited with code: {}", exit_code);
+            }
+            control.set_resource_usage(child.resource_usage());
+            if drain.escalated() {
+                return Err(drain_timeout_error());
+            }
+            return Ok(exit_code);
+        }
+
+        if terminated {
+            let exit_code = child.wait();
+            control.set_resource_usage(child.resource_usage());
+            if drain.escalated() {
+                return Err(drain_timeout_error());
+            }
+            return exit_code;
+        }
+
+        if monitor.confirmation_expired() {
+            return Err(confirmation_timeout_error());
+        }
+        if monitor.prompt_timed_out() {
+            return Err(prompt_timeout_error());
+        }
+
+        // 有 signal 待處理時先回到迴圈開頭，PTY 輸出留在通道中
+        if signal_flags.pending() {
+            continue;
+        }
+
+        // 處理 PTY 輸出；輸出停頓後回報 monitor 暫緩的密碼提示
+        let event = match pty_rx.try_recv() {
+            Err(_) if monitor.hold_expired() => Ok(PtyEvent::Data(Vec::new())),
+            event => event,
+        };
+        match event {
+            Ok(PtyEvent::Data(buffer)) => {
+                let mut result = if buffer.is_empty() {
+                    monitor.finish()
+                } else {
+                    empty_read_count = 0;
+                    log.pty_read(&buffer);
+
+                    if verbose {
+                        crate::diag!("[DEBUG] PTY read {} bytes", buffer.len());
+                        if buffer.len() < 100 {
+                            crate::diag!("[DEBUG] Data: {:?}", String::from_utf8_lossy(&buffer));
+                        }
+                    }
+
+                    // Check for terminal queries (portable-pty handles these internally, but we log them)
+                    if let Some(response) = terminal_response::get_terminal_response(&buffer) {
+                        if verbose {
+                            crate::diag!(
+                                "[DEBUG] Terminal query detected ({} bytes)",
+                                response.len()
+                            );
+                            crate::diag!("[DEBUG] portable-pty handles these automatically");
+                        }
+                    }
+
+                    session_stats.record_output(buffer.len());
+                    control.output(&buffer);
+                    bracketed_paste.observe_output(&buffer);
+                    let result = match sequence {
+                        Some(ref seq) if seq.is_ready() => MonitorResult::Continue,
+                        _ => monitor.handle_output(&buffer),
+                    };
+
+                    // 在密碼發送後，將所有 PTY 輸出轉發到 stdout
+                    let display = flow_control.filter_output(&buffer);
+                    // 遠端送出 XOFF 期間暫停輸入來源，XON 後恢復
+                    if flow_control.is_paused() != source_paused {
+                        source_paused = flow_control.is_paused();
+                        if source_paused {
+                            source.pause();
+                        } else {
+                            source.resume();
+                        }
+                    }
+                    if let Some(ref mut seq) = sequence {
+                        let step = seq.feed(&display);
+                        output.write(&step.output)?;
+                        write_pty(child.terminal(), log, &step.input)?;
+                    } else if password_sent {
+                        output.write(&display)?;
+                        if let Some(ref mut meter) = transfer {
+                            meter.update(&session_stats);
+                        }
+
+                        // 遠端送出 XON 後，送出 XOFF 期間暫存的輸入
+                        if let Some(held) = flow_control.take_released() {
+                            write_pty(child.terminal(), log, &held)?;
+                            session_stats.record_input(held.len());
+                        }
+                    }
+                    result
+                };
+
+                // 一次讀取可能包含多個提示，逐一處理
+                loop {
+                    match result {
+                        MonitorResult::Continue => {
+                            if password_sent {
+                                if monitor.login_succeeded() && !logged_in {
+                                    logged_in = true;
+                                    control.set_status(session::SessionStatus::Authenticated);
+                                }
+                                control.set_status(session::SessionStatus::Running);
+                                initial_resize.running();
+                            }
+
+                            // 登入完成後送出 profile 的設定指令
+                            if sequence.is_none() && password_sent {
+                                if let Some(setup) = login.feed(&buffer) {
+                                    if verbose {
+                                        crate::diag!("Sending profile setup lines");
+                                    }
+                                    write_pty(child.terminal(), log, &setup)?;
+                                    session_stats.record_input(setup.len());
+                                }
+                            }
+                        }
+                        MonitorResult::SendPassword => {
+                            if verbose {
+                                crate::diag!("Sending password");
+                            }
+                            write_pty(
+                                child.terminal(),
+                                log,
+                                &login.password_line(monitor.prompt_match()),
+                            )?;
+                            log.password_sent();
+                            control.set_prompt_match(monitor.prompt_match().cloned());
+                            if let Some(ref mut seq) = sequence {
+                                write_pty(child.terminal(), log, &seq.start())?;
+                            }
+                            password_sent = true; // 標記密碼已發送，開始轉發 stdin
+                            control.set_status(session::SessionStatus::PasswordSent);
+                            if verbose {
+                                crate::diag!("[DEBUG] Password sent, now forwarding stdin");
+                            }
+                        }
+                        MonitorResult::IncorrectPassword => {
+                            return Err(SshpassError::IncorrectPassword);
+                        }
+                        MonitorResult::HostKeyUnknown => {
+                            return Err(SshpassError::HostKeyUnknown);
+                        }
+                        MonitorResult::HostKeyChanged => {
+                            return Err(SshpassError::HostKeyChanged);
+                        }
+                        MonitorResult::PassThroughPrompt => {
+                            // 密碼發送後輸出已轉發到 stdout，不需要重複顯示
+                            if !password_sent {
+                                write_stderr(&buffer);
+                            }
+                        }
+                        MonitorResult::SendResponse(index) => {
+                            let response = login.response_line(index);
+                            write_pty(child.terminal(), log, response)?;
+                            session_stats.record_input(response.len());
+                        }
+                        MonitorResult::Abort => {
+                            return Err(abort_error(monitor));
+                        }
+                        MonitorResult::PasswordExpired => {
+                            return Err(SshpassError::PasswordExpired);
+                        }
+                        MonitorResult::SendNewPassword => {
+                            write_pty(child.terminal(), log, &login.new_password_line())?;
+                        }
+                        MonitorResult::SendDetectorReply => {
+                            let reply = monitor.take_detector_reply();
+                            write_pty(child.terminal(), log, &reply)?;
+                            session_stats.record_input(reply.len());
+                        }
+                    }
+
+                    result = monitor.next_result();
+                    if result == MonitorResult::Continue {
+                        break;
+                    }
+                }
+            }
+            Ok(PtyEvent::Eof) => {
+                if verbose {
+                    crate::diag!("[DEBUG] PTY EOF received");
+                }
+                // Continue to wait for child exit
+            }
+            Ok(PtyEvent::Error(e)) => {
+                if verbose {
+                    crate::diag!("[DEBUG] PTY read error: {}", e);
+                }
+                // Check if child has exited
+                if let Some(exit_code) = child.try_wait()? {
+                    if verbose {
+                        crate::diag!("Child exited with code {}", exit_code);
+                    }
+                    control.set_resource_usage(child.resource_usage());
+                    return Ok(exit_code);
+                }
+            }
+            Err(_) => {
+                // No data available this iteration
+                empty_read_count += 1;
+
+                // Report status every 2 seconds if still getting empty reads
+                if verbose && last_status_report.elapsed().as_secs() >= 2 {
+                    crate::diag!(
+                        "[STATUS] Still waiting for data... (empty reads: {}, elapsed: {:.1}s)",
+                        empty_read_count,
+                        last_status_report.elapsed().as_secs_f64()
+                    );
+                    last_status_report = std::time::Instant::now();
+                }
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
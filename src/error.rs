@@ -1,6 +1,24 @@
 use thiserror::Error;
 
-/// Return codes matching the original C version of sshpass
+/// Return codes: 0-7 match the original C version of sshpass, 8 and up are
+/// this implementation's
+///
+/// | Code | Meaning |
+/// |------|---------|
+/// | 0 | Success |
+/// | 1 | Invalid command line arguments |
+/// | 2 | Conflicting password sources |
+/// | 3 | Any other runtime error |
+/// | 4 | The child's output could not be parsed |
+/// | 5 | Incorrect password |
+/// | 6 | Unknown host key |
+/// | 7 | Changed host key |
+/// | 8 | No password prompt before the child exited (`--require-prompt`) |
+/// | 9 | Expired password |
+/// | 10 | Locked out after incorrect passwords |
+/// | 11 | A timeout ran out |
+/// | 12 | Hosts whose output changed (`sshpass diff`) |
+/// | 13 | Too many hosts of a batch failed |
 #[repr(i32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReturnCode {
@@ -12,6 +30,19 @@ pub enum ReturnCode {
     IncorrectPassword = 5,
     HostKeyUnknown = 6,
     HostKeyChanged = 7,
+    /// The child exited without a password prompt being seen (`--require-prompt`)
+    PromptNotSeen = 8,
+    /// The password has expired and was not changed
+    PasswordExpired = 9,
+    /// Not retried after too many incorrect passwords (lockout guard)
+    LockedOut = 10,
+    /// A timeout ran out: `--prompt-timeout`, `--confirm-timeout` or
+    /// `--drain-timeout`; worth retrying, unlike an incorrect password
+    Timeout = 11,
+    /// `sshpass diff` found hosts whose output changed between the runs
+    RunsDiffer = 12,
+    /// Too many hosts of a batch failed for its exit policy
+    BatchFailed = 13,
 }
 
 impl ReturnCode {
@@ -44,6 +75,23 @@ pub enum SshpassError {
     #[error("Host public key has changed")]
     HostKeyChanged,
 
+    #[error("Child exited without a password prompt being detected")]
+    PromptNotSeen,
+
+    #[error("Password has expired and must be changed")]
+    PasswordExpired,
+
+    #[error("Not retrying {host} after {failures} incorrect password(s), try again in {retry_after} second(s)")]
+    LockedOut {
+        host: String,
+        failures: usize,
+        retry_after: u64,
+    },
+
+    /// What was being waited for, e.g. "waiting for the password prompt"
+    #[error("Timed out {0}")]
+    Timeout(String),
+
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -85,6 +133,10 @@ impl SshpassError {
             SshpassError::IncorrectPassword => ReturnCode::IncorrectPassword,
             SshpassError::HostKeyUnknown => ReturnCode::HostKeyUnknown,
             SshpassError::HostKeyChanged => ReturnCode::HostKeyChanged,
+            SshpassError::PromptNotSeen => ReturnCode::PromptNotSeen,
+            SshpassError::PasswordExpired => ReturnCode::PasswordExpired,
+            SshpassError::LockedOut { .. } => ReturnCode::LockedOut,
+            SshpassError::Timeout(_) => ReturnCode::Timeout,
             SshpassError::IoError(_) => ReturnCode::RuntimeError,
             #[cfg(unix)]
             SshpassError::SystemError(_) => ReturnCode::RuntimeError,
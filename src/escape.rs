@@ -0,0 +1,226 @@
+//! 本地跳脫字元選單（類似 ssh 的 `~.`）
+//!
+//! 在換行後輸入跳脫字元（預設 `~`）再接一個指令字元，即可在本地執行動作，
+//! 而不會送到遠端：
+//!
+//! * `~.` - 終止 session
+//! * `~B` - 送出 BREAK
+//! * `~^Z` - 暫停 sshpass
+//! * `~s` - 顯示 session 統計
+//! * `~?` - 顯示說明
+//! * `~~` - 送出一個字面上的 `~`
+
+/// 跳脫選單觸發的本地動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeAction {
+    /// 終止 session
+    Terminate,
+    /// 送出 BREAK
+    SendBreak,
+    /// 暫停 sshpass
+    Suspend,
+    /// 顯示統計資料
+    ShowStats,
+    /// 顯示說明
+    Help,
+}
+
+/// 解析後的輸入片段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EscapeOutput {
+    /// 轉發到 PTY 的資料
+    Data(Vec<u8>),
+    /// 本地動作
+    Action(EscapeAction),
+}
+
+/// `--escape-char` 的值，`None` 表示停用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscapeChar(pub Option<u8>);
+
+impl Default for EscapeChar {
+    fn default() -> Self {
+        EscapeChar(Some(b'~'))
+    }
+}
+
+/// 解析 `--escape-char`：單一 ASCII 字元、`^X` 控制字元表示法或 `none`
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_escape_char(value: &str) -> Result<EscapeChar, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(EscapeChar(None));
+    }
+
+    let bytes = value.as_bytes();
+    match bytes {
+        [b] if b.is_ascii() => Ok(EscapeChar(Some(*b))),
+        [b'^', b] if b.is_ascii_alphabetic() || b"[\\]^_".contains(b) => {
+            Ok(EscapeChar(Some(b.to_ascii_uppercase() & 0x1f)))
+        }
+        _ => Err(format!(
+            "invalid escape character '{}': expected a single character, ^X or 'none'",
+            value
+        )),
+    }
+}
+
+/// 說明文字
+pub const HELP_TEXT: &str = "Supported escape sequences:\r\n \
+     ~.   - terminate session\r\n \
+     ~B   - send a BREAK to the remote system\r\n \
+     ~^Z  - suspend sshpass\r\n \
+     ~s   - show session statistics\r\n \
+     ~?   - this message\r\n \
+     ~~   - send the escape character by typing it twice\r\n\
+     (Note that escapes are only recognized immediately after newline.)\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// 位於行首，下一個字元若是跳脫字元則進入待命狀態
+    LineStart,
+    /// 行中
+    Normal,
+    /// 已讀到行首的跳脫字元，等待指令字元
+    Pending,
+}
+
+/// 逐位元組解析 stdin 輸入中的跳脫序列，狀態跨呼叫保留
+#[derive(Debug, Clone)]
+pub struct EscapeParser {
+    escape: u8,
+    state: State,
+}
+
+impl EscapeParser {
+    /// 建立解析器
+    pub fn new(escape: u8) -> Self {
+        Self {
+            escape,
+            state: State::LineStart,
+        }
+    }
+
+    /// 處理一段輸入，依原始順序回傳資料與動作
+    pub fn process(&mut self, input: &[u8]) -> Vec<EscapeOutput> {
+        let mut outputs = Vec::new();
+        let mut data = Vec::with_capacity(input.len());
+
+        for &byte in input {
+            match self.state {
+                State::Pending => {
+                    let action = match byte {
+                        b'.' => Some(EscapeAction::Terminate),
+                        b'B' => Some(EscapeAction::SendBreak),
+                        0x1a => Some(EscapeAction::Suspend),
+                        b's' => Some(EscapeAction::ShowStats),
+                        b'?' => Some(EscapeAction::Help),
+                        _ => None,
+                    };
+
+                    match action {
+                        Some(action) => {
+                            if !data.is_empty() {
+                                outputs.push(EscapeOutput::Data(std::mem::take(&mut data)));
+                            }
+                            outputs.push(EscapeOutput::Action(action));
+                            self.state = State::Normal;
+                        }
+                        None if byte == self.escape => {
+                            data.push(byte);
+                            self.state = State::Normal;
+                        }
+                        None => {
+                            // 不是指令，跳脫字元與此字元照常送出
+                            data.push(self.escape);
+                            data.push(byte);
+                            self.state = next_state(byte);
+                        }
+                    }
+                }
+                State::LineStart if byte == self.escape => {
+                    self.state = State::Pending;
+                }
+                _ => {
+                    data.push(byte);
+                    self.state = next_state(byte);
+                }
+            }
+        }
+
+        if !data.is_empty() {
+            outputs.push(EscapeOutput::Data(data));
+        }
+        outputs
+    }
+}
+
+fn next_state(byte: u8) -> State {
+    if byte == b'\r' || byte == b'\n' {
+        State::LineStart
+    } else {
+        State::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(bytes: &[u8]) -> EscapeOutput {
+        EscapeOutput::Data(bytes.to_vec())
+    }
+
+    #[test]
+    fn test_terminate_after_newline() {
+        let mut parser = EscapeParser::new(b'~');
+        assert_eq!(
+            parser.process(b"ls\n~."),
+            vec![data(b"ls\n"), EscapeOutput::Action(EscapeAction::Terminate)]
+        );
+    }
+
+    #[test]
+    fn test_escape_at_session_start() {
+        let mut parser = EscapeParser::new(b'~');
+        assert_eq!(
+            parser.process(b"~?"),
+            vec![EscapeOutput::Action(EscapeAction::Help)]
+        );
+    }
+
+    #[test]
+    fn test_escape_split_across_chunks() {
+        let mut parser = EscapeParser::new(b'~');
+        assert_eq!(parser.process(b"\r~"), vec![data(b"\r")]);
+        assert_eq!(
+            parser.process(b"B"),
+            vec![EscapeOutput::Action(EscapeAction::SendBreak)]
+        );
+    }
+
+    #[test]
+    fn test_escape_in_middle_of_line_is_data() {
+        let mut parser = EscapeParser::new(b'~');
+        assert_eq!(parser.process(b"cd ~."), vec![data(b"cd ~.")]);
+    }
+
+    #[test]
+    fn test_double_escape_sends_literal() {
+        let mut parser = EscapeParser::new(b'~');
+        assert_eq!(parser.process(b"~~/bin"), vec![data(b"~/bin")]);
+    }
+
+    #[test]
+    fn test_unknown_command_passes_through() {
+        let mut parser = EscapeParser::new(b'~');
+        assert_eq!(parser.process(b"~x\n"), vec![data(b"~x\n")]);
+    }
+
+    #[test]
+    fn test_parse_escape_char() {
+        assert_eq!(parse_escape_char("~"), Ok(EscapeChar(Some(b'~'))));
+        assert_eq!(parse_escape_char("none"), Ok(EscapeChar(None)));
+        assert_eq!(parse_escape_char("^]"), Ok(EscapeChar(Some(0x1d))));
+        assert!(parse_escape_char("ab").is_err());
+    }
+}
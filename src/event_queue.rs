@@ -0,0 +1,76 @@
+//! Order of the events an event loop handles
+//!
+//! One pass of an event loop can find signals, requests from session handles
+//! and data all waiting at once. Signals and requests are queued by
+//! [`Priority`] and handled most urgent first, in arrival order within a
+//! priority; the loops look for new signals after each event, and a signal
+//! queued then goes ahead of the requests still waiting. Data comes last:
+//! while a signal is pending the loops leave PTY output and keystrokes where
+//! they are for the next pass, so a flood of either never holds back a
+//! SIGTERM.
+
+use std::collections::VecDeque;
+
+/// How urgent an event is, most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Signals sshpass received
+    Signal,
+    /// Requests of session handles
+    Request,
+}
+
+const PRIORITIES: [Priority; 2] = [Priority::Signal, Priority::Request];
+
+/// Events waiting to be handled
+#[derive(Debug)]
+pub struct EventQueue<T> {
+    queues: [VecDeque<T>; 2],
+}
+
+impl<T> EventQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            queues: Default::default(),
+        }
+    }
+
+    pub fn push(&mut self, priority: Priority, event: T) {
+        self.queues[priority as usize].push_back(event);
+    }
+
+    /// The most urgent event, the oldest of its priority
+    pub fn pop(&mut self) -> Option<T> {
+        PRIORITIES
+            .iter()
+            .find_map(|&priority| self.queues[priority as usize].pop_front())
+    }
+}
+
+impl<T> Default for EventQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signals_go_first() {
+        let mut queue = EventQueue::new();
+        assert_eq!(queue.pop(), None);
+        queue.push(Priority::Request, "input");
+        queue.push(Priority::Request, "cancel");
+        queue.push(Priority::Signal, "SIGTERM");
+        assert_eq!(queue.pop(), Some("SIGTERM"));
+        assert_eq!(queue.pop(), Some("input"));
+
+        // A signal queued later still goes ahead
+        queue.push(Priority::Signal, "SIGWINCH");
+        assert_eq!(queue.pop(), Some("SIGWINCH"));
+        assert_eq!(queue.pop(), Some("cancel"));
+        assert_eq!(queue.pop(), None);
+    }
+}
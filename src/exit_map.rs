@@ -0,0 +1,72 @@
+//! Translation of the child's exit code (`--map-exit-code FROM=TO`)
+//!
+//! Some tools exit non-zero for outcomes a pipeline treats as success, such
+//! as rsync's 24 when source files vanished during the transfer. Each
+//! mapping replaces one exit code of the child with another once it has
+//! exited. The first mapping for a code applies and mappings do not chain.
+//! sshpass's own exit codes (5 for an incorrect password and so on) are
+//! never mapped.
+
+/// One `--map-exit-code` mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitMapping {
+    pub from: i32,
+    pub to: i32,
+}
+
+/// Parse `--map-exit-code`
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_exit_mapping(value: &str) -> std::result::Result<ExitMapping, String> {
+    let code = |text: &str| {
+        text.trim()
+            .parse::<u8>()
+            .map(i32::from)
+            .map_err(|_| format!("expected FROM=TO with exit codes 0-255, got '{}'", value))
+    };
+    let (from, to) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected FROM=TO, got '{}'", value))?;
+    Ok(ExitMapping {
+        from: code(from)?,
+        to: code(to)?,
+    })
+}
+
+/// The exit code sshpass reports for the child's `code`
+pub fn apply(mappings: &[ExitMapping], code: i32) -> i32 {
+    mappings
+        .iter()
+        .find(|mapping| mapping.from == code)
+        .map_or(code, |mapping| mapping.to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exit_mapping() {
+        assert_eq!(
+            parse_exit_mapping("24=0"),
+            Ok(ExitMapping { from: 24, to: 0 })
+        );
+        assert!(parse_exit_mapping("24").is_err());
+        assert!(parse_exit_mapping("24=").is_err());
+        assert!(parse_exit_mapping("256=0").is_err());
+        assert!(parse_exit_mapping("-1=0").is_err());
+    }
+
+    #[test]
+    fn test_apply_exit_mapping() {
+        let mappings = [
+            ExitMapping { from: 24, to: 0 },
+            ExitMapping { from: 1, to: 24 },
+            ExitMapping { from: 24, to: 3 },
+        ];
+        assert_eq!(apply(&mappings, 24), 0);
+        // No chaining through 24
+        assert_eq!(apply(&mappings, 1), 24);
+        assert_eq!(apply(&mappings, 2), 2);
+        assert_eq!(apply(&[], 24), 24);
+    }
+}
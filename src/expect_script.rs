@@ -0,0 +1,179 @@
+//! Expect/send scripts (`--script`)
+//!
+//! For small automations that need more than the password, a script lists
+//! what to wait for and what to type in reply, one directive per line:
+//!
+//! ```text
+//! # blank lines and comments are skipped
+//! expect Username:
+//! send admin
+//! expect $
+//! send uptime
+//! ```
+//!
+//! `expect TEXT` waits until TEXT appears in the output; `send TEXT` types
+//! TEXT followed by the line ending. TEXT is everything after the first space
+//! up to the end of the line, trailing spaces included, with the escapes of
+//! `--ack-send` (`\r`, `\n`, `\t`, `\e`, `\\`, `\xHH`). Sends before the first
+//! `expect` are typed as soon as the child prints anything.
+//!
+//! The password prompt is still answered by sshpass; the script runs
+//! alongside it as a [`Detector`]. `--script -` reads the script from
+//! standard input before the command starts, for scripts generated by other
+//! programs; the child then finds its standard input at end of file.
+
+use crate::monitor::{Detection, Detector, Matcher};
+use crate::profiles::parse_reply_bytes;
+use std::io::Read;
+use std::path::Path;
+
+/// One line of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Wait for this text
+    Expect(String),
+    /// Type these bytes and the line ending
+    Send(Vec<u8>),
+}
+
+/// Parse a script, reporting the first bad line with its number
+pub fn parse(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let line = line.trim_start();
+        let (directive, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let bad = |reason: String| format!("line {}: {}", number + 1, reason);
+        let bytes = parse_reply_bytes(argument).map_err(bad)?.0;
+        match directive {
+            "expect" if bytes.is_empty() => return Err(bad("expect needs text".to_string())),
+            "expect" => steps.push(Step::Expect(String::from_utf8_lossy(&bytes).into_owned())),
+            "send" => steps.push(Step::Send(bytes)),
+            other => return Err(bad(format!("unknown directive '{}'", other))),
+        }
+    }
+    Ok(steps)
+}
+
+/// Read a script from a file, or from standard input for `-`
+pub fn read_script(path: &Path) -> std::io::Result<String> {
+    if path == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Runs a script against the session output
+pub struct ExpectScript {
+    steps: Vec<Step>,
+    next: usize,
+    matcher: Option<Matcher>,
+    newline: Vec<u8>,
+}
+
+impl ExpectScript {
+    /// Create the interpreter
+    ///
+    /// # Arguments
+    /// * `steps` - Parsed script
+    /// * `newline` - Line ending typed after each `send`
+    pub fn new(steps: Vec<Step>, newline: &[u8]) -> Self {
+        Self {
+            steps,
+            next: 0,
+            matcher: None,
+            newline: newline.to_vec(),
+        }
+    }
+}
+
+impl Detector for ExpectScript {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    fn feed(&mut self, mut data: &[u8]) -> Detection {
+        let mut reply = Vec::new();
+        while let Some(step) = self.steps.get(self.next) {
+            match step {
+                Step::Send(bytes) => {
+                    reply.extend_from_slice(bytes);
+                    reply.extend_from_slice(&self.newline);
+                }
+                Step::Expect(text) => {
+                    let matcher = self
+                        .matcher
+                        .get_or_insert_with(|| Matcher::new(text.as_str()));
+                    match matcher.find(data) {
+                        // Later expects only look at the output after this one
+                        Some(end) => {
+                            data = &data[end..];
+                            self.matcher = None;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            self.next += 1;
+        }
+
+        if reply.is_empty() {
+            Detection::Nothing
+        } else {
+            Detection::Reply(reply)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let steps = parse("# login\r\nexpect Username:\r\nsend admin\n\n  send \\x03\nexpect $ \n")
+            .unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                Step::Expect("Username:".to_string()),
+                Step::Send(b"admin".to_vec()),
+                Step::Send(vec![0x03]),
+                Step::Expect("$ ".to_string()),
+            ]
+        );
+
+        assert_eq!(parse("send\n").unwrap(), vec![Step::Send(Vec::new())]);
+        assert_eq!(
+            parse("expect a\nwait 5\n").unwrap_err(),
+            "line 2: unknown directive 'wait'"
+        );
+        assert!(parse("expect\n").is_err());
+        assert!(parse("send \\q\n").is_err());
+    }
+
+    #[test]
+    fn test_script_follows_output() {
+        let steps = parse("send first\nexpect A:\nsend a\nexpect B:\nsend b\nexpect C:\n").unwrap();
+        let mut script = ExpectScript::new(steps, b"\r");
+
+        assert_eq!(script.feed(b"hello"), Detection::Reply(b"first\r".to_vec()));
+        assert_eq!(script.feed(b"..."), Detection::Nothing);
+
+        // Both prompts in one chunk, the second split from the third
+        assert_eq!(
+            script.feed(b"A: B: C"),
+            Detection::Reply(b"a\rb\r".to_vec())
+        );
+        assert_eq!(script.next, 5);
+        assert_eq!(script.feed(b":"), Detection::Nothing);
+        assert_eq!(script.next, 6);
+        assert_eq!(script.feed(b"A: B:"), Detection::Nothing);
+    }
+}
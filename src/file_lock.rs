@@ -0,0 +1,72 @@
+//! Locks on files shared between sshpass processes
+//!
+//! Several sshpass processes can append to the same `--audit-log` or update
+//! the same lockout record at once. A [`FileLock`] serializes them with
+//! flock(2) on Unix and LockFileEx on Windows.
+
+use std::fs::File;
+
+/// Exclusive lock on a whole file, released when dropped
+pub struct FileLock<'a>(&'a File);
+
+#[cfg(unix)]
+impl<'a> FileLock<'a> {
+    /// Wait for the lock
+    pub fn new(file: &'a File) -> std::io::Result<Self> {
+        use nix::fcntl::{flock, FlockArg};
+        use std::os::unix::io::AsRawFd;
+        flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+        Ok(Self(file))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        use nix::fcntl::{flock, FlockArg};
+        use std::os::unix::io::AsRawFd;
+        let _ = flock(self.0.as_raw_fd(), FlockArg::Unlock);
+    }
+}
+
+/// 以 LockFileEx 鎖定整個檔案
+#[cfg(windows)]
+impl<'a> FileLock<'a> {
+    pub fn new(file: &'a File) -> std::io::Result<Self> {
+        use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+        use windows::Win32::System::IO::OVERLAPPED;
+
+        let mut overlapped = OVERLAPPED::default();
+        unsafe {
+            LockFileEx(
+                handle(file),
+                LOCKFILE_EXCLUSIVE_LOCK,
+                0,
+                u32::MAX,
+                u32::MAX,
+                &mut overlapped,
+            )
+        }
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        Ok(Self(file))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        use windows::Win32::Storage::FileSystem::UnlockFileEx;
+        use windows::Win32::System::IO::OVERLAPPED;
+
+        let mut overlapped = OVERLAPPED::default();
+        unsafe {
+            let _ = UnlockFileEx(handle(self.0), 0, u32::MAX, u32::MAX, &mut overlapped);
+        }
+    }
+}
+
+#[cfg(windows)]
+fn handle(file: &File) -> windows::Win32::Foundation::HANDLE {
+    use std::os::windows::io::AsRawHandle;
+    windows::Win32::Foundation::HANDLE(file.as_raw_handle() as isize)
+}
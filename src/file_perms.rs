@@ -0,0 +1,241 @@
+//! Permission checks on password files (`-f`, `--strict-perms`)
+//!
+//! Like ssh with private keys, sshpass looks at who else can get at the file
+//! it reads the password from. On Unix that is a file others can read or
+//! write, or a directory others can write to, where the file can be swapped
+//! for another; on Windows it is a file whose DACL lets Everyone read it.
+//! Each is a warning, or with `--strict-perms` a reason to refuse the file.
+//! A file that cannot be looked at is left for the open to report.
+
+use crate::error::{Result, SshpassError};
+use crate::logging::{self, Tone};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A way others can get at a password file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exposure {
+    /// Everyone may read the file
+    Readable,
+    /// Everyone may write to the file
+    Writable,
+    /// Everyone may write to the directory the file is in
+    WritableDir(PathBuf),
+}
+
+impl fmt::Display for Exposure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exposure::Readable => f.write_str("it is readable by everyone"),
+            Exposure::Writable => f.write_str("it is writable by everyone"),
+            Exposure::WritableDir(dir) => {
+                write!(f, "its directory {} is writable by everyone", dir.display())
+            }
+        }
+    }
+}
+
+/// Check `path`, warning about what others can do with it, or failing with
+/// `strict`
+pub fn enforce(path: &Path, strict: bool) -> Result<()> {
+    let exposures = check(path);
+    if exposures.is_empty() {
+        return Ok(());
+    }
+    let reasons = exposures
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if strict {
+        return Err(SshpassError::PasswordFileError(format!(
+            "{}: {} (refused with --strict-perms; {})",
+            path.display(),
+            reasons,
+            FIX
+        )));
+    }
+    logging::emit(
+        Tone::Warning,
+        &format!(
+            "Warning: password file {}: {} ({})",
+            path.display(),
+            reasons,
+            FIX
+        ),
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+const FIX: &str = "chmod 600 the file and keep it in a directory only you can write to";
+#[cfg(windows)]
+const FIX: &str = "remove Everyone from the file's permissions";
+
+/// The ways others can get at the file at `path`
+#[cfg(unix)]
+pub fn check(path: &Path) -> Vec<Exposure> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut exposures = Vec::new();
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return exposures;
+    };
+    exposures.extend(file_exposures(metadata.permissions().mode()));
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir_metadata) = std::fs::metadata(dir) {
+        if dir_metadata.permissions().mode() & 0o002 != 0 {
+            exposures.push(Exposure::WritableDir(dir.to_path_buf()));
+        }
+    }
+    exposures
+}
+
+/// What the permission bits `mode` of a file let others do
+#[cfg(unix)]
+fn file_exposures(mode: u32) -> impl Iterator<Item = Exposure> {
+    [
+        (mode & 0o004 != 0).then_some(Exposure::Readable),
+        (mode & 0o002 != 0).then_some(Exposure::Writable),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// 其他人能如何取得 `path` 這個檔案
+///
+/// 檢查檔案的 DACL 是否讓 Everyone 讀取；沒有 DACL（null DACL）表示任何人都
+/// 有完整權限。
+#[cfg(windows)]
+pub fn check(path: &Path) -> Vec<Exposure> {
+    match everyone_can_read(path) {
+        Some(true) => vec![Exposure::Readable],
+        _ => Vec::new(),
+    }
+}
+
+/// Everyone 對 `path` 的有效權限是否含讀取；查不到時為 None
+#[cfg(windows)]
+fn everyone_can_read(path: &Path) -> Option<bool> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{LocalFree, ERROR_SUCCESS, HLOCAL};
+    use windows::Win32::Security::Authorization::{GetNamedSecurityInfoW, SE_FILE_OBJECT};
+    use windows::Win32::Security::{ACL, DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR};
+    use windows::Win32::Storage::FileSystem::FILE_READ_DATA;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut dacl: *mut ACL = std::ptr::null_mut();
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            PCWSTR(wide.as_ptr()),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            None,
+            None,
+            Some(std::ptr::addr_of_mut!(dacl)),
+            None,
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return None;
+    }
+
+    let readable = if dacl.is_null() {
+        Some(true)
+    } else {
+        everyone_rights(dacl).map(|rights| rights & FILE_READ_DATA.0 != 0)
+    };
+    // dacl 指向安全描述元之內，兩者一起以 LocalFree 釋放
+    unsafe {
+        let _ = LocalFree(HLOCAL(descriptor.0 as isize));
+    }
+    readable
+}
+
+/// Everyone 依 `dacl` 得到的有效權限
+#[cfg(windows)]
+fn everyone_rights(dacl: *const windows::Win32::Security::ACL) -> Option<u32> {
+    use windows::core::PWSTR;
+    use windows::Win32::Foundation::ERROR_SUCCESS;
+    use windows::Win32::Security::Authorization::{
+        GetEffectiveRightsFromAclW, NO_MULTIPLE_TRUSTEE, TRUSTEE_IS_SID,
+        TRUSTEE_IS_WELL_KNOWN_GROUP, TRUSTEE_W,
+    };
+    use windows::Win32::Security::{CreateWellKnownSid, WinWorldSid, PSID, SECURITY_MAX_SID_SIZE};
+
+    let mut sid = [0u8; SECURITY_MAX_SID_SIZE as usize];
+    let mut sid_size = sid.len() as u32;
+    let everyone = PSID(sid.as_mut_ptr().cast());
+    unsafe { CreateWellKnownSid(WinWorldSid, PSID::default(), everyone, &mut sid_size) }.ok()?;
+
+    let trustee = TRUSTEE_W {
+        pMultipleTrustee: std::ptr::null_mut(),
+        MultipleTrusteeOperation: NO_MULTIPLE_TRUSTEE,
+        TrusteeForm: TRUSTEE_IS_SID,
+        TrusteeType: TRUSTEE_IS_WELL_KNOWN_GROUP,
+        ptstrName: PWSTR(everyone.0.cast()),
+    };
+    let mut rights = 0u32;
+    let status = unsafe { GetEffectiveRightsFromAclW(dacl, &trustee, &mut rights) };
+    (status == ERROR_SUCCESS).then_some(rights)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_file_exposures() {
+        assert_eq!(file_exposures(0o600).count(), 0);
+        assert_eq!(file_exposures(0o640).count(), 0);
+        assert_eq!(
+            file_exposures(0o644).collect::<Vec<_>>(),
+            [Exposure::Readable]
+        );
+        assert_eq!(
+            file_exposures(0o666).collect::<Vec<_>>(),
+            [Exposure::Readable, Exposure::Writable]
+        );
+    }
+
+    #[test]
+    fn test_enforce() {
+        let dir = std::env::temp_dir().join(format!("sshpass-perms-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let file = dir.join("password");
+        std::fs::write(&file, "secret\n").unwrap();
+        let chmod = |path: &Path, mode| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+
+        chmod(&file, 0o600);
+        assert_eq!(check(&file), []);
+        assert!(enforce(&file, true).is_ok());
+
+        chmod(&file, 0o644);
+        assert_eq!(check(&file), [Exposure::Readable]);
+        assert!(enforce(&file, false).is_ok());
+        let error = enforce(&file, true).unwrap_err();
+        assert!(matches!(error, SshpassError::PasswordFileError(_)));
+        assert!(error.to_string().contains("readable by everyone"));
+
+        chmod(&file, 0o600);
+        chmod(&dir, 0o777);
+        assert_eq!(check(&file), [Exposure::WritableDir(dir.clone())]);
+
+        // Left for the open to report
+        assert_eq!(check(&dir.join("missing")), []);
+
+        chmod(&dir, 0o700);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
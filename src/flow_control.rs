@@ -0,0 +1,123 @@
+//! XON/XOFF 流量控制
+//!
+//! 透過 ssh 操作序列埠 console gateway 時，遠端可能送出 XOFF（Ctrl+S）要求
+//! 暫停輸入、XON（Ctrl+Q）恢復。預設照常轉發這些位元組；設定為 `honor` 時，
+//! 由 sshpass 攔截它們，並在 XOFF 期間暫存要轉發到 PTY 的 stdin 輸入。
+
+/// XON（Ctrl+Q）
+pub const XON: u8 = 0x11;
+/// XOFF（Ctrl+S）
+pub const XOFF: u8 = 0x13;
+
+/// `--flow-control` 的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FlowControlMode {
+    /// 不處理，XON/XOFF 當成一般輸出
+    #[default]
+    Ignore,
+    /// 遵從遠端的 XON/XOFF，暫停／恢復 stdin 轉發
+    Honor,
+}
+
+/// 追蹤遠端的 XON/XOFF 狀態
+#[derive(Debug, Default)]
+pub struct FlowControl {
+    mode: FlowControlMode,
+    paused: bool,
+    /// XOFF 期間暫存的輸入
+    held: Vec<u8>,
+}
+
+impl FlowControl {
+    /// 建立追蹤器
+    pub fn new(mode: FlowControlMode) -> Self {
+        Self {
+            mode,
+            paused: false,
+            held: Vec::new(),
+        }
+    }
+
+    /// 遠端目前是否要求暫停
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// 處理 PTY 輸出：honor 模式下移除 XON/XOFF 並更新狀態
+    ///
+    /// 回傳應顯示給使用者的資料
+    pub fn filter_output(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.mode == FlowControlMode::Ignore {
+            return data.to_vec();
+        }
+
+        let mut output = Vec::with_capacity(data.len());
+        for &byte in data {
+            match byte {
+                XOFF => self.paused = true,
+                XON => self.paused = false,
+                _ => output.push(byte),
+            }
+        }
+        output
+    }
+
+    /// 送往 PTY 前的輸入閘門：暫停時暫存並回傳 `None`
+    pub fn gate_input(&mut self, data: Vec<u8>) -> Option<Vec<u8>> {
+        if self.paused {
+            self.held.extend_from_slice(&data);
+            None
+        } else if self.held.is_empty() {
+            Some(data)
+        } else {
+            let mut released = std::mem::take(&mut self.held);
+            released.extend_from_slice(&data);
+            Some(released)
+        }
+    }
+
+    /// 恢復後取出暫存的輸入
+    pub fn take_released(&mut self) -> Option<Vec<u8>> {
+        if self.paused || self.held.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.held))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_passes_through() {
+        let mut flow = FlowControl::new(FlowControlMode::Ignore);
+        assert_eq!(flow.filter_output(b"a\x13b\x11c"), b"a\x13b\x11c");
+        assert!(!flow.is_paused());
+        assert_eq!(flow.gate_input(b"ls\r".to_vec()), Some(b"ls\r".to_vec()));
+    }
+
+    #[test]
+    fn test_honor_strips_and_pauses() {
+        let mut flow = FlowControl::new(FlowControlMode::Honor);
+        assert_eq!(flow.filter_output(b"busy\x13"), b"busy");
+        assert!(flow.is_paused());
+        assert_eq!(flow.filter_output(b"\x11ready"), b"ready");
+        assert!(!flow.is_paused());
+    }
+
+    #[test]
+    fn test_input_held_while_paused() {
+        let mut flow = FlowControl::new(FlowControlMode::Honor);
+        flow.filter_output(&[XOFF]);
+        assert_eq!(flow.gate_input(b"abc".to_vec()), None);
+        assert_eq!(flow.gate_input(b"def".to_vec()), None);
+        assert_eq!(flow.take_released(), None);
+
+        flow.filter_output(&[XON]);
+        assert_eq!(flow.take_released(), Some(b"abcdef".to_vec()));
+        assert_eq!(flow.take_released(), None);
+    }
+}
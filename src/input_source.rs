@@ -0,0 +1,355 @@
+//! Where forwarded input comes from (`--input`)
+//!
+//! The event loop types what an [`InputSource`] gives it into the child's
+//! terminal: the keyboard unless told otherwise. The binary picks the
+//! source with `--input`:
+//!
+//! | Source | Input |
+//! |--------|-------|
+//! | `tty` | the terminal on stdin, with the escape character; the default |
+//! | `pipe` | stdin as it is, even a pipe or file, through the PTY |
+//! | `none` | nothing; the child's terminal only gets what sshpass types |
+//! | `fd:N` | a descriptor the caller left open (Unix) |
+//! | `unix:PATH` | a connection to a Unix socket (Unix) |
+//! | `tcp:HOST:PORT` | a TCP connection (Unix) |
+//!
+//! Library callers pass their own, such as [`Scripted`] input for tests.
+//! While the remote side has sent XOFF under `--flow-control honor`, the
+//! loop pauses the source and stops reading it, so a writer on a pipe or
+//! socket is held back by the kernel instead of sshpass buffering for it.
+//! Input typed through a [`SessionHandle`](crate::session::SessionHandle)
+//! is held back the same way.
+
+use crate::error::{Result, SshpassError};
+use crate::stdin_forwarder::StdinEvent;
+use std::collections::VecDeque;
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+
+/// Gives the event loop input to type into the child's terminal
+pub trait InputSource: Send {
+    /// The descriptor to wait on, None while there is nothing to wait for
+    #[cfg(unix)]
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Whether input can be read without waiting
+    fn ready(&self) -> bool {
+        false
+    }
+
+    /// The input there is now, without blocking; after [`StdinEvent::Eof`]
+    /// the source is not read again
+    fn read(&mut self, verbose: bool) -> Vec<StdinEvent>;
+
+    /// Stop giving input until [`resume`](Self::resume)
+    fn pause(&mut self);
+
+    fn resume(&mut self);
+
+    /// Give the terminal back while sshpass is stopped (`~^Z`)
+    fn suspend(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl InputSource for crate::stdin_forwarder::StdinReader {
+    fn fd(&self) -> Option<RawFd> {
+        self.fd()
+    }
+
+    fn read(&mut self, verbose: bool) -> Vec<StdinEvent> {
+        self.read(verbose)
+    }
+
+    fn pause(&mut self) {
+        self.set_paused(true);
+    }
+
+    fn resume(&mut self) {
+        self.set_paused(false);
+    }
+
+    fn suspend(&self) -> std::io::Result<()> {
+        self.suspend()
+    }
+}
+
+#[cfg(windows)]
+impl InputSource for crate::stdin_forwarder::StdinForwarder {
+    fn read(&mut self, _verbose: bool) -> Vec<StdinEvent> {
+        self.try_recv().into_iter().collect()
+    }
+
+    fn pause(&mut self) {
+        self.set_paused(true);
+    }
+
+    fn resume(&mut self) {
+        self.set_paused(false);
+    }
+}
+
+/// No input
+#[derive(Debug, Default)]
+pub struct Null;
+
+impl InputSource for Null {
+    fn read(&mut self, _verbose: bool) -> Vec<StdinEvent> {
+        Vec::new()
+    }
+
+    fn pause(&mut self) {}
+
+    fn resume(&mut self) {}
+}
+
+/// Input given up front, typed a chunk per read
+///
+/// Like keystrokes on Unix, it is typed from the start, before any password
+/// prompt; input meant for after the login is better sent through a
+/// [`SessionHandle`](crate::session::SessionHandle) once the session is
+/// [`Authenticated`](crate::session::SessionStatus::Authenticated).
+#[derive(Debug, Default)]
+pub struct Scripted {
+    chunks: VecDeque<Vec<u8>>,
+    eof: bool,
+    paused: bool,
+}
+
+impl Scripted {
+    pub fn new<I>(chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>>,
+    {
+        Self {
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            eof: false,
+            paused: false,
+        }
+    }
+
+    /// End with EOF once every chunk is typed, as a closed stdin would
+    pub fn then_eof(mut self) -> Self {
+        self.eof = true;
+        self
+    }
+}
+
+impl InputSource for Scripted {
+    fn ready(&self) -> bool {
+        !self.paused && (!self.chunks.is_empty() || self.eof)
+    }
+
+    fn read(&mut self, _verbose: bool) -> Vec<StdinEvent> {
+        if self.paused {
+            return Vec::new();
+        }
+        match self.chunks.pop_front() {
+            Some(chunk) => vec![StdinEvent::Data(chunk)],
+            None if std::mem::take(&mut self.eof) => vec![StdinEvent::Eof],
+            None => Vec::new(),
+        }
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+    }
+}
+
+/// A pipe, file or socket, read as it is
+#[cfg(unix)]
+pub struct Stream<R> {
+    reader: R,
+    open: bool,
+    paused: bool,
+}
+
+#[cfg(unix)]
+impl<R: std::io::Read + AsRawFd + Send> Stream<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            open: true,
+            paused: false,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl<R: std::io::Read + AsRawFd + Send> InputSource for Stream<R> {
+    fn fd(&self) -> Option<RawFd> {
+        (self.open && !self.paused).then(|| self.reader.as_raw_fd())
+    }
+
+    fn read(&mut self, verbose: bool) -> Vec<StdinEvent> {
+        use std::io::ErrorKind;
+
+        if !self.open || self.paused {
+            return Vec::new();
+        }
+        let mut buffer = [0u8; 1024];
+        match self.reader.read(&mut buffer) {
+            Ok(n @ 1..) => vec![StdinEvent::Data(buffer[..n].to_vec())],
+            Err(e) if matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) => {
+                Vec::new()
+            }
+            result => {
+                if let Err(e) = result {
+                    if verbose {
+                        crate::diag!("[DEBUG] Input read error: {}", e);
+                    }
+                }
+                self.open = false;
+                vec![StdinEvent::Eof]
+            }
+        }
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+    }
+}
+
+/// Check an `--input` source
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_input(spec: &str) -> std::result::Result<String, String> {
+    let known = match spec {
+        "tty" | "pipe" | "none" => true,
+        #[cfg(unix)]
+        _ => ["fd:", "unix:", "tcp:"].iter().any(|prefix| {
+            spec.strip_prefix(prefix)
+                .is_some_and(|rest| !rest.is_empty())
+        }),
+        #[cfg(windows)]
+        _ => false,
+    };
+    if known {
+        Ok(spec.to_string())
+    } else if cfg!(unix) {
+        Err(format!(
+            "expected tty, pipe, none, fd:N, unix:PATH or tcp:HOST:PORT, got '{}'",
+            spec
+        ))
+    } else {
+        Err(format!("expected tty, pipe or none, got '{}'", spec))
+    }
+}
+
+/// Open the source an `--input` value names, other than the terminal
+///
+/// `tty` needs the forwarder settings, so the caller builds it, and on
+/// Windows `pipe` is read by the same forwarder; both give None here.
+pub fn open(spec: &str) -> Result<Option<Box<dyn InputSource>>> {
+    match spec {
+        "none" => return Ok(Some(Box::new(Null))),
+        "tty" => return Ok(None),
+        #[cfg(unix)]
+        "pipe" => return Ok(Some(Box::new(Stream::new(std::io::stdin())))),
+        #[cfg(windows)]
+        "pipe" => return Ok(None),
+        _ => {}
+    }
+
+    #[cfg(unix)]
+    {
+        let failed = |e: std::io::Error| {
+            SshpassError::InvalidArguments(format!("Failed to open --input {}: {}", spec, e))
+        };
+        if let Some(fd) = spec.strip_prefix("fd:") {
+            let file = crate::tee::take_fd(fd, spec, "--input")?;
+            return Ok(Some(Box::new(Stream::new(file))));
+        }
+        if let Some(path) = spec.strip_prefix("unix:") {
+            let socket = std::os::unix::net::UnixStream::connect(path).map_err(failed)?;
+            return Ok(Some(Box::new(Stream::new(socket))));
+        }
+        if let Some(address) = spec.strip_prefix("tcp:") {
+            let socket = std::net::TcpStream::connect(address).map_err(failed)?;
+            return Ok(Some(Box::new(Stream::new(socket))));
+        }
+    }
+    Err(SshpassError::InvalidArguments(format!(
+        "Unknown --input source '{}'",
+        spec
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(events: Vec<StdinEvent>) -> Vec<u8> {
+        match events.as_slice() {
+            [StdinEvent::Data(data)] => data.clone(),
+            other => panic!("expected data, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_scripted() {
+        let mut input = Scripted::new(["ls\n", "exit\n"]).then_eof();
+        assert!(input.ready());
+        assert_eq!(data(input.read(false)), b"ls\n");
+
+        input.pause();
+        assert!(!input.ready());
+        assert!(input.read(false).is_empty());
+        input.resume();
+
+        assert_eq!(data(input.read(false)), b"exit\n");
+        assert!(matches!(input.read(false).as_slice(), [StdinEvent::Eof]));
+        assert!(!input.ready());
+        assert!(input.read(false).is_empty());
+    }
+
+    #[test]
+    fn test_parse_input() {
+        for spec in ["tty", "pipe", "none"] {
+            assert_eq!(parse_input(spec).as_deref(), Ok(spec));
+        }
+        assert!(parse_input("keyboard").is_err());
+        assert!(parse_input("fd:").is_err());
+        #[cfg(unix)]
+        {
+            assert!(parse_input("fd:3").is_ok());
+            assert!(parse_input("unix:/run/input.sock").is_ok());
+            assert!(parse_input("tcp:localhost:9000").is_ok());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stream_pause_and_eof() {
+        use std::io::Write;
+
+        let (reader, mut writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut input = Stream::new(reader);
+        assert!(input.fd().is_some());
+
+        input.pause();
+        assert_eq!(input.fd(), None);
+        writer.write_all(b"held").unwrap();
+        assert!(input.read(false).is_empty());
+
+        // What was written while paused waits in the socket
+        input.resume();
+        assert_eq!(data(input.read(false)), b"held");
+
+        drop(writer);
+        assert!(matches!(input.read(false).as_slice(), [StdinEvent::Eof]));
+        assert_eq!(input.fd(), None);
+    }
+}
@@ -0,0 +1,438 @@
+//! Host inventories with groups and per-group settings
+//!
+//! One command often goes to a fleet of mixed devices: routers that need the
+//! `cisco` prompt profile and their own account, Linux hosts that are fine
+//! with the defaults. An inventory lists them in an INI subset:
+//!
+//! ```text
+//! # core network
+//! [routers]
+//! r1.example.com
+//! r2.example.com port=2222
+//!
+//! [routers:vars]
+//! profile = cisco
+//! user = netops
+//! password = env:ROUTER_PASSWORD
+//!
+//! [linux]
+//! web1 user=deploy
+//! web2
+//! ```
+//!
+//! A `[GROUP]` section lists hosts, one per line, each optionally followed
+//! by `KEY=VALUE` settings; `[GROUP:vars]` gives settings to all hosts of
+//! the group. Hosts above the first section belong to `ungrouped`. The
+//! settings are `user`, `port`, `profile` (a prompt profile, see
+//! [`crate::profiles`]) and `password`, a reference to where the password
+//! is kept (`env:NAME`, `file:PATH`, `fd:N` or `credential:NAME`), never the
+//! password itself. `var.NAME` sets a variable of its own for the command
+//! template of a batch (see [`crate::batch::template`]). A host's own
+//! settings win over those of its group.
+//! Lines starting with `#` or `;` are comments.
+//!
+//! A `jump` setting names the bastion a host is reached through, as
+//! `[USER@]HOST[:PORT]`. When the bastion is itself listed in the inventory,
+//! its settings apply to that hop, including its own password and its own
+//! `jump`, so bastions can be chained.
+//!
+//! [`Inventory::targets`] resolves a group, a host or `all` to the hosts to
+//! run against with their settings.
+
+use crate::error::{Result, SshpassError};
+use crate::password::PasswordSource;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Settings of a host or group; unset ones are left to the next level
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Name of the prompt profile
+    pub profile: Option<String>,
+    /// Where the password is kept, as for `--handle-expiry`
+    pub password: Option<String>,
+    /// Bastion to connect through, `[USER@]HOST[:PORT]`
+    pub jump: Option<String>,
+    /// `var.NAME` settings, by NAME
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// Apply one `KEY=VALUE` setting
+    fn set(&mut self, key: &str, value: &str) -> std::result::Result<(), String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(format!("{} has no value", key));
+        }
+        match key.trim() {
+            "user" => self.user = Some(value.to_string()),
+            "port" => {
+                let port = value
+                    .parse()
+                    .ok()
+                    .filter(|&port| port > 0)
+                    .ok_or_else(|| format!("invalid port '{}'", value))?;
+                self.port = Some(port);
+            }
+            "profile" => self.profile = Some(value.to_string()),
+            "password" => {
+                PasswordSource::from_spec(value).map_err(|e| match e {
+                    SshpassError::InvalidArguments(message) => message,
+                    other => other.to_string(),
+                })?;
+                self.password = Some(value.to_string());
+            }
+            "jump" => {
+                parse_jump(value)?;
+                self.jump = Some(value.to_string());
+            }
+            key if key.starts_with("var.") => {
+                let name = &key["var.".len()..];
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format!("invalid variable name '{}'", name));
+                }
+                self.vars.insert(name.to_string(), value.to_string());
+            }
+            other => {
+                return Err(format!(
+                "unknown setting '{}' (expected user, port, profile, password, jump or var.NAME)",
+                other
+            ))
+            }
+        }
+        Ok(())
+    }
+
+    /// These settings, with `fallback` filling in the unset ones
+    fn or(&self, fallback: &Settings) -> Settings {
+        Settings {
+            user: self.user.clone().or_else(|| fallback.user.clone()),
+            port: self.port.or(fallback.port),
+            profile: self.profile.clone().or_else(|| fallback.profile.clone()),
+            password: self.password.clone().or_else(|| fallback.password.clone()),
+            jump: self.jump.clone().or_else(|| fallback.jump.clone()),
+            vars: fallback
+                .vars
+                .iter()
+                .chain(&self.vars)
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// A host as listed in a group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    pub name: String,
+    pub settings: Settings,
+}
+
+/// A `[GROUP]` section and its `[GROUP:vars]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub hosts: Vec<Host>,
+    pub settings: Settings,
+}
+
+/// A bastion on the way to a host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub host: String,
+    pub settings: Settings,
+}
+
+/// A host to run against, with the settings that apply to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub host: String,
+    /// The group the settings came from
+    pub group: String,
+    pub settings: Settings,
+    /// Bastions to go through, the one connected to first at the front
+    pub jumps: Vec<Jump>,
+}
+
+/// The groups of an inventory file, in the order they appear
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    pub groups: Vec<Group>,
+}
+
+/// Group of the hosts listed before the first section
+pub const UNGROUPED: &str = "ungrouped";
+
+impl Inventory {
+    /// Read an inventory file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            SshpassError::InvalidArguments(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        Self::parse(&text).map_err(|e| match e {
+            SshpassError::InvalidArguments(message) => {
+                SshpassError::InvalidArguments(format!("{}:{}", path.display(), message))
+            }
+            other => other,
+        })
+    }
+
+    /// Parse the text of an inventory
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut inventory = Inventory::default();
+        // The group the lines go to, and whether they are its settings
+        let mut section = (UNGROUPED.to_string(), false);
+        for (index, line) in text.lines().enumerate() {
+            let damaged = |reason: String| {
+                SshpassError::InvalidArguments(format!("{}: {}", index + 1, reason))
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| damaged(format!("unclosed section '{}'", line)))?;
+                let (name, vars) = match header.split_once(':') {
+                    Some((name, "vars")) => (name, true),
+                    Some(_) => return Err(damaged(format!("unknown section '{}'", line))),
+                    None => (header, false),
+                };
+                let name = name.trim();
+                if name.is_empty() || name.contains(char::is_whitespace) {
+                    return Err(damaged(format!("invalid group name '{}'", name)));
+                }
+                inventory.group_mut(name);
+                section = (name.to_string(), vars);
+                continue;
+            }
+
+            let (group, vars) = (&section.0, section.1);
+            if vars {
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| damaged(format!("expected KEY = VALUE, got '{}'", line)))?;
+                let group = inventory.group_mut(group);
+                group.settings.set(key, value).map_err(damaged)?;
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap_or_default().to_string();
+            let mut settings = Settings::default();
+            for word in words {
+                let (key, value) = word
+                    .split_once('=')
+                    .ok_or_else(|| damaged(format!("expected KEY=VALUE, got '{}'", word)))?;
+                settings.set(key, value).map_err(damaged)?;
+            }
+            inventory
+                .group_mut(group)
+                .hosts
+                .push(Host { name, settings });
+        }
+        Ok(inventory)
+    }
+
+    /// The group called `name`, added at the end if it is new
+    fn group_mut(&mut self, name: &str) -> &mut Group {
+        let index = match self.groups.iter().position(|group| group.name == name) {
+            Some(index) => index,
+            None => {
+                self.groups.push(Group {
+                    name: name.to_string(),
+                    hosts: Vec::new(),
+                    settings: Settings::default(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[index]
+    }
+
+    /// The hosts `selector` names: a group, a single host, or `all`; a host
+    /// listed in several groups is taken once, from the first
+    pub fn targets(&self, selector: &str) -> Result<Vec<Target>> {
+        let mut targets: Vec<Target> = Vec::new();
+        for group in &self.groups {
+            let whole = selector == "all" || selector == group.name;
+            for host in &group.hosts {
+                let wanted = whole || selector == host.name;
+                if wanted && !targets.iter().any(|target| target.host == host.name) {
+                    let settings = host.settings.or(&group.settings);
+                    targets.push(Target {
+                        host: host.name.clone(),
+                        group: group.name.clone(),
+                        jumps: self.jumps(&host.name, &settings)?,
+                        settings,
+                    });
+                }
+            }
+        }
+        if targets.is_empty() && !self.groups.iter().any(|group| group.name == selector) {
+            return Err(SshpassError::InvalidArguments(format!(
+                "No group or host '{}' in the inventory",
+                selector
+            )));
+        }
+        Ok(targets)
+    }
+
+    /// The settings of the first listing of a host
+    fn host_settings(&self, name: &str) -> Option<Settings> {
+        self.groups.iter().find_map(|group| {
+            let host = group.hosts.iter().find(|host| host.name == name)?;
+            Some(host.settings.or(&group.settings))
+        })
+    }
+
+    /// The chain of bastions in front of `host`, outermost first
+    fn jumps(&self, host: &str, settings: &Settings) -> Result<Vec<Jump>> {
+        let mut jumps = Vec::new();
+        let mut next = settings.jump.clone();
+        while let Some(spec) = next {
+            let (user, name, port) = parse_jump(&spec).map_err(SshpassError::InvalidArguments)?;
+            if name == host || jumps.iter().any(|jump: &Jump| jump.host == name) {
+                return Err(SshpassError::InvalidArguments(format!(
+                    "The jump hosts of {} loop back to {}",
+                    host, name
+                )));
+            }
+            let listed = self.host_settings(&name).unwrap_or_default();
+            let settings = Settings {
+                user: user.or(listed.user),
+                port: port.or(listed.port),
+                ..listed
+            };
+            next = settings.jump.clone();
+            jumps.insert(
+                0,
+                Jump {
+                    host: name,
+                    settings,
+                },
+            );
+        }
+        Ok(jumps)
+    }
+}
+
+/// Split `[USER@]HOST[:PORT]`, with an IPv6 address in brackets
+fn parse_jump(spec: &str) -> std::result::Result<(Option<String>, String, Option<u16>), String> {
+    let invalid = || format!("invalid jump host '{}': expected [USER@]HOST[:PORT]", spec);
+    let (user, rest) = match spec.rsplit_once('@') {
+        Some((user, rest)) if !user.is_empty() => (Some(user.to_string()), rest),
+        Some(_) => return Err(invalid()),
+        None => (None, spec),
+    };
+    let (host, port) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']').ok_or_else(invalid)?;
+            match after {
+                "" => (host, None),
+                _ => (host, Some(after.strip_prefix(':').ok_or_else(invalid)?)),
+            }
+        }
+        None => match rest.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        },
+    };
+    let port = match port {
+        Some(port) => Some(
+            port.parse::<u16>()
+                .ok()
+                .filter(|&port| port > 0)
+                .ok_or_else(invalid)?,
+        ),
+        None => None,
+    };
+    // A comma would split the -J list
+    if host.is_empty() || host.contains([',', '/']) {
+        return Err(invalid());
+    }
+    Ok((user, host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVENTORY: &str = "\
+web0
+
+# core network
+[routers]
+r1.example.com
+r2.example.com port=2222 user=admin
+
+[routers:vars]
+profile = cisco
+user = netops
+password = env:ROUTER_PASSWORD
+
+[linux]
+web1 user=deploy
+r1.example.com
+";
+
+    #[test]
+    fn test_parse_inventory() {
+        let inventory = Inventory::parse(INVENTORY).unwrap();
+        let names = inventory
+            .groups
+            .iter()
+            .map(|group| group.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [UNGROUPED, "routers", "linux"]);
+        let routers = &inventory.groups[1];
+        assert_eq!(routers.hosts.len(), 2);
+        assert_eq!(routers.hosts[1].settings.port, Some(2222));
+        assert_eq!(routers.settings.profile.as_deref(), Some("cisco"));
+        assert_eq!(
+            routers.settings.password.as_deref(),
+            Some("env:ROUTER_PASSWORD")
+        );
+    }
+
+    #[test]
+    fn test_inventory_targets() {
+        let inventory = Inventory::parse(INVENTORY).unwrap();
+        let routers = inventory.targets("routers").unwrap();
+        assert_eq!(routers[0].settings.user.as_deref(), Some("netops"));
+        // The host's own settings win over the group's
+        assert_eq!(routers[1].settings.user.as_deref(), Some("admin"));
+        assert_eq!(routers[1].settings.profile.as_deref(), Some("cisco"));
+
+        let linux = inventory.targets("linux").unwrap();
+        assert_eq!(linux[0].settings.profile, None);
+        assert_eq!(linux[1].group, "linux");
+
+        let all = inventory.targets("all").unwrap();
+        let hosts = all
+            .iter()
+            .map(|target| target.host.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(hosts, ["web0", "r1.example.com", "r2.example.com", "web1"]);
+        assert_eq!(all[1].group, "routers");
+
+        assert_eq!(inventory.targets("web1").unwrap().len(), 1);
+        assert!(inventory.targets("switches").is_err());
+    }
+
+    #[test]
+    fn test_inventory_errors() {
+        let error = |text: &str| Inventory::parse(text).unwrap_err().to_string();
+        assert!(error("[routers]\nr1 colour=blue").contains("2: unknown setting 'colour'"));
+        assert!(error("[routers:vars]\npassword = hunter2").contains("2: Invalid password source"));
+        assert!(error("[routers]\nr1 port=0").contains("invalid port"));
+        assert!(error("[routers:children]").contains("unknown section"));
+        assert!(error("[routers").contains("unclosed section"));
+        assert!(error("[a:vars]\nuser").contains("expected KEY = VALUE"));
+        assert!(error("[a]\nweb1 var.my-role=x").contains("invalid variable name 'my-role'"));
+    }
+}
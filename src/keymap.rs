@@ -0,0 +1,361 @@
+//! 按鍵對應表 - 將 Windows console 按鍵事件轉成 VT 跳脫序列
+//!
+//! ReadConsoleInputW 回傳的是 virtual-key code，方向鍵、功能鍵等沒有對應字元，
+//! 需要轉成遠端程式（vim、less 等）認得的 xterm 序列才能轉發到 PTY。
+
+/// Virtual-key codes（僅列出需要轉換的按鍵）
+/// 參考：https://learn.microsoft.com/windows/win32/inputdev/virtual-key-codes
+pub mod vk {
+    pub const RETURN: u16 = 0x0D;
+    pub const SPACE: u16 = 0x20;
+    pub const PRIOR: u16 = 0x21;
+    pub const NEXT: u16 = 0x22;
+    pub const END: u16 = 0x23;
+    pub const HOME: u16 = 0x24;
+    pub const LEFT: u16 = 0x25;
+    pub const UP: u16 = 0x26;
+    pub const RIGHT: u16 = 0x27;
+    pub const DOWN: u16 = 0x28;
+    pub const INSERT: u16 = 0x2D;
+    pub const DELETE: u16 = 0x2E;
+    pub const F1: u16 = 0x70;
+    pub const F12: u16 = 0x7B;
+    pub const F24: u16 = 0x87;
+    pub const OEM_2: u16 = 0xBF;
+    pub const OEM_MINUS: u16 = 0xBD;
+    pub const OEM_4: u16 = 0xDB;
+    pub const OEM_5: u16 = 0xDC;
+    pub const OEM_6: u16 = 0xDD;
+}
+
+/// KEY_EVENT_RECORD.dwControlKeyState 的修飾鍵旗標
+pub mod modifier {
+    pub const RIGHT_ALT_PRESSED: u32 = 0x0001;
+    pub const LEFT_ALT_PRESSED: u32 = 0x0002;
+    pub const RIGHT_CTRL_PRESSED: u32 = 0x0004;
+    pub const LEFT_CTRL_PRESSED: u32 = 0x0008;
+    pub const SHIFT_PRESSED: u32 = 0x0010;
+}
+
+/// 按鍵事件轉換結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    /// 送出這些位元組
+    Send(Vec<u8>),
+    /// 一般字元按鍵，由呼叫端依 uChar 處理
+    Char,
+    /// 無法對應的按鍵，直接忽略
+    Ignore,
+}
+
+/// Enter 鍵送出的換行字元
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum EnterMode {
+    /// Carriage return (\r)
+    Cr,
+    /// Line feed (\n)
+    #[default]
+    Lf,
+    /// Carriage return + line feed (\r\n)
+    Crlf,
+}
+
+impl EnterMode {
+    /// 取得要送出的位元組
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            EnterMode::Cr => b"\r",
+            EnterMode::Lf => b"\n",
+            EnterMode::Crlf => b"\r\n",
+        }
+    }
+}
+
+impl std::str::FromStr for EnterMode {
+    type Err = String;
+
+    /// 解析 `cr`、`lf` 或 `crlf`（不分大小寫）
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "cr" => Ok(EnterMode::Cr),
+            "lf" => Ok(EnterMode::Lf),
+            "crlf" => Ok(EnterMode::Crlf),
+            _ => Err(format!(
+                "invalid newline '{}': expected cr, lf or crlf",
+                value
+            )),
+        }
+    }
+}
+
+/// 依 xterm 慣例計算修飾鍵參數（1 + shift + alt*2 + ctrl*4），沒有修飾鍵時回傳 None
+fn modifier_param(control_state: u32) -> Option<u8> {
+    let mut param = 0u8;
+    if control_state & modifier::SHIFT_PRESSED != 0 {
+        param += 1;
+    }
+    if is_alt(control_state) {
+        param += 2;
+    }
+    if control_state & (modifier::LEFT_CTRL_PRESSED | modifier::RIGHT_CTRL_PRESSED) != 0 {
+        param += 4;
+    }
+    if param == 0 {
+        None
+    } else {
+        Some(param + 1)
+    }
+}
+
+/// 是否按下 Alt
+pub fn is_alt(control_state: u32) -> bool {
+    control_state & (modifier::LEFT_ALT_PRESSED | modifier::RIGHT_ALT_PRESSED) != 0
+}
+
+/// CSI 游標鍵：ESC [ X 或 ESC [ 1 ; m X
+fn cursor_key(final_byte: u8, control_state: u32) -> Vec<u8> {
+    match modifier_param(control_state) {
+        Some(m) => format!("\x1b[1;{}{}", m, final_byte as char).into_bytes(),
+        None => vec![0x1b, b'[', final_byte],
+    }
+}
+
+/// CSI 波浪號序列：ESC [ n ~ 或 ESC [ n ; m ~
+fn tilde_key(code: u8, control_state: u32) -> Vec<u8> {
+    match modifier_param(control_state) {
+        Some(m) => format!("\x1b[{};{}~", code, m).into_bytes(),
+        None => format!("\x1b[{}~", code).into_bytes(),
+    }
+}
+
+/// F1-F4 使用 SS3：ESC O P，帶修飾鍵時改用 ESC [ 1 ; m P
+fn ss3_key(final_byte: u8, control_state: u32) -> Vec<u8> {
+    match modifier_param(control_state) {
+        Some(m) => format!("\x1b[1;{}{}", m, final_byte as char).into_bytes(),
+        None => vec![0x1b, b'O', final_byte],
+    }
+}
+
+/// 是否按下 Ctrl（AltGr 除外）
+fn is_ctrl(control_state: u32) -> bool {
+    control_state & (modifier::LEFT_CTRL_PRESSED | modifier::RIGHT_CTRL_PRESSED) != 0
+        && !is_altgr(control_state)
+}
+
+/// Ctrl 組合鍵對應的控制字元
+///
+/// 不依賴 uChar：部分鍵盤配置下 Ctrl+Space、Ctrl+[ 等組合的 uChar 為 0 或一般字元。
+fn control_byte(vk_code: u16) -> Option<u8> {
+    match vk_code {
+        vk::SPACE | 0x32 => Some(0x00), // Ctrl+Space, Ctrl+2 -> NUL
+        0x41..=0x5A => Some((vk_code - 0x40) as u8), // Ctrl+A..Z -> 0x01..0x1A
+        vk::OEM_4 => Some(0x1b),        // Ctrl+[ -> ESC
+        vk::OEM_5 => Some(0x1c),        // Ctrl+\
+        vk::OEM_6 => Some(0x1d),        // Ctrl+]
+        0x36 => Some(0x1e),             // Ctrl+6 -> Ctrl+^
+        vk::OEM_MINUS | vk::OEM_2 => Some(0x1f), // Ctrl+- , Ctrl+/ -> Ctrl+_
+        _ => None,
+    }
+}
+
+/// 將按鍵事件轉成要送往 PTY 的動作
+///
+/// # Arguments
+/// * `vk_code` - wVirtualKeyCode
+/// * `control_state` - dwControlKeyState
+pub fn translate_key(vk_code: u16, control_state: u32) -> KeyAction {
+    let bytes = match vk_code {
+        vk::UP => cursor_key(b'A', control_state),
+        vk::DOWN => cursor_key(b'B', control_state),
+        vk::RIGHT => cursor_key(b'C', control_state),
+        vk::LEFT => cursor_key(b'D', control_state),
+        vk::HOME => cursor_key(b'H', control_state),
+        vk::END => cursor_key(b'F', control_state),
+        vk::INSERT => tilde_key(2, control_state),
+        vk::DELETE => tilde_key(3, control_state),
+        vk::PRIOR => tilde_key(5, control_state),
+        vk::NEXT => tilde_key(6, control_state),
+        code @ vk::F1..=vk::F12 => {
+            let index = (code - vk::F1) as usize;
+            if index < 4 {
+                ss3_key(b"PQRS"[index], control_state)
+            } else {
+                const TILDE_CODES: [u8; 8] = [15, 17, 18, 19, 20, 21, 23, 24];
+                tilde_key(TILDE_CODES[index - 4], control_state)
+            }
+        }
+        // F13-F24 與其餘導覽區按鍵（Select、Print、Help 等）沒有通用的 VT 序列
+        0x21..=0x2F | 0x7C..=vk::F24 => return KeyAction::Ignore,
+        code if is_ctrl(control_state) => match control_byte(code) {
+            Some(byte) => {
+                let mut bytes = Vec::with_capacity(2);
+                if is_alt(control_state) {
+                    bytes.push(0x1b);
+                }
+                bytes.push(byte);
+                bytes
+            }
+            None => return KeyAction::Char,
+        },
+        _ => return KeyAction::Char,
+    };
+
+    KeyAction::Send(bytes)
+}
+
+/// 是否為 AltGr（Windows 以 RightAlt + LeftCtrl 表示），此時字元已由鍵盤配置產生
+fn is_altgr(control_state: u32) -> bool {
+    control_state & modifier::RIGHT_ALT_PRESSED != 0
+        && control_state & modifier::LEFT_CTRL_PRESSED != 0
+}
+
+/// 將字元按鍵編成位元組；按住 Alt 時依 xterm metaSendsEscape 慣例加上 ESC 前綴
+pub fn encode_char(ch: char, control_state: u32) -> Vec<u8> {
+    let mut buf = [0u8; 4];
+    let encoded = ch.encode_utf8(&mut buf).as_bytes();
+
+    let mut bytes = Vec::with_capacity(encoded.len() + 1);
+    if is_alt(control_state) && !is_altgr(control_state) {
+        bytes.push(0x1b);
+    }
+    bytes.extend_from_slice(encoded);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arrow_keys() {
+        assert_eq!(
+            translate_key(vk::UP, 0),
+            KeyAction::Send(b"\x1b[A".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::DOWN, 0),
+            KeyAction::Send(b"\x1b[B".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::RIGHT, 0),
+            KeyAction::Send(b"\x1b[C".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::LEFT, 0),
+            KeyAction::Send(b"\x1b[D".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_navigation_keys() {
+        assert_eq!(
+            translate_key(vk::HOME, 0),
+            KeyAction::Send(b"\x1b[H".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::END, 0),
+            KeyAction::Send(b"\x1b[F".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::PRIOR, 0),
+            KeyAction::Send(b"\x1b[5~".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::NEXT, 0),
+            KeyAction::Send(b"\x1b[6~".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::DELETE, 0),
+            KeyAction::Send(b"\x1b[3~".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_function_keys() {
+        assert_eq!(
+            translate_key(vk::F1, 0),
+            KeyAction::Send(b"\x1bOP".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::F1 + 3, 0),
+            KeyAction::Send(b"\x1bOS".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::F1 + 4, 0),
+            KeyAction::Send(b"\x1b[15~".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::F12, 0),
+            KeyAction::Send(b"\x1b[24~".to_vec())
+        );
+        assert_eq!(translate_key(vk::F24, 0), KeyAction::Ignore);
+    }
+
+    #[test]
+    fn test_modified_keys() {
+        assert_eq!(
+            translate_key(vk::UP, modifier::SHIFT_PRESSED),
+            KeyAction::Send(b"\x1b[1;2A".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::RIGHT, modifier::LEFT_CTRL_PRESSED),
+            KeyAction::Send(b"\x1b[1;5C".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::DELETE, modifier::LEFT_ALT_PRESSED),
+            KeyAction::Send(b"\x1b[3;3~".to_vec())
+        );
+        assert_eq!(
+            translate_key(vk::F1, modifier::SHIFT_PRESSED),
+            KeyAction::Send(b"\x1b[1;2P".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_character_keys_pass_through() {
+        assert_eq!(translate_key(0x41, 0), KeyAction::Char);
+        assert_eq!(translate_key(0x0D, 0), KeyAction::Char);
+    }
+
+    #[test]
+    fn test_ctrl_combinations() {
+        let ctrl = modifier::LEFT_CTRL_PRESSED;
+        assert_eq!(translate_key(vk::SPACE, ctrl), KeyAction::Send(vec![0x00]));
+        assert_eq!(translate_key(vk::OEM_4, ctrl), KeyAction::Send(vec![0x1b]));
+        assert_eq!(translate_key(0x43, ctrl), KeyAction::Send(vec![0x03]));
+        assert_eq!(translate_key(vk::OEM_6, ctrl), KeyAction::Send(vec![0x1d]));
+        assert_eq!(
+            translate_key(0x41, ctrl | modifier::LEFT_ALT_PRESSED),
+            KeyAction::Send(vec![0x1b, 0x01])
+        );
+        // 沒有對應控制字元的 Ctrl 組合交由 uChar 處理
+        assert_eq!(translate_key(0x31, ctrl), KeyAction::Char);
+    }
+
+    #[test]
+    fn test_altgr_is_not_ctrl() {
+        let altgr = modifier::RIGHT_ALT_PRESSED | modifier::LEFT_CTRL_PRESSED;
+        assert_eq!(translate_key(0x51, altgr), KeyAction::Char);
+    }
+
+    #[test]
+    fn test_enter_modes() {
+        assert_eq!(EnterMode::Cr.as_bytes(), b"\r");
+        assert_eq!(EnterMode::Lf.as_bytes(), b"\n");
+        assert_eq!(EnterMode::Crlf.as_bytes(), b"\r\n");
+        assert_eq!(EnterMode::default(), EnterMode::Lf);
+        assert_eq!("CRLF".parse(), Ok(EnterMode::Crlf));
+        assert!("nl".parse::<EnterMode>().is_err());
+    }
+
+    #[test]
+    fn test_alt_prefixes_escape() {
+        assert_eq!(encode_char('x', modifier::LEFT_ALT_PRESSED), b"\x1bx");
+        assert_eq!(encode_char('x', 0), b"x");
+        assert_eq!(encode_char('é', 0), "é".as_bytes());
+
+        let altgr = modifier::RIGHT_ALT_PRESSED | modifier::LEFT_CTRL_PRESSED;
+        assert_eq!(encode_char('@', altgr), b"@");
+    }
+}
@@ -0,0 +1,242 @@
+//! Session labels and correlation IDs (`--label KEY=VALUE`)
+//!
+//! A batch orchestrator running hundreds of sessions side by side needs to
+//! tell their output apart. Every run gets a random session ID (a version 4
+//! UUID), and `--label` attaches KEY=VALUE pairs of the caller's choosing,
+//! such as a job or host name. Both go into the structured records sshpass
+//! writes: the `--audit-log` and `--status-channel` lines and the messages
+//! sent to the system log. With at least one `--label`, every diagnostic on
+//! stderr is tagged as well:
+//!
+//! ```text
+//! SSHPASS: [session=0f8c5a3e-9b1d-4c2e-8a77-3d5e1f0b6c24 job=nightly host=db1] Matched pattern: 'assword'
+//! ```
+//!
+//! Without labels stderr looks as it always did. The ID is unique enough to
+//! correlate logs; it is not meant to be unguessable.
+
+use crate::audit::json_string;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One `--label`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub key: String,
+    pub value: String,
+}
+
+/// Parse `--label`
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_label(value: &str) -> std::result::Result<Label, String> {
+    let (key, label) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", value))?;
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(format!(
+            "label keys are letters, digits, '_', '-' and '.', got '{}'",
+            key
+        ));
+    }
+    if key == "session" {
+        return Err("'session' is the session ID and cannot be a label".to_string());
+    }
+    if label.chars().any(char::is_control) {
+        return Err(format!("label {} has control characters in its value", key));
+    }
+    Ok(Label {
+        key: key.to_string(),
+        value: label.to_string(),
+    })
+}
+
+/// The ID and labels of a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionLabels {
+    pub id: String,
+    pub labels: Vec<Label>,
+}
+
+impl SessionLabels {
+    /// A new session ID with `labels`; a key given twice keeps its last value
+    pub fn new(labels: Vec<Label>) -> Self {
+        let mut unique: Vec<Label> = Vec::with_capacity(labels.len());
+        for label in labels {
+            unique.retain(|seen| seen.key != label.key);
+            unique.push(label);
+        }
+        Self {
+            id: new_session_id(),
+            labels: unique,
+        }
+    }
+
+    /// `session=ID key=value ...` for log lines, values quoted where needed
+    pub fn tag(&self) -> String {
+        let mut tag = format!("session={}", self.id);
+        for label in &self.labels {
+            let plain = !label.value.is_empty()
+                && !label
+                    .value
+                    .chars()
+                    .any(|c| c.is_whitespace() || matches!(c, '"' | '=' | '[' | ']'));
+            if plain {
+                tag.push_str(&format!(" {}={}", label.key, label.value));
+            } else {
+                tag.push_str(&format!(" {}={:?}", label.key, label.value));
+            }
+        }
+        tag
+    }
+
+    /// `"session":"ID","labels":{...}`, to go inside a JSON object
+    pub fn json_fields(&self) -> String {
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| format!("{}:{}", json_string(&label.key), json_string(&label.value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "\"session\":{},\"labels\":{{{}}}",
+            json_string(&self.id),
+            labels
+        )
+    }
+}
+
+/// The session of this process, set once
+static SESSION: OnceLock<SessionLabels> = OnceLock::new();
+
+/// Start this process's session with `labels`
+///
+/// Call it before anything is logged; once the session exists, later calls
+/// return it unchanged.
+pub fn init(labels: Vec<Label>) -> &'static SessionLabels {
+    SESSION.get_or_init(|| SessionLabels::new(labels))
+}
+
+/// This process's session, without labels unless [`init`] came first
+pub fn session() -> &'static SessionLabels {
+    init(Vec::new())
+}
+
+/// The tag for log lines, once the session has labels
+pub fn log_tag() -> Option<&'static str> {
+    static TAG: OnceLock<Option<String>> = OnceLock::new();
+    let session = SESSION.get()?;
+    TAG.get_or_init(|| (!session.labels.is_empty()).then(|| session.tag()))
+        .as_deref()
+}
+
+/// A version 4 UUID
+///
+/// std's `RandomState` is seeded from the operating system's random source,
+/// which spares a dependency; the process ID and the time are mixed in so
+/// that sessions started together still differ.
+fn new_session_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    let mut bytes = [0u8; 16];
+    for (half, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(half);
+        hasher.write_u32(std::process::id());
+        hasher.write_u128(nanos);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    format_uuid(&bytes)
+}
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let hex = |range: std::ops::Range<usize>| {
+        bytes[range]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    };
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex(0..4),
+        hex(4..6),
+        hex(6..8),
+        hex(8..10),
+        hex(10..16)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(key: &str, value: &str) -> Label {
+        Label {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(parse_label("job=nightly"), Ok(label("job", "nightly")));
+        assert_eq!(parse_label("note=a=b"), Ok(label("note", "a=b")));
+        assert_eq!(parse_label("empty="), Ok(label("empty", "")));
+        assert!(parse_label("job").is_err());
+        assert!(parse_label("=x").is_err());
+        assert!(parse_label("a b=x").is_err());
+        assert!(parse_label("session=x").is_err());
+        assert!(parse_label("job=a\nb").is_err());
+    }
+
+    #[test]
+    fn test_session_id_is_uuid_v4() {
+        let id = new_session_id();
+        assert_eq!(id.len(), 36);
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(groups[3].as_bytes()[0], b'8' | b'9' | b'a' | b'b'));
+        assert_ne!(new_session_id(), id);
+    }
+
+    #[test]
+    fn test_tag_and_json_fields() {
+        let session = SessionLabels {
+            id: "ID".to_string(),
+            labels: vec![label("job", "nightly"), label("note", "two words")],
+        };
+        assert_eq!(session.tag(), "session=ID job=nightly note=\"two words\"");
+        assert_eq!(
+            session.json_fields(),
+            r#""session":"ID","labels":{"job":"nightly","note":"two words"}"#
+        );
+
+        let session = SessionLabels {
+            id: "ID".to_string(),
+            labels: Vec::new(),
+        };
+        assert_eq!(session.json_fields(), r#""session":"ID","labels":{}"#);
+    }
+
+    #[test]
+    fn test_repeated_key_keeps_last_value() {
+        let session = SessionLabels::new(vec![
+            label("host", "a"),
+            label("job", "x"),
+            label("host", "b"),
+        ]);
+        assert_eq!(session.labels, [label("job", "x"), label("host", "b")]);
+    }
+}
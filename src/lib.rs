@@ -1,10 +1,65 @@
 // Re-export modules for testing
 pub mod ansi;
+pub mod audit;
+pub mod batch;
+pub mod broken_pipe;
+pub mod build_info;
 pub mod cli;
+pub mod compression;
+pub mod encryption;
+pub mod engine;
 pub mod error;
+pub mod escape;
+pub mod event_queue;
+pub mod exit_map;
+pub mod expect_script;
+pub mod file_lock;
+pub mod file_perms;
+pub mod flow_control;
+pub mod input_source;
+pub mod inventory;
+pub mod keymap;
+pub mod labels;
+pub mod lockout;
+pub mod logging;
 pub mod monitor;
+#[cfg(feature = "notifications")]
+pub mod notification;
+pub mod output_sink;
+pub mod panic_guard;
 pub mod password;
+pub mod password_policy;
+pub mod paste;
+#[cfg(unix)]
+pub mod persist;
+pub mod plugins;
 pub mod process;
+pub mod profiles;
+pub mod progress;
 pub mod pty;
+pub mod recording;
+pub mod resize_retry;
+pub mod runtime_dir;
+pub mod sandbox;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod sequence;
+pub mod session;
 pub mod signal;
+pub mod ssh_argv;
+pub mod ssh_env;
+#[cfg(unix)]
+pub mod ssh_log;
+pub mod stats;
+pub mod status_channel;
+pub mod stdin_eof;
+// Its console helpers serve the Windows event loop
+#[cfg_attr(not(windows), allow(dead_code))]
+pub mod stdin_forwarder;
+pub mod sudo_prompt;
+pub mod system_log;
+pub mod tee;
 pub mod terminal_response;
+pub mod tools;
+#[cfg(feature = "tui")]
+pub mod tui;
@@ -0,0 +1,212 @@
+//! Backing off from hosts that reject the password
+//!
+//! A mass run with a wrong password can get the client banned by fail2ban
+//! or a similar tool on every server it touches. sshpass keeps the times of
+//! incorrect passwords per destination host in a file in the
+//! [shared directory](crate::runtime_dir::shared_dir), and once a host has
+//! rejected `--lockout-attempts` passwords (default 3) within
+//! `--lockout-window` minutes (default 10), it refuses to try that host
+//! again until the oldest failure leaves the window, exiting with code 10.
+//! A login with the password clears the host's record.
+//!
+//! Only ssh, scp and sftp commands are tracked, by the host on their command
+//! line. `--no-lockout-guard` turns the guard off.
+
+use crate::error::{Result, SshpassError};
+use crate::file_lock::FileLock;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Failures within the window that stop further attempts
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// Minutes a failure counts against its host
+pub const DEFAULT_WINDOW_MINUTES: u64 = 10;
+
+/// The failure record of one host
+pub struct LockoutGuard {
+    file: File,
+    window: Duration,
+}
+
+/// Check the host of `command`, returning its guard if it may be tried
+///
+/// Fails only when the host is locked out. Commands without a known host
+/// are not guarded, and a record that cannot be opened disables the guard
+/// with a message under `-v` rather than stopping the session.
+pub fn guard(
+    command: &[String],
+    attempts: u32,
+    window: Duration,
+    verbose: bool,
+) -> Result<Option<LockoutGuard>> {
+    let Some(host) = crate::ssh_argv::destination_host(command) else {
+        return Ok(None);
+    };
+    let opened =
+        crate::runtime_dir::shared_dir().and_then(|dir| LockoutGuard::open(&dir, &host, window));
+    let guard = match opened {
+        Ok(guard) => guard,
+        Err(e) => {
+            if verbose {
+                crate::diag!("Lockout guard disabled: {}", e);
+            }
+            return Ok(None);
+        }
+    };
+    guard.check(&host, attempts, now())?;
+    Ok(Some(guard))
+}
+
+impl LockoutGuard {
+    /// Open the record of `host` in `dir`
+    ///
+    /// The file stays open until the session's outcome is recorded, which a
+    /// `--sandbox` could otherwise not do.
+    pub fn open(dir: &Path, host: &str, window: Duration) -> std::io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(dir.join(file_name(host)))?;
+        Ok(Self { file, window })
+    }
+
+    /// Fail if `host` has used up its attempts at `now` (Unix seconds)
+    fn check(&self, host: &str, attempts: u32, now: u64) -> Result<()> {
+        let failures = {
+            let _lock = FileLock::new(&self.file)?;
+            self.recent_failures(now)?
+        };
+        if failures.len() < attempts as usize {
+            return Ok(());
+        }
+        // The failure that has to expire before `attempts` are in the window
+        let oldest = failures[failures.len() - attempts as usize];
+        Err(SshpassError::LockedOut {
+            host: host.to_string(),
+            failures: failures.len(),
+            retry_after: (oldest + self.window.as_secs()).saturating_sub(now).max(1),
+        })
+    }
+
+    /// Record the outcome of the session
+    ///
+    /// An incorrect password counts against the host and a login with the
+    /// password clears its record; other outcomes leave it alone.
+    pub fn finish(&self, result: &Result<i32>, password_sent: bool, verbose: bool) {
+        let updated = match result {
+            Err(SshpassError::IncorrectPassword) => self.record_failure(now()),
+            Ok(_) if password_sent => FileLock::new(&self.file).and_then(|_lock| self.rewrite(&[])),
+            _ => Ok(()),
+        };
+        if let Err(e) = updated {
+            if verbose {
+                crate::diag!("Failed to update the lockout record: {}", e);
+            }
+        }
+    }
+
+    fn record_failure(&self, now: u64) -> std::io::Result<()> {
+        let _lock = FileLock::new(&self.file)?;
+        let mut failures = self.recent_failures(now)?;
+        failures.push(now);
+        self.rewrite(&failures)
+    }
+
+    /// Failure times still within the window, oldest first
+    fn recent_failures(&self, now: u64) -> std::io::Result<Vec<u64>> {
+        let mut file = &self.file;
+        let mut text = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut text)?;
+        let since = now.saturating_sub(self.window.as_secs());
+        let mut failures = text
+            .lines()
+            .filter_map(|line| line.trim().parse::<u64>().ok())
+            .filter(|&time| time > since && time <= now)
+            .collect::<Vec<_>>();
+        failures.sort_unstable();
+        Ok(failures)
+    }
+
+    fn rewrite(&self, failures: &[u64]) -> std::io::Result<()> {
+        let mut file = &self.file;
+        let text = failures
+            .iter()
+            .map(|time| format!("{}\n", time))
+            .collect::<String>();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(text.as_bytes())
+    }
+}
+
+/// Name of the record file, safe for any host string
+fn file_name(host: &str) -> String {
+    let escaped = host
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02x}", b),
+        })
+        .collect::<String>();
+    format!("lockout-{}", escaped)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("db-1.example"), "lockout-db-1.example");
+        assert_eq!(file_name("fe80::1%eth0"), "lockout-fe80%3a%3a1%25eth0");
+        assert_eq!(file_name("../x"), "lockout-..%2fx");
+    }
+
+    #[test]
+    fn test_lockout_window() {
+        let dir = std::env::temp_dir().join(format!("sshpass-lockout-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let window = Duration::from_secs(600);
+        let guard = LockoutGuard::open(&dir, "db", window).unwrap();
+
+        guard.record_failure(1000).unwrap();
+        guard.record_failure(1100).unwrap();
+        assert!(guard.check("db", 3, 1200).is_ok());
+        guard.record_failure(1200).unwrap();
+        match guard.check("db", 3, 1300) {
+            Err(SshpassError::LockedOut {
+                host,
+                failures,
+                retry_after,
+            }) => {
+                assert_eq!((host.as_str(), failures, retry_after), ("db", 3, 300));
+            }
+            other => panic!("expected a lockout, got {:?}", other.err()),
+        }
+
+        // The first failure has left the window
+        assert!(guard.check("db", 3, 1600).is_ok());
+        // Other hosts are not affected
+        let other = LockoutGuard::open(&dir, "web", window).unwrap();
+        assert!(other.check("web", 1, 1300).is_ok());
+
+        guard.finish(&Ok(0), true, false);
+        assert!(guard.check("db", 1, 1300).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
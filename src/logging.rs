@@ -0,0 +1,323 @@
+//! Diagnostics shared by the Unix and Windows event loops
+//!
+//! A [`Logger`] carries the verbosity level into the event loop. At `-vv`
+//! it reports how long each step of the login took once it is complete. At
+//! `-vvv` it also dumps every read from and write to the PTY as timestamped
+//! hex and ASCII, which helps with devices that send odd control sequences.
+//! Secrets registered with [`Logger::with_secret`] are masked in the dump;
+//! a secret split across two reads is not recognised, but the password is
+//! always written in one piece.
+//!
+//! Diagnostics printed with [`emit`] are colored by their [`Tone`]: prompt
+//! matches green, warnings yellow and failures red. `--color auto`, the
+//! default, colors them when stderr is a terminal, unless `NO_COLOR` is set
+//! or `CLICOLOR=0`; `CLICOLOR_FORCE` colors them even when it is not.
+//!
+//! Every diagnostic starts with [`prefix`], which carries the session tag
+//! once `--label` was given (see [`crate::labels`]); [`diag!`](crate::diag)
+//! is `eprintln!` with that prefix.
+
+use crate::stats::AuthTiming;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use zeroize::Zeroizing;
+
+/// `--color` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub enum ColorMode {
+    /// When stderr is a terminal, following NO_COLOR and CLICOLOR
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How a diagnostic is highlighted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    /// A prompt was recognised
+    Match,
+    Warning,
+    Failure,
+}
+
+/// Whether diagnostics are colored, decided once by `set_color`
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Decide whether diagnostics are colored
+pub fn set_color(mode: ColorMode) {
+    use std::io::IsTerminal;
+
+    let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    let color = wants_color(
+        mode,
+        var("NO_COLOR").is_some(),
+        var("CLICOLOR").map(|value| value != "0"),
+        var("CLICOLOR_FORCE").is_some_and(|value| value != "0"),
+        std::io::stderr().is_terminal(),
+    );
+    COLOR.store(color && enable_escapes(), Ordering::Relaxed);
+}
+
+/// The NO_COLOR and CLICOLOR conventions; an explicit `--color` wins
+fn wants_color(
+    mode: ColorMode,
+    no_color: bool,
+    clicolor: Option<bool>,
+    clicolor_force: bool,
+    terminal: bool,
+) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto if no_color => false,
+        ColorMode::Auto if clicolor_force => true,
+        ColorMode::Auto => clicolor != Some(false) && terminal,
+    }
+}
+
+#[cfg(unix)]
+fn enable_escapes() -> bool {
+    true
+}
+
+/// 在 stderr 的 console 啟用 ANSI 跳脫序列；不是 console 時維持原樣
+#[cfg(windows)]
+fn enable_escapes() -> bool {
+    use windows::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE,
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_ERROR_HANDLE,
+    };
+
+    unsafe {
+        let Ok(handle) = GetStdHandle(STD_ERROR_HANDLE) else {
+            return true;
+        };
+        let mut mode = CONSOLE_MODE::default();
+        if GetConsoleMode(handle, &mut mode).is_err() {
+            return true;
+        }
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING).is_ok()
+    }
+}
+
+/// `text` in the color of `tone`, if diagnostics are colored
+pub fn paint(tone: Tone, text: &str) -> String {
+    if !COLOR.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    let color = match tone {
+        Tone::Match => "32",
+        Tone::Warning => "33",
+        Tone::Failure => "31",
+    };
+    format!("\x1b[{}m{}\x1b[0m", color, text)
+}
+
+/// What diagnostics start with: `SSHPASS: `, followed by the session tag
+/// when the session has labels
+pub fn prefix() -> String {
+    match crate::labels::log_tag() {
+        Some(tag) => format!("SSHPASS: [{}] ", tag),
+        None => "SSHPASS: ".to_string(),
+    }
+}
+
+/// `eprintln!` for diagnostics, after the [`prefix`](crate::logging::prefix)
+#[macro_export]
+macro_rules! diag {
+    ($($arg:tt)*) => {
+        eprintln!("{}{}", $crate::logging::prefix(), format_args!($($arg)*))
+    };
+}
+
+/// Print a diagnostic in the color of its tone
+pub fn emit(tone: Tone, message: &str) {
+    eprintln!("{}{}", prefix(), paint(tone, message));
+}
+
+/// Verbosity level that turns on the login timing report
+pub const TIMING_LEVEL: u8 = 2;
+
+/// Verbosity level that turns on the PTY dump
+pub const DUMP_LEVEL: u8 = 3;
+
+/// Bytes shown per dump line
+const BYTES_PER_LINE: usize = 16;
+
+/// Verbosity and PTY dump for one session
+pub struct Logger {
+    level: u8,
+    started: Instant,
+    secrets: Vec<Zeroizing<Vec<u8>>>,
+    timing: AuthTiming,
+}
+
+impl Logger {
+    /// Create a logger for the given number of `-v` flags (0 is quiet)
+    ///
+    /// Create it right before spawning the child; times are measured from
+    /// here.
+    pub fn new(level: u8) -> Self {
+        let started = Instant::now();
+        Self {
+            level,
+            started,
+            secrets: Vec::new(),
+            timing: AuthTiming::starting_at(started),
+        }
+    }
+
+    /// Mask `secret` wherever it appears in the dump
+    pub fn with_secret(mut self, secret: &[u8]) -> Self {
+        if !secret.is_empty() {
+            self.secrets.push(Zeroizing::new(secret.to_vec()));
+        }
+        self
+    }
+
+    /// Whether `-v` messages are printed
+    pub fn verbose(&self) -> bool {
+        self.level > 0
+    }
+
+    /// Record bytes read from the PTY
+    pub fn pty_read(&mut self, data: &[u8]) {
+        self.dump("read", data);
+        if self.timing.record_output(Instant::now(), data) && self.level >= TIMING_LEVEL {
+            if let Some(summary) = self.timing.summary() {
+                crate::diag!("Login timing: {}", summary);
+            }
+        }
+    }
+
+    /// Record that the password was typed
+    pub fn password_sent(&mut self) {
+        self.timing.record_password_sent(Instant::now());
+    }
+
+    /// Record bytes written to the PTY
+    pub fn pty_write(&self, data: &[u8]) {
+        self.dump("write", data);
+    }
+
+    fn dump(&self, direction: &str, data: &[u8]) {
+        if self.level < DUMP_LEVEL || data.is_empty() {
+            return;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        eprint!(
+            "{}[{:10.6}] pty {} {} bytes\n{}",
+            prefix(),
+            elapsed,
+            direction,
+            data.len(),
+            hex_dump(data, &self.secret_mask(data))
+        );
+    }
+
+    /// Which bytes of `data` belong to a secret
+    fn secret_mask(&self, data: &[u8]) -> Vec<bool> {
+        let mut mask = vec![false; data.len()];
+        for secret in &self.secrets {
+            for start in 0..data.len().saturating_sub(secret.len() - 1) {
+                if data[start..].starts_with(secret) {
+                    mask[start..start + secret.len()].fill(true);
+                }
+            }
+        }
+        mask
+    }
+}
+
+/// Format `data` as hex and ASCII lines; masked bytes show as `**` and `*`
+pub fn hex_dump(data: &[u8], masked: &[bool]) -> String {
+    let mut output = String::new();
+    for (line, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+        let offset = line * BYTES_PER_LINE;
+        let _ = write!(output, "SSHPASS:   {:04x} ", offset);
+        let mut ascii = String::with_capacity(BYTES_PER_LINE);
+        for column in 0..BYTES_PER_LINE {
+            if column == BYTES_PER_LINE / 2 {
+                output.push(' ');
+            }
+            match chunk.get(column) {
+                Some(_) if masked.get(offset + column) == Some(&true) => {
+                    output.push_str(" **");
+                    ascii.push('*');
+                }
+                Some(&byte) => {
+                    let _ = write!(output, " {:02x}", byte);
+                    ascii.push(if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    });
+                }
+                None => output.push_str("   "),
+            }
+        }
+        let _ = writeln!(output, "  |{}|", ascii);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_dump_layout() {
+        let dump = hex_dump(b"Password: \r\nabcdefghij", &[]);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "SSHPASS:   0000  50 61 73 73 77 6f 72 64  3a 20 0d 0a 61 62 63 64  |Password: ..abcd|",
+                "SSHPASS:   0010  65 66 67 68 69 6a                                 |efghij|",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wants_color() {
+        let auto = |no_color, clicolor, force, terminal| {
+            wants_color(ColorMode::Auto, no_color, clicolor, force, terminal)
+        };
+        assert!(auto(false, None, false, true));
+        assert!(!auto(false, None, false, false));
+        assert!(!auto(true, None, true, true));
+        assert!(!auto(false, Some(false), false, true));
+        assert!(auto(false, Some(false), true, false));
+        // An explicit choice wins over the environment
+        assert!(wants_color(ColorMode::Always, true, None, false, false));
+        assert!(!wants_color(ColorMode::Never, false, None, true, true));
+    }
+
+    #[test]
+    fn test_paint() {
+        COLOR.store(true, Ordering::Relaxed);
+        assert_eq!(paint(Tone::Match, "prompt"), "\x1b[32mprompt\x1b[0m");
+        COLOR.store(false, Ordering::Relaxed);
+        assert_eq!(paint(Tone::Failure, "Incorrect"), "Incorrect");
+    }
+
+    #[test]
+    fn test_secret_is_masked() {
+        let logger = Logger::new(DUMP_LEVEL).with_secret(b"hunter2");
+        let data = b"hunter2\nhunter";
+        let dump = hex_dump(data, &logger.secret_mask(data));
+        assert!(dump.contains(" ** ** ** ** ** ** ** 0a  68"));
+        assert!(dump.contains("|*******.hunter|"));
+        assert!(!dump.contains("74 65 72 32"));
+
+        // Nothing to mask without secrets, and short data is fine
+        let logger = Logger::new(DUMP_LEVEL).with_secret(b"");
+        assert_eq!(logger.secret_mask(b"ab"), vec![false, false]);
+        let logger = Logger::new(DUMP_LEVEL).with_secret(b"longer secret");
+        assert_eq!(logger.secret_mask(b"ab"), vec![false, false]);
+    }
+}
@@ -1,666 +1,524 @@
 mod ansi;
+mod audit;
+// Parts of it are library API the binary does not use
+#[allow(dead_code)]
+mod batch;
+mod broken_pipe;
+mod build_info;
 mod cli;
+mod compression;
+mod encryption;
+mod engine;
 mod error;
+mod escape;
+mod event_queue;
+mod exit_map;
+// Only clap can select a script
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod expect_script;
+mod file_lock;
+mod file_perms;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod flow_control;
+// Scripted input is only given by library callers
+#[allow(dead_code)]
+mod input_source;
+mod inventory;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod keymap;
+mod labels;
+mod lockout;
+mod logging;
 mod monitor;
+#[cfg(feature = "notifications")]
+mod notification;
+mod output_sink;
+// Only clap can select detach
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod panic_guard;
 mod password;
+mod password_policy;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod paste;
+#[cfg(unix)]
+mod persist;
+// Plugins are only loaded with the `plugins` feature
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+mod plugins;
 mod process;
+mod profiles;
+mod progress;
 mod pty;
+// Only clap can select a recording format
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod recording;
+mod resize_retry;
+mod runtime_dir;
+#[cfg(unix)]
+mod sandbox;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod sequence;
+// Handles are only taken by library callers
+#[allow(dead_code)]
+mod session;
 mod signal;
+mod ssh_argv;
+// Only clap can select scrub
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod ssh_env;
+#[cfg(unix)]
+mod ssh_log;
+mod stats;
+mod status_channel;
+mod stdin_eof;
+#[cfg_attr(not(windows), allow(dead_code))]
 mod stdin_forwarder;
+mod sudo_prompt;
+// Only clap can select a system log target
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod system_log;
+mod tee;
+#[cfg_attr(not(windows), allow(dead_code))]
 mod terminal_response;
+mod tools;
+#[cfg(feature = "tui")]
+mod tui;
 
 use cli::Cli;
-use error::{Result, SshpassError};
-use monitor::{MonitorResult, OutputMonitor};
-use password::{read_password_from_env, PasswordSource, SecureString};
-use process::ChildProcess;
-use signal::{forward_signal_to_child, handle_window_resize, setup_signal_handlers};
-
-#[cfg(unix)]
-use nix::sys::select::{pselect, FdSet};
-#[cfg(unix)]
-use nix::sys::signal::SigSet;
-#[cfg(unix)]
-use std::os::fd::BorrowedFd;
-#[cfg(windows)]
-use std::time::Duration;
+use engine::launch::{self, Launch};
+use error::SshpassError;
+use logging::Tone;
+use password::PasswordSource;
+use signal::setup_signal_handlers;
+use status_channel::ExitStatus;
+use std::path::Path;
 
 fn main() {
-    std::process::exit(run());
+    // process::exit skips destructors; unwind out of run() first so the
+    // guards it owns clean up even after a panic
+    let code = std::panic::catch_unwind(run)
+        .unwrap_or_else(|_| error::ReturnCode::RuntimeError.as_exit_code());
+    std::process::exit(code);
 }
 
 fn run() -> i32 {
-    let raw_args = std::env::args_os().collect::<Vec<_>>();
-
     // Parse command line arguments
-    let mut args = Cli::parse_args();
-
-    // Allow "-ppassword" inline form (unless user forced command parsing via "--")
-    absorb_inline_password_arg(&mut args, &raw_args);
-
-    // Validate arguments
-    if let Err(e) = args.validate() {
-        eprintln!("SSHPASS: {}", e);
-        eprintln!("Use \"sshpass -h\" to get help");
-        return e.exit_code();
+    let args = Cli::parse_args();
+    logging::set_color(args.color);
+    // Before the first diagnostic, which carries the labels
+    labels::init(args.label.clone());
+
+    if args.version {
+        let _ = broken_pipe::write_stdout(build_info::version_text(args.build_info).as_bytes());
+        return 0;
     }
 
-    // Determine password source
-    let password_source = get_password_source(&args);
-
-    // Read the password
-    let password = match read_password(&args, password_source) {
-        Ok(pw) => pw,
-        Err(e) => {
-            eprintln!("SSHPASS: {}", e);
-            return e.exit_code();
-        }
-    };
-
-    // Set up signal handlers
-    let signal_flags = match setup_signal_handlers() {
-        Ok(flags) => flags,
-        Err(e) => {
-            eprintln!("SSHPASS: Failed to setup signal handlers: {}", e);
-            return e.exit_code();
-        }
-    };
-
-    // Run the main program
-    let result = run_program(&args, password, signal_flags);
-    match result {
-        Ok(exit_code) => {
-            if args.is_verbose() {
-                eprintln!("SSHPASS: Child exited with code {}", exit_code);
-            }
-            exit_code
-        }
-        Err(e) => {
-            eprintln!("SSHPASS: {}", e);
-            e.exit_code()
+    // Not a command to run, but a check of an --audit-log
+    if let [audit, verify, path] = args.command.as_slice() {
+        if audit == "audit" && verify == "verify" {
+            return verify_audit_log(Path::new(path));
         }
     }
-}
 
-/// Determine the password source from command line arguments
-fn get_password_source(args: &Cli) -> PasswordSource {
-    #[cfg(unix)]
-    if let Some(fd) = args.password_fd {
-        return PasswordSource::Fd(fd);
+    // Not a command to run, but the replay of a recording
+    if args.command.first().is_some_and(|word| word == "play") {
+        return play_recording(&args.command[1..]);
     }
-
-    if let Some(ref path) = args.password_file {
-        PasswordSource::File(path.clone())
-    } else if let Some(ref pw) = args.password {
-        PasswordSource::Password(SecureString::new(pw.clone()))
-    } else if args.env_var.is_some() {
-        // Will be handled separately because we need to clear the env var
-        PasswordSource::Stdin // Placeholder
-    } else {
-        PasswordSource::Stdin
+    if args.command.first().is_some_and(|word| word == "export") {
+        return export_recording(&args.command[1..]);
     }
-}
-
-/// Detect and consume "-ppassword" inline values that clap treated as part of the command.
-fn absorb_inline_password_arg(args: &mut Cli, raw_args: &[std::ffi::OsString]) {
-    if args.password.is_some() || args.command.is_empty() {
-        return;
-    }
-
-    if let Some(password) = inline_password_from_command(&args.command, raw_args) {
-        args.password = Some(password);
-        args.command.remove(0);
+    if args.command.first().is_some_and(|word| word == "diff") {
+        return diff_runs(&args.command[1..]);
     }
-}
-
-/// Determine whether the first command argument encodes "-ppassword".
-fn inline_password_from_command(
-    command_args: &[String],
-    raw_args: &[std::ffi::OsString],
-) -> Option<String> {
-    let first = command_args.first()?;
-    let password = parse_inline_password_token(first)?;
 
-    if inline_arg_after_double_dash(first, raw_args) {
-        return None;
+    // Not a command to run, but a look at a host of a running batch
+    #[cfg(unix)]
+    if args.command.first().is_some_and(|word| word == "tail") {
+        return tail_host(&args.command[1..]);
     }
 
-    Some(password)
-}
-
-/// Extract the password from an inline "-ppassword" or "-p=password" token.
-fn parse_inline_password_token(token: &str) -> Option<String> {
-    let rest = token.strip_prefix("-p")?;
-    let rest = rest.strip_prefix('=').unwrap_or(rest);
-    if rest.is_empty() {
-        return None;
+    // Not a command to run, but the teardown of --persist
+    #[cfg(unix)]
+    if args.command == ["persist", "stop"] {
+        return stop_persistent(args.is_verbose());
     }
-    Some(rest.to_string())
-}
-
-/// Check whether the token appeared after "--", meaning it belongs to the target command.
-fn inline_arg_after_double_dash(candidate: &str, raw_args: &[std::ffi::OsString]) -> bool {
-    let candidate_os = std::ffi::OsStr::new(candidate);
-    let mut after_double_dash = false;
 
-    for arg in raw_args.iter().skip(1) {
-        if arg == "--" {
-            after_double_dash = true;
-            continue;
-        }
-        if arg == candidate_os {
-            return after_double_dash;
-        }
+    // Not a command to run, but a batch watched on a dashboard
+    #[cfg(feature = "tui")]
+    if args.command.first().is_some_and(|word| word == "tui") {
+        return run_dashboard(&args);
     }
 
-    false
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn os_args(args: &[&str]) -> Vec<std::ffi::OsString> {
-        args.iter().map(|s| std::ffi::OsString::from(s)).collect()
+    if let Err(code) = check_args(&args) {
+        return code;
     }
 
-    #[test]
-    fn detects_inline_password_before_command() {
-        let command = vec!["-ppassword".to_string(), "ssh".to_string()];
-        let raw = os_args(&["sshpass", "-ppassword", "ssh", "example.com"]);
-        assert_eq!(
-            inline_password_from_command(&command, &raw),
-            Some("password".to_string())
-        );
+    // Not one command to run, but one per host of an inventory
+    if args.inventory.is_some() {
+        return batch::runner::run_batch(args, false);
     }
 
-    #[test]
-    fn ignores_inline_password_after_double_dash() {
-        let command = vec!["-ppassword".to_string(), "echo".to_string()];
-        let raw = os_args(&["sshpass", "--", "-ppassword", "echo", "ok"]);
-        assert_eq!(inline_password_from_command(&command, &raw), None);
-    }
+    // Opened first so that every failure from here on is reported
+    let open = |spec: &Option<String>, option| match spec {
+        Some(spec) => status_channel::StatusChannel::open(spec, option).map(Some),
+        None => Ok(None),
+    };
+    let mut status_channel = match open(&args.status_channel, "--status-channel") {
+        Ok(channel) => channel,
+        Err(e) => {
+            crate::diag!("{}", e);
+            return e.exit_code();
+        }
+    };
+    let mut json_result = match open(&args.json_result, "--json-result") {
+        Ok(channel) => channel,
+        Err(e) => {
+            crate::diag!("{}", e);
+            return e.exit_code();
+        }
+    };
 
-    #[test]
-    fn parses_inline_password_with_equals() {
-        let command = vec!["-p=secret".to_string(), "ssh".to_string()];
-        let raw = os_args(&["sshpass", "-p=secret", "ssh", "example.com"]);
-        assert_eq!(
-            inline_password_from_command(&command, &raw),
-            Some("secret".to_string())
-        );
+    let mut prompt = None;
+    let status = run_session(args, &mut prompt);
+    if let Some(ref mut channel) = status_channel {
+        if let Err(e) = channel.report(&status) {
+            crate::diag!("Failed to write to --status-channel: {}", e);
+        }
     }
-
-    #[test]
-    fn returns_none_when_first_command_arg_not_password() {
-        let command = vec!["ssh".to_string(), "-p2222".to_string()];
-        let raw = os_args(&["sshpass", "ssh", "-p2222", "example.com"]);
-        assert_eq!(inline_password_from_command(&command, &raw), None);
+    if let Some(ref mut channel) = json_result {
+        if let Err(e) = channel.report_result(&status, prompt.as_ref()) {
+            crate::diag!("Failed to write to --json-result: {}", e);
+        }
     }
+    status.code
 }
-/// Read the password from the configured source
-fn read_password(args: &Cli, source: PasswordSource) -> Result<SecureString> {
-    // Special handling for environment variables
-    if let Some(ref env_var) = args.env_var {
-        return read_password_from_env(env_var, args.is_verbose());
-    }
 
-    // Security warning for -p option
-    if args.password.is_some() {
-        eprintln!(
-            "SSHPASS: Warning: Using -p option is insecure. Consider using -f or -e instead."
-        );
-    }
+/// Run the command, and say whether the exit code is the child's; `prompt`
+/// is set to the prompt the password was typed at
+fn run_session(mut args: Cli, prompt: &mut Option<monitor::PromptMatch>) -> ExitStatus {
+    // Before anything touches the terminal or starts the child
+    panic_guard::install(args.on_panic);
+    broken_pipe::set_mode(args.on_broken_pipe);
 
-    source.read_password(args.is_verbose())
-}
-
-/// Main program logic: spawn child and monitor output
-fn run_program(
-    args: &Cli,
-    password: SecureString,
-    signal_flags: signal::SignalFlags,
-) -> Result<i32> {
-    let verbose = args.is_verbose();
-    if verbose {
-        eprintln!("SSHPASS: Verbose logging enabled");
-    }
+    // Determine password source
+    let password_source = launch::password_source(&mut args);
 
-    // Spawn the child process with PTY
-    let child = match ChildProcess::spawn(&args.command, verbose) {
-        Ok(child) => child,
+    // Read the password
+    let password = match launch::read_password(&args, password_source) {
+        Ok(pw) => pw,
         Err(e) => {
-            eprintln!("SSHPASS: Failed to spawn child process: {}", e);
-            return Err(e);
+            crate::diag!("{}", e);
+            return ExitStatus::sshpass(e.exit_code());
         }
     };
 
-    if verbose {
-        eprintln!("SSHPASS: Spawned child process (debug)");
-        #[cfg(unix)]
-        {
-            eprintln!("SSHPASS: Child process spawned with PID: {}", child.pid);
-        }
-        #[cfg(windows)]
-        {
-            if let Some(pid) = child.process_id {
-                eprintln!("SSHPASS: Child process spawned with PID: {}", pid);
-            }
-        }
+    // Catch a broken secret before the server counts it as a failed login
+    if let Err(e) = password_policy::check(&args.validate_password, &password) {
+        crate::diag!("{}", e);
+        return ExitStatus::sshpass(e.exit_code());
     }
 
-    // Create output monitor
-    let prompt = args.prompt.as_deref();
-    let mut monitor = OutputMonitor::new(prompt, verbose);
+    // Read the replacement for an expired password up front, like the password
+    let new_password = match args.handle_expiry {
+        Some(ref spec) => {
+            match PasswordSource::from_spec(spec).and_then(|s| s.read_password(args.is_verbose())) {
+                Ok(pw) => Some(pw),
+                Err(e) => {
+                    crate::diag!("Failed to read new password: {}", e);
+                    return ExitStatus::sshpass(e.exit_code());
+                }
+            }
+        }
+        None => None,
+    };
 
-    // Run the event loop
-    run_event_loop(child, &password, &mut monitor, signal_flags, verbose)
-}
+    // Set up signal handlers
+    #[cfg(windows)]
+    let signal_flags = if args.no_console {
+        signal::setup_signal_handlers_no_console()
+    } else {
+        setup_signal_handlers()
+    };
+    #[cfg(unix)]
+    let signal_flags = setup_signal_handlers();
 
-/// Main event loop: monitor PTY output and handle signals (Unix implementation)
-#[cfg(unix)]
-fn run_event_loop(
-    child: ChildProcess,
-    password: &SecureString,
-    monitor: &mut OutputMonitor,
-    signal_flags: signal::SignalFlags,
-    verbose: bool,
-) -> Result<i32> {
-    let mut buffer = vec![0u8; 256];
-    let master_fd = child.pty.master_fd();
-    let mut terminated = false;
-
-    // Handle initial window size
-    if let Err(e) = handle_window_resize(&child.pty) {
-        if verbose {
-            eprintln!("SSHPASS: Warning: Failed to set initial window size: {}", e);
+    let signal_flags = match signal_flags {
+        Ok(flags) => flags,
+        Err(e) => {
+            crate::diag!("Failed to setup signal handlers: {}", e);
+            return ExitStatus::sshpass(e.exit_code());
         }
-    }
+    };
 
-    loop {
-        // Check for signals
-        if signal_flags.check_and_clear_sigwinch() {
-            if let Err(e) = handle_window_resize(&child.pty) {
-                if verbose {
-                    eprintln!("SSHPASS: Warning: Failed to handle window resize: {}", e);
-                }
-            }
+    let control = match session::SessionControl::new(signal_flags) {
+        Ok(control) => control,
+        Err(e) => {
+            crate::diag!("Failed to set up session control: {}", e);
+            return ExitStatus::sshpass(e.exit_code());
         }
+    };
 
-        if signal_flags.check_and_clear_sigtstp() {
-            if let Err(e) = forward_signal_to_child(nix::sys::signal::SIGTSTP, &child, verbose) {
-                if verbose {
-                    eprintln!("SSHPASS: Warning: Failed to forward SIGTSTP: {}", e);
-                }
+    // Copies of the output follow the session as an observer
+    let control = if args.tee.is_empty() {
+        control
+    } else {
+        let rotation = args.record_max_size.map(|max_size| tee::Rotation {
+            max_size,
+            keep: args.record_rotate,
+        });
+        let opened = tee::Tee::open(&args.tee)
+            .and_then(|tee| tee.with_rotation(rotation).with_format(args.record_format))
+            .and_then(|tee| tee.with_encryption(&args.record_recipient))
+            .and_then(|tee| tee.with_compression(args.record_compress));
+        match opened {
+            Ok(tee) => control.with_observer(tee),
+            Err(e) => {
+                crate::diag!("{}", e);
+                return ExitStatus::sshpass(e.exit_code());
             }
         }
+    };
 
-        if let Some(sig) = signal_flags.get_term_signal() {
-            if verbose {
-                eprintln!("SSHPASS: Received termination signal, forwarding to child");
+    // The system log follows the session as an observer
+    let control = if args.log_target.is_native() {
+        match system_log::SystemLog::open() {
+            Ok(log) => control.with_observer(system_log::Lifecycle::new(log)),
+            Err(e) => {
+                crate::diag!("{}", e);
+                return ExitStatus::sshpass(e.exit_code());
             }
-            let _ = forward_signal_to_child(sig, &child, verbose);
-            terminated = true;
         }
+    } else {
+        control
+    };
 
-        // Check if child has exited
-        if let Some(exit_code) = child.try_wait()? {
-            if verbose {
-                eprintln!("SSHPASS: Child process exited with code: {}", exit_code);
-            }
-            return Ok(exit_code);
-        }
+    // A spinner on stderr until ssh shows signs of life; a reused --persist
+    // master never asks for the password
+    #[cfg(unix)]
+    let show_progress = args.persist.is_none();
+    #[cfg(windows)]
+    let show_progress = true;
+    let control = if show_progress && progress::wanted(args.progress, args.is_verbose()) {
+        control.with_observer(progress::Progress::new())
+    } else {
+        control
+    };
 
-        if terminated {
-            // Wait for child to exit
-            return child.wait();
-        }
+    #[cfg(feature = "notifications")]
+    let control = if args.notify {
+        let after = args
+            .notify_after
+            .unwrap_or(notification::DEFAULT_NOTIFY_AFTER_SECS);
+        control.with_observer(notification::Notifier::new(
+            ssh_argv::destination_host(&args.command),
+            std::time::Duration::from_secs(after),
+            args.is_verbose(),
+        ))
+    } else {
+        control
+    };
 
-        // Use pselect to monitor the PTY with signal handling
-        let mut read_fds = FdSet::new();
-        let master_fd_borrowed = unsafe { BorrowedFd::borrow_raw(master_fd) };
-        read_fds.insert(&master_fd_borrowed);
-
-        let empty_sigset = SigSet::empty();
-        match pselect(
-            master_fd + 1,
-            Some(&mut read_fds),
-            None,
-            None,
-            None,
-            Some(&empty_sigset),
-        ) {
-            Ok(n) if n > 0 => {
-                // Data available to read
-                match child.pty.read(&mut buffer) {
-                    Ok(0) => {
-                        // EOF on PTY
-                        if verbose {
-                            eprintln!("SSHPASS: EOF on PTY");
-                        }
-                        // Continue to wait for child exit
-                        continue;
-                    }
-                    Ok(n) => {
-                        // Process the output
-                        match monitor.handle_output(&buffer[..n]) {
-                            MonitorResult::Continue => {
-                                // Keep monitoring
-                            }
-                            MonitorResult::SendPassword => {
-                                // Send the password
-                                if verbose {
-                                    eprintln!("SSHPASS: Sending password");
-                                }
-                                child.pty.write_all(password.as_bytes())?;
-                                child.pty.write_all(b"\n")?;
-                            }
-                            MonitorResult::IncorrectPassword => {
-                                // Wrong password, terminate
-                                return Err(SshpassError::IncorrectPassword);
-                            }
-                            MonitorResult::HostKeyUnknown => {
-                                return Err(SshpassError::HostKeyUnknown);
-                            }
-                            MonitorResult::HostKeyChanged => {
-                                return Err(SshpassError::HostKeyChanged);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        // Check if this is EIO (I/O error)
-                        if let SshpassError::SystemError(nix_err) = &e {
-                            if *nix_err == nix::errno::Errno::EIO {
-                                // EIO can mean:
-                                // 1. Child hasn't opened slave yet (temporary)
-                                // 2. Child has terminated (permanent)
-                                // Check if child is still running
-                                if let Some(exit_code) = child.try_wait()? {
-                                    if verbose {
-                                        eprintln!(
-                                            "SSHPASS: Child exited with code {}, after EIO",
-                                            exit_code
-                                        );
-                                    }
-                                    return Ok(exit_code);
-                                }
-                                // Child still running, EIO is temporary (slave not open yet)
-                                // Continue to next iteration
-                                continue;
-                            }
-                        }
-
-                        if verbose {
-                            eprintln!("SSHPASS: Read error: {}", e);
-                        }
-                        // For other errors, return the error
-                        return Err(e);
-                    }
+    // A Lua script follows the session as its observer
+    #[cfg(feature = "scripting")]
+    let control = match args.lua_script {
+        Some(ref path) => {
+            match scripting::LuaScript::load(path, control.handle(), args.is_verbose()) {
+                Ok(script) => control.with_observer(script),
+                Err(e) => {
+                    crate::diag!("{}", e);
+                    return ExitStatus::sshpass(e.exit_code());
                 }
             }
-            Ok(_) => {
-                // Timeout or interrupted by signal
-                continue;
+        }
+        None => control,
+    };
+
+    // Run the main program
+    launch::apply_ssh_env(&args);
+    let result = Launch::new(&args, password)
+        .with_new_password(new_password)
+        .run(&control)
+        .map(|code| {
+            let mapped = exit_map::apply(&args.map_exit_code, code);
+            if mapped != code && args.is_verbose() {
+                crate::diag!("Mapped exit code {} to {}", code, mapped);
             }
-            Err(nix::errno::Errno::EINTR) => {
-                // Interrupted by signal, continue
-                continue;
+            mapped
+        });
+    if let Err(ref e) = result {
+        control.error(e);
+    }
+    *prompt = control.handle().prompt_match();
+    control.set_status(session::SessionStatus::Exited(match result {
+        Ok(exit_code) => exit_code,
+        Err(ref e) => e.exit_code(),
+    }));
+    match result {
+        Ok(exit_code) => {
+            let termination = control.handle().termination();
+            if args.is_verbose() {
+                match termination {
+                    Some(termination) => crate::diag!(
+                        "Child was killed by {}, exiting with code {}",
+                        termination.summary(),
+                        exit_code
+                    ),
+                    None => crate::diag!("Child exited with code {}", exit_code),
+                }
             }
-            Err(e) => {
-                return Err(SshpassError::RuntimeError(format!("pselect error: {}", e)));
+            let usage = control.handle().resource_usage().filter(|_| args.rusage);
+            if let (Some(usage), true) = (usage, args.is_verbose()) {
+                crate::diag!("Child used {}", usage.summary());
             }
+            ExitStatus::child(exit_code, termination).with_usage(usage)
+        }
+        Err(e) => {
+            logging::emit(Tone::Failure, &e.to_string());
+            ExitStatus::sshpass(e.exit_code())
         }
     }
 }
 
-/// PTY 輸出事件
-#[cfg(windows)]
-enum PtyEvent {
-    Data(Vec<u8>),
-    Eof,
-    Error(String),
+/// Validate the arguments, reporting every problem
+fn check_args(args: &Cli) -> std::result::Result<(), i32> {
+    args.validate().map_err(|errors| {
+        for problem in errors.problems() {
+            logging::emit(Tone::Failure, &problem.error.to_string());
+            eprintln!("  hint: {}", problem.hint);
+        }
+        eprintln!("Use \"sshpass -h\" to get help");
+        errors.exit_code()
+    })
 }
 
-/// Main event loop: monitor PTY output and handle signals (Windows stub)
-#[cfg(windows)]
-fn run_event_loop(
-    mut child: ChildProcess,
-    password: &SecureString,
-    monitor: &mut OutputMonitor,
-    signal_flags: signal::SignalFlags,
-    verbose: bool,
-) -> Result<i32> {
-    use std::sync::mpsc::channel;
-    use std::thread;
-
-    if verbose {
-        eprintln!("SSHPASS: [DEBUG] Entering run_event_loop (Windows)");
-    }
-
-    let mut terminated = false;
-    let mut empty_read_count = 0u32;
-    let mut last_status_report = std::time::Instant::now();
-
-    if verbose {
-        eprintln!("SSHPASS: [DEBUG] About to create StdinForwarder...");
-    }
-
-    // 創建 stdin 轉發器
-    let stdin_forwarder = stdin_forwarder::StdinForwarder::new(verbose).map_err(|e| {
-        SshpassError::RuntimeError(format!("Failed to setup stdin forwarder: {}", e))
-    })?;
-
-    if verbose {
-        eprintln!("SSHPASS: [DEBUG] StdinForwarder created");
+/// `sshpass tui [OPTIONS] --inventory FILE COMMAND`: the batch of
+/// `--inventory`, shown on a dashboard
+#[cfg(feature = "tui")]
+fn run_dashboard(args: &Cli) -> i32 {
+    // The options may come after the word as well as before it: parse again
+    // without it. The command's words end the command line.
+    let mut raw_args = std::env::args_os().collect::<Vec<_>>();
+    raw_args.remove(raw_args.len() - args.command.len());
+    let args = Cli::parse_args_from(raw_args);
+    if let Err(code) = check_args(&args) {
+        return code;
     }
+    batch::runner::run_dashboard(args)
+}
 
-    // 創建 PTY 讀取線程
-    let (pty_tx, pty_rx) = channel();
-    let pty_reader = child.pty_ref().reader.clone();
-
-    if verbose {
-        eprintln!("SSHPASS: [DEBUG] Starting PTY reader thread...");
+/// `sshpass audit verify FILE`: check the hash chain of an --audit-log
+fn verify_audit_log(path: &Path) -> i32 {
+    match audit::verify(path) {
+        Ok(records) => {
+            crate::diag!("{}: {} record(s), chain intact", path.display(), records);
+            0
+        }
+        Err(e) => {
+            let e = SshpassError::RuntimeError(format!("{}: {}", path.display(), e));
+            crate::diag!("{}", e);
+            e.exit_code()
+        }
     }
+}
 
-    thread::spawn(move || {
-        let mut buffer = vec![0u8; 512];
-        loop {
-            let mut reader = match pty_reader.lock() {
-                Ok(r) => r,
-                Err(_) => {
-                    let _ = pty_tx.send(PtyEvent::Error("Reader lock poisoned".into()));
-                    break;
-                }
-            };
-
-            match reader.read(&mut buffer) {
-                Ok(0) => {
-                    // EOF
-                    let _ = pty_tx.send(PtyEvent::Eof);
-                    break;
-                }
-                Ok(n) => {
-                    let data = buffer[..n].to_vec();
-                    if pty_tx.send(PtyEvent::Data(data)).is_err() {
-                        break; // 接收端已關閉
-                    }
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // 非阻塞模式下沒有數據
-                    std::thread::sleep(std::time::Duration::from_millis(1));
-                    continue;
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::UnexpectedEof
-                        || e.kind() == std::io::ErrorKind::BrokenPipe
-                    {
-                        let _ = pty_tx.send(PtyEvent::Eof);
-                    } else {
-                        let _ = pty_tx.send(PtyEvent::Error(format!("PTY read failed: {}", e)));
-                    }
-                    break;
-                }
-            }
-        }
+/// `sshpass play FILE`: replay a recorded session
+fn play_recording(args: &[String]) -> i32 {
+    let played = recording::PlayOptions::parse(args).and_then(|options| {
+        let reader = recording::open(&options.path, options.identity.as_deref())?;
+        recording::play(reader, &mut std::io::stdout().lock(), &options)
     });
-
-    if verbose {
-        eprintln!("SSHPASS: [DEBUG] PTY reader thread started, entering main loop");
+    match played {
+        Ok(_) => 0,
+        Err(e) => {
+            crate::diag!("{}", e);
+            e.exit_code()
+        }
     }
+}
 
-    let mut password_sent = false;
-
-    if let Err(e) = handle_window_resize(child.pty_ref()) {
-        if verbose {
-            eprintln!("SSHPASS: Warning: Failed to set initial window size: {}", e);
+/// `sshpass export FILE`: write the output of a recording as a log
+fn export_recording(args: &[String]) -> i32 {
+    let exported = recording::ExportOptions::parse(args).and_then(|options| {
+        let reader = recording::open(&options.path, options.identity.as_deref())?;
+        recording::export(reader, &mut std::io::stdout().lock(), &options)
+    });
+    match exported {
+        Ok(_) => 0,
+        Err(e) => {
+            crate::diag!("{}", e);
+            e.exit_code()
         }
     }
+}
 
-    loop {
-        // 處理 stdin 輸入（在密碼發送後才開始轉發）
-        if password_sent {
-            while let Some(event) = stdin_forwarder.try_recv() {
-                match event {
-                    stdin_forwarder::StdinEvent::Data(data) => {
-                        if verbose {
-                            eprintln!(
-                                "SSHPASS: [DEBUG] Forwarding {} bytes from stdin to PTY",
-                                data.len()
-                            );
-                        }
-                        child.pty_ref().write_all(&data)?;
-                    }
-                    stdin_forwarder::StdinEvent::Eof => {
-                        if verbose {
-                            eprintln!("SSHPASS: [DEBUG] stdin EOF received (will continue reading PTY output)");
-                        }
-                        // 不要立即終止 - 繼續讀取 PTY 輸出直到子進程退出
-                        // 這對於非互動式使用很重要（例如 echo "command" | sshpass ...）
-                    }
-                }
-            }
+/// `sshpass diff RUN1 RUN2`: report the hosts whose output changed
+fn diff_runs(args: &[String]) -> i32 {
+    let compared = tools::diff::DiffOptions::parse(args).and_then(|options| {
+        let (hosts, changes) = tools::diff::compare(&options)?;
+        let report = changes
+            .iter()
+            .map(|change| change.describe(&options) + "\n")
+            .collect::<String>();
+        broken_pipe::write_stdout(report.as_bytes())?;
+        Ok((hosts, changes.len()))
+    });
+    match compared {
+        Ok((hosts, 0)) => {
+            crate::diag!("{} host(s) compared, no changes", hosts);
+            0
         }
-
-        if signal_flags.check_and_clear_sigwinch() {
-            if let Err(e) = handle_window_resize(child.pty_ref()) {
-                if verbose {
-                    eprintln!("SSHPASS: Warning: Failed to handle window resize: {}", e);
-                }
-            }
+        Ok((hosts, changes)) => {
+            crate::diag!("{} host(s) compared, {} difference(s)", hosts, changes);
+            error::ReturnCode::RunsDiffer.as_exit_code()
         }
-
-        if let Some(_) = signal_flags.get_term_signal() {
-            if verbose {
-                eprintln!("SSHPASS: Received console termination event, forwarding to child");
-            }
-            let _ = forward_signal_to_child((), &mut child, verbose);
-            terminated = true;
+        Err(e) => {
+            crate::diag!("{}", e);
+            e.exit_code()
         }
+    }
+}
 
-        if let Some(exit_code) = child.try_wait()? {
-            if verbose {
-                eprintln!("SSHPASS: Child process exited with code: {}", exit_code);
-            }
-            return Ok(exit_code);
+/// `sshpass tail SOCKET [HOST]`: follow the output of a host of a running
+/// batch, or list the hosts in flight
+#[cfg(unix)]
+fn tail_host(args: &[String]) -> i32 {
+    let watched = match args {
+        [socket] => batch::tail::hosts(Path::new(socket)).and_then(|hosts| {
+            let list = hosts.iter().map(|host| format!("{}\n", host));
+            broken_pipe::write_stdout(list.collect::<String>().as_bytes())
+        }),
+        [socket, host] => {
+            batch::tail::watch(Path::new(socket), host, &mut std::io::stdout().lock())
         }
-
-        if terminated {
-            return child.wait();
+        _ => Err(SshpassError::InvalidArguments(
+            "Usage: sshpass tail SOCKET [HOST]".to_string(),
+        )),
+    };
+    match watched {
+        Ok(()) => 0,
+        Err(e) => {
+            crate::diag!("{}", e);
+            e.exit_code()
         }
+    }
+}
 
-        // 處理 PTY 輸出
-        match pty_rx.try_recv() {
-            Ok(PtyEvent::Data(buffer)) => {
-                empty_read_count = 0;
-
-                if verbose {
-                    eprintln!("SSHPASS: [DEBUG] PTY read {} bytes", buffer.len());
-                    if buffer.len() < 100 {
-                        eprintln!(
-                            "SSHPASS: [DEBUG] Data: {:?}",
-                            String::from_utf8_lossy(&buffer)
-                        );
-                    }
-                }
-
-                // Check for terminal queries (portable-pty handles these internally, but we log them)
-                if let Some(response) = terminal_response::get_terminal_response(&buffer) {
-                    if verbose {
-                        eprintln!(
-                            "SSHPASS: [DEBUG] Terminal query detected ({} bytes)",
-                            response.len()
-                        );
-                        eprintln!("SSHPASS: [DEBUG] portable-pty handles these automatically");
-                    }
-                }
-
-                let result = monitor.handle_output(&buffer);
-
-                // 在密碼發送後，將所有 PTY 輸出轉發到 stdout
-                if password_sent {
-                    use std::io::Write;
-                    let _ = std::io::stdout().write_all(&buffer);
-                    let _ = std::io::stdout().flush();
-                }
-
-                match result {
-                    MonitorResult::Continue => {
-                        // Just continue monitoring
-                    }
-                    MonitorResult::SendPassword => {
-                        if verbose {
-                            eprintln!("SSHPASS: Sending password");
-                        }
-                        child.pty_ref().write_all(password.as_bytes())?;
-                        child.pty_ref().write_all(b"\r\n")?;
-                        password_sent = true; // 標記密碼已發送，開始轉發 stdin
-                        if verbose {
-                            eprintln!("SSHPASS: [DEBUG] Password sent, now forwarding stdin");
-                        }
-                    }
-                    MonitorResult::IncorrectPassword => {
-                        return Err(SshpassError::IncorrectPassword);
-                    }
-                    MonitorResult::HostKeyUnknown => {
-                        return Err(SshpassError::HostKeyUnknown);
-                    }
-                    MonitorResult::HostKeyChanged => {
-                        return Err(SshpassError::HostKeyChanged);
-                    }
-                }
-            }
-            Ok(PtyEvent::Eof) => {
-                if verbose {
-                    eprintln!("SSHPASS: [DEBUG] PTY EOF received");
-                }
-                // Continue to wait for child exit
-            }
-            Ok(PtyEvent::Error(e)) => {
-                if verbose {
-                    eprintln!("SSHPASS: [DEBUG] PTY read error: {}", e);
-                }
-                // Check if child has exited
-                if let Some(exit_code) = child.try_wait()? {
-                    if verbose {
-                        eprintln!("SSHPASS: Child exited with code {}", exit_code);
-                    }
-                    return Ok(exit_code);
-                }
-            }
-            Err(_) => {
-                // No data available this iteration
-                empty_read_count += 1;
-
-                // Report status every 2 seconds if still getting empty reads
-                if verbose && last_status_report.elapsed().as_secs() >= 2 {
-                    eprintln!("SSHPASS: [STATUS] Still waiting for data... (empty reads: {}, elapsed: {:.1}s)",
-                        empty_read_count,
-                        last_status_report.elapsed().as_secs_f64());
-                    last_status_report = std::time::Instant::now();
-                }
-            }
+/// `sshpass persist stop`: close the masters started with `--persist`
+#[cfg(unix)]
+fn stop_persistent(verbose: bool) -> i32 {
+    let Some(dir) = persist::control_dir() else {
+        return 0;
+    };
+    match persist::stop_all(&dir, verbose) {
+        Ok(stopped) => {
+            crate::diag!("Stopped {} persistent connection(s)", stopped);
+            0
+        }
+        Err(e) => {
+            let e = SshpassError::IoError(e);
+            crate::diag!("{}", e);
+            e.exit_code()
         }
-
-        std::thread::sleep(Duration::from_millis(1));
     }
 }
@@ -1,3 +1,8 @@
+use crate::logging::{self, Tone};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
 /// State machine for matching strings in output
 ///
 /// This implements a simple string matching algorithm that can handle
@@ -23,13 +28,18 @@ impl Matcher {
     /// Returns true if the complete pattern has been matched.
     /// The matcher maintains state across multiple calls.
     pub fn feed(&mut self, buffer: &[u8]) -> bool {
+        self.find(buffer).is_some()
+    }
+
+    /// Like `feed`, but return the offset just past the end of the match
+    pub fn find(&mut self, buffer: &[u8]) -> Option<usize> {
         let reference_bytes = self.reference.as_bytes();
 
-        for &byte in buffer {
+        for (index, &byte) in buffer.iter().enumerate() {
             if self.state < reference_bytes.len() && reference_bytes[self.state] == byte {
                 self.state += 1;
                 if self.state == reference_bytes.len() {
-                    return true;
+                    return Some(index + 1);
                 }
             } else {
                 // No match, reset and try again from the beginning
@@ -40,7 +50,7 @@ impl Matcher {
             }
         }
 
-        false
+        None
     }
 
     /// Reset the matcher state
@@ -65,6 +75,48 @@ impl Matcher {
     pub fn pattern(&self) -> &str {
         &self.reference
     }
+
+    /// The byte a match must start with
+    fn first_byte(&self) -> Option<u8> {
+        self.reference.as_bytes().first().copied()
+    }
+}
+
+/// Finds the next byte a match could start with
+///
+/// While no matcher is partway through its pattern, a byte that begins
+/// none of the patterns leaves every matcher as it is, so the output up to
+/// the next candidate can be skipped. Up to three distinct first bytes, as
+/// after the login with one profile prompt, are looked for with memchr;
+/// the login patterns begin with common letters, where a table lookup per
+/// byte is faster than restarting memchr at every candidate.
+struct Prefilter {
+    starts: Vec<u8>,
+    table: [bool; 256],
+}
+
+impl Prefilter {
+    fn new<'a>(matchers: impl Iterator<Item = &'a Matcher>) -> Self {
+        let mut starts: Vec<u8> = matchers.filter_map(Matcher::first_byte).collect();
+        starts.sort_unstable();
+        starts.dedup();
+        let mut table = [false; 256];
+        for &byte in &starts {
+            table[byte as usize] = true;
+        }
+        Self { starts, table }
+    }
+
+    /// Offset of the first candidate in `data`
+    fn find(&self, data: &[u8]) -> Option<usize> {
+        match *self.starts {
+            [] => None,
+            [a] => memchr::memchr(a, data),
+            [a, b] => memchr::memchr2(a, b, data),
+            [a, b, c] => memchr::memchr3(a, b, c, data),
+            _ => data.iter().position(|&byte| self.table[byte as usize]),
+        }
+    }
 }
 
 /// Result of monitoring output from SSH
@@ -80,17 +132,279 @@ pub enum MonitorResult {
     HostKeyUnknown,
     /// Host key changed prompt detected
     HostKeyChanged,
+    /// A prompt the user must answer locally (e.g. touching a FIDO2 security
+    /// key); show it to the user and wait instead of sending the password
+    PassThroughPrompt,
+    /// A profile response prompt detected, send the reply at this index
+    SendResponse(usize),
+    /// A profile abort pattern detected
+    Abort,
+    /// The server reports the password has expired and it cannot be changed
+    PasswordExpired,
+    /// Password change dialog asks for the new password
+    SendNewPassword,
+    /// A detector asks for a reply, taken with `take_detector_reply`
+    SendDetectorReply,
+}
+
+/// Messages announcing that the password has expired
+const EXPIRY_PATTERNS: &[&str] = &["password has expired", "You must change your password"];
+
+/// Password change prompts answered with the current password
+/// ("Current password:", "(current) UNIX password:")
+const CURRENT_PASSWORD_PATTERNS: &[&str] = &["urrent password", "urrent) UNIX password"];
+
+/// Password change prompts answered with the new password
+/// ("New password:", "Retype new password:", "Enter new UNIX password:")
+const NEW_PASSWORD_PATTERNS: &[&str] = &["ew password", "ew UNIX password"];
+
+/// The new password is typed twice; more prompts mean it was rejected
+const MAX_NEW_PASSWORD_PROMPTS: usize = 2;
+
+/// OpenSSH's message for a rejected password
+const DENIED_PATTERN: &str = "Permission denied, please try again";
+
+/// Part of OpenSSH's warning about a changed host key
+const KEY_CHANGED_PATTERN: &str = "differs from the key for the IP address";
+
+/// ssh's refusal of an unknown host key under StrictHostKeyChecking=yes
+const STRICT_HOST_PATTERN: &str = "you have requested strict checking";
+
+/// ssh asking to touch a security key (sk-* keys)
+pub const CONFIRM_PRESENCE_PATTERN: &str = "Confirm user presence for key";
+
+/// Output after the password that shows the login went through
+const SUCCESS_PATTERNS: &[&str] = &["Last login", "Authenticated to "];
+
+/// What is known when the password prompt appears again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reprompt {
+    /// Time since the password was last sent
+    pub since_password: Duration,
+    /// Whether output since the password showed a successful login
+    pub success_seen: bool,
+}
+
+/// Decides whether a repeated password prompt means the password was rejected
+///
+/// A prompt that is not a failure (e.g. sudo asking on the remote host) is
+/// left for the user to answer. Closures taking a [`Reprompt`] can be used
+/// directly.
+pub trait RepromptHeuristic: Send {
+    fn is_auth_failure(&mut self, reprompt: &Reprompt) -> bool;
+}
+
+impl<F> RepromptHeuristic for F
+where
+    F: FnMut(&Reprompt) -> bool + Send,
+{
+    fn is_auth_failure(&mut self, reprompt: &Reprompt) -> bool {
+        self(reprompt)
+    }
+}
+
+/// The C sshpass rule: every repeated prompt is a rejected password
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnyReprompt;
+
+impl RepromptHeuristic for AnyReprompt {
+    fn is_auth_failure(&mut self, _reprompt: &Reprompt) -> bool {
+        true
+    }
+}
+
+/// Only a prompt shortly after the password, before the login succeeded,
+/// is a rejected password (`--reauth-window`)
+#[derive(Debug, Clone, Copy)]
+pub struct ReauthWindow {
+    window: Duration,
+}
+
+impl ReauthWindow {
+    pub fn new(window: Duration) -> Self {
+        Self { window }
+    }
+}
+
+impl RepromptHeuristic for ReauthWindow {
+    fn is_auth_failure(&mut self, reprompt: &Reprompt) -> bool {
+        !reprompt.success_seen && reprompt.since_password <= self.window
+    }
+}
+
+/// What a [`Detector`] found in a chunk of output
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+pub enum Detection {
+    /// Nothing of interest
+    Nothing,
+    /// The password prompt, handled like the built-in one
+    PasswordPrompt,
+    /// Bytes to type into the session
+    Reply(Vec<u8>),
+    /// Give up, for the reason given
+    Abort(String),
+}
+
+/// Recognises output of devices the built-in matchers do not know
+///
+/// Detectors see each chunk of filtered output once, before the built-in
+/// matchers; the first one reporting something decides the result for that
+/// chunk. After a reply the built-in matchers still scan the chunk; after
+/// anything else they skip it. Profile files can load detectors from
+/// plugins (see `plugins`), and `--script` runs as one.
+pub trait Detector: Send {
+    /// Name shown in messages
+    fn name(&self) -> &str;
+    fn feed(&mut self, data: &[u8]) -> Detection;
+}
+
+/// Password prompt pattern used when neither `-P` nor a profile sets one
+pub const DEFAULT_PROMPT: &str = "assword";
+
+/// Milliseconds the password prompt is ignored after sending a password
+/// that contains it (`--echo-guard`)
+pub const DEFAULT_ECHO_GUARD_MS: u64 = 1000;
+
+/// Seconds without a re-prompt after which the login is taken to have
+/// succeeded (`--stop-matching-after-auth`)
+pub const DEFAULT_STOP_MATCHING_SECS: u64 = 10;
+
+/// Whether `password` contains the prompt pattern, so that a device echoing
+/// the password would seem to ask for it again
+pub fn prompt_in_password(prompt: &str, password: &[u8]) -> bool {
+    !prompt.is_empty()
+        && password
+            .windows(prompt.len())
+            .any(|window| window == prompt.as_bytes())
+}
+
+/// How long a password prompt is held back while the output may still turn
+/// out to be an expiry message ("Your password has expired")
+const PROMPT_HOLD: Duration = Duration::from_millis(100);
+
+/// Output kept before a prompt for [`PromptMatch::context`]
+pub const CONTEXT_BYTES: usize = 80;
+
+/// Where the prompt that was answered with the password was found
+///
+/// Offsets count the output as the matchers see it: with escape sequences
+/// removed and line endings normalised, so they can differ from the raw
+/// bytes shown at `-vvv` (unless the filter is turned off, see
+/// [`OutputMonitor::set_ansi_filter`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptMatch {
+    /// The pattern that matched, or `detector NAME` for a plugin's match
+    pub pattern: String,
+    /// Offset of the match in the session's output; for a detector, the
+    /// offset of the chunk it recognised
+    pub offset: u64,
+    /// Up to [`CONTEXT_BYTES`] of output ending with the match
+    pub context: Vec<u8>,
+}
+
+impl PromptMatch {
+    /// The line of the prompt; earlier lines of the context may belong to
+    /// other prompts
+    pub fn line(&self) -> String {
+        let context = String::from_utf8_lossy(&self.context);
+        context.rsplit('\n').next().unwrap_or_default().to_string()
+    }
+}
+
+/// Keys the prompt a password attempt answers, from the line of the prompt
+pub type AttemptKey = Box<dyn Fn(&str) -> String + Send>;
+
+/// State of the forced password change dialog
+struct PasswordChange {
+    current: Vec<Matcher>,
+    new: Vec<Matcher>,
+    new_sent: usize,
 }
 
 /// Monitors SSH output for password prompts and error conditions
 pub struct OutputMonitor {
     password_matcher: Matcher,
+    /// Second-entry prompt that is answered once with the password
+    repeat_matcher: Option<Matcher>,
+    /// Ignore prompt matches until the line holding the answered prompt ends
+    skip_prompt_line: bool,
     host_auth_matcher: Matcher,
+    strict_host_matcher: Matcher,
+    denied_matcher: Matcher,
+    /// Matchers for ssh's log when it arrives on its own pipe
+    log_denied_matcher: Matcher,
+    log_key_changed_matcher: Matcher,
+    log_strict_host_matcher: Matcher,
     host_key_changed_matcher: Matcher,
-    ansi_filter: crate::ansi::AnsiFilter,
+    confirm_presence_matcher: Matcher,
+    /// Whether ssh's own messages are looked for in the output
+    ssh_checks: bool,
+    /// Other prompts answered from the profile, in profile order
+    response_matchers: Vec<Matcher>,
+    /// Output that ends the session
+    abort_matchers: Vec<Matcher>,
+    /// Abort pattern that matched
+    aborted_on: Option<String>,
+    expiry_matchers: Vec<Matcher>,
+    /// Whether an expired password is changed instead of reported
+    handle_expiry: bool,
+    /// Set once the password change dialog has started
+    password_change: Option<PasswordChange>,
+    /// None when the matchers see the raw output
+    ansi_filter: Option<crate::ansi::AnsiFilter>,
+    /// Filtered output after the last reported event, not yet scanned
+    pending: Vec<u8>,
+    /// When a password prompt match was held back
+    held_since: Option<Instant>,
     password_sent: bool,
+    /// When the password was last sent
+    password_sent_at: Option<Instant>,
+    /// How long after sending the password a prompt match is taken to be
+    /// its echo
+    echo_guard: Option<Duration>,
+    success_matchers: Vec<Matcher>,
+    success_seen: bool,
+    /// Stop looking for login prompts this long after the password when no
+    /// re-prompt came, or once the login succeeded
+    stop_matching_after: Option<Duration>,
+    /// Set once the login is over and its matchers are no longer fed
+    matching_stopped: bool,
+    reprompt: Box<dyn RepromptHeuristic>,
+    /// Password prompts seen, not counting re-prompts after the login
+    password_prompts: u32,
+    /// How many times each prompt (by its key) was answered
+    attempts: HashMap<String, u32>,
+    /// Key of the prompt the password was last sent for
+    attempt_key: String,
+    /// None when every prompt shares one count
+    attempt_keyer: Option<AttemptKey>,
+    /// How many times a prompt is answered before giving up
+    max_attempts: u32,
     verbose: bool,
     first_output: bool,
+    /// How long to wait for the user to answer a pass-through prompt
+    confirm_timeout: Option<Duration>,
+    /// When the pending pass-through prompt was shown
+    confirm_started: Option<Instant>,
+    /// How long to wait for the password prompt
+    prompt_timeout: Option<Duration>,
+    /// When monitoring started
+    started: Instant,
+    detectors: Vec<Box<dyn Detector>>,
+    detector_reply: Vec<u8>,
+    /// Filtered output bytes scanned so far
+    scanned: u64,
+    /// The last bytes scanned, for the context of a match
+    recent: VecDeque<u8>,
+    /// The last password prompt match, which may still be held back or
+    /// taken for an echo
+    candidate: Option<PromptMatch>,
+    /// The match the password was last sent for
+    prompt_match: Option<PromptMatch>,
+    /// Whether output no pattern can start in is skipped; off only to
+    /// check the results are the same
+    prefilter: bool,
 }
 
 impl OutputMonitor {
@@ -100,26 +414,309 @@ impl OutputMonitor {
     /// * `prompt` - Optional custom password prompt (default: "assword")
     /// * `verbose` - Enable verbose logging
     pub fn new(prompt: Option<&str>, verbose: bool) -> Self {
-        let password_prompt = prompt.unwrap_or("assword");
+        let password_prompt = prompt.unwrap_or(DEFAULT_PROMPT);
 
         if verbose {
-            eprintln!(
-                "SSHPASS: searching for password prompt using match \"{}\"",
+            crate::diag!(
+                "searching for password prompt using match \"{}\"",
                 password_prompt
             );
         }
 
         Self {
             password_matcher: Matcher::new(password_prompt),
+            repeat_matcher: None,
+            skip_prompt_line: false,
             host_auth_matcher: Matcher::new("The authenticity of host "),
-            host_key_changed_matcher: Matcher::new("differs from the key for the IP address"),
-            ansi_filter: crate::ansi::AnsiFilter::new(),
+            strict_host_matcher: Matcher::new(STRICT_HOST_PATTERN),
+            denied_matcher: Matcher::new(DENIED_PATTERN),
+            log_denied_matcher: Matcher::new(DENIED_PATTERN),
+            log_key_changed_matcher: Matcher::new(KEY_CHANGED_PATTERN),
+            log_strict_host_matcher: Matcher::new(STRICT_HOST_PATTERN),
+            host_key_changed_matcher: Matcher::new(KEY_CHANGED_PATTERN),
+            confirm_presence_matcher: Matcher::new(CONFIRM_PRESENCE_PATTERN),
+            ssh_checks: true,
+            response_matchers: Vec::new(),
+            abort_matchers: Vec::new(),
+            aborted_on: None,
+            expiry_matchers: EXPIRY_PATTERNS.iter().copied().map(Matcher::new).collect(),
+            handle_expiry: false,
+            password_change: None,
+            ansi_filter: Some(crate::ansi::AnsiFilter::new()),
+            pending: Vec::new(),
+            held_since: None,
             password_sent: false,
+            password_sent_at: None,
+            echo_guard: None,
+            success_matchers: SUCCESS_PATTERNS.iter().copied().map(Matcher::new).collect(),
+            success_seen: false,
+            stop_matching_after: None,
+            matching_stopped: false,
+            reprompt: Box::new(AnyReprompt),
+            password_prompts: 0,
+            attempts: HashMap::new(),
+            attempt_key: String::new(),
+            attempt_keyer: None,
+            max_attempts: 1,
             verbose,
             first_output: true,
+            confirm_timeout: None,
+            confirm_started: None,
+            prompt_timeout: None,
+            started: Instant::now(),
+            detectors: Vec::new(),
+            detector_reply: Vec::new(),
+            scanned: 0,
+            recent: VecDeque::with_capacity(CONTEXT_BYTES),
+            candidate: None,
+            prompt_match: None,
+            prefilter: true,
         }
     }
 
+    /// The prompt the password was last sent for
+    pub fn prompt_match(&self) -> Option<&PromptMatch> {
+        self.prompt_match.as_ref()
+    }
+
+    /// Note a password prompt match of `pattern` ending with the byte just
+    /// scanned
+    fn note_match(&mut self, pattern: &str) {
+        self.candidate = Some(PromptMatch {
+            pattern: pattern.to_string(),
+            offset: self.scanned.saturating_sub(pattern.len() as u64),
+            context: self.recent.iter().copied().collect(),
+        });
+    }
+
+    /// Prompts to answer with the profile's responses; a match is reported as
+    /// `SendResponse` with the position of the pattern
+    pub fn set_responses<'a>(&mut self, patterns: impl IntoIterator<Item = &'a str>) {
+        self.response_matchers = patterns.into_iter().map(Matcher::new).collect();
+    }
+
+    /// Answer the forced password change dialog instead of reporting
+    /// `PasswordExpired`
+    pub fn set_expiry_handling(&mut self, enabled: bool) {
+        self.handle_expiry = enabled;
+    }
+
+    /// Look for ssh's host key, rejected password and security key messages
+    ///
+    /// Turned off when the command is not an ssh login, so the output of
+    /// e.g. `sudo` cannot be mistaken for them.
+    pub fn set_ssh_checks(&mut self, enabled: bool) {
+        self.ssh_checks = enabled;
+    }
+
+    /// Whether the new password has been typed and confirmed
+    pub fn password_changed(&self) -> bool {
+        self.password_change
+            .as_ref()
+            .is_some_and(|change| change.new_sent == MAX_NEW_PASSWORD_PROMPTS)
+    }
+
+    /// Add one more response prompt after those already set
+    pub fn add_response(&mut self, pattern: &str) {
+        self.response_matchers.push(Matcher::new(pattern));
+    }
+
+    /// Output that makes sshpass give up (e.g. "% Authorization failed")
+    pub fn set_abort_patterns<'a>(&mut self, patterns: impl IntoIterator<Item = &'a str>) {
+        self.abort_matchers = patterns.into_iter().map(Matcher::new).collect();
+    }
+
+    /// The abort pattern that ended the session, if any
+    pub fn aborted_on(&self) -> Option<&str> {
+        self.aborted_on.as_deref()
+    }
+
+    /// Limit how long to wait for the password prompt (None waits forever)
+    pub fn set_prompt_timeout(&mut self, timeout: Option<Duration>) {
+        self.prompt_timeout = timeout;
+    }
+
+    /// Time left until the password prompt must appear, if a timeout is set
+    /// and the password has not been sent yet
+    pub fn prompt_remaining(&self) -> Option<Duration> {
+        if self.password_sent {
+            return None;
+        }
+        let timeout = self.prompt_timeout?;
+        Some(timeout.saturating_sub(self.started.elapsed()))
+    }
+
+    /// Whether the password prompt failed to appear in time
+    pub fn prompt_timed_out(&self) -> bool {
+        self.prompt_remaining() == Some(Duration::ZERO)
+    }
+
+    /// How long the event loop may block before a timeout must be checked
+    pub fn wait_timeout(&self) -> Option<Duration> {
+        [
+            self.prompt_remaining(),
+            self.confirmation_remaining(),
+            self.hold_remaining(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Time left until a held-back password prompt is answered
+    fn hold_remaining(&self) -> Option<Duration> {
+        let held_since = self.held_since?;
+        Some(PROMPT_HOLD.saturating_sub(held_since.elapsed()))
+    }
+
+    /// Whether the output has paused long enough to answer a held-back
+    /// password prompt with `finish`
+    pub fn hold_expired(&self) -> bool {
+        self.hold_remaining() == Some(Duration::ZERO)
+    }
+
+    /// Report a held-back password prompt as if the output had ended
+    pub fn finish(&mut self) -> MonitorResult {
+        if self.held_since.take().is_some() {
+            return self.password_prompt();
+        }
+        MonitorResult::Continue
+    }
+
+    /// Answer a second-entry prompt (e.g. "Enter same passphrase again") once
+    /// with the password instead of treating it as a rejected password
+    pub fn set_repeat_prompt(&mut self, prompt: Option<&str>) {
+        if self.verbose {
+            if let Some(prompt) = prompt {
+                crate::diag!("searching for repeat prompt using match \"{}\"", prompt);
+            }
+        }
+        self.repeat_matcher = prompt.map(Matcher::new);
+    }
+
+    /// Decide with `heuristic` whether a repeated password prompt means the
+    /// password was rejected
+    pub fn set_reprompt_heuristic(&mut self, heuristic: impl RepromptHeuristic + 'static) {
+        self.reprompt = Box::new(heuristic);
+    }
+
+    /// Answer up to `attempts` password prompts with the same password, for
+    /// servers that drop the first attempt
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.max_attempts = attempts.max(1);
+    }
+
+    /// Count the attempts of [`set_max_attempts`](Self::set_max_attempts)
+    /// per prompt, by the key `keyer` gives for the line of the prompt
+    ///
+    /// A session through jump hosts keys each prompt by the hop it names,
+    /// so every hop gets the whole limit and a rejected hop is not retried
+    /// with the attempts of the others. Without a keyer all prompts share
+    /// one count.
+    pub fn set_attempt_key(&mut self, keyer: impl Fn(&str) -> String + Send + 'static) {
+        self.attempt_keyer = Some(Box::new(keyer));
+    }
+
+    /// Key of the prompt in `candidate`
+    fn candidate_key(&self) -> String {
+        match (&self.attempt_keyer, &self.candidate) {
+            (Some(keyer), Some(candidate)) => keyer(&candidate.line()),
+            _ => String::new(),
+        }
+    }
+
+    /// Whether the prompt the password was last sent for used up its
+    /// attempts
+    fn attempts_used_up(&self) -> bool {
+        self.attempts.get(&self.attempt_key).copied().unwrap_or(0) >= self.max_attempts
+    }
+
+    /// Whether escape sequences are removed and line endings normalised
+    /// before matching
+    ///
+    /// Turned off, the matchers see the raw bytes: cheaper on large plain
+    /// output, but a prompt drawn with colours or cursor movement is no
+    /// longer found.
+    pub fn set_ansi_filter(&mut self, enabled: bool) {
+        self.ansi_filter = enabled.then(crate::ansi::AnsiFilter::new);
+    }
+
+    /// Ignore the password prompt for `window` after sending the password,
+    /// for passwords that contain the prompt pattern
+    pub fn set_echo_guard(&mut self, window: Option<Duration>) {
+        self.echo_guard = window;
+    }
+
+    /// Whether a prompt match now may be the echoed password
+    fn echo_guarded(&self) -> bool {
+        match (self.echo_guard, self.password_sent_at) {
+            (Some(window), Some(sent)) => sent.elapsed() < window,
+            _ => false,
+        }
+    }
+
+    /// Stop looking for the password prompt and ssh's messages once the
+    /// login succeeded or `quiet` passed after the password without a
+    /// re-prompt (None keeps matching for the whole session)
+    ///
+    /// Remote output such as an sshd_config being shown can then not be
+    /// taken for a prompt. Profile responses, abort patterns, detectors and
+    /// the expiry dialog are still matched.
+    pub fn set_stop_matching_after_auth(&mut self, quiet: Option<Duration>) {
+        self.stop_matching_after = quiet;
+    }
+
+    /// Whether the login is over, stopping its matchers when it just ended
+    fn login_over(&mut self) -> bool {
+        if self.matching_stopped || !self.password_sent {
+            return self.matching_stopped;
+        }
+        let (Some(quiet), Some(sent)) = (self.stop_matching_after, self.password_sent_at) else {
+            return false;
+        };
+        if self.success_seen || sent.elapsed() >= quiet {
+            if self.verbose {
+                eprintln!();
+                crate::diag!("Login succeeded, no longer looking for password prompts");
+            }
+            self.matching_stopped = true;
+        }
+        self.matching_stopped
+    }
+
+    /// How many times the server asked for the password
+    pub fn password_prompts(&self) -> u32 {
+        self.password_prompts
+    }
+
+    /// Add a detector, consulted after those added before it
+    pub fn add_detector(&mut self, detector: impl Detector + 'static) {
+        self.detectors.push(Box::new(detector));
+    }
+
+    /// Reply requested by the last `SendDetectorReply`
+    pub fn take_detector_reply(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.detector_reply)
+    }
+
+    /// Limit how long a pass-through prompt may go unanswered (None waits forever)
+    pub fn set_confirm_timeout(&mut self, timeout: Option<Duration>) {
+        self.confirm_timeout = timeout;
+    }
+
+    /// Time left to answer the pending pass-through prompt, if one is pending
+    /// and a timeout is set
+    pub fn confirmation_remaining(&self) -> Option<Duration> {
+        let started = self.confirm_started?;
+        let timeout = self.confirm_timeout?;
+        Some(timeout.saturating_sub(started.elapsed()))
+    }
+
+    /// Whether the pending pass-through prompt has timed out
+    pub fn confirmation_expired(&self) -> bool {
+        self.confirmation_remaining() == Some(Duration::ZERO)
+    }
+
     /// Handle output from SSH and determine what action to take
     ///
     /// # Arguments
@@ -128,8 +725,14 @@ impl OutputMonitor {
     /// # Returns
     /// MonitorResult indicating what action should be taken
     pub fn handle_output(&mut self, data: &[u8]) -> MonitorResult {
+        // Any further output means the pass-through prompt was answered
+        self.confirm_started = None;
+
         // Filter ANSI escape sequences and normalize line endings
-        let filtered_data = self.ansi_filter.process(data);
+        let filtered_data = match self.ansi_filter {
+            Some(ref mut filter) => Cow::Owned(filter.process(data)),
+            None => Cow::Borrowed(data),
+        };
 
         if self.verbose {
             if self.first_output {
@@ -137,30 +740,152 @@ impl OutputMonitor {
             }
             // Print the raw data for debugging
             if let Ok(s) = std::str::from_utf8(data) {
-                eprint!("SSHPASS: read: {}", s);
+                eprint!("{}read: {}", crate::logging::prefix(), s);
             }
             // Also show filtered data if different
             if filtered_data != data {
                 if let Ok(filtered_s) = std::str::from_utf8(&filtered_data) {
                     eprintln!();
-                    eprintln!("SSHPASS: (filtered): {}", filtered_s);
+                    crate::diag!("(filtered): {}", filtered_s);
                 }
             }
         }
 
+        // The caller drains the chunk with next_result after typing a
+        // detector's reply; a chunk a detector otherwise acted on is not
+        // scanned again
+        let detected = self.run_detectors(&filtered_data);
+        if matches!(
+            detected,
+            MonitorResult::Continue | MonitorResult::SendDetectorReply
+        ) {
+            self.pending.extend_from_slice(&filtered_data);
+        }
+        if detected != MonitorResult::Continue {
+            return detected;
+        }
+        self.next_result()
+    }
+
+    /// Pass a chunk of filtered output to the detectors
+    fn run_detectors(&mut self, data: &[u8]) -> MonitorResult {
+        if data.is_empty() {
+            return MonitorResult::Continue;
+        }
+        for detector in &mut self.detectors {
+            match detector.feed(data) {
+                Detection::Nothing => continue,
+                Detection::PasswordPrompt => {
+                    if self.verbose {
+                        eprintln!();
+                        logging::emit(
+                            Tone::Match,
+                            &format!("detector {} found the password prompt", detector.name()),
+                        );
+                    }
+                    let context = data[data.len().saturating_sub(CONTEXT_BYTES)..].to_vec();
+                    self.candidate = Some(PromptMatch {
+                        pattern: format!("detector {}", detector.name()),
+                        offset: self.scanned + self.pending.len() as u64,
+                        context,
+                    });
+                    self.password_matcher.reset();
+                    return self.password_prompt();
+                }
+                Detection::Reply(reply) => {
+                    if self.verbose {
+                        eprintln!();
+                        crate::diag!(
+                            "detector {} sends a {} byte reply",
+                            detector.name(),
+                            reply.len()
+                        );
+                    }
+                    self.detector_reply = reply;
+                    return MonitorResult::SendDetectorReply;
+                }
+                Detection::Abort(reason) => {
+                    if self.verbose {
+                        eprintln!();
+                        logging::emit(
+                            Tone::Failure,
+                            &format!("detector {} gives up: {}", detector.name(), reason),
+                        );
+                    }
+                    self.aborted_on = Some(format!("{} ({})", reason, detector.name()));
+                    return MonitorResult::Abort;
+                }
+            }
+        }
+        MonitorResult::Continue
+    }
+
+    /// Handle a chunk of ssh's own log (`--ssh-stderr-pipe`)
+    ///
+    /// The log holds no prompts, only messages that end the login.
+    pub fn handle_ssh_log(&mut self, data: &[u8]) -> MonitorResult {
+        if self.login_over() {
+            return MonitorResult::Continue;
+        }
+        if self.password_sent && self.log_denied_matcher.feed(data) && self.attempts_used_up() {
+            if self.verbose {
+                logging::emit(Tone::Failure, "ssh logged a rejected password. Exiting.");
+            }
+            return MonitorResult::IncorrectPassword;
+        }
+        if self.log_strict_host_matcher.feed(data) {
+            if self.verbose {
+                logging::emit(Tone::Failure, "ssh logged an unknown host key. Exiting.");
+            }
+            return MonitorResult::HostKeyUnknown;
+        }
+        if self.log_key_changed_matcher.feed(data) {
+            if self.verbose {
+                logging::emit(Tone::Failure, "ssh logged a changed host key. Exiting.");
+            }
+            return MonitorResult::HostKeyChanged;
+        }
+        MonitorResult::Continue
+    }
+
+    /// Scan output left over from the last event
+    ///
+    /// A single read can hold more than one prompt. `handle_output` reports
+    /// the first event and keeps the output after it, so the same events are
+    /// found however the output is split into reads. Call this until it
+    /// returns `Continue` to handle all of them.
+    pub fn next_result(&mut self) -> MonitorResult {
         // Store matcher state before feeding
         let prev_state = self.password_matcher.current_state();
 
-        // Check for password prompt
-        let matched = self.password_matcher.feed(&filtered_data);
+        let pending = std::mem::take(&mut self.pending);
+        let prefilter = Prefilter::new(self.live_matchers());
+        let mut index = 0;
+        while index < pending.len() {
+            if self.prefilter && self.idle() {
+                let rest = &pending[index..];
+                let skipped = prefilter.find(rest).unwrap_or(rest.len());
+                self.skip(&rest[..skipped]);
+                index += skipped;
+                if index == pending.len() {
+                    break;
+                }
+            }
+            let result = self.scan_byte(pending[index]);
+            index += 1;
+            if result != MonitorResult::Continue {
+                self.pending = pending[index..].to_vec();
+                return result;
+            }
+        }
 
         // Show matching progress in verbose mode
-        if self.verbose && !matched {
+        if self.verbose {
             let new_state = self.password_matcher.current_state();
             if new_state > 0 && new_state != prev_state {
                 eprintln!();
-                eprintln!(
-                    "SSHPASS: Partial match: {}/{} chars of '{}'",
+                crate::diag!(
+                    "Partial match: {}/{} chars of '{}'",
                     new_state,
                     self.password_matcher.pattern().len(),
                     self.password_matcher.pattern()
@@ -168,51 +893,355 @@ impl OutputMonitor {
             }
         }
 
-        if matched {
-            if !self.password_sent {
+        MonitorResult::Continue
+    }
+
+    /// The matchers `scan_byte` feeds at this point of the session
+    fn live_matchers(&self) -> impl Iterator<Item = &Matcher> {
+        let login = (!self.matching_stopped).then(|| {
+            let ssh = [
+                &self.denied_matcher,
+                &self.host_auth_matcher,
+                &self.strict_host_matcher,
+                &self.host_key_changed_matcher,
+                &self.confirm_presence_matcher,
+            ];
+            [&self.password_matcher]
+                .into_iter()
+                .chain(self.repeat_matcher.as_ref())
+                .chain(self.success_matchers.iter().filter(|_| !self.success_seen))
+                .chain(ssh.into_iter().filter(|_| self.ssh_checks))
+        });
+        // An expired password is noticed even after the login
+        self.abort_matchers
+            .iter()
+            .chain(&self.expiry_matchers)
+            .chain(&self.response_matchers)
+            .chain(login.into_iter().flatten())
+    }
+
+    /// Whether a byte no pattern starts with would change nothing
+    ///
+    /// The password change dialog, a held-back prompt and the rest of an
+    /// answered line are left to `scan_byte`.
+    fn idle(&self) -> bool {
+        self.password_change.is_none()
+            && self.held_since.is_none()
+            && !self.skip_prompt_line
+            && self.live_matchers().all(|m| m.current_state() == 0)
+    }
+
+    /// Account for output skipped while idle
+    fn skip(&mut self, data: &[u8]) {
+        self.scanned += data.len() as u64;
+        let kept = &data[data.len().saturating_sub(CONTEXT_BYTES)..];
+        let excess = (self.recent.len() + kept.len()).saturating_sub(CONTEXT_BYTES);
+        self.recent.drain(..excess);
+        self.recent.extend(kept);
+    }
+
+    /// Feed one byte of filtered output to the matchers
+    ///
+    /// Matchers are checked in priority order, and the first one completing
+    /// on this byte decides the result.
+    fn scan_byte(&mut self, byte: u8) -> MonitorResult {
+        let data = &[byte][..];
+        self.scanned += 1;
+        if self.recent.len() == CONTEXT_BYTES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(byte);
+
+        // Check for output the profile treats as fatal
+        for matcher in &mut self.abort_matchers {
+            if matcher.feed(data) {
                 if self.verbose {
                     eprintln!();
-                    eprintln!("SSHPASS: *** Password prompt detected! ***");
-                    eprintln!(
-                        "SSHPASS: Matched pattern: '{}'",
-                        self.password_matcher.pattern()
+                    logging::emit(
+                        Tone::Failure,
+                        &format!("detected abort pattern '{}'", matcher.pattern()),
                     );
-                    if let Ok(s) = std::str::from_utf8(&filtered_data) {
-                        eprintln!("SSHPASS: In data: {:?}", s);
+                }
+                self.aborted_on = Some(matcher.pattern().to_string());
+                return MonitorResult::Abort;
+            }
+        }
+
+        // Once the change dialog runs, its prompts replace the login prompts
+        if self.password_change.is_some() {
+            return self.handle_password_change(data);
+        }
+
+        // An expired password shows messages and prompts that also contain
+        // the password pattern, so it is checked first
+        if self.expiry_matchers.iter_mut().any(|m| m.feed(data)) {
+            self.held_since = None;
+            if !self.handle_expiry {
+                if self.verbose {
+                    eprintln!();
+                    logging::emit(Tone::Failure, "detected expired password. Exiting.");
+                }
+                return MonitorResult::PasswordExpired;
+            }
+            if self.verbose {
+                eprintln!();
+                crate::diag!("detected expired password, answering the change dialog");
+            }
+            self.password_change = Some(PasswordChange {
+                current: CURRENT_PASSWORD_PATTERNS
+                    .iter()
+                    .copied()
+                    .map(Matcher::new)
+                    .collect(),
+                new: NEW_PASSWORD_PATTERNS
+                    .iter()
+                    .copied()
+                    .map(Matcher::new)
+                    .collect(),
+                new_sent: 0,
+            });
+            return MonitorResult::Continue;
+        }
+
+        // The held-back prompt was not part of an expiry message after all
+        if self.held_since.is_some() && self.expiry_matchers.iter().all(|m| m.current_state() == 0)
+        {
+            self.held_since = None;
+            return self.password_prompt();
+        }
+
+        // Only the prompts a profile answers are left once logged in
+        if self.login_over() {
+            return self.response_prompt(data);
+        }
+
+        // OpenSSH says so before prompting again; no need to wait for the
+        // prompt unless another attempt is allowed
+        if self.ssh_checks
+            && self.password_sent
+            && self.denied_matcher.feed(data)
+            && self.attempts_used_up()
+        {
+            if self.verbose {
+                eprintln!();
+                logging::emit(Tone::Failure, "detected rejected password. Exiting.");
+            }
+            return MonitorResult::IncorrectPassword;
+        }
+
+        // Prompts such as "Enter passphrase (empty for no passphrase):"
+        // contain the pattern twice; ignore the rest of an answered line
+        let prompt_data = if self.skip_prompt_line {
+            self.skip_prompt_line = byte != b'\n';
+            &[][..]
+        } else {
+            data
+        };
+
+        // A second-entry prompt also contains the password pattern, so it is
+        // checked first
+        if self.password_sent {
+            if let Some(mut repeat) = self.repeat_matcher.take() {
+                if repeat.feed(prompt_data) {
+                    self.note_match(repeat.pattern());
+                    self.prompt_match = self.candidate.take();
+                    if self.verbose {
+                        eprintln!();
+                        logging::emit(
+                            Tone::Match,
+                            "*** Repeat prompt detected, sending password again ***",
+                        );
                     }
-                    eprintln!("SSHPASS: Sending password now...");
+                    self.password_matcher.reset();
+                    self.skip_prompt_line = true;
+                    self.password_sent_at = Some(Instant::now());
+                    return MonitorResult::SendPassword;
                 }
-                self.password_sent = true;
-                self.password_matcher.reset();
-                return MonitorResult::SendPassword;
-            } else {
-                // Password prompt appeared again - wrong password
+                self.repeat_matcher = Some(repeat);
+            }
+        }
+
+        // Check for password prompt; an expiry message also contains the
+        // pattern, so wait until it is clear this is not one
+        if self.password_matcher.feed(prompt_data) {
+            let pattern = self.password_matcher.pattern().to_string();
+            self.note_match(&pattern);
+            self.password_matcher.reset();
+            if self.echo_guarded() {
                 if self.verbose {
                     eprintln!();
-                    eprintln!("SSHPASS: *** Password prompt detected again! ***");
-                    eprintln!("SSHPASS: This indicates incorrect password.");
-                    eprintln!("SSHPASS: Terminating...");
+                    crate::diag!("Ignoring the password prompt, it may be the echoed password");
                 }
-                return MonitorResult::IncorrectPassword;
+                return MonitorResult::Continue;
+            }
+            if self.expiry_matchers.iter().any(|m| m.current_state() > 0) {
+                self.held_since.get_or_insert_with(Instant::now);
+                return MonitorResult::Continue;
             }
+            return self.password_prompt();
         }
 
-        // Check for host authentication prompt
-        if self.host_auth_matcher.feed(&filtered_data) {
+        // Check for other prompts the profile answers
+        let response = self.response_prompt(prompt_data);
+        if response != MonitorResult::Continue {
+            return response;
+        }
+
+        // Signs that the login went through
+        if self.password_sent && !self.success_seen {
+            self.success_seen = self.success_matchers.iter_mut().any(|m| m.feed(data));
+        }
+
+        if !self.ssh_checks {
+            return MonitorResult::Continue;
+        }
+
+        // Check for host authentication prompt, or ssh refusing the host
+        // outright under StrictHostKeyChecking=yes
+        let strict_host = self.strict_host_matcher.feed(data);
+        if self.host_auth_matcher.feed(data) || strict_host {
             if self.verbose {
-                eprintln!("SSHPASS: detected host authentication prompt. Exiting.");
+                logging::emit(
+                    Tone::Failure,
+                    "detected host authentication prompt. Exiting.",
+                );
             }
             return MonitorResult::HostKeyUnknown;
         }
 
         // Check for host key changed prompt
-        if self.host_key_changed_matcher.feed(&filtered_data) {
+        if self.host_key_changed_matcher.feed(data) {
             if self.verbose {
-                eprintln!("SSHPASS: detected host key changed prompt. Exiting.");
+                logging::emit(Tone::Failure, "detected host key changed prompt. Exiting.");
             }
             return MonitorResult::HostKeyChanged;
         }
 
+        // Check for security key (sk-*) user presence confirmation
+        if self.confirm_presence_matcher.feed(data) {
+            if self.verbose {
+                crate::diag!("detected security key confirmation prompt, passing it through.");
+            }
+            self.confirm_presence_matcher.reset();
+            self.confirm_started = Some(Instant::now());
+            return MonitorResult::PassThroughPrompt;
+        }
+
+        MonitorResult::Continue
+    }
+
+    /// Feed the prompts the profile answers
+    fn response_prompt(&mut self, data: &[u8]) -> MonitorResult {
+        for (index, matcher) in self.response_matchers.iter_mut().enumerate() {
+            if matcher.feed(data) {
+                if self.verbose {
+                    eprintln!();
+                    logging::emit(
+                        Tone::Match,
+                        &format!("detected profile prompt '{}'", matcher.pattern()),
+                    );
+                }
+                matcher.reset();
+                return MonitorResult::SendResponse(index);
+            }
+        }
+        MonitorResult::Continue
+    }
+
+    /// React to the password prompt
+    fn password_prompt(&mut self) -> MonitorResult {
+        if !self.password_sent {
+            if self.verbose {
+                eprintln!();
+                logging::emit(Tone::Match, "*** Password prompt detected! ***");
+                crate::diag!("Matched pattern: '{}'", self.password_matcher.pattern());
+                crate::diag!("Sending password now...");
+            }
+            self.password_sent = true;
+            self.password_sent_at = Some(Instant::now());
+            self.attempt_key = self.candidate_key();
+            self.attempts.insert(self.attempt_key.clone(), 1);
+            self.prompt_match = self.candidate.take();
+            self.password_prompts = 1;
+            self.skip_prompt_line = self.repeat_matcher.is_some();
+            MonitorResult::SendPassword
+        } else {
+            let reprompt = Reprompt {
+                since_password: self
+                    .password_sent_at
+                    .map_or(Duration::ZERO, |sent| sent.elapsed()),
+                success_seen: self.success_seen,
+            };
+            if !self.reprompt.is_auth_failure(&reprompt) {
+                if self.verbose {
+                    eprintln!();
+                    crate::diag!(
+                        "Password prompt seen again after login ({:?} after the password), leaving it to the user",
+                        reprompt.since_password
+                    );
+                }
+                return MonitorResult::Continue;
+            }
+
+            self.password_prompts += 1;
+            self.attempt_key = self.candidate_key();
+            let attempt = self.attempts.entry(self.attempt_key.clone()).or_insert(0);
+            *attempt += 1;
+            let attempt = *attempt;
+            if attempt <= self.max_attempts {
+                if self.verbose {
+                    eprintln!();
+                    crate::diag!(
+                        "Password prompt seen again, sending password (attempt {} of {})",
+                        attempt,
+                        self.max_attempts
+                    );
+                }
+                self.password_sent_at = Some(Instant::now());
+                self.prompt_match = self.candidate.take();
+                return MonitorResult::SendPassword;
+            }
+
+            // Password prompt appeared again - wrong password
+            if self.verbose {
+                eprintln!();
+                logging::emit(Tone::Failure, "*** Password prompt detected again! ***");
+                crate::diag!("This indicates incorrect password.");
+                crate::diag!("Terminating...");
+            }
+            MonitorResult::IncorrectPassword
+        }
+    }
+
+    /// Answer the prompts of the forced password change dialog
+    fn handle_password_change(&mut self, data: &[u8]) -> MonitorResult {
+        let Some(change) = self.password_change.as_mut() else {
+            return MonitorResult::Continue;
+        };
+
+        if change.current.iter_mut().any(|m| m.feed(data)) {
+            change.current.iter_mut().for_each(Matcher::reset);
+            if self.verbose {
+                crate::diag!("sending current password to the change dialog");
+            }
+            return MonitorResult::SendPassword;
+        }
+
+        if change.new.iter_mut().any(|m| m.feed(data)) {
+            change.new.iter_mut().for_each(Matcher::reset);
+            change.new_sent += 1;
+            if change.new_sent > MAX_NEW_PASSWORD_PROMPTS {
+                if self.verbose {
+                    logging::emit(Tone::Failure, "new password was rejected. Exiting.");
+                }
+                return MonitorResult::PasswordExpired;
+            }
+            if self.verbose {
+                crate::diag!("sending new password to the change dialog");
+            }
+            return MonitorResult::SendNewPassword;
+        }
+
         MonitorResult::Continue
     }
 
@@ -221,11 +1250,20 @@ impl OutputMonitor {
     pub fn password_sent(&self) -> bool {
         self.password_sent
     }
+
+    /// Whether the output since the password shows the login went through:
+    /// a message such as `Last login`, or the quiet time of
+    /// [`set_stop_matching_after_auth`](Self::set_stop_matching_after_auth)
+    /// passed without a new prompt
+    pub fn login_succeeded(&self) -> bool {
+        self.password_sent && (self.success_seen || self.matching_stopped)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_matcher_simple() {
@@ -272,6 +1310,39 @@ mod tests {
         assert_eq!(result, MonitorResult::IncorrectPassword);
     }
 
+    #[test]
+    fn test_output_monitor_prompt_match() {
+        let mut monitor = OutputMonitor::new(None, false);
+        assert_eq!(monitor.prompt_match(), None);
+
+        // Found across reads, with the colour codes filtered out
+        assert_eq!(
+            monitor.handle_output(b"Welcome\r\n\x1b[1muser@host's pass"),
+            MonitorResult::Continue
+        );
+        assert_eq!(
+            monitor.handle_output(b"word:\x1b[0m "),
+            MonitorResult::SendPassword
+        );
+        let found = monitor.prompt_match().unwrap();
+        assert_eq!(found.pattern, "assword");
+        assert_eq!(found.offset, "Welcome\nuser@host's p".len() as u64);
+        assert_eq!(found.context, b"Welcome\nuser@host's password");
+
+        // The context is the output just before the match
+        let mut monitor = OutputMonitor::new(None, false);
+        let banner = vec![b'x'; 200];
+        monitor.handle_output(&banner);
+        assert_eq!(
+            monitor.handle_output(b"\nPassword:"),
+            MonitorResult::SendPassword
+        );
+        let found = monitor.prompt_match().unwrap();
+        assert_eq!(found.offset, 202);
+        assert_eq!(found.context.len(), CONTEXT_BYTES);
+        assert!(found.context.ends_with(b"x\nPassword"));
+    }
+
     #[test]
     fn test_output_monitor_host_auth() {
         let mut monitor = OutputMonitor::new(None, false);
@@ -281,6 +1352,19 @@ mod tests {
         assert_eq!(result, MonitorResult::HostKeyUnknown);
     }
 
+    #[test]
+    fn test_output_monitor_strict_host_key_checking() {
+        let mut monitor = OutputMonitor::new(None, false);
+        let result = monitor.handle_output(
+            b"No ED25519 host key is known for h and you have requested strict checking.\r\n",
+        );
+        assert_eq!(result, MonitorResult::HostKeyUnknown);
+
+        let mut monitor = OutputMonitor::new(None, false);
+        let result = monitor.handle_ssh_log(b"you have requested strict checking.\n");
+        assert_eq!(result, MonitorResult::HostKeyUnknown);
+    }
+
     #[test]
     fn test_output_monitor_host_key_changed() {
         let mut monitor = OutputMonitor::new(None, false);
@@ -289,4 +1373,596 @@ mod tests {
             monitor.handle_output(b"WARNING: The key differs from the key for the IP address");
         assert_eq!(result, MonitorResult::HostKeyChanged);
     }
+
+    #[test]
+    fn test_output_monitor_security_key_confirmation() {
+        let mut monitor = OutputMonitor::new(None, false);
+
+        let result =
+            monitor.handle_output(b"Confirm user presence for key ED25519-SK SHA256:abc\r\n");
+        assert_eq!(result, MonitorResult::PassThroughPrompt);
+        assert!(!monitor.password_sent());
+        assert_eq!(monitor.confirmation_remaining(), None);
+
+        let result = monitor.handle_output(b"User presence confirmed\r\n");
+        assert_eq!(result, MonitorResult::Continue);
+    }
+
+    #[test]
+    fn test_output_monitor_keygen_double_entry() {
+        let mut monitor = OutputMonitor::new(Some("passphrase"), false);
+        monitor.set_repeat_prompt(Some("same passphrase"));
+
+        // The rest of the first prompt arrives in a separate read
+        let result = monitor.handle_output(b"Enter passphrase (empty for no ");
+        assert_eq!(result, MonitorResult::SendPassword);
+        let result = monitor.handle_output(b"passphrase): ");
+        assert_eq!(result, MonitorResult::Continue);
+
+        let result = monitor.handle_output(b"\r\nEnter same passphrase again: ");
+        assert_eq!(result, MonitorResult::SendPassword);
+
+        // Only one confirmation is answered
+        let result = monitor.handle_output(b"\r\nEnter same passphrase again: ");
+        assert_eq!(result, MonitorResult::IncorrectPassword);
+    }
+
+    #[test]
+    fn test_output_monitor_ssh_add_bad_passphrase() {
+        let mut monitor = OutputMonitor::new(Some("passphrase"), false);
+        monitor.set_repeat_prompt(Some("same passphrase"));
+
+        let result = monitor.handle_output(b"Enter passphrase for /home/u/.ssh/id_ed25519: ");
+        assert_eq!(result, MonitorResult::SendPassword);
+
+        let result =
+            monitor.handle_output(b"\r\nBad passphrase, try again for /home/u/.ssh/id_ed25519: ");
+        assert_eq!(result, MonitorResult::IncorrectPassword);
+    }
+
+    #[test]
+    fn test_output_monitor_profile_responses_and_abort() {
+        let mut monitor = OutputMonitor::new(Some("assword:"), false);
+        monitor.set_responses(["Username:"]);
+        monitor.set_abort_patterns(["% Authorization failed"]);
+
+        let result = monitor.handle_output(b"User Access Verification\r\nUsername: ");
+        assert_eq!(result, MonitorResult::SendResponse(0));
+
+        let result = monitor.handle_output(b"\r\nPassword: ");
+        assert_eq!(result, MonitorResult::SendPassword);
+
+        // A banner that must be acknowledged before the prompt
+        monitor.add_response("Press any key");
+        let result = monitor.handle_output(b"\r\nPress any key to continue");
+        assert_eq!(result, MonitorResult::SendResponse(1));
+
+        let result = monitor.handle_output(b"\r\n% Authorization failed.\r\n");
+        assert_eq!(result, MonitorResult::Abort);
+        assert_eq!(monitor.aborted_on(), Some("% Authorization failed"));
+    }
+
+    #[test]
+    fn test_output_monitor_prompt_timeout() {
+        let mut monitor = OutputMonitor::new(None, false);
+        assert!(!monitor.prompt_timed_out());
+
+        monitor.set_prompt_timeout(Some(Duration::ZERO));
+        assert!(monitor.prompt_timed_out());
+        assert_eq!(monitor.wait_timeout(), Some(Duration::ZERO));
+
+        // The timeout only covers the wait for the password prompt
+        monitor.handle_output(b"password: ");
+        assert!(!monitor.prompt_timed_out());
+        assert_eq!(monitor.wait_timeout(), None);
+    }
+
+    #[test]
+    fn test_output_monitor_password_expired() {
+        let mut monitor = OutputMonitor::new(None, false);
+        assert_eq!(
+            monitor.handle_output(b"user@host's password: "),
+            MonitorResult::SendPassword
+        );
+
+        // "Current password:" must not be mistaken for a second prompt
+        let result = monitor.handle_output(
+            b"\r\nWARNING: Your password has expired.\r\n\
+              You must change your password now and login again!\r\n\
+              Current password: ",
+        );
+        assert_eq!(result, MonitorResult::PasswordExpired);
+    }
+
+    #[test]
+    fn test_output_monitor_password_change_dialog() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_expiry_handling(true);
+        monitor.handle_output(b"password: ");
+
+        let result = monitor.handle_output(
+            b"\r\nWARNING: Your password has expired.\r\n\
+              You must change your password now and login again!\r\n\
+              Changing password for user.\r\nCurrent password: ",
+        );
+        assert_eq!(result, MonitorResult::SendPassword);
+        assert!(!monitor.password_changed());
+
+        let result = monitor.handle_output(b"\r\nNew password: ");
+        assert_eq!(result, MonitorResult::SendNewPassword);
+        let result = monitor.handle_output(b"\r\nRetype new password: ");
+        assert_eq!(result, MonitorResult::SendNewPassword);
+        assert!(monitor.password_changed());
+
+        // A third prompt means the new password was not accepted
+        let result = monitor.handle_output(b"\r\nBAD PASSWORD\r\nNew password: ");
+        assert_eq!(result, MonitorResult::PasswordExpired);
+    }
+
+    #[test]
+    fn test_output_monitor_confirmation_timeout() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_confirm_timeout(Some(Duration::ZERO));
+        assert!(!monitor.confirmation_expired());
+
+        monitor.handle_output(b"Confirm user presence for key ECDSA-SK SHA256:abc\r\n");
+        assert!(monitor.confirmation_expired());
+
+        // Output after the prompt clears the pending wait
+        monitor.handle_output(b"User presence confirmed\r\n");
+        assert!(!monitor.confirmation_expired());
+    }
+
+    #[test]
+    fn test_output_monitor_several_prompts_in_one_read() {
+        let mut monitor = OutputMonitor::new(Some("assword:"), false);
+        monitor.set_responses(["Username:"]);
+
+        let result = monitor.handle_output(b"Username: \r\nPassword: ");
+        assert_eq!(result, MonitorResult::SendResponse(0));
+        assert_eq!(monitor.next_result(), MonitorResult::SendPassword);
+        assert_eq!(monitor.next_result(), MonitorResult::Continue);
+    }
+
+    #[test]
+    fn test_output_monitor_holds_prompt_inside_expiry_message() {
+        let mut monitor = OutputMonitor::new(None, false);
+
+        // "password " could still become "password has expired"
+        assert_eq!(
+            monitor.handle_output(b"Enter password "),
+            MonitorResult::Continue
+        );
+        assert!(!monitor.hold_expired());
+        assert!(monitor.wait_timeout().is_some_and(|t| t <= PROMPT_HOLD));
+        assert_eq!(monitor.finish(), MonitorResult::SendPassword);
+        assert_eq!(monitor.finish(), MonitorResult::Continue);
+
+        // More output settles it without waiting
+        let mut monitor = OutputMonitor::new(None, false);
+        assert_eq!(monitor.handle_output(b"password"), MonitorResult::Continue);
+        assert_eq!(monitor.handle_output(b": "), MonitorResult::SendPassword);
+        assert_eq!(monitor.wait_timeout(), None);
+    }
+
+    #[test]
+    fn test_output_monitor_reauth_window() {
+        let logged_in = || {
+            let mut monitor = OutputMonitor::new(None, false);
+            monitor.set_reprompt_heuristic(ReauthWindow::new(Duration::from_secs(60)));
+            monitor.handle_output(b"user@host's password: ");
+            monitor
+        };
+
+        // A re-prompt right after the password is still a rejection
+        let result = logged_in()
+            .handle_output(b"\r\nPermission denied, please try again.\r\nuser@host's password: ");
+        assert_eq!(result, MonitorResult::IncorrectPassword);
+
+        // sudo asking after a successful login is left to the user
+        let result = logged_in().handle_output(
+            b"\r\nLast login: Mon Jan  1 00:00:00 2024\r\n$ sudo ls\r\n[sudo] password for user: ",
+        );
+        assert_eq!(result, MonitorResult::Continue);
+    }
+
+    #[test]
+    fn test_output_monitor_echo_guard() {
+        assert!(prompt_in_password("assword", b"MyPassword1"));
+        assert!(!prompt_in_password("assword", b"ASSWORD"));
+        assert!(!prompt_in_password("", b"hunter2"));
+
+        // The echoed password holds the pattern but is not a second prompt
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_echo_guard(Some(Duration::from_secs(60)));
+        monitor.handle_output(b"Password: ");
+        assert_eq!(
+            monitor.handle_output(b"MyPassword1\r\n"),
+            MonitorResult::Continue
+        );
+        // ssh's rejection message is still seen
+        assert_eq!(
+            monitor.handle_output(b"Permission denied, please try again.\r\n"),
+            MonitorResult::IncorrectPassword
+        );
+
+        // Once the window has passed, the prompt counts again
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_echo_guard(Some(Duration::ZERO));
+        monitor.handle_output(b"Password: ");
+        assert_eq!(
+            monitor.handle_output(b"MyPassword1\r\n"),
+            MonitorResult::IncorrectPassword
+        );
+    }
+
+    #[test]
+    fn test_output_monitor_stop_matching_after_auth() {
+        // After the login, a prompt in the remote output is not answered
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_stop_matching_after_auth(Some(Duration::from_secs(60)));
+        monitor.handle_output(b"Password: ");
+        monitor.handle_output(b"\r\nLast login: Mon Jan  1 00:00:00 2024\r\n");
+        assert_eq!(
+            monitor.handle_output(b"$ grep Password /etc/ssh/sshd_config\r\nPasswordAuthentication yes\r\nThe authenticity of host "),
+            MonitorResult::Continue
+        );
+
+        // Without a success message, only a quiet period ends the login
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_stop_matching_after_auth(Some(Duration::from_secs(60)));
+        monitor.handle_output(b"Password: ");
+        assert_eq!(
+            monitor.handle_output(b"\r\nPassword: "),
+            MonitorResult::IncorrectPassword
+        );
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_stop_matching_after_auth(Some(Duration::ZERO));
+        monitor.handle_output(b"Password: ");
+        assert_eq!(
+            monitor.handle_output(b"\r\nPassword: "),
+            MonitorResult::Continue
+        );
+    }
+
+    #[test]
+    fn test_output_monitor_permission_denied() {
+        let mut monitor = OutputMonitor::new(None, false);
+        assert_eq!(
+            monitor.handle_output(b"Permission denied, please try again.\r\n"),
+            MonitorResult::Continue
+        );
+        monitor.handle_output(b"user@host's password: ");
+
+        // Reported before the next prompt arrives
+        assert_eq!(
+            monitor.handle_output(b"\r\nPermission denied, please try again.\r\n"),
+            MonitorResult::IncorrectPassword
+        );
+    }
+
+    #[test]
+    fn test_output_monitor_without_ssh_checks() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_ssh_checks(false);
+        assert_eq!(
+            monitor.handle_output(b"[sudo] password for user: "),
+            MonitorResult::SendPassword
+        );
+        for output in [
+            &b"The authenticity of host 'example.com' can't be established.\r\n"[..],
+            b"WARNING: The key differs from the key for the IP address\r\n",
+            b"Permission denied, please try again.\r\n",
+            b"Confirm user presence for key\r\n",
+        ] {
+            assert_eq!(monitor.handle_output(output), MonitorResult::Continue);
+        }
+    }
+
+    #[test]
+    fn test_output_monitor_without_ansi_filter() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_ansi_filter(false);
+        // Raw bytes: an escape sequence inside the prompt hides it
+        assert_eq!(
+            monitor.handle_output(b"Pa\x1b[1mssword: "),
+            MonitorResult::Continue
+        );
+        assert_eq!(
+            monitor.handle_output(b"\r\nPassword: "),
+            MonitorResult::SendPassword
+        );
+        // Offsets count the raw output
+        assert_eq!(monitor.prompt_match().unwrap().offset, 17);
+    }
+
+    #[test]
+    fn test_output_monitor_max_attempts() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_max_attempts(2);
+        assert_eq!(monitor.password_prompts(), 0);
+
+        assert_eq!(
+            monitor.handle_output(b"user@host's password: "),
+            MonitorResult::SendPassword
+        );
+        assert_eq!(
+            monitor.handle_output(b"\r\nPermission denied, please try again.\r\n"),
+            MonitorResult::Continue
+        );
+        assert_eq!(
+            monitor.handle_output(b"user@host's password: "),
+            MonitorResult::SendPassword
+        );
+        assert_eq!(monitor.password_prompts(), 2);
+
+        assert_eq!(
+            monitor.handle_output(b"\r\nPermission denied, please try again.\r\n"),
+            MonitorResult::IncorrectPassword
+        );
+    }
+
+    #[test]
+    fn test_output_monitor_max_attempts_per_prompt() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_attempt_key(|line| {
+            line.split_once('@')
+                .map_or("", |(_, host)| host.trim_end_matches("'s password: "))
+                .to_string()
+        });
+
+        // The bastion and then the host each get their one attempt
+        assert_eq!(
+            monitor.handle_output(b"gate@outer's password: "),
+            MonitorResult::SendPassword
+        );
+        assert_eq!(
+            monitor.handle_output(b"\r\ndba@db1's password: "),
+            MonitorResult::SendPassword
+        );
+        assert_eq!(monitor.password_prompts(), 2);
+        // A host rejecting its password is not retried on the bastion's count
+        assert_eq!(
+            monitor.handle_output(b"\r\nPermission denied, please try again.\r\n"),
+            MonitorResult::IncorrectPassword
+        );
+
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_attempt_key(|line| line.split("'s").next().unwrap_or_default().to_string());
+        assert_eq!(
+            monitor.handle_output(b"gate@outer's password: "),
+            MonitorResult::SendPassword
+        );
+        assert_eq!(
+            monitor.handle_output(b"\r\ngate@outer's password: "),
+            MonitorResult::IncorrectPassword
+        );
+    }
+
+    #[test]
+    fn test_output_monitor_ssh_log() {
+        let mut monitor = OutputMonitor::new(None, false);
+        assert_eq!(
+            monitor.handle_ssh_log(b"Permission denied, please try again.\r\n"),
+            MonitorResult::Continue
+        );
+        monitor.handle_output(b"user@host's password: ");
+        assert_eq!(
+            monitor.handle_ssh_log(b"Permission denied, please "),
+            MonitorResult::Continue
+        );
+        assert_eq!(
+            monitor.handle_ssh_log(b"try again.\r\n"),
+            MonitorResult::IncorrectPassword
+        );
+
+        let mut monitor = OutputMonitor::new(None, false);
+        assert_eq!(
+            monitor.handle_ssh_log(
+                b"The ECDSA host key for h differs from the key for the IP address"
+            ),
+            MonitorResult::HostKeyChanged
+        );
+    }
+
+    #[test]
+    fn test_output_monitor_custom_reprompt_heuristic() {
+        let mut monitor = OutputMonitor::new(None, false);
+        let mut seen = Vec::new();
+        monitor.set_reprompt_heuristic(move |reprompt: &Reprompt| {
+            seen.push(*reprompt);
+            seen.len() > 1
+        });
+        monitor.handle_output(b"password: ");
+        assert_eq!(
+            monitor.handle_output(b"\r\npassword: "),
+            MonitorResult::Continue
+        );
+        assert_eq!(
+            monitor.handle_output(b"\r\npassword: "),
+            MonitorResult::IncorrectPassword
+        );
+    }
+
+    /// Answers "Token:" and gives up on "locked", one chunk at a time
+    struct TokenDetector;
+
+    impl Detector for TokenDetector {
+        fn name(&self) -> &str {
+            "token"
+        }
+
+        fn feed(&mut self, data: &[u8]) -> Detection {
+            let text = String::from_utf8_lossy(data);
+            if text.contains("Token:") {
+                Detection::Reply(b"123456\r".to_vec())
+            } else if text.contains("Secret:") {
+                Detection::PasswordPrompt
+            } else if text.contains("locked") {
+                Detection::Abort("account locked".to_string())
+            } else {
+                Detection::Nothing
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_monitor_detector() {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.add_detector(TokenDetector);
+
+        assert_eq!(
+            monitor.handle_output(b"Token: "),
+            MonitorResult::SendDetectorReply
+        );
+        assert_eq!(monitor.take_detector_reply(), b"123456\r");
+        assert_eq!(monitor.next_result(), MonitorResult::Continue);
+
+        // A reply leaves the chunk to the built-in matchers
+        assert_eq!(
+            monitor.handle_output(b"Token: ok\r\nPassword: "),
+            MonitorResult::SendDetectorReply
+        );
+        monitor.take_detector_reply();
+        assert_eq!(monitor.next_result(), MonitorResult::SendPassword);
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.add_detector(TokenDetector);
+
+        // The detector's prompt counts as the password prompt, and the
+        // chunk is not scanned again by the built-in matcher
+        assert_eq!(
+            monitor.handle_output(b"Secret (password): "),
+            MonitorResult::SendPassword
+        );
+        assert_eq!(monitor.next_result(), MonitorResult::Continue);
+        assert!(monitor.password_sent());
+        assert_eq!(monitor.prompt_match().unwrap().pattern, "assword");
+
+        assert_eq!(
+            monitor.handle_output(b"\r\nAccount locked\r\n"),
+            MonitorResult::Abort
+        );
+        assert_eq!(monitor.aborted_on(), Some("account locked (token)"));
+
+        // A prompt only the detector knows is recorded as its match
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.add_detector(TokenDetector);
+        monitor.handle_output(b"Hello\r\n");
+        assert_eq!(
+            monitor.handle_output(b"Secret: "),
+            MonitorResult::SendPassword
+        );
+        let found = monitor.prompt_match().unwrap();
+        assert_eq!(found.pattern, "detector token");
+        assert_eq!(found.offset, 6);
+        assert_eq!(found.context, b"Secret: ");
+    }
+
+    /// Output fragments that exercise every matcher, the ANSI filter and
+    /// line ending normalization
+    const FRAGMENTS: &[&[u8]] = &[
+        b"user@host's password: ",
+        b"Password: ",
+        b"Permission denied, please try again.",
+        b"Your password has expired.",
+        b"You must change your password now",
+        b"Current password: ",
+        b"New password: ",
+        b"Enter same passphrase again: ",
+        b"The authenticity of host 'h' can't be established.",
+        b"WARNING: key differs from the key for the IP address",
+        b"Confirm user presence for key ED25519-SK",
+        b"Username: ",
+        b"% Authorization failed",
+        b"pass",
+        b"word",
+        b"\x1b[1m",
+        b"\x1b[0m",
+        b"\x1b]0;title\x07",
+        b"\xc3\xa9",
+        b"\r\n",
+        b"\r",
+        b"\n",
+    ];
+
+    fn output_stream() -> impl Strategy<Value = Vec<u8>> {
+        let fragment = prop_oneof![
+            3 => proptest::sample::select(FRAGMENTS).prop_map(<[u8]>::to_vec),
+            1 => proptest::collection::vec(any::<u8>(), 0..8),
+        ];
+        proptest::collection::vec(fragment, 0..24).prop_map(|parts| parts.concat())
+    }
+
+    fn configured_monitor(handle_expiry: bool, profile: bool) -> OutputMonitor {
+        let mut monitor = OutputMonitor::new(None, false);
+        monitor.set_expiry_handling(handle_expiry);
+        if profile {
+            monitor.set_repeat_prompt(Some("same passphrase"));
+            monitor.set_responses(["Username:"]);
+            monitor.set_abort_patterns(["% Authorization failed"]);
+        }
+        monitor
+    }
+
+    /// Feed `chunks` one read at a time, collecting every event reported
+    fn collect_events(monitor: &mut OutputMonitor, chunks: &[&[u8]]) -> Vec<MonitorResult> {
+        let mut events = Vec::new();
+        for chunk in chunks {
+            let mut result = monitor.handle_output(chunk);
+            while result != MonitorResult::Continue {
+                events.push(result);
+                result = monitor.next_result();
+            }
+        }
+        match monitor.finish() {
+            MonitorResult::Continue => {}
+            result => events.push(result),
+        }
+        events
+    }
+
+    proptest! {
+        #[test]
+        fn test_output_monitor_chunk_split_invariance(
+            stream in output_stream(),
+            cuts in proptest::collection::vec(any::<proptest::sample::Index>(), 0..8),
+            handle_expiry in any::<bool>(),
+            profile in any::<bool>(),
+        ) {
+            let mut cuts: Vec<usize> = cuts.iter().map(|i| i.index(stream.len() + 1)).collect();
+            cuts.sort_unstable();
+            let chunks: Vec<&[u8]> = std::iter::once(0)
+                .chain(cuts.iter().copied())
+                .zip(cuts.iter().copied().chain(std::iter::once(stream.len())))
+                .map(|(start, end)| &stream[start..end])
+                .collect();
+
+            let whole = collect_events(&mut configured_monitor(handle_expiry, profile), &[&stream]);
+            let split = collect_events(&mut configured_monitor(handle_expiry, profile), &chunks);
+            prop_assert_eq!(whole, split);
+        }
+
+        #[test]
+        fn test_output_monitor_prefilter_invariance(
+            stream in output_stream(),
+            handle_expiry in any::<bool>(),
+            profile in any::<bool>(),
+            stop_matching in any::<bool>(),
+        ) {
+            let mut skipping = configured_monitor(handle_expiry, profile);
+            let mut scanning = configured_monitor(handle_expiry, profile);
+            scanning.prefilter = false;
+            if stop_matching {
+                // Only the profile's prompts are left once the password is sent
+                skipping.set_stop_matching_after_auth(Some(Duration::ZERO));
+                scanning.set_stop_matching_after_auth(Some(Duration::ZERO));
+            }
+            let skipped = collect_events(&mut skipping, &[&stream]);
+            let scanned = collect_events(&mut scanning, &[&stream]);
+            prop_assert_eq!(skipped, scanned);
+            prop_assert_eq!(skipping.prompt_match(), scanning.prompt_match());
+            prop_assert_eq!(skipping.scanned, scanning.scanned);
+            prop_assert_eq!(&skipping.recent, &scanning.recent);
+        }
+    }
 }
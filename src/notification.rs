@@ -0,0 +1,176 @@
+//! Desktop notifications (`--notify`)
+//!
+//! A provisioning job left running in a background terminal can stall on a
+//! prompt nobody sees. With `--notify`, sshpass sends a desktop notification
+//! (D-Bus on Linux and the BSDs, Notification Center on macOS, a toast on
+//! Windows) when the login fails, and when ssh has been waiting longer than
+//! `--notify-after` seconds for a security key to be touched.
+
+use crate::error::SshpassError;
+use crate::monitor::{Matcher, CONFIRM_PRESENCE_PATTERN};
+use crate::session::{SessionEvent, SessionObserver};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Default for `--notify-after`, in seconds
+pub const DEFAULT_NOTIFY_AFTER_SECS: u64 = 30;
+
+type Deliver = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Notifies the desktop about a session
+pub struct Notifier {
+    host: String,
+    after: Duration,
+    send: Deliver,
+    presence: Matcher,
+    timer: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Notifier {
+    /// Notify about the session with `host`, the `after` wait for a security
+    /// key included
+    pub fn new(host: Option<String>, after: Duration, verbose: bool) -> Self {
+        let send: Deliver = Arc::new(move |body: &str| {
+            let shown = notify_rust::Notification::new()
+                .appname("sshpass")
+                .summary("sshpass")
+                .body(body)
+                .show();
+            if let Err(e) = shown {
+                if verbose {
+                    crate::diag!("Cannot show a desktop notification: {}", e);
+                }
+            }
+        });
+        Self::with_sender(host, after, send)
+    }
+
+    fn with_sender(host: Option<String>, after: Duration, send: Deliver) -> Self {
+        Self {
+            host: host.unwrap_or_else(|| "the server".to_string()),
+            after,
+            send,
+            presence: Matcher::new(CONFIRM_PRESENCE_PATTERN),
+            timer: None,
+        }
+    }
+
+    /// Notify unless the prompt is answered within `after`
+    fn start_timer(&mut self) {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let after = self.after;
+        let send = Arc::clone(&self.send);
+        let body = format!(
+            "Waiting for you to touch the security key for {}",
+            self.host
+        );
+        let timer = std::thread::Builder::new()
+            .name("sshpass-notify".to_string())
+            .spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(after) {
+                    send(&body);
+                }
+            });
+        if let Ok(timer) = timer {
+            self.timer = Some((stop, timer));
+        }
+    }
+
+    fn stop_timer(&mut self) {
+        if let Some((stop, timer)) = self.timer.take() {
+            drop(stop);
+            let _ = timer.join();
+        }
+    }
+}
+
+impl SessionObserver for Notifier {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        match event {
+            SessionEvent::Output(data) => {
+                // Anything ssh prints after the prompt means it was answered
+                self.stop_timer();
+                if self.presence.feed(data) {
+                    self.presence.reset();
+                    self.start_timer();
+                }
+            }
+            SessionEvent::Error(e) => {
+                self.stop_timer();
+                if let Some(reason) = login_failure(e) {
+                    (self.send)(&format!("Login to {} failed: {}", self.host, reason));
+                }
+            }
+            SessionEvent::Status(_) => self.stop_timer(),
+        }
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.stop_timer();
+    }
+}
+
+/// Why the login failed, for errors that mean it did
+fn login_failure(error: &SshpassError) -> Option<String> {
+    match error {
+        SshpassError::IncorrectPassword
+        | SshpassError::HostKeyUnknown
+        | SshpassError::HostKeyChanged
+        | SshpassError::PromptNotSeen
+        | SshpassError::PasswordExpired
+        | SshpassError::LockedOut { .. } => Some(error.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SessionStatus;
+    use std::sync::Mutex;
+
+    fn recording_notifier(after: Duration) -> (Notifier, Arc<Mutex<Vec<String>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&sent);
+        let send: Deliver = Arc::new(move |body: &str| log.lock().unwrap().push(body.to_string()));
+        let notifier = Notifier::with_sender(Some("db1".to_string()), after, send);
+        (notifier, sent)
+    }
+
+    #[test]
+    fn test_notify_login_failure() {
+        let (mut notifier, sent) = recording_notifier(Duration::from_secs(60));
+        notifier.on_event(SessionEvent::Error(&SshpassError::RuntimeError(
+            "pselect error".to_string(),
+        )));
+        notifier.on_event(SessionEvent::Error(&SshpassError::IncorrectPassword));
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec!["Login to db1 failed: Incorrect password provided"]
+        );
+    }
+
+    #[test]
+    fn test_notify_presence_wait() {
+        let (mut notifier, sent) = recording_notifier(Duration::from_millis(10));
+        notifier.on_event(SessionEvent::Output(b"Confirm user presence "));
+        notifier.on_event(SessionEvent::Output(b"for key ED25519-SK SHA256:abc\r\n"));
+        std::thread::sleep(Duration::from_millis(200));
+        notifier.on_event(SessionEvent::Status(SessionStatus::Exited(0)));
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec!["Waiting for you to touch the security key for db1"]
+        );
+
+        // Touched in time
+        let (mut notifier, sent) = recording_notifier(Duration::from_secs(60));
+        notifier.on_event(SessionEvent::Output(b"Confirm user presence for key\r\n"));
+        notifier.on_event(SessionEvent::Output(b"User presence confirmed\r\n"));
+        drop(notifier);
+        assert!(sent.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+//! Where forwarded output goes (`--output`)
+//!
+//! Once the password is typed, the event loop passes what the child prints
+//! to an [`OutputSink`]: sshpass's stdout unless told otherwise. The binary
+//! builds the sinks from `--output` (repeatable):
+//!
+//! | Target | Sink |
+//! |--------|------|
+//! | `stdout` | sshpass's stdout, the default |
+//! | `null` | nothing; the output is only seen by `--tee` and observers |
+//! | `fd:N` | a descriptor the caller left open (Unix) |
+//! | `unix:PATH` | a connection to a Unix socket (Unix) |
+//! | `tcp:HOST:PORT` | a TCP connection |
+//! | anything else | a file, created or truncated with mode 0600 on Unix |
+//!
+//! Library callers pass their own, and a closure taking `&[u8]` is a sink.
+//! Copies that follow the whole session, such as `--tee` and recordings,
+//! remain [`SessionObserver`](crate::session::SessionObserver)s; sinks only
+//! decide where the forwarded output is written. A sink whose reader goes
+//! away is handled per `--on-broken-pipe`, like stdout.
+
+use crate::broken_pipe;
+use crate::error::{Result, SshpassError};
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+
+/// Receives the output the event loop forwards
+pub trait OutputSink: Send {
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+}
+
+impl<F> OutputSink for F
+where
+    F: FnMut(&[u8]) -> Result<()> + Send,
+{
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self(data)
+    }
+}
+
+/// Every sink in turn, stopping at the first error
+impl OutputSink for Vec<Box<dyn OutputSink>> {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        for sink in self.iter_mut() {
+            sink.write(data)?;
+        }
+        Ok(())
+    }
+}
+
+/// sshpass's stdout
+#[derive(Debug, Default)]
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        broken_pipe::write_stdout(data)
+    }
+}
+
+/// Drops the output
+#[derive(Debug, Default)]
+pub struct Null;
+
+impl OutputSink for Null {
+    fn write(&mut self, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A file, descriptor or socket
+pub struct Stream {
+    name: String,
+    writer: Box<dyn Write + Send>,
+    closed: AtomicBool,
+}
+
+impl Stream {
+    /// Write to `writer`, called `name` in errors
+    pub fn new(name: impl Into<String>, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            name: name.into(),
+            writer: Box::new(writer),
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl OutputSink for Stream {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        broken_pipe::write_to(&mut self.writer, data, broken_pipe::mode(), &self.closed)
+            .map_err(|_| SshpassError::RuntimeError(format!("Output {} was closed", self.name)))
+    }
+}
+
+/// Open the sink an `--output` target names
+pub fn open(spec: &str) -> Result<Box<dyn OutputSink>> {
+    let failed = |e: std::io::Error| {
+        SshpassError::InvalidArguments(format!("Failed to open --output {}: {}", spec, e))
+    };
+    match spec {
+        "stdout" => return Ok(Box::new(Stdout)),
+        "null" => return Ok(Box::new(Null)),
+        _ => {}
+    }
+
+    #[cfg(unix)]
+    if let Some(fd) = spec.strip_prefix("fd:") {
+        let file = crate::tee::take_fd(fd, spec, "--output")?;
+        return Ok(Box::new(Stream::new(spec, file)));
+    }
+    #[cfg(unix)]
+    if let Some(path) = spec.strip_prefix("unix:") {
+        let socket = std::os::unix::net::UnixStream::connect(path).map_err(failed)?;
+        return Ok(Box::new(Stream::new(spec, socket)));
+    }
+    if let Some(address) = spec.strip_prefix("tcp:") {
+        let socket = std::net::TcpStream::connect(address).map_err(failed)?;
+        socket.set_nodelay(true).map_err(failed)?;
+        return Ok(Box::new(Stream::new(spec, socket)));
+    }
+
+    let file = crate::tee::create_file(spec.as_ref()).map_err(failed)?;
+    Ok(Box::new(Stream::new(spec, file)))
+}
+
+/// The sinks for the `--output` targets, stdout when there are none
+pub fn open_all(specs: &[String]) -> Result<Box<dyn OutputSink>> {
+    match specs {
+        [] => Ok(Box::new(Stdout)),
+        [spec] => open(spec),
+        specs => {
+            let sinks = specs
+                .iter()
+                .map(|spec| open(spec))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Box::new(sinks))
+        }
+    }
+}
+
+/// Whether the targets send the output anywhere but sshpass's stdout
+pub fn redirects(specs: &[String]) -> bool {
+    specs.iter().any(|spec| spec != "stdout")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callback_and_fanout() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let copy = std::sync::Arc::clone(&seen);
+        let mut sinks: Vec<Box<dyn OutputSink>> = vec![
+            Box::new(Null),
+            Box::new(move |data: &[u8]| {
+                copy.lock().unwrap().extend_from_slice(data);
+                Ok(())
+            }),
+        ];
+        sinks.write(b"hello ").unwrap();
+        sinks.write(b"world").unwrap();
+        assert_eq!(*seen.lock().unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_file_target() {
+        let path = std::env::temp_dir().join(format!("sshpass-output-{}", std::process::id()));
+        let spec = path.to_str().unwrap().to_string();
+        let mut sink = open_all(&[spec.clone(), "null".to_string()]).unwrap();
+        sink.write(b"output\r\n").unwrap();
+        drop(sink);
+        assert_eq!(std::fs::read(&path).unwrap(), b"output\r\n");
+        let _ = std::fs::remove_file(&path);
+
+        assert!(redirects(&[spec]));
+        assert!(!redirects(&["stdout".to_string()]));
+        assert!(!redirects(&[]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_targets() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut sink = open(&format!("tcp:{}", address)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        sink.write(b"over tcp").unwrap();
+        drop(sink);
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"over tcp");
+
+        let path = std::env::temp_dir().join(format!("sshpass-output-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let mut sink = open(&format!("unix:{}", path.display())).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        sink.write(b"over a socket").unwrap();
+        drop(sink);
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"over a socket");
+        let _ = std::fs::remove_file(&path);
+
+        assert!(matches!(
+            open("unix:/nonexistent/sshpass.sock"),
+            Err(SshpassError::InvalidArguments(_))
+        ));
+    }
+}
@@ -0,0 +1,303 @@
+//! Cleanup after an internal error (`--on-panic`)
+//!
+//! A bug in sshpass must not leave the user's terminal in raw mode with ssh
+//! still attached to it. [`install`] remembers the terminal modes of the
+//! standard handles and sets a panic hook which, on the first panic in any
+//! thread:
+//!
+//! * terminates the child registered with [`watch_child`], or leaves it
+//!   running with `--on-panic detach`,
+//! * puts the terminal modes back if they were changed (termios on Unix, the
+//!   console modes on Windows), and
+//! * prints a report with the version, thread, location and message in
+//!   place of Rust's default panic message.
+//!
+//! The hook runs before unwinding starts, so this also holds for builds with
+//! `panic = "abort"` such as the `minimal` profile.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+/// `--on-panic` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ChildPolicy {
+    /// Terminate the child
+    #[default]
+    Kill,
+    /// Leave the child running
+    Detach,
+}
+
+struct State {
+    policy: ChildPolicy,
+    terminal: Option<terminal::Modes>,
+    child: Option<u32>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    policy: ChildPolicy::Kill,
+    terminal: None,
+    child: None,
+});
+
+/// Set once the cleanup has run; later panics only print their report
+static CLEANED_UP: AtomicBool = AtomicBool::new(false);
+
+/// Remember the terminal modes and install the panic hook
+///
+/// Call before the child is spawned, while the terminal is as the user left
+/// it.
+pub fn install(policy: ChildPolicy) {
+    {
+        let mut state = lock_state();
+        state.policy = policy;
+        state.terminal = terminal::save();
+    }
+    std::panic::set_hook(Box::new(|info| {
+        let message = payload_message(info.payload());
+        let location = info.location().map(ToString::to_string);
+        let child = if CLEANED_UP.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            clean_up()
+        };
+        let backtrace = std::backtrace::Backtrace::capture();
+        let backtrace = match backtrace.status() {
+            std::backtrace::BacktraceStatus::Captured => Some(backtrace.to_string()),
+            _ => None,
+        };
+        eprint!(
+            "{}",
+            report(
+                message,
+                location.as_deref(),
+                std::thread::current().name().unwrap_or("<unnamed>"),
+                child.as_deref(),
+                backtrace.as_deref(),
+            )
+        );
+    }));
+}
+
+/// Registration of the child, cleared when dropped
+#[must_use = "the child is only watched while the guard is alive"]
+pub struct ChildWatch {
+    pid: u32,
+}
+
+/// Have the panic hook deal with the child `pid` until the returned guard
+/// is dropped, which should happen once the child has been reaped
+pub fn watch_child(pid: u32) -> ChildWatch {
+    lock_state().child = Some(pid);
+    ChildWatch { pid }
+}
+
+impl Drop for ChildWatch {
+    fn drop(&mut self) {
+        let mut state = lock_state();
+        if state.child == Some(self.pid) {
+            state.child = None;
+        }
+    }
+}
+
+fn lock_state() -> MutexGuard<'static, State> {
+    STATE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Deal with the child and the terminal, describing what became of the child
+fn clean_up() -> Option<String> {
+    // A panic while the state is locked must not deadlock the hook
+    let state = match STATE.try_lock() {
+        Ok(state) => state,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => return None,
+    };
+    // First the child, which may still be changing the terminal modes
+    let child = state.child.map(|pid| match state.policy {
+        ChildPolicy::Kill if kill(pid) => format!("pid {} terminated", pid),
+        ChildPolicy::Kill => format!("pid {} could not be terminated", pid),
+        ChildPolicy::Detach => format!("pid {} left running", pid),
+    });
+    if let Some(ref saved) = state.terminal {
+        terminal::restore(saved);
+    }
+    child
+}
+
+/// Text of a panic payload
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "(no message)"
+    }
+}
+
+/// The report printed in place of Rust's panic message
+fn report(
+    message: &str,
+    location: Option<&str>,
+    thread: &str,
+    child: Option<&str>,
+    backtrace: Option<&str>,
+) -> String {
+    let mut text = String::from("SSHPASS: Internal error, please report it with these lines\n");
+    let mut field = |name: &str, value: &str| {
+        text.push_str(&format!("SSHPASS:   {}: {}\n", name, value));
+    };
+    field("version", env!("CARGO_PKG_VERSION"));
+    field("target", crate::build_info::TARGET);
+    field("thread", thread);
+    field("location", location.unwrap_or("unknown"));
+    field("message", message);
+    if let Some(child) = child {
+        field("child", child);
+    }
+    if let Some(backtrace) = backtrace {
+        text.push_str("SSHPASS:   backtrace:\n");
+        text.push_str(backtrace);
+        if !backtrace.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    // ssh restores the terminal it put in raw mode when terminated this way
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), nix::sys::signal::SIGTERM).is_ok()
+}
+
+#[cfg(windows)]
+fn kill(pid: u32) -> bool {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE};
+
+    unsafe {
+        let Ok(process) = OpenProcess(PROCESS_TERMINATE, false, pid) else {
+            return false;
+        };
+        let terminated = TerminateProcess(process, 1).is_ok();
+        let _ = CloseHandle(process);
+        terminated
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+
+    pub type Modes = Termios;
+
+    /// Modes of standard input, if it is a terminal
+    pub fn save() -> Option<Modes> {
+        tcgetattr(std::io::stdin()).ok()
+    }
+
+    /// Put back `saved`, leaving an unchanged terminal alone so a background
+    /// sshpass is not stopped by SIGTTOU
+    pub fn restore(saved: &Modes) {
+        let stdin = std::io::stdin();
+        if tcgetattr(&stdin).is_ok_and(|current| current != *saved) {
+            let _ = tcsetattr(&stdin, SetArg::TCSANOW, saved);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod terminal {
+    use windows::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, STD_HANDLE, STD_INPUT_HANDLE,
+        STD_OUTPUT_HANDLE,
+    };
+
+    /// 標準輸入與標準輸出各自的 console 模式（不是 console 時為 None）
+    pub type Modes = [(STD_HANDLE, Option<CONSOLE_MODE>); 2];
+
+    pub fn save() -> Option<Modes> {
+        let modes = [STD_INPUT_HANDLE, STD_OUTPUT_HANDLE].map(|which| (which, get_mode(which)));
+        modes
+            .iter()
+            .any(|(_, mode)| mode.is_some())
+            .then_some(modes)
+    }
+
+    pub fn restore(saved: &Modes) {
+        for &(which, mode) in saved {
+            let Some(mode) = mode else { continue };
+            if get_mode(which) != Some(mode) {
+                unsafe {
+                    if let Ok(handle) = GetStdHandle(which) {
+                        let _ = SetConsoleMode(handle, mode);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_mode(which: STD_HANDLE) -> Option<CONSOLE_MODE> {
+        unsafe {
+            let handle = GetStdHandle(which).ok()?;
+            let mut mode = CONSOLE_MODE(0);
+            GetConsoleMode(handle, &mut mode).ok()?;
+            Some(mode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_report() {
+        let text = report(
+            "index out of bounds",
+            Some("src/monitor.rs:10:5"),
+            "main",
+            Some("pid 42 terminated"),
+            None,
+        );
+        assert!(text.starts_with("SSHPASS: Internal error"));
+        assert!(text.contains(&format!(
+            "SSHPASS:   version: {}\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(text.contains("SSHPASS:   thread: main\n"));
+        assert!(text.contains("SSHPASS:   location: src/monitor.rs:10:5\n"));
+        assert!(text.contains("SSHPASS:   message: index out of bounds\n"));
+        assert!(text.contains("SSHPASS:   child: pid 42 terminated\n"));
+        assert!(!text.contains("backtrace"));
+
+        let text = report("boom", None, "<unnamed>", None, Some("0: main"));
+        assert!(text.contains("location: unknown\n"));
+        assert!(!text.contains("child:"));
+        assert!(text.ends_with("SSHPASS:   backtrace:\n0: main\n"));
+    }
+
+    #[test]
+    fn test_payload_message() {
+        let payload = std::panic::catch_unwind(|| panic!("code {}", 7)).unwrap_err();
+        assert_eq!(payload_message(payload.as_ref()), "code 7");
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(7)).unwrap_err();
+        assert_eq!(payload_message(payload.as_ref()), "(no message)");
+    }
+
+    #[test]
+    fn test_child_watch_clears_on_drop() {
+        let watch = watch_child(u32::MAX);
+        assert_eq!(lock_state().child, Some(u32::MAX));
+        drop(watch);
+        assert_eq!(lock_state().child, None);
+    }
+}
@@ -0,0 +1,449 @@
+//! Password source management
+//!
+//! Reads the password from the configured source (stdin, file, fd, argument,
+//! environment variable or systemd credential) and keeps it in a buffer that is wiped on drop.
+
+use crate::error::{Result, SshpassError};
+use std::fs::File;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// String wrapper that zeroes its contents when dropped
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SecureString {
+    inner: String,
+}
+
+impl SecureString {
+    /// Wrap an existing string
+    pub fn new(value: String) -> Self {
+        Self { inner: value }
+    }
+
+    /// Get the password as bytes
+    #[allow(dead_code)]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.inner.as_bytes()
+    }
+
+    /// The password followed by a line terminator, in a buffer wiped on drop
+    ///
+    /// Lets the password and its newline reach the PTY in a single write.
+    pub fn with_terminator(&self, terminator: &[u8]) -> Zeroizing<Vec<u8>> {
+        let mut line = Zeroizing::new(Vec::with_capacity(self.inner.len() + terminator.len()));
+        line.extend_from_slice(self.inner.as_bytes());
+        line.extend_from_slice(terminator);
+        line
+    }
+}
+
+impl std::ops::Deref for SecureString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl std::fmt::Debug for SecureString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecureString(***)")
+    }
+}
+
+/// Where the password comes from
+#[derive(Debug)]
+pub enum PasswordSource {
+    /// Read the first line of standard input
+    Stdin,
+    /// Read the first line of a file
+    File(PathBuf),
+    /// Read the first line from an already open file descriptor
+    #[cfg(unix)]
+    Fd(i32),
+    /// Password given directly on the command line
+    Password(SecureString),
+    /// Read a systemd credential from `$CREDENTIALS_DIRECTORY`
+    Credential(String),
+    /// Read the first line from the descriptor systemd passed under this
+    /// name (`LISTEN_FDS`/`LISTEN_FDNAMES`)
+    #[cfg(unix)]
+    ListenFd(String),
+    /// Read an environment variable (removed from the environment afterwards)
+    Env(String),
+}
+
+/// Environment variable systemd sets to the directory holding the unit's credentials
+pub const CREDENTIALS_DIRECTORY_ENV: &str = "CREDENTIALS_DIRECTORY";
+
+impl PasswordSource {
+    /// Parse a `kind:value` source specification
+    ///
+    /// Accepts `env:NAME`, `file:PATH`, `fd:N` (Unix) and `credential:NAME`,
+    /// for options that take a second secret such as `--handle-expiry`.
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        let invalid = || {
+            SshpassError::InvalidArguments(format!(
+                "Invalid password source {:?} (expected env:NAME, file:PATH, fd:N or credential:NAME)",
+                spec
+            ))
+        };
+
+        let (kind, value) = spec.split_once(':').ok_or_else(invalid)?;
+        if value.is_empty() {
+            return Err(invalid());
+        }
+
+        match kind {
+            "env" => Ok(PasswordSource::Env(value.to_string())),
+            "file" => Ok(PasswordSource::File(PathBuf::from(value))),
+            #[cfg(unix)]
+            "fd" => value.parse().map(PasswordSource::Fd).map_err(|_| invalid()),
+            "credential" => Ok(PasswordSource::Credential(value.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Resolve the password from this source
+    pub fn read_password(self, verbose: bool) -> Result<SecureString> {
+        match self {
+            PasswordSource::Stdin => {
+                if io::stdin().is_terminal() {
+                    if verbose {
+                        crate::diag!("stdin is a terminal, prompting for password");
+                    }
+                    let password = rpassword::prompt_password("SSHPASS: Enter password: ")
+                        .map_err(SshpassError::IoError)?;
+                    return Ok(SecureString::new(password));
+                }
+
+                if verbose {
+                    crate::diag!("reading password from stdin");
+                }
+                read_first_line(io::stdin().lock())
+            }
+            PasswordSource::File(path) => {
+                if verbose {
+                    crate::diag!("reading password from file {}", path.display());
+                }
+                let file = File::open(&path).map_err(|e| {
+                    SshpassError::PasswordFileError(format!("{}: {}", path.display(), e))
+                })?;
+                read_first_line(file)
+            }
+            #[cfg(unix)]
+            PasswordSource::Fd(fd) => {
+                use std::os::unix::io::FromRawFd;
+
+                if verbose {
+                    crate::diag!("reading password from fd {}", fd);
+                }
+                if fd < 0 {
+                    return Err(SshpassError::InvalidFileDescriptor(fd));
+                }
+                // SAFETY: the fd was handed to us by the caller for exclusive use
+                let file = unsafe { File::from_raw_fd(fd) };
+                read_first_line(file)
+            }
+            #[cfg(unix)]
+            PasswordSource::ListenFd(name) => {
+                use std::os::unix::io::FromRawFd;
+
+                let fd = listen_fd(&name)?;
+                if verbose {
+                    crate::diag!("reading password from passed fd {} ({})", fd, name);
+                }
+                release_listen_fds();
+                // SAFETY: systemd passed the fd to this process, and only
+                // this source reads it
+                let file = unsafe { File::from_raw_fd(fd) };
+                read_first_line(file)
+            }
+            PasswordSource::Password(password) => Ok(password),
+            PasswordSource::Credential(name) => {
+                let path = credential_path(&name)?;
+                if verbose {
+                    crate::diag!(
+                        "reading password from credential {} ({})",
+                        name,
+                        path.display()
+                    );
+                }
+                let file = File::open(&path).map_err(|e| {
+                    SshpassError::PasswordFileError(format!("{}: {}", path.display(), e))
+                })?;
+                read_first_line(file)
+            }
+            PasswordSource::Env(name) => read_password_from_env(&name, verbose),
+        }
+    }
+}
+
+/// Resolve a systemd credential name to its file in `$CREDENTIALS_DIRECTORY`
+pub fn credential_path(name: &str) -> Result<PathBuf> {
+    let directory = std::env::var_os(CREDENTIALS_DIRECTORY_ENV)
+        .ok_or_else(|| SshpassError::EnvVarNotSet(CREDENTIALS_DIRECTORY_ENV.to_string()))?;
+    credential_path_in(Path::new(&directory), name)
+}
+
+/// Resolve a systemd credential name to its file in `directory`
+///
+/// The name must be a plain file name; systemd does not allow path separators
+/// in credential names.
+fn credential_path_in(directory: &Path, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(SshpassError::InvalidArguments(format!(
+            "Invalid credential name: {:?}",
+            name
+        )));
+    }
+    Ok(directory.join(name))
+}
+
+/// Number of the first descriptor systemd passes (`SD_LISTEN_FDS_START`)
+#[cfg(unix)]
+pub const LISTEN_FDS_START: i32 = 3;
+
+/// Find the descriptor systemd passed under `name`
+///
+/// Socket activation and the file descriptor store both pass descriptors
+/// from [`LISTEN_FDS_START`] on, counted in `LISTEN_FDS` and named in
+/// `LISTEN_FDNAMES` (`FileDescriptorName=`, or `FDNAME=` for the store).
+/// They are meant for the process `LISTEN_PID` names.
+#[cfg(unix)]
+pub fn listen_fd(name: &str) -> Result<i32> {
+    let var = |name| std::env::var(name).ok();
+    listen_fd_from(
+        var("LISTEN_PID").as_deref(),
+        var("LISTEN_FDS").as_deref(),
+        var("LISTEN_FDNAMES").as_deref(),
+        std::process::id(),
+        name,
+    )
+}
+
+#[cfg(unix)]
+fn listen_fd_from(
+    pid: Option<&str>,
+    count: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+    name: &str,
+) -> Result<i32> {
+    let for_us = pid.map_or(true, |pid| pid.parse() == Ok(own_pid));
+    let count: i32 = match count.map(str::parse) {
+        Some(Ok(count)) if for_us => count,
+        _ => {
+            return Err(SshpassError::InvalidArguments(
+                "No file descriptors were passed to sshpass (LISTEN_FDS)".to_string(),
+            ))
+        }
+    };
+    let names = names.unwrap_or_default();
+    names
+        .split(':')
+        .take(count.max(0) as usize)
+        .position(|passed| passed == name)
+        .map(|index| LISTEN_FDS_START + index as i32)
+        .ok_or_else(|| {
+            SshpassError::InvalidArguments(format!(
+                "No file descriptor named {:?} was passed to sshpass (LISTEN_FDNAMES={:?})",
+                name, names
+            ))
+        })
+}
+
+/// Keep the passed descriptors and their variables from the child, as
+/// sd_listen_fds(3) does
+#[cfg(unix)]
+fn release_listen_fds() {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let _ = fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC));
+    }
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+}
+
+/// Read the password from an environment variable and remove it from the environment
+pub fn read_password_from_env(name: &str, verbose: bool) -> Result<SecureString> {
+    if verbose {
+        crate::diag!("reading password from environment variable {}", name);
+    }
+
+    let value = std::env::var(name).map_err(|_| SshpassError::EnvVarNotSet(name.to_string()))?;
+
+    // Do not leak the password to the child process
+    std::env::remove_var(name);
+
+    Ok(SecureString::new(value))
+}
+
+/// Read up to the first newline, stripping the line terminator
+///
+/// Reads one byte at a time so no buffered reader keeps a copy of the
+/// password, and grows the buffer by hand so reallocation never frees an
+/// unwiped copy. (Standard input is still buffered by std itself.)
+fn read_first_line<R: Read>(mut reader: R) -> Result<SecureString> {
+    let mut line = Zeroizing::new(Vec::with_capacity(128));
+    let mut byte = [0u8; 1];
+
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => {
+                if line.len() == line.capacity() {
+                    let mut grown = Zeroizing::new(Vec::with_capacity(line.capacity() * 2));
+                    grown.extend_from_slice(&line);
+                    line = grown;
+                }
+                line.push(byte[0]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    byte.zeroize();
+
+    while line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    match String::from_utf8(std::mem::take(&mut *line)) {
+        Ok(password) => Ok(SecureString::new(password)),
+        Err(e) => {
+            e.into_bytes().zeroize();
+            Err(SshpassError::IoError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "password is not valid UTF-8",
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_first_line_strips_newline() {
+        let password = read_first_line(&b"secret\nignored\n"[..]).unwrap();
+        assert_eq!(password.as_bytes(), b"secret");
+    }
+
+    #[test]
+    fn test_read_first_line_strips_crlf() {
+        let password = read_first_line(&b"secret\r\n"[..]).unwrap();
+        assert_eq!(password.as_bytes(), b"secret");
+    }
+
+    #[test]
+    fn test_password_from_env() {
+        std::env::set_var("SSHPASS_TEST_PASSWORD_ENV", "envsecret");
+        let password = read_password_from_env("SSHPASS_TEST_PASSWORD_ENV", false).unwrap();
+        assert_eq!(password.as_bytes(), b"envsecret");
+        assert!(std::env::var("SSHPASS_TEST_PASSWORD_ENV").is_err());
+    }
+
+    #[test]
+    fn test_source_from_spec() {
+        assert!(matches!(
+            PasswordSource::from_spec("env:NEW_PASSWORD"),
+            Ok(PasswordSource::Env(name)) if name == "NEW_PASSWORD"
+        ));
+        assert!(matches!(
+            PasswordSource::from_spec("file:/run/new:pass"),
+            Ok(PasswordSource::File(path)) if path == Path::new("/run/new:pass")
+        ));
+        assert!(matches!(
+            PasswordSource::from_spec("credential:new-password"),
+            Ok(PasswordSource::Credential(name)) if name == "new-password"
+        ));
+        assert!(PasswordSource::from_spec("env:").is_err());
+        assert!(PasswordSource::from_spec("hunter2").is_err());
+        assert!(PasswordSource::from_spec("pass:hunter2").is_err());
+    }
+
+    #[test]
+    fn test_credential_source() {
+        let directory = std::env::temp_dir().join(format!("sshpass-cred-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("ssh-password"), "credsecret").unwrap();
+
+        let path = credential_path_in(&directory, "ssh-password").unwrap();
+        let password = PasswordSource::File(path).read_password(false).unwrap();
+        assert_eq!(password.as_bytes(), b"credsecret");
+        assert!(credential_path_in(&directory, "../etc/passwd").is_err());
+        assert!(credential_path_in(&directory, "..").is_err());
+        assert!(credential_path_in(&directory, "").is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_listen_fd_from() {
+        let names = Some("socket:ssh-password:other");
+        assert_eq!(
+            listen_fd_from(Some("42"), Some("3"), names, 42, "ssh-password").unwrap(),
+            4
+        );
+        assert_eq!(
+            listen_fd_from(None, Some("3"), names, 42, "other").unwrap(),
+            5
+        );
+
+        // Not passed: unknown name, past LISTEN_FDS, another process's, none
+        assert!(listen_fd_from(Some("42"), Some("3"), names, 42, "missing").is_err());
+        assert!(listen_fd_from(Some("42"), Some("2"), names, 42, "other").is_err());
+        assert!(listen_fd_from(Some("41"), Some("3"), names, 42, "socket").is_err());
+        assert!(listen_fd_from(None, None, None, 42, "socket").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_listen_fd_source() {
+        use std::io::Write;
+        use std::os::unix::io::FromRawFd;
+
+        // Passed as the last of many, clear of the descriptors the test
+        // harness holds
+        let (reader, writer) = nix::unistd::pipe().unwrap();
+        let fd = 60;
+        nix::unistd::dup2(reader, fd).unwrap();
+        nix::unistd::close(reader).unwrap();
+        // SAFETY: pipe just returned this descriptor
+        let mut writer = unsafe { File::from_raw_fd(writer) };
+        writer.write_all(b"fdsecret\n").unwrap();
+        drop(writer);
+
+        let count = fd - LISTEN_FDS_START + 1;
+        let mut names = vec!["unused"; count as usize];
+        names[count as usize - 1] = "ssh-password";
+        std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        std::env::set_var("LISTEN_FDS", count.to_string());
+        std::env::set_var("LISTEN_FDNAMES", names.join(":"));
+
+        let password = PasswordSource::ListenFd("ssh-password".to_string())
+            .read_password(false)
+            .unwrap();
+        assert_eq!(password.as_bytes(), b"fdsecret");
+        // Hidden from the child
+        assert!(std::env::var_os("LISTEN_FDS").is_none());
+        assert!(listen_fd("ssh-password").is_err());
+    }
+
+    #[test]
+    fn test_secure_string_debug_is_redacted() {
+        let password = SecureString::new("hunter2".to_string());
+        assert_eq!(format!("{:?}", password), "SecureString(***)");
+    }
+}
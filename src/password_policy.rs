@@ -0,0 +1,176 @@
+//! Checks on the password before it is typed (`--validate-password`)
+//!
+//! A vault template that renders an empty value, or an environment variable
+//! exported from a file with Windows line endings, still gives sshpass a
+//! "password", and the server then counts a failed login against the
+//! account. Each rule is
+//! checked once the password has been read and before the child is
+//! spawned; the first one that fails ends sshpass with a message naming the
+//! rule but never the password:
+//!
+//! * `nonempty` - at least one character
+//! * `printable` - no control characters, such as a stray `\r`
+//! * `len:MIN-MAX` - the length in characters, where either bound may be
+//!   left out (`len:8-`, `len:-64`); `len:N` means exactly N
+//! * `regex:PATTERN` - the password matches PATTERN somewhere; anchor it with
+//!   `^...$` to match all of it (needs the `cli` feature)
+
+use crate::error::{Result, SshpassError};
+
+/// One `--validate-password` rule
+#[derive(Debug)]
+pub enum Rule {
+    NonEmpty,
+    Printable,
+    Length {
+        min: usize,
+        max: Option<usize>,
+    },
+    #[cfg(feature = "cli")]
+    Regex(regex::Regex),
+}
+
+impl Rule {
+    /// Parse a rule as given on the command line
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            SshpassError::InvalidArguments(format!(
+                "Invalid --validate-password rule {:?}: {}",
+                spec, reason
+            ))
+        };
+        match spec.split_once(':') {
+            None if spec == "nonempty" => Ok(Rule::NonEmpty),
+            None if spec == "printable" => Ok(Rule::Printable),
+            Some(("len", range)) => {
+                let bound = |text: &str| -> Result<Option<usize>> {
+                    if text.is_empty() {
+                        return Ok(None);
+                    }
+                    text.parse()
+                        .map(Some)
+                        .map_err(|_| invalid("expected len:MIN-MAX"))
+                };
+                let (min, max) = match range.split_once('-') {
+                    Some((min, max)) => (bound(min)?, bound(max)?),
+                    None => (bound(range)?, bound(range)?),
+                };
+                let min = min.unwrap_or(0);
+                if max.is_some_and(|max| max < min) {
+                    return Err(invalid("the maximum is below the minimum"));
+                }
+                Ok(Rule::Length { min, max })
+            }
+            #[cfg(feature = "cli")]
+            Some(("regex", pattern)) => regex::Regex::new(pattern)
+                .map(Rule::Regex)
+                .map_err(|e| invalid(&e.to_string())),
+            #[cfg(not(feature = "cli"))]
+            Some(("regex", _)) => Err(invalid("needs sshpass built with the `cli` feature")),
+            _ => Err(invalid(
+                "expected nonempty, printable, len:MIN-MAX or regex:PATTERN",
+            )),
+        }
+    }
+
+    /// Why `password` breaks this rule, if it does
+    fn violation(&self, password: &str) -> Option<String> {
+        match self {
+            Rule::NonEmpty => password.is_empty().then(|| "it is empty".to_string()),
+            Rule::Printable => password
+                .chars()
+                .enumerate()
+                .find(|(_, c)| c.is_control())
+                .map(|(at, c)| {
+                    format!(
+                        "it contains the control character U+{:04X} at position {}",
+                        c as u32,
+                        at + 1
+                    )
+                }),
+            Rule::Length { min, max } => {
+                let length = password.chars().count();
+                let too_long = max.is_some_and(|max| length > max);
+                (length < *min || too_long).then(|| format!("it is {} characters long", length))
+            }
+            #[cfg(feature = "cli")]
+            Rule::Regex(regex) => {
+                (!regex.is_match(password)).then(|| format!("it does not match {}", regex.as_str()))
+            }
+        }
+    }
+}
+
+/// Check `password` against every rule in `specs`
+pub fn check(specs: &[String], password: &str) -> Result<()> {
+    for spec in specs {
+        if let Some(reason) = Rule::parse(spec)?.violation(password) {
+            return Err(SshpassError::RuntimeError(format!(
+                "Password rejected by --validate-password {}: {}",
+                spec, reason
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(specs: &[&str]) -> Vec<String> {
+        specs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert!(matches!(Rule::parse("nonempty"), Ok(Rule::NonEmpty)));
+        assert!(matches!(
+            Rule::parse("len:8-"),
+            Ok(Rule::Length { min: 8, max: None })
+        ));
+        assert!(matches!(
+            Rule::parse("len:-64"),
+            Ok(Rule::Length {
+                min: 0,
+                max: Some(64)
+            })
+        ));
+        assert!(matches!(
+            Rule::parse("len:12"),
+            Ok(Rule::Length {
+                min: 12,
+                max: Some(12)
+            })
+        ));
+        assert!(Rule::parse("len:9-8").is_err());
+        assert!(Rule::parse("len:x").is_err());
+        assert!(Rule::parse("strong").is_err());
+        #[cfg(feature = "cli")]
+        assert!(Rule::parse("regex:(").is_err());
+    }
+
+    #[test]
+    fn test_check_password() {
+        let policy = rules(&["nonempty", "printable", "len:4-8"]);
+        assert!(check(&policy, "hunter2").is_ok());
+
+        let error = check(&policy, "").unwrap_err().to_string();
+        assert!(error.ends_with("--validate-password nonempty: it is empty"));
+        let error = check(&policy, "hunter2\r").unwrap_err().to_string();
+        assert!(error.ends_with("it contains the control character U+000D at position 8"));
+        let error = check(&policy, "correct horse").unwrap_err().to_string();
+        assert!(error.ends_with("--validate-password len:4-8: it is 13 characters long"));
+        // Length counts characters, not bytes
+        assert!(check(&rules(&["len:-3"]), "äöü").is_ok());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_regex_rule() {
+        let policy = rules(&["regex:^[^{}]+$"]);
+        assert!(check(&policy, "s3cret").is_ok());
+        let error = check(&policy, "{{ vault.password }}").unwrap_err();
+        assert!(!error.to_string().contains("vault.password"));
+    }
+}
@@ -0,0 +1,150 @@
+//! Bracketed paste 支援
+//!
+//! 遠端程式以 `ESC[?2004h` / `ESC[?2004l` 開關 bracketed paste 模式。開啟時，
+//! 將一次抵達的大量 stdin 輸入包在 `ESC[200~` / `ESC[201~` 之間，讓遠端 shell
+//! 或編輯器把它當成貼上內容，而不是逐鍵輸入（避免自動縮排、逐行執行等問題）。
+
+/// 開啟 bracketed paste 的序列
+const ENABLE_SEQ: &[u8] = b"\x1b[?2004h";
+/// 關閉 bracketed paste 的序列
+const DISABLE_SEQ: &[u8] = b"\x1b[?2004l";
+/// 貼上內容起始標記
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+/// 貼上內容結束標記
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// 單次輸入至少這麼長才視為貼上，一般打字不會包上標記
+pub const PASTE_THRESHOLD: usize = 16;
+
+/// 追蹤遠端 bracketed paste 狀態並包裝貼上內容
+#[derive(Debug, Default)]
+pub struct BracketedPaste {
+    enabled: bool,
+    paste_safe: bool,
+    /// 上一段輸出的尾端，用來偵測被切開的序列
+    tail: Vec<u8>,
+}
+
+impl BracketedPaste {
+    /// 建立追蹤器
+    ///
+    /// # Arguments
+    /// * `paste_safe` - 貼上內容中的 CR / CRLF 一律轉為 LF
+    pub fn new(paste_safe: bool) -> Self {
+        Self {
+            enabled: false,
+            paste_safe,
+            tail: Vec::new(),
+        }
+    }
+
+    /// 遠端目前是否開啟 bracketed paste
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 檢查 PTY 輸出中的開關序列，以最後出現者為準
+    pub fn observe_output(&mut self, data: &[u8]) {
+        let mut window = std::mem::take(&mut self.tail);
+        window.extend_from_slice(data);
+
+        let last_enable = rfind(&window, ENABLE_SEQ);
+        let last_disable = rfind(&window, DISABLE_SEQ);
+        match (last_enable, last_disable) {
+            (Some(on), Some(off)) => self.enabled = on > off,
+            (Some(_), None) => self.enabled = true,
+            (None, Some(_)) => self.enabled = false,
+            (None, None) => {}
+        }
+
+        let keep = ENABLE_SEQ.len() - 1;
+        let start = window.len().saturating_sub(keep);
+        self.tail = window.split_off(start);
+    }
+
+    /// 視需要把一段 stdin 輸入包成貼上內容
+    pub fn wrap_input(&self, data: Vec<u8>) -> Vec<u8> {
+        if !self.enabled || data.len() < PASTE_THRESHOLD || data.starts_with(PASTE_START) {
+            return data;
+        }
+
+        let body = if self.paste_safe {
+            crate::ansi::normalize_line_endings(&data)
+        } else {
+            data
+        };
+
+        let mut wrapped = Vec::with_capacity(body.len() + PASTE_START.len() + PASTE_END.len());
+        wrapped.extend_from_slice(PASTE_START);
+        wrapped.extend_from_slice(&body);
+        wrapped.extend_from_slice(PASTE_END);
+        wrapped
+    }
+}
+
+/// 找出 needle 最後一次出現的位置
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASTE: &[u8] = b"for i in 1 2 3; do\n  echo $i\ndone\n";
+
+    #[test]
+    fn test_disabled_by_default() {
+        let paste = BracketedPaste::new(false);
+        assert!(!paste.is_enabled());
+        assert_eq!(paste.wrap_input(PASTE.to_vec()), PASTE);
+    }
+
+    #[test]
+    fn test_wraps_when_remote_enables() {
+        let mut paste = BracketedPaste::new(false);
+        paste.observe_output(b"prompt$ \x1b[?2004h");
+        assert!(paste.is_enabled());
+
+        let wrapped = paste.wrap_input(PASTE.to_vec());
+        assert!(wrapped.starts_with(PASTE_START));
+        assert!(wrapped.ends_with(PASTE_END));
+    }
+
+    #[test]
+    fn test_short_input_not_wrapped() {
+        let mut paste = BracketedPaste::new(false);
+        paste.observe_output(b"\x1b[?2004h");
+        assert_eq!(paste.wrap_input(b"ls\r".to_vec()), b"ls\r");
+    }
+
+    #[test]
+    fn test_sequence_split_across_chunks() {
+        let mut paste = BracketedPaste::new(false);
+        paste.observe_output(b"$ \x1b[?20");
+        assert!(!paste.is_enabled());
+        paste.observe_output(b"04h");
+        assert!(paste.is_enabled());
+        paste.observe_output(b"\x1b[?2004l\r\n");
+        assert!(!paste.is_enabled());
+    }
+
+    #[test]
+    fn test_last_toggle_wins() {
+        let mut paste = BracketedPaste::new(false);
+        paste.observe_output(b"\x1b[?2004l output \x1b[?2004h");
+        assert!(paste.is_enabled());
+    }
+
+    #[test]
+    fn test_paste_safe_normalizes_newlines() {
+        let mut paste = BracketedPaste::new(true);
+        paste.observe_output(b"\x1b[?2004h");
+
+        let wrapped = paste.wrap_input(b"line one\r\nline two\rend".to_vec());
+        let mut expected = PASTE_START.to_vec();
+        expected.extend_from_slice(b"line one\nline two\nend");
+        expected.extend_from_slice(PASTE_END);
+        assert_eq!(wrapped, expected);
+    }
+}
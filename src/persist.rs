@@ -0,0 +1,148 @@
+//! Persistent ssh connections (`--persist`, `sshpass persist stop`)
+//!
+//! Tools that run sshpass hundreds of times pay for a full login each time.
+//! With `--persist SECONDS`, sshpass has ssh start a ControlMaster whose
+//! socket lives in a private cache directory and stays up for that long
+//! after the last session. The next invocation for the same user, host and
+//! port finds the socket and ssh reuses the connection without asking for
+//! the password.
+//!
+//! Sockets are named by ssh's `%C` hash, so the directory path must leave
+//! room for it within the ~100 byte limit of Unix socket paths.
+//! `sshpass persist stop` asks every master in the directory to exit.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Directory holding the control sockets
+///
+/// `$XDG_CACHE_HOME/sshpass/control`, or `~/.cache/sshpass/control`.
+pub fn control_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|dir| dir.join("sshpass").join("control"))
+}
+
+/// Create the directory, readable by the user only
+pub fn prepare_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+/// ssh options that start or reuse a master in `dir`
+pub fn options(dir: &Path, seconds: u64) -> Vec<(&'static str, String)> {
+    vec![
+        ("ControlMaster", "auto".to_string()),
+        ("ControlPath", dir.join("%C").display().to_string()),
+        ("ControlPersist", seconds.to_string()),
+    ]
+}
+
+/// Whether a master for this ssh command is running
+///
+/// Runs the command with `-O check` added, which asks the master without
+/// connecting to the host.
+pub fn master_running(command: &[String]) -> bool {
+    let Some((program, args)) = command.split_first() else {
+        return false;
+    };
+    Command::new(program)
+        .args(["-O", "check"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Ask every master in `dir` to exit, returning how many sockets there were
+pub fn stop_all(dir: &Path, verbose: bool) -> std::io::Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut stopped = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if verbose {
+            crate::diag!("Stopping ssh master {}", path.display());
+        }
+        // The destination is only a placeholder; the path is given in full
+        let _ = Command::new("ssh")
+            .args(stop_args(&path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        // A master removes its socket when it exits; stale ones are left
+        // behind by masters that were killed
+        if path.exists() {
+            let _ = std::fs::remove_file(&path);
+        }
+        stopped += 1;
+    }
+    Ok(stopped)
+}
+
+/// Arguments of the `ssh` call that stops the master at `socket`
+fn stop_args(socket: &Path) -> Vec<String> {
+    vec![
+        "-o".to_string(),
+        format!("ControlPath={}", socket.display()),
+        "-O".to_string(),
+        "exit".to_string(),
+        "sshpass-persist".to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persist_options() {
+        let options = options(Path::new("/home/u/.cache/sshpass/control"), 600);
+        assert_eq!(
+            options,
+            vec![
+                ("ControlMaster", "auto".to_string()),
+                (
+                    "ControlPath",
+                    "/home/u/.cache/sshpass/control/%C".to_string()
+                ),
+                ("ControlPersist", "600".to_string()),
+            ]
+        );
+        assert_eq!(
+            stop_args(Path::new("/tmp/c/abc")),
+            vec![
+                "-o",
+                "ControlPath=/tmp/c/abc",
+                "-O",
+                "exit",
+                "sshpass-persist"
+            ]
+        );
+        assert!(!master_running(&[]));
+    }
+
+    #[test]
+    fn test_stop_removes_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("sshpass-persist-test-{}", std::process::id()));
+        assert_eq!(stop_all(&dir, false).unwrap(), 0);
+
+        prepare_dir(&dir).unwrap();
+        std::fs::write(dir.join("stale"), b"").unwrap();
+        assert_eq!(stop_all(&dir, false).unwrap(), 1);
+        assert!(!dir.join("stale").exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
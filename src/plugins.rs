@@ -0,0 +1,246 @@
+//! Detector plugins named in profile files
+//!
+//! Devices with login flows of their own can be supported without patterns
+//! in sshpass: a profile file lists shared libraries under `[[plugins]]`,
+//! and each one becomes a [`Detector`] that sees the filtered output before
+//! the built-in matchers. A plugin exports these C functions:
+//!
+//! ```c
+//! /* Must return SSHPASS_DETECTOR_ABI (1) */
+//! uint32_t sshpass_detector_abi(void);
+//! /* config is the profile's `config` string, or NULL; NULL on failure */
+//! void *sshpass_detector_new(const char *config);
+//! /* out holds *out_len bytes; store the reply or abort reason there and
+//!    its length in *out_len. Returns 0 (nothing), 1 (password prompt),
+//!    2 (reply) or 3 (abort) */
+//! int sshpass_detector_feed(void *detector, const uint8_t *data, size_t len,
+//!                           uint8_t *out, size_t *out_len);
+//! void sshpass_detector_free(void *detector);
+//! ```
+//!
+//! Plugins run inside sshpass with the password in memory, so only load
+//! libraries you trust. With `--sandbox` they are bound by the same system
+//! call filter as the event loop.
+//!
+//! Loading needs the `plugins` feature; without it a profile naming plugins
+//! is rejected.
+
+use crate::error::Result;
+#[cfg(not(feature = "plugins"))]
+use crate::error::SshpassError;
+use crate::monitor::Detection;
+#[cfg(not(feature = "plugins"))]
+use crate::monitor::Detector;
+use crate::profiles::PluginSpec;
+
+/// Interface version a plugin must report
+pub const ABI_VERSION: u32 = 1;
+
+/// Room given to a plugin for its reply or abort reason
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+const OUT_CAPACITY: usize = 4096;
+
+/// Turn the result of `sshpass_detector_feed` into a detection
+///
+/// Unknown results end the session rather than be ignored.
+pub fn decode_result(code: i32, out: &[u8]) -> Detection {
+    match code {
+        0 => Detection::Nothing,
+        1 => Detection::PasswordPrompt,
+        2 => Detection::Reply(out.to_vec()),
+        3 => Detection::Abort(String::from_utf8_lossy(out).into_owned()),
+        _ => Detection::Abort(format!("plugin returned unknown result {}", code)),
+    }
+}
+
+/// Load every plugin in `specs`, in order
+pub fn load_all(specs: &[PluginSpec], verbose: bool) -> Result<Vec<Plugin>> {
+    specs
+        .iter()
+        .map(|spec| {
+            if verbose {
+                crate::diag!("Loading detector plugin {}", spec.path.display());
+            }
+            Plugin::load(spec)
+        })
+        .collect()
+}
+
+#[cfg(feature = "plugins")]
+pub use self::dynamic::Plugin;
+
+#[cfg(feature = "plugins")]
+mod dynamic {
+    use super::{decode_result, ABI_VERSION, OUT_CAPACITY};
+    use crate::error::{Result, SshpassError};
+    use crate::monitor::{Detection, Detector};
+    use crate::profiles::PluginSpec;
+    use libloading::Library;
+    use std::ffi::{c_char, c_int, c_void, CString};
+
+    type AbiFn = unsafe extern "C" fn() -> u32;
+    type NewFn = unsafe extern "C" fn(*const c_char) -> *mut c_void;
+    type FeedFn = unsafe extern "C" fn(*mut c_void, *const u8, usize, *mut u8, *mut usize) -> c_int;
+    type FreeFn = unsafe extern "C" fn(*mut c_void);
+
+    /// A loaded detector plugin
+    pub struct Plugin {
+        name: String,
+        detector: *mut c_void,
+        feed: FeedFn,
+        free: FreeFn,
+        // Dropped after the detector is freed
+        _library: Library,
+    }
+
+    // SAFETY: the detector is only used through &mut self, from one thread
+    // at a time
+    unsafe impl Send for Plugin {}
+
+    impl Plugin {
+        /// Load the library and create its detector
+        pub fn load(spec: &PluginSpec) -> Result<Self> {
+            let name = spec
+                .path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| spec.path.display().to_string());
+            let error = |reason: String| {
+                SshpassError::InvalidArguments(format!(
+                    "Failed to load plugin {}: {}",
+                    spec.path.display(),
+                    reason
+                ))
+            };
+            let config = spec
+                .config
+                .as_deref()
+                .map(CString::new)
+                .transpose()
+                .map_err(|_| error("config contains a NUL byte".to_string()))?;
+
+            // SAFETY: loading runs the library's initialisers; the user
+            // named it in their profile and vouches for it. The symbols are
+            // declared with the types documented for the plugin interface.
+            unsafe {
+                let library = Library::new(&spec.path).map_err(|e| error(e.to_string()))?;
+                let abi: AbiFn = *library
+                    .get(b"sshpass_detector_abi\0")
+                    .map_err(|e| error(e.to_string()))?;
+                let version = abi();
+                if version != ABI_VERSION {
+                    return Err(error(format!(
+                        "interface version {} (expected {})",
+                        version, ABI_VERSION
+                    )));
+                }
+                let new: NewFn = *library
+                    .get(b"sshpass_detector_new\0")
+                    .map_err(|e| error(e.to_string()))?;
+                let feed: FeedFn = *library
+                    .get(b"sshpass_detector_feed\0")
+                    .map_err(|e| error(e.to_string()))?;
+                let free: FreeFn = *library
+                    .get(b"sshpass_detector_free\0")
+                    .map_err(|e| error(e.to_string()))?;
+
+                let detector = new(config.as_ref().map_or(std::ptr::null(), |c| c.as_ptr()));
+                if detector.is_null() {
+                    return Err(error("the detector could not be created".to_string()));
+                }
+                Ok(Self {
+                    name,
+                    detector,
+                    feed,
+                    free,
+                    _library: library,
+                })
+            }
+        }
+    }
+
+    impl Detector for Plugin {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn feed(&mut self, data: &[u8]) -> Detection {
+            let mut out = vec![0u8; OUT_CAPACITY];
+            let mut out_len = out.len();
+            // SAFETY: the buffers are valid for the lengths passed, and the
+            // detector was created by this library and not yet freed
+            let code = unsafe {
+                (self.feed)(
+                    self.detector,
+                    data.as_ptr(),
+                    data.len(),
+                    out.as_mut_ptr(),
+                    &mut out_len,
+                )
+            };
+            out.truncate(out_len);
+            decode_result(code, &out)
+        }
+    }
+
+    impl Drop for Plugin {
+        fn drop(&mut self) {
+            // SAFETY: created by sshpass_detector_new and freed only here
+            unsafe { (self.free)(self.detector) }
+        }
+    }
+}
+
+/// Stand-in for builds without the `plugins` feature; none can be loaded
+#[cfg(not(feature = "plugins"))]
+pub enum Plugin {}
+
+#[cfg(not(feature = "plugins"))]
+impl Plugin {
+    fn load(spec: &PluginSpec) -> Result<Self> {
+        Err(SshpassError::InvalidArguments(format!(
+            "Plugin {} requires sshpass built with the `plugins` feature",
+            spec.path.display()
+        )))
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+impl Detector for Plugin {
+    fn name(&self) -> &str {
+        match *self {}
+    }
+
+    fn feed(&mut self, _data: &[u8]) -> Detection {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_decode_result() {
+        assert_eq!(decode_result(0, b""), Detection::Nothing);
+        assert_eq!(decode_result(1, b""), Detection::PasswordPrompt);
+        assert_eq!(decode_result(2, b"y\r"), Detection::Reply(b"y\r".to_vec()));
+        assert_eq!(
+            decode_result(3, b"locked"),
+            Detection::Abort("locked".to_string())
+        );
+        assert!(matches!(decode_result(-1, b""), Detection::Abort(_)));
+    }
+
+    #[test]
+    fn test_load_missing_plugin() {
+        let spec = PluginSpec {
+            path: PathBuf::from("/nonexistent/sshpass-plugin.so"),
+            config: None,
+        };
+        assert!(load_all(&[], false).unwrap().is_empty());
+        let error = load_all(&[spec], false).err().unwrap();
+        assert!(error.to_string().contains("sshpass-plugin.so"));
+    }
+}
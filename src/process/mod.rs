@@ -3,6 +3,12 @@
 //! 此模組提供跨平台的進程管理介面。在 Unix 系統上使用 fork/exec，
 //! 在 Windows 上使用 CreateProcess。
 
+// Partly library API; the binary only sets what its options call for
+#[allow(dead_code)]
+mod options;
+#[allow(unused_imports)]
+pub use options::{EnvPolicy, SpawnOptions};
+
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
@@ -12,3 +18,67 @@ pub use unix::ChildProcess;
 mod windows_portable;
 #[cfg(windows)]
 pub use windows_portable::ChildProcess;
+
+use std::time::Duration;
+
+/// CPU time and memory the child used, known once it has been waited for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// Peak resident set size in bytes, where the platform reports it
+    pub max_rss: Option<u64>,
+}
+
+impl ResourceUsage {
+    /// One-line human readable summary
+    pub fn summary(&self) -> String {
+        let memory = self.max_rss.map_or(String::new(), |bytes| {
+            format!(", peak memory {:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+        });
+        format!(
+            "CPU time {:.3}s user, {:.3}s system{}",
+            self.user_time.as_secs_f64(),
+            self.system_time.as_secs_f64(),
+            memory
+        )
+    }
+}
+
+/// How a signal ended the child, rather than an exit code of 128 + N
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termination {
+    /// The signal number
+    pub signal: i32,
+    /// Whether the child left a core dump
+    pub core_dumped: bool,
+}
+
+impl Termination {
+    /// The name of the signal, such as `SIGKILL`, if the platform knows it
+    pub fn signal_name(&self) -> Option<&'static str> {
+        #[cfg(unix)]
+        {
+            nix::sys::signal::Signal::try_from(self.signal)
+                .ok()
+                .map(|signal| signal.as_str())
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// One-line human readable description
+    pub fn summary(&self) -> String {
+        let name = self
+            .signal_name()
+            .map_or_else(|| format!("signal {}", self.signal), str::to_string);
+        let core = if self.core_dumped {
+            " (core dumped)"
+        } else {
+            ""
+        };
+        format!("{}{}", name, core)
+    }
+}
@@ -0,0 +1,191 @@
+//! How to start the child: [`SpawnOptions`]
+//!
+//! Both backends take the same options. The child always runs on a new PTY;
+//! on Unix it also gets a session of its own with the PTY as its controlling
+//! terminal. A ConPTY child's standard streams are always the PTY, so the
+//! stdio wiring only matters on Unix.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Where the child's environment starts from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvPolicy {
+    /// sshpass's own environment
+    #[default]
+    Inherit,
+    /// An empty environment
+    Clear,
+}
+
+/// What to start, and how
+#[derive(Debug, Clone)]
+pub struct SpawnOptions {
+    pub(super) command: Vec<String>,
+    pub(super) env_policy: EnvPolicy,
+    /// Variables set, or removed for `None`, in order
+    pub(super) env: Vec<(OsString, Option<OsString>)>,
+    pub(super) cwd: Option<PathBuf>,
+    pub(super) size: Option<(u16, u16)>,
+    /// stdin, stdout and stderr attached to the PTY
+    pub(super) on_pty: [bool; 3],
+    pub(super) kill_on_drop: bool,
+    pub(super) verbose: bool,
+}
+
+impl SpawnOptions {
+    /// Run `command`, the program followed by its arguments, with sshpass's
+    /// environment, working directory and standard streams
+    pub fn new<I, S>(command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            command: command.into_iter().map(Into::into).collect(),
+            env_policy: EnvPolicy::Inherit,
+            env: Vec::new(),
+            cwd: None,
+            size: None,
+            on_pty: [false; 3],
+            kill_on_drop: false,
+            verbose: false,
+        }
+    }
+
+    /// Start from an empty environment rather than sshpass's
+    pub fn env_clear(mut self) -> Self {
+        self.env_policy = EnvPolicy::Clear;
+        self.env.clear();
+        self
+    }
+
+    /// Set `key` in the child's environment
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), Some(value.into())));
+        self
+    }
+
+    /// Leave `key` out of the child's environment
+    pub fn env_remove(mut self, key: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), None));
+        self
+    }
+
+    /// The child's working directory
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// The PTY's rows and columns; by default the terminal's own size on
+    /// Windows and the PTY's default on Unix, where the event loop sizes it
+    pub fn size(mut self, rows: u16, cols: u16) -> Self {
+        self.size = Some((rows, cols));
+        self
+    }
+
+    /// Have the child read its standard input from the PTY
+    pub fn stdin_on_pty(mut self, on_pty: bool) -> Self {
+        self.on_pty[0] = on_pty;
+        self
+    }
+
+    /// Have the child write its standard output to the PTY
+    pub fn stdout_on_pty(mut self, on_pty: bool) -> Self {
+        self.on_pty[1] = on_pty;
+        self
+    }
+
+    /// Have the child write its standard error to the PTY
+    pub fn stderr_on_pty(mut self, on_pty: bool) -> Self {
+        self.on_pty[2] = on_pty;
+        self
+    }
+
+    /// Attach all three standard streams to the PTY, for sessions sshpass
+    /// drives itself (sequential command mode)
+    pub fn interactive(self) -> Self {
+        self.stdin_on_pty(true)
+            .stdout_on_pty(true)
+            .stderr_on_pty(true)
+    }
+
+    /// Kill the child when its [`ChildProcess`](super::ChildProcess) is
+    /// dropped before the child was waited for: its whole process group on
+    /// Unix, the process itself on Windows
+    pub fn kill_on_drop(mut self, kill: bool) -> Self {
+        self.kill_on_drop = kill;
+        self
+    }
+
+    /// Print debug messages while starting the child
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Whether the environment differs from sshpass's
+    pub(super) fn changes_env(&self) -> bool {
+        self.env_policy == EnvPolicy::Clear || !self.env.is_empty()
+    }
+
+    /// The child's environment, from sshpass's or an empty one
+    pub(super) fn environment(&self) -> Vec<(OsString, OsString)> {
+        let mut vars: Vec<(OsString, OsString)> = match self.env_policy {
+            EnvPolicy::Inherit => std::env::vars_os().collect(),
+            EnvPolicy::Clear => Vec::new(),
+        };
+        for (key, value) in &self.env {
+            vars.retain(|(k, _)| k != key);
+            if let Some(value) = value {
+                vars.push((key.clone(), value.clone()));
+            }
+        }
+        vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spawn_options_defaults() {
+        let options = SpawnOptions::new(["ssh", "host"]);
+        assert_eq!(options.command, ["ssh", "host"]);
+        assert_eq!(options.env_policy, EnvPolicy::Inherit);
+        assert!(!options.changes_env());
+        assert_eq!(options.cwd, None);
+        assert_eq!(options.size, None);
+        assert_eq!(options.on_pty, [false; 3]);
+        assert!(!options.kill_on_drop && !options.verbose);
+
+        let options = SpawnOptions::new(["ssh"]).interactive();
+        assert_eq!(options.on_pty, [true; 3]);
+        let options = SpawnOptions::new(["ssh"])
+            .stdin_on_pty(true)
+            .stdout_on_pty(false);
+        assert_eq!(options.on_pty, [true, false, false]);
+    }
+
+    #[test]
+    fn test_spawn_options_environment() {
+        let options = SpawnOptions::new(["env"])
+            .env_clear()
+            .env("A", "1")
+            .env("B", "2")
+            .env_remove("A")
+            .env("B", "3");
+        assert!(options.changes_env());
+        let vars = options.environment();
+        assert_eq!(vars, [(OsString::from("B"), OsString::from("3"))]);
+
+        // sshpass's environment, less the removed variable
+        let key = std::env::vars_os().next().map(|(key, _)| key);
+        if let Some(key) = key {
+            let options = SpawnOptions::new(["env"]).env_remove(key.clone());
+            assert!(options.environment().iter().all(|(k, _)| *k != key));
+        }
+    }
+}
@@ -2,45 +2,109 @@
 //!
 //! 使用 fork/exec 來產生子進程
 
+use super::{ResourceUsage, SpawnOptions, Termination};
 use crate::error::{Result, SshpassError};
 use crate::pty::Pty;
+use crate::ssh_log::SshLog;
 use nix::fcntl::OFlag;
-use nix::sys::signal::{sigprocmask, SigSet, SigmaskHow};
+use nix::pty::Winsize;
+use nix::sys::signal::{
+    pthread_sigmask, signal, sigprocmask, SigHandler, SigSet, SigmaskHow, Signal,
+};
+use nix::sys::stat::Mode;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::{close, execvp, fork, setsid, ForkResult, Pid};
-use std::ffi::CString;
-use std::fs::OpenOptions;
-use std::os::unix::fs::OpenOptionsExt;
-use std::os::unix::io::AsRawFd;
+use nix::unistd::{chdir, dup2, execvp, fork, setsid, ForkResult, Pid};
+use std::cell::Cell;
+use std::ffi::{CStr, CString, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 
 /// Represents a child process running with a PTY
 pub struct ChildProcess {
     pub pid: Pid,
     pub pty: Pty,
-    slave_fd: Option<i32>,
+    /// ssh 自己的記錄管道（`--ssh-stderr-pipe`）
+    pub ssh_log: Option<SshLog>,
+    /// Whether the child reads its standard input from the PTY
+    stdin_on_pty: bool,
+    /// Whether the child writes its standard output to the PTY
+    stdout_on_pty: bool,
+    /// The signal that killed the child, once it has been waited for
+    termination: Cell<Option<Termination>>,
+    /// What the child used, once it has been waited for
+    usage: Cell<Option<ResourceUsage>>,
+    /// Kill the child's process group on drop unless it was reaped
+    kill_on_drop: bool,
+    /// Whether the child was waited for
+    reaped: Cell<bool>,
 }
 
 impl ChildProcess {
-    /// Spawn a child process with a PTY to execute the given command
+    /// Spawn a child process on a new PTY as `options` say
     ///
-    /// # Arguments
-    /// * `command` - Command and arguments to execute
-    /// * `verbose` - Enable verbose logging
-    ///
-    /// # Returns
-    /// A ChildProcess handle on success
-    pub fn spawn(command: &[String], verbose: bool) -> Result<Self> {
+    /// The child gets a session of its own with the PTY as its controlling
+    /// terminal; the standard streams `options` leave off the PTY stay
+    /// sshpass's.
+    pub fn spawn(options: &SpawnOptions) -> Result<Self> {
+        let command = &options.command;
+        let verbose = options.verbose;
         if command.is_empty() {
             return Err(SshpassError::InvalidArguments(
                 "No command specified".to_string(),
             ));
         }
 
+        // Build the exec arguments, environment and directory before forking;
+        // allocating in the child of a multi-threaded process is not safe
+        let c_strings = command
+            .iter()
+            .map(|s| {
+                CString::new(s.as_str()).map_err(|e| {
+                    SshpassError::InvalidArguments(format!("Invalid argument '{}': {}", s, e))
+                })
+            })
+            .collect::<Result<Vec<CString>>>()?;
+        let env = if options.changes_env() {
+            Some(environment_block(&options.environment())?)
+        } else {
+            None
+        };
+        let envp = env.as_ref().map(|env| {
+            env.iter()
+                .map(|var| var.as_ptr())
+                .chain(std::iter::once(std::ptr::null()))
+                .collect::<Vec<_>>()
+        });
+        let cwd = options
+            .cwd
+            .as_ref()
+            .map(|dir| {
+                CString::new(dir.as_os_str().as_bytes()).map_err(|e| {
+                    SshpassError::InvalidArguments(format!(
+                        "Invalid working directory {:?}: {}",
+                        dir, e
+                    ))
+                })
+            })
+            .transpose()?;
+        let on_pty: Vec<RawFd> = (0..3).filter(|&fd| options.on_pty[fd as usize]).collect();
+
         // Create PTY before forking
         let pty = Pty::new()?;
+        let slave_path = CString::new(pty.slave_name()).map_err(|e| {
+            SshpassError::PtyCreationError(format!("Invalid PTY slave name: {}", e))
+        })?;
+        if let Some((rows, cols)) = options.size {
+            pty.set_winsize(&Winsize {
+                ws_row: rows,
+                ws_col: cols,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            })?;
+        }
 
         if verbose {
-            eprintln!("SSHPASS: Created PTY with slave: {}", pty.slave_name());
+            crate::diag!("Created PTY with slave: {}", pty.slave_name());
         }
 
         // Set up signal mask before fork
@@ -51,56 +115,99 @@ impl ChildProcess {
         sigset.add(nix::sys::signal::SIGINT);
         sigset.add(nix::sys::signal::SIGTSTP);
 
-        // Block signals during fork
-        sigprocmask(SigmaskHow::SIG_SETMASK, Some(&sigset), None)
-            .map_err(|e| SshpassError::SystemError(e))?;
+        // Block signals during fork, remembering this thread's mask so other
+        // sessions and the embedding program keep theirs
+        let mut saved_sigset = SigSet::empty();
+        pthread_sigmask(
+            SigmaskHow::SIG_BLOCK,
+            Some(&sigset),
+            Some(&mut saved_sigset),
+        )
+        .map_err(SshpassError::SystemError)?;
 
         // Fork the process
         match unsafe { fork() } {
             Ok(ForkResult::Parent { child }) => {
                 // Parent process
                 if verbose {
-                    eprintln!("SSHPASS: Forked child process with PID: {}", child);
+                    crate::diag!("Forked child process with PID: {}", child);
                 }
 
-                // Open the slave PTY to keep it alive (see C version comment 3.14159)
-                let slave_fd = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(pty.slave_name())
-                    .map(|f| f.as_raw_fd())
-                    .ok();
+                // The slave is not held open here. Until the child opens it
+                // the event loop backs off on the master's hangup, and an
+                // open slave would hide the hangup once the child exits
 
-                // Restore empty signal mask for pselect
-                let empty_sigset = SigSet::empty();
-                sigprocmask(SigmaskHow::SIG_SETMASK, Some(&empty_sigset), None)
-                    .map_err(|e| SshpassError::SystemError(e))?;
+                // Restore the previous signal mask for pselect
+                pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&saved_sigset), None)
+                    .map_err(SshpassError::SystemError)?;
 
                 Ok(ChildProcess {
                     pid: child,
                     pty,
-                    slave_fd,
+                    ssh_log: None,
+                    stdin_on_pty: options.on_pty[0],
+                    stdout_on_pty: options.on_pty[1],
+                    termination: Cell::new(None),
+                    usage: Cell::new(None),
+                    kill_on_drop: options.kill_on_drop,
+                    reaped: Cell::new(false),
                 })
             }
             Ok(ForkResult::Child) => {
                 // Child process
-                if let Err(e) = run_child(&pty, command, verbose) {
-                    eprintln!("SSHPASS: Failed to run command: {}", e);
+                let setup = ChildSetup {
+                    slave_path: &slave_path,
+                    command: &c_strings,
+                    envp: envp.as_deref(),
+                    cwd: cwd.as_deref(),
+                    on_pty: &on_pty,
+                };
+                if let Err(e) = run_child(&setup, verbose) {
+                    crate::diag!("Failed to run command: {}", e);
                     std::process::exit(3); // RETURN_RUNTIME_ERROR
                 }
                 unreachable!();
             }
-            Err(e) => Err(SshpassError::ForkError(format!("Fork failed: {}", e))),
+            Err(e) => {
+                let _ = pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&saved_sigset), None);
+                Err(SshpassError::ForkError(format!("Fork failed: {}", e)))
+            }
         }
     }
 
+    /// Watch the log pipe the child's ssh was told to write to
+    pub fn with_ssh_log(mut self, log: Option<SshLog>) -> Self {
+        self.ssh_log = log.map(|mut log| {
+            log.close_child_end();
+            log
+        });
+        self
+    }
+
+    /// Whether the child's standard input comes through the PTY
+    pub fn stdin_on_pty(&self) -> bool {
+        self.stdin_on_pty
+    }
+
+    /// Whether the child's standard output arrives through the PTY
+    pub fn stdout_on_pty(&self) -> bool {
+        self.stdout_on_pty
+    }
+
     /// Wait for the child process without blocking
     ///
     /// Returns Some(exit_code) if the process has exited, None if still running
     pub fn try_wait(&self) -> Result<Option<i32>> {
-        match waitpid(self.pid, Some(WaitPidFlag::WNOHANG)) {
-            Ok(WaitStatus::Exited(_, code)) => Ok(Some(code)),
-            Ok(WaitStatus::Signaled(_, sig, _)) => Ok(Some(128 + sig as i32)),
+        match self.wait4(Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(_, code)) => {
+                self.reaped.set(true);
+                Ok(Some(code))
+            }
+            Ok(WaitStatus::Signaled(_, sig, core_dumped)) => {
+                self.reaped.set(true);
+                self.set_termination(sig, core_dumped);
+                Ok(Some(128 + sig as i32))
+            }
             Ok(WaitStatus::StillAlive) => Ok(None),
             Ok(_) => Ok(None), // Other statuses, continue waiting
             Err(e) => Err(SshpassError::SystemError(e)),
@@ -109,50 +216,173 @@ impl ChildProcess {
 
     /// Wait for the child process to exit (blocking)
     pub fn wait(&self) -> Result<i32> {
-        match waitpid(self.pid, None) {
-            Ok(WaitStatus::Exited(_, code)) => Ok(code),
-            Ok(WaitStatus::Signaled(_, sig, _)) => Ok(128 + sig as i32),
+        match self.wait4(None) {
+            Ok(WaitStatus::Exited(_, code)) => {
+                self.reaped.set(true);
+                Ok(code)
+            }
+            Ok(WaitStatus::Signaled(_, sig, core_dumped)) => {
+                self.reaped.set(true);
+                self.set_termination(sig, core_dumped);
+                Ok(128 + sig as i32)
+            }
             Ok(_) => Ok(255), // Unknown status
             Err(e) => Err(SshpassError::SystemError(e)),
         }
     }
 
+    /// waitpid that also collects the resources the child used
+    fn wait4(&self, flags: Option<WaitPidFlag>) -> nix::Result<WaitStatus> {
+        let mut status = 0;
+        // SAFETY: rusage is plain data, filled in by wait4
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        let flags = flags.map_or(0, |flags| flags.bits());
+        // SAFETY: both pointers are to locals that outlive the call
+        let pid = unsafe { libc::wait4(self.pid.as_raw(), &mut status, flags, &mut usage) };
+        let pid = nix::errno::Errno::result(pid)?;
+        if pid == 0 {
+            return Ok(WaitStatus::StillAlive);
+        }
+        let status = WaitStatus::from_raw(Pid::from_raw(pid), status)?;
+        if matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..)) {
+            self.usage.set(Some(resource_usage(&usage)));
+        }
+        Ok(status)
+    }
+
+    fn set_termination(&self, signal: Signal, core_dumped: bool) {
+        self.termination.set(Some(Termination {
+            signal: signal as i32,
+            core_dumped,
+        }));
+    }
+
+    /// The signal that killed the child and whether it dumped core, rather
+    /// than an exit code of 128 + N
+    pub fn termination(&self) -> Option<Termination> {
+        self.termination.get()
+    }
+
+    /// CPU time and peak memory of the child, once it has exited
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.usage.get()
+    }
+
     /// Send a signal to the child process
     pub fn kill(&self, signal: nix::sys::signal::Signal) -> Result<()> {
-        nix::sys::signal::kill(self.pid, signal).map_err(|e| SshpassError::SystemError(e))
+        nix::sys::signal::kill(self.pid, signal).map_err(SshpassError::SystemError)
     }
 }
 
 impl Drop for ChildProcess {
     fn drop(&mut self) {
-        // Close slave fd if we opened it
-        if let Some(fd) = self.slave_fd {
-            let _ = close(fd);
+        if self.kill_on_drop && !self.reaped.get() {
+            // The child leads its own session, so its group has its PID;
+            // until it has called setsid there is no such group yet
+            if nix::sys::signal::killpg(self.pid, Signal::SIGKILL).is_err() {
+                let _ = nix::sys::signal::kill(self.pid, Signal::SIGKILL);
+            }
+            let _ = waitpid(self.pid, None);
         }
     }
 }
 
+/// Convert what wait4 reported
+fn resource_usage(usage: &libc::rusage) -> ResourceUsage {
+    let time = |tv: libc::timeval| {
+        std::time::Duration::new(
+            tv.tv_sec.max(0) as u64,
+            tv.tv_usec.clamp(0, 999_999) as u32 * 1000,
+        )
+    };
+    // Kilobytes everywhere but on macOS, which counts bytes
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    ResourceUsage {
+        user_time: time(usage.ru_utime),
+        system_time: time(usage.ru_stime),
+        max_rss: Some(usage.ru_maxrss.max(0) as u64 * unit),
+    }
+}
+
+/// `KEY=VALUE` strings of the child's environment
+fn environment_block(vars: &[(OsString, OsString)]) -> Result<Vec<CString>> {
+    vars.iter()
+        .map(|(key, value)| {
+            let mut var = key.as_bytes().to_vec();
+            var.push(b'=');
+            var.extend_from_slice(value.as_bytes());
+            CString::new(var).map_err(|e| {
+                SshpassError::InvalidArguments(format!(
+                    "Invalid environment variable {:?}: {}",
+                    key, e
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Make `envp`, a null-terminated `KEY=VALUE` array, the process's
+/// environment, which execvp passes on and takes PATH from
+///
+/// # Safety
+///
+/// Only for the child between fork and exec; `envp` must outlive the exec.
+unsafe fn set_environ(envp: &[*const libc::c_char]) {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        *libc::_NSGetEnviron() = envp.as_ptr() as *mut *mut libc::c_char;
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    {
+        extern "C" {
+            static mut environ: *const *const libc::c_char;
+        }
+        environ = envp.as_ptr();
+    }
+}
+
+/// What the child needs to exec, all built before the fork
+struct ChildSetup<'a> {
+    slave_path: &'a CStr,
+    command: &'a [CString],
+    /// The environment, when it differs from sshpass's
+    envp: Option<&'a [*const libc::c_char]>,
+    cwd: Option<&'a CStr>,
+    /// Standard descriptors attached to the PTY
+    on_pty: &'a [RawFd],
+}
+
 /// Run the command in the child process
 ///
 /// This function sets up the child's environment and executes the command.
 /// It does not return on success (execvp replaces the process).
-fn run_child(pty: &Pty, command: &[String], verbose: bool) -> Result<()> {
+fn run_child(setup: &ChildSetup, verbose: bool) -> Result<()> {
+    let ChildSetup {
+        slave_path,
+        command,
+        envp,
+        cwd,
+        on_pty,
+    } = *setup;
+
     // Restore signal mask (unblock all signals)
     let empty_sigset = SigSet::empty();
     sigprocmask(SigmaskHow::SIG_SETMASK, Some(&empty_sigset), None)
-        .map_err(|e| SshpassError::SystemError(e))?;
+        .map_err(SshpassError::SystemError)?;
+
+    // Rust ignores SIGPIPE in sshpass, and an ignored signal stays ignored
+    // across exec
+    unsafe { signal(Signal::SIGPIPE, SigHandler::SigDfl) }.map_err(SshpassError::SystemError)?;
 
     // Create a new session (detach from current TTY)
     setsid()
         .map_err(|e| SshpassError::RuntimeError(format!("Failed to create new session: {}", e)))?;
 
-    // Open the slave PTY
-    let slave = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .custom_flags(OFlag::O_NOCTTY.bits())
-        .open(pty.slave_name())
+    // Open the slave PTY without allocating (the path was built before fork)
+    let slave = nix::fcntl::open(slave_path, OFlag::O_RDWR | OFlag::O_NOCTTY, Mode::empty())
         .map_err(|e| SshpassError::RuntimeError(format!("Failed to open slave PTY: {}", e)))?;
+    // SAFETY: open just returned this descriptor and nothing else owns it
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
 
     let slave_fd = slave.as_raw_fd();
 
@@ -166,30 +396,36 @@ fn run_child(pty: &Pty, command: &[String], verbose: bool) -> Result<()> {
         })?;
     }
 
-    // Close the slave fd (we don't need it open, it's now our controlling TTY)
-    drop(slave);
+    for &target in on_pty {
+        dup2(slave_fd, target).map_err(|e| {
+            SshpassError::RuntimeError(format!("Failed to attach PTY to stdio: {}", e))
+        })?;
+    }
 
-    if verbose {
-        eprintln!(
-            "SSHPASS: Child process set up PTY, executing: {:?}",
-            command
-        );
+    // Close the slave fd (we don't need it open, it's now our controlling TTY)
+    if on_pty.contains(&slave_fd) {
+        // The slave landed on one of the stdio fds; keep it open
+        std::mem::forget(slave);
+    } else {
+        drop(slave);
     }
 
-    // Prepare arguments for execvp
-    let c_strings: Result<Vec<CString>> = command
-        .iter()
-        .map(|s| {
-            CString::new(s.as_str()).map_err(|e| {
-                SshpassError::InvalidArguments(format!("Invalid argument '{}': {}", s, e))
-            })
-        })
-        .collect();
+    if let Some(dir) = cwd {
+        chdir(dir).map_err(|e| {
+            SshpassError::RuntimeError(format!("Failed to change directory to {:?}: {}", dir, e))
+        })?;
+    }
+    if let Some(envp) = envp {
+        // SAFETY: this is the child, and envp lives until the exec
+        unsafe { set_environ(envp) };
+    }
 
-    let c_strings = c_strings?;
+    if verbose {
+        crate::diag!("Child process set up PTY, executing: {:?}", command);
+    }
 
     // Execute the command (this replaces the current process)
-    execvp(&c_strings[0], &c_strings)
+    execvp(&command[0], command)
         .map_err(|e| SshpassError::ExecError(format!("Failed to execute command: {}", e)))?;
 
     // If execvp returns, it's an error
@@ -204,9 +440,9 @@ mod tests {
     fn test_child_process_spawn() {
         // Simple test that spawns echo
         let command = vec!["echo".to_string(), "test".to_string()];
-        let result = ChildProcess::spawn(&command, false);
+        let result = ChildProcess::spawn(&SpawnOptions::new(command));
 
-        if let Ok(mut child) = result {
+        if let Ok(child) = result {
             // Wait a bit for the process to complete
             std::thread::sleep(std::time::Duration::from_millis(100));
 
@@ -223,4 +459,95 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_child_resource_usage() {
+        let command = vec!["sh".to_string(), "-c".to_string(), "exit 3".to_string()];
+        let child = ChildProcess::spawn(&SpawnOptions::new(command)).unwrap();
+        assert_eq!(child.resource_usage(), None);
+        assert_eq!(child.wait().unwrap(), 3);
+        let usage = child.resource_usage().expect("wait4 reports the usage");
+        assert!(usage.max_rss.is_some_and(|bytes| bytes > 0));
+    }
+
+    #[test]
+    fn test_child_gets_default_sigpipe() {
+        // The test harness ignores SIGPIPE like sshpass does
+        let command = ["sh", "-c", "kill -PIPE $$; exit 0"].map(String::from);
+        let child = ChildProcess::spawn(&SpawnOptions::new(command)).unwrap();
+        assert_eq!(child.wait().unwrap(), 128 + libc::SIGPIPE);
+        let termination = child.termination().unwrap();
+        assert_eq!(termination.signal, libc::SIGPIPE);
+        assert_eq!(termination.signal_name(), Some("SIGPIPE"));
+        assert!(!termination.core_dumped);
+
+        // The same code from exit is not a signal
+        let command = ["sh", "-c", "exit 141"].map(String::from);
+        let child = ChildProcess::spawn(&SpawnOptions::new(command)).unwrap();
+        assert_eq!(child.wait().unwrap(), 141);
+        assert_eq!(child.termination(), None);
+    }
+
+    #[test]
+    fn test_child_controlling_terminal() {
+        // /dev/tty only opens for a process with a controlling terminal, and
+        // tty names the PTY the child's stdin is on
+        let command = ["sh", "-c", ": </dev/tty && tty"].map(String::from);
+        let child = ChildProcess::spawn(&SpawnOptions::new(command).interactive()).unwrap();
+        assert_eq!(child.wait().unwrap(), 0);
+        assert_eq!(pty_output(&child).trim_end(), child.pty.slave_name());
+    }
+
+    #[test]
+    fn test_child_spawn_options() {
+        let script = "pwd; echo \"$SSHPASS_TEST_VAR ${HOME-unset}\"; stty size";
+        let options = SpawnOptions::new(["sh", "-c", script])
+            .cwd("/")
+            .env_remove("HOME")
+            .env("SSHPASS_TEST_VAR", "set")
+            .size(30, 100)
+            .interactive();
+        let child = ChildProcess::spawn(&options).unwrap();
+        assert_eq!(child.wait().unwrap(), 0);
+        assert_eq!(pty_output(&child), "/\r\nset unset\r\n30 100\r\n");
+
+        // Only PATH, for sh to be found
+        let path = std::env::var_os("PATH").unwrap();
+        let options = SpawnOptions::new(["sh", "-c", "env | grep -v '^PWD='"])
+            .env_clear()
+            .env("PATH", &path)
+            .interactive();
+        let child = ChildProcess::spawn(&options).unwrap();
+        assert_eq!(child.wait().unwrap(), 0);
+        let expected = format!("PATH={}\r\n", path.to_string_lossy());
+        assert_eq!(pty_output(&child), expected);
+    }
+
+    #[test]
+    fn test_child_kill_on_drop() {
+        let options = SpawnOptions::new(["sleep", "10"]).kill_on_drop(true);
+        let child = ChildProcess::spawn(&options).unwrap();
+        let pid = child.pid;
+        drop(child);
+        // Killed and reaped
+        assert_eq!(
+            nix::sys::signal::kill(pid, None),
+            Err(nix::errno::Errno::ESRCH)
+        );
+
+        // Nothing to do once the child was waited for
+        let options = SpawnOptions::new(["true"]).kill_on_drop(true);
+        let child = ChildProcess::spawn(&options).unwrap();
+        assert_eq!(child.wait().unwrap(), 0);
+    }
+
+    /// What the child wrote to the PTY
+    fn pty_output(child: &ChildProcess) -> String {
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 256];
+        while let Ok(n @ 1..) = child.pty.read(&mut buffer) {
+            output.extend_from_slice(&buffer[..n]);
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    }
 }
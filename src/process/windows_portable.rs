@@ -1,38 +1,109 @@
 //! Windows 子行程管理（使用 portable-pty）
 
+use super::{EnvPolicy, ResourceUsage, SpawnOptions};
 use crate::error::Result;
-use crate::pty::{Pty, PtyPair};
+use crate::pty::{Pty, PtyOptions, PtyPair};
 
 /// 使用 PTY 包裝的 Windows 子行程
 pub struct ChildProcess {
     pair: PtyPair,
     pub process_id: Option<u32>,
+    /// 卸除時若尚未結束則終止子行程
+    kill_on_drop: bool,
+    /// 是否已等到子行程結束
+    exited: bool,
+    /// 子行程結束後使用的 CPU 時間
+    usage: Option<ResourceUsage>,
 }
 
 impl ChildProcess {
-    /// 建立子行程並接上 PTY
-    pub fn spawn(command: &[String], verbose: bool) -> Result<Self> {
-        let pair = PtyPair::spawn(command, verbose)?;
+    /// 依 `options` 建立子行程並接上 PTY
+    ///
+    /// ConPTY 子行程的標準輸入輸出一律接在 PTY 上，`options` 的 stdio 設定不影響。
+    /// 未指定大小時 PTY 一開始就採用主控台的大小，ssh 啟動時向伺服器要求的終端機
+    /// 大小才會正確。
+    pub fn spawn(options: &SpawnOptions) -> Result<Self> {
+        let verbose = options.verbose;
+        let mut pty_options = PtyOptions::new().verbose(verbose);
+        if let Some((rows, cols)) = options.size.or_else(crate::signal::current_console_size) {
+            pty_options = pty_options.size(rows, cols);
+        }
+        if let Some(dir) = &options.cwd {
+            pty_options = pty_options.cwd(dir);
+        }
+        if options.env_policy == EnvPolicy::Clear {
+            pty_options = pty_options.env_clear();
+        }
+        for (key, value) in &options.env {
+            pty_options = match value {
+                Some(value) => pty_options.env(key, value),
+                None => pty_options.env_remove(key),
+            };
+        }
+        let pair = PtyPair::spawn(&options.command, &pty_options)?;
 
         let process_id = pair.child.process_id();
 
         if verbose {
             if let Some(pid) = process_id {
-                eprintln!("SSHPASS: Child process spawned with PID: {}", pid);
+                crate::diag!("Child process spawned with PID: {}", pid);
             }
         }
 
-        Ok(Self { pair, process_id })
+        Ok(Self {
+            pair,
+            process_id,
+            kill_on_drop: options.kill_on_drop,
+            exited: false,
+            usage: None,
+        })
     }
 
     /// 嘗試非阻塞等待，若仍在執行則回傳 None
     pub fn try_wait(&mut self) -> Result<Option<i32>> {
-        self.pair.try_wait()
+        let status = self.pair.try_wait()?;
+        if status.is_some() && !self.exited {
+            self.exited = true;
+            self.usage = self.process_times();
+        }
+        Ok(status)
     }
 
     /// 阻塞等待子行程結束
     pub fn wait(&mut self) -> Result<i32> {
-        self.pair.wait()
+        let code = self.pair.wait()?;
+        if !self.exited {
+            self.exited = true;
+            self.usage = self.process_times();
+        }
+        Ok(code)
+    }
+
+    /// 子行程結束後的 CPU 時間；Windows 不提供峰值記憶體
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.usage
+    }
+
+    /// 以 GetProcessTimes 讀取已結束子行程的使用者與核心時間
+    fn process_times(&self) -> Option<ResourceUsage> {
+        use windows::Win32::Foundation::{FILETIME, HANDLE};
+        use windows::Win32::System::Threading::GetProcessTimes;
+
+        let handle = HANDLE(self.pair.child.as_raw_handle()? as isize);
+        let mut times = [FILETIME::default(); 4];
+        let [created, exited, kernel, user] = &mut times;
+        // SAFETY: 行程物件在 child 被釋放前都有效，四個指標指向本地變數
+        unsafe { GetProcessTimes(handle, created, exited, kernel, user) }.ok()?;
+        // FILETIME 以 100 奈秒為單位
+        let duration = |time: &FILETIME| {
+            let ticks = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+            std::time::Duration::from_nanos(ticks * 100)
+        };
+        Some(ResourceUsage {
+            user_time: duration(&times[3]),
+            system_time: duration(&times[2]),
+            max_rss: None,
+        })
     }
 
     /// 強制終止子行程
@@ -57,3 +128,11 @@ impl ChildProcess {
         &self.pair.pty
     }
 }
+
+impl Drop for ChildProcess {
+    fn drop(&mut self) {
+        if self.kill_on_drop && !self.exited {
+            let _ = self.pair.kill();
+        }
+    }
+}
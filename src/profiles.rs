@@ -0,0 +1,689 @@
+//! Prompt profiles (`--profile`)
+//!
+//! A profile bundles what sshpass needs to know about a kind of login: the
+//! password prompt to search for, which line ending the remote side expects,
+//! and setup lines to type once the login has finished (for example turning
+//! off paging on network devices).
+//!
+//! Built-in profiles are embedded below. A user can add a profile, or
+//! override a built-in one, by placing `NAME.toml` in the `sshpass/profiles`
+//! directory under the user's config directory. A file can also be given
+//! directly with `--profile-file`, in which case it is layered over the
+//! `--profile` profile. Every key is optional:
+//!
+//! ```toml
+//! prompt = "assword:"
+//! newline = "cr"              # cr, lf or crlf
+//! ready = ["#", ">"]          # shell prompt that ends the login
+//! setup = ["terminal length 0"]
+//! abort = ["% Authorization failed"]
+//! prompt_timeout = 30         # seconds to wait for the password prompt
+//! confirm_timeout = 60        # see --confirm-timeout
+//!
+//! [[responses]]               # answered every time the text appears
+//! expect = "Username:"
+//! send = "admin"
+//!
+//! [[plugins]]                 # detector plugin, see `plugins`
+//! path = "/usr/local/lib/sshpass/acme.so"
+//! config = "model=x200"       # passed to the plugin as is
+//! ```
+//!
+//! Command line flags (`-P`, `--prompt-timeout`, `--confirm-timeout`) take
+//! precedence over the profile.
+//!
+//! Profile files need the default `profile-files` feature; without it only the
+//! built-in profiles are available.
+
+use crate::error::{Result, SshpassError};
+use crate::keymap::EnterMode;
+use crate::monitor::{Matcher, PromptMatch};
+use crate::password::SecureString;
+#[cfg(feature = "profile-files")]
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// A resolved prompt profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Profile name
+    pub name: String,
+    /// Password prompt to search for
+    pub prompt: String,
+    /// Prompt asking for the same secret a second time (e.g. when ssh-keygen
+    /// sets a new passphrase), answered with the password again
+    pub repeat_prompt: Option<String>,
+    /// Line ending sent after the password and setup lines (None keeps the
+    /// platform default)
+    pub newline: Option<EnterMode>,
+    /// Patterns that show the remote shell is ready for the setup lines
+    pub ready: Vec<String>,
+    /// Lines typed once the login has finished
+    pub setup: Vec<String>,
+    /// Other prompts answered automatically before the login finishes
+    pub responses: Vec<Response>,
+    /// Output that makes sshpass give up
+    pub abort: Vec<String>,
+    /// Seconds to wait for the password prompt
+    pub prompt_timeout: Option<u64>,
+    /// Seconds to wait for a security key confirmation (0 waits forever)
+    pub confirm_timeout: Option<u64>,
+    /// Whether the command is an ssh login, whose host key and security key
+    /// messages are watched for
+    pub ssh_checks: bool,
+    /// Detector plugins to load
+    pub plugins: Vec<PluginSpec>,
+}
+
+/// A prompt and the line typed in reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "profile-files",
+    derive(Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct Response {
+    /// Text to search for
+    pub expect: String,
+    /// Line to send when it appears
+    pub send: String,
+}
+
+/// A detector plugin named in a profile file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "profile-files",
+    derive(Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct PluginSpec {
+    /// Shared library to load
+    pub path: PathBuf,
+    /// Settings handed to the plugin when it is created
+    pub config: Option<String>,
+}
+
+impl Profile {
+    /// A profile with nothing but the default prompt
+    fn plain(name: &str) -> Self {
+        Profile {
+            name: name.to_string(),
+            prompt: "assword".to_string(),
+            repeat_prompt: None,
+            newline: None,
+            ready: Vec::new(),
+            setup: Vec::new(),
+            responses: Vec::new(),
+            abort: Vec::new(),
+            prompt_timeout: None,
+            confirm_timeout: None,
+            ssh_checks: true,
+            plugins: Vec::new(),
+        }
+    }
+}
+
+/// Entry of the embedded profile registry
+struct BuiltinProfile {
+    name: &'static str,
+    prompt: &'static str,
+    repeat_prompt: Option<&'static str>,
+    newline: Option<EnterMode>,
+    ready: &'static [&'static str],
+    setup: &'static [&'static str],
+    /// Commands that select this profile automatically
+    commands: &'static [&'static str],
+    ssh_checks: bool,
+}
+
+const BUILTIN_PROFILES: &[BuiltinProfile] = &[
+    BuiltinProfile {
+        name: "openssh",
+        prompt: "assword",
+        repeat_prompt: None,
+        newline: None,
+        ready: &[],
+        setup: &[],
+        commands: &[],
+        ssh_checks: true,
+    },
+    BuiltinProfile {
+        name: "keytools",
+        prompt: "passphrase",
+        repeat_prompt: Some("same passphrase"),
+        newline: None,
+        ready: &[],
+        setup: &[],
+        commands: &["ssh-add", "ssh-keygen"],
+        ssh_checks: true,
+    },
+    // Local privilege prompts; ssh's messages never appear, and the output
+    // of the command run is no place to look for them
+    BuiltinProfile {
+        name: "sudo",
+        prompt: "assword",
+        repeat_prompt: None,
+        newline: None,
+        ready: &[],
+        setup: &[],
+        commands: &["sudo", "su", "doas"],
+        ssh_checks: false,
+    },
+    BuiltinProfile {
+        name: "telnet",
+        prompt: "assword:",
+        repeat_prompt: None,
+        newline: Some(EnterMode::Cr),
+        ready: &[],
+        setup: &[],
+        commands: &["telnet"],
+        ssh_checks: false,
+    },
+    BuiltinProfile {
+        name: "cisco-ios",
+        prompt: "assword:",
+        repeat_prompt: None,
+        newline: Some(EnterMode::Cr),
+        ready: &["#", ">"],
+        setup: &["terminal length 0", "terminal width 0"],
+        commands: &[],
+        ssh_checks: true,
+    },
+    BuiltinProfile {
+        name: "junos",
+        prompt: "assword:",
+        repeat_prompt: None,
+        newline: Some(EnterMode::Lf),
+        ready: &["> ", "# "],
+        setup: &["set cli screen-length 0", "set cli screen-width 0"],
+        commands: &[],
+        ssh_checks: true,
+    },
+    BuiltinProfile {
+        name: "mikrotik",
+        prompt: "assword:",
+        repeat_prompt: None,
+        newline: Some(EnterMode::Crlf),
+        ready: &["] > "],
+        setup: &[],
+        commands: &[],
+        ssh_checks: true,
+    },
+    BuiltinProfile {
+        name: "fortinet",
+        prompt: "assword:",
+        repeat_prompt: None,
+        newline: Some(EnterMode::Lf),
+        ready: &["# ", "$ "],
+        setup: &["config system console", "set output standard", "end"],
+        commands: &[],
+        ssh_checks: true,
+    },
+];
+
+impl BuiltinProfile {
+    fn to_profile(&self) -> Profile {
+        Profile {
+            name: self.name.to_string(),
+            prompt: self.prompt.to_string(),
+            repeat_prompt: self.repeat_prompt.map(str::to_string),
+            newline: self.newline,
+            ready: self.ready.iter().map(|s| s.to_string()).collect(),
+            setup: self.setup.iter().map(|s| s.to_string()).collect(),
+            ssh_checks: self.ssh_checks,
+            ..Profile::plain(self.name)
+        }
+    }
+}
+
+/// On-disk representation of a user profile
+#[cfg(feature = "profile-files")]
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    prompt: Option<String>,
+    repeat_prompt: Option<String>,
+    newline: Option<String>,
+    ready: Option<Vec<String>>,
+    setup: Option<Vec<String>>,
+    responses: Option<Vec<Response>>,
+    abort: Option<Vec<String>>,
+    prompt_timeout: Option<u64>,
+    confirm_timeout: Option<u64>,
+    plugins: Option<Vec<PluginSpec>>,
+}
+
+#[cfg(feature = "profile-files")]
+impl ProfileFile {
+    /// Override the fields of `base` that the file sets
+    fn apply_to(self, mut base: Profile) -> Result<Profile> {
+        if let Some(ref value) = self.newline {
+            base.newline = Some(parse_newline(value)?);
+        }
+        if let Some(prompt) = self.prompt {
+            base.prompt = prompt;
+        }
+        if self.repeat_prompt.is_some() {
+            base.repeat_prompt = self.repeat_prompt;
+        }
+        if let Some(ready) = self.ready {
+            base.ready = ready;
+        }
+        if let Some(setup) = self.setup {
+            base.setup = setup;
+        }
+        if let Some(responses) = self.responses {
+            base.responses = responses;
+        }
+        if let Some(abort) = self.abort {
+            base.abort = abort;
+        }
+        if self.prompt_timeout.is_some() {
+            base.prompt_timeout = self.prompt_timeout;
+        }
+        if self.confirm_timeout.is_some() {
+            base.confirm_timeout = self.confirm_timeout;
+        }
+        if let Some(plugins) = self.plugins {
+            base.plugins = plugins;
+        }
+
+        if base.prompt.is_empty() || base.responses.iter().any(|r| r.expect.is_empty()) {
+            return Err(SshpassError::InvalidArguments(format!(
+                "Profile {} has an empty prompt",
+                base.name
+            )));
+        }
+        Ok(base)
+    }
+}
+
+#[cfg(feature = "profile-files")]
+fn parse_newline(value: &str) -> Result<EnterMode> {
+    value.parse::<EnterMode>().map_err(|_| {
+        SshpassError::InvalidArguments(format!(
+            "Invalid newline \"{}\" (expected cr, lf or crlf)",
+            value
+        ))
+    })
+}
+
+/// Names of the built-in profiles
+pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+    BUILTIN_PROFILES.iter().map(|p| p.name)
+}
+
+/// Look up a built-in profile by name
+pub fn builtin(name: &str) -> Option<Profile> {
+    BUILTIN_PROFILES
+        .iter()
+        .find(|p| p.name == name)
+        .map(BuiltinProfile::to_profile)
+}
+
+/// Pick a built-in profile from the name of the command being run
+pub fn detect(program: &str) -> Option<Profile> {
+    let name = Path::new(program).file_name()?.to_str()?;
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+    BUILTIN_PROFILES
+        .iter()
+        .find(|p| p.commands.contains(&name))
+        .map(BuiltinProfile::to_profile)
+}
+
+/// Directory holding user profiles
+#[cfg(feature = "profile-files")]
+pub fn user_profile_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+
+    base.map(|dir| dir.join("sshpass").join("profiles"))
+}
+
+/// Parse a profile from TOML text
+#[cfg(feature = "profile-files")]
+pub fn parse_profile(name: &str, text: &str) -> Result<Profile> {
+    overlay(Profile::plain(name), text)
+}
+
+/// Apply the settings in TOML text on top of an existing profile
+#[cfg(feature = "profile-files")]
+pub fn overlay(base: Profile, text: &str) -> Result<Profile> {
+    let file: ProfileFile = toml::from_str(text).map_err(|e| {
+        SshpassError::InvalidArguments(format!("Invalid profile {}: {}", base.name, e))
+    })?;
+    file.apply_to(base)
+}
+
+/// Read a profile file and layer it over `base` (or the default prompt)
+#[cfg(feature = "profile-files")]
+pub fn load_file(path: &Path, base: Option<Profile>) -> Result<Profile> {
+    let text = read_profile_file(path)?;
+    let base = base.unwrap_or_else(|| {
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+        Profile::plain(name)
+    });
+    overlay(base, &text)
+}
+
+/// Read a profile file (not supported in this build)
+#[cfg(not(feature = "profile-files"))]
+pub fn load_file(_path: &Path, _base: Option<Profile>) -> Result<Profile> {
+    Err(SshpassError::InvalidArguments(
+        "--profile-file requires sshpass built with the `profile-files` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "profile-files")]
+fn read_profile_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| {
+        SshpassError::InvalidArguments(format!("Failed to read profile {}: {}", path.display(), e))
+    })
+}
+
+/// Resolve a profile by name, preferring a user file over the built-in one
+pub fn lookup(name: &str) -> Result<Profile> {
+    #[cfg(feature = "profile-files")]
+    {
+        let is_plain_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if is_plain_name {
+            if let Some(dir) = user_profile_dir() {
+                let path = dir.join(format!("{}.toml", name));
+                if path.is_file() {
+                    return parse_profile(name, &read_profile_file(&path)?);
+                }
+            }
+        }
+    }
+
+    builtin(name).ok_or_else(|| {
+        SshpassError::InvalidArguments(format!(
+            "Unknown profile \"{}\" (built-in: {})",
+            name,
+            builtin_names().collect::<Vec<_>>().join(", ")
+        ))
+    })
+}
+
+/// Bytes given on the command line, with `\r`, `\n`, `\t`, `\e`, `\\` and
+/// `\xHH` escapes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyBytes(pub Vec<u8>);
+
+/// Parse `--ack-send`
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_reply_bytes(value: &str) -> std::result::Result<ReplyBytes, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('e') => bytes.push(0x1b),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| format!("invalid \\x escape in '{}'", value))?;
+                bytes.push(byte);
+            }
+            _ => return Err(format!("invalid escape in '{}'", value)),
+        }
+    }
+
+    Ok(ReplyBytes(bytes))
+}
+
+/// Picks the password for a prompt from the line that asked for it, None
+/// meaning the session's own password
+pub type PasswordChooser<'a> = Box<dyn Fn(&str) -> Option<&'a SecureString> + 'a>;
+
+/// What sshpass types into the session: the password and, once the remote
+/// shell is ready, the profile's setup lines
+pub struct LoginScript<'a> {
+    password: &'a SecureString,
+    /// For logins asking for several passwords, such as through bastions
+    chooser: Option<PasswordChooser<'a>>,
+    new_password: Option<&'a SecureString>,
+    newline: Vec<u8>,
+    ready: Vec<Matcher>,
+    setup: Vec<String>,
+    /// Complete replies, in the order their prompts were given to the monitor
+    responses: Vec<Vec<u8>>,
+}
+
+impl<'a> LoginScript<'a> {
+    /// Create the login script
+    ///
+    /// # Arguments
+    /// * `password` - Password to send at the prompt
+    /// * `profile` - Active profile, if any
+    /// * `default_newline` - Line ending used when the profile does not set one
+    pub fn new(
+        password: &'a SecureString,
+        profile: Option<&Profile>,
+        default_newline: &[u8],
+    ) -> Self {
+        let newline = profile
+            .and_then(|p| p.newline)
+            .map(EnterMode::as_bytes)
+            .unwrap_or(default_newline);
+
+        let mut script = Self {
+            password,
+            chooser: None,
+            new_password: None,
+            newline: newline.to_vec(),
+            ready: profile
+                .map(|p| p.ready.iter().map(Matcher::new).collect())
+                .unwrap_or_default(),
+            setup: profile.map(|p| p.setup.clone()).unwrap_or_default(),
+            responses: Vec::new(),
+        };
+        for response in profile.map(|p| p.responses.as_slice()).unwrap_or_default() {
+            let mut reply = response.send.as_bytes().to_vec();
+            reply.extend_from_slice(&script.newline);
+            script.responses.push(reply);
+        }
+        script
+    }
+
+    /// Add a reply sent as-is (None sends just the line ending)
+    pub fn push_response(&mut self, reply: Option<&[u8]>) {
+        let reply = reply.unwrap_or(&self.newline).to_vec();
+        self.responses.push(reply);
+    }
+
+    /// Reply for the response prompt at `index`
+    pub fn response_line(&self, index: usize) -> &[u8] {
+        self.responses.get(index).map_or(&[], Vec::as_slice)
+    }
+
+    /// Password to set when the server forces a password change
+    pub fn with_new_password(mut self, new_password: Option<&'a SecureString>) -> Self {
+        self.new_password = new_password;
+        self
+    }
+
+    /// Choose the password by the prompt instead of always sending the same
+    pub fn with_password_chooser(mut self, chooser: PasswordChooser<'a>) -> Self {
+        self.chooser = Some(chooser);
+        self
+    }
+
+    /// Password for `prompt` followed by the line ending
+    pub fn password_line(&self, prompt: Option<&PromptMatch>) -> Zeroizing<Vec<u8>> {
+        let chosen = self
+            .chooser
+            .as_ref()
+            .zip(prompt)
+            .and_then(|(chooser, prompt)| chooser(&prompt.line()));
+        chosen
+            .unwrap_or(self.password)
+            .with_terminator(&self.newline)
+    }
+
+    /// New password followed by the line ending (empty without one)
+    pub fn new_password_line(&self) -> Zeroizing<Vec<u8>> {
+        match self.new_password {
+            Some(password) => password.with_terminator(&self.newline),
+            None => Zeroizing::new(Vec::new()),
+        }
+    }
+
+    /// Feed output received after the password was sent
+    ///
+    /// Returns the setup lines once one of the ready patterns has been seen
+    /// (or on the first output when the profile defines none).
+    pub fn feed(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        if self.setup.is_empty() || data.is_empty() {
+            return None;
+        }
+
+        let ready = self.ready.is_empty() || self.ready.iter_mut().any(|m| m.feed(data));
+        if !ready {
+            return None;
+        }
+
+        let mut input = Vec::new();
+        for line in self.setup.drain(..) {
+            input.extend_from_slice(line.as_bytes());
+            input.extend_from_slice(&self.newline);
+        }
+        Some(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_profiles() {
+        for name in ["openssh", "cisco-ios", "junos", "mikrotik", "fortinet"] {
+            assert!(builtin(name).is_some(), "missing profile {}", name);
+        }
+        assert!(builtin("nonexistent").is_none());
+
+        let ios = builtin("cisco-ios").unwrap();
+        assert_eq!(ios.newline, Some(EnterMode::Cr));
+        assert_eq!(ios.setup[0], "terminal length 0");
+    }
+
+    #[test]
+    fn test_detect_profile_from_command() {
+        assert_eq!(detect("ssh-add").unwrap().name, "keytools");
+        assert_eq!(detect("/usr/bin/ssh-keygen").unwrap().name, "keytools");
+        assert_eq!(detect("ssh-keygen.exe").unwrap().name, "keytools");
+        assert!(detect("ssh").is_none());
+
+        let sudo = detect("/usr/bin/sudo").unwrap();
+        assert_eq!(sudo.name, "sudo");
+        assert!(!sudo.ssh_checks);
+        assert_eq!(detect("su").unwrap().name, "sudo");
+        let telnet = detect("telnet.exe").unwrap();
+        assert_eq!(telnet.newline, Some(EnterMode::Cr));
+        assert!(!telnet.ssh_checks);
+        assert!(builtin("openssh").unwrap().ssh_checks);
+    }
+
+    #[cfg(feature = "profile-files")]
+    #[test]
+    fn test_parse_user_profile() {
+        let profile = parse_profile(
+            "lab",
+            "prompt = \"PIN:\"\nnewline = \"crlf\"\nsetup = [\"no pager\"]\n",
+        )
+        .unwrap();
+        assert_eq!(profile.prompt, "PIN:");
+        assert_eq!(profile.newline, Some(EnterMode::Crlf));
+        assert_eq!(profile.setup, vec!["no pager".to_string()]);
+
+        assert!(parse_profile("lab", "newline = \"nl\"\n").is_err());
+        assert!(parse_profile("lab", "unknown = 1\n").is_err());
+    }
+
+    #[cfg(feature = "profile-files")]
+    #[test]
+    fn test_overlay_profile_file() {
+        let text = r#"
+            abort = ["% Authorization failed"]
+            prompt_timeout = 30
+
+            [[responses]]
+            expect = "Username:"
+            send = "admin"
+
+            [[plugins]]
+            path = "/opt/acme/detector.so"
+        "#;
+        let profile = overlay(builtin("cisco-ios").unwrap(), text).unwrap();
+
+        // Settings the file does not mention come from the base profile
+        assert_eq!(profile.name, "cisco-ios");
+        assert_eq!(profile.prompt, "assword:");
+        assert_eq!(profile.newline, Some(EnterMode::Cr));
+        assert_eq!(profile.abort, vec!["% Authorization failed".to_string()]);
+        assert_eq!(profile.prompt_timeout, Some(30));
+        assert_eq!(profile.responses[0].expect, "Username:");
+        assert_eq!(
+            profile.plugins,
+            vec![PluginSpec {
+                path: PathBuf::from("/opt/acme/detector.so"),
+                config: None,
+            }]
+        );
+
+        let password = SecureString::new("secret".to_string());
+        let script = LoginScript::new(&password, Some(&profile), b"\n");
+        assert_eq!(script.response_line(0), b"admin\r");
+
+        assert!(overlay(Profile::plain("x"), "prompt = \"\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_reply_bytes() {
+        assert_eq!(parse_reply_bytes("y\\r"), Ok(ReplyBytes(b"y\r".to_vec())));
+        assert_eq!(
+            parse_reply_bytes("\\x20\\\\"),
+            Ok(ReplyBytes(b" \\".to_vec()))
+        );
+        assert!(parse_reply_bytes("\\x2").is_err());
+        assert!(parse_reply_bytes("\\q").is_err());
+    }
+
+    #[test]
+    fn test_login_script_sends_setup_when_ready() {
+        let password = SecureString::new("secret".to_string());
+        let profile = builtin("cisco-ios").unwrap();
+        let mut script = LoginScript::new(&password, Some(&profile), b"\n");
+
+        assert_eq!(&script.password_line(None)[..], b"secret\r");
+        assert_eq!(script.feed(b"\r\nBanner text\r\n"), None);
+        assert_eq!(
+            script.feed(b"router#").unwrap(),
+            b"terminal length 0\rterminal width 0\r".to_vec()
+        );
+        // Setup lines are only sent once
+        assert_eq!(script.feed(b"router#"), None);
+    }
+}
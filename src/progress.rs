@@ -0,0 +1,286 @@
+//! Progress indicator while waiting for the password prompt (`--progress`)
+//!
+//! A login can sit silent for a while before the server asks for the
+//! password (DNS, GSSAPI, a slow key exchange), which looks like a hang.
+//! With `--progress auto`, the default, and stderr a terminal without `-v`,
+//! sshpass draws a one-line spinner with the elapsed time on stderr after a
+//! second of silence. The line is cleared as soon as ssh writes to its
+//! terminal, the status of the session changes or it ends.
+//!
+//! When ssh logs in without asking for a password it writes nothing to its
+//! terminal, so the indicator would run on during the session; it is not
+//! shown with `--persist` for that reason, and `--progress never` turns it
+//! off for logins with keys.
+//!
+//! `--progress-io` adds a [`TransferMeter`] for after the login: once the
+//! data sshpass forwards flows faster than 256 KiB/s, a line like pv's shows
+//! the bytes received and sent, the rate and the elapsed time. It counts
+//! what passes through sshpass: output sent to `--output` or `--tee` and
+//! input read from `--input`; a pipe the child reads or writes directly is
+//! not seen.
+
+use crate::session::{SessionEvent, SessionObserver, SessionStatus};
+use crate::stats::{SessionStats, Throughput};
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Silence before the indicator appears
+const DELAY: Duration = Duration::from_secs(1);
+
+/// Time between two frames
+const TICK: Duration = Duration::from_millis(100);
+
+const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Rate at which the transfer meter appears, in bytes per second
+const TRANSFER_THRESHOLD: f64 = 256.0 * 1024.0;
+
+/// Time between two redraws of the transfer meter
+const TRANSFER_TICK: Duration = Duration::from_millis(250);
+
+/// `--progress` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub enum ProgressMode {
+    /// When stderr is a terminal and -v is off
+    #[default]
+    Auto,
+    Never,
+}
+
+/// Whether the indicator is shown
+pub fn wanted(mode: ProgressMode, verbose: bool) -> bool {
+    use std::io::IsTerminal;
+
+    mode == ProgressMode::Auto && !verbose && std::io::stderr().is_terminal()
+}
+
+/// Whether the `--progress-io` meter is drawn: only on a terminal
+pub fn transfer_wanted(requested: bool) -> bool {
+    use std::io::IsTerminal;
+
+    requested && std::io::stderr().is_terminal()
+}
+
+/// Draws the indicator while the session waits for the prompt
+#[derive(Default)]
+pub struct Progress {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn start(&mut self) {
+        if self.thread.is_some() {
+            return;
+        }
+        let (stop, stopped) = mpsc::channel();
+        self.stop = Some(stop);
+        self.thread = std::thread::Builder::new()
+            .name("sshpass-progress".to_string())
+            .spawn(move || draw(&stopped, std::io::stderr(), DELAY, TICK))
+            .ok();
+    }
+
+    /// Stop drawing and wait until the line is cleared
+    fn stop(&mut self) {
+        // Dropping the sender wakes the thread
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl SessionObserver for Progress {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        match event {
+            SessionEvent::Status(SessionStatus::WaitingForPrompt) => self.start(),
+            SessionEvent::Status(SessionStatus::Starting) => {}
+            _ => self.stop(),
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The `--progress-io` line, drawn on stderr from the event loop
+pub struct TransferMeter {
+    throughput: Throughput,
+    last_draw: Option<Instant>,
+    drawn: usize,
+}
+
+impl TransferMeter {
+    pub fn new() -> Self {
+        Self {
+            throughput: Throughput::new(Duration::from_secs(1), Instant::now()),
+            last_draw: None,
+            drawn: 0,
+        }
+    }
+
+    /// Redraw the line after data was forwarded
+    pub fn update(&mut self, stats: &SessionStats) {
+        if let Some(line) = self.line_at(Instant::now(), stats) {
+            let mut out = std::io::stderr().lock();
+            let _ = write!(out, "\r{:width$}", line, width = self.drawn);
+            let _ = out.flush();
+            self.drawn = self.drawn.max(line.len());
+        }
+    }
+
+    /// The line to draw at `now`, if the data flows fast enough and the
+    /// last one is old enough
+    fn line_at(&mut self, now: Instant, stats: &SessionStats) -> Option<String> {
+        let rate = self
+            .throughput
+            .sample(now, stats.bytes_from_child + stats.bytes_to_child);
+        match self.last_draw {
+            None if rate < TRANSFER_THRESHOLD => return None,
+            Some(last) if now.saturating_duration_since(last) < TRANSFER_TICK => return None,
+            _ => {}
+        }
+        self.last_draw = Some(now);
+        let elapsed = stats.elapsed().as_secs();
+        Some(format!(
+            "received {}, sent {}, {}/s, {}:{:02}",
+            human_bytes(stats.bytes_from_child as f64),
+            human_bytes(stats.bytes_to_child as f64),
+            human_bytes(rate),
+            elapsed / 60,
+            elapsed % 60
+        ))
+    }
+}
+
+impl Default for TransferMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Leaves the last line on the screen, as pv does
+impl Drop for TransferMeter {
+    fn drop(&mut self) {
+        if self.drawn > 0 {
+            let _ = writeln!(std::io::stderr());
+        }
+    }
+}
+
+/// `bytes` in B, KiB, MiB or GiB
+fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", value as u64)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Draw frames to `out` until `stopped` fires, then clear the line
+fn draw(stopped: &mpsc::Receiver<()>, mut out: impl Write, delay: Duration, tick: Duration) {
+    let started = Instant::now();
+    let mut timeout = delay;
+    let mut drawn = 0;
+    let mut frame = 0;
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout) {
+        timeout = tick;
+        let line = format!(
+            "{} Waiting for the password prompt ({}s)",
+            FRAMES[frame % FRAMES.len()],
+            started.elapsed().as_secs()
+        );
+        frame += 1;
+        // Pad over a longer line drawn before
+        let _ = write!(out, "\r{:width$}", line, width = drawn);
+        let _ = out.flush();
+        drawn = drawn.max(line.len());
+    }
+    if drawn > 0 {
+        let _ = write!(out, "\r{}\r", " ".repeat(drawn));
+        let _ = out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_and_clear() {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let drawer = std::thread::spawn(move || {
+            let mut out = Vec::new();
+            draw(&stopped, &mut out, Duration::ZERO, Duration::from_millis(5));
+            out
+        });
+        std::thread::sleep(Duration::from_millis(30));
+        drop(stop);
+        let out = String::from_utf8(drawer.join().unwrap()).unwrap();
+        assert!(out.starts_with("\r| Waiting for the password prompt (0s)"));
+        assert!(out.contains("\r/ Waiting"));
+        let width = "| Waiting for the password prompt (0s)".len();
+        assert!(out.ends_with(&format!("\r{}\r", " ".repeat(width))));
+
+        // Stopped before the delay: nothing is drawn
+        let (stop, stopped) = mpsc::channel::<()>();
+        drop(stop);
+        let mut out = Vec::new();
+        draw(&stopped, &mut out, Duration::from_secs(60), TICK);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_transfer_meter() {
+        let mut meter = TransferMeter::new();
+        let now = Instant::now() + Duration::from_secs(1);
+        let mut stats = SessionStats::new();
+
+        // Slow data is not shown
+        stats.record_output(1024);
+        assert_eq!(meter.line_at(now, &stats), None);
+
+        stats.record_output(3 * 1024 * 1024);
+        stats.record_input(10);
+        let later = now + Duration::from_secs(1);
+        let line = meter.line_at(later, &stats).unwrap();
+        assert!(
+            line.starts_with("received 3.0 MiB, sent 10 B, 3.0 MiB/s, 0:0"),
+            "{}",
+            line
+        );
+        // Redrawn at most every tick
+        assert_eq!(meter.line_at(later, &stats), None);
+        assert!(meter.line_at(later + TRANSFER_TICK, &stats).is_some());
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(0.0), "0 B");
+        assert_eq!(human_bytes(1023.0), "1023 B");
+        assert_eq!(human_bytes(1536.0), "1.5 KiB");
+        assert_eq!(
+            human_bytes(5.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0),
+            "5120.0 GiB"
+        );
+    }
+}
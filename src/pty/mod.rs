@@ -8,12 +8,27 @@ use crate::error::Result;
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
-pub use unix::{get_terminal_winsize, Pty};
+pub use unix::{get_terminal_winsize, MasterRead, Pty};
 
 #[cfg(windows)]
 mod windows_portable;
 #[cfg(windows)]
-pub use windows_portable::{Pty, PtyPair};
+pub use windows_portable::{Pty, PtyOptions, PtyPair, DEFAULT_SIZE};
+
+/// 環境變數 `LINES` 與 `COLUMNS` 給的視窗大小（列數, 欄數）
+///
+/// 查詢視窗大小時兩個平台的順序相同：先問標準串流，再問終端機本身（Unix 的
+/// `/dev/tty`、Windows 的 `CONOUT$`），都查不到才用這裡的環境變數。
+pub fn env_window_size() -> Option<(u16, u16)> {
+    let var = |name| std::env::var(name).ok();
+    window_size_from(var("LINES").as_deref(), var("COLUMNS").as_deref())
+}
+
+/// 解析 `LINES` 與 `COLUMNS`；兩者都要是正整數
+fn window_size_from(lines: Option<&str>, columns: Option<&str>) -> Option<(u16, u16)> {
+    let parse = |value: Option<&str>| value?.trim().parse::<u16>().ok().filter(|&n| n > 0);
+    Some((parse(lines)?, parse(columns)?))
+}
 
 /// PTY 介面 trait
 ///
@@ -48,3 +63,18 @@ pub trait PtyInterface {
     #[cfg(windows)]
     fn set_winsize(&self, rows: u16, cols: u16) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_size_from() {
+        assert_eq!(window_size_from(Some("24"), Some("80")), Some((24, 80)));
+        assert_eq!(window_size_from(Some(" 50 "), Some("132")), Some((50, 132)));
+        assert_eq!(window_size_from(Some("24"), None), None);
+        assert_eq!(window_size_from(None, Some("80")), None);
+        assert_eq!(window_size_from(Some("0"), Some("80")), None);
+        assert_eq!(window_size_from(Some("24"), Some("wide")), None);
+    }
+}
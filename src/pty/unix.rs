@@ -3,44 +3,89 @@
 //! 使用 POSIX PTY API 實作虛擬終端功能
 
 use crate::error::{Result, SshpassError};
-use nix::fcntl::{fcntl, FcntlArg, OFlag};
-use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt, PtyMaster, Winsize};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::pty::{grantpt, posix_openpt, unlockpt, Winsize};
+use nix::sys::termios::Termios;
 use nix::unistd::write;
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
 
 /// Wrapper around PTY master file descriptor with RAII cleanup
 pub struct Pty {
-    master: PtyMaster,
+    master: OwnedFd,
     slave_name: String,
 }
 
-impl Pty {
-    /// Create a new PTY pair (master and slave)
-    pub fn new() -> Result<Self> {
-        // Open the master PTY
-        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY).map_err(|e| {
-            SshpassError::PtyCreationError(format!("Failed to open PTY master: {}", e))
-        })?;
+/// What a read of the master PTY came back with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MasterRead {
+    /// This many bytes of output
+    Data(usize),
+    /// Nothing to read right now, or a signal interrupted the read
+    Empty,
+    /// No process has the slave open: the child has not opened it yet, or
+    /// it closed it or exited
+    Hangup,
+}
 
-        // Grant access to the slave PTY
-        grantpt(&master).map_err(|e| {
-            SshpassError::PtyCreationError(format!("Failed to grant PTY permissions: {}", e))
-        })?;
+/// Errors a read of the master fails with while the slave is closed
+///
+/// Linux fails such reads with EIO. FreeBSD and macOS report end of file
+/// instead, or ENXIO once the slave has gone for good.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+const HANGUP_ERRNOS: &[Errno] = &[Errno::EIO, Errno::ENXIO];
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+const HANGUP_ERRNOS: &[Errno] = &[Errno::EIO];
 
-        // Unlock the slave PTY
-        unlockpt(&master)
-            .map_err(|e| SshpassError::PtyCreationError(format!("Failed to unlock PTY: {}", e)))?;
+/// Make sense of what `read(2)` on the master returned
+pub fn classify_read(result: nix::Result<usize>) -> Result<MasterRead> {
+    match result {
+        Ok(0) => Ok(MasterRead::Hangup),
+        Ok(n) => Ok(MasterRead::Data(n)),
+        Err(Errno::EAGAIN | Errno::EINTR) => Ok(MasterRead::Empty),
+        Err(e) if HANGUP_ERRNOS.contains(&e) => Ok(MasterRead::Hangup),
+        Err(e) => Err(SshpassError::SystemError(e)),
+    }
+}
 
-        // Get the slave PTY name
-        let slave_name = unsafe { ptsname(&master) }.map_err(|e| {
-            SshpassError::PtyCreationError(format!("Failed to get PTY slave name: {}", e))
-        })?;
+impl Pty {
+    /// Create a new PTY pair (master and slave)
+    ///
+    /// macOS gets its pair from openpty(3), the other systems from
+    /// posix_openpt(3); either way the slave is then opened by name.
+    pub fn new() -> Result<Self> {
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        let (master, slave_name) = open_with_openpty()?;
+        #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+        let (master, slave_name) = open_with_posix_openpt()?;
 
         // Set master to non-blocking mode
         fcntl(master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(|e| {
             SshpassError::PtyCreationError(format!("Failed to set PTY to non-blocking: {}", e))
         })?;
 
+        // Children must not inherit the master: one that holds it keeps the
+        // terminal from hanging up once we close it
+        fcntl(master.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(|e| {
+            SshpassError::PtyCreationError(format!("Failed to set close-on-exec on PTY: {}", e))
+        })?;
+
         Ok(Self { master, slave_name })
     }
 
@@ -80,44 +125,136 @@ impl Pty {
         }
     }
 
+    /// Read output from the master PTY, telling a closed slave apart from
+    /// an empty read the same way on every platform
+    pub fn read_master(&self, buffer: &mut [u8]) -> Result<MasterRead> {
+        classify_read(nix::unistd::read(self.master_fd(), buffer))
+    }
+
     /// Write data to the master PTY (with reliability guarantee)
     pub fn write_all(&self, data: &[u8]) -> Result<()> {
         reliable_write(self.master_fd(), data)
     }
+
+    /// Send a BREAK condition to the child side of the PTY
+    pub fn send_break(&self) -> Result<()> {
+        use std::os::fd::BorrowedFd;
+
+        // SAFETY: the fd is owned by self.master and outlives this call
+        let fd = unsafe { BorrowedFd::borrow_raw(self.master_fd()) };
+        nix::sys::termios::tcsendbreak(fd, 0)
+            .map_err(|e| SshpassError::RuntimeError(format!("Failed to send break: {}", e)))
+    }
 }
 
 impl Drop for Pty {
     fn drop(&mut self) {
-        // The PtyMaster will automatically close the fd when dropped
+        // The OwnedFd will automatically close the fd when dropped
+    }
+}
+
+/// Open a PTY pair with posix_openpt(3), grantpt(3) and unlockpt(3)
+#[cfg_attr(any(target_os = "macos", target_os = "ios"), allow(dead_code))]
+fn open_with_posix_openpt() -> Result<(OwnedFd, String)> {
+    // Open the master PTY
+    let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)
+        .map_err(|e| SshpassError::PtyCreationError(format!("Failed to open PTY master: {}", e)))?;
+
+    // Grant access to the slave PTY
+    grantpt(&master).map_err(|e| {
+        SshpassError::PtyCreationError(format!("Failed to grant PTY permissions: {}", e))
+    })?;
+
+    // Unlock the slave PTY
+    unlockpt(&master)
+        .map_err(|e| SshpassError::PtyCreationError(format!("Failed to unlock PTY: {}", e)))?;
+
+    // Get the slave PTY name; ptsname_r (glibc and musl) is thread-safe
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let slave_name = nix::pty::ptsname_r(&master);
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let slave_name = unsafe { nix::pty::ptsname(&master) };
+    let slave_name = slave_name.map_err(|e| {
+        SshpassError::PtyCreationError(format!("Failed to get PTY slave name: {}", e))
+    })?;
+
+    // SAFETY: the descriptor comes straight out of the PtyMaster
+    let master = unsafe { OwnedFd::from_raw_fd(master.into_raw_fd()) };
+    Ok((master, check_slave_name(slave_name.into())?))
+}
+
+/// Open a PTY pair with openpty(3), naming the slave with ttyname(3)
+///
+/// macOS has no ptsname_r, and its ptsname returns a static buffer that
+/// another session's thread may overwrite; ttyname fills in a buffer of our
+/// own. The slave openpty opened is closed again once it is named.
+#[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+fn open_with_openpty() -> Result<(OwnedFd, String)> {
+    let pair = nix::pty::openpty(None::<&Winsize>, None::<&Termios>)
+        .map_err(|e| SshpassError::PtyCreationError(format!("Failed to open PTY pair: {}", e)))?;
+    let slave_name = nix::unistd::ttyname(pair.slave.as_raw_fd()).map_err(|e| {
+        SshpassError::PtyCreationError(format!("Failed to get PTY slave name: {}", e))
+    })?;
+    Ok((pair.master, check_slave_name(slave_name)?))
+}
+
+/// The slave's path as the string the child opens: a device under /dev,
+/// `/dev/pts/N` on Linux and the BSDs and `/dev/ttysNNN` on macOS
+fn check_slave_name(path: PathBuf) -> Result<String> {
+    match path.into_os_string().into_string() {
+        Ok(name) if name.starts_with("/dev/") => Ok(name),
+        Ok(name) => Err(SshpassError::PtyCreationError(format!(
+            "Unexpected PTY slave name: {}",
+            name
+        ))),
+        Err(name) => Err(SshpassError::PtyCreationError(format!(
+            "PTY slave name is not UTF-8: {}",
+            name.to_string_lossy()
+        ))),
     }
 }
 
 /// Get the current window size of the terminal
+///
+/// Asks stdin, stdout and stderr first: under a PTY wrapper that gives
+/// sshpass no controlling terminal, /dev/tty cannot be opened but a standard
+/// stream is often a terminal. /dev/tty comes next, then `LINES` and
+/// `COLUMNS`. A terminal that reports 0x0 was never sized and does not count.
 pub fn get_terminal_winsize() -> Option<Winsize> {
     use nix::ioctl_read_bad;
     use std::fs::OpenOptions;
 
     ioctl_read_bad!(tiocgwinsz, libc::TIOCGWINSZ, Winsize);
 
-    // Try to open /dev/tty
-    let tty = OpenOptions::new()
-        .read(true)
-        .write(false)
-        .open("/dev/tty")
-        .ok()?;
-
-    let mut winsize = Winsize {
-        ws_row: 0,
-        ws_col: 0,
-        ws_xpixel: 0,
-        ws_ypixel: 0,
+    let query = |fd: RawFd| {
+        let mut winsize = Winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe { tiocgwinsz(fd, &mut winsize as *mut Winsize) }
+            .ok()
+            .map(|_| winsize)
+            .filter(|winsize| winsize.ws_row > 0 && winsize.ws_col > 0)
     };
 
-    unsafe {
-        tiocgwinsz(tty.as_raw_fd(), &mut winsize as *mut Winsize).ok()?;
-    }
-
-    Some(winsize)
+    [0, 1, 2]
+        .into_iter()
+        .find_map(query)
+        .or_else(|| {
+            let tty = OpenOptions::new().read(true).open("/dev/tty").ok()?;
+            query(tty.as_raw_fd())
+        })
+        .or_else(|| {
+            let (rows, cols) = super::env_window_size()?;
+            Some(Winsize {
+                ws_row: rows,
+                ws_col: cols,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            })
+        })
 }
 
 /// Reliably write all data to a file descriptor
@@ -166,9 +303,111 @@ mod tests {
             assert!(pty.master_fd() > 0);
             assert!(!pty.slave_name().is_empty());
             assert!(pty.slave_name().starts_with("/dev/"));
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            assert!(pty.slave_name().starts_with("/dev/pts/"));
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            assert!(pty.slave_name().starts_with("/dev/ttys"));
         }
     }
 
+    /// The slave of a pair is the terminal its name says
+    fn check_pair(master: &OwnedFd, slave_name: &str) {
+        use std::io::Write;
+
+        let mut slave = std::fs::OpenOptions::new()
+            .write(true)
+            .open(slave_name)
+            .unwrap();
+        assert!(nix::unistd::isatty(slave.as_raw_fd()).unwrap());
+        slave.write_all(b"x").unwrap();
+
+        let mut buffer = [0u8; 8];
+        let n = nix::unistd::read(master.as_raw_fd(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"x");
+    }
+
+    // Both backends build on every Unix, so either is tested wherever the
+    // tests run
+
+    #[test]
+    fn test_posix_openpt_backend() {
+        let (master, slave_name) = open_with_posix_openpt().unwrap();
+        check_pair(&master, &slave_name);
+    }
+
+    #[test]
+    fn test_openpty_backend() {
+        let (master, slave_name) = open_with_openpty().unwrap();
+        check_pair(&master, &slave_name);
+    }
+
+    #[test]
+    fn test_check_slave_name() {
+        use std::os::unix::ffi::OsStringExt;
+
+        assert_eq!(
+            check_slave_name("/dev/pts/3".into()).ok().as_deref(),
+            Some("/dev/pts/3")
+        );
+        assert_eq!(
+            check_slave_name("/dev/ttys003".into()).ok().as_deref(),
+            Some("/dev/ttys003")
+        );
+        assert!(check_slave_name("pts/3".into()).is_err());
+        assert!(check_slave_name("".into()).is_err());
+        let not_utf8 = std::ffi::OsString::from_vec(b"/dev/tty\xff".to_vec());
+        assert!(check_slave_name(not_utf8.into()).is_err());
+    }
+
+    #[test]
+    fn test_send_break() {
+        let pty = Pty::new().unwrap();
+        assert!(pty.send_break().is_ok());
+    }
+
+    #[test]
+    fn test_classify_read() {
+        let read = |result| classify_read(result).ok();
+        assert_eq!(read(Ok(5)), Some(MasterRead::Data(5)));
+        // End of file on FreeBSD and macOS
+        assert_eq!(read(Ok(0)), Some(MasterRead::Hangup));
+        assert_eq!(read(Err(Errno::EAGAIN)), Some(MasterRead::Empty));
+        assert_eq!(read(Err(Errno::EINTR)), Some(MasterRead::Empty));
+        // Linux, and FreeBSD and macOS at times
+        assert_eq!(read(Err(Errno::EIO)), Some(MasterRead::Hangup));
+        assert!(matches!(
+            classify_read(Err(Errno::EBADF)),
+            Err(SshpassError::SystemError(Errno::EBADF))
+        ));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    fn test_classify_read_enxio_bsd() {
+        assert_eq!(
+            classify_read(Err(Errno::ENXIO)).ok(),
+            Some(MasterRead::Hangup)
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_classify_read_enxio_linux() {
+        // Not how Linux reports a closed slave, so it stays an error
+        assert!(classify_read(Err(Errno::ENXIO)).is_err());
+    }
+
+    #[test]
+    fn test_read_master_before_slave_opens() {
+        let pty = Pty::new().unwrap();
+        let mut buffer = [0u8; 16];
+        // Nobody has opened the slave yet
+        assert!(matches!(
+            pty.read_master(&mut buffer),
+            Ok(MasterRead::Hangup | MasterRead::Empty)
+        ));
+    }
+
     #[test]
     fn test_reliable_write() {
         // This test requires a valid file descriptor
@@ -4,10 +4,18 @@
 
 use crate::error::{Result, SshpassError};
 use portable_pty::{CommandBuilder, NativePtySystem, PtySize, PtySystem};
+use std::ffi::OsString;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
+/// 新 PTY 的預設大小
+pub const DEFAULT_SIZE: (u16, u16) = (24, 80);
+
 /// Windows 平台專用 PTY 包裝
+///
+/// ConPTY 的 slave 端只能在建立子行程時交給它，沒有獨立的 PTY 可先開再接，
+/// 因此 PTY 只能經由 [`PtyPair::spawn`] 與子行程一起建立。
 pub struct Pty {
     master: Box<dyn portable_pty::MasterPty + Send>,
     pub(crate) reader: Arc<Mutex<Box<dyn Read + Send>>>,
@@ -15,50 +23,23 @@ pub struct Pty {
 }
 
 impl Pty {
-    /// 建立新的 PTY 實例
-    pub fn new() -> Result<Self> {
-        eprintln!("SSHPASS: [DEBUG] Creating PTY using portable-pty...");
-
-        let pty_system = NativePtySystem::default();
-
-        let pty_size = PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        };
-
-        let pair = pty_system
-            .openpty(pty_size)
-            .map_err(|e| SshpassError::PtyCreationError(format!("Failed to create PTY: {}", e)))?;
-
-        eprintln!("SSHPASS: [DEBUG] PTY created successfully");
-
-        let reader = pair.master.try_clone_reader().map_err(|e| {
+    /// 取出 master 端的讀寫端
+    fn from_master(master: Box<dyn portable_pty::MasterPty + Send>) -> Result<Self> {
+        let reader = master.try_clone_reader().map_err(|e| {
             SshpassError::PtyCreationError(format!("Failed to clone reader: {}", e))
         })?;
 
-        let writer = pair
-            .master
+        let writer = master
             .take_writer()
             .map_err(|e| SshpassError::PtyCreationError(format!("Failed to take writer: {}", e)))?;
 
         Ok(Self {
-            master: pair.master,
+            master,
             reader: Arc::new(Mutex::new(reader)),
             writer: Arc::new(Mutex::new(writer)),
         })
     }
 
-    /// 取得 slave PTY 供子進程使用
-    pub(crate) fn take_slave(&mut self) -> Result<Box<dyn portable_pty::SlavePty + Send>> {
-        // portable-pty 的 openpty 返回的是 pair，我們需要重新設計
-        // 由於架構限制，我們需要在 spawn 時才創建 PTY
-        Err(SshpassError::RuntimeError(
-            "Slave PTY should be obtained during spawn".to_string(),
-        ))
-    }
-
     /// 非阻塞讀取 PTY 輸出
     pub fn read(&self, buffer: &mut [u8]) -> Result<usize> {
         if buffer.is_empty() {
@@ -113,6 +94,13 @@ impl Pty {
         Ok(())
     }
 
+    /// 送出 BREAK
+    ///
+    /// ConPTY 沒有 BREAK 的對應操作，以 Ctrl+C（0x03）代替
+    pub fn send_break(&self) -> Result<()> {
+        self.write_all(&[0x03])
+    }
+
     /// 調整終端視窗大小
     pub fn set_winsize(&self, rows: u16, cols: u16) -> Result<()> {
         let size = PtySize {
@@ -129,6 +117,108 @@ impl Pty {
     }
 }
 
+/// 建立 PTY 與子行程時的設定
+///
+/// 未設定的項目沿用 sshpass 自己的：工作目錄與環境變數繼承自 sshpass。
+#[derive(Debug, Clone)]
+pub struct PtyOptions {
+    rows: u16,
+    cols: u16,
+    cwd: Option<PathBuf>,
+    /// 不繼承 sshpass 的環境變數
+    env_clear: bool,
+    /// 依序設定的環境變數，`None` 表示移除
+    env: Vec<(OsString, Option<OsString>)>,
+    verbose: bool,
+}
+
+impl PtyOptions {
+    /// [`DEFAULT_SIZE`]、繼承 sshpass 的工作目錄與環境變數
+    pub fn new() -> Self {
+        Self {
+            rows: DEFAULT_SIZE.0,
+            cols: DEFAULT_SIZE.1,
+            cwd: None,
+            env_clear: false,
+            env: Vec::new(),
+            verbose: false,
+        }
+    }
+
+    /// PTY 的列數與欄數；0 表示沿用預設值
+    pub fn size(mut self, rows: u16, cols: u16) -> Self {
+        if rows > 0 && cols > 0 {
+            self.rows = rows;
+            self.cols = cols;
+        }
+        self
+    }
+
+    /// 子行程的工作目錄
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// 子行程從空的環境變數開始，不繼承 sshpass 的
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    /// 為子行程設定 `key`
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), Some(value.into())));
+        self
+    }
+
+    /// 不讓子行程看到 `key`
+    pub fn env_remove(mut self, key: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), None));
+        self
+    }
+
+    /// 印出除錯訊息
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    fn pty_size(&self) -> PtySize {
+        PtySize {
+            rows: self.rows,
+            cols: self.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+
+    fn command(&self, command: &[String]) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new(&command[0]);
+        cmd.args(&command[1..]);
+        if let Some(dir) = &self.cwd {
+            cmd.cwd(dir);
+        }
+        if self.env_clear {
+            cmd.env_clear();
+        }
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            }
+        }
+        cmd
+    }
+}
+
+impl Default for PtyOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// 包含 PTY 和子進程的配對
 pub struct PtyPair {
     pub pty: Pty,
@@ -136,84 +226,58 @@ pub struct PtyPair {
 }
 
 impl PtyPair {
-    /// 創建 PTY 並啟動子進程
-    pub fn spawn(command: &[String], verbose: bool) -> Result<Self> {
+    /// 依 `options` 創建 PTY 並啟動子進程
+    pub fn spawn(command: &[String], options: &PtyOptions) -> Result<Self> {
         if command.is_empty() {
             return Err(SshpassError::InvalidArguments(
                 "No command specified".to_string(),
             ));
         }
 
+        let verbose = options.verbose;
         if verbose {
-            eprintln!(
-                "SSHPASS: [DEBUG] Command array has {} elements:",
-                command.len()
-            );
+            crate::diag!("[DEBUG] Command array has {} elements:", command.len());
             for (i, arg) in command.iter().enumerate() {
-                eprintln!("SSHPASS: [DEBUG]   [{}] = {:?}", i, arg);
+                crate::diag!("[DEBUG]   [{}] = {:?}", i, arg);
             }
-            eprintln!("SSHPASS: [DEBUG] Creating PTY using portable-pty...");
+            crate::diag!(
+                "[DEBUG] Creating {}x{} PTY using portable-pty...",
+                options.rows,
+                options.cols
+            );
         }
 
-        let pty_system = NativePtySystem::default();
-
-        let pty_size = PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        };
-
-        let pair = pty_system
-            .openpty(pty_size)
+        let pair = NativePtySystem::default()
+            .openpty(options.pty_size())
             .map_err(|e| SshpassError::PtyCreationError(format!("Failed to create PTY: {}", e)))?;
 
         if verbose {
-            eprintln!("SSHPASS: Created Windows PTY (portable-pty)");
-        }
-
-        // 構建命令
-        let mut cmd = CommandBuilder::new(&command[0]);
-        for arg in &command[1..] {
-            cmd.arg(arg);
-        }
-
-        if verbose {
-            eprintln!("SSHPASS: [DEBUG] Spawning process...");
-            eprintln!("SSHPASS: [DEBUG] Program: {:?}", command[0]);
-            if command.len() > 1 {
-                eprintln!("SSHPASS: [DEBUG] Args: {:?}", &command[1..]);
+            crate::diag!("Created Windows PTY (portable-pty)");
+            crate::diag!("[DEBUG] Spawning process...");
+            if let Some(dir) = &options.cwd {
+                crate::diag!("[DEBUG] Working directory: {:?}", dir);
             }
         }
 
         let child = pair
             .slave
-            .spawn_command(cmd)
+            .spawn_command(options.command(command))
             .map_err(|e| SshpassError::ExecError(format!("Failed to spawn process: {}", e)))?;
 
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Process spawned successfully");
+            crate::diag!("[DEBUG] Process spawned successfully");
             if let Some(pid) = child.process_id() {
-                eprintln!("SSHPASS: Spawned child process with PID: {}", pid);
+                crate::diag!("Spawned child process with PID: {}", pid);
             }
         }
 
-        let reader = pair.master.try_clone_reader().map_err(|e| {
-            SshpassError::PtyCreationError(format!("Failed to clone reader: {}", e))
-        })?;
-
-        let writer = pair
-            .master
-            .take_writer()
-            .map_err(|e| SshpassError::PtyCreationError(format!("Failed to take writer: {}", e)))?;
-
-        let pty = Pty {
-            master: pair.master,
-            reader: Arc::new(Mutex::new(reader)),
-            writer: Arc::new(Mutex::new(writer)),
-        };
+        // 子行程已持有 slave 端，此處放掉父行程這份
+        drop(pair.slave);
 
-        Ok(PtyPair { pty, child })
+        Ok(PtyPair {
+            pty: Pty::from_master(pair.master)?,
+            child,
+        })
     }
 
     /// 嘗試非阻塞等待，若仍在執行則回傳 None
@@ -251,10 +315,45 @@ impl PtyPair {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_pty_options() {
+        let options = PtyOptions::new();
+        assert_eq!((options.rows, options.cols), DEFAULT_SIZE);
+        assert!(options.cwd.is_none() && !options.env_clear && options.env.is_empty());
+
+        let options = PtyOptions::new()
+            .size(50, 132)
+            .cwd("C:\\work")
+            .env("TERM", "xterm")
+            .env_remove("PROMPT")
+            .verbose(true);
+        assert_eq!((options.rows, options.cols), (50, 132));
+        assert_eq!(
+            options.cwd.as_deref(),
+            Some(std::path::Path::new("C:\\work"))
+        );
+        assert_eq!(
+            options.env,
+            [
+                (OsString::from("TERM"), Some(OsString::from("xterm"))),
+                (OsString::from("PROMPT"), None)
+            ]
+        );
+        assert!(options.verbose);
+
+        // 查不到大小時沿用預設值
+        let options = PtyOptions::new().size(0, 0);
+        assert_eq!((options.rows, options.cols), DEFAULT_SIZE);
+    }
+
     #[test]
     #[ignore]
-    fn test_pty_creation() {
-        let pty = Pty::new();
-        assert!(pty.is_ok());
+    fn test_pty_spawn() {
+        let command = ["cmd", "/c", "exit 3"].map(String::from);
+        let mut pair = PtyPair::spawn(&command, &PtyOptions::new()).unwrap();
+        assert_eq!(pair.wait().unwrap(), 3);
+
+        let empty = PtyPair::spawn(&[], &PtyOptions::new());
+        assert!(matches!(empty, Err(SshpassError::InvalidArguments(_))));
     }
 }
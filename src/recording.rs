@@ -0,0 +1,586 @@
+//! Session recordings in the asciinema v2 format (`sshpass play`)
+//!
+//! A recording starts with a header, a JSON object holding `"version": 2`,
+//! followed by one event per line: `[TIME, TYPE, DATA]`, where TIME is the
+//! number of seconds since the start and TYPE `"o"` marks output written to
+//! the terminal. `sshpass play [--speed N] [--idle-limit SECONDS] FILE`
+//! writes the output events to stdout with the pauses between them, so the
+//! terminal shows what an automated job saw. Input, resize and marker
+//! events are skipped.
+//!
+//! The file is read line by line as it is played, so a recording of any
+//! length is replayed without loading it into memory.
+//!
+//! `sshpass export [--format txt|raw] FILE` writes the output stored in a
+//! recording to stdout without the pauses: the output events of an
+//! asciinema recording, or the stream of a raw copy or typescript. `txt`,
+//! the default, runs it through the same [`AnsiFilter`] that reads the
+//! child's output for prompts, leaving a readable log without control
+//! sequences and with plain `\n` line endings.
+//!
+//! A recording encrypted with `--record-recipient` is decrypted with the
+//! identities in `--identity FILE`; a compressed one is recognised as it is.
+//!
+//! `--tee` targets are written in this format with `--record-format
+//! asciinema`. `--record-format typescript` writes the typescript and
+//! timing file pair of script(1) instead, for `scriptreplay -t FILE.timing
+//! FILE`.
+
+use crate::ansi::AnsiFilter;
+use crate::error::{Result, SshpassError};
+use std::io::{BufRead, Write};
+use std::time::{Duration, SystemTime};
+
+/// `--record-format` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum RecordFormat {
+    /// The output as it is
+    #[default]
+    Raw,
+    /// asciinema v2, for `sshpass play` and the asciinema tools
+    Asciinema,
+    /// A script(1) typescript with its timing in FILE.timing
+    Typescript,
+}
+
+/// The header of a recording started at `started`
+pub fn cast_header(width: u16, height: u16, started: SystemTime) -> String {
+    let timestamp = started
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!(
+        "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}}}\n",
+        width, height, timestamp
+    )
+}
+
+/// The line of an output event `time` after the start
+pub fn cast_event(time: Duration, data: &str) -> String {
+    format!(
+        "[{:.6}, \"o\", {}]\n",
+        time.as_secs_f64(),
+        crate::audit::json_string(data)
+    )
+}
+
+/// How the header of a typescript starts
+const TYPESCRIPT_START: &str = "Script started on ";
+
+/// The first line of a typescript, which scriptreplay skips
+pub fn typescript_header(started: SystemTime) -> String {
+    format!(
+        "{}{}\n",
+        TYPESCRIPT_START,
+        crate::audit::utc_timestamp(started)
+    )
+}
+
+/// The timing file line of `bytes` written `delay` after the previous ones
+pub fn timing_line(delay: Duration, bytes: usize) -> String {
+    format!("{:.6} {}\n", delay.as_secs_f64(), bytes)
+}
+
+/// Take the text from `pending`, leaving a character cut off at the end for
+/// the next chunk; invalid bytes become U+FFFD
+pub fn take_utf8(pending: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut rest = &pending[..];
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(&String::from_utf8_lossy(valid));
+                rest = after;
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &rest[len..];
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+    let incomplete = rest.to_vec();
+    *pending = incomplete;
+    text
+}
+
+/// How a recording is replayed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayOptions {
+    pub path: String,
+    /// Playback speed, 2.0 being twice as fast as recorded
+    pub speed: f64,
+    /// Longest pause between two events
+    pub idle_limit: Option<Duration>,
+    /// age identity file of an encrypted recording
+    pub identity: Option<String>,
+}
+
+impl PlayOptions {
+    /// Parse the words after `sshpass play`
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let invalid = |message: String| SshpassError::InvalidArguments(message);
+        let mut path = None;
+        let mut speed = 1.0;
+        let mut idle_limit = None;
+        let mut identity = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut seconds = |name: &str| {
+                args.next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|value| value.is_finite() && *value > 0.0)
+                    .ok_or_else(|| invalid(format!("{} expects a positive number", name)))
+            };
+            match arg.as_str() {
+                "--speed" => speed = seconds("--speed")?,
+                "--idle-limit" => {
+                    idle_limit = Some(Duration::from_secs_f64(seconds("--idle-limit")?))
+                }
+                "--identity" => {
+                    identity = Some(args.next().cloned().ok_or_else(|| {
+                        invalid("--identity expects an identity file".to_string())
+                    })?)
+                }
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+                _ => return Err(invalid(format!("Unexpected argument to play: {}", arg))),
+            }
+        }
+        let path = path.ok_or_else(|| {
+            invalid(
+                "Usage: sshpass play [--speed N] [--idle-limit SECONDS] [--identity FILE] FILE"
+                    .to_string(),
+            )
+        })?;
+        Ok(Self {
+            path,
+            speed,
+            idle_limit,
+            identity,
+        })
+    }
+}
+
+/// `sshpass export --format` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// Text without control sequences, with `\n` line endings
+    #[default]
+    Txt,
+    /// The output as it was recorded
+    Raw,
+}
+
+/// How a recording is exported
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    pub path: String,
+    pub format: ExportFormat,
+    /// age identity file of an encrypted recording
+    pub identity: Option<String>,
+}
+
+impl ExportOptions {
+    /// Parse the words after `sshpass export`
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let invalid = |message: String| SshpassError::InvalidArguments(message);
+        let mut path = None;
+        let mut format = ExportFormat::Txt;
+        let mut identity = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--format" => {
+                    format = match args.next().map(String::as_str) {
+                        Some("txt") => ExportFormat::Txt,
+                        Some("raw") => ExportFormat::Raw,
+                        _ => return Err(invalid("--format expects txt or raw".to_string())),
+                    }
+                }
+                "--identity" => {
+                    identity = Some(args.next().cloned().ok_or_else(|| {
+                        invalid("--identity expects an identity file".to_string())
+                    })?)
+                }
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+                _ => return Err(invalid(format!("Unexpected argument to export: {}", arg))),
+            }
+        }
+        let path = path.ok_or_else(|| {
+            invalid("Usage: sshpass export [--format txt|raw] [--identity FILE] FILE".to_string())
+        })?;
+        Ok(Self {
+            path,
+            format,
+            identity,
+        })
+    }
+}
+
+/// Open a recording, decrypting and decompressing it as needed
+pub fn open(path: &str, identity: Option<&str>) -> Result<Box<dyn BufRead + Send>> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| SshpassError::RuntimeError(format!("Failed to open {}: {}", path, e)))?;
+    crate::encryption::decryptor(std::io::BufReader::new(file), identity)
+        .and_then(crate::compression::decoder)
+        .map_err(|e| SshpassError::RuntimeError(format!("Failed to read {}: {}", path, e)))
+}
+
+/// One line of a recording after the header
+#[derive(Debug, Clone, PartialEq)]
+struct Event {
+    time: f64,
+    kind: String,
+    data: String,
+}
+
+/// Replay the recording in `reader` to `out`, returning the events played
+pub fn play(mut reader: impl BufRead, out: &mut impl Write, options: &PlayOptions) -> Result<u64> {
+    let mut header = String::new();
+    let damaged =
+        |reason: String| SshpassError::RuntimeError(format!("{}:1: {}", options.path, reason));
+    if reader.read_line(&mut header)? == 0 {
+        return Err(damaged("empty recording".to_string()));
+    }
+    check_header(&header).map_err(damaged)?;
+
+    let mut last = 0.0;
+    output_events(reader, &options.path, |event| {
+        let mut pause = Duration::from_secs_f64((event.time - last).max(0.0) / options.speed);
+        if let Some(limit) = options.idle_limit {
+            pause = pause.min(limit);
+        }
+        last = event.time;
+        std::thread::sleep(pause);
+        out.write_all(event.data.as_bytes())?;
+        out.flush()?;
+        Ok(())
+    })
+}
+
+/// Hand the output events after the header to `each`, returning how many
+/// there were
+fn output_events(
+    reader: impl BufRead,
+    path: &str,
+    mut each: impl FnMut(Event) -> Result<()>,
+) -> Result<u64> {
+    let mut count = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // The header was line 1
+        let event = parse_event(&line)
+            .map_err(|e| SshpassError::RuntimeError(format!("{}:{}: {}", path, index + 2, e)))?;
+        if event.kind == "o" {
+            each(event)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Write the output stored in the recording in `reader` to `out`, returning
+/// the bytes written
+///
+/// An asciinema recording gives its output events, a typescript what follows
+/// its header line, and a raw copy all of it, read a block at a time.
+pub fn export(
+    mut reader: impl BufRead,
+    out: &mut impl Write,
+    options: &ExportOptions,
+) -> Result<u64> {
+    let mut filter = (options.format == ExportFormat::Txt).then(AnsiFilter::new);
+    let mut written = 0;
+    let mut emit = |data: &[u8]| -> Result<()> {
+        let data = match filter.as_mut() {
+            Some(filter) => filter.process(data),
+            None => data.to_vec(),
+        };
+        out.write_all(&data)?;
+        written += data.len() as u64;
+        Ok(())
+    };
+
+    let head = reader.fill_buf()?;
+    if head.starts_with(b"{") || head.starts_with(TYPESCRIPT_START.as_bytes()) {
+        let mut first = Vec::new();
+        reader.read_until(b'\n', &mut first)?;
+        let cast = std::str::from_utf8(&first).is_ok_and(|line| check_header(line).is_ok());
+        if cast {
+            output_events(reader, &options.path, |event| emit(event.data.as_bytes()))?;
+            out.flush()?;
+            return Ok(written);
+        }
+        if !first.starts_with(TYPESCRIPT_START.as_bytes()) {
+            emit(&first)?;
+        }
+    }
+    let mut buffer = [0u8; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => emit(&buffer[..n])?,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Accept only asciinema v2 headers
+fn check_header(line: &str) -> std::result::Result<(), String> {
+    let version = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.split_once("\"version\""))
+        .and_then(|(_, rest)| rest.trim_start().strip_prefix(':'))
+        .map(|rest| {
+            rest.trim_start()
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>()
+        });
+    match version.as_deref() {
+        Some("2") => Ok(()),
+        Some(version) if !version.is_empty() => Err(format!(
+            "asciinema version {} is not supported, only 2",
+            version
+        )),
+        _ => Err("not an asciinema recording".to_string()),
+    }
+}
+
+/// Parse `[TIME, "TYPE", "DATA"]`
+fn parse_event(line: &str) -> std::result::Result<Event, String> {
+    let damaged = || "not an asciinema event".to_string();
+    let rest = line.trim().strip_prefix('[').ok_or_else(damaged)?;
+    let (time, rest) = rest.split_once(',').ok_or_else(damaged)?;
+    let time = time
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|time| time.is_finite())
+        .ok_or_else(damaged)?;
+    let (kind, rest) = parse_string(rest.trim_start())?;
+    let rest = rest.trim_start().strip_prefix(',').ok_or_else(damaged)?;
+    let (data, rest) = parse_string(rest.trim_start())?;
+    if rest.trim() != "]" {
+        return Err(damaged());
+    }
+    Ok(Event { time, kind, data })
+}
+
+/// Parse the JSON string at the start of `text`, returning it and the rest
+pub(crate) fn parse_string(text: &str) -> std::result::Result<(String, &str), String> {
+    let damaged = || "invalid JSON string".to_string();
+    let mut chars = text.strip_prefix('"').ok_or_else(damaged)?.char_indices();
+    let mut value = String::new();
+    while let Some((at, ch)) = chars.next() {
+        match ch {
+            '"' => return Ok((value, &text[at + 2..])),
+            '\\' => {
+                let escaped = match chars.next().ok_or_else(damaged)?.1 {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let unit = hex4(&mut chars).ok_or_else(damaged)?;
+                        let code = if (0xd800..0xdc00).contains(&unit) {
+                            // A surrogate pair: the low half follows as \uXXXX
+                            match (chars.next(), chars.next()) {
+                                (Some((_, '\\')), Some((_, 'u'))) => {}
+                                _ => return Err(damaged()),
+                            }
+                            let low = hex4(&mut chars).ok_or_else(damaged)?;
+                            0x10000 + ((unit - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+                        } else {
+                            unit
+                        };
+                        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    }
+                    _ => return Err(damaged()),
+                };
+                value.push(escaped);
+            }
+            ch => value.push(ch),
+        }
+    }
+    Err(damaged())
+}
+
+/// The four hex digits of a `\uXXXX` escape
+fn hex4(chars: &mut std::str::CharIndices<'_>) -> Option<u32> {
+    let digits = chars.take(4).map(|(_, c)| c).collect::<String>();
+    (digits.len() == 4)
+        .then(|| u32::from_str_radix(&digits, 16).ok())
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<PlayOptions> {
+        PlayOptions::parse(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_play_options() {
+        let parsed = options(&["--speed", "4", "--idle-limit", "0.5", "job.cast"]).unwrap();
+        assert_eq!(parsed.path, "job.cast");
+        assert_eq!(parsed.speed, 4.0);
+        assert_eq!(parsed.idle_limit, Some(Duration::from_millis(500)));
+        assert_eq!(options(&["job.cast"]).unwrap().speed, 1.0);
+        let parsed = options(&["--identity", "key.txt", "job.cast.age"]).unwrap();
+        assert_eq!(parsed.identity.as_deref(), Some("key.txt"));
+
+        assert!(options(&[]).is_err());
+        assert!(options(&["--speed", "0", "job.cast"]).is_err());
+        assert!(options(&["job.cast", "other.cast"]).is_err());
+    }
+
+    #[test]
+    fn test_export_options() {
+        let parse = |args: &[&str]| {
+            ExportOptions::parse(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+        };
+        let parsed = parse(&["job.cast"]).unwrap();
+        assert_eq!(parsed.format, ExportFormat::Txt);
+        let parsed = parse(&["--format", "raw", "--identity", "key.txt", "job.log"]).unwrap();
+        assert_eq!(parsed.format, ExportFormat::Raw);
+        assert_eq!(parsed.identity.as_deref(), Some("key.txt"));
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--format", "html", "job.cast"]).is_err());
+    }
+
+    #[test]
+    fn test_export_recording() {
+        let export_as = |recording: &[u8], format| {
+            let options = ExportOptions {
+                path: "job".to_string(),
+                format,
+                identity: None,
+            };
+            let mut out = Vec::new();
+            let written = export(recording, &mut out, &options).unwrap();
+            assert_eq!(written, out.len() as u64);
+            String::from_utf8(out).unwrap()
+        };
+
+        let cast = concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[0.1, \"o\", \"\\u001b[1m$ ls\\u001b[0m\\r\"]\n",
+            "[0.2, \"i\", \"q\"]\n",
+            "[0.3, \"o\", \"\\nfile\\r\\n\"]\n",
+        );
+        // The CRLF split between two events is still one line ending
+        assert_eq!(
+            export_as(cast.as_bytes(), ExportFormat::Txt),
+            "$ ls\nfile\n"
+        );
+        assert_eq!(
+            export_as(cast.as_bytes(), ExportFormat::Raw),
+            "\u{1b}[1m$ ls\u{1b}[0m\r\nfile\r\n"
+        );
+
+        let typescript = b"Script started on 2024-01-01 00:00:00+00:00\n\x1b[32mok\x1b[0m\r\n";
+        assert_eq!(export_as(typescript, ExportFormat::Txt), "ok\n");
+        let raw = b"{\"json\": true}\r\n\x1b]0;title\x07done\r\n";
+        assert_eq!(
+            export_as(raw, ExportFormat::Txt),
+            "{\"json\": true}\ndone\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_event() {
+        assert_eq!(
+            parse_event(r#"[0.25, "o", "\u001b[1mhi\"\\\r\n"]"#),
+            Ok(Event {
+                time: 0.25,
+                kind: "o".to_string(),
+                data: "\u{1b}[1mhi\"\\\r\n".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_event(r#"[1, "o", "😀 ok"]"#).unwrap().data,
+            "\u{1f600} ok"
+        );
+        assert!(parse_event(r#"[1, "o", "open]"#).is_err());
+        assert!(parse_event(r#"{"time": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_cast_event_round_trip() {
+        let line = cast_event(Duration::from_millis(1500), "\u{1b}[0m\"x\"\r\n");
+        assert_eq!(
+            parse_event(&line),
+            Ok(Event {
+                time: 1.5,
+                kind: "o".to_string(),
+                data: "\u{1b}[0m\"x\"\r\n".to_string(),
+            })
+        );
+        assert!(check_header(&cast_header(80, 24, SystemTime::UNIX_EPOCH)).is_ok());
+    }
+
+    #[test]
+    fn test_take_utf8() {
+        let mut pending = "aé".as_bytes()[..2].to_vec();
+        assert_eq!(take_utf8(&mut pending), "a");
+        pending.push("é".as_bytes()[1]);
+        assert_eq!(take_utf8(&mut pending), "é");
+        assert!(pending.is_empty());
+
+        let mut pending = b"x\xffy".to_vec();
+        assert_eq!(take_utf8(&mut pending), "x\u{fffd}y");
+    }
+
+    #[test]
+    fn test_play_recording() {
+        let recording = concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[0.1, \"o\", \"$ ls\\r\\n\"]\n",
+            "[0.2, \"i\", \"q\"]\n",
+            "\n",
+            "[0.3, \"o\", \"file\\r\\n\"]\n",
+        );
+        let fast = PlayOptions {
+            path: "job.cast".to_string(),
+            speed: 1000.0,
+            idle_limit: None,
+            identity: None,
+        };
+        let mut out = Vec::new();
+        assert_eq!(play(recording.as_bytes(), &mut out, &fast).unwrap(), 2);
+        assert_eq!(out, b"$ ls\r\nfile\r\n");
+
+        let error = play(&b"{\"version\": 1}\n"[..], &mut out, &fast).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("job.cast:1: asciinema version 1"));
+        let error = play(&b"{\"version\":2}\n[x]\n"[..], &mut out, &fast).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("job.cast:2: not an asciinema event"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Sending the window size again after login (`--initial-resize-retry`)
+//!
+//! sshpass sizes the PTY before the child starts, and ssh passes that size
+//! on when it asks the server for a terminal. Some servers lose it when the
+//! shell starts right after the login, leaving vim or htop drawing for the
+//! wrong size until the window is resized by hand. With
+//! `--initial-resize-retry`, once the remote side has printed something
+//! after the password, sshpass waits [`DELAY`], shrinks the PTY by a row and
+//! puts the size back [`NUDGE`] later. The size has to change for the
+//! terminal to signal the child, so setting the same size again would go
+//! unnoticed.
+
+use std::time::{Duration, Instant};
+
+/// Wait after the first output that follows the password
+pub const DELAY: Duration = Duration::from_millis(500);
+
+/// How long the PTY stays a row short
+pub const NUDGE: Duration = Duration::from_millis(100);
+
+/// What to do to the PTY's size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Set the size a row off
+    Shrink,
+    /// Set the terminal's own size again
+    Restore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Not asked for
+    Off,
+    /// Waiting for the login to finish
+    Waiting,
+    /// `step` is due at the given time
+    Due(Instant, Step),
+    /// The size was sent again
+    Done,
+}
+
+/// When to send the window size again
+#[derive(Debug)]
+pub struct ResizeRetry {
+    state: State,
+}
+
+impl ResizeRetry {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            state: if enabled { State::Waiting } else { State::Off },
+        }
+    }
+
+    /// The child printed output after the password was typed
+    pub fn running(&mut self) {
+        if self.state == State::Waiting {
+            self.state = State::Due(Instant::now() + DELAY, Step::Shrink);
+        }
+    }
+
+    /// Time left until the next step
+    pub fn remaining(&self) -> Option<Duration> {
+        match self.state {
+            State::Due(at, _) => Some(at.saturating_duration_since(Instant::now())),
+            _ => None,
+        }
+    }
+
+    /// The step that is due, if any
+    pub fn due(&mut self) -> Option<Step> {
+        let State::Due(at, step) = self.state else {
+            return None;
+        };
+        if Instant::now() < at {
+            return None;
+        }
+        self.state = match step {
+            Step::Shrink => State::Due(Instant::now() + NUDGE, Step::Restore),
+            Step::Restore => State::Done,
+        };
+        Some(step)
+    }
+}
+
+/// The row count to shrink `rows` to: one fewer, or one more for a terminal
+/// a single row high
+pub fn shrunk(rows: u16) -> u16 {
+    if rows > 1 {
+        rows - 1
+    } else {
+        rows + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_retry_steps() {
+        let mut retry = ResizeRetry::new(true);
+        assert_eq!(retry.remaining(), None);
+        assert_eq!(retry.due(), None);
+
+        retry.running();
+        assert!(retry.remaining().unwrap() > DELAY / 2);
+        assert_eq!(retry.due(), None);
+
+        // As if the delay ran out
+        retry.state = State::Due(Instant::now(), Step::Shrink);
+        assert_eq!(retry.due(), Some(Step::Shrink));
+        assert!(retry.remaining().unwrap() <= NUDGE);
+        retry.state = State::Due(Instant::now(), Step::Restore);
+        assert_eq!(retry.due(), Some(Step::Restore));
+
+        // Once per session
+        retry.running();
+        assert_eq!(retry.remaining(), None);
+        assert_eq!(retry.due(), None);
+    }
+
+    #[test]
+    fn test_resize_retry_off() {
+        let mut retry = ResizeRetry::new(false);
+        retry.running();
+        assert_eq!(retry.remaining(), None);
+        assert_eq!(retry.due(), None);
+    }
+
+    #[test]
+    fn test_shrunk() {
+        assert_eq!(shrunk(24), 23);
+        assert_eq!(shrunk(1), 2);
+        assert_eq!(shrunk(0), 1);
+    }
+}
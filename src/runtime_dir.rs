@@ -0,0 +1,242 @@
+//! Per-invocation scratch directory
+//!
+//! Features that need files of their own for the length of one session
+//! (control sockets, recordings, metrics) keep them in a [`RuntimeDir`]:
+//! `$XDG_RUNTIME_DIR/sshpass-<pid>`, or the same name under the system
+//! temporary directory when that variable is not set. The directory is
+//! created with mode 0700 the first time it is asked for and removed with
+//! everything in it when the `RuntimeDir` is dropped, which includes
+//! unwinding from a panic; `main` catches panics so the unwinding reaches
+//! it. On Unix, directories left behind by sshpass processes that were
+//! killed outright are removed the next time one is created.
+//!
+//! State that must outlive one invocation, such as the failed logins counted
+//! by [`crate::lockout`], goes to [`shared_dir`] instead.
+
+use std::cell::OnceCell;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the directory name, followed by the process ID
+const DIR_PREFIX: &str = "sshpass-";
+
+/// Owner of this process's scratch directory
+pub struct RuntimeDir {
+    base: PathBuf,
+    created: OnceCell<PathBuf>,
+}
+
+impl RuntimeDir {
+    /// Scratch directory under `$XDG_RUNTIME_DIR` or the temporary directory
+    pub fn new() -> Self {
+        Self::in_base(base_dir())
+    }
+
+    /// Scratch directory under `base`
+    pub fn in_base(base: impl Into<PathBuf>) -> Self {
+        Self {
+            base: base.into(),
+            created: OnceCell::new(),
+        }
+    }
+
+    /// The directory, created on first use
+    pub fn path(&self) -> std::io::Result<&Path> {
+        if let Some(path) = self.created.get() {
+            return Ok(path);
+        }
+        let path = self
+            .base
+            .join(format!("{}{}", DIR_PREFIX, std::process::id()));
+        #[cfg(unix)]
+        remove_stale(&self.base);
+        // Left over from an earlier process with our PID
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        create_private_dir(&path)?;
+        Ok(self.created.get_or_init(|| path))
+    }
+
+    /// Path of a file named `name` in the directory
+    pub fn file(&self, name: &str) -> std::io::Result<PathBuf> {
+        Ok(self.path()?.join(name))
+    }
+}
+
+impl Default for RuntimeDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RuntimeDir {
+    fn drop(&mut self) {
+        if let Some(path) = self.created.take() {
+            let _ = std::fs::remove_dir_all(path);
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR`, or the temporary directory when it is not set
+fn base_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Directory shared by this user's sshpass processes, created with mode 0700
+///
+/// Kept under the same base as [`RuntimeDir`], so it lasts until logout or
+/// reboot on systems with `$XDG_RUNTIME_DIR`.
+pub fn shared_dir() -> std::io::Result<PathBuf> {
+    shared_dir_in(&base_dir())
+}
+
+fn shared_dir_in(base: &Path) -> std::io::Result<PathBuf> {
+    // With the user in the name, others sharing /tmp cannot take it over
+    #[cfg(unix)]
+    // SAFETY: getuid has no preconditions and cannot fail
+    let path = base.join(format!("{}shared-{}", DIR_PREFIX, unsafe {
+        libc::getuid()
+    }));
+    #[cfg(windows)]
+    let path = base.join(format!("{}shared", DIR_PREFIX));
+    match create_private_dir(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    #[cfg(unix)]
+    if !is_owned_by_us(&path) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{} is not a directory of this user", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(windows)]
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(path)
+}
+
+/// Remove directories of sshpass processes that no longer exist
+#[cfg(unix)]
+fn remove_stale(base: &Path) {
+    let Ok(entries) = std::fs::read_dir(base) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(DIR_PREFIX))
+            .and_then(|pid| pid.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        let exists = nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None)
+            != Err(nix::errno::Errno::ESRCH);
+        // Only our own directories; others' cannot be removed anyway
+        if !exists && is_owned_by_us(&entry.path()) {
+            let _ = std::fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_owned_by_us(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    std::fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir() && meta.uid() == uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_base(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("sshpass-rt-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        base
+    }
+
+    #[test]
+    fn test_runtime_dir_lifecycle() {
+        let base = test_base("lifecycle");
+        let runtime = RuntimeDir::in_base(&base);
+        let expected = base.join(format!("sshpass-{}", std::process::id()));
+        assert!(!expected.exists());
+
+        let file = runtime.file("metrics.json").unwrap();
+        assert_eq!(file, expected.join("metrics.json"));
+        std::fs::write(&file, b"{}").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&expected).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        drop(runtime);
+        assert!(!expected.exists());
+        std::fs::remove_dir(&base).unwrap();
+    }
+
+    #[test]
+    fn test_runtime_dir_removed_on_panic() {
+        let base = test_base("panic");
+        let expected = base.join(format!("sshpass-{}", std::process::id()));
+        let result = std::panic::catch_unwind(|| {
+            let runtime = RuntimeDir::in_base(&base);
+            runtime.path().unwrap();
+            panic!("session failed");
+        });
+        assert!(result.is_err());
+        assert!(!expected.exists());
+        std::fs::remove_dir(&base).unwrap();
+    }
+
+    #[test]
+    fn test_shared_dir_is_kept() {
+        let base = test_base("shared");
+        let shared = shared_dir_in(&base).unwrap();
+        std::fs::write(shared.join("state"), b"1").unwrap();
+        assert_eq!(shared_dir_in(&base).unwrap(), shared);
+
+        // Neither a running nor a stale per-invocation directory
+        let runtime = RuntimeDir::in_base(&base);
+        runtime.path().unwrap();
+        drop(runtime);
+        assert!(shared.join("state").exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stale_dirs_are_removed() {
+        let base = test_base("stale");
+        // PIDs are below 2^22 on Linux, so this one cannot be running
+        let stale = base.join("sshpass-2147483646");
+        let unrelated = base.join("sshpass-notapid");
+        std::fs::create_dir(&stale).unwrap();
+        std::fs::create_dir(&unrelated).unwrap();
+
+        let runtime = RuntimeDir::in_base(&base);
+        runtime.path().unwrap();
+        assert!(!stale.exists());
+        assert!(unrelated.exists());
+
+        drop(runtime);
+        std::fs::remove_dir(&unrelated).unwrap();
+        std::fs::remove_dir(&base).unwrap();
+    }
+}
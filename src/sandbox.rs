@@ -0,0 +1,194 @@
+//! Optional hardening of the sshpass parent process (`--sandbox`)
+//!
+//! The parent keeps the password in memory for the whole session, so once the
+//! child has been spawned it gives up everything it no longer needs:
+//!
+//! * Landlock denies all filesystem access except `/dev/tty` (window size
+//!   lookups) and any extra paths the caller passes in.
+//! * A seccomp filter allows only the system calls used by the event loop
+//!   (PTY I/O, pselect, waitpid, signals, memory management); everything else
+//!   fails with `EPERM`.
+//!
+//! File descriptors that are already open, such as the PTY master and
+//! stdout/stderr, keep working.
+//!
+//! Only available on Linux when built with the `sandbox` feature.
+
+use crate::error::{Result, SshpassError};
+use std::path::Path;
+
+/// Paths the parent still needs after the sandbox is applied
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+const DEFAULT_PATHS: &[&str] = &["/dev/tty"];
+
+/// Restrict the current process
+///
+/// # Arguments
+/// * `extra_paths` - Additional files the parent must still be able to open for
+///   reading and writing (for example a log file)
+/// * `verbose` - Enable verbose logging
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub fn apply(extra_paths: &[&Path], verbose: bool) -> Result<()> {
+    let paths: Vec<&Path> = DEFAULT_PATHS
+        .iter()
+        .map(Path::new)
+        .chain(extra_paths.iter().copied())
+        .collect();
+
+    let status = linux::restrict_filesystem(&paths)?;
+    if verbose {
+        crate::diag!("Landlock ruleset status: {:?}", status);
+    }
+
+    linux::apply_syscall_filter()?;
+    if verbose {
+        crate::diag!("seccomp filter installed");
+    }
+
+    Ok(())
+}
+
+/// Restrict the current process (not supported in this build)
+#[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+pub fn apply(_extra_paths: &[&Path], _verbose: bool) -> Result<()> {
+    Err(SshpassError::InvalidArguments(
+        "--sandbox requires Linux and sshpass built with the `sandbox` feature".to_string(),
+    ))
+}
+
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+mod linux {
+    use super::*;
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
+        RulesetStatus, ABI,
+    };
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, SeccompRule, TargetArch};
+    use std::collections::BTreeMap;
+
+    /// System calls the event loop needs after the child has been spawned
+    const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+        // PTY and stdio
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_close,
+        libc::SYS_openat,
+        libc::SYS_fcntl,
+        libc::SYS_ioctl,
+        libc::SYS_lseek,
+        libc::SYS_newfstatat,
+        libc::SYS_statx,
+        libc::SYS_pselect6,
+        libc::SYS_ppoll,
+        // Locking and rewriting the --audit-log and lockout records opened
+        // before the spawn
+        libc::SYS_flock,
+        libc::SYS_ftruncate,
+        // The syslog socket of --log-target, connected before the spawn
+        libc::SYS_sendto,
+        // Child management and signals
+        libc::SYS_wait4,
+        libc::SYS_waitid,
+        libc::SYS_kill,
+        libc::SYS_tgkill,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        // Memory, threads and time
+        libc::SYS_brk,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_futex,
+        libc::SYS_sched_yield,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_getrandom,
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_nanosleep,
+        // Exit
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_poll,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_fstat,
+    ];
+
+    /// Deny all filesystem access except read/write on the given files
+    pub(super) fn restrict_filesystem(paths: &[&Path]) -> Result<RulesetStatus> {
+        let abi = ABI::V2;
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(abi))
+            .and_then(|ruleset| ruleset.create())
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    paths.iter().filter(|path| path.exists()),
+                    AccessFs::ReadFile | AccessFs::WriteFile,
+                ))
+            })
+            .and_then(|ruleset| ruleset.restrict_self())
+            .map_err(|e| SshpassError::RuntimeError(format!("Failed to apply Landlock: {}", e)))?;
+
+        Ok(status.ruleset)
+    }
+
+    /// Build the seccomp program for the current architecture
+    pub(super) fn build_syscall_filter() -> Result<BpfProgram> {
+        let arch = TargetArch::try_from(std::env::consts::ARCH)
+            .map_err(|e| SshpassError::RuntimeError(format!("Unsupported seccomp arch: {}", e)))?;
+
+        // c_long is only 32 bits wide on 32-bit targets
+        #[allow(clippy::useless_conversion)]
+        let rules: BTreeMap<i64, Vec<SeccompRule>> = ALLOWED_SYSCALLS
+            .iter()
+            .map(|&syscall| (i64::from(syscall), Vec::new()))
+            .collect();
+
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Errno(libc::EPERM as u32),
+            SeccompAction::Allow,
+            arch,
+        )
+        .map_err(|e| SshpassError::RuntimeError(format!("Invalid seccomp filter: {}", e)))?;
+
+        filter
+            .try_into()
+            .map_err(|e| SshpassError::RuntimeError(format!("Failed to compile seccomp: {}", e)))
+    }
+
+    pub(super) fn apply_syscall_filter() -> Result<()> {
+        let program = build_syscall_filter()?;
+        seccompiler::apply_filter(&program)
+            .map_err(|e| SshpassError::RuntimeError(format!("Failed to apply seccomp: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    #[test]
+    fn test_syscall_filter_builds() {
+        let program = linux::build_syscall_filter().unwrap();
+        assert!(!program.is_empty());
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+    #[test]
+    fn test_sandbox_unavailable_without_feature() {
+        assert!(apply(&[], false).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Lua session scripts (`--lua-script`)
+//!
+//! A script is an observer of the session: it defines any of these global
+//! functions, which sshpass calls as things happen:
+//!
+//! ```lua
+//! function on_output(data) end   -- a chunk of the child's output
+//! function on_prompt() end       -- the password was accepted
+//! function on_exit(code) end     -- the session ended with this exit code
+//! ```
+//!
+//! and drives the session with:
+//!
+//! * `send(text)` - type `text` into the child's terminal (no newline added)
+//! * `signal(name)` - send a signal such as `"INT"` or `"SIGHUP"` to the
+//!   child; on Windows every signal terminates it
+//! * `set_timeout(seconds)` - terminate the child unless the session ends
+//!   within `seconds`; a later call replaces the deadline and `0` clears it
+//!
+//! Callbacks run on the event loop thread, so they should return quickly.
+//! An error in a callback is reported and the session carries on.
+//!
+//! Needs the `scripting` feature, which embeds Lua 5.4.
+
+use crate::error::{Result, SshpassError};
+use crate::session::{SessionEvent, SessionHandle, SessionObserver, SessionStatus};
+use mlua::{Function, IntoLuaMulti, Lua};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A loaded Lua script
+pub struct LuaScript {
+    lua: Lua,
+}
+
+impl LuaScript {
+    /// Load and run the script at `path`, which then drives the session
+    /// behind `handle`
+    pub fn load(path: &Path, handle: SessionHandle, verbose: bool) -> Result<Self> {
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            SshpassError::InvalidArguments(format!(
+                "Failed to read Lua script {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::from_source(&path.display().to_string(), &source, handle, verbose)
+    }
+
+    /// Run a script given as text
+    pub fn from_source(
+        name: &str,
+        source: &str,
+        handle: SessionHandle,
+        verbose: bool,
+    ) -> Result<Self> {
+        let lua = Lua::new();
+        register(&lua, handle, verbose)
+            .and_then(|_| lua.load(source).set_name(name).exec())
+            .map_err(|e| {
+                SshpassError::InvalidArguments(format!("Lua script {} failed: {}", name, e))
+            })?;
+        Ok(Self { lua })
+    }
+
+    /// Call a callback if the script defines it
+    fn call<'lua>(&'lua self, name: &str, args: impl IntoLuaMulti<'lua>) {
+        let callback = match self.lua.globals().get::<_, Option<Function>>(name) {
+            Ok(Some(callback)) => callback,
+            _ => return,
+        };
+        if let Err(e) = callback.call::<_, ()>(args) {
+            crate::diag!("Lua {} failed: {}", name, e);
+        }
+    }
+}
+
+impl SessionObserver for LuaScript {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        match event {
+            SessionEvent::Output(data) => {
+                if let Ok(data) = self.lua.create_string(data) {
+                    self.call("on_output", data);
+                }
+            }
+            SessionEvent::Status(SessionStatus::Authenticated) => self.call("on_prompt", ()),
+            SessionEvent::Status(SessionStatus::Exited(code)) => self.call("on_exit", code),
+            _ => {}
+        }
+    }
+}
+
+/// Define `send`, `signal` and `set_timeout`
+fn register(lua: &Lua, handle: SessionHandle, verbose: bool) -> mlua::Result<()> {
+    let globals = lua.globals();
+
+    let session = handle.clone();
+    let send = lua.create_function(move |_, text: mlua::String| {
+        session.send_input(text.as_bytes());
+        Ok(())
+    })?;
+    globals.set("send", send)?;
+
+    let session = handle.clone();
+    let signal = lua.create_function(move |_, name: String| {
+        #[cfg(unix)]
+        {
+            let number = signal_number(&name)
+                .ok_or_else(|| mlua::Error::runtime(format!("unknown signal {}", name)))?;
+            session.signal(number);
+        }
+        #[cfg(windows)]
+        {
+            let _ = name;
+            session.cancel();
+        }
+        Ok(())
+    })?;
+    globals.set("signal", signal)?;
+
+    // Each call starts a new deadline; older timers see a newer generation
+    // and do nothing
+    let generation = Arc::new(AtomicU64::new(0));
+    let set_timeout = lua.create_function(move |_, seconds: f64| {
+        let current = generation.fetch_add(1, Ordering::SeqCst) + 1;
+        if seconds <= 0.0 {
+            return Ok(());
+        }
+        let timeout = Duration::try_from_secs_f64(seconds)
+            .map_err(|e| mlua::Error::runtime(format!("invalid timeout: {}", e)))?;
+        let generation = Arc::clone(&generation);
+        let session = handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(timeout);
+            if generation.load(Ordering::SeqCst) == current && !session.is_finished() {
+                if verbose {
+                    crate::diag!("Lua script timeout expired, terminating child");
+                }
+                session.cancel();
+            }
+        });
+        Ok(())
+    })?;
+    globals.set("set_timeout", set_timeout)?;
+
+    Ok(())
+}
+
+/// Number of the signal called `name`, with or without the `SIG` prefix
+#[cfg(unix)]
+fn signal_number(name: &str) -> Option<i32> {
+    let name = name.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    name.parse::<nix::sys::signal::Signal>()
+        .ok()
+        .map(|signal| signal as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{Request, SessionControl};
+    use crate::signal::SignalFlags;
+
+    #[test]
+    fn test_script_drives_session() {
+        let control = SessionControl::new(SignalFlags::new()).unwrap();
+        let script = LuaScript::from_source(
+            "test",
+            r#"
+                function on_output(data)
+                    if data:find("Username:") then send("admin\r") end
+                end
+                function on_prompt() send("echo hi\r") end
+                function on_exit(code) signal("HUP") end
+            "#,
+            control.handle(),
+            false,
+        )
+        .unwrap();
+        let control = control.with_observer(script);
+
+        control.output(b"Username: ");
+        control.output(b"nothing here");
+        control.set_status(SessionStatus::Authenticated);
+        control.set_status(SessionStatus::Exited(0));
+
+        let mut expected = vec![
+            Request::Input(b"admin\r".to_vec()),
+            Request::Input(b"echo hi\r".to_vec()),
+        ];
+        #[cfg(unix)]
+        expected.push(Request::Signal(libc::SIGHUP));
+        #[cfg(windows)]
+        expected.push(Request::Cancel);
+        assert_eq!(control.take_requests(), expected);
+    }
+
+    #[test]
+    fn test_script_timeout_cancels() {
+        let control = SessionControl::new(SignalFlags::new()).unwrap();
+        LuaScript::from_source(
+            "test",
+            "set_timeout(60); set_timeout(0.01)",
+            control.handle(),
+            false,
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(control.take_requests(), vec![Request::Cancel]);
+
+        // Syntax errors surface when the script is loaded
+        assert!(LuaScript::from_source("bad", "function (", control.handle(), false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_signal_number() {
+        assert_eq!(signal_number("int"), Some(libc::SIGINT));
+        assert_eq!(signal_number("SIGTERM"), Some(libc::SIGTERM));
+        assert_eq!(signal_number("NOPE"), None);
+    }
+}
@@ -0,0 +1,413 @@
+//! Sequential command mode
+//!
+//! With `--then`, sshpass keeps the authenticated interactive shell open and
+//! types each command into it in turn, instead of paying for a fresh login
+//! per command. The end of every command is detected by having the shell echo
+//! a unique sentinel line after it. The sentinel is typed with an empty quote
+//! pair in the middle (`"__SSHPASS_x""_DONE_1__"`) so the typed command line
+//! itself can never match.
+//!
+//! The shell on the remote side is assumed to be POSIX-like: echo is turned
+//! off with `stty -echo` and the prompts are cleared so they do not end up in
+//! the captured output.
+//!
+//! Each sentinel also carries the command's `$?`, which gives the exit status
+//! reported in `--json` output.
+
+use crate::audit::json_string;
+use std::time::{Duration, Instant};
+
+/// Progress of the command sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the password to be sent
+    Idle,
+    /// Setup line sent, waiting for the shell to answer
+    WaitingReady,
+    /// Command with this index is running
+    Running(usize),
+    /// Sentinel of this command seen, reading the `:$?` that follows it
+    Status(usize),
+    /// Skipping the rest of the ready line before the first command
+    SkipLine,
+    /// All commands done, `exit` sent
+    Finished,
+}
+
+/// Output captured for one command of the sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandResult {
+    /// The command as typed
+    pub command: String,
+    /// Everything the command printed
+    pub output: Vec<u8>,
+    /// Wall time from sending the command to seeing its sentinel
+    pub duration: Duration,
+    /// Value of `$?` after the command, if the shell reported one
+    pub exit_status: Option<i32>,
+}
+
+/// What the event loop should do after feeding PTY output
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SequenceStep {
+    /// Data to show on stdout
+    pub output: Vec<u8>,
+    /// Data to write to the PTY
+    pub input: Vec<u8>,
+    /// Commands that completed during this step
+    pub completed: Vec<CommandResult>,
+}
+
+/// Drives a list of commands through one interactive shell session
+#[derive(Debug)]
+pub struct CommandSequence {
+    commands: Vec<String>,
+    state: State,
+    nonce: String,
+    /// Unscanned output that may hold the start of a sentinel
+    pending: Vec<u8>,
+    /// Output of the running command
+    captured: Vec<u8>,
+    started: Instant,
+    /// Collect results for a JSON report instead of streaming output
+    json: bool,
+    results: Vec<CommandResult>,
+    /// Password prompts answered during the login
+    auth_attempts: u32,
+}
+
+impl CommandSequence {
+    /// Create a sequence for the given commands
+    ///
+    /// # Arguments
+    /// * `commands` - Commands to type into the shell, in order
+    /// * `json` - Collect results for [`CommandSequence::to_json`] instead of
+    ///   streaming each command's output
+    pub fn new(commands: Vec<String>, json: bool) -> Self {
+        let nonce = format!(
+            "{:x}{:x}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0)
+        );
+        Self::with_nonce(commands, nonce, json)
+    }
+
+    fn with_nonce(commands: Vec<String>, nonce: String, json: bool) -> Self {
+        Self {
+            commands,
+            state: State::Idle,
+            nonce,
+            pending: Vec::new(),
+            captured: Vec::new(),
+            started: Instant::now(),
+            json,
+            results: Vec::new(),
+            auth_attempts: 0,
+        }
+    }
+
+    /// Whether the shell has answered and commands are being run
+    pub fn is_ready(&self) -> bool {
+        !matches!(self.state, State::Idle | State::WaitingReady)
+    }
+
+    /// Called once the password has been sent; returns the setup line to type
+    pub fn start(&mut self) -> Vec<u8> {
+        if self.state != State::Idle {
+            return Vec::new();
+        }
+        self.state = State::WaitingReady;
+        format!(
+            "stty -echo; PS1=''; PS2=''; echo \"{}\"\n",
+            self.typed_marker("READY")
+        )
+        .into_bytes()
+    }
+
+    /// Feed output read from the PTY
+    pub fn feed(&mut self, data: &[u8]) -> SequenceStep {
+        let mut step = SequenceStep::default();
+        if matches!(self.state, State::Idle | State::Finished) {
+            return step;
+        }
+
+        self.pending.extend_from_slice(data);
+        loop {
+            match self.state {
+                State::WaitingReady => {
+                    let marker = self.marker("READY");
+                    match find(&self.pending, marker.as_bytes()) {
+                        Some(pos) => {
+                            self.pending.drain(..pos + marker.len());
+                            self.state = State::SkipLine;
+                        }
+                        None => {
+                            // Login banner and the echoed setup line are discarded
+                            let keep = self.pending.len().min(marker.len() - 1);
+                            self.pending.drain(..self.pending.len() - keep);
+                            return step;
+                        }
+                    }
+                }
+                State::SkipLine => match self.pending.iter().position(|&b| b == b'\n') {
+                    Some(pos) => {
+                        self.pending.drain(..=pos);
+                        self.begin_command(0, &mut step);
+                    }
+                    None => {
+                        self.pending.clear();
+                        return step;
+                    }
+                },
+                State::Running(index) => {
+                    let marker = self.marker(&format!("DONE_{}", index + 1));
+                    match find(&self.pending, marker.as_bytes()) {
+                        Some(pos) => {
+                            let output: Vec<u8> = self.pending.drain(..pos).collect();
+                            self.pending.drain(..marker.len());
+                            self.capture(&output, &mut step);
+                            self.state = State::Status(index);
+                        }
+                        None => {
+                            // Hold back what could be the start of the sentinel
+                            let keep = self.pending.len().min(marker.len() - 1);
+                            let output: Vec<u8> =
+                                self.pending.drain(..self.pending.len() - keep).collect();
+                            self.capture(&output, &mut step);
+                            return step;
+                        }
+                    }
+                }
+                State::Status(index) => match self.pending.iter().position(|&b| b == b'\n') {
+                    Some(pos) => {
+                        let line: Vec<u8> = self.pending.drain(..=pos).collect();
+                        let exit_status = parse_status(&line);
+                        let result = CommandResult {
+                            command: self.commands[index].clone(),
+                            output: std::mem::take(&mut self.captured),
+                            duration: self.started.elapsed(),
+                            exit_status,
+                        };
+                        step.completed.push(result.clone());
+                        self.results.push(result);
+                        self.begin_command(index + 1, &mut step);
+                    }
+                    None => return step,
+                },
+                State::Idle | State::Finished => return step,
+            }
+        }
+    }
+
+    fn begin_command(&mut self, index: usize, step: &mut SequenceStep) {
+        let Some(command) = self.commands.get(index) else {
+            self.state = State::Finished;
+            self.pending.clear();
+            step.input.extend_from_slice(b"exit\n");
+            return;
+        };
+
+        if !self.json {
+            step.output.extend_from_slice(
+                format!(
+                    "==> [{}/{}] {} <==\n",
+                    index + 1,
+                    self.commands.len(),
+                    command
+                )
+                .as_bytes(),
+            );
+        }
+        step.input.extend_from_slice(
+            format!(
+                "{}\necho \"{}:$?\"\n",
+                command,
+                self.typed_marker(&format!("DONE_{}", index + 1))
+            )
+            .as_bytes(),
+        );
+        self.started = Instant::now();
+        self.state = State::Running(index);
+    }
+
+    /// Record output of the running command
+    fn capture(&mut self, output: &[u8], step: &mut SequenceStep) {
+        self.captured.extend_from_slice(output);
+        if !self.json {
+            step.output.extend_from_slice(output);
+        }
+    }
+
+    /// Results of the commands completed so far
+    #[allow(dead_code)]
+    pub fn results(&self) -> &[CommandResult] {
+        &self.results
+    }
+
+    /// Whether results are reported as JSON
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Record how many times the server asked for the password, reported
+    /// with every result
+    pub fn set_auth_attempts(&mut self, attempts: u32) {
+        self.auth_attempts = attempts;
+    }
+
+    /// Render the completed commands as a JSON array
+    ///
+    /// Output is decoded lossily as UTF-8 with line endings normalized to LF.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, result) in self.results.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let output = crate::ansi::normalize_line_endings(&result.output);
+            json.push_str(&format!(
+                "\n  {{\"command\": {}, \"output\": {}, \"duration_ms\": {}, \"exit_status\": {}, \"auth_attempts\": {}}}",
+                json_string(&result.command),
+                json_string(&String::from_utf8_lossy(&output)),
+                result.duration.as_millis(),
+                result
+                    .exit_status
+                    .map_or_else(|| "null".to_string(), |s| s.to_string()),
+                self.auth_attempts
+            ));
+        }
+        if !self.results.is_empty() {
+            json.push('\n');
+        }
+        json.push_str("]\n");
+        json
+    }
+
+    /// Sentinel as printed by the shell
+    fn marker(&self, tag: &str) -> String {
+        format!("__SSHPASS_{}_{}__", self.nonce, tag)
+    }
+
+    /// Sentinel as typed, split by an empty quote pair
+    fn typed_marker(&self, tag: &str) -> String {
+        format!("__SSHPASS_{}\"\"_{}__", self.nonce, tag)
+    }
+}
+
+/// Find the first occurrence of needle in haystack
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parse the `:$?` suffix of a sentinel line
+fn parse_status(line: &[u8]) -> Option<i32> {
+    let text = String::from_utf8_lossy(line);
+    text.trim().strip_prefix(':')?.parse().ok()
+}
+
+/// Read commands for `--then-file`: one per line, blank lines and `#` comments skipped
+pub fn read_command_file(path: &std::path::Path) -> std::io::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(commands: &[&str]) -> CommandSequence {
+        CommandSequence::with_nonce(
+            commands.iter().map(|c| c.to_string()).collect(),
+            "n".into(),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_setup_line_does_not_contain_marker() {
+        let mut seq = sequence(&["ls"]);
+        let setup = String::from_utf8(seq.start()).unwrap();
+        assert!(setup.starts_with("stty -echo"));
+        assert!(!setup.contains("__SSHPASS_n_READY__"));
+    }
+
+    #[test]
+    fn test_runs_commands_in_order() {
+        let mut seq = sequence(&["uname", "id"]);
+        seq.start();
+
+        let step = seq.feed(b"Welcome\r\n$ stty -echo ...\r\n__SSHPASS_n_READY__\r\n");
+        assert!(seq.is_ready());
+        assert_eq!(step.output, b"==> [1/2] uname <==\n");
+        assert_eq!(step.input, b"uname\necho \"__SSHPASS_n\"\"_DONE_1__:$?\"\n");
+
+        let step = seq.feed(b"Linux\r\n__SSHPASS_n_DONE_1__:0\r\n");
+        assert_eq!(step.completed.len(), 1);
+        assert_eq!(step.completed[0].command, "uname");
+        assert_eq!(step.completed[0].output, b"Linux\r\n");
+        assert!(step.input.starts_with(b"id\n"));
+
+        let step = seq.feed(b"uid=0\r\n__SSHPASS_n_DONE_2__:1\r\n");
+        assert_eq!(step.completed[0].output, b"uid=0\r\n");
+        assert_eq!(step.completed[0].exit_status, Some(1));
+        assert_eq!(step.input, b"exit\n");
+        assert_eq!(seq.state, State::Finished);
+    }
+
+    #[test]
+    fn test_marker_split_across_reads() {
+        let mut seq = sequence(&["ls"]);
+        seq.start();
+        seq.feed(b"__SSHPASS_n_RE");
+        let step = seq.feed(b"ADY__\r\n");
+        assert!(seq.is_ready());
+        assert_eq!(step.input, b"ls\necho \"__SSHPASS_n\"\"_DONE_1__:$?\"\n");
+
+        let first = seq.feed(b"a b c\r\n__SSHPASS_n_DO");
+        let second = seq.feed(b"NE_1__:0\r\n");
+        let mut shown = first.output.clone();
+        shown.extend_from_slice(&second.output);
+        assert_eq!(shown, b"a b c\r\n");
+        assert_eq!(second.completed[0].output, b"a b c\r\n");
+    }
+
+    #[test]
+    fn test_feed_before_start_is_ignored() {
+        let mut seq = sequence(&["ls"]);
+        assert_eq!(seq.feed(b"__SSHPASS_n_READY__\n"), SequenceStep::default());
+        assert!(!seq.is_ready());
+    }
+
+    #[test]
+    fn test_json_report() {
+        let mut seq = CommandSequence::with_nonce(vec!["echo \"hi\"".into()], "n".into(), true);
+        seq.start();
+        let step = seq.feed(b"__SSHPASS_n_READY__\r\n");
+        assert!(step.output.is_empty());
+
+        let step = seq.feed(b"hi\r\n__SSHPASS_n_DONE_1__:0\r\n");
+        assert!(step.output.is_empty());
+        assert_eq!(seq.results().len(), 1);
+
+        seq.set_auth_attempts(1);
+        let json = seq.to_json();
+        assert!(json.contains(r#""command": "echo \"hi\"""#));
+        assert!(json.contains(r#""output": "hi\n""#));
+        assert!(json.contains(r#""exit_status": 0"#));
+        assert!(json.contains(r#""auth_attempts": 1"#));
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status(b":127\r\n"), Some(127));
+        assert_eq!(parse_status(b"\r\n"), None);
+    }
+}
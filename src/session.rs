@@ -0,0 +1,431 @@
+//! Thread-safe control of a running session
+//!
+//! The event loop owns a [`SessionControl`], which combines the session's
+//! signal source with a queue of requests from library callers. Front ends
+//! such as GUI tools take [`SessionHandle`]s from it; a handle can be cloned
+//! and moved to any thread to cancel the session, type input into the child
+//! or query how far the login has got, and once it is over, which prompt
+//! the password was typed at.
+//!
+//! [`SessionObserver`]s registered on the control receive the child's
+//! output, status changes and the final error as they happen, so a front end
+//! can show live progress without parsing sshpass's stderr.
+//!
+//! On Unix a self-pipe wakes the loop out of `pselect` as soon as a request is
+//! queued, so cancellation does not wait for the next burst of child output.
+
+use crate::error::{Result, SshpassError};
+use crate::monitor::PromptMatch;
+use crate::process::{ResourceUsage, Termination};
+use crate::signal::SignalSource;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// How far a session has progressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// The child is being spawned
+    Starting,
+    /// The child is running and no password has been typed yet
+    WaitingForPrompt,
+    /// The password has been typed; whether it was right is not known yet
+    PasswordSent,
+    /// The output shows the login went through (see
+    /// [`OutputMonitor::login_succeeded`](crate::monitor::OutputMonitor::login_succeeded))
+    Authenticated,
+    /// The child has produced output since the password was typed
+    Running,
+    /// The session is over; holds the code sshpass exits with
+    Exited(i32),
+}
+
+/// Something that happened during a session
+#[derive(Debug, Clone, Copy)]
+pub enum SessionEvent<'a> {
+    /// A chunk of output read from the child's terminal
+    Output(&'a [u8]),
+    /// The session moved to a new status
+    Status(SessionStatus),
+    /// The session failed; [`SessionStatus::Exited`] follows
+    Error(&'a SshpassError),
+}
+
+/// Receives progress events from the event loop
+///
+/// Called on the thread running the session, so implementations should
+/// return quickly. Closures taking a [`SessionEvent`] can be used directly.
+pub trait SessionObserver: Send {
+    fn on_event(&mut self, event: SessionEvent<'_>);
+}
+
+impl<F> SessionObserver for F
+where
+    F: FnMut(SessionEvent<'_>) + Send,
+{
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        self(event)
+    }
+}
+
+/// A request queued by a [`SessionHandle`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Terminate the child as if sshpass had received SIGTERM
+    Cancel,
+    /// Write bytes to the child's terminal
+    Input(Vec<u8>),
+    /// Send the child this signal (a Unix signal number); on Windows the
+    /// child is terminated
+    Signal(i32),
+}
+
+/// State shared between the event loop and its handles
+struct Shared {
+    requests: Mutex<VecDeque<Request>>,
+    status: Mutex<SessionStatus>,
+    /// Bytes of child output seen so far
+    output_bytes: AtomicU64,
+    /// The signal that killed the child, if one did
+    termination: Mutex<Option<Termination>>,
+    /// What the child used, once it has exited
+    resource_usage: Mutex<Option<ResourceUsage>>,
+    /// The prompt the password was last typed at
+    prompt_match: Mutex<Option<PromptMatch>>,
+    /// Write end of the wake-up pipe
+    #[cfg(unix)]
+    wake: OwnedFd,
+}
+
+impl Shared {
+    fn push(&self, request: Request) {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.push_back(request);
+        }
+        // The pipe is non-blocking; when it is full the loop is awake anyway
+        #[cfg(unix)]
+        let _ = nix::unistd::write(self.wake.as_raw_fd(), &[0]);
+    }
+}
+
+/// Cloneable handle for controlling a session from other threads
+#[derive(Clone)]
+pub struct SessionHandle {
+    shared: Arc<Shared>,
+}
+
+impl SessionHandle {
+    /// Ask the session to terminate the child
+    pub fn cancel(&self) {
+        self.shared.push(Request::Cancel);
+    }
+
+    /// Type `data` into the child's terminal
+    pub fn send_input(&self, data: &[u8]) {
+        self.shared.push(Request::Input(data.to_vec()));
+    }
+
+    /// Send the child a signal, such as `libc::SIGINT`
+    pub fn signal(&self, signal: i32) {
+        self.shared.push(Request::Signal(signal));
+    }
+
+    /// Current status of the session
+    pub fn status(&self) -> SessionStatus {
+        self.shared
+            .status
+            .lock()
+            .map(|status| *status)
+            .unwrap_or(SessionStatus::Starting)
+    }
+
+    /// Whether the session has ended
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status(), SessionStatus::Exited(_))
+    }
+
+    /// Bytes of child output the session has seen
+    pub fn output_bytes(&self) -> u64 {
+        self.shared.output_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The signal that killed the child, once it has exited
+    pub fn child_signal(&self) -> Option<i32> {
+        self.termination().map(|termination| termination.signal)
+    }
+
+    /// The signal that killed the child and whether it dumped core, once it
+    /// has exited
+    pub fn termination(&self) -> Option<Termination> {
+        self.shared
+            .termination
+            .lock()
+            .ok()
+            .and_then(|termination| *termination)
+    }
+
+    /// CPU time and peak memory of the child, once it has exited
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.shared
+            .resource_usage
+            .lock()
+            .ok()
+            .and_then(|usage| *usage)
+    }
+
+    /// The prompt the password was last typed at: the pattern, where it was
+    /// in the output and the output before it
+    pub fn prompt_match(&self) -> Option<PromptMatch> {
+        self.shared
+            .prompt_match
+            .lock()
+            .ok()
+            .and_then(|found| found.clone())
+    }
+}
+
+/// Event loop side of the control channel
+pub struct SessionControl {
+    shared: Arc<Shared>,
+    signals: Box<dyn SignalSource + Send + Sync>,
+    observers: Mutex<Vec<Box<dyn SessionObserver>>>,
+    /// Read end of the wake-up pipe
+    #[cfg(unix)]
+    wake: OwnedFd,
+}
+
+impl SessionControl {
+    /// Create the control channel for a session
+    ///
+    /// # Arguments
+    /// * `signals` - Signals the event loop should watch, usually the
+    ///   [`SignalFlags`](crate::signal::SignalFlags) of the process's handlers
+    pub fn new(signals: impl SignalSource + Send + Sync + 'static) -> Result<Self> {
+        #[cfg(unix)]
+        let (wake_read, wake_write) = {
+            use nix::fcntl::OFlag;
+            let (read_fd, write_fd) = nix::unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+            // SAFETY: pipe2 just returned these descriptors and nothing else owns them
+            unsafe {
+                (
+                    OwnedFd::from_raw_fd(read_fd),
+                    OwnedFd::from_raw_fd(write_fd),
+                )
+            }
+        };
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                requests: Mutex::new(VecDeque::new()),
+                status: Mutex::new(SessionStatus::Starting),
+                output_bytes: AtomicU64::new(0),
+                termination: Mutex::new(None),
+                resource_usage: Mutex::new(None),
+                prompt_match: Mutex::new(None),
+                #[cfg(unix)]
+                wake: wake_write,
+            }),
+            signals: Box::new(signals),
+            observers: Mutex::new(Vec::new()),
+            #[cfg(unix)]
+            wake: wake_read,
+        })
+    }
+
+    /// Get a handle for controlling this session
+    pub fn handle(&self) -> SessionHandle {
+        SessionHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Signals of this session
+    pub fn signals(&self) -> &dyn SignalSource {
+        self.signals.as_ref()
+    }
+
+    /// Add an observer of this session's progress events
+    ///
+    /// Observers are called in the order they were added.
+    pub fn with_observer(self, observer: impl SessionObserver + 'static) -> Self {
+        if let Ok(mut observers) = self.observers.lock() {
+            observers.push(Box::new(observer));
+        }
+        self
+    }
+
+    /// Publish the session's progress to its handles and observers
+    ///
+    /// Repeating the current status is a no-op.
+    pub fn set_status(&self, status: SessionStatus) {
+        if let Ok(mut current) = self.shared.status.lock() {
+            if *current == status {
+                return;
+            }
+            *current = status;
+        }
+        self.notify(SessionEvent::Status(status));
+    }
+
+    /// Pass a chunk of child output to the observers
+    pub fn output(&self, data: &[u8]) {
+        self.shared
+            .output_bytes
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.notify(SessionEvent::Output(data));
+    }
+
+    /// Record the signal that killed the child
+    pub fn set_termination(&self, termination: Option<Termination>) {
+        if let Ok(mut current) = self.shared.termination.lock() {
+            *current = termination;
+        }
+    }
+
+    /// Record what the child used
+    pub fn set_resource_usage(&self, usage: Option<ResourceUsage>) {
+        if let Ok(mut current) = self.shared.resource_usage.lock() {
+            *current = usage;
+        }
+    }
+
+    /// Record the prompt the password was typed at
+    pub fn set_prompt_match(&self, found: Option<PromptMatch>) {
+        if let Ok(mut current) = self.shared.prompt_match.lock() {
+            *current = found;
+        }
+    }
+
+    /// Pass the error that ended the session to the observers
+    pub fn error(&self, error: &SshpassError) {
+        self.notify(SessionEvent::Error(error));
+    }
+
+    fn notify(&self, event: SessionEvent<'_>) {
+        if let Ok(mut observers) = self.observers.lock() {
+            for observer in observers.iter_mut() {
+                observer.on_event(event);
+            }
+        }
+    }
+
+    /// Descriptor that becomes readable when a request is queued
+    #[cfg(unix)]
+    pub fn wake_fd(&self) -> RawFd {
+        self.wake.as_raw_fd()
+    }
+
+    /// Take all queued requests in the order they were made
+    pub fn take_requests(&self) -> Vec<Request> {
+        #[cfg(unix)]
+        {
+            let mut buf = [0u8; 64];
+            while matches!(nix::unistd::read(self.wake.as_raw_fd(), &mut buf), Ok(n) if n > 0) {}
+        }
+
+        match self.shared.requests.lock() {
+            Ok(mut requests) => requests.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::SignalFlags;
+
+    #[test]
+    fn test_requests_from_other_threads() {
+        let control = SessionControl::new(SignalFlags::new()).unwrap();
+        let handle = control.handle();
+
+        let worker = handle.clone();
+        std::thread::spawn(move || {
+            worker.send_input(b"yes\n");
+            worker.cancel();
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            control.take_requests(),
+            vec![Request::Input(b"yes\n".to_vec()), Request::Cancel]
+        );
+        assert!(control.take_requests().is_empty());
+    }
+
+    #[test]
+    fn test_status_is_shared() {
+        let control = SessionControl::new(SignalFlags::new()).unwrap();
+        let handle = control.handle();
+        assert_eq!(handle.status(), SessionStatus::Starting);
+
+        control.set_status(SessionStatus::Authenticated);
+        assert_eq!(handle.clone().status(), SessionStatus::Authenticated);
+        assert!(!handle.is_finished());
+
+        control.set_status(SessionStatus::Exited(5));
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn test_observer_receives_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        let outputs = Arc::new(Mutex::new(0));
+        let counted = Arc::clone(&outputs);
+        let control = SessionControl::new(SignalFlags::new())
+            .unwrap()
+            .with_observer(move |event: SessionEvent<'_>| {
+                seen.lock().unwrap().push(format!("{:?}", event));
+            })
+            .with_observer(move |event: SessionEvent<'_>| {
+                if let SessionEvent::Output(_) = event {
+                    *counted.lock().unwrap() += 1;
+                }
+            });
+
+        control.set_status(SessionStatus::WaitingForPrompt);
+        control.output(b"Password: ");
+        control.set_status(SessionStatus::Authenticated);
+        control.set_status(SessionStatus::Authenticated);
+        control.error(&SshpassError::IncorrectPassword);
+        control.set_status(SessionStatus::Exited(5));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "Status(WaitingForPrompt)",
+                "Output([80, 97, 115, 115, 119, 111, 114, 100, 58, 32])",
+                "Status(Authenticated)",
+                "Error(IncorrectPassword)",
+                "Status(Exited(5))",
+            ]
+        );
+        assert_eq!(*outputs.lock().unwrap(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_request_wakes_event_loop() {
+        use nix::poll::{poll, PollFd, PollFlags};
+        use std::os::fd::BorrowedFd;
+
+        let control = SessionControl::new(SignalFlags::new()).unwrap();
+        let wake = unsafe { BorrowedFd::borrow_raw(control.wake_fd()) };
+        let readable = || {
+            let mut fds = [PollFd::new(&wake, PollFlags::POLLIN)];
+            poll(&mut fds, 0).unwrap() > 0
+        };
+
+        assert!(!readable());
+        control.handle().cancel();
+        assert!(readable());
+        control.take_requests();
+        assert!(!readable());
+    }
+}
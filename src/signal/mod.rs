@@ -2,6 +2,9 @@
 //!
 //! 此模組提供跨平台的信號和主控台事件處理介面。
 //! 在 Unix 系統上使用 POSIX 信號，在 Windows 上使用主控台事件。
+//!
+//! 每次呼叫 `setup_signal_handlers` 都會產生一組獨立的註冊，同一個行程中的多個
+//! session 各自收到信號；最後一個 `SignalFlags` 複本被丟棄時自動取消註冊。
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -16,6 +19,16 @@ pub struct SignalFlags {
     pub sigint_received: Arc<AtomicBool>,
     pub sighup_received: Arc<AtomicBool>,
     pub sigtstp_received: Arc<AtomicBool>,
+    /// 此組旗標的信號註冊，所有複本丟棄後取消註冊
+    registration: Option<Arc<Registration>>,
+}
+
+impl std::fmt::Debug for SignalFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignalFlags")
+            .field("should_terminate", &self.should_terminate())
+            .finish_non_exhaustive()
+    }
 }
 
 impl SignalFlags {
@@ -27,9 +40,16 @@ impl SignalFlags {
             sigint_received: Arc::new(AtomicBool::new(false)),
             sighup_received: Arc::new(AtomicBool::new(false)),
             sigtstp_received: Arc::new(AtomicBool::new(false)),
+            registration: None,
         }
     }
 
+    /// 附加信號註冊
+    fn with_registration(mut self, registration: Registration) -> Self {
+        self.registration = Some(Arc::new(registration));
+        self
+    }
+
     /// Check if SIGWINCH (window resize) was received and clear the flag
     pub fn check_and_clear_sigwinch(&self) -> bool {
         self.sigwinch_received.swap(false, Ordering::SeqCst)
@@ -56,11 +76,17 @@ impl SignalFlags {
     }
 
     /// Check if any termination signal was received
-    #[allow(dead_code)]
     pub fn should_terminate(&self) -> bool {
         self.check_sigterm() || self.check_sigint() || self.check_sighup()
     }
 
+    /// Check if a signal is waiting to be handled, without clearing it
+    pub fn pending(&self) -> bool {
+        self.should_terminate()
+            || self.sigwinch_received.load(Ordering::SeqCst)
+            || self.sigtstp_received.load(Ordering::SeqCst)
+    }
+
     /// Get the termination signal if any (Unix only)
     #[cfg(unix)]
     pub fn get_term_signal(&self) -> Option<nix::sys::signal::Signal> {
@@ -93,12 +119,63 @@ impl Default for SignalFlags {
     }
 }
 
+/// The signal a termination request carries
+#[cfg(unix)]
+pub type TermSignal = nix::sys::signal::Signal;
+/// The signal a termination request carries; a console event has none
+#[cfg(windows)]
+pub type TermSignal = ();
+
+/// Where the event loop learns of signals
+///
+/// [`SignalFlags`] are set by the handlers of [`setup_signal_handlers`]; a
+/// program embedding sessions, or a test, can raise its own.
+pub trait SignalSource {
+    /// The termination signal that came in, if any; it stays set
+    fn term_signal(&self) -> Option<TermSignal>;
+    /// Whether SIGTSTP came in, clearing it
+    #[cfg(unix)]
+    fn take_stop(&self) -> bool;
+    /// Whether the terminal was resized, clearing it
+    fn take_resize(&self) -> bool;
+    /// Whether a signal is waiting to be handled, without clearing it
+    fn pending(&self) -> bool;
+}
+
+impl SignalSource for SignalFlags {
+    fn term_signal(&self) -> Option<TermSignal> {
+        self.get_term_signal()
+    }
+
+    #[cfg(unix)]
+    fn take_stop(&self) -> bool {
+        self.check_and_clear_sigtstp()
+    }
+
+    fn take_resize(&self) -> bool {
+        self.check_and_clear_sigwinch()
+    }
+
+    fn pending(&self) -> bool {
+        SignalFlags::pending(self)
+    }
+}
+
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
-pub use unix::{forward_signal_to_child, handle_window_resize, setup_signal_handlers};
+use unix::Registration;
+#[cfg(unix)]
+pub use unix::{
+    forward_signal_to_child, handle_window_resize, retry_window_size, setup_signal_handlers,
+};
 
 #[cfg(windows)]
 mod windows;
 #[cfg(windows)]
-pub use windows::{forward_signal_to_child, handle_window_resize, setup_signal_handlers};
+use windows::Registration;
+#[cfg(windows)]
+pub use windows::{
+    current_console_size, forward_signal_to_child, handle_window_resize, retry_window_size,
+    setup_signal_handlers, setup_signal_handlers_no_console,
+};
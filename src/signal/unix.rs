@@ -5,60 +5,57 @@
 use super::SignalFlags;
 use crate::error::Result;
 use nix::sys::signal::{Signal, SIGINT, SIGTSTP};
+use signal_hook::SigId;
 use std::sync::Arc;
 
+/// Signal handlers registered for one set of flags
+pub(super) struct Registration {
+    ids: Vec<SigId>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        for id in self.ids.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
 /// Set up signal handlers for the application
 ///
 /// This function registers signal handlers that set atomic flags when
 /// signals are received. The main loop can check these flags to respond
 /// to signals appropriately.
+///
+/// Each call adds its own handlers, so several sessions in one process all
+/// see the signals. The handlers are removed when the last clone of the
+/// returned flags is dropped.
 pub fn setup_signal_handlers() -> Result<SignalFlags> {
     use signal_hook::consts::signal::*;
-    use signal_hook::flag;
 
     let flags = SignalFlags::new();
+    let mut registration = Registration { ids: Vec::new() };
+
+    let handlers = [
+        (SIGWINCH, "SIGWINCH", &flags.sigwinch_received),
+        (SIGTERM, "SIGTERM", &flags.sigterm_received),
+        (SIGINT, "SIGINT", &flags.sigint_received),
+        (SIGHUP, "SIGHUP", &flags.sighup_received),
+        (SIGTSTP, "SIGTSTP", &flags.sigtstp_received),
+    ];
+
+    // On failure, dropping the registration removes the handlers added so far
+    for (signal, name, flag) in handlers {
+        let id = signal_hook::flag::register(signal, Arc::clone(flag)).map_err(|e| {
+            crate::error::SshpassError::RuntimeError(format!(
+                "Failed to register {} handler: {}",
+                name, e
+            ))
+        })?;
+        registration.ids.push(id);
+    }
 
-    // Register SIGWINCH (window size change)
-    flag::register(SIGWINCH, Arc::clone(&flags.sigwinch_received)).map_err(|e| {
-        crate::error::SshpassError::RuntimeError(format!(
-            "Failed to register SIGWINCH handler: {}",
-            e
-        ))
-    })?;
-
-    // Register SIGTERM
-    flag::register(SIGTERM, Arc::clone(&flags.sigterm_received)).map_err(|e| {
-        crate::error::SshpassError::RuntimeError(format!(
-            "Failed to register SIGTERM handler: {}",
-            e
-        ))
-    })?;
-
-    // Register SIGINT (Ctrl-C)
-    flag::register(SIGINT, Arc::clone(&flags.sigint_received)).map_err(|e| {
-        crate::error::SshpassError::RuntimeError(format!(
-            "Failed to register SIGINT handler: {}",
-            e
-        ))
-    })?;
-
-    // Register SIGHUP
-    flag::register(SIGHUP, Arc::clone(&flags.sighup_received)).map_err(|e| {
-        crate::error::SshpassError::RuntimeError(format!(
-            "Failed to register SIGHUP handler: {}",
-            e
-        ))
-    })?;
-
-    // Register SIGTSTP (Ctrl-Z)
-    flag::register(SIGTSTP, Arc::clone(&flags.sigtstp_received)).map_err(|e| {
-        crate::error::SshpassError::RuntimeError(format!(
-            "Failed to register SIGTSTP handler: {}",
-            e
-        ))
-    })?;
-
-    Ok(flags)
+    Ok(flags.with_registration(registration))
 }
 
 /// Handle window resize signal by updating PTY window size
@@ -69,36 +66,50 @@ pub fn handle_window_resize(pty: &crate::pty::Pty) -> Result<()> {
     Ok(())
 }
 
+/// Take a step of `--initial-resize-retry`
+pub fn retry_window_size(pty: &crate::pty::Pty, step: crate::resize_retry::Step) -> Result<()> {
+    use crate::resize_retry::{shrunk, Step};
+
+    match (step, crate::pty::get_terminal_winsize()) {
+        (Step::Shrink, Some(mut winsize)) => {
+            winsize.ws_row = shrunk(winsize.ws_row);
+            pty.set_winsize(&winsize)
+        }
+        (Step::Restore, _) => handle_window_resize(pty),
+        (_, None) => Ok(()),
+    }
+}
+
 /// Forward a signal to the child process
 ///
 /// SIGINT and SIGTSTP are sent as control characters to the PTY,
 /// other signals are sent directly to the process.
 pub fn forward_signal_to_child(
     signal: Signal,
-    child: &crate::process::ChildProcess,
+    child: &dyn crate::engine::SessionChild,
     verbose: bool,
 ) -> Result<()> {
     match signal {
         SIGINT => {
             // Send Ctrl-C (0x03) to the PTY
             if verbose {
-                eprintln!("SSHPASS: Forwarding SIGINT as Ctrl-C");
+                crate::diag!("Forwarding SIGINT as Ctrl-C");
             }
-            child.pty.write_all(&[0x03])?;
+            child.terminal().write_all(&[0x03])?;
         }
         SIGTSTP => {
             // Send Ctrl-Z (0x1a) to the PTY
             if verbose {
-                eprintln!("SSHPASS: Forwarding SIGTSTP as Ctrl-Z");
+                crate::diag!("Forwarding SIGTSTP as Ctrl-Z");
             }
-            child.pty.write_all(&[0x1a])?;
+            child.terminal().write_all(&[0x1a])?;
         }
         _ => {
             // Send signal directly to the child process
             if verbose {
-                eprintln!("SSHPASS: Forwarding signal {:?} to child", signal);
+                crate::diag!("Forwarding signal {:?} to child", signal);
             }
-            child.kill(signal)?;
+            child.send_signal(signal)?;
         }
     }
     Ok(())
@@ -125,9 +136,33 @@ mod tests {
 
         // Set a flag
         flags.sigwinch_received.store(true, Ordering::SeqCst);
+        assert!(flags.pending());
         assert!(flags.check_and_clear_sigwinch());
         // Should be cleared now
         assert!(!flags.check_and_clear_sigwinch());
+        assert!(!flags.pending());
+    }
+
+    #[test]
+    fn test_registrations_are_per_session() {
+        use std::sync::atomic::Ordering;
+
+        let first = setup_signal_handlers().unwrap();
+        let second = setup_signal_handlers().unwrap();
+
+        signal_hook::low_level::raise(signal_hook::consts::SIGWINCH).unwrap();
+        assert!(first.check_and_clear_sigwinch());
+        assert!(second.check_and_clear_sigwinch());
+
+        // Dropping the last clone removes only that session's handlers
+        let second_winch = Arc::clone(&second.sigwinch_received);
+        let second_clone = second.clone();
+        drop(second);
+        drop(second_clone);
+
+        signal_hook::low_level::raise(signal_hook::consts::SIGWINCH).unwrap();
+        assert!(first.check_and_clear_sigwinch());
+        assert!(!second_winch.load(Ordering::SeqCst));
     }
 
     #[test]
@@ -4,39 +4,83 @@
 
 use super::SignalFlags;
 use crate::error::{Result, SshpassError};
-use std::sync::atomic::Ordering;
-use std::sync::OnceLock;
+use std::os::windows::io::AsRawHandle;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 use windows::Win32::Foundation::{BOOL, HANDLE, INVALID_HANDLE_VALUE};
 use windows::Win32::System::Console::{
     GetConsoleScreenBufferInfo, GetStdHandle, SetConsoleCtrlHandler, CONSOLE_SCREEN_BUFFER_INFO,
-    CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT, STD_OUTPUT_HANDLE,
+    CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
 };
 
-static SIGNAL_STATE: OnceLock<SignalFlags> = OnceLock::new();
+/// 訂閱 console 事件的 session
+struct Subscriber {
+    id: u64,
+    /// 不含註冊的旗標複本，避免循環參照
+    flags: SignalFlags,
+    monitor_resize: bool,
+}
+
+/// 目前訂閱中的 session；console handler 與視窗大小監控執行緒都只有一個，
+/// 收到事件時分派給所有訂閱者
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(0);
+static HANDLER_INSTALLED: OnceLock<std::result::Result<(), String>> = OnceLock::new();
 static RESIZE_THREAD: OnceLock<()> = OnceLock::new();
 
+/// 一組旗標的訂閱，丟棄時取消訂閱
+pub(super) struct Registration {
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Ok(mut subscribers) = SUBSCRIBERS.lock() {
+            subscribers.retain(|subscriber| subscriber.id != self.id);
+        }
+    }
+}
+
 /// 註冊 console handler 並回傳旗標物件
 pub fn setup_signal_handlers() -> Result<SignalFlags> {
-    if let Some(flags) = SIGNAL_STATE.get() {
-        return Ok(flags.clone());
-    }
+    register_handlers(true)
+}
+
+/// 沒有 console 時（服務、排程工作）使用：只註冊 control handler，不啟動視窗大小監控
+pub fn setup_signal_handlers_no_console() -> Result<SignalFlags> {
+    register_handlers(false)
+}
 
-    unsafe {
-        SetConsoleCtrlHandler(Some(console_handler), BOOL(1)).map_err(|err| {
+fn register_handlers(monitor_resize: bool) -> Result<SignalFlags> {
+    // console handler 整個行程只安裝一次
+    HANDLER_INSTALLED
+        .get_or_init(|| unsafe {
+            SetConsoleCtrlHandler(Some(console_handler), BOOL(1)).map_err(|err| err.to_string())
+        })
+        .clone()
+        .map_err(|err| {
             SshpassError::WindowsError(format!(
                 "Failed to register console control handler: {}",
                 err
             ))
         })?;
-    }
 
     let flags = SignalFlags::new();
-    SIGNAL_STATE
-        .set(flags.clone())
-        .map_err(|_| SshpassError::WindowsError("Signal handler already registered".into()))?;
-    start_resize_monitor(flags.clone());
-    Ok(flags)
+    let id = NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS
+        .lock()
+        .map_err(|_| SshpassError::WindowsError("Signal subscriber list poisoned".into()))?
+        .push(Subscriber {
+            id,
+            flags: flags.clone(),
+            monitor_resize,
+        });
+
+    if monitor_resize {
+        start_resize_monitor();
+    }
+    Ok(flags.with_registration(Registration { id }))
 }
 
 /// 視窗大小改變時更新 PTY
@@ -50,22 +94,35 @@ pub fn handle_window_resize(pty: &crate::pty::Pty) -> Result<()> {
     }
 }
 
+/// 執行 `--initial-resize-retry` 的一個步驟
+pub fn retry_window_size(pty: &crate::pty::Pty, step: crate::resize_retry::Step) -> Result<()> {
+    use crate::resize_retry::{shrunk, Step};
+
+    match step {
+        Step::Shrink => match current_console_size() {
+            Some((rows, cols)) => pty.set_winsize(shrunk(rows), cols),
+            None => Ok(()),
+        },
+        Step::Restore => handle_window_resize(pty),
+    }
+}
+
 /// 將控制事件轉發到子行程
 pub fn forward_signal_to_child(
     _signal: (),
-    child: &mut crate::process::ChildProcess,
+    child: &mut dyn crate::engine::SessionChild,
     verbose: bool,
 ) -> Result<()> {
     if verbose {
-        eprintln!("SSHPASS: Forwarding console event to child process");
+        crate::diag!("Forwarding console event to child process");
     }
 
-    match child.pty_ref().write_all(&[0x03]) {
+    match child.terminal().write_all(&[0x03]) {
         Ok(()) => Ok(()),
         Err(err) => {
             if verbose {
-                eprintln!("SSHPASS: Failed to send Ctrl+C via PTY: {}", err);
-                eprintln!("SSHPASS: Falling back to forcefully terminating child");
+                crate::diag!("Failed to send Ctrl+C via PTY: {}", err);
+                crate::diag!("Falling back to forcefully terminating child");
             }
             child.kill()
         }
@@ -73,56 +130,76 @@ pub fn forward_signal_to_child(
 }
 
 unsafe extern "system" fn console_handler(ctrl_type: u32) -> BOOL {
-    if let Some(flags) = SIGNAL_STATE.get() {
-        match ctrl_type {
-            CTRL_C_EVENT => {
-                flags.sigint_received.store(true, Ordering::SeqCst);
-                return BOOL(1);
-            }
-            CTRL_BREAK_EVENT => {
-                flags.sigterm_received.store(true, Ordering::SeqCst);
-                return BOOL(1);
-            }
-            CTRL_CLOSE_EVENT => {
-                flags.sighup_received.store(true, Ordering::SeqCst);
-                return BOOL(1);
-            }
-            _ => {}
-        }
+    let Ok(subscribers) = SUBSCRIBERS.lock() else {
+        return BOOL(0);
+    };
+    if subscribers.is_empty() {
+        return BOOL(0);
     }
-    BOOL(0)
+
+    for subscriber in subscribers.iter() {
+        let flag = match ctrl_type {
+            CTRL_C_EVENT => &subscriber.flags.sigint_received,
+            CTRL_BREAK_EVENT => &subscriber.flags.sigterm_received,
+            CTRL_CLOSE_EVENT => &subscriber.flags.sighup_received,
+            _ => return BOOL(0),
+        };
+        flag.store(true, Ordering::SeqCst);
+    }
+    BOOL(1)
 }
 
-fn start_resize_monitor(flags: SignalFlags) {
+fn start_resize_monitor() {
     RESIZE_THREAD.get_or_init(|| {
         std::thread::spawn(move || {
             let mut last_size = current_console_size();
             loop {
                 let current_size = current_console_size();
                 if current_size.is_some() && current_size != last_size {
-                    flags.sigwinch_received.store(true, Ordering::SeqCst);
+                    if let Ok(subscribers) = SUBSCRIBERS.lock() {
+                        for subscriber in subscribers.iter().filter(|s| s.monitor_resize) {
+                            subscriber
+                                .flags
+                                .sigwinch_received
+                                .store(true, Ordering::SeqCst);
+                        }
+                    }
                 }
                 last_size = current_size;
                 std::thread::sleep(Duration::from_millis(250));
             }
         });
-        ()
     });
 }
 
-fn current_console_size() -> Option<(u16, u16)> {
-    unsafe {
-        let handle = match GetStdHandle(STD_OUTPUT_HANDLE) {
-            Ok(h) if h != HANDLE(0) && h != INVALID_HANDLE_VALUE => h,
-            _ => return None,
-        };
-        let mut info = CONSOLE_SCREEN_BUFFER_INFO::default();
-        if GetConsoleScreenBufferInfo(handle, &mut info).is_ok() {
-            let cols = (info.srWindow.Right - info.srWindow.Left + 1) as u16;
-            let rows = (info.srWindow.Bottom - info.srWindow.Top + 1) as u16;
-            Some((rows, cols))
-        } else {
-            None
-        }
+/// 目前主控台視窗的列數與欄數
+///
+/// 依序詢問 stdout、stderr（stdin 是輸入緩衝區，查不到視窗大小），再開啟
+/// `CONOUT$` 詢問主控台本身，最後退回 `LINES` 與 `COLUMNS`；與 Unix 的
+/// `get_terminal_winsize` 順序一致。
+pub fn current_console_size() -> Option<(u16, u16)> {
+    [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE]
+        .into_iter()
+        .find_map(|which| unsafe { GetStdHandle(which) }.ok().and_then(console_size))
+        .or_else(|| {
+            let console = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("CONOUT$")
+                .ok()?;
+            console_size(HANDLE(console.as_raw_handle() as isize))
+        })
+        .or_else(crate::pty::env_window_size)
+}
+
+/// 一個主控台輸出 handle 的視窗大小
+fn console_size(handle: HANDLE) -> Option<(u16, u16)> {
+    if handle == HANDLE(0) || handle == INVALID_HANDLE_VALUE {
+        return None;
     }
+    let mut info = CONSOLE_SCREEN_BUFFER_INFO::default();
+    unsafe { GetConsoleScreenBufferInfo(handle, &mut info) }.ok()?;
+    let cols = (info.srWindow.Right - info.srWindow.Left + 1) as u16;
+    let rows = (info.srWindow.Bottom - info.srWindow.Top + 1) as u16;
+    Some((rows, cols))
 }
@@ -0,0 +1,261 @@
+//! Rewriting the command line of OpenSSH tools
+//!
+//! sshpass can add options to an ssh, scp or sftp command so the login
+//! behaves the same whatever the user's ssh_config says (`--ssh-hardened`).
+//! Options go right after the program name, where every tool reads them and
+//! ssh does not mistake them for the remote command. ssh keeps the first
+//! value it sees for an option, so an option the user already passes is
+//! left alone.
+
+use std::path::Path;
+
+/// An OpenSSH client program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshTool {
+    Ssh,
+    Scp,
+    Sftp,
+}
+
+impl SshTool {
+    /// The tool `program` runs, if it is one
+    pub fn detect(program: &str) -> Option<Self> {
+        let name = Path::new(program).file_name()?.to_str()?;
+        let name = name.to_ascii_lowercase();
+        match name.strip_suffix(".exe").unwrap_or(&name) {
+            "ssh" => Some(Self::Ssh),
+            "scp" => Some(Self::Scp),
+            "sftp" => Some(Self::Sftp),
+            _ => None,
+        }
+    }
+}
+
+/// The tool `command` runs, if it is one
+pub fn tool(command: &[String]) -> Option<SshTool> {
+    command.first().and_then(|program| SshTool::detect(program))
+}
+
+/// The host an ssh, scp or sftp command connects to, in lower case
+///
+/// Looks at the destination operand (`[user@]host`, `[user@]host:path` or a
+/// `ssh://` style URI), not at `-o HostName` or the user's ssh_config. For
+/// scp, the first remote operand counts.
+pub fn destination_host(command: &[String]) -> Option<String> {
+    let tool = tool(command)?;
+    // Options that take a value
+    let with_value = match tool {
+        SshTool::Ssh => "BbcDEeFIiJLlmOoPpQRSWw",
+        SshTool::Scp => "cDFiJloPSX",
+        SshTool::Sftp => "BbcDFiJloPRSsX",
+    };
+
+    let mut args = command.iter().skip(1);
+    let mut options_done = false;
+    while let Some(arg) = args.next() {
+        if !options_done {
+            if arg == "--" {
+                options_done = true;
+                continue;
+            }
+            if let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) {
+                // The value is the rest of the argument or the next one
+                if let Some(at) = flags.find(|flag| with_value.contains(flag)) {
+                    if at + 1 == flags.len() {
+                        args.next();
+                    }
+                }
+                continue;
+            }
+        }
+        let is_remote = tool != SshTool::Scp || uri_authority(arg).is_some() || {
+            let colon = arg.find(':');
+            colon.is_some_and(|colon| !arg[..colon].contains('/'))
+        };
+        if is_remote {
+            return host_of(arg);
+        }
+    }
+    None
+}
+
+/// The part of a `ssh://`, `scp://` or `sftp://` URI before the path
+fn uri_authority(operand: &str) -> Option<&str> {
+    let rest = ["ssh://", "scp://", "sftp://"]
+        .iter()
+        .find_map(|scheme| operand.strip_prefix(scheme))?;
+    rest.split('/').next()
+}
+
+/// `host` out of `[user@]host[:port-or-path]`, `[user@][v6]:...` or a URI
+fn host_of(operand: &str) -> Option<String> {
+    let is_uri = uri_authority(operand).is_some();
+    let target = uri_authority(operand).unwrap_or(operand);
+    // A bare ssh destination may be an IPv6 address without brackets
+    let bracketed = target.find('[').and_then(|open| {
+        let close = target[open..].find(']')? + open;
+        Some(&target[open + 1..close])
+    });
+    let host = match bracketed {
+        Some(host) => host,
+        None if is_uri || target.matches(':').count() == 1 => {
+            let before = target.split(':').next().unwrap_or_default();
+            before.rsplit_once('@').map_or(before, |(_, host)| host)
+        }
+        None => target.rsplit_once('@').map_or(target, |(_, host)| host),
+    };
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+/// Insert arguments right after the program name
+pub fn insert_args(command: &mut Vec<String>, args: impl IntoIterator<Item = String>) {
+    if !command.is_empty() {
+        command.splice(1..1, args);
+    }
+}
+
+/// Whether the command already sets the `-o` option `name`
+pub fn sets_option(command: &[String], name: &str) -> bool {
+    let mut args = command.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("-o") {
+            Some("") => args.next().map(String::as_str),
+            Some(value) => Some(value),
+            None => continue,
+        };
+        let option = value
+            .and_then(|value| value.split(['=', ' ']).next())
+            .unwrap_or_default();
+        if option.eq_ignore_ascii_case(name) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Options `--ssh-hardened` adds
+///
+/// # Arguments
+/// * `max_attempts` - How many password prompts sshpass answers
+pub fn hardened_options(max_attempts: u32) -> Vec<(&'static str, String)> {
+    vec![
+        ("NumberOfPasswordPrompts", max_attempts.to_string()),
+        (
+            "PreferredAuthentications",
+            "password,keyboard-interactive".to_string(),
+        ),
+        // sshpass never accepts an unknown host key; fail with a message
+        // instead of a prompt nobody answers
+        ("StrictHostKeyChecking", "yes".to_string()),
+    ]
+}
+
+/// Add `options` as `-o name=value`, except those the command already sets
+/// or `skip` names, returning the options added
+pub fn add_options(
+    command: &mut Vec<String>,
+    options: &[(&str, String)],
+    skip: &[String],
+) -> Vec<String> {
+    let added: Vec<String> = options
+        .iter()
+        .filter(|(name, _)| {
+            !sets_option(command, name) && !skip.iter().any(|s| s.eq_ignore_ascii_case(name))
+        })
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    insert_args(
+        command,
+        added
+            .iter()
+            .flat_map(|option| ["-o".to_string(), option.clone()]),
+    );
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_detect_tool() {
+        assert_eq!(SshTool::detect("/usr/bin/ssh"), Some(SshTool::Ssh));
+        assert_eq!(SshTool::detect("scp"), Some(SshTool::Scp));
+        assert_eq!(SshTool::detect("SFTP.EXE"), Some(SshTool::Sftp));
+        assert_eq!(SshTool::detect("ssh-keygen"), None);
+        assert_eq!(tool(&[]), None);
+    }
+
+    #[test]
+    fn test_destination_host() {
+        let host = |args: &[&str]| destination_host(&argv(args));
+        assert_eq!(host(&["ssh", "Host.Example"]), Some("host.example".into()));
+        assert_eq!(
+            host(&["ssh", "-p", "2222", "-vv", "-oUser=x", "root@db", "uptime"]),
+            Some("db".into())
+        );
+        assert_eq!(
+            host(&["ssh", "-l", "root", "-p2222", "db"]),
+            Some("db".into())
+        );
+        assert_eq!(host(&["ssh", "ssh://me@[::1]:2222"]), Some("::1".into()));
+        assert_eq!(host(&["ssh", "fe80::1"]), Some("fe80::1".into()));
+        assert_eq!(host(&["ssh", "--", "-weird"]), Some("-weird".into()));
+        assert_eq!(
+            host(&["scp", "-P", "22", "./a:b", "local", "me@files:/tmp/x"]),
+            Some("files".into())
+        );
+        assert_eq!(
+            host(&["scp", "scp://backup:22/x", "."]),
+            Some("backup".into())
+        );
+        assert_eq!(host(&["scp", "[fe80::1]:x", "."]), Some("fe80::1".into()));
+        assert_eq!(
+            host(&["sftp", "-b", "batch", "me@files"]),
+            Some("files".into())
+        );
+        assert_eq!(host(&["scp", "a", "b"]), None);
+        assert_eq!(host(&["ssh", "-p", "22"]), None);
+        assert_eq!(host(&["telnet", "router"]), None);
+    }
+
+    #[test]
+    fn test_sets_option() {
+        let command = argv(&[
+            "ssh",
+            "-o",
+            "stricthostkeychecking=no",
+            "-oNumberOfPasswordPrompts 3",
+            "host",
+        ]);
+        assert!(sets_option(&command, "StrictHostKeyChecking"));
+        assert!(sets_option(&command, "NumberOfPasswordPrompts"));
+        assert!(!sets_option(&command, "PreferredAuthentications"));
+    }
+
+    #[test]
+    fn test_add_options_respects_user_and_skip() {
+        let mut command = argv(&["ssh", "-o", "StrictHostKeyChecking=accept-new", "host"]);
+        let added = add_options(
+            &mut command,
+            &hardened_options(2),
+            &["preferredauthentications".to_string()],
+        );
+        assert_eq!(added, vec!["NumberOfPasswordPrompts=2"]);
+        assert_eq!(
+            command,
+            argv(&[
+                "ssh",
+                "-o",
+                "NumberOfPasswordPrompts=2",
+                "-o",
+                "StrictHostKeyChecking=accept-new",
+                "host"
+            ])
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! ssh-related environment of the child (`--ssh-env`)
+//!
+//! The child inherits sshpass's environment. With an agent reachable through
+//! `SSH_AUTH_SOCK`, ssh may log in with a key and never ask for the
+//! password; sshpass then just waits for it to exit, unless
+//! `--require-prompt` asks for proof of password authentication. With
+//! `SSH_ASKPASS_REQUIRE=force`, ssh asks the `SSH_ASKPASS` program instead of
+//! the terminal, where sshpass cannot answer. `scrub` removes these
+//! variables before the child is spawned.
+
+/// Variables removed by `--ssh-env scrub`
+pub const SSH_ENV_VARS: &[&str] = &[
+    "SSH_AUTH_SOCK",
+    "SSH_AGENT_PID",
+    "SSH_ASKPASS",
+    "SSH_ASKPASS_REQUIRE",
+];
+
+/// `--ssh-env` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SshEnvMode {
+    /// Pass the variables on unchanged
+    #[default]
+    Preserve,
+    /// Remove them, so ssh can only log in with the password
+    Scrub,
+}
+
+/// Prepare our environment, which the child inherits, returning the
+/// variables removed
+pub fn apply(mode: SshEnvMode) -> Vec<&'static str> {
+    match mode {
+        SshEnvMode::Preserve => Vec::new(),
+        SshEnvMode::Scrub => remove_vars(SSH_ENV_VARS),
+    }
+}
+
+/// Whether an ssh agent is reachable by the child
+pub fn agent_present() -> bool {
+    std::env::var_os("SSH_AUTH_SOCK").is_some_and(|sock| !sock.is_empty())
+}
+
+fn remove_vars(names: &[&'static str]) -> Vec<&'static str> {
+    let mut removed = Vec::new();
+    for &name in names {
+        if std::env::var_os(name).is_some() {
+            std::env::remove_var(name);
+            removed.push(name);
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_only_set_vars() {
+        std::env::set_var("SSHPASS_TEST_SSH_ENV_SET", "/tmp/agent.sock");
+        let removed = remove_vars(&["SSHPASS_TEST_SSH_ENV_SET", "SSHPASS_TEST_SSH_ENV_UNSET"]);
+        assert_eq!(removed, vec!["SSHPASS_TEST_SSH_ENV_SET"]);
+        assert!(std::env::var_os("SSHPASS_TEST_SSH_ENV_SET").is_none());
+
+        assert!(apply(SshEnvMode::Preserve).is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Separate pipe for ssh's own diagnostics (`--ssh-stderr-pipe`)
+//!
+//! ssh prints its errors and `-v` debug output on the terminal, where they
+//! mix with the remote session and can trip the prompt detectors. Given
+//! `-E /dev/fd/N`, ssh logs to a pipe instead; sshpass labels each line and
+//! copies it to its own stderr.
+
+use crate::error::Result;
+use crate::ssh_argv::{self, SshTool};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// Prefix of every line ssh logs
+const LABEL: &[u8] = b"ssh: ";
+
+/// The log pipe shared with an ssh child
+pub struct SshLog {
+    read: OwnedFd,
+    /// Inherited by the child; closed in the parent once it is spawned
+    write: Option<OwnedFd>,
+    at_line_start: bool,
+}
+
+impl SshLog {
+    /// Create the pipe; only the write end is inherited across exec
+    pub fn new() -> Result<Self> {
+        let (read_fd, write_fd) = nix::unistd::pipe()?;
+        // SAFETY: pipe just returned these descriptors and nothing else owns them
+        let (read, write) = unsafe {
+            (
+                OwnedFd::from_raw_fd(read_fd),
+                OwnedFd::from_raw_fd(write_fd),
+            )
+        };
+        fcntl(read.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+        Ok(Self {
+            read,
+            write: Some(write),
+            at_line_start: true,
+        })
+    }
+
+    /// Make ssh log to the pipe, returning false if `command` is not ssh
+    ///
+    /// The option goes right after the program name so it is not taken as
+    /// part of the remote command.
+    pub fn inject(&self, command: &mut Vec<String>) -> bool {
+        let Some(write) = self.write.as_ref() else {
+            return false;
+        };
+        if ssh_argv::tool(command) != Some(SshTool::Ssh) {
+            return false;
+        }
+        ssh_argv::insert_args(
+            command,
+            ["-E".to_string(), format!("/dev/fd/{}", write.as_raw_fd())],
+        );
+        true
+    }
+
+    /// Close the parent's copy of the write end, so the pipe reports EOF
+    /// once ssh exits
+    pub fn close_child_end(&mut self) {
+        self.write = None;
+    }
+
+    /// Read what ssh logged; `None` at EOF
+    pub fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buffer = [0u8; 4096];
+        let n = nix::unistd::read(self.read.as_raw_fd(), &mut buffer)?;
+        Ok((n > 0).then(|| buffer[..n].to_vec()))
+    }
+
+    /// Prefix every line of `data` with the label
+    pub fn label(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len() + LABEL.len());
+        for &byte in data {
+            if self.at_line_start {
+                output.extend_from_slice(LABEL);
+            }
+            output.push(byte);
+            self.at_line_start = byte == b'\n';
+        }
+        output
+    }
+}
+
+/// The read end, to wait on for log output
+impl AsRawFd for SshLog {
+    fn as_raw_fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_only_into_ssh() {
+        let log = SshLog::new().unwrap();
+        let mut command = vec!["/usr/bin/ssh".to_string(), "host".to_string()];
+        assert!(log.inject(&mut command));
+        assert_eq!(command[1], "-E");
+        assert!(command[2].starts_with("/dev/fd/"));
+        assert_eq!(command[3], "host");
+
+        let mut command = vec!["scp".to_string(), "a".to_string(), "host:".to_string()];
+        assert!(!log.inject(&mut command));
+        assert_eq!(command.len(), 3);
+    }
+
+    #[test]
+    fn test_log_lines_are_labelled() {
+        let mut log = SshLog::new().unwrap();
+        let write = log.write.take().unwrap();
+        nix::unistd::write(write.as_raw_fd(), b"debug1: one\ndebug1: t").unwrap();
+
+        let data = log.read().unwrap().unwrap();
+        assert_eq!(log.label(&data), b"ssh: debug1: one\nssh: debug1: t");
+        assert_eq!(log.label(b"wo\n"), b"wo\n");
+
+        drop(write);
+        assert_eq!(log.read().unwrap(), None);
+    }
+}
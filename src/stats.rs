@@ -0,0 +1,211 @@
+//! Session statistics
+//!
+//! Byte counters and timing for the running session, shown by the `~s`
+//! escape command and the `--progress-io` meter, and the timing of the
+//! login steps reported at `-vv`.
+
+use std::time::{Duration, Instant};
+
+/// Counters for data flowing through the PTY
+#[derive(Debug, Clone)]
+pub struct SessionStats {
+    started: Instant,
+    /// Bytes read from the child's PTY
+    pub bytes_from_child: u64,
+    /// Bytes written to the child's PTY (excluding the password)
+    pub bytes_to_child: u64,
+}
+
+impl SessionStats {
+    /// Start counting from now
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes_from_child: 0,
+            bytes_to_child: 0,
+        }
+    }
+
+    /// Record data read from the PTY
+    pub fn record_output(&mut self, len: usize) {
+        self.bytes_from_child += len as u64;
+    }
+
+    /// Record data forwarded to the PTY
+    pub fn record_input(&mut self, len: usize) {
+        self.bytes_to_child += len as u64;
+    }
+
+    /// Time since the session started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// One-line human readable summary
+    pub fn summary(&self) -> String {
+        format!(
+            "session time {:.1}s, received {} bytes, sent {} bytes",
+            self.elapsed().as_secs_f64(),
+            self.bytes_from_child,
+            self.bytes_to_child
+        )
+    }
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The rate of a byte counter, measured over windows of `window`
+#[derive(Debug, Clone)]
+pub struct Throughput {
+    window: Duration,
+    window_start: Instant,
+    window_bytes: u64,
+    rate: f64,
+}
+
+impl Throughput {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            window,
+            window_start: now,
+            window_bytes: 0,
+            rate: 0.0,
+        }
+    }
+
+    /// Take the counter's value `total` at `now`, returning the bytes per
+    /// second over the last complete window
+    pub fn sample(&mut self, now: Instant, total: u64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= self.window {
+            self.rate = total.saturating_sub(self.window_bytes) as f64 / elapsed.as_secs_f64();
+            self.window_start = now;
+            self.window_bytes = total;
+        }
+        self.rate
+    }
+}
+
+/// When each step of the login happened
+///
+/// Shows where a slow login spends its time: connecting (DNS, GSSAPI)
+/// shows up before the first output, the server checking the password
+/// after it was sent.
+#[derive(Debug, Clone)]
+pub struct AuthTiming {
+    spawned: Instant,
+    first_output: Option<Instant>,
+    last_output: Option<Instant>,
+    /// Output that held the password prompt
+    prompt: Option<Instant>,
+    password_sent: Option<Instant>,
+    /// First output after the password other than the echoed line break
+    post_auth_output: Option<Instant>,
+}
+
+impl AuthTiming {
+    /// Start timing a child spawned at `spawned`
+    pub fn starting_at(spawned: Instant) -> Self {
+        Self {
+            spawned,
+            first_output: None,
+            last_output: None,
+            prompt: None,
+            password_sent: None,
+            post_auth_output: None,
+        }
+    }
+
+    /// Record output read at `at`, returning true once the login is complete
+    pub fn record_output(&mut self, at: Instant, data: &[u8]) -> bool {
+        self.first_output.get_or_insert(at);
+        if self.password_sent.is_some() && self.post_auth_output.is_none() {
+            if data.iter().all(u8::is_ascii_whitespace) {
+                return false;
+            }
+            self.post_auth_output = Some(at);
+            return true;
+        }
+        self.last_output = Some(at);
+        false
+    }
+
+    /// Record the password being typed at `at`; later passwords are ignored
+    pub fn record_password_sent(&mut self, at: Instant) {
+        if self.password_sent.is_none() {
+            self.prompt = self.last_output;
+            self.password_sent = Some(at);
+        }
+    }
+
+    /// One-line report, once the login is complete
+    pub fn summary(&self) -> Option<String> {
+        let first_output = self.first_output?;
+        let prompt = self.prompt?;
+        let password_sent = self.password_sent?;
+        let post_auth_output = self.post_auth_output?;
+        let step = |from: Instant, to: Instant| to.saturating_duration_since(from).as_secs_f64();
+        Some(format!(
+            "spawn->output {:.3}s, output->prompt {:.3}s, prompt->password {:.3}s, password->output {:.3}s",
+            step(self.spawned, first_output),
+            step(first_output, prompt),
+            step(prompt, password_sent),
+            step(password_sent, post_auth_output)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_stats_counts() {
+        let mut stats = SessionStats::new();
+        stats.record_output(10);
+        stats.record_output(5);
+        stats.record_input(3);
+        assert_eq!(stats.bytes_from_child, 15);
+        assert_eq!(stats.bytes_to_child, 3);
+        assert!(stats.summary().contains("received 15 bytes, sent 3 bytes"));
+    }
+
+    #[test]
+    fn test_throughput() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throughput = Throughput::new(Duration::from_secs(1), start);
+        assert_eq!(throughput.sample(at(500), 1_000), 0.0);
+        assert_eq!(throughput.sample(at(1000), 4_000), 4_000.0);
+        // The rate holds until the next window is complete
+        assert_eq!(throughput.sample(at(1500), 100_000), 4_000.0);
+        assert_eq!(throughput.sample(at(3000), 10_000), 3_000.0);
+    }
+
+    #[test]
+    fn test_auth_timing_summary() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut timing = AuthTiming::starting_at(start);
+
+        assert!(!timing.record_output(at(200), b"Banner\r\n"));
+        assert!(!timing.record_output(at(250), b"user@host's password: "));
+        timing.record_password_sent(at(350));
+        assert_eq!(timing.summary(), None);
+
+        // The line break ssh prints after reading the password does not count
+        assert!(!timing.record_output(at(351), b"\r\n"));
+        assert!(timing.record_output(at(1350), b"Last login: ..."));
+        assert!(!timing.record_output(at(1400), b"$ "));
+        timing.record_password_sent(at(2000));
+
+        assert_eq!(
+            timing.summary().unwrap(),
+            "spawn->output 0.200s, output->prompt 0.050s, prompt->password 0.100s, password->output 1.000s"
+        );
+    }
+}
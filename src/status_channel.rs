@@ -0,0 +1,286 @@
+//! Exit status side channel (`--status-channel`)
+//!
+//! sshpass exits with the child's code, 128 plus the signal number when the
+//! child was killed, and its own codes (1-11) when it fails itself, so a
+//! remote command that returns 5 or 143 cannot be told apart from an
+//! incorrect password or a SIGTERM. The exit code stays as it is; with
+//! `--status-channel` sshpass also writes one JSON line on its way out, to a
+//! file or to `fd:N` (Unix), saying where the code came from:
+//!
+//! ```text
+//! {"source":"child","code":143,"signal":15,"signal_name":"SIGTERM","core_dumped":false,"session":"<uuid>","labels":{}}
+//! {"source":"sshpass","code":5,"signal":null,"session":"<uuid>","labels":{"job":"nightly"}}
+//! ```
+//!
+//! `code` is the code sshpass exits with, after `--map-exit-code`, and
+//! `signal` the number of the signal that killed the child, if one did; only
+//! then do `signal_name` (`null` for a signal the platform has no name for)
+//! and `core_dumped` follow. `session` and `labels` identify the run (see
+//! [`crate::labels`]).
+//!
+//! `--json-result` writes the same object, to a target of its own, with a
+//! `prompt` field telling where the password was typed, or `null` if it
+//! never was:
+//!
+//! ```text
+//! {...,"prompt":{"pattern":"assword","offset":512,"context":"Last login: ...\nuser@host's password"}}
+//! ```
+//!
+//! `offset` counts the output with escape sequences removed (see
+//! [`PromptMatch`]), and `context` is the output up to the end of the match.
+//!
+//! With `--rusage`, both objects also say what the child used, in seconds
+//! of CPU time and bytes of peak memory (`null` where the platform does not
+//! report it):
+//!
+//! ```text
+//! {...,"signal":null,"rusage":{"user":1.250,"system":0.310,"max_rss":10485760},...}
+//! ```
+
+use crate::audit::json_string;
+use crate::error::{Result, SshpassError};
+use crate::labels::{self, SessionLabels};
+use crate::monitor::PromptMatch;
+use crate::process::{ResourceUsage, Termination};
+use std::fs::File;
+use std::io::Write;
+
+/// Who produced the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitSource {
+    /// The child exited, or a signal killed it
+    Child,
+    /// sshpass failed itself
+    Sshpass,
+}
+
+/// How the session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus {
+    pub source: ExitSource,
+    /// The code sshpass exits with
+    pub code: i32,
+    /// The signal that killed the child
+    pub signal: Option<Termination>,
+    /// What the child used, when asked for
+    pub usage: Option<ResourceUsage>,
+}
+
+impl ExitStatus {
+    /// The child ended the session
+    pub fn child(code: i32, signal: Option<Termination>) -> Self {
+        Self {
+            source: ExitSource::Child,
+            code,
+            signal,
+            usage: None,
+        }
+    }
+
+    /// sshpass failed with `code`
+    pub fn sshpass(code: i32) -> Self {
+        Self {
+            source: ExitSource::Sshpass,
+            code,
+            signal: None,
+            usage: None,
+        }
+    }
+
+    /// Report what the child used as well
+    pub fn with_usage(mut self, usage: Option<ResourceUsage>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// The status line of `session`, without the newline
+    pub fn line(&self, session: &SessionLabels) -> String {
+        let source = match self.source {
+            ExitSource::Child => "child",
+            ExitSource::Sshpass => "sshpass",
+        };
+        let signal = self.signal.map_or_else(
+            || "null".to_string(),
+            |termination| {
+                format!(
+                    "{},\"signal_name\":{},\"core_dumped\":{}",
+                    termination.signal,
+                    termination
+                        .signal_name()
+                        .map_or_else(|| "null".to_string(), json_string),
+                    termination.core_dumped
+                )
+            },
+        );
+        let usage = self.usage.map_or_else(String::new, |usage| {
+            format!(
+                ",\"rusage\":{{\"user\":{:.3},\"system\":{:.3},\"max_rss\":{}}}",
+                usage.user_time.as_secs_f64(),
+                usage.system_time.as_secs_f64(),
+                usage
+                    .max_rss
+                    .map_or_else(|| "null".to_string(), |bytes| bytes.to_string())
+            )
+        });
+        format!(
+            "{{\"source\":\"{}\",\"code\":{},\"signal\":{}{},{}}}",
+            source,
+            self.code,
+            signal,
+            usage,
+            session.json_fields()
+        )
+    }
+
+    /// The `--json-result` line of `session`, without the newline
+    pub fn result_line(&self, session: &SessionLabels, prompt: Option<&PromptMatch>) -> String {
+        let line = self.line(session);
+        let prompt = prompt.map_or_else(
+            || "null".to_string(),
+            |found| {
+                format!(
+                    "{{\"pattern\":{},\"offset\":{},\"context\":{}}}",
+                    json_string(&found.pattern),
+                    found.offset,
+                    json_string(&String::from_utf8_lossy(&found.context))
+                )
+            },
+        );
+        format!("{},\"prompt\":{}}}", &line[..line.len() - 1], prompt)
+    }
+}
+
+/// Where the status or result line goes
+pub struct StatusChannel {
+    out: File,
+}
+
+impl StatusChannel {
+    /// Create or truncate a file, or take over `fd:N` on Unix; `option`
+    /// names the option in errors
+    pub fn open(spec: &str, option: &str) -> Result<Self> {
+        #[cfg(unix)]
+        if let Some(fd) = spec.strip_prefix("fd:") {
+            let out = crate::tee::take_fd(fd, spec, option)?;
+            return Ok(Self { out });
+        }
+
+        let out = crate::tee::create_file(spec.as_ref()).map_err(|e| {
+            SshpassError::InvalidArguments(format!(
+                "Failed to open {} file {}: {}",
+                option, spec, e
+            ))
+        })?;
+        Ok(Self { out })
+    }
+
+    /// Write the status line
+    pub fn report(&mut self, status: &ExitStatus) -> Result<()> {
+        self.write_line(&status.line(labels::session()))
+    }
+
+    /// Write the `--json-result` line
+    pub fn report_result(
+        &mut self,
+        status: &ExitStatus,
+        prompt: Option<&PromptMatch>,
+    ) -> Result<()> {
+        self.write_line(&status.result_line(labels::session(), prompt))
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        writeln!(self.out, "{}", line)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_line() {
+        let session = SessionLabels {
+            id: "ID".to_string(),
+            labels: Vec::new(),
+        };
+        // Signal names come from the platform
+        #[cfg(unix)]
+        {
+            let sigterm = Termination {
+                signal: 15,
+                core_dumped: false,
+            };
+            assert_eq!(
+                ExitStatus::child(143, Some(sigterm)).line(&session),
+                r#"{"source":"child","code":143,"signal":15,"signal_name":"SIGTERM","core_dumped":false,"session":"ID","labels":{}}"#
+            );
+        }
+        let unnamed = Termination {
+            signal: 200,
+            core_dumped: true,
+        };
+        assert_eq!(
+            ExitStatus::child(0, Some(unnamed)).line(&session),
+            r#"{"source":"child","code":0,"signal":200,"signal_name":null,"core_dumped":true,"session":"ID","labels":{}}"#
+        );
+        assert_eq!(
+            ExitStatus::child(143, None).line(&session),
+            r#"{"source":"child","code":143,"signal":null,"session":"ID","labels":{}}"#
+        );
+        assert_eq!(
+            ExitStatus::sshpass(5).line(&session),
+            r#"{"source":"sshpass","code":5,"signal":null,"session":"ID","labels":{}}"#
+        );
+        let usage = ResourceUsage {
+            user_time: std::time::Duration::from_millis(1250),
+            system_time: std::time::Duration::from_millis(310),
+            max_rss: Some(10 << 20),
+        };
+        assert_eq!(
+            ExitStatus::child(0, None)
+                .with_usage(Some(usage))
+                .line(&session),
+            r#"{"source":"child","code":0,"signal":null,"rusage":{"user":1.250,"system":0.310,"max_rss":10485760},"session":"ID","labels":{}}"#
+        );
+    }
+
+    #[test]
+    fn test_result_line() {
+        let session = SessionLabels {
+            id: "ID".to_string(),
+            labels: Vec::new(),
+        };
+        let found = PromptMatch {
+            pattern: "assword".to_string(),
+            offset: 12,
+            context: b"Hi\nuser@h's password".to_vec(),
+        };
+        assert_eq!(
+            ExitStatus::child(0, None).result_line(&session, Some(&found)),
+            r#"{"source":"child","code":0,"signal":null,"session":"ID","labels":{},"prompt":{"pattern":"assword","offset":12,"context":"Hi\nuser@h's password"}}"#
+        );
+        assert_eq!(
+            ExitStatus::sshpass(11).result_line(&session, None),
+            r#"{"source":"sshpass","code":11,"signal":null,"session":"ID","labels":{},"prompt":null}"#
+        );
+    }
+
+    #[test]
+    fn test_status_channel_file() {
+        let path = std::env::temp_dir().join(format!("sshpass-status-{}", std::process::id()));
+        let mut channel = StatusChannel::open(path.to_str().unwrap(), "--status-channel").unwrap();
+        channel.report(&ExitStatus::sshpass(3)).unwrap();
+        drop(channel);
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            written,
+            format!(
+                "{{\"source\":\"sshpass\",\"code\":3,\"signal\":null,{}}}\n",
+                labels::session().json_fields()
+            )
+        );
+    }
+}
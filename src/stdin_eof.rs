@@ -0,0 +1,207 @@
+//! What happens when the forwarded stdin ends (`--on-stdin-eof`)
+//!
+//! sshpass forwards stdin to the PTY on Windows, and on Unix when stdin is a
+//! terminal. A PTY has no write half to close, so by default the end of stdin
+//! goes unnoticed and `echo cmd | sshpass ssh host bash` waits on the remote
+//! shell for good. `send-eof` types the terminal's EOF character (Ctrl-D)
+//! instead, which ends a shell or `cat` reading from the terminal;
+//! `close-session` hangs up on the child (SIGHUP on Unix; it is terminated on
+//! Windows). On Unix a pipe or file stays the child's own stdin and ends the
+//! usual way.
+//!
+//! Either way the session then drains: the event loop passes on the child's
+//! output until it exits. With `--drain-timeout`, a child still running when
+//! the timeout runs out is hung up on, then terminated, then killed, the
+//! timeout starting over after each step.
+
+use std::time::{Duration, Instant};
+
+/// `--on-stdin-eof` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub enum StdinEofMode {
+    /// Leave the session running until the child exits
+    #[default]
+    KeepOpen,
+    /// Type Ctrl-D
+    SendEof,
+    /// Hang up on the child
+    CloseSession,
+}
+
+/// The EOF character of a terminal (Ctrl-D)
+pub const EOF_CHAR: u8 = 0x04;
+
+/// What to type so the child reads EOF, after `last`, the last byte
+/// forwarded
+///
+/// Ctrl-D in the middle of a line only hands the line over, so a second one
+/// follows when the input did not end with a newline.
+pub fn eof_input(last: Option<u8>) -> &'static [u8] {
+    match last {
+        None | Some(b'\n' | b'\r') => &[EOF_CHAR],
+        Some(_) => &[EOF_CHAR, EOF_CHAR],
+    }
+}
+
+/// What is done to a child that outlasts the drain timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    /// SIGHUP, as if the terminal hung up
+    Hangup,
+    /// SIGTERM
+    Terminate,
+    /// SIGKILL
+    Kill,
+}
+
+impl Escalation {
+    fn next(self) -> Option<Self> {
+        match self {
+            Escalation::Hangup => Some(Escalation::Terminate),
+            Escalation::Terminate => Some(Escalation::Kill),
+            Escalation::Kill => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// stdin is open, or its end was not passed on
+    Open,
+    /// Waiting for the child to exit; `next` is due at `deadline`
+    Draining {
+        deadline: Option<Instant>,
+        next: Escalation,
+    },
+    /// The child was killed
+    Killed,
+}
+
+/// The session after the end of stdin was passed on
+#[derive(Debug)]
+pub struct Drain {
+    timeout: Option<Duration>,
+    state: State,
+    escalated: bool,
+}
+
+impl Drain {
+    /// Escalate every `timeout`, or never
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            state: State::Open,
+            escalated: false,
+        }
+    }
+
+    /// The end of stdin was passed on with `mode`
+    pub fn start(&mut self, mode: StdinEofMode) {
+        let next = match mode {
+            StdinEofMode::KeepOpen => return,
+            StdinEofMode::SendEof => Escalation::Hangup,
+            // Hung up already
+            StdinEofMode::CloseSession => Escalation::Terminate,
+        };
+        if self.state == State::Open {
+            self.state = State::Draining {
+                deadline: self.deadline(),
+                next,
+            };
+        }
+    }
+
+    /// Time left until the next escalation
+    pub fn remaining(&self) -> Option<Duration> {
+        match self.state {
+            State::Draining {
+                deadline: Some(deadline),
+                ..
+            } => Some(deadline.saturating_duration_since(Instant::now())),
+            _ => None,
+        }
+    }
+
+    /// The escalation that is due, if any; the timeout starts over for the
+    /// one after it
+    pub fn due(&mut self) -> Option<Escalation> {
+        let State::Draining {
+            deadline: Some(deadline),
+            next,
+        } = self.state
+        else {
+            return None;
+        };
+        if Instant::now() < deadline {
+            return None;
+        }
+        self.state = match next.next() {
+            Some(after) => State::Draining {
+                deadline: self.deadline(),
+                next: after,
+            },
+            None => State::Killed,
+        };
+        self.escalated = true;
+        Some(next)
+    }
+
+    /// Whether the timeout ran out and the child was signalled
+    pub fn escalated(&self) -> bool {
+        self.escalated
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eof_input() {
+        assert_eq!(eof_input(None), b"\x04");
+        assert_eq!(eof_input(Some(b'\n')), b"\x04");
+        assert_eq!(eof_input(Some(b'\r')), b"\x04");
+        assert_eq!(eof_input(Some(b'x')), b"\x04\x04");
+    }
+
+    #[test]
+    fn test_drain_escalation() {
+        let mut drain = Drain::new(Some(Duration::ZERO));
+        assert_eq!(drain.due(), None);
+        drain.start(StdinEofMode::KeepOpen);
+        assert_eq!(drain.due(), None);
+        assert!(!drain.escalated());
+
+        drain.start(StdinEofMode::SendEof);
+        assert_eq!(drain.due(), Some(Escalation::Hangup));
+        assert!(drain.escalated());
+        assert_eq!(drain.due(), Some(Escalation::Terminate));
+        assert_eq!(drain.due(), Some(Escalation::Kill));
+        assert_eq!(drain.due(), None);
+        assert_eq!(drain.remaining(), None);
+
+        // The child was hung up on already
+        let mut drain = Drain::new(Some(Duration::ZERO));
+        drain.start(StdinEofMode::CloseSession);
+        assert_eq!(drain.due(), Some(Escalation::Terminate));
+
+        let mut drain = Drain::new(Some(Duration::from_secs(60)));
+        drain.start(StdinEofMode::SendEof);
+        assert_eq!(drain.due(), None);
+        assert!(drain.remaining().unwrap() > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn test_drain_without_timeout() {
+        let mut drain = Drain::new(None);
+        drain.start(StdinEofMode::SendEof);
+        assert_eq!(drain.remaining(), None);
+        assert_eq!(drain.due(), None);
+    }
+}
@@ -1,18 +1,39 @@
 //! stdin 轉發器 - 將用戶輸入轉發到 PTY
 //!
-//! 在 Windows 上使用獨立執行緒讀取 stdin 並轉發
+//! 在 Windows 上使用獨立執行緒讀取 stdin 並轉發；Unix 上由主迴圈把 stdin 的
+//! fd 加入 pselect 直接讀取（[`StdinReader`]），不另開執行緒。
+//!
+//! Unix 只在 stdin 是前景終端時轉發。管道、檔案與已關閉的 stdin 仍由子程序
+//! 直接讀取，二進位資料與 EOF 都原樣到達，不經過 PTY 的行規範。
+
+use crate::escape::{EscapeAction, EscapeOutput, EscapeParser};
+use crate::flow_control::FlowControlMode;
+use crate::keymap::EnterMode;
+use crate::stdin_eof::StdinEofMode;
+use std::cell::Cell;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
 
-use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(windows)]
+use std::io;
+#[cfg(windows)]
+use std::sync::mpsc::channel;
+#[cfg(windows)]
 use std::thread;
 
 #[cfg(unix)]
-use std::io::{self, Read};
+use nix::sys::termios::Termios;
+#[cfg(unix)]
+use std::os::fd::RawFd;
 
 #[cfg(windows)]
-use std::io;
+use crate::keymap::{self, KeyAction};
 
 #[cfg(windows)]
-use windows::Win32::Storage::FileSystem::ReadFile;
+use windows::Win32::Storage::FileSystem::{
+    FileNameInfo, GetFileInformationByHandleEx, GetFileType, ReadFile, FILE_NAME_INFO,
+    FILE_TYPE_PIPE,
+};
 #[cfg(windows)]
 use windows::Win32::System::Console::{
     GetConsoleMode, GetStdHandle, ReadConsoleInputW, SetConsoleMode, CONSOLE_MODE,
@@ -21,16 +42,50 @@ use windows::Win32::System::Console::{
 };
 
 /// stdin 輸入事件
+#[derive(Debug)]
 pub enum StdinEvent {
     Data(Vec<u8>),
+    /// 使用者透過跳脫字元選單要求的本地動作
+    Escape(EscapeAction),
     Eof,
 }
 
+/// stdin 轉發器設定
+#[derive(Debug, Clone, Default)]
+pub struct ForwarderConfig {
+    /// 在此時間窗內抵達的輸入會合併為一次 PTY 寫入（0 表示只合併已在佇列中的資料）
+    pub coalesce: Duration,
+    /// stdin 結束後等待子程序結束的時間，逾時即逐步升級（None 表示一直等待）
+    pub drain_timeout: Option<Duration>,
+    /// Enter 鍵（console 模式）送出的換行字元
+    pub enter: EnterMode,
+    /// 跳脫字元（僅在互動式終端輸入時生效），None 表示停用
+    pub escape_char: Option<u8>,
+    /// 是否遵從遠端的 XON/XOFF
+    pub flow_control: FlowControlMode,
+    /// 登入後改變一次視窗大小再改回，讓遠端重新取得大小
+    pub initial_resize_retry: bool,
+    /// 沒有 console 可用：不設定 console mode，stdin 一律當管道讀取
+    #[cfg(windows)]
+    pub no_console: bool,
+    /// stdin 結束時對 session 的處理
+    pub on_eof: StdinEofMode,
+    /// 包裝 bracketed paste 時，貼上內容中的 CR/CRLF 不轉換、一律以 LF 送出
+    pub paste_safe: bool,
+    /// 大量資料流動時在 stderr 顯示傳輸進度（`--progress-io`）
+    pub progress_io: bool,
+}
+
 /// stdin 轉發器
+#[cfg(windows)]
 pub struct StdinForwarder {
     receiver: Receiver<StdinEvent>,
-    #[cfg(windows)]
+    config: ForwarderConfig,
+    /// 合併資料時遇到的非資料事件，留待下一次 try_recv 回傳
+    pending: Cell<Option<StdinEvent>>,
     original_mode: Option<CONSOLE_MODE>,
+    /// 暫停時不取出事件，留在通道中
+    paused: bool,
 }
 
 /// 檢查 stdin 是否為 console (Windows)
@@ -46,46 +101,121 @@ fn is_stdin_console() -> bool {
     }
 }
 
+/// 檢查 stdin 是否為 MSYS2/Cygwin 的 pty（Git Bash、mintty）
+///
+/// 這類終端對原生程式來說只是一個具名管道，需以管道方式讀取，但仍是互動式輸入
+#[cfg(windows)]
+fn is_stdin_cygwin_pty() -> bool {
+    unsafe {
+        let Ok(handle) = GetStdHandle(STD_INPUT_HANDLE) else {
+            return false;
+        };
+        if GetFileType(handle) != FILE_TYPE_PIPE {
+            return false;
+        }
+
+        // FILE_NAME_INFO 後接 UTF-16 名稱；以 u32 陣列確保對齊
+        let mut info = [0u32; 256];
+        if GetFileInformationByHandleEx(
+            handle,
+            FileNameInfo,
+            info.as_mut_ptr().cast(),
+            std::mem::size_of_val(&info) as u32,
+        )
+        .is_err()
+        {
+            return false;
+        }
+
+        let info = &*(info.as_ptr() as *const FILE_NAME_INFO);
+        let len = (info.FileNameLength as usize / 2).min(255 * 2);
+        let name = std::slice::from_raw_parts(info.FileName.as_ptr(), len);
+        is_cygwin_pty_name(&String::from_utf16_lossy(name))
+    }
+}
+
+/// 判斷管道名稱是否為 Cygwin/MSYS2 pty，例如 `\msys-1888ae32e00d56aa-pty0-from-master`
+fn is_cygwin_pty_name(name: &str) -> bool {
+    let name = name.trim_start_matches('\\');
+    (name.starts_with("msys-") || name.starts_with("cygwin-"))
+        && name.contains("-pty")
+        && (name.ends_with("-from-master") || name.ends_with("-to-master"))
+}
+
+/// 將終端送出的 CR、LF 或 CRLF 換成設定的 Enter 字元
+fn translate_enter(data: &[u8], enter: EnterMode) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'\r' => {
+                bytes.next_if_eq(&&b'\n');
+                out.extend_from_slice(enter.as_bytes());
+            }
+            b'\n' => out.extend_from_slice(enter.as_bytes()),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+#[cfg(windows)]
 impl StdinForwarder {
     /// 創建新的 stdin 轉發器並啟動後台執行緒
-    pub fn new(verbose: bool) -> io::Result<Self> {
+    pub fn new(config: ForwarderConfig, verbose: bool) -> io::Result<Self> {
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] StdinForwarder::new() called");
+            crate::diag!("[DEBUG] StdinForwarder::new() called");
         }
 
         let (sender, receiver) = channel();
 
-        // 在 Windows 上設定 raw mode
-        #[cfg(windows)]
-        let original_mode = Self::set_raw_mode(verbose)?;
+        // 設定 console 為 raw mode
+        let original_mode = if config.no_console {
+            None
+        } else {
+            Self::set_raw_mode(verbose)?
+        };
 
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Spawning stdin reader thread...");
+            crate::diag!("[DEBUG] Spawning stdin reader thread...");
         }
 
-        // 啟動後台執行緒讀取 stdin（捕獲 verbose 變數）
+        // 啟動後台執行緒讀取 stdin（捕獲設定與 verbose 變數）
+        let thread_config = config.clone();
         thread::spawn(move || {
-            Self::read_stdin_loop(sender, verbose);
+            Self::read_stdin_loop(sender, thread_config, verbose);
         });
 
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] StdinForwarder created successfully");
+            crate::diag!("[DEBUG] StdinForwarder created successfully");
         }
 
         Ok(Self {
             receiver,
-            #[cfg(windows)]
+            config,
+            pending: Cell::new(None),
             original_mode,
+            paused: false,
         })
     }
 
     /// 嘗試接收 stdin 事件（非阻塞）
+    ///
+    /// 佇列中所有的資料會合併成單一 `Data` 事件，讓呼叫端一次寫入 PTY；
+    /// 若設定了合併時間窗，會在收到第一筆資料後再等待該時間以收集後續按鍵。
     pub fn try_recv(&self) -> Option<StdinEvent> {
-        self.receiver.try_recv().ok()
+        if self.paused {
+            return None;
+        }
+        recv_coalesced(&self.receiver, &self.pending, self.config.coalesce)
+    }
+
+    /// 暫停或恢復取出輸入（遠端送出 XOFF 期間）
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
     }
 
     /// Windows: 設定 console 為 raw mode
-    #[cfg(windows)]
     fn set_raw_mode(verbose: bool) -> io::Result<Option<CONSOLE_MODE>> {
         unsafe {
             let handle = GetStdHandle(STD_INPUT_HANDLE).map_err(|e| {
@@ -98,13 +228,13 @@ impl StdinForwarder {
             let mut mode = CONSOLE_MODE(0);
             if GetConsoleMode(handle, &mut mode).is_err() {
                 if verbose {
-                    eprintln!("SSHPASS: [DEBUG] stdin is not a console (probably a pipe/file), skipping raw mode setup");
+                    crate::diag!("[DEBUG] stdin is not a console (probably a pipe/file), skipping raw mode setup");
                 }
                 return Ok(None);
             }
 
             if verbose {
-                eprintln!("SSHPASS: [DEBUG] stdin is a console, setting raw mode");
+                crate::diag!("[DEBUG] stdin is a console, setting raw mode");
             }
 
             let original_mode = mode;
@@ -122,48 +252,49 @@ impl StdinForwarder {
             })?;
 
             if verbose {
-                eprintln!(
-                    "SSHPASS: [DEBUG] Console set to raw mode (mode={:#x})",
-                    mode.0
-                );
+                crate::diag!("[DEBUG] Console set to raw mode (mode={:#x})", mode.0);
             }
 
             Ok(Some(original_mode))
         }
     }
 
-    /// Unix: 設定終端為 raw mode
-    #[cfg(unix)]
-    fn set_raw_mode(_verbose: bool) -> io::Result<()> {
-        // Unix 實作暫時省略，因為目前只需要 Windows
-        Ok(())
-    }
-
     /// 後台執行緒：持續讀取 stdin (Windows 版本)
-    #[cfg(windows)]
-    fn read_stdin_loop(sender: Sender<StdinEvent>, verbose: bool) {
+    fn read_stdin_loop(sender: Sender<StdinEvent>, config: ForwarderConfig, verbose: bool) {
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Starting stdin read loop (Windows)");
+            crate::diag!("[DEBUG] Starting stdin read loop (Windows)");
         }
 
-        // 檢查 stdin 是否為 console
-        let is_console = is_stdin_console();
+        // 檢查 stdin 是否為 console（--no-console 時一律當管道讀取）
+        let is_console = !config.no_console && is_stdin_console();
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] stdin is_console: {}", is_console);
+            crate::diag!("[DEBUG] stdin is_console: {}", is_console);
         }
 
         if is_console {
-            Self::read_console_loop(sender, verbose);
+            let parser = config.escape_char.map(EscapeParser::new);
+            Self::read_console_loop(sender, config.enter, parser, verbose);
+        } else if !config.no_console && is_stdin_cygwin_pty() {
+            // Git Bash/mintty：按鍵已是 VT 序列，只需處理 Enter 與跳脫字元
+            if verbose {
+                crate::diag!("[DEBUG] stdin is an MSYS2/Cygwin pty");
+            }
+            let parser = config.escape_char.map(EscapeParser::new);
+            Self::read_pipe_loop(sender, Some(config.enter), parser, verbose);
         } else {
-            Self::read_pipe_loop(sender, verbose);
+            Self::read_pipe_loop(sender, None, None, verbose);
         }
     }
 
     /// 從 Console 讀取（使用 ReadConsoleInputW）
-    #[cfg(windows)]
-    fn read_console_loop(sender: Sender<StdinEvent>, verbose: bool) {
+    fn read_console_loop(
+        sender: Sender<StdinEvent>,
+        enter: EnterMode,
+        mut parser: Option<EscapeParser>,
+        verbose: bool,
+    ) {
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Using ReadConsoleInputW for console input");
+            crate::diag!("[DEBUG] Using ReadConsoleInputW for console input");
         }
 
         unsafe {
@@ -171,7 +302,7 @@ impl StdinForwarder {
                 Ok(h) => h,
                 Err(e) => {
                     if verbose {
-                        eprintln!("SSHPASS: [DEBUG] Failed to get stdin handle: {}", e);
+                        crate::diag!("[DEBUG] Failed to get stdin handle: {}", e);
                     }
                     return;
                 }
@@ -184,6 +315,9 @@ impl StdinForwarder {
 
                 match ReadConsoleInputW(handle, &mut input_buffer, &mut events_read) {
                     Ok(_) => {
+                        // 同一次 ReadConsoleInputW 取得的按鍵合併為一筆資料送出
+                        let mut batch = Vec::new();
+
                         for i in 0..events_read as usize {
                             let event = &input_buffer[i];
 
@@ -201,49 +335,63 @@ impl StdinForwarder {
                                         continue;
                                     }
 
-                                    // 過濾掉非字符按鍵（方向鍵、功能鍵等）
-                                    // VK codes: https://docs.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes
+                                    let control_state = key_event.dwControlKeyState;
 
-                                    // 跳過功能鍵、方向鍵等（0x21-0x2F, 0x70-0x87）
-                                    if vk_code >= 0x21 && vk_code <= 0x2F {
-                                        continue; // Page Up/Down, End, Home, 方向鍵等
+                                    // Enter 依設定送出 CR/LF/CRLF
+                                    if vk_code == keymap::vk::RETURN {
+                                        batch.extend_from_slice(enter.as_bytes());
+                                        continue;
                                     }
-                                    if vk_code >= 0x70 && vk_code <= 0x87 {
-                                        continue; // F1-F24
+
+                                    // 方向鍵、功能鍵等轉成 VT 跳脫序列
+                                    match keymap::translate_key(vk_code, control_state) {
+                                        KeyAction::Send(bytes) => {
+                                            if verbose {
+                                                crate::diag!(
+                                                    "[DEBUG] Console key: vk={:#04x}, seq={:?}",
+                                                    vk_code,
+                                                    String::from_utf8_lossy(&bytes)
+                                                );
+                                            }
+                                            batch.extend_from_slice(&bytes);
+                                            continue;
+                                        }
+                                        KeyAction::Ignore => continue,
+                                        KeyAction::Char => {}
                                     }
 
                                     if char_code != 0 {
                                         // 將 UTF-16 字符轉換為 UTF-8
                                         let utf16_char = [char_code];
-                                        if let Ok(s) = String::from_utf16(&utf16_char) {
-                                            let mut bytes = s.into_bytes();
-
-                                            // 將 Windows 的 \r (Enter) 轉換為 \n
-                                            if bytes == vec![b'\r'] {
-                                                bytes = vec![b'\n'];
-                                            }
+                                        if let Some(Ok(ch)) = char::decode_utf16(utf16_char).next()
+                                        {
+                                            let bytes = keymap::encode_char(ch, control_state);
 
                                             if verbose {
-                                                eprintln!("SSHPASS: [DEBUG] Console key: vk={:#04x}, char={:?}",
-                                                    vk_code, String::from_utf8_lossy(&bytes));
+                                                crate::diag!(
+                                                    "[DEBUG] Console key: vk={:#04x}, char={:?}",
+                                                    vk_code,
+                                                    String::from_utf8_lossy(&bytes)
+                                                );
                                             }
 
-                                            // 立即發送每個字符，不累積
-                                            if sender.send(StdinEvent::Data(bytes)).is_err() {
-                                                if verbose {
-                                                    eprintln!("SSHPASS: [DEBUG] Failed to send data - receiver closed");
-                                                }
-                                                return; // 接收端已關閉
-                                            }
+                                            batch.extend_from_slice(&bytes);
                                         }
                                     }
                                 }
                             }
                         }
+
+                        if !batch.is_empty() && !send_input(&sender, &mut parser, batch) {
+                            if verbose {
+                                crate::diag!("[DEBUG] Failed to send data - receiver closed");
+                            }
+                            return; // 接收端已關閉
+                        }
                     }
                     Err(e) => {
                         if verbose {
-                            eprintln!("SSHPASS: [DEBUG] ReadConsoleInputW error: {}", e);
+                            crate::diag!("[DEBUG] ReadConsoleInputW error: {}", e);
                         }
                         break;
                     }
@@ -252,15 +400,21 @@ impl StdinForwarder {
         }
 
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Console read loop terminated");
+            crate::diag!("[DEBUG] Console read loop terminated");
         }
     }
 
     /// 從管道讀取（使用 ReadFile）
-    #[cfg(windows)]
-    fn read_pipe_loop(sender: Sender<StdinEvent>, verbose: bool) {
+    ///
+    /// `enter` 不為 None 時管道是互動式終端，換行會轉換成設定的 Enter 字元
+    fn read_pipe_loop(
+        sender: Sender<StdinEvent>,
+        enter: Option<EnterMode>,
+        mut parser: Option<EscapeParser>,
+        verbose: bool,
+    ) {
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Using ReadFile for pipe input");
+            crate::diag!("[DEBUG] Using ReadFile for pipe input");
         }
 
         unsafe {
@@ -268,7 +422,7 @@ impl StdinForwarder {
                 Ok(h) => h,
                 Err(e) => {
                     if verbose {
-                        eprintln!("SSHPASS: [DEBUG] Failed to get stdin handle: {}", e);
+                        crate::diag!("[DEBUG] Failed to get stdin handle: {}", e);
                     }
                     return;
                 }
@@ -283,24 +437,28 @@ impl StdinForwarder {
                         if bytes_read == 0 {
                             // EOF
                             if verbose {
-                                eprintln!("SSHPASS: [DEBUG] stdin EOF (pipe)");
+                                crate::diag!("[DEBUG] stdin EOF (pipe)");
                             }
                             let _ = sender.send(StdinEvent::Eof);
                             break;
                         }
 
                         if verbose {
-                            eprintln!("SSHPASS: [DEBUG] stdin read {} bytes (pipe)", bytes_read);
+                            crate::diag!("[DEBUG] stdin read {} bytes (pipe)", bytes_read);
                         }
 
-                        let data = buffer[..bytes_read as usize].to_vec();
-                        if sender.send(StdinEvent::Data(data)).is_err() {
+                        let data = &buffer[..bytes_read as usize];
+                        let data = match enter {
+                            Some(enter) => translate_enter(data, enter),
+                            None => data.to_vec(),
+                        };
+                        if !send_input(&sender, &mut parser, data) {
                             break; // 接收端已關閉
                         }
                     }
                     Err(e) => {
                         if verbose {
-                            eprintln!("SSHPASS: [DEBUG] stdin read error (pipe): {}", e);
+                            crate::diag!("[DEBUG] stdin read error (pipe): {}", e);
                         }
                         let _ = sender.send(StdinEvent::Eof);
                         break;
@@ -310,50 +468,15 @@ impl StdinForwarder {
         }
 
         if verbose {
-            eprintln!("SSHPASS: [DEBUG] Pipe read loop terminated");
-        }
-    }
-
-    /// 後台執行緒：持續讀取 stdin (Unix 版本)
-    #[cfg(unix)]
-    fn read_stdin_loop(sender: Sender<StdinEvent>, verbose: bool) {
-        let mut stdin = io::stdin();
-        let mut buffer = vec![0u8; 256];
-
-        loop {
-            match stdin.read(&mut buffer) {
-                Ok(0) => {
-                    // EOF
-                    if verbose {
-                        eprintln!("SSHPASS: [DEBUG] stdin EOF");
-                    }
-                    let _ = sender.send(StdinEvent::Eof);
-                    break;
-                }
-                Ok(n) => {
-                    if verbose {
-                        eprintln!("SSHPASS: [DEBUG] stdin read {} bytes", n);
-                    }
-                    let data = buffer[..n].to_vec();
-                    if sender.send(StdinEvent::Data(data)).is_err() {
-                        break; // 接收端已關閉
-                    }
-                }
-                Err(e) => {
-                    if verbose {
-                        eprintln!("SSHPASS: [DEBUG] stdin read error: {}", e);
-                    }
-                    break;
-                }
-            }
+            crate::diag!("[DEBUG] Pipe read loop terminated");
         }
     }
 }
 
+#[cfg(windows)]
 impl Drop for StdinForwarder {
     fn drop(&mut self) {
         // 恢復原始 console mode
-        #[cfg(windows)]
         if let Some(original_mode) = self.original_mode {
             unsafe {
                 if let Ok(handle) = GetStdHandle(STD_INPUT_HANDLE) {
@@ -363,3 +486,315 @@ impl Drop for StdinForwarder {
         }
     }
 }
+
+/// Unix：由主迴圈的 pselect 讀取終端 stdin
+///
+/// 建立時把終端設為 raw mode，按鍵原樣送往 PTY，Ctrl-C、Ctrl-Z 等控制字元由
+/// PTY 那一端處理；輸出處理保持不變，子程序直接寫到終端的 `\n` 仍會換行。
+/// drop 時恢復原本的終端設定。
+#[cfg(unix)]
+pub struct StdinReader {
+    parser: Option<EscapeParser>,
+    coalesce: Duration,
+    /// 進入 raw mode 前的終端設定
+    saved: Termios,
+    /// 尚未讀到 EOF
+    open: bool,
+    /// 暫停時不交給 pselect，輸入留在終端
+    paused: bool,
+}
+
+#[cfg(unix)]
+impl StdinReader {
+    /// stdin 是否為前景程序群組的終端（背景執行時讀取會收到 SIGTTIN）
+    pub fn wanted() -> bool {
+        use nix::unistd::{getpgrp, tcgetpgrp};
+
+        tcgetpgrp(libc::STDIN_FILENO).is_ok_and(|group| group == getpgrp())
+    }
+
+    /// 將終端設為 raw mode 並建立讀取器
+    pub fn new(config: &ForwarderConfig, verbose: bool) -> std::io::Result<Self> {
+        let saved = nix::sys::termios::tcgetattr(std::io::stdin())?;
+        set_raw_mode(&saved)?;
+        if verbose {
+            crate::diag!("[DEBUG] stdin is a terminal, forwarding it to the PTY");
+        }
+        Ok(Self {
+            parser: config.escape_char.map(EscapeParser::new),
+            coalesce: config.coalesce,
+            saved,
+            open: true,
+            paused: false,
+        })
+    }
+
+    /// 要交給 pselect 監看的 fd，讀到 EOF 後或暫停時為 None
+    pub fn fd(&self) -> Option<RawFd> {
+        (self.open && !self.paused).then_some(libc::STDIN_FILENO)
+    }
+
+    /// 暫停或恢復讀取（遠端送出 XOFF 期間）
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// stdin 可讀時呼叫：讀取並在合併時間窗內收集後續按鍵
+    ///
+    /// 讀到 EOF 或發生錯誤（例如終端已關閉）時回傳 `Eof`，之後不再讀取。
+    pub fn read(&mut self, verbose: bool) -> Vec<StdinEvent> {
+        use nix::errno::Errno;
+        use nix::poll::{poll, PollFd, PollFlags};
+
+        let mut buffer = [0u8; 1024];
+        let mut data = match nix::unistd::read(libc::STDIN_FILENO, &mut buffer) {
+            Ok(0) => {
+                self.open = false;
+                return vec![StdinEvent::Eof];
+            }
+            Ok(n) => buffer[..n].to_vec(),
+            Err(Errno::EINTR | Errno::EAGAIN) => return Vec::new(),
+            Err(e) => {
+                if verbose {
+                    crate::diag!("[DEBUG] stdin read error: {}", e);
+                }
+                self.open = false;
+                return vec![StdinEvent::Eof];
+            }
+        };
+
+        // 時間窗內抵達的按鍵併入同一次寫入；EOF 留給下一次讀取
+        let stdin = std::io::stdin();
+        let deadline = Instant::now() + self.coalesce;
+        while let Some(remaining) = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+        {
+            let mut fds = [PollFd::new(&stdin, PollFlags::POLLIN)];
+            let millis = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            if !matches!(poll(&mut fds, millis), Ok(1..)) {
+                break;
+            }
+            match nix::unistd::read(libc::STDIN_FILENO, &mut buffer) {
+                Ok(n @ 1..) => data.extend_from_slice(&buffer[..n]),
+                _ => break,
+            }
+        }
+
+        parse_input(&mut self.parser, data)
+    }
+
+    /// 暫停 sshpass（`~^Z`）：恢復終端設定後停止自己，繼續執行時再回到 raw mode
+    pub fn suspend(&self) -> std::io::Result<()> {
+        restore_mode(&self.saved);
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGSTOP)?;
+        set_raw_mode(&self.saved)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for StdinReader {
+    fn drop(&mut self) {
+        restore_mode(&self.saved);
+    }
+}
+
+/// 以 `saved` 為基礎設定 raw mode，保留輸出處理
+#[cfg(unix)]
+fn set_raw_mode(saved: &Termios) -> std::io::Result<()> {
+    use nix::sys::termios::{cfmakeraw, tcsetattr, SetArg};
+
+    let mut raw = saved.clone();
+    cfmakeraw(&mut raw);
+    raw.output_flags = saved.output_flags;
+    tcsetattr(std::io::stdin(), SetArg::TCSADRAIN, &raw)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restore_mode(saved: &Termios) {
+    use nix::sys::termios::{tcsetattr, SetArg};
+
+    let _ = tcsetattr(std::io::stdin(), SetArg::TCSADRAIN, saved);
+}
+
+/// 從通道取出一個事件，並把合併時間窗內抵達的資料接在一起
+fn recv_coalesced(
+    receiver: &Receiver<StdinEvent>,
+    pending: &Cell<Option<StdinEvent>>,
+    window: Duration,
+) -> Option<StdinEvent> {
+    if let Some(event) = pending.take() {
+        return Some(event);
+    }
+
+    let mut data = match receiver.try_recv().ok()? {
+        StdinEvent::Data(data) => data,
+        other => return Some(other),
+    };
+
+    let deadline = Instant::now() + window;
+    loop {
+        let next = match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => receiver.recv_timeout(remaining).ok(),
+            _ => receiver.try_recv().ok(),
+        };
+
+        match next {
+            Some(StdinEvent::Data(more)) => data.extend_from_slice(&more),
+            Some(other) => {
+                pending.set(Some(other));
+                break;
+            }
+            None => break,
+        }
+    }
+
+    Some(StdinEvent::Data(data))
+}
+
+/// 將讀到的輸入（視需要經過跳脫字元解析）送往主迴圈，接收端關閉時回傳 false
+fn send_input(
+    sender: &Sender<StdinEvent>,
+    parser: &mut Option<EscapeParser>,
+    data: Vec<u8>,
+) -> bool {
+    parse_input(parser, data)
+        .into_iter()
+        .all(|event| sender.send(event).is_ok())
+}
+
+/// 依跳脫字元解析輸入，拆成資料與本地動作事件
+fn parse_input(parser: &mut Option<EscapeParser>, data: Vec<u8>) -> Vec<StdinEvent> {
+    let Some(parser) = parser else {
+        return vec![StdinEvent::Data(data)];
+    };
+
+    parser
+        .process(&data)
+        .into_iter()
+        .map(|output| match output {
+            EscapeOutput::Data(data) => StdinEvent::Data(data),
+            EscapeOutput::Action(action) => StdinEvent::Escape(action),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    fn expect_data(event: Option<StdinEvent>) -> Vec<u8> {
+        match event {
+            Some(StdinEvent::Data(data)) => data,
+            Some(other) => panic!("expected data, got {:?}", other),
+            None => panic!("expected data, got nothing"),
+        }
+    }
+
+    #[test]
+    fn test_cygwin_pty_name() {
+        assert!(is_cygwin_pty_name(
+            "\\msys-1888ae32e00d56aa-pty0-from-master"
+        ));
+        assert!(is_cygwin_pty_name(
+            "\\cygwin-e022582115c10879-pty4-to-master"
+        ));
+        assert!(!is_cygwin_pty_name("\\msys-1888ae32e00d56aa-cygwait"));
+        assert!(!is_cygwin_pty_name(
+            "\\Winsock2\\CatalogChangeListener-1a4-0"
+        ));
+    }
+
+    #[test]
+    fn test_translate_enter() {
+        assert_eq!(translate_enter(b"ls\r", EnterMode::Lf), b"ls\n");
+        assert_eq!(translate_enter(b"a\r\nb\n", EnterMode::Cr), b"a\rb\r");
+        assert_eq!(translate_enter(b"\x1b[A", EnterMode::Crlf), b"\x1b[A");
+    }
+
+    #[test]
+    fn test_recv_coalesced_merges_queued_data() {
+        let (sender, receiver) = channel();
+        let pending = Cell::new(None);
+        sender.send(StdinEvent::Data(b"ab".to_vec())).unwrap();
+        sender.send(StdinEvent::Data(b"c".to_vec())).unwrap();
+
+        let data = expect_data(recv_coalesced(&receiver, &pending, Duration::ZERO));
+        assert_eq!(data, b"abc");
+        assert!(recv_coalesced(&receiver, &pending, Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn test_recv_coalesced_defers_eof() {
+        let (sender, receiver) = channel();
+        let pending = Cell::new(None);
+        sender.send(StdinEvent::Data(b"x".to_vec())).unwrap();
+        sender.send(StdinEvent::Eof).unwrap();
+
+        let data = expect_data(recv_coalesced(&receiver, &pending, Duration::ZERO));
+        assert_eq!(data, b"x");
+        assert!(matches!(
+            recv_coalesced(&receiver, &pending, Duration::ZERO),
+            Some(StdinEvent::Eof)
+        ));
+    }
+
+    #[test]
+    fn test_recv_coalesced_waits_for_window() {
+        let (sender, receiver) = channel();
+        let pending = Cell::new(None);
+        sender.send(StdinEvent::Data(b"l".to_vec())).unwrap();
+
+        let late_sender = sender.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            late_sender.send(StdinEvent::Data(b"s".to_vec())).unwrap();
+        });
+
+        let data = expect_data(recv_coalesced(
+            &receiver,
+            &pending,
+            Duration::from_millis(200),
+        ));
+        handle.join().unwrap();
+        assert_eq!(data, b"ls");
+    }
+
+    #[test]
+    fn test_send_input_splits_escape_actions() {
+        let (sender, receiver) = channel();
+        let mut parser = Some(EscapeParser::new(b'~'));
+        assert!(send_input(&sender, &mut parser, b"exit\n~.".to_vec()));
+
+        assert!(matches!(receiver.try_recv(), Ok(StdinEvent::Data(d)) if d == b"exit\n"));
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(StdinEvent::Escape(EscapeAction::Terminate))
+        ));
+    }
+
+    #[test]
+    fn test_recv_coalesced_stops_at_escape() {
+        let (sender, receiver) = channel();
+        let pending = Cell::new(None);
+        sender.send(StdinEvent::Data(b"a".to_vec())).unwrap();
+        sender.send(StdinEvent::Escape(EscapeAction::Help)).unwrap();
+        sender.send(StdinEvent::Data(b"b".to_vec())).unwrap();
+
+        assert_eq!(
+            expect_data(recv_coalesced(&receiver, &pending, Duration::ZERO)),
+            b"a"
+        );
+        assert!(matches!(
+            recv_coalesced(&receiver, &pending, Duration::ZERO),
+            Some(StdinEvent::Escape(EscapeAction::Help))
+        ));
+        assert_eq!(
+            expect_data(recv_coalesced(&receiver, &pending, Duration::ZERO)),
+            b"b"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Password prompts of sudo, su and doas in any language
+//!
+//! The `sudo` profile looks for "assword", which a translated prompt such as
+//! "[sudo] Mot de passe de alice : " or "密碼：" does not contain. Two ways
+//! around that:
+//!
+//! * [`PamPrompt`] is a [`Detector`] that takes a line ending in a colon
+//!   (`:` or the full-width `：`, followed by spaces at most) for the password
+//!   prompt when it names the program, as sudo's `[sudo] ...` and doas's
+//!   `doas (...)` prompts do, or when it is the first line of output, as the
+//!   PAM prompt of su is. It is added to the `sudo` profile unless `-P` sets
+//!   the prompt.
+//! * `--sudo-sentinel` sets `SUDO_PROMPT` in the child's environment to
+//!   [`SENTINEL`] and looks for that instead, the same in every locale. Only
+//!   sudo honors the variable.
+
+use crate::monitor::{Detection, Detector};
+
+/// The prompt sudo shows with `--sudo-sentinel`
+pub const SENTINEL: &str = "[sshpass-sudo-prompt]: ";
+
+/// Longest prompt line kept
+const MAX_LINE: usize = 512;
+
+/// Make sudo, which the child runs, show [`SENTINEL`] as its prompt
+pub fn set_sentinel() {
+    std::env::set_var("SUDO_PROMPT", SENTINEL);
+}
+
+/// Finds PAM-style password prompts of the program `process`
+pub struct PamPrompt {
+    process: String,
+    /// Output since the last newline
+    line: Vec<u8>,
+    first_line: bool,
+    /// The current line was reported already
+    reported: bool,
+}
+
+impl PamPrompt {
+    /// `command` is the program the child runs, with or without its path
+    pub fn new(command: &str) -> Self {
+        let process = std::path::Path::new(command)
+            .file_name()
+            .map_or(command.into(), |name| name.to_string_lossy());
+        Self {
+            process: process.into_owned(),
+            line: Vec::new(),
+            first_line: true,
+            reported: false,
+        }
+    }
+
+    /// Whether the line so far is a prompt waiting for the password
+    fn is_prompt(&self) -> bool {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim_start_matches(['\r', ' ']);
+        let tail = line.trim_end_matches(' ');
+        if !(tail.ends_with(':') || tail.ends_with('：')) {
+            return false;
+        }
+        self.first_line || names_process(line, &self.process)
+    }
+}
+
+/// Whether `line` starts with `[process]` or `process (`, like the prompts
+/// of sudo and doas
+fn names_process(line: &str, process: &str) -> bool {
+    if let Some(rest) = line.strip_prefix('[') {
+        return rest
+            .strip_prefix(process)
+            .is_some_and(|rest| rest.starts_with(']'));
+    }
+    line.strip_prefix(process)
+        .is_some_and(|rest| rest.starts_with(" ("))
+}
+
+impl Detector for PamPrompt {
+    fn name(&self) -> &str {
+        "pam-prompt"
+    }
+
+    fn feed(&mut self, data: &[u8]) -> Detection {
+        for &byte in data {
+            if byte == b'\n' {
+                self.line.clear();
+                self.first_line = false;
+                self.reported = false;
+            } else if self.line.len() < MAX_LINE {
+                self.line.push(byte);
+            }
+        }
+        // A prompt waits for input, so it ends the output read
+        if self.reported || !self.is_prompt() {
+            return Detection::Nothing;
+        }
+        self.reported = true;
+        Detection::PasswordPrompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pam_prompt_sudo() {
+        let mut detector = PamPrompt::new("/usr/bin/sudo");
+        let lecture = "\r\nWe trust you have received the usual lecture:\r\n\r\n";
+        assert_eq!(detector.feed(lecture.as_bytes()), Detection::Nothing);
+        assert_eq!(
+            detector.feed("[sudo] Mot de passe de alice ".as_bytes()),
+            Detection::Nothing
+        );
+        assert_eq!(detector.feed(b": "), Detection::PasswordPrompt);
+        // Reported once per line
+        assert_eq!(detector.feed(b""), Detection::Nothing);
+
+        // Command output that happens to end in a colon
+        assert_eq!(detector.feed(b"\r\nName: "), Detection::Nothing);
+        assert_eq!(
+            detector.feed("\r\nSorry, try again.\r\n[sudo] 密碼：".as_bytes()),
+            Detection::PasswordPrompt
+        );
+    }
+
+    #[test]
+    fn test_pam_prompt_su_and_doas() {
+        let mut su = PamPrompt::new("su");
+        assert_eq!(su.feed(b"Passwort: "), Detection::PasswordPrompt);
+        assert_eq!(su.feed(b"\r\nsu: Anmeldung:"), Detection::Nothing);
+
+        let mut doas = PamPrompt::new("doas");
+        assert_eq!(doas.feed(b"doas: not permitted\n"), Detection::Nothing);
+        assert_eq!(
+            doas.feed(b"doas (alice@host) Passwort:"),
+            Detection::PasswordPrompt
+        );
+        assert!(!names_process("[sudoedit] x:", "sudo"));
+    }
+}
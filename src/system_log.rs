@@ -0,0 +1,241 @@
+//! Audit and lifecycle events for the system log (`--log-target`)
+//!
+//! With `file`, the default, audit records only go to `--audit-log`. With
+//! `syslog` (Unix) or `eventlog` (Windows) the records of [`crate::audit`]
+//! and the progress of the session go to the system log instead, where a
+//! SIEM collector already picks them up: syslog(3) with the `authpriv`
+//! facility, which journald receives on systemd hosts, or the Application
+//! event log under the source `sshpass`. Progress messages start with the
+//! session's tag, its ID and `--label`s (see [`crate::labels`]).
+
+use crate::error::{Result, SshpassError};
+use crate::session::{SessionEvent, SessionObserver, SessionStatus};
+
+/// `--log-target` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LogTarget {
+    /// The `--audit-log` file
+    #[default]
+    File,
+    /// syslog(3), including journald (Unix)
+    Syslog,
+    /// The Windows Application event log
+    Eventlog,
+}
+
+impl LogTarget {
+    /// The system log of this platform, if it is that
+    pub fn is_native(self) -> bool {
+        match self {
+            LogTarget::File => false,
+            LogTarget::Syslog => cfg!(unix),
+            LogTarget::Eventlog => cfg!(windows),
+        }
+    }
+}
+
+/// How serious a message is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Connection to the platform's system log
+pub struct SystemLog {
+    #[cfg(windows)]
+    source: windows::Win32::System::EventLog::EventSourceHandle,
+}
+
+#[cfg(unix)]
+impl SystemLog {
+    /// Connect to syslog
+    ///
+    /// The socket is opened right away, so messages still get through once
+    /// `--sandbox` has taken filesystem access away.
+    pub fn open() -> Result<Self> {
+        static OPEN: std::sync::Once = std::sync::Once::new();
+        OPEN.call_once(|| {
+            // SAFETY: the identifier is a static C string, as openlog keeps
+            // the pointer
+            unsafe {
+                libc::openlog(
+                    b"sshpass\0".as_ptr().cast(),
+                    libc::LOG_PID | libc::LOG_NDELAY,
+                    libc::LOG_AUTHPRIV,
+                )
+            };
+        });
+        Ok(Self {})
+    }
+
+    /// Send one message
+    pub fn send(&self, severity: Severity, message: &str) {
+        let priority = match severity {
+            Severity::Info => libc::LOG_INFO,
+            Severity::Warning => libc::LOG_WARNING,
+            Severity::Error => libc::LOG_ERR,
+        };
+        let Ok(message) = std::ffi::CString::new(message.replace('\0', "")) else {
+            return;
+        };
+        // SAFETY: both strings are NUL-terminated and the format takes one
+        // string argument
+        unsafe { libc::syslog(priority, b"%s\0".as_ptr().cast(), message.as_ptr()) };
+    }
+}
+
+/// 以事件來源 `sshpass` 寫入應用程式事件記錄
+#[cfg(windows)]
+impl SystemLog {
+    pub fn open() -> Result<Self> {
+        use windows::core::{w, PCWSTR};
+        use windows::Win32::System::EventLog::RegisterEventSourceW;
+
+        let source =
+            unsafe { RegisterEventSourceW(PCWSTR::null(), w!("sshpass")) }.map_err(|e| {
+                SshpassError::RuntimeError(format!("Failed to open the event log: {}", e))
+            })?;
+        Ok(Self { source })
+    }
+
+    /// 寫入一筆事件；未註冊訊息檔，事件檢視器會在描述前附註找不到事件識別碼
+    pub fn send(&self, severity: Severity, message: &str) {
+        use windows::core::PCWSTR;
+        use windows::Win32::Foundation::PSID;
+        use windows::Win32::System::EventLog::{
+            ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+        };
+
+        let kind = match severity {
+            Severity::Info => EVENTLOG_INFORMATION_TYPE,
+            Severity::Warning => EVENTLOG_WARNING_TYPE,
+            Severity::Error => EVENTLOG_ERROR_TYPE,
+        };
+        let wide = message
+            .encode_utf16()
+            .filter(|&unit| unit != 0)
+            .chain(std::iter::once(0))
+            .collect::<Vec<u16>>();
+        unsafe {
+            let _ = ReportEventW(
+                self.source,
+                kind,
+                0,
+                1,
+                PSID::default(),
+                0,
+                Some(&[PCWSTR(wide.as_ptr())]),
+                None,
+            );
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for SystemLog {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = windows::Win32::System::EventLog::DeregisterEventSource(self.source);
+        }
+    }
+}
+
+// SAFETY: the event source handle may be used from any thread
+#[cfg(windows)]
+unsafe impl Send for SystemLog {}
+
+/// Reports the progress of a session to the system log
+pub struct Lifecycle {
+    log: SystemLog,
+    tag: String,
+}
+
+impl Lifecycle {
+    pub fn new(log: SystemLog) -> Self {
+        Self {
+            log,
+            tag: crate::labels::session().tag(),
+        }
+    }
+}
+
+impl SessionObserver for Lifecycle {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        if let Some((severity, message)) = describe(event) {
+            self.log
+                .send(severity, &format!("[{}] {}", self.tag, message));
+        }
+    }
+}
+
+/// The message for an event worth logging
+fn describe(event: SessionEvent<'_>) -> Option<(Severity, String)> {
+    match event {
+        SessionEvent::Status(SessionStatus::PasswordSent) => {
+            Some((Severity::Info, "Password sent".to_string()))
+        }
+        SessionEvent::Status(SessionStatus::Authenticated) => {
+            Some((Severity::Info, "Logged in".to_string()))
+        }
+        SessionEvent::Status(SessionStatus::Exited(code)) => Some((
+            Severity::Info,
+            format!("Session ended with exit code {}", code),
+        )),
+        SessionEvent::Error(e) => Some((Severity::Error, format!("Session failed: {}", e))),
+        _ => None,
+    }
+}
+
+/// Check that the target exists on this platform
+pub fn check_target(target: LogTarget) -> Result<()> {
+    match target {
+        LogTarget::File => Ok(()),
+        _ if target.is_native() => Ok(()),
+        LogTarget::Syslog => Err(SshpassError::InvalidArguments(
+            "--log-target syslog is only available on Unix".to_string(),
+        )),
+        LogTarget::Eventlog => Err(SshpassError::InvalidArguments(
+            "--log-target eventlog is only available on Windows".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle_messages() {
+        assert_eq!(
+            describe(SessionEvent::Status(SessionStatus::PasswordSent)),
+            Some((Severity::Info, "Password sent".to_string()))
+        );
+        assert_eq!(
+            describe(SessionEvent::Status(SessionStatus::Authenticated)),
+            Some((Severity::Info, "Logged in".to_string()))
+        );
+        assert_eq!(
+            describe(SessionEvent::Status(SessionStatus::Exited(5))),
+            Some((Severity::Info, "Session ended with exit code 5".to_string()))
+        );
+        assert_eq!(
+            describe(SessionEvent::Error(&SshpassError::IncorrectPassword)),
+            Some((
+                Severity::Error,
+                "Session failed: Incorrect password provided".to_string()
+            ))
+        );
+        assert_eq!(describe(SessionEvent::Output(b"secret")), None);
+        assert_eq!(describe(SessionEvent::Status(SessionStatus::Running)), None);
+    }
+
+    #[test]
+    fn test_check_target() {
+        assert!(check_target(LogTarget::File).is_ok());
+        assert_eq!(check_target(LogTarget::Syslog).is_ok(), cfg!(unix));
+        assert_eq!(check_target(LogTarget::Eventlog).is_ok(), cfg!(windows));
+    }
+}
@@ -0,0 +1,663 @@
+//! Copies of the session output (`--tee`)
+//!
+//! Piping sshpass into `tee` makes the child's stdout a pipe, and programs
+//! that check for a terminal then change how they print. `--tee TARGET`
+//! keeps the terminal and writes everything the child prints after the
+//! password was typed to each target as well as to stdout. A target is a
+//! file, created or truncated with mode 0600 on Unix, or `fd:N` for a
+//! descriptor the caller left open (Unix).
+//!
+//! On Unix the child's stdout then goes through the PTY, like on Windows, so
+//! output before the password is answered is not shown. A target that fails
+//! is reported once and dropped; the session and the other targets go on.
+//!
+//! `--record-format` picks what goes into the targets: the output as it is,
+//! an asciinema recording, or a script(1) typescript with its timing file
+//! next to it (see [`crate::recording`]).
+//!
+//! Output is written to the targets as it arrives and never collected in
+//! memory, so a long session only grows the files. `--record-max-size`
+//! bounds those too: a file that reaches the size is renamed to `FILE.1`
+//! (older copies move on to `FILE.2` and so on, up to `--record-rotate`
+//! copies) and a new one is started. Without `--record-rotate`, or for an
+//! `fd:N` target, the copy stops at the size instead. Recordings are cut
+//! between events, and each new file starts with its own header.
+//!
+//! `--record-compress` compresses every target on the fly (see
+//! [`crate::compression`]); the size limit counts the data before
+//! compression. `--record-recipient` encrypts them (see
+//! [`crate::encryption`]).
+
+use crate::compression::{self, Compression};
+use crate::encryption;
+use crate::error::{Result, SshpassError};
+use crate::recording::{self, RecordFormat};
+use crate::session::{SessionEvent, SessionObserver, SessionStatus};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+/// Size limit of the copies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Bytes written to a target before it is rotated or stopped
+    pub max_size: u64,
+    /// Rotated files kept next to a file target
+    pub keep: u32,
+}
+
+/// A chunk of output as it goes into the targets
+struct Record {
+    data: Vec<u8>,
+    /// Line for the timing file of a typescript
+    timing: Option<String>,
+    /// Whether the data may be cut to fit the size limit
+    divisible: bool,
+}
+
+/// One destination of the copies
+struct Target {
+    name: String,
+    writer: Box<dyn Write + Send>,
+    /// The file behind a file target, for rotating it
+    path: Option<PathBuf>,
+    /// The timing file of a typescript
+    timing: Option<File>,
+    /// Bytes written since the target was opened or rotated
+    written: u64,
+    /// Whether the header is still to be written
+    fresh: bool,
+    compression: Compression,
+    /// age recipients the target is encrypted to
+    recipients: Vec<String>,
+    /// When a compressed target was last flushed
+    last_flush: Option<Instant>,
+}
+
+impl Target {
+    fn new(name: String, writer: Box<dyn Write + Send>, path: Option<PathBuf>) -> Self {
+        Self {
+            name,
+            writer,
+            path,
+            timing: None,
+            written: 0,
+            fresh: true,
+            compression: Compression::None,
+            recipients: Vec::new(),
+            last_flush: None,
+        }
+    }
+
+    /// Write all of `record`, returning false once the size limit stops the
+    /// copy
+    fn write(
+        &mut self,
+        record: &Record,
+        header: &[u8],
+        rotation: Option<Rotation>,
+    ) -> std::io::Result<bool> {
+        if self.fresh {
+            self.fresh = false;
+            self.put(header)?;
+        }
+        let Some(rotation) = rotation else {
+            self.put(&record.data)?;
+            return self.finish(record);
+        };
+
+        if !record.divisible {
+            // Records are kept whole; one larger than the limit gets a file
+            // of its own
+            let used = self.written.saturating_sub(header.len() as u64);
+            let full = self.written + record.data.len() as u64 > rotation.max_size;
+            if used > 0 && full && !self.rotate(rotation, header)? {
+                return Ok(false);
+            }
+            self.put(&record.data)?;
+            return self.finish(record);
+        }
+
+        let mut data = &record.data[..];
+        while !data.is_empty() {
+            let room = rotation.max_size.saturating_sub(self.written);
+            if room == 0 {
+                if !self.rotate(rotation, header)? {
+                    return Ok(false);
+                }
+                continue;
+            }
+            let (now, rest) = data.split_at(room.min(data.len() as u64) as usize);
+            self.put(now)?;
+            data = rest;
+        }
+        self.finish(record)
+    }
+
+    fn put(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Add the timing of `record` and flush, a compressed target at most
+    /// once every [`compression::FLUSH_INTERVAL`]
+    fn finish(&mut self, record: &Record) -> std::io::Result<bool> {
+        let now = Instant::now();
+        let due = self
+            .last_flush
+            .map_or(true, |last| now - last >= compression::FLUSH_INTERVAL);
+        if self.compression == Compression::None || due {
+            self.writer.flush()?;
+            self.last_flush = Some(now);
+        }
+        if let (Some(timing), Some(line)) = (self.timing.as_mut(), record.timing.as_ref()) {
+            timing.write_all(line.as_bytes())?;
+        }
+        Ok(true)
+    }
+
+    /// Move on to a new file, returning false if the target cannot
+    fn rotate(&mut self, rotation: Rotation, header: &[u8]) -> std::io::Result<bool> {
+        self.writer.flush()?;
+        let Some(ref path) = self.path else {
+            return Ok(false);
+        };
+        if rotation.keep == 0 {
+            return Ok(false);
+        }
+        // Replacing the writer finishes the streams of the old file
+        let file = encryption::encryptor(rotate(path, rotation.keep)?, &self.recipients)?;
+        self.writer = compression::encoder(file, self.compression)?;
+        if self.timing.is_some() {
+            self.timing = Some(rotate(&timing_path(path), rotation.keep)?);
+        }
+        self.written = 0;
+        self.put(header)?;
+        Ok(true)
+    }
+}
+
+/// Fan-out writer that follows a session as its observer
+pub struct Tee {
+    targets: Vec<Target>,
+    rotation: Option<Rotation>,
+    format: RecordFormat,
+    /// Written at the start of every file
+    header: Vec<u8>,
+    /// When the recording started, and when output was last written
+    started_at: Option<Instant>,
+    last_write: Option<Instant>,
+    /// Bytes of a character cut off at the end of the last chunk
+    utf8_pending: Vec<u8>,
+    started: bool,
+}
+
+impl Tee {
+    /// Open every target
+    pub fn open(specs: &[String]) -> Result<Self> {
+        let targets = specs
+            .iter()
+            .map(|spec| {
+                open_target(spec).map(|(writer, path)| Target::new(spec.clone(), writer, path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::with_targets(targets))
+    }
+
+    fn with_targets(targets: Vec<Target>) -> Self {
+        Self {
+            targets,
+            rotation: None,
+            format: RecordFormat::Raw,
+            header: Vec::new(),
+            started_at: None,
+            last_write: None,
+            utf8_pending: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Limit the size of the copies
+    pub fn with_rotation(mut self, rotation: Option<Rotation>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Encrypt the copies to age `recipients` as they are written; called
+    /// before [`with_compression`](Self::with_compression), so that the
+    /// output is compressed before it is encrypted
+    pub fn with_encryption(mut self, recipients: &[String]) -> Result<Self> {
+        for target in &mut self.targets {
+            let writer = std::mem::replace(&mut target.writer, Box::new(std::io::sink()));
+            target.writer = encryption::encryptor(writer, recipients).map_err(|e| {
+                SshpassError::InvalidArguments(format!("Failed to encrypt {}: {}", target.name, e))
+            })?;
+            target.recipients = recipients.to_vec();
+        }
+        Ok(self)
+    }
+
+    /// Compress the copies as they are written
+    pub fn with_compression(mut self, compression: Compression) -> Result<Self> {
+        for target in &mut self.targets {
+            let writer = std::mem::replace(&mut target.writer, Box::new(std::io::sink()));
+            target.writer = compression::encoder(writer, compression).map_err(|e| {
+                SshpassError::InvalidArguments(format!("Failed to compress {}: {}", target.name, e))
+            })?;
+            target.compression = compression;
+        }
+        Ok(self)
+    }
+
+    /// Write the copies as recordings; a typescript needs file targets for
+    /// its timing files, which are created here
+    pub fn with_format(mut self, format: RecordFormat) -> Result<Self> {
+        if format == RecordFormat::Typescript {
+            for target in &mut self.targets {
+                let path = target.path.as_ref().ok_or_else(|| {
+                    SshpassError::InvalidArguments(format!(
+                        "--record-format typescript needs a file, not {}",
+                        target.name
+                    ))
+                })?;
+                let timing = timing_path(path);
+                target.timing = Some(create_file(&timing).map_err(|e| {
+                    SshpassError::InvalidArguments(format!(
+                        "Failed to open timing file {}: {}",
+                        timing.display(),
+                        e
+                    ))
+                })?);
+            }
+        }
+        self.format = format;
+        Ok(self)
+    }
+
+    /// Start the recording, writing the headers from now on
+    fn start(&mut self) {
+        self.started = true;
+        self.started_at = Some(Instant::now());
+        let now = SystemTime::now();
+        self.header = match self.format {
+            RecordFormat::Raw => String::new(),
+            RecordFormat::Asciinema => {
+                let (width, height) = terminal_size().unwrap_or((80, 24));
+                recording::cast_header(width, height, now)
+            }
+            RecordFormat::Typescript => recording::typescript_header(now),
+        }
+        .into_bytes();
+    }
+
+    /// Put `data` into the shape of the recording
+    fn record(&mut self, data: &[u8]) -> Record {
+        let now = Instant::now();
+        let since_start = now - *self.started_at.get_or_insert(now);
+        let since_last = self.last_write.map_or(since_start, |last| now - last);
+        self.last_write = Some(now);
+        match self.format {
+            RecordFormat::Raw => Record {
+                data: data.to_vec(),
+                timing: None,
+                divisible: true,
+            },
+            RecordFormat::Asciinema => {
+                self.utf8_pending.extend_from_slice(data);
+                let text = recording::take_utf8(&mut self.utf8_pending);
+                Record {
+                    data: recording::cast_event(since_start, &text).into_bytes(),
+                    timing: None,
+                    divisible: false,
+                }
+            }
+            RecordFormat::Typescript => Record {
+                data: data.to_vec(),
+                timing: Some(recording::timing_line(since_last, data.len())),
+                divisible: false,
+            },
+        }
+    }
+
+    /// Write `data` to every target, dropping those that fail or are full
+    pub fn write(&mut self, data: &[u8]) {
+        let record = self.record(data);
+        let (header, rotation) = (&self.header, self.rotation);
+        self.targets
+            .retain_mut(|target| match target.write(&record, header, rotation) {
+                Ok(true) => true,
+                Ok(false) => {
+                    crate::diag!(
+                        "Stopped writing to {}: --record-max-size reached",
+                        target.name
+                    );
+                    false
+                }
+                Err(e) => {
+                    crate::diag!("Stopped writing to {}: {}", target.name, e);
+                    false
+                }
+            });
+    }
+}
+
+impl SessionObserver for Tee {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        match event {
+            SessionEvent::Status(SessionStatus::PasswordSent) => self.start(),
+            SessionEvent::Output(data) if self.started => self.write(data),
+            _ => {}
+        }
+    }
+}
+
+/// Open a file, or take over `fd:N`; files come with their path
+fn open_target(spec: &str) -> Result<(Box<dyn Write + Send>, Option<PathBuf>)> {
+    #[cfg(unix)]
+    if let Some(fd) = spec.strip_prefix("fd:") {
+        return Ok((Box::new(take_fd(fd, spec, "--tee")?), None));
+    }
+
+    let path = PathBuf::from(spec);
+    let file = create_file(&path).map_err(|e| {
+        SshpassError::InvalidArguments(format!("Failed to open --tee file {}: {}", spec, e))
+    })?;
+    Ok((Box::new(file), Some(path)))
+}
+
+/// Take over the descriptor `fd` of the `fd:N` target `spec` given to
+/// `option`
+#[cfg(unix)]
+pub(crate) fn take_fd(fd: &str, spec: &str, option: &str) -> Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    let fd = fd.parse::<i32>().ok().filter(|&fd| fd > 2).ok_or_else(|| {
+        SshpassError::InvalidArguments(format!(
+            "Invalid {} target {:?} (expected fd:N with N above 2)",
+            option, spec
+        ))
+    })?;
+    nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_GETFD)
+        .map_err(|_| SshpassError::InvalidFileDescriptor(fd))?;
+    // SAFETY: the fd is open and was handed to us by the caller for this
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Create or truncate a copy, readable only by the user on Unix
+pub(crate) fn create_file(path: &Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Move `path` to `path.1`, shifting older copies up to `path.keep`, and
+/// start a new file
+fn rotate(path: &Path, keep: u32) -> std::io::Result<File> {
+    for n in (1..keep).rev() {
+        match std::fs::rename(numbered(path, n), numbered(path, n + 1)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    std::fs::rename(path, numbered(path, 1))?;
+    create_file(path)
+}
+
+/// `path.n`
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// `path.timing`, the timing file of a typescript
+fn timing_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".timing");
+    PathBuf::from(name)
+}
+
+/// Columns and rows of the user's terminal
+fn terminal_size() -> Option<(u16, u16)> {
+    #[cfg(unix)]
+    {
+        crate::pty::get_terminal_winsize().map(|size| (size.ws_col, size.ws_row))
+    }
+    #[cfg(windows)]
+    {
+        crate::signal::current_console_size().map(|(rows, cols)| (cols, rows))
+    }
+}
+
+/// Parse `--record-max-size`: bytes, or a number with a K, M or G suffix
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let (digits, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((at, _)) => value.split_at(at),
+        None => (value, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => {
+            return Err(format!(
+                "invalid size '{}': expected bytes or K, M, G",
+                value
+            ))
+        }
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid size '{}': expected a positive number", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Shared buffer, failing once `fail` is set
+    #[derive(Clone, Default)]
+    struct Buffer {
+        data: Arc<Mutex<Vec<u8>>>,
+        fail: Arc<Mutex<bool>>,
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if *self.fail.lock().unwrap() {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            self.data.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tee_copies_output_after_password() {
+        let (first, second) = (Buffer::default(), Buffer::default());
+        let mut tee = Tee::with_targets(vec![
+            Target::new("first".to_string(), Box::new(first.clone()), None),
+            Target::new("second".to_string(), Box::new(second.clone()), None),
+        ]);
+
+        tee.on_event(SessionEvent::Output(b"Password: "));
+        tee.on_event(SessionEvent::Status(SessionStatus::PasswordSent));
+        tee.on_event(SessionEvent::Output(b"motd\r\n"));
+        *second.fail.lock().unwrap() = true;
+        tee.on_event(SessionEvent::Output(b"$ "));
+        *second.fail.lock().unwrap() = false;
+        tee.on_event(SessionEvent::Output(b"exit"));
+
+        assert_eq!(*first.data.lock().unwrap(), b"motd\r\n$ exit");
+        assert_eq!(*second.data.lock().unwrap(), b"motd\r\n");
+        assert_eq!(tee.targets.len(), 1);
+    }
+
+    #[test]
+    fn test_tee_rotation() {
+        let dir = std::env::temp_dir().join(format!("sshpass-tee-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.log");
+        let rotation = Rotation {
+            max_size: 4,
+            keep: 2,
+        };
+        let mut tee = Tee::open(&[path.display().to_string()])
+            .unwrap()
+            .with_rotation(Some(rotation));
+        tee.write(b"abc");
+        tee.write(b"defghijklm");
+        drop(tee);
+        let read = |n: u32| std::fs::read(numbered(&path, n)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"m");
+        assert_eq!(read(1), b"ijkl");
+        assert_eq!(read(2), b"efgh");
+        // The oldest copy is gone
+        assert!(!numbered(&path, 3).exists());
+
+        // Without rotation the copy stops at the size
+        let full = Buffer::default();
+        let mut tee = Tee::with_targets(vec![Target::new(
+            "full".to_string(),
+            Box::new(full.clone()),
+            None,
+        )])
+        .with_rotation(Some(rotation));
+        tee.write(b"abc");
+        tee.write(b"defg");
+        assert_eq!(*full.data.lock().unwrap(), b"abcd");
+        assert!(tee.targets.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tee_recording_formats() {
+        let dir = std::env::temp_dir().join(format!("sshpass-tee-format-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let cast = dir.join("session.cast");
+        let mut tee = Tee::open(&[cast.display().to_string()])
+            .unwrap()
+            .with_format(RecordFormat::Asciinema)
+            .unwrap();
+        tee.on_event(SessionEvent::Status(SessionStatus::PasswordSent));
+        // A character split between two reads stays whole
+        tee.on_event(SessionEvent::Output(b"caf\xc3"));
+        tee.on_event(SessionEvent::Output(b"\xa9\r\n"));
+        drop(tee);
+        let options = recording::PlayOptions::parse(&[cast.display().to_string()]).unwrap();
+        let file = std::io::BufReader::new(File::open(&cast).unwrap());
+        let mut played = Vec::new();
+        assert_eq!(recording::play(file, &mut played, &options).unwrap(), 2);
+        assert_eq!(played, "café\r\n".as_bytes());
+
+        let typescript = dir.join("typescript");
+        let mut tee = Tee::open(&[typescript.display().to_string()])
+            .unwrap()
+            .with_format(RecordFormat::Typescript)
+            .unwrap();
+        tee.on_event(SessionEvent::Status(SessionStatus::PasswordSent));
+        tee.on_event(SessionEvent::Output(b"$ ls\r\n"));
+        tee.on_event(SessionEvent::Output(b"file\r\n"));
+        drop(tee);
+        let text = std::fs::read_to_string(&typescript).unwrap();
+        let (header, output) = text.split_once('\n').unwrap();
+        assert!(header.starts_with("Script started on "));
+        assert_eq!(output, "$ ls\r\nfile\r\n");
+        let timing = std::fs::read_to_string(timing_path(&typescript)).unwrap();
+        let sizes = timing
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, ["6", "6"]);
+
+        #[cfg(unix)]
+        {
+            let tee = Tee::with_targets(vec![Target::new(
+                "fd:3".to_string(),
+                Box::new(Buffer::default()),
+                None,
+            )]);
+            assert!(tee.with_format(RecordFormat::Typescript).is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_tee_compressed_rotation() {
+        use std::io::Read;
+
+        let dir = std::env::temp_dir().join(format!("sshpass-tee-gzip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.log.gz");
+        let mut tee = Tee::open(&[path.display().to_string()])
+            .unwrap()
+            .with_rotation(Some(Rotation {
+                max_size: 4,
+                keep: 1,
+            }))
+            .with_compression(Compression::Gzip)
+            .unwrap();
+        tee.write(b"abcdef");
+        drop(tee);
+        // Each file is a whole stream of its own, limited before compression
+        let read = |path: &Path| {
+            let file = std::io::BufReader::new(File::open(path).unwrap());
+            let mut data = Vec::new();
+            compression::decoder(file)
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+        assert_eq!(read(&numbered(&path, 1)), b"abcd");
+        assert_eq!(read(&path), b"ef");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("10k"), Ok(10 << 10));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_tee_targets() {
+        let path = std::env::temp_dir().join(format!("sshpass-tee-{}", std::process::id()));
+        std::fs::write(&path, b"old contents").unwrap();
+        let mut tee = Tee::open(&[path.display().to_string()]).unwrap();
+        tee.write(b"new");
+        drop(tee);
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Tee::open(&["/nonexistent/dir/file".to_string()]).is_err());
+        #[cfg(unix)]
+        {
+            assert!(Tee::open(&["fd:1".to_string()]).is_err());
+            assert!(Tee::open(&["fd:x".to_string()]).is_err());
+        }
+    }
+}
@@ -1,8 +1,8 @@
-/// Handle terminal query sequences and provide appropriate responses
-///
-/// SSH (and other programs) may send ANSI escape sequences to query
-/// the terminal capabilities. We need to respond to these queries
-/// to prevent the program from hanging.
+//! Handle terminal query sequences and provide appropriate responses
+//!
+//! SSH (and other programs) may send ANSI escape sequences to query
+//! the terminal capabilities. We need to respond to these queries
+//! to prevent the program from hanging.
 
 /// Check if data contains a terminal query and return appropriate response
 pub fn get_terminal_response(data: &[u8]) -> Option<Vec<u8>> {
@@ -11,14 +11,14 @@ pub fn get_terminal_response(data: &[u8]) -> Option<Vec<u8>> {
     // Device Attributes query: ESC [ c
     // Response: ESC [ ? 1 ; 2 c (VT100 with Advanced Video Option)
     if s.contains("\x1b[c") {
-        eprintln!("SSHPASS: [TERMINAL] Responding to Device Attributes query (ESC[c)");
+        crate::diag!("[TERMINAL] Responding to Device Attributes query (ESC[c)");
         return Some(b"\x1b[?1;2c".to_vec());
     }
 
     // Cursor Position Report query: ESC [ 6 n
     // Response: ESC [ row ; col R
     if s.contains("\x1b[6n") {
-        eprintln!("SSHPASS: [TERMINAL] Responding to Cursor Position query (ESC[6n)");
+        crate::diag!("[TERMINAL] Responding to Cursor Position query (ESC[6n)");
         return Some(b"\x1b[1;1R".to_vec());
     }
 
@@ -0,0 +1,237 @@
+//! Compare two runs host by host (`sshpass diff RUN1 RUN2`)
+//!
+//! After a change rolled out to a fleet, the same command run before and
+//! after shows where configuration drifted. A run is a directory holding one
+//! file per host, as an orchestrator leaves it with `--tee DIR/HOST.cast`:
+//! recordings in any `--record-format`, compressed or encrypted or not, or
+//! the JSON results it wrote for each host.
+//!
+//! Files are paired by name, ignoring `.gz`, `.zst` and `.age` suffixes, and
+//! each is read through the same pipeline as `sshpass export`, so colours,
+//! cursor movement, line endings and the timing of a recording do not count
+//! as a change. Typescript `.timing` files are skipped. The report names the
+//! hosts whose output changed, with the first line that differs, and those
+//! found in only one of the runs; sshpass exits with 12 if there are any.
+
+use crate::error::{Result, SshpassError};
+use crate::recording::{self, ExportFormat, ExportOptions};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Suffixes added by `--record-compress` and `--record-recipient`
+const WRAPPERS: [&str; 3] = [".age", ".gz", ".zst"];
+/// Suffixes left out of the host names in the report
+const EXTENSIONS: [&str; 5] = [".cast", ".json", ".log", ".txt", ".typescript"];
+
+/// How two runs are compared
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffOptions {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    /// age identity file of encrypted recordings
+    pub identity: Option<String>,
+}
+
+impl DiffOptions {
+    /// Parse the words after `sshpass diff`
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let invalid = |message: String| SshpassError::InvalidArguments(message);
+        let mut runs = Vec::new();
+        let mut identity = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--identity" => {
+                    identity = Some(args.next().cloned().ok_or_else(|| {
+                        invalid("--identity expects an identity file".to_string())
+                    })?)
+                }
+                _ if runs.len() < 2 && !arg.starts_with("--") => runs.push(PathBuf::from(arg)),
+                _ => return Err(invalid(format!("Unexpected argument to diff: {}", arg))),
+            }
+        }
+        let [old, new]: [PathBuf; 2] = runs
+            .try_into()
+            .map_err(|_| invalid("Usage: sshpass diff [--identity FILE] RUN1 RUN2".to_string()))?;
+        Ok(Self { old, new, identity })
+    }
+}
+
+/// A difference between the two runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The output of the host differs from this line on
+    Changed { host: String, line: usize },
+    /// Only the first run has the host
+    Removed(String),
+    /// Only the second run has the host
+    Added(String),
+}
+
+impl Change {
+    /// The line of the report
+    pub fn describe(&self, options: &DiffOptions) -> String {
+        match self {
+            Change::Changed { host, line } => format!("{}: changed from line {}", host, line),
+            Change::Removed(host) => format!("{}: only in {}", host, options.old.display()),
+            Change::Added(host) => format!("{}: only in {}", host, options.new.display()),
+        }
+    }
+}
+
+/// Compare the runs, returning the hosts compared and what differs
+pub fn compare(options: &DiffOptions) -> Result<(usize, Vec<Change>)> {
+    let old = hosts(&options.old)?;
+    let mut new = hosts(&options.new)?;
+    let mut changes = Vec::new();
+    let mut compared = 0;
+    for (key, old_path) in old {
+        let host = host_name(&key).to_string();
+        let Some(new_path) = new.remove(&key) else {
+            changes.push(Change::Removed(host));
+            continue;
+        };
+        compared += 1;
+        let before = transcript(&old_path, options)?;
+        let after = transcript(&new_path, options)?;
+        if let Some(line) = first_difference(&before, &after) {
+            changes.push(Change::Changed { host, line });
+        }
+    }
+    changes.extend(
+        new.keys()
+            .map(|key| Change::Added(host_name(key).to_string())),
+    );
+    Ok((compared, changes))
+}
+
+/// The files of a run by name, without their compression and encryption
+/// suffixes
+fn hosts(run: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    let failed =
+        |e: std::io::Error| SshpassError::RuntimeError(format!("{}: {}", run.display(), e));
+    let mut hosts = BTreeMap::new();
+    for entry in std::fs::read_dir(run).map_err(failed)? {
+        let entry = entry.map_err(failed)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type().map_err(failed)?.is_file() || name.ends_with(".timing") {
+            continue;
+        }
+        let mut key = name.as_str();
+        while let Some(inner) = WRAPPERS.iter().find_map(|suffix| key.strip_suffix(suffix)) {
+            key = inner;
+        }
+        hosts.insert(key.to_string(), entry.path());
+    }
+    Ok(hosts)
+}
+
+/// `db1.example.com.cast` is `db1.example.com`
+fn host_name(key: &str) -> &str {
+    EXTENSIONS
+        .iter()
+        .find_map(|extension| key.strip_suffix(extension))
+        .unwrap_or(key)
+}
+
+/// The output in a file as `sshpass export` gives it
+fn transcript(path: &Path, options: &DiffOptions) -> Result<Vec<u8>> {
+    let export = ExportOptions {
+        path: path.display().to_string(),
+        format: ExportFormat::Txt,
+        identity: options.identity.clone(),
+    };
+    let reader = recording::open(&export.path, export.identity.as_deref())?;
+    let mut text = Vec::new();
+    recording::export(reader, &mut text, &export)?;
+    Ok(text)
+}
+
+/// The first line, counting from 1, where the texts differ
+fn first_difference(before: &[u8], after: &[u8]) -> Option<usize> {
+    let mut old = before.split(|&byte| byte == b'\n');
+    let mut new = after.split(|&byte| byte == b'\n');
+    let mut line = 1;
+    loop {
+        match (old.next(), new.next()) {
+            (None, None) => return None,
+            (a, b) if a != b => return Some(line),
+            _ => line += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff_options() {
+        let parsed = DiffOptions::parse(&args(&["--identity", "key.txt", "a", "b"])).unwrap();
+        assert_eq!(parsed.old, Path::new("a"));
+        assert_eq!(parsed.new, Path::new("b"));
+        assert_eq!(parsed.identity.as_deref(), Some("key.txt"));
+        assert!(DiffOptions::parse(&args(&["a"])).is_err());
+        assert!(DiffOptions::parse(&args(&["a", "b", "c"])).is_err());
+    }
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference(b"a\nb\n", b"a\nb\n"), None);
+        assert_eq!(first_difference(b"a\nb\n", b"a\nc\n"), Some(2));
+        assert_eq!(first_difference(b"a\n", b"a\nb\n"), Some(2));
+        assert_eq!(host_name("db1.example.com.cast"), "db1.example.com");
+        assert_eq!(host_name("10.0.0.1"), "10.0.0.1");
+    }
+
+    #[test]
+    fn test_compare_runs() {
+        let dir = std::env::temp_dir().join(format!("sshpass-diff-{}", std::process::id()));
+        let (old, new) = (dir.join("before"), dir.join("after"));
+        std::fs::create_dir_all(&old).unwrap();
+        std::fs::create_dir_all(&new).unwrap();
+        let cast = |lines: &str| {
+            format!(
+                "{{\"version\": 2, \"width\": 80, \"height\": 24}}\n[0.5, \"o\", \"{}\"]\n",
+                lines
+            )
+        };
+        // Only colours and timing differ
+        std::fs::write(old.join("db1.cast"), cast("ntp on\\r\\n")).unwrap();
+        let recolored = cast("\\u001b[32mntp on\\u001b[0m\\r\\n").replace("0.5", "2.5");
+        std::fs::write(new.join("db1.cast"), recolored).unwrap();
+        std::fs::write(old.join("db2.log"), "ntp on\r\nssh on\r\n").unwrap();
+        std::fs::write(new.join("db2.log"), "ntp on\r\nssh off\r\n").unwrap();
+        std::fs::write(old.join("db3.json"), "{\"code\":0}\n").unwrap();
+        std::fs::write(new.join("db4.json"), "{\"code\":0}\n").unwrap();
+        std::fs::write(new.join("db2.log.timing"), "0.1 6\n").unwrap();
+
+        let options = DiffOptions {
+            old: old.clone(),
+            new: new.clone(),
+            identity: None,
+        };
+        let (compared, changes) = compare(&options).unwrap();
+        assert_eq!(compared, 2);
+        assert_eq!(
+            changes,
+            [
+                Change::Changed {
+                    host: "db2".to_string(),
+                    line: 2
+                },
+                Change::Removed("db3".to_string()),
+                Change::Added("db4".to_string()),
+            ]
+        );
+        assert_eq!(
+            changes[1].describe(&options),
+            format!("db3: only in {}", old.display())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,8 @@
+//! Tools that work on what sessions left behind
+//!
+//! These are subcommands that do not run a session: they read recordings
+//! and results written by earlier runs.
+//!
+//! - [`diff`]: compare the per-host output of two runs (`sshpass diff`)
+
+pub mod diff;
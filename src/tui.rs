@@ -0,0 +1,627 @@
+//! Live dashboard of a batch (`sshpass tui`)
+//!
+//! `sshpass tui [OPTIONS] --inventory FILE COMMAND` runs the batch the way
+//! `--inventory` does, but instead of the prefixed output of the hosts it
+//! shows a table of them: the state of each host, how long it has been
+//! running or took, and the last line it printed. Below the table are the
+//! diagnostics of sshpass and of the children, which would otherwise be
+//! written over the screen (on Unix, stderr goes there while the dashboard
+//! is shown).
+//!
+//! | Key | In the table | Attached to a host |
+//! |-----|--------------|--------------------|
+//! | `↑` `↓` | select a host | typed into the session |
+//! | `Enter` | attach to the selected host | typed into the session |
+//! | `Ctrl-]` | | back to the table |
+//! | `Ctrl-C` | stop the batch | typed into the session |
+//! | `q` | leave, once the batch is over | typed into the session |
+//!
+//! Attached to a host, the screen shows its recent output without control
+//! sequences, and what is typed goes to its session through its
+//! [`SessionHandle`], as if the operator had logged in there: a host stuck
+//! on a question the batch did not expect can be answered. Full-screen
+//! programs show up as lines only.
+//!
+//! Needs the `tui` feature.
+
+use crate::ansi::AnsiFilter;
+use crate::batch::HostStatus;
+use crate::error::{Result, SshpassError};
+use crate::inventory::Target;
+use crate::session::{SessionEvent, SessionHandle, SessionObserver, SessionStatus};
+use crate::signal::SignalFlags;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Lines of output kept for each host, for attaching
+const KEPT_LINES: usize = 500;
+/// Diagnostics kept below the table
+const KEPT_MESSAGES: usize = 200;
+/// How often the screen is redrawn without a key press
+const TICK: Duration = Duration::from_millis(200);
+
+/// How far a host got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HostState {
+    /// Not started yet
+    Queued,
+    /// Its session is at this status
+    Session(SessionStatus),
+    /// The job of the host returned
+    Done(HostStatus),
+}
+
+/// One host of the table
+struct HostRow {
+    host: String,
+    group: String,
+    state: HostState,
+    started: Option<Instant>,
+    /// How long the host took, once done
+    took: Option<Duration>,
+    /// The output with control sequences removed, last lines only
+    lines: VecDeque<String>,
+    /// The start of a line that has not ended yet
+    partial: String,
+    filter: AnsiFilter,
+    /// Present while the session can be typed into
+    handle: Option<SessionHandle>,
+}
+
+impl HostRow {
+    fn new(target: &Target) -> Self {
+        Self {
+            host: target.host.clone(),
+            group: target.group.clone(),
+            state: HostState::Queued,
+            started: None,
+            took: None,
+            lines: VecDeque::new(),
+            partial: String::new(),
+            filter: AnsiFilter::new(),
+            handle: None,
+        }
+    }
+
+    fn output(&mut self, data: &[u8]) {
+        let text = self.filter.process(data);
+        for part in String::from_utf8_lossy(&text).split_inclusive('\n') {
+            match part.strip_suffix('\n') {
+                Some(line) => {
+                    self.partial.push_str(line);
+                    self.lines.push_back(std::mem::take(&mut self.partial));
+                    if self.lines.len() > KEPT_LINES {
+                        self.lines.pop_front();
+                    }
+                }
+                None => self.partial.push_str(part),
+            }
+        }
+    }
+
+    /// The last line that is not blank
+    fn last_line(&self) -> &str {
+        std::iter::once(&self.partial)
+            .chain(self.lines.iter().rev())
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+    }
+
+    fn running_for(&self) -> Option<Duration> {
+        self.took
+            .or_else(|| self.started.map(|started| started.elapsed()))
+    }
+
+    /// The state in words and its color; hosts still queued when the batch
+    /// is over were skipped
+    fn describe(&self, batch_over: bool) -> (String, Color) {
+        match self.state {
+            HostState::Queued if batch_over => ("skipped".to_string(), Color::DarkGray),
+            HostState::Queued => ("queued".to_string(), Color::DarkGray),
+            HostState::Session(SessionStatus::Starting) => ("starting".to_string(), Color::Yellow),
+            HostState::Session(SessionStatus::WaitingForPrompt) => {
+                ("connecting".to_string(), Color::Yellow)
+            }
+            HostState::Session(SessionStatus::PasswordSent) => {
+                ("logging in".to_string(), Color::Yellow)
+            }
+            HostState::Session(SessionStatus::Authenticated) => {
+                ("logged in".to_string(), Color::Yellow)
+            }
+            HostState::Session(SessionStatus::Running) => ("running".to_string(), Color::Yellow),
+            HostState::Session(SessionStatus::Exited(code)) => {
+                (format!("exit {}", code), Color::Yellow)
+            }
+            HostState::Done(HostStatus::Succeeded) => ("succeeded".to_string(), Color::Green),
+            HostState::Done(status) => (status.as_str().to_string(), Color::Red),
+        }
+    }
+}
+
+/// What the dashboard shows
+struct State {
+    rows: Vec<HostRow>,
+    messages: VecDeque<String>,
+}
+
+/// The hosts of a batch, kept up to date by their sessions
+#[derive(Clone)]
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+}
+
+/// Updates the row of one host from its session
+pub struct DashboardObserver {
+    state: Arc<Mutex<State>>,
+    index: usize,
+}
+
+impl SessionObserver for DashboardObserver {
+    fn on_event(&mut self, event: SessionEvent<'_>) {
+        let mut state = self.state.lock().unwrap();
+        let row = &mut state.rows[self.index];
+        match event {
+            SessionEvent::Output(data) => row.output(data),
+            SessionEvent::Status(status) => {
+                if let SessionStatus::Exited(_) = status {
+                    row.handle = None;
+                }
+                row.state = HostState::Session(status);
+            }
+            SessionEvent::Error(_) => {}
+        }
+    }
+}
+
+/// Where the keys go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Table,
+    Attached(usize),
+}
+
+impl Dashboard {
+    pub fn new(targets: &[Target]) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                rows: targets.iter().map(HostRow::new).collect(),
+                messages: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Row of `target`, by its host and group
+    fn index(&self, target: &Target) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        state
+            .rows
+            .iter()
+            .position(|row| row.host == target.host && row.group == target.group)
+    }
+
+    /// The observer of the session of `target`, starting now; the session
+    /// can be attached to through `handle` until it ends
+    pub fn observer(&self, target: &Target, handle: SessionHandle) -> Option<DashboardObserver> {
+        let index = self.index(target)?;
+        let mut state = self.state.lock().unwrap();
+        let row = &mut state.rows[index];
+        row.state = HostState::Session(SessionStatus::Starting);
+        row.started = Some(Instant::now());
+        row.handle = Some(handle);
+        drop(state);
+        Some(DashboardObserver {
+            state: Arc::clone(&self.state),
+            index,
+        })
+    }
+
+    /// Note what the job of `target` returned
+    pub fn finish_host(&self, target: &Target, result: &Result<i32>) {
+        let Some(index) = self.index(target) else {
+            return;
+        };
+        let status = match result {
+            Ok(0) => HostStatus::Succeeded,
+            Err(SshpassError::Timeout(_)) => HostStatus::TimedOut,
+            _ => HostStatus::Failed,
+        };
+        if let Err(e) = result {
+            self.message(&format!("{}: {}", target.host, e));
+        }
+        let mut state = self.state.lock().unwrap();
+        let row = &mut state.rows[index];
+        row.took = row.started.map(|started| started.elapsed());
+        row.handle = None;
+        row.state = HostState::Done(status);
+    }
+
+    /// Add a line below the table
+    pub fn message(&self, line: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.messages.push_back(line.to_string());
+        if state.messages.len() > KEPT_MESSAGES {
+            state.messages.pop_front();
+        }
+    }
+
+    /// Run `batch` while the dashboard is shown on the terminal, until the
+    /// batch is over and the operator leaves
+    ///
+    /// Ctrl-C in the table stops the batch: no more hosts start, through
+    /// `signals` as with SIGINT, and the sessions running are cancelled.
+    /// When the terminal fails, the batch is stopped the same way before
+    /// the error is returned.
+    pub fn show<T: Send>(
+        &self,
+        signals: &SignalFlags,
+        batch: impl FnOnce() -> T + Send,
+    ) -> Result<T> {
+        let screen = Screen::enter()?;
+        // Before the first child, which inherits stderr
+        #[cfg(unix)]
+        let capture = StderrCapture::start(self.clone()).ok();
+        let (shown, result) = std::thread::scope(|scope| {
+            let running = scope.spawn(batch);
+            let shown = self.interact(&|| running.is_finished(), signals);
+            if shown.is_err() {
+                self.stop(signals);
+            }
+            match running.join() {
+                Ok(result) => (shown, result),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        });
+        #[cfg(unix)]
+        drop(capture);
+        screen.leave()?;
+        shown.map(|()| result)
+    }
+
+    /// Stop starting hosts and cancel the sessions running
+    fn stop(&self, signals: &SignalFlags) {
+        signals.sigint_received.store(true, Ordering::SeqCst);
+        let state = self.state.lock().unwrap();
+        for handle in state.rows.iter().filter_map(|row| row.handle.as_ref()) {
+            handle.cancel();
+        }
+    }
+
+    /// Draw and take keys until the operator leaves
+    fn interact(&self, batch_over: &dyn Fn() -> bool, signals: &SignalFlags) -> Result<()> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+        let mut table = TableState::default().with_selected(Some(0));
+        let mut view = View::Table;
+        loop {
+            let over = batch_over();
+            // Without an operator to press q
+            if over && (signals.check_sigterm() || signals.check_sighup()) {
+                return Ok(());
+            }
+            terminal.draw(|frame| self.draw(frame, view, &mut table, over))?;
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            // Windows reports releases too
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match view {
+                // Terminals send Ctrl-] as the byte crossterm reads as Ctrl-5
+                View::Attached(_) if ctrl && matches!(key.code, KeyCode::Char(']' | '5')) => {
+                    view = View::Table;
+                }
+                View::Attached(index) => {
+                    let handle = self.state.lock().unwrap().rows[index].handle.clone();
+                    match handle {
+                        Some(handle) => handle.send_input(&key_bytes(key)),
+                        // Nothing left to type into
+                        None => view = View::Table,
+                    }
+                }
+                View::Table => match key.code {
+                    KeyCode::Char('c') if ctrl && over => return Ok(()),
+                    KeyCode::Char('c') if ctrl => {
+                        self.message("Stopping the batch");
+                        self.stop(signals);
+                    }
+                    KeyCode::Char('q') | KeyCode::Esc if over => return Ok(()),
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        let selected = table.selected().unwrap_or(0);
+                        table.select(Some(selected.saturating_sub(1)));
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        let last = self.state.lock().unwrap().rows.len().saturating_sub(1);
+                        let selected = table.selected().unwrap_or(0);
+                        table.select(Some((selected + 1).min(last)));
+                    }
+                    KeyCode::Enter => {
+                        // An empty group leaves nothing to attach to
+                        let rows = self.state.lock().unwrap().rows.len();
+                        if let Some(index) = table.selected().filter(|&index| index < rows) {
+                            view = View::Attached(index);
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, view: View, table: &mut TableState, over: bool) {
+        let state = self.state.lock().unwrap();
+        let [main, messages, help] = split(frame.size());
+        match view {
+            View::Table => draw_table(frame, main, &state.rows, table, over),
+            View::Attached(index) => draw_host(frame, main, &state.rows[index]),
+        }
+
+        let shown = usize::from(messages.height.saturating_sub(2));
+        let start = state.messages.len().saturating_sub(shown);
+        let lines = state
+            .messages
+            .iter()
+            .skip(start)
+            .map(|message| Line::from(message.as_str()))
+            .collect::<Vec<_>>();
+        let block = Block::default().borders(Borders::ALL).title("Messages");
+        frame.render_widget(Paragraph::new(lines).block(block), messages);
+
+        let keys = match view {
+            View::Attached(_) => "Ctrl-] back to the table; other keys go to the host",
+            View::Table if over => "↑↓ select  Enter attach  q leave",
+            View::Table => "↑↓ select  Enter attach  Ctrl-C stop the batch",
+        };
+        let style = Style::default().add_modifier(Modifier::REVERSED);
+        frame.render_widget(Paragraph::new(keys).style(style), help);
+    }
+}
+
+/// The table, the messages and the line of keys
+fn split(area: Rect) -> [Rect; 3] {
+    let parts = Layout::vertical([
+        Constraint::Min(5),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .split(area);
+    [parts[0], parts[1], parts[2]]
+}
+
+fn draw_table(frame: &mut Frame, area: Rect, rows: &[HostRow], table: &mut TableState, over: bool) {
+    let mut counts = [0; 4];
+    let lines = rows
+        .iter()
+        .map(|row| {
+            let (state, color) = row.describe(over);
+            match row.state {
+                HostState::Done(HostStatus::Succeeded) => counts[0] += 1,
+                HostState::Done(_) => counts[1] += 1,
+                HostState::Queued if !over => counts[2] += 1,
+                _ => counts[3] += 1,
+            }
+            let time = row
+                .running_for()
+                .map_or(String::new(), |took| format!("{}s", took.as_secs()));
+            Row::new([
+                row.host.clone(),
+                row.group.clone(),
+                state,
+                time,
+                row.last_line().to_string(),
+            ])
+            .style(Style::default().fg(color))
+        })
+        .collect::<Vec<_>>();
+    let title = format!(
+        "sshpass tui: {} host(s), {} succeeded, {} failed, {} running, {} queued{}",
+        rows.len(),
+        counts[0],
+        counts[1],
+        counts[3],
+        counts[2],
+        if over { ", done" } else { "" }
+    );
+    let widths = [
+        Constraint::Length(24),
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Length(7),
+        Constraint::Min(10),
+    ];
+    let header = Row::new(["HOST", "GROUP", "STATE", "TIME", "LAST OUTPUT"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    let widget = Table::new(lines, widths)
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(widget, area, table);
+}
+
+fn draw_host(frame: &mut Frame, area: Rect, row: &HostRow) {
+    let (state, _) = row.describe(false);
+    let shown = usize::from(area.height.saturating_sub(2));
+    let lines = row
+        .lines
+        .iter()
+        .chain(std::iter::once(&row.partial))
+        .collect::<Vec<_>>();
+    let start = lines.len().saturating_sub(shown);
+    let text = lines[start..]
+        .iter()
+        .map(|line| Line::from(line.as_str()))
+        .collect::<Vec<_>>();
+    let title = format!("{} ({}): {}", row.host, row.group, state);
+    let block = Block::default().borders(Borders::ALL).title(title);
+    frame.render_widget(Paragraph::new(text).block(block), area);
+}
+
+/// What a key types into a terminal
+fn key_bytes(key: KeyEvent) -> Vec<u8> {
+    match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            vec![(c.to_ascii_lowercase() as u8) & 0x1f]
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => b"\r".to_vec(),
+        KeyCode::Backspace => b"\x7f".to_vec(),
+        KeyCode::Tab => b"\t".to_vec(),
+        KeyCode::Esc => b"\x1b".to_vec(),
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped
+struct Screen {
+    active: bool,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let screen = Self { active: true };
+        crossterm::execute!(std::io::stdout(), EnterAlternateScreen)?;
+        Ok(screen)
+    }
+
+    fn leave(mut self) -> Result<()> {
+        self.active = false;
+        crossterm::execute!(std::io::stdout(), LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if self.active {
+            let _ = crossterm::execute!(std::io::stdout(), LeaveAlternateScreen);
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+}
+
+/// stderr sent to the messages of the dashboard, until dropped
+#[cfg(unix)]
+struct StderrCapture {
+    saved: std::os::fd::OwnedFd,
+}
+
+#[cfg(unix)]
+impl StderrCapture {
+    fn start(dashboard: Dashboard) -> Result<Self> {
+        use nix::fcntl::OFlag;
+        use std::io::BufRead;
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        let (read_fd, write_fd) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+        // SAFETY: pipe2 just returned these descriptors and nothing else owns them
+        let (read, write) = unsafe {
+            (
+                OwnedFd::from_raw_fd(read_fd),
+                OwnedFd::from_raw_fd(write_fd),
+            )
+        };
+        let saved = nix::unistd::dup(libc::STDERR_FILENO)?;
+        // SAFETY: dup just returned this descriptor and nothing else owns it
+        let saved = unsafe { OwnedFd::from_raw_fd(saved) };
+        nix::unistd::dup2(std::os::fd::AsRawFd::as_raw_fd(&write), libc::STDERR_FILENO)?;
+        drop(write);
+
+        // Not joined: children such as ssh masters may keep the pipe open
+        std::thread::spawn(move || {
+            let reader = std::io::BufReader::new(std::fs::File::from(read));
+            for line in reader.lines() {
+                match line {
+                    Ok(line) => dashboard.message(line.trim_end()),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(Self { saved })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for StderrCapture {
+    fn drop(&mut self) {
+        let _ = nix::unistd::dup2(
+            std::os::fd::AsRawFd::as_raw_fd(&self.saved),
+            libc::STDERR_FILENO,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Inventory;
+
+    #[test]
+    fn test_rows_follow_sessions() {
+        let targets = Inventory::parse("[web]\nweb1\nweb2\n")
+            .unwrap()
+            .targets("all")
+            .unwrap();
+        let dashboard = Dashboard::new(&targets);
+        let control = crate::session::SessionControl::new(SignalFlags::new()).unwrap();
+        let mut observer = dashboard.observer(&targets[0], control.handle()).unwrap();
+        assert_eq!(
+            dashboard.state.lock().unwrap().rows[0].describe(false).0,
+            "starting"
+        );
+        observer.on_event(SessionEvent::Output(b"\x1b[1mup\x1b[0m 3 d"));
+        observer.on_event(SessionEvent::Output(b"ays\r\n\r\n"));
+        dashboard.finish_host(&targets[0], &Ok(0));
+        dashboard.finish_host(
+            &targets[1],
+            &Err(SshpassError::Timeout(
+                "after 5 seconds on the host".to_string(),
+            )),
+        );
+
+        let state = dashboard.state.lock().unwrap();
+        let web1 = &state.rows[0];
+        assert_eq!(web1.last_line(), "up 3 days");
+        assert!(web1.handle.is_none());
+        assert_eq!(web1.describe(true).0, "succeeded");
+        assert_eq!(state.rows[1].describe(true).0, "timed-out");
+        assert_eq!(
+            state.messages.back().unwrap(),
+            "web2: Timed out after 5 seconds on the host"
+        );
+    }
+
+    #[test]
+    fn test_key_bytes() {
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        assert_eq!(
+            key_bytes(key(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            b"\x03"
+        );
+        assert_eq!(
+            key_bytes(key(KeyCode::Char('é'), KeyModifiers::NONE)),
+            "é".as_bytes()
+        );
+        assert_eq!(key_bytes(key(KeyCode::Enter, KeyModifiers::NONE)), b"\r");
+        assert_eq!(key_bytes(key(KeyCode::Up, KeyModifiers::NONE)), b"\x1b[A");
+    }
+}
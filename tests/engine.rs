@@ -0,0 +1,361 @@
+//! Runs sessions through the library's event loop, without the binary
+//!
+//! The children prompt on /dev/tty with echo off, the way ssh does; the
+//! tests hand the loop their own signal flags and no stdin. The last ones
+//! drive the loop with a child and signals of their own.
+
+#![cfg(unix)]
+
+use nix::sys::signal::Signal;
+use sshpass::batch::jump;
+use sshpass::engine::{EventLoop, SessionChild, Terminal};
+use sshpass::error::{Result, SshpassError};
+use sshpass::input_source::{InputSource, Null, Scripted};
+use sshpass::inventory::Inventory;
+use sshpass::logging::Logger;
+use sshpass::monitor::OutputMonitor;
+use sshpass::output_sink::Stdout;
+use sshpass::password::SecureString;
+use sshpass::process::{ChildProcess, SpawnOptions};
+use sshpass::profiles::LoginScript;
+use sshpass::pty::MasterRead;
+use sshpass::resize_retry::Step;
+use sshpass::session::{SessionControl, SessionEvent, SessionStatus};
+use sshpass::signal::{SignalFlags, SignalSource};
+use sshpass::stdin_forwarder::ForwarderConfig;
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+/// Prompts until it reads `secret`
+const PROMPT_LOOP: &str =
+    "exec </dev/tty >/dev/tty; stty -echo; while :; do printf 'Password: '; read p; \
+     [ \"$p\" = secret ] && exit 0; printf '\\nPermission denied, please try again.\\n'; done";
+
+fn run_session(script: &str, password: &str, control: &SessionControl) -> Result<i32> {
+    run_with(
+        script,
+        password,
+        control,
+        OutputMonitor::new(None, false),
+        &mut Null,
+    )
+}
+
+fn run_with(
+    script: &str,
+    password: &str,
+    control: &SessionControl,
+    mut monitor: OutputMonitor,
+    input: &mut dyn InputSource,
+) -> Result<i32> {
+    let child = ChildProcess::spawn(&SpawnOptions::new(["sh", "-c", script]))?;
+    let password = SecureString::new(password.to_string());
+    let mut log = Logger::new(0);
+    EventLoop {
+        child,
+        login: LoginScript::new(&password, None, b"\n"),
+        monitor: &mut monitor,
+        control,
+        config: ForwarderConfig::default(),
+        input,
+        sequence: None,
+        output: &mut Stdout,
+        log: &mut log,
+    }
+    .run()
+}
+
+#[test]
+fn test_engine_types_the_password() {
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    assert_eq!(run_session(PROMPT_LOOP, "secret", &control).unwrap(), 0);
+
+    let found = control.handle().prompt_match().unwrap();
+    assert_eq!(found.pattern, "assword");
+    assert_eq!(found.offset, 1);
+    assert_eq!(found.context, b"Password");
+}
+
+#[test]
+fn test_engine_reports_incorrect_password() {
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    let result = run_session(PROMPT_LOOP, "wrong", &control);
+    assert!(matches!(result, Err(SshpassError::IncorrectPassword)));
+}
+
+/// A session that records the statuses it goes through
+fn recording_statuses() -> (SessionControl, Arc<Mutex<Vec<SessionStatus>>>) {
+    let statuses = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&statuses);
+    let control = SessionControl::new(SignalFlags::new())
+        .unwrap()
+        .with_observer(move |event: SessionEvent<'_>| {
+            if let SessionEvent::Status(status) = event {
+                seen.lock().unwrap().push(status);
+            }
+        });
+    (control, statuses)
+}
+
+#[test]
+fn test_engine_rejected_password_is_not_authenticated() {
+    let (control, statuses) = recording_statuses();
+    let result = run_session(PROMPT_LOOP, "wrong", &control);
+    assert!(matches!(result, Err(SshpassError::IncorrectPassword)));
+    let statuses = statuses.lock().unwrap();
+    assert!(statuses.contains(&SessionStatus::PasswordSent));
+    assert!(!statuses.contains(&SessionStatus::Authenticated));
+}
+
+#[test]
+fn test_engine_authenticated_on_login_message() {
+    let (control, statuses) = recording_statuses();
+    // The shell stays up, as a login's would; output left when the child
+    // exits is drained without the monitor
+    let script = "exec </dev/tty >/dev/tty; stty -echo; printf 'Password: '; read p; \
+         printf '\nLast login: Mon Oct 12 09:00:00 2026\n'; sleep 1";
+    assert_eq!(run_session(script, "secret", &control).unwrap(), 0);
+    let statuses = statuses.lock().unwrap();
+    let sent = statuses
+        .iter()
+        .position(|s| *s == SessionStatus::PasswordSent);
+    let logged_in = statuses
+        .iter()
+        .position(|s| *s == SessionStatus::Authenticated);
+    assert!(sent.unwrap() < logged_in.unwrap(), "{:?}", statuses);
+}
+
+#[test]
+fn test_engine_cancel_from_handle() {
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    let handle = control.handle();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        handle.cancel();
+    });
+    let code = run_session("exec sleep 10", "unused", &control).unwrap();
+    canceller.join().unwrap();
+    assert_eq!(code, 128 + libc::SIGTERM);
+}
+
+#[test]
+fn test_engine_prompt_timeout() {
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    let mut monitor = OutputMonitor::new(None, false);
+    monitor.set_prompt_timeout(Some(std::time::Duration::from_millis(200)));
+    let result = run_with("exec sleep 10", "unused", &control, monitor, &mut Null);
+    let error = result.unwrap_err();
+    assert!(matches!(error, SshpassError::Timeout(_)));
+    assert_eq!(error.exit_code(), 11);
+}
+
+#[test]
+fn test_engine_types_scripted_input() {
+    // No password prompt, as with a key login; the input is typed from the
+    // start like keystrokes
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    let mut input = Scripted::new(["hello\n"]).then_eof();
+    let code = run_with(
+        "exec </dev/tty; read line; [ \"$line\" = hello ]",
+        "unused",
+        &control,
+        OutputMonitor::new(None, false),
+        &mut input,
+    )
+    .unwrap();
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn test_engine_types_each_hop_its_password() {
+    // ssh -J asks for the bastion's password, then for the host's
+    const HOPS: &str = "exec </dev/tty >/dev/tty; stty -echo; \
+         printf \"gate@outer.example.com's password: \"; read p; [ \"$p\" = outer ] || exit 1; \
+         printf \"\\ndba@db1's password: \"; read p; [ \"$p\" = inner ] || exit 2";
+    let inventory = Inventory::parse(
+        "[db]\ndb1 user=dba password=env:DB jump=gate@outer.example.com\n\
+         [bastions]\nouter.example.com password=env:OUTER",
+    )
+    .unwrap();
+    let target = &inventory.targets("db1").unwrap()[0];
+    let db = SecureString::new("inner".to_string());
+    let outer = SecureString::new("outer".to_string());
+    let chooser = |prompt: &str| match jump::password_for(target, prompt) {
+        Some("env:OUTER") => Some(&outer),
+        _ => None,
+    };
+
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    let child = ChildProcess::spawn(&SpawnOptions::new(["sh", "-c", HOPS])).unwrap();
+    let mut monitor = OutputMonitor::new(None, false);
+    let hops = target.clone();
+    monitor.set_attempt_key(move |prompt| {
+        jump::hop_for(&hops, prompt).map_or_else(String::new, |jump| jump.host.clone())
+    });
+    let mut log = Logger::new(0);
+    let code = EventLoop {
+        child,
+        login: LoginScript::new(&db, None, b"\n").with_password_chooser(Box::new(chooser)),
+        monitor: &mut monitor,
+        control: &control,
+        config: ForwarderConfig::default(),
+        input: &mut Null,
+        sequence: None,
+        output: &mut Stdout,
+        log: &mut log,
+    }
+    .run()
+    .unwrap();
+    assert_eq!(code, 0);
+}
+
+/// A terminal on a socket, recording what the loop types
+struct FakeTerminal {
+    near: UnixStream,
+    /// Kept open so that the near end does not hang up
+    _far: UnixStream,
+    typed: RefCell<Vec<u8>>,
+}
+
+impl FakeTerminal {
+    /// A terminal showing `output`
+    fn showing(output: &[u8]) -> Self {
+        let (near, mut far) = UnixStream::pair().unwrap();
+        near.set_nonblocking(true).unwrap();
+        far.write_all(output).unwrap();
+        Self {
+            near,
+            _far: far,
+            typed: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl Terminal for FakeTerminal {
+    fn write_all(&self, data: &[u8]) -> Result<()> {
+        self.typed.borrow_mut().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn send_break(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn retry_size(&self, _step: Step) -> Result<()> {
+        Ok(())
+    }
+
+    fn master_fd(&self) -> RawFd {
+        self.near.as_raw_fd()
+    }
+
+    fn read_master(&self, buffer: &mut [u8]) -> Result<MasterRead> {
+        match self.read(buffer)? {
+            0 => Ok(MasterRead::Empty),
+            n => Ok(MasterRead::Data(n)),
+        }
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        match (&self.near).read(buffer) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
+            read => Ok(read?),
+        }
+    }
+}
+
+/// A child that exits once it was typed `secret`, or killed by a signal
+struct FakeChild {
+    terminal: FakeTerminal,
+    signal: Cell<Option<Signal>>,
+}
+
+impl SessionChild for FakeChild {
+    fn terminal(&self) -> &dyn Terminal {
+        &self.terminal
+    }
+
+    fn try_wait(&mut self) -> Result<Option<i32>> {
+        if let Some(signal) = self.signal.get() {
+            return Ok(Some(128 + signal as i32));
+        }
+        let typed = self.terminal.typed.borrow();
+        Ok(typed.ends_with(b"secret\n").then_some(0))
+    }
+
+    fn wait(&mut self) -> Result<i32> {
+        Ok(self.try_wait()?.unwrap_or(255))
+    }
+
+    fn send_signal(&self, signal: Signal) -> Result<()> {
+        self.signal.set(Some(signal));
+        Ok(())
+    }
+}
+
+fn run_fake(child: FakeChild, control: &SessionControl) -> Result<i32> {
+    let password = SecureString::new("secret".to_string());
+    let mut monitor = OutputMonitor::new(None, false);
+    let mut log = Logger::new(0);
+    EventLoop {
+        child,
+        login: LoginScript::new(&password, None, b"\n"),
+        monitor: &mut monitor,
+        control,
+        config: ForwarderConfig::default(),
+        input: &mut Null,
+        sequence: None,
+        output: &mut Stdout,
+        log: &mut log,
+    }
+    .run()
+}
+
+#[test]
+fn test_engine_drives_a_child_of_its_own() {
+    let control = SessionControl::new(SignalFlags::new()).unwrap();
+    let child = FakeChild {
+        terminal: FakeTerminal::showing(b"Password: "),
+        signal: Cell::new(None),
+    };
+    assert_eq!(run_fake(child, &control).unwrap(), 0);
+    assert_eq!(control.handle().status(), SessionStatus::PasswordSent);
+}
+
+/// Signals raised by the caller rather than the process's handlers
+struct Raised(Option<Signal>);
+
+impl SignalSource for Raised {
+    fn term_signal(&self) -> Option<Signal> {
+        self.0
+    }
+
+    fn take_stop(&self) -> bool {
+        false
+    }
+
+    fn take_resize(&self) -> bool {
+        false
+    }
+
+    fn pending(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn test_engine_takes_signals_from_its_source() {
+    let control = SessionControl::new(Raised(Some(Signal::SIGTERM))).unwrap();
+    let child = FakeChild {
+        terminal: FakeTerminal::showing(b""),
+        signal: Cell::new(None),
+    };
+    assert_eq!(run_fake(child, &control).unwrap(), 128 + 15);
+}
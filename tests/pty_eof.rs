@@ -0,0 +1,49 @@
+//! Runs sshpass with children that close the terminal before exiting
+//!
+//! The master reports EOF (EIO on Linux) as soon as the child has no
+//! terminal descriptor left, which can be well before waitpid sees it exit.
+//! sshpass must wait for the exit without spinning on the readable master.
+
+#![cfg(unix)]
+
+use std::process::Command;
+use std::time::Duration;
+
+/// CPU time used by the reaped children of this test
+fn children_cpu_time() -> Duration {
+    // SAFETY: getrusage only writes into the struct it is given
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        assert_eq!(libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage), 0);
+        usage
+    };
+    let time = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+fn run_sshpass(command: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_sshpass"))
+        .args(["-p", "unused"])
+        .args(command)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn test_eof_before_exit_does_not_spin() {
+    // Exits right away, racing the EOF against waitpid
+    for _ in 0..20 {
+        assert_eq!(run_sshpass(&["sh", "-c", "exit 7"]), Some(7));
+    }
+
+    // Never opens the terminal and stays around for a while
+    let before = children_cpu_time();
+    assert_eq!(run_sshpass(&["sleep", "0.5"]), Some(0));
+    let used = children_cpu_time() - before;
+    assert!(used < Duration::from_millis(200), "used {:?} of CPU", used);
+}
@@ -0,0 +1,63 @@
+//! Checks that the password does not linger in freed heap memory
+//!
+//! A scanning global allocator inspects every block as it is freed and counts
+//! the ones that still contain the secret. Every buffer the password passes
+//! through must be wiped before it is released.
+
+use sshpass::password::{read_password_from_env, PasswordSource};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const SECRET: &str = "Zq7-sshpass-hygiene-marker";
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static STRAY_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+struct ScanningAllocator;
+
+unsafe impl GlobalAlloc for ScanningAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if TRACKING.load(Ordering::SeqCst) {
+            let block = std::slice::from_raw_parts(ptr, layout.size());
+            if block
+                .windows(SECRET.len())
+                .any(|window| window == SECRET.as_bytes())
+            {
+                STRAY_COPIES.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: ScanningAllocator = ScanningAllocator;
+
+#[test]
+fn test_no_stray_password_copies() {
+    let path = std::env::temp_dir().join(format!("sshpass-hygiene-{}", std::process::id()));
+    std::fs::write(&path, format!("{}\nsecond line\n", SECRET)).unwrap();
+    std::env::set_var("SSHPASS_HYGIENE_TEST", SECRET);
+
+    TRACKING.store(true, Ordering::SeqCst);
+    {
+        let password = PasswordSource::File(path.clone())
+            .read_password(false)
+            .unwrap();
+        assert_eq!(password.as_bytes(), SECRET.as_bytes());
+
+        let line = password.with_terminator(b"\r\n");
+        assert!(line.ends_with(b"\r\n"));
+
+        let from_env = read_password_from_env("SSHPASS_HYGIENE_TEST", false).unwrap();
+        assert_eq!(from_env.as_bytes(), SECRET.as_bytes());
+    }
+    TRACKING.store(false, Ordering::SeqCst);
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(STRAY_COPIES.load(Ordering::SeqCst), 0);
+}
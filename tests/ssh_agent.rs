@@ -0,0 +1,76 @@
+//! Runs sshpass with a real ssh-agent in the environment
+//!
+//! The child asks the agent for its keys with `ssh-add -l`, which exits with
+//! 1 when it reaches an agent without identities and 2 when there is none.
+//! No password prompt ever appears, so sshpass has to end with the child's
+//! exit code unless `--require-prompt` is given. Skipped when OpenSSH's
+//! agent tools are not installed; needs the clap command line.
+
+#![cfg(all(unix, feature = "cli"))]
+
+use std::process::{Command, Output};
+
+/// An ssh-agent killed when dropped
+struct Agent {
+    sock: String,
+    pid: String,
+}
+
+impl Agent {
+    fn start() -> Option<Self> {
+        let output = Command::new("ssh-agent").arg("-s").output().ok()?;
+        let script = String::from_utf8_lossy(&output.stdout);
+        let value = |name: &str| {
+            let start = script.find(&format!("{}=", name))? + name.len() + 1;
+            let end = start + script[start..].find(';')?;
+            Some(script[start..end].to_string())
+        };
+        Some(Self {
+            sock: value("SSH_AUTH_SOCK")?,
+            pid: value("SSH_AGENT_PID")?,
+        })
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        let _ = Command::new("kill").arg(&self.pid).status();
+    }
+}
+
+fn run_sshpass(agent: &Agent, options: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sshpass"))
+        .args(["-p", "unused"])
+        .args(options)
+        .args(["sh", "-c", "ssh-add -l >/dev/null 2>&1; echo \"agent=$?\""])
+        .env("SSH_AUTH_SOCK", &agent.sock)
+        .env("SSH_AGENT_PID", &agent.pid)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_agent_login_without_prompt() {
+    if Command::new("ssh-add").arg("-h").output().is_err() {
+        eprintln!("ssh-add not installed, skipping");
+        return;
+    }
+    let Some(agent) = Agent::start() else {
+        eprintln!("ssh-agent not available, skipping");
+        return;
+    };
+
+    // The agent is passed through and the session ends cleanly
+    let output = run_sshpass(&agent, &[]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "agent=1\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run_sshpass(&agent, &["--ssh-env", "preserve", "--require-prompt"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "agent=1\n");
+    assert_eq!(output.status.code(), Some(8));
+
+    // Scrubbed, the child cannot reach it
+    let output = run_sshpass(&agent, &["--ssh-env", "scrub"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "agent=2\n");
+    assert_eq!(output.status.code(), Some(0));
+}